@@ -0,0 +1,100 @@
+//! Throughput of small messages through a `Connection` and its encryption
+//! stream, comparing the allocating and buffer reusing encryption paths.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use criterion::{
+    black_box, criterion_group, criterion_main, Criterion, Throughput,
+};
+
+use drop::crypto::key::{exchange::Exchanger, Key};
+use drop::crypto::stream::Push;
+use drop::net::{Connection, Connector, Listener, TcpConnector, TcpListener};
+
+/// A small message without any heap allocated content
+type Small = (u64, [u8; 24]);
+
+fn small(i: u64) -> Small {
+    (i, [i as u8; 24])
+}
+
+async fn connection_pair() -> (Connection, Connection) {
+    let exchanger = Exchanger::random();
+    let pkey = *exchanger.keypair().public();
+    let any = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let mut listener = TcpListener::new(any, exchanger)
+        .await
+        .expect("listen failed");
+    let addr = listener.local_addr().expect("no address");
+    let connector = TcpConnector::new(Exchanger::random());
+
+    let (accepted, connected) =
+        futures::join!(listener.accept(), connector.connect(&pkey, &addr));
+
+    (
+        accepted.expect("accept failed"),
+        connected.expect("connect failed"),
+    )
+}
+
+fn encrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encrypt");
+    let mut push = Push::new(Key::random());
+    let mut output = Vec::new();
+
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("allocating", |b| {
+        b.iter(|| push.encrypt(black_box(&small(0))).expect("encrypt failed"))
+    });
+
+    group.bench_function("into", |b| {
+        b.iter(|| {
+            push.encrypt_into(black_box(&small(0)), &mut output)
+                .expect("encrypt failed")
+        })
+    });
+
+    group.finish();
+}
+
+fn connection(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("no runtime");
+    let (mut receiver, mut sender) = runtime.block_on(connection_pair());
+    let mut group = c.benchmark_group("connection");
+
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("small", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let start = Instant::now();
+
+                for i in 0..iters {
+                    sender.send(&small(i)).await.expect("send failed");
+                    black_box(
+                        receiver
+                            .receive::<Small>()
+                            .await
+                            .expect("receive failed"),
+                    );
+                }
+
+                start.elapsed()
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(3));
+    targets = encrypt, connection
+}
+criterion_main!(benches);
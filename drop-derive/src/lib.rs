@@ -2,17 +2,171 @@ use proc_macro2::TokenStream;
 
 use quote::quote;
 
+use syn::{
+    parse_macro_input, parse_quote, AttributeArgs, Data, DeriveInput, Error,
+    Lit, Member, Meta, NestedMeta,
+};
+
+mod protocol;
+
+/// Options given to the `message` attribute
+#[derive(Default)]
+struct Options {
+    /// Do not implement `IdentifiableMessage`
+    no_hash: bool,
+    /// Field used to compute the identifier of the message
+    id: Option<Member>,
+}
+
+impl Options {
+    fn parse(args: AttributeArgs) -> Result<Self, Error> {
+        let mut options = Self::default();
+
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::Path(path))
+                    if path.is_ident("no_hash") =>
+                {
+                    options.no_hash = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(value))
+                    if value.path.is_ident("id") =>
+                {
+                    let field = match value.lit {
+                        Lit::Str(field) => field,
+                        lit => {
+                            return Err(Error::new_spanned(
+                                lit,
+                                "expected the name of a field",
+                            ))
+                        }
+                    };
+
+                    options.id = Some(field.parse()?);
+                }
+                arg => {
+                    return Err(Error::new_spanned(
+                        arg,
+                        "expected `no_hash` or `id = \"field\"`",
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn expand(options: Options, input: DeriveInput) -> Result<TokenStream, Error> {
+    let definition = quote! {
+        #[derive(Clone, Debug, Serialize, Deserialize)]
+        #input
+    };
+
+    if options.no_hash {
+        if let Some(id) = options.id {
+            return Err(Error::new_spanned(
+                id,
+                "`id` cannot be used along with `no_hash`",
+            ));
+        }
+
+        return Ok(definition);
+    }
+
+    let body = match options.id {
+        Some(id) => {
+            if !matches!(input.data, Data::Struct(_)) {
+                return Err(Error::new_spanned(
+                    id,
+                    "`id` can only be used on structs",
+                ));
+            }
+
+            quote! {
+                fn id(&self) -> ::drop::crypto::Digest {
+                    ::drop::crypto::hash(&self.#id)
+                        .expect("failed to serialize message id")
+                }
+            }
+        }
+        None => quote! {},
+    };
+
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(#name #ty_generics: ::drop::Message));
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        #definition
+
+        impl #impl_generics ::drop::IdentifiableMessage for #name #ty_generics
+            #where_clause
+        {
+            #body
+        }
+    })
+}
+
+/// Derive the traits required to use a type as a message, and implement
+/// `IdentifiableMessage` for it.
+///
+/// * `#[message(no_hash)]` does not implement `IdentifiableMessage`, leaving
+///   it to be implemented manually if needed
+/// * `#[message(id = "field")]` identifies messages by hashing only the given
+///   field instead of the whole message
 #[proc_macro_attribute]
 pub fn message(
-    _metadata: proc_macro::TokenStream,
+    metadata: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let def: TokenStream = input.into();
+    let args = parse_macro_input!(metadata as AttributeArgs);
+    let input = parse_macro_input!(input as DeriveInput);
 
-    let expanded = quote! {
-        #[derive(Clone, Debug, Serialize, Deserialize)]
-        #def
-    };
+    Options::parse(args)
+        .and_then(|options| expand(options, input))
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Describe a session between a client and a server by annotating the
+/// variants of a message enum, generating typestate sessions that only let
+/// each side send the messages that are legal at each point. This implies
+/// `#[message]`.
+///
+/// * `#[from(client)]` or `#[from(server)]` tells which side sends the
+///   variant
+/// * `#[next(A | B)]` lists the variants that may follow, which must be
+///   sent by the same side
+/// * `#[terminal]` ends the session after the variant
+/// * `#[repeat]` allows variants declared later to lead back to the
+///   variant, otherwise variants may only lead to later ones
+///
+/// The session starts with the first variant. The sessions of enum `Name`
+/// are generated in module `name` as `ClientSession<S>` and
+/// `ServerSession<S>`, where `S` is one of the states in `name::state`.
+#[proc_macro_attribute]
+pub fn protocol(
+    metadata: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let metadata = TokenStream::from(metadata);
+    let input = parse_macro_input!(input as DeriveInput);
+
+    if !metadata.is_empty() {
+        return Error::new_spanned(metadata, "`protocol` takes no arguments")
+            .into_compile_error()
+            .into();
+    }
 
-    expanded.into()
+    protocol::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
 }
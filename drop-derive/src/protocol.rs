@@ -0,0 +1,619 @@
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::TokenStream;
+
+use quote::{format_ident, quote};
+
+use syn::{
+    punctuated::Punctuated, Attribute, Data, DataEnum, DeriveInput, Error,
+    Fields, Ident, Token, Type, Variant,
+};
+
+use super::{expand as expand_message, Options};
+
+/// Name of the state of a session before its first message
+const INITIAL: &str = "Initial";
+
+/// Side of a session sending a message
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    fn session(self) -> Ident {
+        match self {
+            Self::Client => format_ident!("ClientSession"),
+            Self::Server => format_ident!("ServerSession"),
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Client => "Client",
+            Self::Server => "Server",
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Self::Client => Self::Server,
+            Self::Server => Self::Client,
+        }
+    }
+}
+
+/// What may follow a message of the session
+enum Then {
+    /// One of the given variants
+    Next(Vec<Ident>),
+    /// Nothing, the session is over
+    Terminal,
+}
+
+/// A variant of the protocol enum along with its annotations
+struct Step {
+    variant: Variant,
+    from: Role,
+    then: Then,
+    /// Whether later messages may lead back to this one
+    repeat: bool,
+}
+
+impl Step {
+    fn name(&self) -> &Ident {
+        &self.variant.ident
+    }
+
+    /// Parameters of the method sending this message
+    fn parameters(&self) -> Vec<(Ident, Type)> {
+        match &self.variant.fields {
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                vec![(format_ident!("value"), fields.unnamed[0].ty.clone())]
+            }
+            Fields::Unnamed(fields) => fields
+                .unnamed
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    (format_ident!("value{}", i), field.ty.clone())
+                })
+                .collect(),
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .map(|field| (field.ident.clone().unwrap(), field.ty.clone()))
+                .collect(),
+        }
+    }
+
+    /// Pattern or expression of this variant bound to its parameters
+    fn destructure(&self, protocol: &Ident) -> TokenStream {
+        let name = self.name();
+        let names = self.parameters().into_iter().map(|(name, _)| name);
+
+        match &self.variant.fields {
+            Fields::Unit => quote!(super::#protocol::#name),
+            Fields::Unnamed(_) => quote!(super::#protocol::#name(#(#names),*)),
+            Fields::Named(_) => quote!(super::#protocol::#name { #(#names),* }),
+        }
+    }
+
+    /// Type and value of the content of this message once received
+    fn payload(&self) -> (TokenStream, TokenStream) {
+        let (names, types): (Vec<_>, Vec<_>) =
+            self.parameters().into_iter().unzip();
+
+        match names.len() {
+            1 => {
+                let (name, ty) = (&names[0], &types[0]);
+
+                (quote!(#ty), quote!(#name))
+            }
+            _ => (quote!((#(#types),*)), quote!((#(#names),*))),
+        }
+    }
+}
+
+/// Take the protocol annotations out of `attrs`
+fn annotations(
+    span: &Variant,
+    attrs: &mut Vec<Attribute>,
+) -> Result<(Role, Then, bool), Error> {
+    let mut from = None;
+    let mut then = None;
+    let mut repeat = None;
+    let mut kept = Vec::new();
+
+    for attr in attrs.drain(..) {
+        let parsed = if attr.path.is_ident("from") {
+            let role: Ident = attr.parse_args()?;
+            let role = match role.to_string().as_str() {
+                "client" => Role::Client,
+                "server" => Role::Server,
+                _ => {
+                    return Err(Error::new_spanned(
+                        role,
+                        "expected `client` or `server`",
+                    ))
+                }
+            };
+
+            from.replace(role).map(|_| "from")
+        } else if attr.path.is_ident("next") {
+            let next = attr.parse_args_with(
+                Punctuated::<Ident, Token![|]>::parse_separated_nonempty,
+            )?;
+
+            then.replace(Then::Next(next.into_iter().collect()))
+                .map(|_| "next")
+        } else if attr.path.is_ident("terminal") {
+            then.replace(Then::Terminal).map(|_| "terminal")
+        } else if attr.path.is_ident("repeat") {
+            repeat.replace(true).map(|_| "repeat")
+        } else {
+            kept.push(attr);
+            None
+        };
+
+        if let Some(duplicate) = parsed {
+            return Err(Error::new_spanned(
+                span,
+                format!(
+                    "more than one `{}` or conflicting annotation",
+                    duplicate
+                ),
+            ));
+        }
+    }
+
+    *attrs = kept;
+
+    match (from, then) {
+        (Some(from), Some(then)) => Ok((from, then, repeat.is_some())),
+        (None, _) => Err(Error::new_spanned(
+            &span.ident,
+            "missing `#[from(client)]` or `#[from(server)]`",
+        )),
+        (_, None) => Err(Error::new_spanned(
+            &span.ident,
+            "missing `#[next(..)]` or `#[terminal]`",
+        )),
+    }
+}
+
+/// Check that the session described by `steps` is well formed: every
+/// message only leads to messages declared after it or marked with
+/// `#[repeat]`, the messages that may follow the same one are sent by the
+/// same side, and every message can be reached from the first one
+fn check(steps: &[Step]) -> Result<(), Error> {
+    let position: HashMap<_, _> = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| (step.name().to_string(), i))
+        .collect();
+    let mut follows = vec![Vec::new(); steps.len()];
+
+    for (i, step) in steps.iter().enumerate() {
+        if step.name() == INITIAL {
+            return Err(Error::new_spanned(
+                step.name(),
+                format!("`{}` is reserved for the initial state", INITIAL),
+            ));
+        }
+
+        let next = match &step.then {
+            Then::Next(next) => next,
+            _ => continue,
+        };
+        let mut from = None;
+
+        for name in next {
+            let j = *position
+                .get(&name.to_string())
+                .ok_or_else(|| Error::new_spanned(name, "no such variant"))?;
+
+            if j <= i && !steps[j].repeat {
+                return Err(Error::new_spanned(
+                    name,
+                    "a message can only lead back to one marked with \
+                     `#[repeat]`",
+                ));
+            }
+
+            if from.is_some_and(|from| from != steps[j].from) {
+                return Err(Error::new_spanned(
+                    name,
+                    "messages that may follow the same one must be sent by \
+                     the same side",
+                ));
+            }
+
+            from = Some(steps[j].from);
+            follows[i].push(j);
+        }
+    }
+
+    let mut reached = HashSet::from([0]);
+    let mut pending = vec![0];
+
+    while let Some(i) = pending.pop() {
+        pending.extend(follows[i].iter().filter(|j| reached.insert(**j)));
+    }
+
+    match steps.iter().enumerate().find(|(i, _)| !reached.contains(i)) {
+        Some((_, step)) => Err(Error::new_spanned(
+            step.name(),
+            "this message can never be reached",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Generate the methods of the sessions in the state `state`, where one of
+/// `expected` is exchanged next
+fn transitions(
+    protocol: &Ident,
+    state: &Ident,
+    expected: &[&Step],
+) -> TokenStream {
+    let sender = expected[0].from;
+    let sending = sender.session();
+    let receiving = sender.peer().session();
+    let sends = expected.iter().map(|step| {
+        let method = format_ident!("send_{}", snake_case(step.name()));
+        let (names, types): (Vec<_>, Vec<_>) =
+            step.parameters().into_iter().unzip();
+        let message = step.destructure(protocol);
+        let next = step.name();
+        let doc = format!("Send `{}::{}`", protocol, step.name());
+
+        quote! {
+            #[doc = #doc]
+            pub async fn #method(
+                mut self,
+                #(#names: #types),*
+            ) -> ::std::result::Result<
+                #sending<state::#next>,
+                ::drop::net::ProtocolError,
+            > {
+                ::drop::net::send_message(&mut self.connection, &#message)
+                    .await?;
+
+                Ok(self.transition())
+            }
+        }
+    });
+    let branch = if state == INITIAL {
+        format_ident!("{}{}", sender.peer().prefix(), state)
+    } else {
+        format_ident!("{}After{}", sender.peer().prefix(), state)
+    };
+    let branch_doc = format!(
+        "Message received by a `{}` in the state `{}`",
+        receiving, state
+    );
+    let variants = expected.iter().map(|step| {
+        let name = step.name();
+        let (payload, _) = step.payload();
+
+        quote!(#name(#receiving<state::#name>, #payload))
+    });
+    let arms = expected.iter().map(|step| {
+        let name = step.name();
+        let pattern = step.destructure(protocol);
+        let (_, value) = step.payload();
+
+        quote!(#pattern => #branch::#name(self.transition(), #value))
+    });
+    let names = expected.iter().map(|step| step.name().to_string());
+
+    quote! {
+        impl #sending<state::#state> {
+            #(#sends)*
+        }
+
+        #[doc = #branch_doc]
+        pub enum #branch {
+            #(#variants),*
+        }
+
+        impl #receiving<state::#state> {
+            /// Receive the next message, failing with
+            /// `ProtocolError::ProtocolViolation` if it is not one of those
+            /// allowed in this state
+            pub async fn receive(
+                mut self,
+            ) -> ::std::result::Result<#branch, ::drop::net::ProtocolError> {
+                const EXPECTED: &[&str] = &[#(#names),*];
+
+                let message = ::drop::net::receive_expected::<super::#protocol>(
+                    &mut self.connection,
+                    EXPECTED,
+                )
+                .await?;
+
+                #[allow(unreachable_patterns)]
+                Ok(match message {
+                    #(#arms,)*
+                    _ => unreachable!("unexpected message was accepted"),
+                })
+            }
+        }
+    }
+}
+
+pub(crate) fn expand(mut input: DeriveInput) -> Result<TokenStream, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "protocols can not be generic",
+        ));
+    }
+
+    let variants = match &mut input.data {
+        Data::Enum(DataEnum { variants, .. }) if !variants.is_empty() => {
+            variants
+        }
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "a protocol must be an enum with at least one variant",
+            ))
+        }
+    };
+    let mut steps = Vec::new();
+
+    for variant in variants.iter_mut() {
+        let span = variant.clone();
+        let (from, then, repeat) = annotations(&span, &mut variant.attrs)?;
+
+        steps.push(Step {
+            variant: variant.clone(),
+            from,
+            then,
+            repeat,
+        });
+    }
+
+    check(&steps)?;
+
+    let message = expand_message(Options::default(), input.clone())?;
+    let protocol = &input.ident;
+    let vis = &input.vis;
+    let module = format_ident!("{}", snake_case(protocol));
+    let names = steps.iter().map(Step::name);
+    let labels = steps.iter().map(|step| step.name().to_string());
+    let initial = format_ident!("{}", INITIAL);
+    let states = steps.iter().map(Step::name);
+    let by_name: HashMap<_, _> = steps
+        .iter()
+        .map(|step| (step.name().to_string(), step))
+        .collect();
+    let mut methods = vec![transitions(protocol, &initial, &[&steps[0]])];
+    let mut ends = vec![initial.clone()];
+
+    for step in steps.iter() {
+        match &step.then {
+            Then::Next(next) => {
+                let expected: Vec<_> = next
+                    .iter()
+                    .map(|name| by_name[&name.to_string()])
+                    .collect();
+
+                methods.push(transitions(protocol, step.name(), &expected));
+            }
+            Then::Terminal => ends.push(step.name().clone()),
+        }
+    }
+
+    let sessions = [Role::Client, Role::Server].map(|role| {
+        let session = role.session();
+        let doc = format!(
+            "A `Connection` used by the {} side of `{}`, in state `S`",
+            role.prefix().to_lowercase(),
+            protocol
+        );
+
+        quote! {
+            #[doc = #doc]
+            pub struct #session<S> {
+                connection: ::drop::net::Connection,
+                state: ::std::marker::PhantomData<S>,
+            }
+
+            impl #session<state::#initial> {
+                /// Start a session on a secured `Connection`
+                pub fn new(connection: ::drop::net::Connection) -> Self {
+                    Self {
+                        connection,
+                        state: ::std::marker::PhantomData,
+                    }
+                }
+            }
+
+            impl<S> #session<S> {
+                #[allow(dead_code)]
+                fn transition<T>(self) -> #session<T> {
+                    #session {
+                        connection: self.connection,
+                        state: ::std::marker::PhantomData,
+                    }
+                }
+            }
+
+            #(
+                impl #session<state::#ends> {
+                    /// Get back the `Connection` once nothing is expected
+                    pub fn into_inner(self) -> ::drop::net::Connection {
+                        self.connection
+                    }
+                }
+            )*
+        }
+    });
+    let module_doc = format!("Typed sessions of `{}`", protocol);
+
+    Ok(quote! {
+        #message
+
+        impl ::drop::net::Protocol for #protocol {
+            fn variant(&self) -> &'static str {
+                match self {
+                    #(Self::#names { .. } => #labels,)*
+                }
+            }
+        }
+
+        #[doc = #module_doc]
+        #vis mod #module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// States of the sessions
+            pub mod state {
+                /// Nothing was exchanged yet
+                pub struct #initial;
+
+                #(
+                    #[allow(missing_docs)]
+                    pub struct #states;
+                )*
+            }
+
+            #(#sessions)*
+
+            #(#methods)*
+        }
+    })
+}
+
+/// Convert a `CamelCase` identifier to `snake_case`
+fn snake_case(ident: &Ident) -> String {
+    let mut snake = String::new();
+
+    for (i, c) in ident.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+
+    snake
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use syn::parse_quote;
+
+    fn error(input: DeriveInput) -> String {
+        match expand(input) {
+            Ok(_) => panic!("invalid protocol was accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn expands_sessions() {
+        let output = expand(parse_quote! {
+            enum Transfer {
+                #[from(client)]
+                #[next(Chunk)]
+                Hello(String),
+                #[from(client)]
+                #[next(Ack)]
+                #[repeat]
+                Chunk(u32, Vec<u8>),
+                #[from(server)]
+                #[next(Chunk | Done)]
+                Ack { seq: u32 },
+                #[from(client)]
+                #[terminal]
+                Done,
+            }
+        })
+        .expect("valid protocol was refused")
+        .to_string();
+
+        for expected in [
+            "mod transfer",
+            "pub struct Initial",
+            "fn send_hello (mut self , value : String)",
+            "fn send_chunk (mut self , value0 : u32 , value1 : Vec < u8 >)",
+            "fn send_ack (mut self , seq : u32)",
+            "pub enum ServerAfterAck",
+            "Chunk (ServerSession < state :: Chunk > , (u32 , Vec < u8 >))",
+            "impl ServerSession < state :: Done >",
+        ] {
+            assert!(output.contains(expected), "missing {}", expected);
+        }
+    }
+
+    #[test]
+    fn invalid_protocols() {
+        assert!(error(parse_quote! {
+            enum P {
+                #[terminal]
+                A,
+            }
+        })
+        .contains("from"));
+        assert!(error(parse_quote! {
+            enum P {
+                #[from(client)]
+                #[next(B)]
+                A,
+                #[from(server)]
+                #[next(A)]
+                B,
+            }
+        })
+        .contains("repeat"));
+        assert!(error(parse_quote! {
+            enum P {
+                #[from(client)]
+                #[next(B | C)]
+                A,
+                #[from(server)]
+                #[terminal]
+                B,
+                #[from(client)]
+                #[terminal]
+                C,
+            }
+        })
+        .contains("same side"));
+        assert!(error(parse_quote! {
+            enum P {
+                #[from(client)]
+                #[terminal]
+                A,
+                #[from(server)]
+                #[next(C)]
+                #[repeat]
+                B,
+                #[from(client)]
+                #[next(B)]
+                C,
+            }
+        })
+        .contains("never be reached"));
+        assert!(error(parse_quote! {
+            enum P {
+                #[from(client)]
+                #[terminal]
+                Initial,
+            }
+        })
+        .contains("reserved"));
+    }
+}
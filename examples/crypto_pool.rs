@@ -0,0 +1,228 @@
+//! Compare message processing latency of a `SystemManager` with and without a
+//! dedicated crypto pool while 50 peers exchange 64 KiB messages with it.
+//!
+//! Run with `cargo run --release --example crypto_pool`
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use drop::async_trait;
+use drop::crypto::key::exchange::{Exchanger, PublicKey};
+use drop::net::{Connector, TcpConnector, TcpListener};
+use drop::system::{
+    AllSampler, Handle, ManagerConfig, NetworkSender, Processor, Sampler,
+    Sender, System, SystemManager,
+};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::task;
+use tokio::time;
+
+const PEERS: usize = 50;
+const PAYLOAD_SIZE: usize = 64 * 1024;
+const DURATION: Duration = Duration::from_secs(3);
+const PING_INTERVAL: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum Bench {
+    /// Bulk data that only needs to be decrypted
+    Payload(Vec<u8>),
+    /// Small message carrying the time at which it was sent in nanoseconds
+    Ping(u128),
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock went backwards")
+        .as_nanos()
+}
+
+type Slot = Arc<Mutex<Option<Arc<NetworkSender<Bench>>>>>;
+
+/// A `Processor` that records the latency of every `Ping` it processes
+#[derive(Default)]
+struct Timing {
+    latencies: Arc<Mutex<Vec<Duration>>>,
+    sender: Slot,
+}
+
+#[derive(Clone)]
+struct NoHandle;
+
+#[async_trait]
+impl Handle<Bench, ()> for NoHandle {
+    type Error = io::Error;
+
+    async fn deliver(&mut self) -> Result<(), Self::Error> {
+        Err(io::Error::other("nothing to deliver"))
+    }
+
+    async fn try_deliver(&mut self) -> Result<Option<()>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn broadcast(&mut self, _: &Bench) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Processor<Bench, Bench, (), NetworkSender<Bench>> for Timing {
+    type Handle = NoHandle;
+
+    type Error = io::Error;
+
+    async fn process(
+        &self,
+        message: Bench,
+        _: PublicKey,
+        _: Arc<NetworkSender<Bench>>,
+    ) -> Result<(), Self::Error> {
+        if let Bench::Ping(sent) = message {
+            let latency = Duration::from_nanos((now() - sent) as u64);
+
+            self.latencies.lock().unwrap().push(latency);
+        }
+
+        Ok(())
+    }
+
+    async fn setup<SA: Sampler>(
+        &mut self,
+        _: Arc<SA>,
+        sender: Arc<NetworkSender<Bench>>,
+    ) -> Self::Handle {
+        self.sender.lock().unwrap().replace(sender);
+
+        NoHandle
+    }
+
+    async fn disconnect<SA: Sampler>(
+        &self,
+        _: PublicKey,
+        _: Arc<NetworkSender<Bench>>,
+        _: Arc<SA>,
+    ) {
+    }
+
+    async fn garbage_collection(&self) {}
+}
+
+/// Connect a peer that floods the node with payloads and drains everything
+/// the node sends back
+async fn flood(server: PublicKey, addr: SocketAddr) {
+    let connection = TcpConnector::new(Exchanger::random())
+        .connect(&server, &addr)
+        .await
+        .expect("connect failed");
+    let (mut read, mut write) = connection.split().expect("not secured");
+
+    task::spawn(async move { while read.receive::<Bench>().await.is_ok() {} });
+
+    let payload = Bench::Payload(vec![0; PAYLOAD_SIZE]);
+
+    while write.send(&payload).await.is_ok() {}
+}
+
+/// Connect a peer that periodically sends timestamped pings
+async fn probe(server: PublicKey, addr: SocketAddr) {
+    let mut connection = TcpConnector::new(Exchanger::random())
+        .connect(&server, &addr)
+        .await
+        .expect("connect failed");
+
+    loop {
+        if connection.send(&Bench::Ping(now())).await.is_err() {
+            return;
+        }
+
+        time::sleep(PING_INTERVAL).await;
+    }
+}
+
+async fn run(port: u16, config: ManagerConfig) -> Vec<Duration> {
+    let exchanger = Exchanger::random();
+    let server = *exchanger.keypair().public();
+    let addr: SocketAddr = (Ipv4Addr::LOCALHOST, port).into();
+
+    let mut system = System::default();
+    let listener = TcpListener::new(addr, exchanger)
+        .await
+        .expect("listen failed");
+    let _ = system.add_listener(listener).await;
+
+    let timing = Timing::default();
+    let latencies = timing.latencies.clone();
+    let sender = timing.sender.clone();
+    let handle = SystemManager::with_config(system, config)
+        .run(timing, AllSampler::default(), 4)
+        .await;
+
+    let floods = (0..PEERS)
+        .map(|_| task::spawn(flood(server, addr)))
+        .collect::<Vec<_>>();
+
+    handle
+        .wait_for_peers(PEERS, Some(Duration::from_secs(10)))
+        .await
+        .expect("peers failed to connect");
+
+    let probe = task::spawn(probe(server, addr));
+
+    // the node also broadcasts payloads to every peer
+    let sender = sender.lock().unwrap().clone().expect("not setup");
+    let broadcast = task::spawn(async move {
+        let payload = Bench::Payload(vec![0; PAYLOAD_SIZE]);
+
+        loop {
+            let keys = sender.keys().await;
+
+            let _ = sender.send_many(payload.clone(), keys.iter()).await;
+        }
+    });
+
+    time::sleep(DURATION).await;
+
+    probe.abort();
+    broadcast.abort();
+    floods.into_iter().for_each(|flood| flood.abort());
+
+    let latencies = latencies.lock().unwrap().clone();
+
+    latencies
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    if latencies.is_empty() {
+        println!("{:>12}: no ping processed", name);
+        return;
+    }
+
+    latencies.sort_unstable();
+
+    let percentile = |p: usize| {
+        latencies[(latencies.len() * p / 100).min(latencies.len() - 1)]
+    };
+
+    println!(
+        "{:>12}: {} pings, p50 {:?}, p99 {:?}, max {:?}",
+        name,
+        latencies.len(),
+        percentile(50),
+        percentile(99),
+        latencies[latencies.len() - 1]
+    );
+}
+
+#[tokio::main(worker_threads = 4)]
+async fn main() {
+    let inline = run(2100, ManagerConfig::default()).await;
+    let pooled = run(2200, ManagerConfig::default().crypto_pool(4)).await;
+
+    report("runtime", inline);
+    report("crypto pool", pooled);
+}
@@ -0,0 +1,234 @@
+use bincode::{
+    config::{
+        FixintEncoding, LittleEndian, RejectTrailing, WithOtherEndian,
+        WithOtherIntEncoding, WithOtherTrailing,
+    },
+    DefaultOptions, Options,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Type of the bincode configuration returned by [`bincode_options`]
+///
+/// [`bincode_options`]: self::bincode_options
+pub type BincodeOptions = WithOtherTrailing<
+    WithOtherIntEncoding<
+        WithOtherEndian<DefaultOptions, LittleEndian>,
+        FixintEncoding,
+    >,
+    RejectTrailing,
+>;
+
+/// Get the bincode configuration used by every serialization in this crate,
+/// which defines the wire format of drop:
+/// * integers are encoded using a fixed number of bytes in little endian,
+///   sequence and string lengths are encoded as `u64`
+/// * deserializing a buffer that contains bytes past the end of the encoded
+///   value fails
+/// * there is no limit on the size of encoded values, use
+///   `bincode_options().with_limit(limit)` to bound it. Limits apply when
+///   serializing and when deserializing from a reader, slices are already
+///   bounded by their length.
+pub fn bincode_options() -> BincodeOptions {
+    DefaultOptions::new()
+        .with_little_endian()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+/// Append the encoding of `value` to `buffer`. Clearing and reusing the same
+/// buffer for many values avoids allocating once per value.
+pub fn serialize_into<T>(buffer: &mut Vec<u8>, value: &T) -> bincode::Result<()>
+where
+    T: Serialize + ?Sized,
+{
+    bincode_options().serialize_into(buffer, value)
+}
+
+/// Get the number of bytes taken by the encoding of `value` without
+/// encoding it
+pub fn serialized_size<T>(value: &T) -> bincode::Result<u64>
+where
+    T: Serialize + ?Sized,
+{
+    bincode_options().serialized_size(value)
+}
+
+/// Decode a value from the start of `bytes`, returning it along with the
+/// number of bytes it was encoded on. Unlike [`bincode_options`] this accepts
+/// trailing bytes so that values encoded back to back in one buffer can be
+/// decoded one after the other.
+///
+/// [`bincode_options`]: self::bincode_options
+pub fn deserialize_prefix<T>(bytes: &[u8]) -> bincode::Result<(T, usize)>
+where
+    T: DeserializeOwned,
+{
+    let mut rest = bytes;
+    let value = bincode_options()
+        .allow_trailing_bytes()
+        .deserialize_from(&mut rest)?;
+
+    Ok((value, bytes.len() - rest.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Sample {
+        Unit,
+        Tuple(u8, i32),
+        Struct { name: String, values: Vec<u16> },
+    }
+
+    fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+        bincode_options()
+            .serialize(value)
+            .expect("serialize failed")
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(encode(&1u8), [1]);
+        assert_eq!(encode(&0x0102u16), [2, 1]);
+        assert_eq!(encode(&0x01020304u32), [4, 3, 2, 1]);
+        assert_eq!(encode(&-1i64), [0xff; 8]);
+        assert_eq!(
+            encode(&(u64::MAX - 1)),
+            [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(encode(&true), [1]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(encode(&"ab"), [2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b']);
+        assert_eq!(encode(&vec![1u8]), [1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(encode(&Some(3u16)), [1, 3, 0]);
+        assert_eq!(encode(&None::<u16>), [0]);
+
+        let map = (0u8..2).map(|x| (x, x)).collect::<BTreeMap<_, _>>();
+
+        assert_eq!(encode(&map), [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn enums() {
+        assert_eq!(encode(&Sample::Unit), [0, 0, 0, 0]);
+        assert_eq!(
+            encode(&Sample::Tuple(7, -2)),
+            [1, 0, 0, 0, 7, 0xfe, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            encode(&Sample::Struct {
+                name: "x".to_string(),
+                values: vec![0x0201],
+            }),
+            [
+                2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'x', 1, 0, 0, 0, 0, 0, 0,
+                0, 1, 2
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_legacy_encoding() {
+        let value = Sample::Struct {
+            name: "legacy".to_string(),
+            values: vec![1, 2, 3],
+        };
+
+        assert_eq!(
+            encode(&value),
+            bincode::serialize(&value).expect("serialize failed"),
+            "wire format differs from previous versions"
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_rejected() {
+        let mut bytes = encode(&Sample::Tuple(1, 2));
+
+        bincode_options()
+            .deserialize::<Sample>(&bytes)
+            .expect("deserialize failed");
+
+        bytes.push(0);
+
+        bincode_options()
+            .deserialize::<Sample>(&bytes)
+            .expect_err("trailing bytes accepted");
+    }
+
+    #[test]
+    fn limit() {
+        let value = vec![0u32; 16];
+        let bytes = encode(&value);
+
+        bincode_options()
+            .with_limit(8)
+            .serialize(&value)
+            .expect_err("limit not enforced when serializing");
+        bincode_options()
+            .with_limit(8)
+            .deserialize_from::<_, Vec<u32>>(&bytes[..])
+            .expect_err("limit not enforced when deserializing");
+    }
+
+    #[test]
+    fn buffer_reuse() {
+        let mut buffer = Vec::with_capacity(64);
+
+        for i in 0..16u32 {
+            let value = Sample::Tuple(i as u8, -(i as i32));
+
+            buffer.clear();
+            serialize_into(&mut buffer, &value).expect("serialize failed");
+
+            assert_eq!(buffer, encode(&value));
+            assert_eq!(
+                serialized_size(&value).expect("size failed"),
+                buffer.len() as u64
+            );
+        }
+
+        assert_eq!(buffer.capacity(), 64, "buffer was reallocated");
+    }
+
+    #[test]
+    fn back_to_back() {
+        let first = Sample::Struct {
+            name: "first".to_string(),
+            values: vec![1, 2],
+        };
+        let second = Sample::Tuple(3, 4);
+        let mut buffer = Vec::new();
+
+        serialize_into(&mut buffer, &first).expect("serialize failed");
+        serialize_into(&mut buffer, &second).expect("serialize failed");
+
+        let (decoded, used) =
+            deserialize_prefix::<Sample>(&buffer).expect("first failed");
+
+        assert_eq!(decoded, first);
+        assert_eq!(used as u64, serialized_size(&first).unwrap());
+
+        let (decoded, rest) = deserialize_prefix::<Sample>(&buffer[used..])
+            .expect("second failed");
+
+        assert_eq!(decoded, second);
+        assert_eq!(used + rest, buffer.len(), "bytes left over");
+
+        // truncating the second value only breaks decoding from its start
+        buffer.pop();
+
+        assert_eq!(deserialize_prefix::<Sample>(&buffer).unwrap().1, used);
+        deserialize_prefix::<Sample>(&buffer[used..])
+            .expect_err("truncated value decoded");
+    }
+}
@@ -20,25 +20,59 @@
 //! # }
 //! ```
 //!
+//! Threshold signatures are obtained by splitting a [`PrivateKey`] into
+//! [`KeyShare`]s, any `threshold` of which produce [`PartialSignature`]s
+//! that combine into a signature verified like any other
+//!
+//! ```
+//! # use drop::crypto::bls::{PartialSignature, PrivateKey};
+//! let key = PrivateKey::random().unwrap();
+//! let shares = key.split(3, 5).unwrap();
+//! let partials = shares[2..]
+//!     .iter()
+//!     .map(|share| share.sign(&0usize).unwrap())
+//!     .collect::<Vec<_>>();
+//! let signature = PartialSignature::combine(&partials, 3).unwrap();
+//!
+//! signature.verify(&0usize, &key.public().into()).unwrap();
+//! ```
+//!
 //! [`Iterator`]: std::iter::Iterator
 //! [`AggregatePublicKey`]: self::AggregatePublicKey
 //! [`Signature`]: self::Signature
+//! [`PrivateKey`]: self::PrivateKey
+//! [`KeyShare`]: self::KeyShare
+//! [`PartialSignature`]: self::PartialSignature
+
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::{self, FromIterator},
+    str::FromStr,
+};
 
-use std::{fmt, iter::FromIterator};
-
-use bincode::serialize_into;
+use bincode::Options;
 use blst::{
+    blst_bendian_from_scalar, blst_fr, blst_fr_add, blst_fr_from_scalar,
+    blst_fr_from_uint64, blst_fr_inverse, blst_fr_mul, blst_fr_sub,
+    blst_scalar, blst_scalar_from_bendian, blst_scalar_from_fr,
     min_sig::{
         AggregateSignature as BlsAggrSig, PublicKey as BlsPublicKey,
         SecretKey as BlsPrivateKey, Signature as BlsSignature,
     },
-    BLST_ERROR,
+    MultiPoint, BLST_ERROR,
 };
 use rand::{rngs::OsRng, RngCore};
 use serde::{de, Deserialize, Deserializer, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use zeroize::{Zeroize, Zeroizing};
 
-use super::BincodeError;
+use super::{
+    keys::{self, KeyError},
+    BincodeError,
+};
+use crate::codec::bincode_options;
 
 const BLST_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
 
@@ -65,6 +99,54 @@ pub enum BlsError {
     #[snafu(display("empty signature list"))]
     /// List of provided [`Signature`] is empty
     EmptySignature,
+
+    #[snafu(display(
+        "threshold of {} is invalid for {} shares",
+        threshold,
+        shares
+    ))]
+    /// The threshold for splitting a [`PrivateKey`] is either zero or larger
+    /// than the number of shares
+    ///
+    /// [`PrivateKey`]: self::PrivateKey
+    InvalidThreshold {
+        /// Requested threshold
+        threshold: usize,
+        /// Requested number of shares
+        shares: usize,
+    },
+
+    #[snafu(display(
+        "{} distinct partial signatures, threshold is {}",
+        got,
+        threshold
+    ))]
+    /// Not enough distinct [`PartialSignature`]s to reach the threshold
+    ///
+    /// [`PartialSignature`]: self::PartialSignature
+    NotEnoughShares {
+        /// Number of distinct partial signatures provided
+        got: usize,
+        /// Number of partial signatures required
+        threshold: usize,
+    },
+
+    #[snafu(display(
+        "partial signature of share {} checked against share {}",
+        got,
+        expected
+    ))]
+    /// A [`PartialSignature`] was checked using the [`PublicKeyShare`] of
+    /// another share
+    ///
+    /// [`PartialSignature`]: self::PartialSignature
+    /// [`PublicKeyShare`]: self::PublicKeyShare
+    ShareMismatch {
+        /// Index of the share of the `PublicKeyShare`
+        expected: usize,
+        /// Index of the share that made the partial signature
+        got: usize,
+    },
 }
 
 trait ToResult {
@@ -106,6 +188,7 @@ impl fmt::Display for BlstError {
             BLST_ERROR::BLST_SUCCESS => "no error",
             BLST_ERROR::BLST_BAD_ENCODING => "bad encoding",
             BLST_ERROR::BLST_POINT_NOT_ON_CURVE => "point not on curve",
+            BLST_ERROR::BLST_BAD_SCALAR => "bad scalar",
             BLST_ERROR::BLST_VERIFY_FAIL => "bad signature",
         };
 
@@ -163,7 +246,9 @@ impl PrivateKey {
     {
         let mut buffer = Vec::new();
 
-        serialize_into(&mut buffer, message).context(Serializer)?;
+        bincode_options()
+            .serialize_into(&mut buffer, message)
+            .context(Serializer)?;
 
         Ok(self.0.sign(buffer.as_slice(), BLST_DST, &[]).into())
     }
@@ -174,6 +259,49 @@ impl PrivateKey {
     pub fn public(&self) -> PublicKey {
         self.0.sk_to_pk().into()
     }
+
+    /// Split this `PrivateKey` into `shares` [`KeyShare`]s using Shamir's
+    /// secret sharing, such that the [`PartialSignature`]s of any
+    /// `threshold` of them combine into a signature that is valid for the
+    /// [`PublicKey`] of this `PrivateKey`
+    ///
+    /// [`KeyShare`]: self::KeyShare
+    /// [`PartialSignature`]: self::PartialSignature
+    /// [`PublicKey`]: self::PublicKey
+    pub fn split(
+        &self,
+        threshold: usize,
+        shares: usize,
+    ) -> Result<Vec<KeyShare>, BlsError> {
+        ensure!(
+            threshold > 0 && threshold <= shares,
+            InvalidThreshold { threshold, shares }
+        );
+
+        // the coefficients of the polynomial are as secret as the key itself
+        let coefficients = iter::once(Ok(self.clone()))
+            .chain((1..threshold).map(|_| Self::random()))
+            .map(|key| key.map(|key| Scalar::from_key(&key)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Zeroizing::new)?;
+
+        (1..=shares)
+            .map(|index| {
+                let x = Scalar::from_index(index);
+                let y = Zeroizing::new(
+                    coefficients
+                        .iter()
+                        .rev()
+                        .fold(Scalar::default(), |acc, c| acc.mul(&x).add(c)),
+                );
+
+                Ok(KeyShare {
+                    index,
+                    key: y.to_key()?,
+                })
+            })
+            .collect()
+    }
 }
 
 impl PartialEq for PrivateKey {
@@ -230,15 +358,27 @@ impl<'de> Deserialize<'de> for PrivateKey {
     }
 }
 
+/// Size of a compressed BLS `PublicKey`
+pub const PUBLIC_KEY_SIZE: usize = 96;
+
 /// A BLS `PublicKey`
-#[derive(Clone, Debug)]
-pub struct PublicKey(BlsPublicKey);
+#[derive(Clone, Copy, Debug)]
+pub struct PublicKey(BlsPublicKey, [u8; PUBLIC_KEY_SIZE]);
 
 impl PublicKey {
     /// Aggregate this `PublicKey`
     pub fn aggregate(self) -> AggregatePublicKey {
         AggregatePublicKey(vec![self.0])
     }
+
+    /// Read a `PublicKey` from its compressed form, checking that it is a
+    /// valid key
+    pub fn from_compressed(bytes: &[u8]) -> Result<Self, BlsError> {
+        BlsPublicKey::key_validate(bytes)
+            .map_err(Into::into)
+            .context(Bls)
+            .map(Into::into)
+    }
 }
 
 impl<'de> Deserialize<'de> for PublicKey {
@@ -268,7 +408,11 @@ impl<'de> Deserialize<'de> for PublicKey {
             }
         }
 
-        Ok(Self(deserializer.deserialize_bytes(ByteVisitor)?))
+        if deserializer.is_human_readable() {
+            keys::deserialize_str(deserializer)
+        } else {
+            Ok(deserializer.deserialize_bytes(ByteVisitor)?.into())
+        }
     }
 }
 
@@ -277,19 +421,63 @@ impl Serialize for PublicKey {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(&self.0.to_bytes())
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.1)
+        }
     }
 }
 
 impl PartialEq for PublicKey {
     fn eq(&self, other: &Self) -> bool {
-        self.0.to_bytes() == other.0.to_bytes()
+        self.1 == other.1
+    }
+}
+
+impl Eq for PublicKey {}
+
+impl PartialOrd for PublicKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PublicKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.1.cmp(&other.1)
+    }
+}
+
+impl Hash for PublicKey {
+    fn hash<H: Hasher>(&self, h: &mut H) {
+        h.write(&self.1)
+    }
+}
+
+impl AsRef<[u8]> for PublicKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.1
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        keys::display(&self.1, f)
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        keys::parse(s)
     }
 }
 
 impl From<BlsPublicKey> for PublicKey {
     fn from(k: BlsPublicKey) -> Self {
-        Self(k)
+        Self(k, k.to_bytes())
     }
 }
 
@@ -326,7 +514,7 @@ impl FromIterator<PublicKey> for AggregatePublicKey {
 }
 
 /// A BLS `Signature`
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Signature(BlsSignature);
 
 impl Signature {
@@ -372,6 +560,46 @@ impl From<BlsSignature> for Signature {
     }
 }
 
+impl Serialize for Signature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0.compress())
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Visitor;
+
+        struct ByteVisitor;
+
+        impl<'de> Visitor<'de> for ByteVisitor {
+            type Value = BlsSignature;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("byte representation of a bls signature")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                BlsSignature::sig_validate(v, false)
+                    .map_err(Into::into)
+                    .context(Bls)
+                    .map_err(E::custom)
+            }
+        }
+
+        Ok(Self(deserializer.deserialize_bytes(ByteVisitor)?))
+    }
+}
+
 /// An aggregation of many different signature into a single one
 #[derive(Clone)]
 pub struct AggregateSignature(BlsAggrSig);
@@ -438,7 +666,9 @@ impl AggregateSignature {
             .iter()
             .map(|x| {
                 let mut buffer = Vec::new();
-                serialize_into(&mut buffer, x).expect("serialize failed");
+                bincode_options()
+                    .serialize_into(&mut buffer, x)
+                    .expect("serialize failed");
 
                 buffer
             })
@@ -491,7 +721,9 @@ impl AggregateSignature {
         T: Serialize,
     {
         let mut buffer = Vec::new();
-        serialize_into(&mut buffer, message).expect("serialize failed");
+        bincode_options()
+            .serialize_into(&mut buffer, message)
+            .expect("serialize failed");
 
         let keys_refs = keys.as_slice().iter().collect::<Vec<_>>();
 
@@ -514,10 +746,280 @@ impl From<BlsAggrSig> for AggregateSignature {
     }
 }
 
+/// Serialize a message to be signed
+fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, BlsError> {
+    let mut buffer = Vec::new();
+
+    bincode_options()
+        .serialize_into(&mut buffer, message)
+        .context(Serializer)?;
+
+    Ok(buffer)
+}
+
+/// An element of the scalar field of the curve, used to split
+/// [`PrivateKey`]s and combine [`PartialSignature`]s
+///
+/// [`PrivateKey`]: self::PrivateKey
+/// [`PartialSignature`]: self::PartialSignature
+#[derive(Clone, Copy, Default)]
+struct Scalar(blst_fr);
+
+impl Scalar {
+    fn from_index(index: usize) -> Self {
+        let mut fr = blst_fr::default();
+
+        unsafe {
+            blst_fr_from_uint64(&mut fr, [index as u64, 0, 0, 0].as_ptr())
+        };
+
+        Self(fr)
+    }
+
+    fn from_key(key: &PrivateKey) -> Self {
+        let bytes = Zeroizing::new(key.to_vec());
+        let mut scalar = blst_scalar::default();
+        let mut fr = blst_fr::default();
+
+        unsafe {
+            blst_scalar_from_bendian(&mut scalar, bytes.as_ptr());
+            blst_fr_from_scalar(&mut fr, &scalar);
+        }
+
+        scalar.b.zeroize();
+
+        Self(fr)
+    }
+
+    fn to_scalar(self) -> blst_scalar {
+        let mut scalar = blst_scalar::default();
+
+        unsafe { blst_scalar_from_fr(&mut scalar, &self.0) };
+
+        scalar
+    }
+
+    fn to_key(self) -> Result<PrivateKey, BlsError> {
+        let mut bytes = Zeroizing::new([0; 32]);
+        let mut scalar = self.to_scalar();
+
+        unsafe { blst_bendian_from_scalar(bytes.as_mut_ptr(), &scalar) };
+
+        scalar.b.zeroize();
+
+        PrivateKey::new(bytes.as_ref())
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut fr = blst_fr::default();
+
+        unsafe { blst_fr_add(&mut fr, &self.0, &other.0) };
+
+        Self(fr)
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        let mut fr = blst_fr::default();
+
+        unsafe { blst_fr_sub(&mut fr, &self.0, &other.0) };
+
+        Self(fr)
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut fr = blst_fr::default();
+
+        unsafe { blst_fr_mul(&mut fr, &self.0, &other.0) };
+
+        Self(fr)
+    }
+
+    fn inverse(&self) -> Self {
+        let mut fr = blst_fr::default();
+
+        unsafe { blst_fr_inverse(&mut fr, &self.0) };
+
+        Self(fr)
+    }
+}
+
+impl Zeroize for Scalar {
+    fn zeroize(&mut self) {
+        self.0.l.zeroize();
+    }
+}
+
+/// A share of a [`PrivateKey`] obtained using [`PrivateKey::split`]
+///
+/// [`PrivateKey`]: self::PrivateKey
+/// [`PrivateKey::split`]: self::PrivateKey::split
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyShare {
+    index: usize,
+    key: PrivateKey,
+}
+
+impl KeyShare {
+    /// Get the index of this `KeyShare`, starting from 1
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Sign a message using this `KeyShare`
+    pub fn sign<T>(&self, message: &T) -> Result<PartialSignature, BlsError>
+    where
+        T: Serialize,
+    {
+        Ok(PartialSignature {
+            index: self.index,
+            signature: self.key.sign(message)?,
+        })
+    }
+
+    /// Get the [`PublicKeyShare`] used to check the [`PartialSignature`]s
+    /// made using this `KeyShare`
+    ///
+    /// [`PublicKeyShare`]: self::PublicKeyShare
+    /// [`PartialSignature`]: self::PartialSignature
+    pub fn public(&self) -> PublicKeyShare {
+        PublicKeyShare {
+            index: self.index,
+            key: self.key.public(),
+        }
+    }
+}
+
+/// The public counterpart of a [`KeyShare`], used to detect invalid
+/// [`PartialSignature`]s before combining them
+///
+/// [`KeyShare`]: self::KeyShare
+/// [`PartialSignature`]: self::PartialSignature
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PublicKeyShare {
+    index: usize,
+    key: PublicKey,
+}
+
+impl PublicKeyShare {
+    /// Get the index of the share this `PublicKeyShare` belongs to
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the [`PublicKey`] of the share
+    ///
+    /// [`PublicKey`]: self::PublicKey
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    /// Check that `signature` was made for `message` by the share this
+    /// `PublicKeyShare` belongs to
+    pub fn verify<T>(
+        &self,
+        message: &T,
+        signature: &PartialSignature,
+    ) -> Result<(), BlsError>
+    where
+        T: Serialize,
+    {
+        ensure!(
+            signature.index == self.index,
+            ShareMismatch {
+                expected: self.index,
+                got: signature.index,
+            }
+        );
+
+        signature
+            .signature
+            .0
+            .verify(true, &encode(message)?, BLST_DST, &[], &self.key.0, true)
+            .into_result(())
+            .context(Bls)
+    }
+}
+
+/// A signature made using a [`KeyShare`]
+///
+/// [`KeyShare`]: self::KeyShare
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PartialSignature {
+    index: usize,
+    signature: Signature,
+}
+
+impl PartialSignature {
+    /// Get the index of the share that made this `PartialSignature`
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Combine the `PartialSignature`s of `threshold` distinct shares into
+    /// an [`AggregateSignature`] that is valid for the [`PublicKey`] of the
+    /// split [`PrivateKey`]. Only the first `PartialSignature` of each share
+    /// is used, and those after the first `threshold` shares are ignored.
+    ///
+    /// Invalid `PartialSignature`s yield an invalid signature, they should
+    /// be checked using [`PublicKeyShare::verify`] beforehand.
+    ///
+    /// [`AggregateSignature`]: self::AggregateSignature
+    /// [`PublicKey`]: self::PublicKey
+    /// [`PrivateKey`]: self::PrivateKey
+    /// [`PublicKeyShare::verify`]: self::PublicKeyShare::verify
+    pub fn combine(
+        shares: &[Self],
+        threshold: usize,
+    ) -> Result<AggregateSignature, BlsError> {
+        ensure!(threshold > 0, EmptySignature);
+
+        let mut seen = HashSet::with_capacity(threshold);
+        let shares = shares
+            .iter()
+            .filter(|share| seen.insert(share.index))
+            .take(threshold)
+            .collect::<Vec<_>>();
+
+        ensure!(
+            shares.len() == threshold,
+            NotEnoughShares {
+                got: shares.len(),
+                threshold,
+            }
+        );
+
+        let xs = shares
+            .iter()
+            .map(|share| Scalar::from_index(share.index))
+            .collect::<Vec<_>>();
+
+        // lagrange coefficients of each share for interpolating at zero
+        let scalars = xs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, xi)| {
+                let (num, den) =
+                    xs.iter().enumerate().filter(|(j, _)| *j != i).fold(
+                        (Scalar::from_index(1), Scalar::from_index(1)),
+                        |(num, den), (_, xj)| {
+                            (num.mul(xj), den.mul(&xj.sub(xi)))
+                        },
+                    );
+
+                num.mul(&den.inverse()).to_scalar().b
+            })
+            .collect::<Vec<_>>();
+        let signatures = shares
+            .iter()
+            .map(|share| share.signature.0)
+            .collect::<Vec<_>>();
+
+        Ok(signatures.mult(&scalars, 255).into())
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use bincode::deserialize_from;
-
     use super::*;
 
     fn generate_sequence(
@@ -553,7 +1055,7 @@ mod test {
 
         let public =
             keys.map(PrivateKey::public).collect::<AggregatePublicKey>();
-        let initial = signatures.next().map(Clone::clone).unwrap().aggregate();
+        let initial = signatures.next().cloned().unwrap().aggregate();
 
         let aggregate = signatures.fold(initial, |mut acc, curr| {
             acc.aggregate(curr).unwrap();
@@ -589,7 +1091,7 @@ mod test {
 
         let (keys, sigs): (Vec<_>, Vec<_>) = sign_same(MSG, 10).unzip();
 
-        let aggr_sig = Signature::aggregate_iter(sigs.into_iter()).unwrap();
+        let aggr_sig = Signature::aggregate_iter(sigs).unwrap();
         let aggr_key = keys.into_iter().collect::<AggregatePublicKey>();
 
         aggr_sig.verify(&MSG, &aggr_key).expect("verify failed");
@@ -621,30 +1123,139 @@ mod test {
         let key = PrivateKey::random().unwrap();
         let mut buffer = Vec::new();
 
-        serialize_into(&mut buffer, &key).expect("serialize failed");
+        bincode_options()
+            .serialize_into(&mut buffer, &key)
+            .expect("serialize failed");
 
-        let dkey =
-            deserialize_from(Cursor::new(buffer)).expect("deserialize failed");
+        let dkey = bincode_options()
+            .deserialize_from(Cursor::new(buffer))
+            .expect("deserialize failed");
 
         assert_eq!(key, dkey, "wrong key");
 
         let pkey = key.public();
         let mut buffer = Vec::new();
 
-        serialize_into(&mut buffer, &pkey).expect("serialize failed");
+        bincode_options()
+            .serialize_into(&mut buffer, &pkey)
+            .expect("serialize failed");
 
-        let dpkey =
-            deserialize_from(Cursor::new(buffer)).expect("deserialize failed");
+        let dpkey = bincode_options()
+            .deserialize_from(Cursor::new(buffer))
+            .expect("deserialize failed");
 
         assert_eq!(pkey, dpkey, "wrong pubkey");
     }
 
+    #[test]
+    fn threshold_sign_and_combine() {
+        const MSG: usize = 0;
+
+        let key = PrivateKey::random().unwrap();
+        let public = key.public().into();
+        let shares = key.split(3, 5).expect("split failed");
+        let partials = shares
+            .iter()
+            .map(|share| share.sign(&MSG).expect("sign failed"))
+            .collect::<Vec<_>>();
+
+        for (share, partial) in shares.iter().zip(&partials) {
+            share.public().verify(&MSG, partial).expect("verify failed");
+        }
+
+        for subset in [&partials[..3], &partials[2..], &partials[1..4]] {
+            PartialSignature::combine(subset, 3)
+                .expect("combine failed")
+                .verify(&MSG, &public)
+                .expect("verify failed");
+        }
+
+        // duplicate shares don't count towards the threshold
+        let duplicated = [partials[0].clone(), partials[0].clone()];
+
+        assert!(matches!(
+            PartialSignature::combine(&duplicated, 2),
+            Err(BlsError::NotEnoughShares {
+                got: 1,
+                threshold: 2
+            })
+        ));
+
+        PartialSignature::combine(&partials[..2], 2)
+            .expect("combine failed")
+            .verify(&MSG, &public)
+            .expect_err("verified below threshold");
+    }
+
+    #[test]
+    fn partial_signature_serialize() {
+        const MSG: usize = 0;
+
+        let key = PrivateKey::random().unwrap();
+        let shares = key.split(2, 3).expect("split failed");
+        let partials = shares
+            .iter()
+            .map(|share| {
+                let partial = share.sign(&MSG).expect("sign failed");
+                let bytes = bincode_options()
+                    .serialize(&partial)
+                    .expect("serialize failed");
+
+                bincode_options()
+                    .deserialize::<PartialSignature>(&bytes)
+                    .expect("deserialize failed")
+            })
+            .collect::<Vec<_>>();
+
+        for (share, partial) in shares.iter().zip(&partials) {
+            assert_eq!(partial.index(), share.index(), "wrong index");
+            share.public().verify(&MSG, partial).expect("verify failed");
+        }
+
+        PartialSignature::combine(&partials, 2)
+            .expect("combine failed")
+            .verify(&MSG, &key.public().into())
+            .expect("verify failed");
+
+        bincode_options()
+            .deserialize::<PartialSignature>(&[0; 16])
+            .expect_err("deserialized garbage");
+    }
+
+    #[test]
+    fn threshold_bad_partial() {
+        let key = PrivateKey::random().unwrap();
+        let shares = key.split(2, 3).expect("split failed");
+        let partial = shares[0].sign(&0usize).expect("sign failed");
+
+        shares[0]
+            .public()
+            .verify(&1usize, &partial)
+            .expect_err("verified wrong message");
+
+        assert!(matches!(
+            shares[1].public().verify(&0usize, &partial),
+            Err(BlsError::ShareMismatch {
+                expected: 2,
+                got: 1
+            })
+        ));
+        assert!(matches!(
+            key.split(4, 3),
+            Err(BlsError::InvalidThreshold {
+                threshold: 4,
+                shares: 3
+            })
+        ));
+    }
+
     #[test]
     fn bad_deserialize() {
         use std::io::Cursor;
         let bad = [0u8; 32];
 
-        let key: Result<PrivateKey, _> = deserialize_from(Cursor::new(bad));
+        let key: Result<PrivateKey, _> =
+            bincode_options().deserialize_from(Cursor::new(bad));
 
         key.expect_err("deserialized garbage");
     }
@@ -1,18 +1,19 @@
 use std::{
     cmp, fmt,
     fmt::{Debug, Display},
+    io,
 };
 
-use bincode::serialize;
+use bincode::Options;
 pub use blake3::Hash;
-use blake3::Hasher as BlakeHasher;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 
 use super::{key::Key, BincodeError};
+use crate::codec::bincode_options;
 
-/// Static size for hashes
-pub const SIZE: usize = blake3::OUT_LEN;
+/// Static size for hashes computed with the default algorithm
+pub const SIZE: usize = Blake3::SIZE;
 
 #[derive(Debug, Snafu)]
 /// Errors enountered by [`Hasher`]
@@ -27,37 +28,228 @@ pub enum HashError {
     },
 }
 
-/// Wrapper for blake3 hasher
-pub struct Hasher(BlakeHasher);
+mod sealed {
+    pub trait Sealed {}
+}
 
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Hash")]
-struct SerdeDigest(#[serde(getter = "Hash::as_bytes")] pub(crate) [u8; SIZE]);
+/// A hash function that can be used to compute a [`GenericDigest`]. <br />
+/// This trait is sealed, the available algorithms are [`Blake3`] and, with
+/// the `sha256` feature, [`Sha256`].
+///
+/// [`GenericDigest`]: self::GenericDigest
+/// [`Blake3`]: self::Blake3
+/// [`Sha256`]: self::Sha256
+pub trait HashAlgorithm:
+    sealed::Sealed
+    + Copy
+    + Debug
+    + Default
+    + Eq
+    + Ord
+    + std::hash::Hash
+    + Send
+    + Sync
+    + 'static
+{
+    /// Size of the digests produced by this algorithm in bytes
+    const SIZE: usize;
+
+    /// Human readable name of this algorithm
+    const NAME: &'static str;
+
+    /// Raw bytes of a digest
+    type Output: AsRef<[u8]>
+        + AsMut<[u8]>
+        + Copy
+        + Debug
+        + Default
+        + Eq
+        + Ord
+        + std::hash::Hash
+        + Send
+        + Sync
+        + Serialize
+        + DeserializeOwned
+        + 'static;
+
+    /// Intermediate state of a computation
+    type State: Clone + Send + Sync;
+
+    /// Start an unkeyed computation
+    fn start() -> Self::State;
+
+    /// Start a computation producing a MAC using the given `Key`
+    fn start_keyed(key: &Key) -> Self::State;
+
+    /// Feed a chunk of bytes to an ongoing computation
+    fn update(state: &mut Self::State, chunk: &[u8]);
+
+    /// Complete a computation
+    fn finish(state: Self::State) -> Self::Output;
+}
+
+/// The blake3 hash function, used by default. MACs use its keyed mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Blake3;
+
+impl sealed::Sealed for Blake3 {}
+
+impl HashAlgorithm for Blake3 {
+    const SIZE: usize = blake3::OUT_LEN;
+
+    const NAME: &'static str = "blake3";
+
+    type Output = [u8; blake3::OUT_LEN];
+
+    type State = blake3::Hasher;
+
+    fn start() -> Self::State {
+        blake3::Hasher::new()
+    }
+
+    fn start_keyed(key: &Key) -> Self::State {
+        blake3::Hasher::new_keyed(key.as_ref())
+    }
+
+    fn update(state: &mut Self::State, chunk: &[u8]) {
+        state.update(chunk);
+    }
+
+    fn finish(state: Self::State) -> Self::Output {
+        state.finalize().into()
+    }
+}
+
+#[cfg(feature = "sha256")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sha256")))]
+pub use sha256::Sha256;
+
+#[cfg(feature = "sha256")]
+mod sha256 {
+    use sha2::Digest as _;
+
+    use super::{sealed, HashAlgorithm, Key};
+
+    const BLOCK_SIZE: usize = 64;
+
+    /// The SHA-256 hash function. MACs use HMAC-SHA256.
+    #[derive(
+        Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+    )]
+    pub struct Sha256;
+
+    /// State of an ongoing SHA-256 computation, the outer hash is only used
+    /// when computing a MAC
+    #[derive(Clone)]
+    pub struct Sha256State {
+        inner: sha2::Sha256,
+        outer: Option<sha2::Sha256>,
+    }
+
+    impl sealed::Sealed for Sha256 {}
 
-impl From<SerdeDigest> for Hash {
-    fn from(d: SerdeDigest) -> Self {
-        Self::from(d.0)
+    impl HashAlgorithm for Sha256 {
+        const SIZE: usize = 32;
+
+        const NAME: &'static str = "sha256";
+
+        type Output = [u8; 32];
+
+        type State = Sha256State;
+
+        fn start() -> Self::State {
+            Sha256State {
+                inner: sha2::Sha256::new(),
+                outer: None,
+            }
+        }
+
+        fn start_keyed(key: &Key) -> Self::State {
+            let pad = |byte: u8| {
+                let mut block = [byte; BLOCK_SIZE];
+
+                block
+                    .iter_mut()
+                    .zip(key.as_ref())
+                    .for_each(|(b, k)| *b ^= k);
+
+                sha2::Sha256::new().chain(block)
+            };
+
+            Sha256State {
+                inner: pad(0x36),
+                outer: Some(pad(0x5c)),
+            }
+        }
+
+        fn update(state: &mut Self::State, chunk: &[u8]) {
+            state.inner.update(chunk);
+        }
+
+        fn finish(state: Self::State) -> Self::Output {
+            let inner = state.inner.finalize();
+
+            match state.outer {
+                Some(outer) => outer.chain(inner).finalize().into(),
+                None => inner.into(),
+            }
+        }
     }
 }
 
-/// A hash digest using blake3
-#[derive(Serialize, Deserialize, Eq, PartialEq, Copy, Clone, Hash)]
-pub struct Digest(#[serde(with = "SerdeDigest")] Hash);
+/// A hash digest computed using the algorithm `A`. <br />
+/// Digests computed using different algorithms have different types and
+/// cannot be mixed up:
+///
+/// ```compile_fail
+/// use drop::crypto::hash::{hash, hash_with, Sha256};
+///
+/// let sha = hash_with::<Sha256, _>(&0u32).unwrap();
+///
+/// assert_eq!(hash(&0u32).unwrap(), sha);
+/// ```
+#[derive(Eq, PartialEq, Copy, Clone, Hash)]
+pub struct GenericDigest<A: HashAlgorithm>(pub(crate) A::Output);
 
-impl Digest {
+/// A hash digest using the default algorithm, blake3
+pub type Digest = GenericDigest<Blake3>;
+
+impl<A: HashAlgorithm> GenericDigest<A> {
     /// Get the content of this `Digest` as a reference to a slice of bytes
-    pub fn as_bytes(&self) -> &[u8; SIZE] {
-        self.0.as_bytes()
+    pub fn as_bytes(&self) -> &A::Output {
+        &self.0
     }
 }
 
-impl Ord for Digest {
+/// Serialized form of a `GenericDigest`
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Digest")]
+struct SerdeDigest<O>(O);
+
+impl<A: HashAlgorithm> Serialize for GenericDigest<A> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        SerdeDigest(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de, A: HashAlgorithm> Deserialize<'de> for GenericDigest<A> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        SerdeDigest::deserialize(deserializer).map(|d| Self(d.0))
+    }
+}
+
+impl<A: HashAlgorithm> Ord for GenericDigest<A> {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        Ord::cmp(self.0.as_bytes(), other.0.as_bytes())
+        Ord::cmp(self.0.as_ref(), other.0.as_ref())
     }
 }
 
-impl PartialOrd for Digest {
+impl<A: HashAlgorithm> PartialOrd for GenericDigest<A> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         Some(self.cmp(other))
     }
@@ -65,20 +257,23 @@ impl PartialOrd for Digest {
 
 impl From<Hash> for Digest {
     fn from(h: Hash) -> Self {
-        Self(h)
+        Self(h.into())
     }
 }
 
-impl From<[u8; SIZE]> for Digest {
-    fn from(s: [u8; SIZE]) -> Self {
-        Self(Hash::from(s))
+impl<A, const N: usize> From<[u8; N]> for GenericDigest<A>
+where
+    A: HashAlgorithm<Output = [u8; N]>,
+{
+    fn from(s: [u8; N]) -> Self {
+        Self(s)
     }
 }
 
-impl Display for Digest {
+impl<A: HashAlgorithm> Display for GenericDigest<A> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "<")?;
-        for byte in self.as_bytes() {
+        for byte in self.0.as_ref() {
             write!(fmt, "{:02x}", byte)?;
         }
         write!(fmt, ">")?;
@@ -87,55 +282,101 @@ impl Display for Digest {
     }
 }
 
-impl Debug for Digest {
+impl<A: HashAlgorithm> Debug for GenericDigest<A> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "{}", self)
     }
 }
 
+/// Incremental hasher using the algorithm `A`, blake3 by default. <br />
+/// Feeding it several messages using `Hasher::update_message` yields the
+/// same digest as hashing a tuple of these messages at once, without
+/// serializing them to a single buffer first.
+pub struct Hasher<A: HashAlgorithm = Blake3>(A::State);
+
 impl Hasher {
     /// Create a `Hasher` without a `Key`
     pub fn new() -> Self {
-        Self(BlakeHasher::new())
+        Self::with_algorithm()
     }
 
     /// Create a `Hasher` with a specified `Key`. <br/ >
     /// This, in effect, creates a MAC producer for authenticating data.
     pub fn keyed(key: &Key) -> Self {
-        Self(BlakeHasher::new_keyed(key.as_ref()))
+        Self::keyed_with_algorithm(key)
+    }
+}
+
+impl<A: HashAlgorithm> Hasher<A> {
+    /// Create a `Hasher` using algorithm `A` without a `Key`
+    pub fn with_algorithm() -> Self {
+        Self(A::start())
+    }
+
+    /// Create a `Hasher` using algorithm `A` with a specified `Key`
+    pub fn keyed_with_algorithm(key: &Key) -> Self {
+        Self(A::start_keyed(key))
     }
 
     /// Feed a chunk of bytes to this hasher
     pub fn update(&mut self, chunk: &[u8]) {
-        self.0.update(chunk);
+        A::update(&mut self.0, chunk);
+    }
+
+    /// Serialize a message directly into this hasher
+    pub fn update_message<M: Serialize>(
+        &mut self,
+        message: &M,
+    ) -> Result<(), HashError> {
+        bincode_options()
+            .serialize_into(self, message)
+            .context(SerializeError)
     }
 
     /// Considers the data complete and returns the resulting hash
-    pub fn finalize(self) -> Digest {
-        self.0.finalize().into()
+    pub fn finalize(self) -> GenericDigest<A> {
+        GenericDigest(A::finish(self.0))
     }
 }
 
-impl Default for Hasher {
+impl<A: HashAlgorithm> Default for Hasher<A> {
     fn default() -> Self {
-        Self::new()
+        Self::with_algorithm()
+    }
+}
+
+impl<A: HashAlgorithm> io::Write for Hasher<A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
-fn do_hash<M: Serialize>(
-    mut hasher: Hasher,
+fn do_hash<A: HashAlgorithm, M: Serialize>(
+    mut hasher: Hasher<A>,
     message: &M,
-) -> Result<Digest, HashError> {
-    hasher.update(&serialize(message).context(SerializeError)?);
+) -> Result<GenericDigest<A>, HashError> {
+    hasher.update_message(message)?;
 
     Ok(hasher.finalize())
 }
 
 /// Computes the cryptographic hash of the specified message.
 pub fn hash<M: Serialize>(message: &M) -> Result<Digest, HashError> {
-    let hasher = Hasher::new();
+    hash_with::<Blake3, _>(message)
+}
 
-    do_hash(hasher, message)
+/// Computes the cryptographic hash of the specified message using the
+/// algorithm `A`
+pub fn hash_with<A: HashAlgorithm, M: Serialize>(
+    message: &M,
+) -> Result<GenericDigest<A>, HashError> {
+    do_hash(Hasher::<A>::with_algorithm(), message)
 }
 
 /// Computes the message authentication code for the given message and `Key`.
@@ -143,9 +384,16 @@ pub fn authenticate<Message: Serialize>(
     key: &Key,
     message: &Message,
 ) -> Result<Digest, HashError> {
-    let hasher = Hasher::keyed(key);
+    authenticate_with::<Blake3, _>(key, message)
+}
 
-    do_hash(hasher, message)
+/// Computes the message authentication code for the given message and `Key`
+/// using the algorithm `A`
+pub fn authenticate_with<A: HashAlgorithm, Message: Serialize>(
+    key: &Key,
+    message: &Message,
+) -> Result<GenericDigest<A>, HashError> {
+    do_hash(Hasher::<A>::keyed_with_algorithm(key), message)
 }
 
 #[cfg(test)]
@@ -234,6 +482,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn correct_hash_raw_bytes() {
+        let mut hasher = Hasher::new();
+
+        hasher.update(b"abc");
+
+        assert_eq!(
+            hasher.finalize(),
+            Digest::from_hex(
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn serialized_as_bytes() {
+        let digest = hash(&0u32).unwrap();
+        let bytes = bincode_options().serialize(&digest).unwrap();
+
+        assert_eq!(bytes, digest.as_bytes(), "digest serialized with framing");
+        assert_eq!(
+            bincode_options().deserialize::<Digest>(&bytes).unwrap(),
+            digest
+        );
+    }
+
+    #[cfg(feature = "sha256")]
+    mod sha256 {
+        use super::*;
+
+        fn sha256(hex: &str) -> GenericDigest<Sha256> {
+            GenericDigest::from_hex(hex).expect("failed to create digest")
+        }
+
+        #[test]
+        fn correct_raw_bytes() {
+            let mut hasher = Hasher::<Sha256>::with_algorithm();
+
+            hasher.update(b"abc");
+
+            assert_eq!(
+                hasher.finalize(),
+                sha256(
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                )
+            );
+        }
+
+        #[test]
+        fn correct_hashes() {
+            assert_eq!(
+                hash_with::<Sha256, _>(&0u32).unwrap(),
+                sha256(
+                    "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119"
+                )
+            );
+            assert_eq!(
+                hash_with::<Sha256, _>(&"Hello World!").unwrap(),
+                sha256(
+                    "297f84b7387684a04664d925e21e54c9998954a31cce620a65630504f772ada1"
+                )
+            );
+            assert_eq!(
+                hash_with::<Sha256, _>(&[0u32, 1, 2, 3, 4, 5, 6, 7]).unwrap(),
+                sha256(
+                    "ff1f6ee5d67458cfac950f62e93042e21fcb867e2234dcc8721801231064ad40"
+                )
+            );
+        }
+
+        #[test]
+        fn correct_macs() {
+            let key = Key::from_hex(KEY).unwrap();
+
+            assert_eq!(
+                authenticate_with::<Sha256, _>(&key, &0u32).unwrap(),
+                sha256(
+                    "08edbc1d865bdb94b8bd59641a38f1cd899fbd2c13c88126470bdef4e091f7a9"
+                )
+            );
+            assert_eq!(
+                authenticate_with::<Sha256, _>(&key, &"Hello World!").unwrap(),
+                sha256(
+                    "0f8109e54ed719cf88a60028ff53063f61f8cb5fc1719a5e40ce288b34a651dc"
+                )
+            );
+        }
+
+        #[test]
+        fn distinct_from_default() {
+            let default = hash(&0u32).unwrap();
+            let sha = hash_with::<Sha256, _>(&0u32).unwrap();
+
+            assert_ne!(default.as_bytes(), sha.as_bytes());
+        }
+    }
+
+    #[test]
+    fn incremental_matches_tuple() {
+        let mut hasher = Hasher::new();
+
+        hasher.update_message(&0u32).expect("failed to hash data");
+        hasher
+            .update_message(&"piece")
+            .expect("failed to hash data");
+        hasher
+            .update_message(&vec![1u8, 2, 3])
+            .expect("failed to hash data");
+
+        assert_eq!(
+            hasher.finalize(),
+            hash(&(0u32, "piece", vec![1u8, 2, 3])).expect("failed to hash"),
+            "incremental digest differs"
+        );
+    }
+
     #[test]
     fn hash_collisions() {
         let mut set = HashSet::new();
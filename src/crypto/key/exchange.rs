@@ -1,14 +1,29 @@
-use std::fmt;
+use std::{fmt, path::Path, str::FromStr};
 
+use bincode::Options;
+use curve25519_dalek::{constants::ED25519_BASEPOINT_TABLE, scalar::Scalar};
+use ed25519_dalek::{
+    ExpandedSecretKey, PublicKey as DalekPublicKey, SignatureError,
+};
 use rand::rngs::OsRng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snafu::{ensure, ResultExt, Snafu};
 
 use super::{
-    super::stream::{Pull, Push},
+    super::{
+        keys::{self, KeyError},
+        sign::{
+            Dalek, PublicKey as SignPublicKey, SignError, SignSerialize,
+            Signature, VerifyError,
+        },
+        stream::{Pull, Push},
+    },
     Key,
 };
+use crate::codec::bincode_options;
+use crate::persist::{self, PersistError, Versioned};
 
-#[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
 /// A `PublicKey` used to compute a shared secret with a remote party
 // We need a separated type for `PublicKey` as it needs to implement `Ord` for
 // use in a `BTreeSet`. As rust doesn't allow (yet) for a foreign trait to be
@@ -23,10 +38,15 @@ impl From<crypto_kx::PublicKey> for PublicKey {
 
 impl fmt::Display for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for b in self.0.as_ref() {
-            write!(f, "{:02x}", b)?;
-        }
-        Ok(())
+        keys::display(self.as_ref(), f)
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        keys::parse(s)
     }
 }
 
@@ -38,6 +58,32 @@ impl fmt::Debug for PublicKey {
     }
 }
 
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            keys::deserialize_str(deserializer)
+        } else {
+            crypto_kx::PublicKey::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
 impl PartialOrd for PublicKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -56,6 +102,135 @@ impl AsRef<[u8]> for PublicKey {
     }
 }
 
+impl PublicKey {
+    /// Verify a `Signature` produced by `KeyPair::sign` using this
+    /// `PublicKey`
+    pub fn verify<T: Serialize>(
+        &self,
+        message: &T,
+        signature: &Signature,
+    ) -> Result<(), VerifyError> {
+        signature.verify(message, &self.to_signing()?)
+    }
+
+    /// Get the `Fingerprint` of this `PublicKey`, used to tell peers apart in
+    /// logs
+    pub fn fingerprint(&self) -> Fingerprint {
+        let hash = blake3::hash(self.as_ref());
+        let mut bytes = [0; FINGERPRINT_SIZE];
+
+        bytes.copy_from_slice(&hash.as_bytes()[..FINGERPRINT_SIZE]);
+
+        Fingerprint(bytes)
+    }
+
+    /// Convert this Montgomery `PublicKey` to the equivalent Edwards key with
+    /// a positive sign, as specified by XEdDSA
+    fn to_signing(self) -> Result<SignPublicKey, VerifyError> {
+        SignPublicKey::try_from(&self)
+            .map_err(|_| SignatureError::new())
+            .context(Dalek)
+    }
+}
+
+/// Size of a `Fingerprint` in bytes
+pub const FINGERPRINT_SIZE: usize = 8;
+
+/// A short identifier of a `PublicKey`, made of the first 8 bytes of the
+/// blake3 hash of the key and displayed as 16 hexadecimal characters. <br />
+/// `Fingerprint`s are meant for correlating logs across nodes: different
+/// keys can share a `Fingerprint`, so they must never be used to
+/// authenticate or authorize a peer.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fingerprint([u8; FINGERPRINT_SIZE]);
+
+impl Fingerprint {
+    /// Get the bytes of this `Fingerprint`
+    pub fn as_bytes(&self) -> &[u8; FINGERPRINT_SIZE] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        keys::display(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Error encountered when parsing a `Fingerprint`
+pub enum FingerprintError {
+    #[snafu(display("malformed fingerprint: {}", source))]
+    /// The fingerprint was not valid hexadecimal
+    MalformedFingerprint {
+        /// Error source
+        source: hex::FromHexError,
+    },
+
+    #[snafu(display(
+        "fingerprint must be {} bytes long, got {}",
+        FINGERPRINT_SIZE,
+        actual
+    ))]
+    /// The fingerprint did not have the right size
+    FingerprintLength {
+        /// Size of the rejected fingerprint
+        actual: usize,
+    },
+}
+
+impl FromStr for Fingerprint {
+    type Err = FingerprintError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).context(MalformedFingerprint)?;
+
+        ensure!(
+            bytes.len() == FINGERPRINT_SIZE,
+            FingerprintLength {
+                actual: bytes.len()
+            }
+        );
+
+        let mut fingerprint = [0; FINGERPRINT_SIZE];
+
+        fingerprint.copy_from_slice(&bytes);
+
+        Ok(Self(fingerprint))
+    }
+}
+
+impl From<&PublicKey> for Fingerprint {
+    fn from(key: &PublicKey) -> Self {
+        key.fingerprint()
+    }
+}
+
+#[cfg(feature = "interop-keys")]
+impl PublicKey {
+    /// Convert an Ed25519 signing `PublicKey` to the X25519 `PublicKey` of
+    /// the same identity, rejecting keys of small order. This is the same
+    /// conversion as `PublicKey::try_from`.
+    pub fn from_signing(key: &SignPublicKey) -> Result<Self, VerifyError> {
+        Self::try_from(key)
+            .map_err(|_| SignatureError::new())
+            .context(Dalek)
+    }
+
+    /// Get the Ed25519 signing `PublicKey` of the same identity as this
+    /// `PublicKey`, with the positive sign used by XEdDSA.
+    /// `PublicKey::from_signing` converts it back to this `PublicKey`.
+    pub fn signing_key(&self) -> Result<SignPublicKey, VerifyError> {
+        self.to_signing()
+    }
+}
+
 /// A `PrivateKey` used to compute a shared secret with a remote party
 pub use crypto_kx::SecretKey as PrivateKey;
 
@@ -74,7 +249,13 @@ impl KeyPair {
 
     /// Generate a new random `KeyPair`
     pub fn random() -> Self {
-        Self::from(crypto_kx::KeyPair::generate(&mut OsRng))
+        Self::from(crypto_kx::KeyPair::generate(OsRng))
+    }
+
+    /// Create a deterministic `KeyPair` using the given seed as secret key.
+    /// This should only be used for testing purposes.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self::new(PrivateKey::from(seed))
     }
 
     /// Get the `PublicKey` from this `KeyPair`
@@ -87,10 +268,72 @@ impl KeyPair {
         &self.secret
     }
 
+    /// Save this `KeyPair` to `path`, see `persist::save_versioned`. The
+    /// secret key is stored in plain text, use [`keyfile`] to protect it
+    /// with a passphrase.
+    ///
+    /// [`keyfile`]: crate::crypto::keyfile
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistError> {
+        persist::save_versioned(path, self)
+    }
+
+    /// Load a `KeyPair` saved using `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PersistError> {
+        let stored: Self = persist::load_versioned(path)?;
+
+        // the public key is derived again in case the file was tampered with
+        Ok(Self::new(stored.secret))
+    }
+
     /// Creates a [`crypto_kx::KeyPair`] from this one
     pub fn as_sodium(&self) -> crypto_kx::KeyPair {
         crypto_kx::KeyPair::from(self.secret.clone())
     }
+
+    /// Sign a message using this `KeyPair`. This uses XEdDSA so that the
+    /// resulting `Signature` can be verified using only the `PublicKey` of
+    /// this `KeyPair`, see `PublicKey::verify`.
+    pub fn sign<T: Serialize>(
+        &self,
+        message: &T,
+    ) -> Result<Signature, SignError> {
+        let message = bincode_options()
+            .serialize(message)
+            .context(SignSerialize)?;
+        let secret = Scalar::from_bytes_mod_order(self.secret.to_bytes());
+        let point = (&secret * &ED25519_BASEPOINT_TABLE).compress();
+
+        // the public key of XEdDSA always has a positive sign
+        let (secret, point) = if point.as_bytes()[31] >> 7 == 1 {
+            let secret = -secret;
+
+            (secret, (&secret * &ED25519_BASEPOINT_TABLE).compress())
+        } else {
+            (secret, point)
+        };
+
+        let nonce = blake3::derive_key(
+            "drop exchange key signature nonce",
+            secret.as_bytes(),
+        );
+
+        let mut expanded = [0u8; 64];
+        expanded[..32].copy_from_slice(secret.as_bytes());
+        expanded[32..].copy_from_slice(&nonce);
+
+        let expanded = ExpandedSecretKey::from_bytes(&expanded)
+            .expect("expanded secret key has a fixed size");
+        let public = DalekPublicKey::from_bytes(point.as_bytes())
+            .expect("public key derived from a valid scalar");
+
+        Ok(expanded.sign(&message, &public).into())
+    }
+}
+
+impl Versioned for KeyPair {
+    const MAGIC: [u8; 8] = *b"drop-kpr";
+
+    const CURRENT_VERSION: u16 = 1;
 }
 
 impl From<crypto_kx::KeyPair> for KeyPair {
@@ -104,12 +347,56 @@ impl From<crypto_kx::KeyPair> for KeyPair {
     }
 }
 
+/// Context string used to derive the `Exporter` of a `Session`
+const EXPORTER_CONTEXT: &str = "drop 2021 session exporter secret";
+
+/// Context string used to combine the keys of two `Session`s
+const BIND_CONTEXT: &str = "drop 2021 session binding";
+
 /// A pair of exchanged ephemeral keys that can be used to
 /// securely exchange data with a peer.
 #[derive(Debug)]
 pub struct Session {
     transmit: Key,
     receive: Key,
+    exporter: Exporter,
+}
+
+impl Session {
+    fn new(transmit: Key, receive: Key) -> Self {
+        let exporter = Exporter::new(&transmit, &receive);
+
+        Self {
+            transmit,
+            receive,
+            exporter,
+        }
+    }
+
+    /// Get the `Exporter` shared by both ends of this `Session`
+    pub fn exporter(&self) -> &Exporter {
+        &self.exporter
+    }
+
+    /// Combine this `Session` with `other`, established with the same peer,
+    /// into a `Session` that can only be derived knowing both. Binding a
+    /// `Session` between long-term keys to one between ephemeral keys makes
+    /// it forward secret.
+    pub fn bind(&self, other: &Session) -> Session {
+        let mix = |first: &Key, second: &Key| {
+            let mut hasher = blake3::Hasher::new_derive_key(BIND_CONTEXT);
+
+            hasher.update(first.as_ref());
+            hasher.update(second.as_ref());
+
+            Key::from(*hasher.finalize().as_bytes())
+        };
+
+        Session::new(
+            mix(&self.transmit, &other.transmit),
+            mix(&self.receive, &other.receive),
+        )
+    }
 }
 
 impl From<Session> for (Push, Pull) {
@@ -118,6 +405,57 @@ impl From<Session> for (Push, Pull) {
     }
 }
 
+/// A secret known only to both ends of a `Session` that is used to derive
+/// keying material for other protocols. The secret is derived one-way from
+/// both session keys so that exported material never reveals them, and it
+/// does not change while data is exchanged.
+#[derive(Clone)]
+pub struct Exporter(Key);
+
+impl Exporter {
+    fn new(transmit: &Key, receive: &Key) -> Self {
+        // each end transmits using the key the other end receives with
+        let (first, second) = if transmit.as_ref() < receive.as_ref() {
+            (transmit, receive)
+        } else {
+            (receive, transmit)
+        };
+
+        let mut hasher = blake3::Hasher::new_derive_key(EXPORTER_CONTEXT);
+
+        hasher.update(first.as_ref());
+        hasher.update(second.as_ref());
+
+        Self(Key::from(*hasher.finalize().as_bytes()))
+    }
+
+    /// Derive `len` bytes of keying material bound to the given `label` and
+    /// `context`. Both ends of a `Session` derive the same output given the
+    /// same arguments, and different labels or contexts yield unrelated
+    /// outputs.
+    pub fn export(&self, label: &[u8], context: &[u8], len: usize) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new_keyed(self.0.as_ref());
+
+        for input in [label, context] {
+            hasher.update(&(input.len() as u64).to_le_bytes());
+            hasher.update(input);
+        }
+        hasher.update(&(len as u64).to_le_bytes());
+
+        let mut output = vec![0; len];
+
+        hasher.finalize_xof().fill(&mut output);
+
+        output
+    }
+}
+
+impl fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Exporter {{ .. }}")
+    }
+}
+
 /// A structure used to compute a shared secret with another
 /// party using a `KeyPair` and the other party's `PublicKey`
 #[derive(Clone)]
@@ -138,6 +476,12 @@ impl Exchanger {
         }
     }
 
+    /// Create a new `KeyExchanger` using a deterministic `KeyPair` derived
+    /// from the given seed. This should only be used for testing purposes.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self::new(KeyPair::from_seed(seed))
+    }
+
     /// Get a reference to the `KeyPair` used by this `KeyExchanger`
     pub fn keypair(&self) -> &KeyPair {
         &self.keypair
@@ -155,10 +499,10 @@ impl Exchanger {
             (keys.rx, keys.tx)
         };
 
-        Session {
-            receive: rx.as_ref().to_owned().into(),
-            transmit: tx.as_ref().to_owned().into(),
-        }
+        Session::new(
+            tx.as_ref().to_owned().into(),
+            rx.as_ref().to_owned().into(),
+        )
     }
 }
 
@@ -214,4 +558,108 @@ mod tests {
             "computed same secret with different keys"
         );
     }
+
+    #[test]
+    fn bound_sessions_match() {
+        let (srv, cli) = (KeyPair::random(), KeyPair::random());
+        let (srv_eph, cli_eph) = (KeyPair::random(), KeyPair::random());
+
+        let srv_session = exchange_key!(srv.clone(), cli.public)
+            .bind(&exchange_key!(srv_eph.clone(), cli_eph.public));
+        let cli_session = exchange_key!(cli.clone(), srv.public)
+            .bind(&exchange_key!(cli_eph.clone(), srv_eph.public));
+
+        assert_eq!(srv_session.receive, cli_session.transmit);
+        assert_eq!(srv_session.transmit, cli_session.receive);
+
+        let unbound = exchange_key!(cli, srv.public);
+
+        assert_ne!(unbound.transmit, cli_session.transmit, "keys not mixed");
+    }
+
+    #[test]
+    fn exporter_is_symmetric() {
+        let (srv, cli) = (KeyPair::random(), KeyPair::random());
+
+        let srv_session = exchange_key!(srv.clone(), cli.public);
+        let cli_session = exchange_key!(cli, srv.public);
+
+        let srv_output = srv_session.exporter().export(b"label", b"ctx", 48);
+        let cli_output = cli_session.exporter().export(b"label", b"ctx", 48);
+
+        assert_eq!(srv_output, cli_output, "exported material differs");
+        assert_eq!(srv_output.len(), 48, "wrong output length");
+
+        for key in [&srv_session.transmit, &srv_session.receive] {
+            assert_ne!(
+                &srv_output[..key.as_ref().len()],
+                key.as_ref(),
+                "exporter revealed a session key"
+            );
+        }
+
+        assert_ne!(
+            srv_session.exporter().export(b"lab", b"elctx", 48),
+            srv_output,
+            "label and context are not separated"
+        );
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        // half of the keys need their sign flipped to match the public key
+        for _ in 0..16 {
+            let keypair = KeyPair::random();
+            let signature = keypair.sign(&42u64).expect("sign failed");
+
+            keypair
+                .public()
+                .verify(&42u64, &signature)
+                .expect("valid signature rejected");
+            keypair
+                .public()
+                .verify(&43u64, &signature)
+                .expect_err("signature verified for wrong message");
+            KeyPair::random()
+                .public()
+                .verify(&42u64, &signature)
+                .expect_err("signature verified for wrong key");
+        }
+    }
+
+    #[test]
+    fn fingerprint_golden() {
+        let golden = [
+            (0, "ea7075b1b6955ed7"),
+            (1, "9314efbc51e58f3f"),
+            (255, "a769215cb0bfab7b"),
+        ];
+
+        for (seed, expected) in golden {
+            let keypair = KeyPair::from_seed([seed; 32]);
+
+            assert_eq!(
+                keypair.public().fingerprint().to_string(),
+                expected,
+                "fingerprint derivation changed"
+            );
+        }
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let fingerprint = KeyPair::random().public().fingerprint();
+        let rendered = fingerprint.to_string();
+
+        assert_eq!(rendered.len(), FINGERPRINT_SIZE * 2);
+        assert_eq!(rendered.parse::<Fingerprint>().unwrap(), fingerprint);
+        assert!(matches!(
+            "ea7075b1".parse::<Fingerprint>(),
+            Err(FingerprintError::FingerprintLength { actual: 4 })
+        ));
+        assert!(matches!(
+            "not a fingerprint".parse::<Fingerprint>(),
+            Err(FingerprintError::MalformedFingerprint { .. })
+        ));
+    }
 }
@@ -1,6 +1,11 @@
 /// Utilities to compute a shared secret to establish a secure network stream
 pub mod exchange;
 
+/// Conversion of keys to and from the OpenSSH public key format
+#[cfg(feature = "interop-keys")]
+#[cfg_attr(docsrs, doc(cfg(feature = "interop-keys")))]
+pub mod ssh;
+
 use std::{
     fmt,
     fmt::{Debug, Display},
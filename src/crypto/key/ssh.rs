@@ -0,0 +1,539 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::PublicKey as DalekPublicKey;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use snafu::{ensure, IntoError, ResultExt, Snafu};
+
+use super::exchange::PublicKey as ExchangePublicKey;
+use crate::crypto::sign::{
+    Dalek, PublicKey as SignPublicKey, VerifyError, PUBLICKEYBYTES,
+};
+
+/// Name of the only OpenSSH key type that can be used with drop
+pub const ED25519: &str = "ssh-ed25519";
+
+#[derive(Debug, Snafu)]
+/// Error encountered when parsing keys in the OpenSSH format
+pub enum SshKeyError {
+    #[snafu(display("missing key type or key data"))]
+    /// The line did not contain both a key type and key data
+    MissingField,
+
+    #[snafu(display("unsupported key type {}, expected {}", kind, ED25519))]
+    /// The key is not an Ed25519 key, for instance an RSA or ECDSA key
+    UnsupportedKeyType {
+        /// Key type found in the line
+        kind: String,
+    },
+
+    #[snafu(display("malformed base64 key data: {}", source))]
+    /// The key data was not valid base64
+    MalformedBase64 {
+        /// Error source
+        source: base64::DecodeError,
+    },
+
+    #[snafu(display("malformed key data: {}", reason))]
+    /// The decoded key data did not follow the OpenSSH wire format
+    MalformedBlob {
+        /// What was wrong with the key data
+        reason: &'static str,
+    },
+
+    #[snafu(display("key data of type {} declared as {}", inner, outer))]
+    /// The type in the key data differs from the declared type
+    MismatchedType {
+        /// Key type declared in the line
+        outer: String,
+        /// Key type found in the key data
+        inner: String,
+    },
+
+    #[snafu(display("invalid ed25519 key: {}", source))]
+    /// The key data did not contain a usable Ed25519 key
+    InvalidKey {
+        /// Error source
+        source: VerifyError,
+    },
+
+    #[snafu(display("line {}: {}", line, source))]
+    /// A line of an `authorized_keys` file could not be parsed
+    Line {
+        /// Line number, starting at 1
+        line: usize,
+        /// Error source
+        source: Box<SshKeyError>,
+    },
+}
+
+/// An Ed25519 public key in the format used by OpenSSH in `.pub` and
+/// `authorized_keys` files: `ssh-ed25519 <base64 key data> [comment]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshPublicKey {
+    key: SignPublicKey,
+    comment: String,
+}
+
+impl SshPublicKey {
+    /// Create a new `SshPublicKey` without a comment
+    pub fn new(key: SignPublicKey) -> Self {
+        Self {
+            key,
+            comment: String::new(),
+        }
+    }
+
+    /// Create an `SshPublicKey` for the identity of a node, so that it can be
+    /// distributed along with other OpenSSH keys
+    pub fn from_exchange(key: &ExchangePublicKey) -> Result<Self, VerifyError> {
+        key.signing_key().map(Self::new)
+    }
+
+    /// Set the comment of this `SshPublicKey`
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Get the signing `PublicKey` contained in this `SshPublicKey`
+    pub fn key(&self) -> &SignPublicKey {
+        &self.key
+    }
+
+    /// Get the comment of this `SshPublicKey`, empty if there is none
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Get the exchange `PublicKey` of the identity owning this
+    /// `SshPublicKey`, see `PublicKey::from_signing`
+    pub fn exchange_key(&self) -> Result<ExchangePublicKey, VerifyError> {
+        ExchangePublicKey::from_signing(&self.key)
+    }
+
+    /// Encode this key in the OpenSSH wire format
+    fn blob(&self) -> Vec<u8> {
+        let mut blob = Vec::with_capacity(8 + ED25519.len() + PUBLICKEYBYTES);
+
+        for field in [ED25519.as_bytes(), self.key.as_ref()] {
+            blob.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            blob.extend_from_slice(field);
+        }
+
+        blob
+    }
+
+    /// Decode a key in the OpenSSH wire format
+    fn from_blob(mut blob: &[u8]) -> Result<SignPublicKey, SshKeyError> {
+        let kind = read_string(&mut blob)?;
+
+        ensure!(
+            kind == ED25519.as_bytes(),
+            MismatchedType {
+                outer: ED25519,
+                inner: String::from_utf8_lossy(kind),
+            }
+        );
+
+        let key = read_string(&mut blob)?;
+
+        ensure!(
+            key.len() == PUBLICKEYBYTES,
+            MalformedBlob {
+                reason: "wrong key length"
+            }
+        );
+        ensure!(
+            blob.is_empty(),
+            MalformedBlob {
+                reason: "trailing data"
+            }
+        );
+
+        DalekPublicKey::from_bytes(key)
+            .context(Dalek)
+            .context(InvalidKey)
+            .map(Into::into)
+    }
+}
+
+impl From<SignPublicKey> for SshPublicKey {
+    fn from(key: SignPublicKey) -> Self {
+        Self::new(key)
+    }
+}
+
+impl FromStr for SshPublicKey {
+    type Err = SshKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = next_field(s);
+        let (data, comment) = next_field(rest);
+
+        ensure!(!kind.is_empty() && !data.is_empty(), MissingField);
+        ensure!(kind == ED25519, UnsupportedKeyType { kind });
+
+        let blob = STANDARD.decode(data).context(MalformedBase64)?;
+
+        Ok(Self {
+            key: Self::from_blob(&blob)?,
+            comment: comment.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for SshPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", ED25519, STANDARD.encode(self.blob()))?;
+
+        if !self.comment.is_empty() {
+            write!(f, " {}", self.comment)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for SshPublicKey {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SshPublicKey {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A set of identities parsed from a file in the format of OpenSSH's
+/// `authorized_keys`, that can be used to decide which peers may connect.
+/// Options preceding keys are ignored, as are keys of unsupported types.
+#[derive(Clone, Debug, Default)]
+pub struct AuthorizedKeys {
+    keys: Vec<SshPublicKey>,
+    exchange: HashSet<ExchangePublicKey>,
+    skipped: usize,
+}
+
+impl AuthorizedKeys {
+    /// Create an empty set of `AuthorizedKeys`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Authorize the identity owning the given `SshPublicKey`
+    pub fn insert(&mut self, key: SshPublicKey) -> Result<(), VerifyError> {
+        self.exchange.insert(key.exchange_key()?);
+        self.keys.push(key);
+
+        Ok(())
+    }
+
+    /// Check whether the peer using the given exchange `PublicKey` is
+    /// authorized, for instance the remote key of a `Connection`
+    pub fn contains(&self, key: &ExchangePublicKey) -> bool {
+        self.exchange.contains(key)
+    }
+
+    /// Get all authorized `SshPublicKey`s in the order they were added
+    pub fn keys(&self) -> &[SshPublicKey] {
+        &self.keys
+    }
+
+    /// Number of keys that were ignored because of their type
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Number of authorized keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check whether no key is authorized
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl FromStr for AuthorizedKeys {
+    type Err = SshKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut authorized = Self::new();
+
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parsed = strip_options(line)
+                .parse::<SshPublicKey>()
+                .and_then(|key| authorized.insert(key).context(InvalidKey));
+
+            match parsed {
+                Ok(()) => {}
+                Err(SshKeyError::UnsupportedKeyType { .. }) => {
+                    authorized.skipped += 1;
+                }
+                Err(e) => {
+                    return Err(Line { line: idx + 1 }.into_error(e.into()))
+                }
+            }
+        }
+
+        Ok(authorized)
+    }
+}
+
+/// Split the first whitespace separated field from `s`
+fn next_field(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+
+    s.split_at(s.find(char::is_whitespace).unwrap_or(s.len()))
+}
+
+/// Remove the options that may precede a key in an `authorized_keys` line.
+/// Options are separated from the key by whitespace that is not quoted.
+fn strip_options(line: &str) -> &str {
+    if ["ssh-", "ecdsa-", "sk-"]
+        .iter()
+        .any(|p| line.starts_with(p))
+    {
+        return line;
+    }
+
+    let mut quoted = false;
+
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return &line[idx..],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// Read a length prefixed field from an OpenSSH key blob
+fn read_string<'a>(blob: &mut &'a [u8]) -> Result<&'a [u8], SshKeyError> {
+    ensure!(
+        blob.len() >= 4,
+        MalformedBlob {
+            reason: "truncated length"
+        }
+    );
+
+    let (len, rest) = blob.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+    ensure!(
+        rest.len() >= len,
+        MalformedBlob {
+            reason: "truncated field"
+        }
+    );
+
+    let (field, rest) = rest.split_at(len);
+
+    *blob = rest;
+
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{ExpandedSecretKey, SecretKey as DalekSecretKey};
+
+    use super::*;
+    use crate::codec::bincode_options;
+    use crate::crypto::key::exchange::{KeyPair, PrivateKey};
+    use crate::crypto::sign::KeyPair as SignKeyPair;
+    use crate::test::dialer;
+
+    use bincode::Options;
+
+    const ED25519_PUB: &str =
+        include_str!("../../../fixtures/ssh/id_ed25519.pub");
+    const RSA_PUB: &str = include_str!("../../../fixtures/ssh/id_rsa.pub");
+    const ECDSA_PUB: &str = include_str!("../../../fixtures/ssh/id_ecdsa.pub");
+    const AUTHORIZED: &str =
+        include_str!("../../../fixtures/ssh/authorized_keys");
+
+    /// Raw key contained in `id_ed25519.pub`
+    const ED25519_RAW: &str =
+        "3df5ea939f6f8980c4f8ba390f534768bd57472bb48d40f548ad782e47c17750";
+
+    #[test]
+    fn ssh_keygen_round_trip() {
+        let key: SshPublicKey = ED25519_PUB.parse().expect("parse failed");
+
+        assert_eq!(hex::encode(key.key()), ED25519_RAW, "wrong key");
+        assert_eq!(key.comment(), "alice@example.org", "wrong comment");
+        assert_eq!(key.to_string(), ED25519_PUB.trim(), "wrong encoding");
+
+        let serialized = bincode_options().serialize(&key).unwrap();
+        let deserialized: SshPublicKey =
+            bincode_options().deserialize(&serialized).unwrap();
+
+        assert_eq!(deserialized, key, "serde round trip failed");
+    }
+
+    #[test]
+    fn unsupported_types() {
+        for (line, expected) in
+            [(RSA_PUB, "ssh-rsa"), (ECDSA_PUB, "ecdsa-sha2-nistp256")]
+        {
+            match line.parse::<SshPublicKey>() {
+                Err(SshKeyError::UnsupportedKeyType { kind }) => {
+                    assert_eq!(kind, expected, "wrong key type")
+                }
+                other => panic!("parsed unsupported key: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_keys() {
+        let rsa_data = RSA_PUB.split_whitespace().nth(1).unwrap();
+        let truncated = &ED25519_PUB.split_whitespace().nth(1).unwrap()[..40];
+
+        let cases = [
+            String::new(),
+            ED25519.to_string(),
+            format!("{} not*base64", ED25519),
+            format!("{} {}", ED25519, rsa_data),
+            format!("{} {}", ED25519, truncated),
+        ];
+
+        for case in cases.iter() {
+            case.parse::<SshPublicKey>()
+                .expect_err("parsed malformed key");
+        }
+
+        assert!(matches!(
+            cases[3].parse::<SshPublicKey>(),
+            Err(SshKeyError::MismatchedType { .. })
+        ));
+    }
+
+    #[test]
+    fn exchange_identity_round_trip() {
+        for _ in 0..16 {
+            let keypair = KeyPair::random();
+            let ssh = SshPublicKey::from_exchange(keypair.public())
+                .expect("conversion failed")
+                .with_comment("node 0");
+            let parsed: SshPublicKey =
+                ssh.to_string().parse().expect("parse failed");
+
+            assert_eq!(parsed, ssh, "wrong ssh key");
+            assert_eq!(
+                &parsed.exchange_key().expect("conversion failed"),
+                keypair.public(),
+                "wrong exchange key"
+            );
+        }
+    }
+
+    #[test]
+    fn signing_identity() {
+        let keypair = SignKeyPair::random();
+        let line = SshPublicKey::from(keypair.public()).to_string();
+        let parsed: SshPublicKey = line.parse().expect("parse failed");
+        let signature = keypair.sign(&42u64).expect("sign failed");
+
+        signature
+            .verify(&42u64, parsed.key())
+            .expect("valid signature rejected");
+
+        // the exchange key matches the one derived from the signing secret
+        // like libsodium's crypto_sign_ed25519_sk_to_curve25519
+        let secret =
+            DalekSecretKey::from_bytes(&keypair.private().to_bytes()).unwrap();
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(
+            &ExpandedSecretKey::from(&secret).to_bytes()[..32],
+        );
+        let exchange = KeyPair::new(PrivateKey::from(scalar));
+
+        assert_eq!(
+            &parsed.exchange_key().expect("conversion failed"),
+            exchange.public(),
+            "wrong exchange key"
+        );
+    }
+
+    #[test]
+    fn authorized_keys_file() {
+        let authorized: AuthorizedKeys =
+            AUTHORIZED.parse().expect("parse failed");
+
+        assert_eq!(authorized.len(), 3, "wrong number of keys");
+        assert_eq!(
+            authorized.keys()[2],
+            SshPublicKey::from_exchange(dialer().keypair().public())
+                .unwrap()
+                .with_comment("drop dialer"),
+            "wrong exported identity"
+        );
+        assert_eq!(authorized.skipped(), 1, "rsa key not skipped");
+        assert_eq!(
+            authorized.keys()[0],
+            ED25519_PUB.parse().unwrap(),
+            "wrong first key"
+        );
+
+        let err = format!("{}\nssh-ed25519 AAAA\n", ED25519_PUB.trim())
+            .parse::<AuthorizedKeys>()
+            .expect_err("parsed malformed file");
+
+        assert!(matches!(err, SshKeyError::Line { line: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn authorized_handshake() {
+        use crate::crypto::key::exchange::Exchanger;
+        use crate::net::{Connector, Listener, TcpConnector, TcpListener};
+        use crate::test::{acceptor, next_test_ip4};
+
+        let authorized: AuthorizedKeys =
+            AUTHORIZED.parse().expect("parse failed");
+        let addr = next_test_ip4();
+        let exchanger = acceptor();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+
+        for (local, expected) in
+            [(dialer(), true), (Exchanger::random(), false)]
+        {
+            let connect = async {
+                TcpConnector::new(local)
+                    .connect(&public, &addr)
+                    .await
+                    .expect("connect failed")
+            };
+            let (accepted, _) = futures::join!(listener.accept(), connect);
+            let remote = accepted
+                .expect("accept failed")
+                .remote_key()
+                .expect("no remote key");
+
+            assert_eq!(authorized.contains(&remote), expected);
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Passphrase protected files holding the secret of a [`KeyPair`]
+//!
+//! A keyfile is laid out as follows, all integers being little endian:
+//!
+//! | offset | size | content                                                |
+//! |-------:|-----:|--------------------------------------------------------|
+//! |      0 |    8 | magic bytes `drop-key`                                 |
+//! |      8 |    1 | format version, currently `1`                          |
+//! |      9 |    4 | argon2id memory cost in KiB                            |
+//! |     13 |    4 | argon2id number of iterations                          |
+//! |     17 |    4 | argon2id degree of parallelism                         |
+//! |     21 |   16 | random salt                                            |
+//! |     37 |   24 | secretstream header, which acts as a nonce             |
+//! |     61 |   49 | encrypted secret key, stream tag and authentication code |
+//!
+//! The encryption `Key` is derived from the passphrase and salt using
+//! argon2id with the stored parameters, and then bound to the first 37 bytes
+//! of the file so that tampering with the parameters is detected.
+//!
+//! [`KeyPair`]: super::key::exchange::KeyPair
+
+use std::{fs, io, path::Path};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{rngs::OsRng, RngCore};
+use snafu::{ensure, ResultExt, Snafu};
+use zeroize::Zeroizing;
+
+use super::{
+    key::{exchange::KeyPair, Key, SIZE},
+    stream::{EncryptError, Pull, Push, ENCRYPTION_OVERHEAD, HEADER_SIZE},
+};
+use crate::persist;
+
+/// Magic bytes opening every keyfile
+pub const MAGIC: [u8; 8] = *b"drop-key";
+
+/// Version of the keyfile format written by this module
+pub const VERSION: u8 = 1;
+
+/// Weakest argon2id memory cost accepted when decrypting, in KiB
+pub const MIN_KDF_MEMORY: u32 = 8 * 1024;
+
+/// Fewest argon2id iterations accepted when decrypting
+pub const MIN_KDF_ITERATIONS: u32 = 1;
+
+/// Largest argon2id memory cost accepted, in KiB. This bounds the memory a
+/// crafted keyfile can make us allocate to 1 GiB
+pub const MAX_KDF_MEMORY: u32 = 1024 * 1024;
+
+/// Most argon2id iterations accepted
+pub const MAX_KDF_ITERATIONS: u32 = 64;
+
+/// Highest argon2id degree of parallelism accepted
+pub const MAX_KDF_PARALLELISM: u32 = 64;
+
+/// Number of bytes of the salt
+const SALT_SIZE: usize = 16;
+
+/// Number of bytes of the part of the file preceding the header
+const PREFIX_SIZE: usize = MAGIC.len() + 1 + 3 * 4 + SALT_SIZE;
+
+/// Total number of bytes of a keyfile
+const FILE_SIZE: usize = PREFIX_SIZE + HEADER_SIZE + SIZE + ENCRYPTION_OVERHEAD;
+
+/// Context used to bind the derived `Key` to the content of the file
+const BIND_CONTEXT: &str = "drop 2021 keyfile encryption key";
+
+#[derive(Debug, Snafu)]
+/// Error encountered when reading or writing a keyfile
+pub enum KeyfileError {
+    #[snafu(display("i/o error: {}", source))]
+    /// The keyfile could not be read or written
+    KeyfileIo {
+        /// Underlying error cause
+        source: io::Error,
+    },
+
+    #[snafu(display("not a keyfile"))]
+    /// The file does not start with the keyfile magic bytes
+    NotKeyfile,
+
+    #[snafu(display("unsupported keyfile version {}", version))]
+    /// The file was written using an unknown version of the format
+    UnsupportedVersion {
+        /// Version of the file
+        version: u8,
+    },
+
+    #[snafu(display(
+        "truncated keyfile: {} bytes instead of {}",
+        actual,
+        expected
+    ))]
+    /// The file does not have the size of a keyfile
+    Truncated {
+        /// Size of a keyfile
+        expected: usize,
+        /// Size of the file
+        actual: usize,
+    },
+
+    #[snafu(display(
+        "key derivation parameters too weak: {} KiB and {} iterations, \
+         at least {} KiB and {} iterations required",
+        memory,
+        iterations,
+        MIN_KDF_MEMORY,
+        MIN_KDF_ITERATIONS
+    ))]
+    /// The key derivation parameters are below the accepted minimum, which
+    /// may be an attempt to downgrade the protection of the file
+    WeakKdf {
+        /// Memory cost of the file
+        memory: u32,
+        /// Iterations of the file
+        iterations: u32,
+    },
+
+    #[snafu(display(
+        "key derivation parameters too costly: {} KiB, {} iterations and \
+         {} lanes, at most {} KiB, {} iterations and {} lanes accepted",
+        memory,
+        iterations,
+        parallelism,
+        MAX_KDF_MEMORY,
+        MAX_KDF_ITERATIONS,
+        MAX_KDF_PARALLELISM
+    ))]
+    /// The key derivation parameters exceed the accepted maximum, which may
+    /// be an attempt to exhaust our memory or cpu
+    CostlyKdf {
+        /// Memory cost of the file
+        memory: u32,
+        /// Iterations of the file
+        iterations: u32,
+        /// Degree of parallelism of the file
+        parallelism: u32,
+    },
+
+    #[snafu(display("invalid key derivation parameters: {}", source))]
+    /// The key could not be derived using the given parameters
+    InvalidKdf {
+        /// Underlying error cause
+        source: argon2::Error,
+    },
+
+    #[snafu(display("wrong passphrase or corrupted keyfile"))]
+    /// The secret key could not be decrypted
+    WrongPassphrase,
+
+    #[snafu(display("could not encrypt secret key: {}", source))]
+    /// The secret key could not be encrypted
+    EncryptKey {
+        /// Underlying error cause
+        source: EncryptError,
+    },
+}
+
+/// argon2id parameters used to derive the encryption `Key` of a keyfile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory: u32,
+    /// Number of iterations
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// The weakest parameters accepted when decrypting, which keep
+    /// derivation fast in tests but should not be used otherwise
+    pub fn minimum() -> Self {
+        Self {
+            memory: MIN_KDF_MEMORY,
+            iterations: MIN_KDF_ITERATIONS,
+            parallelism: 1,
+        }
+    }
+
+    fn check(&self) -> Result<(), KeyfileError> {
+        ensure!(
+            self.memory >= MIN_KDF_MEMORY
+                && self.iterations >= MIN_KDF_ITERATIONS,
+            WeakKdf {
+                memory: self.memory,
+                iterations: self.iterations,
+            }
+        );
+        ensure!(
+            self.memory <= MAX_KDF_MEMORY
+                && self.iterations <= MAX_KDF_ITERATIONS
+                && self.parallelism <= MAX_KDF_PARALLELISM,
+            CostlyKdf {
+                memory: self.memory,
+                iterations: self.iterations,
+                parallelism: self.parallelism,
+            }
+        );
+
+        Ok(())
+    }
+
+    fn derive(
+        &self,
+        passphrase: &[u8],
+        prefix: &[u8],
+    ) -> Result<Key, KeyfileError> {
+        let params = Params::new(
+            self.memory,
+            self.iterations,
+            self.parallelism,
+            Some(SIZE),
+        )
+        .context(InvalidKdf)?;
+        let salt = &prefix[PREFIX_SIZE - SALT_SIZE..];
+        let mut derived = Zeroizing::new([0u8; SIZE]);
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase, salt, derived.as_mut())
+            .context(InvalidKdf)?;
+
+        let mut hasher = blake3::Hasher::new_derive_key(BIND_CONTEXT);
+
+        hasher.update(derived.as_ref());
+        hasher.update(prefix);
+
+        Ok(Key::from(*hasher.finalize().as_bytes()))
+    }
+}
+
+/// The recommended argon2id parameters: 19 MiB of memory, 2 iterations and
+/// no parallelism
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Encrypt the secret of `keypair` into a keyfile protected by `passphrase`
+pub fn encrypt_keypair(
+    keypair: &KeyPair,
+    passphrase: &[u8],
+    params: KdfParams,
+) -> Result<Vec<u8>, KeyfileError> {
+    params.check()?;
+
+    let mut output = Vec::with_capacity(FILE_SIZE);
+    let mut salt = [0u8; SALT_SIZE];
+
+    OsRng.fill_bytes(&mut salt);
+
+    output.extend_from_slice(&MAGIC);
+    output.push(VERSION);
+    output.extend_from_slice(&params.memory.to_le_bytes());
+    output.extend_from_slice(&params.iterations.to_le_bytes());
+    output.extend_from_slice(&params.parallelism.to_le_bytes());
+    output.extend_from_slice(&salt);
+
+    let key = params.derive(passphrase, &output)?;
+    let sealed = Push::new(key)
+        .encrypt_bytes(&keypair.secret().to_bytes())
+        .context(EncryptKey)?;
+
+    output.extend_from_slice(&sealed);
+
+    Ok(output)
+}
+
+/// Decrypt the `KeyPair` stored in a keyfile protected by `passphrase`
+pub fn decrypt_keypair(
+    keyfile: &[u8],
+    passphrase: &[u8],
+) -> Result<KeyPair, KeyfileError> {
+    let truncated = Truncated {
+        expected: FILE_SIZE,
+        actual: keyfile.len(),
+    };
+
+    ensure!(keyfile.len() > MAGIC.len(), truncated);
+    ensure!(keyfile.starts_with(&MAGIC), NotKeyfile);
+
+    let version = keyfile[MAGIC.len()];
+
+    ensure!(version == VERSION, UnsupportedVersion { version });
+    ensure!(keyfile.len() == FILE_SIZE, truncated);
+
+    let word = |index: usize| {
+        let start = MAGIC.len() + 1 + index * 4;
+        let bytes = keyfile[start..start + 4].try_into().expect("4 bytes");
+
+        u32::from_le_bytes(bytes)
+    };
+    let params = KdfParams {
+        memory: word(0),
+        iterations: word(1),
+        parallelism: word(2),
+    };
+
+    params.check()?;
+
+    let (prefix, sealed) = keyfile.split_at(PREFIX_SIZE);
+    let key = params.derive(passphrase, prefix)?;
+    let mut pull = Pull::new(key);
+    let secret = pull
+        .decrypt_bytes(sealed)
+        .ok()
+        .and_then(|secret| <[u8; SIZE]>::try_from(secret).ok())
+        .ok_or_else(|| WrongPassphrase.build())?;
+
+    Ok(KeyPair::from_seed(secret))
+}
+
+/// Encrypt the secret of `keypair` into a keyfile at `path`, see
+/// `encrypt_keypair`
+pub fn encrypt_keypair_to_file<P: AsRef<Path>>(
+    keypair: &KeyPair,
+    path: P,
+    passphrase: &[u8],
+    params: KdfParams,
+) -> Result<(), KeyfileError> {
+    let keyfile = encrypt_keypair(keypair, passphrase, params)?;
+
+    persist::write_atomic(path, &keyfile).context(KeyfileIo)
+}
+
+/// Decrypt the `KeyPair` stored in the keyfile at `path`, see
+/// `decrypt_keypair`
+pub fn decrypt_keypair_from_file<P: AsRef<Path>>(
+    path: P,
+    passphrase: &[u8],
+) -> Result<KeyPair, KeyfileError> {
+    let keyfile = fs::read(path).context(KeyfileIo)?;
+
+    decrypt_keypair(&keyfile, passphrase)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PASSPHRASE: &[u8] = b"correct horse battery staple";
+
+    fn keyfile(keypair: &KeyPair) -> Vec<u8> {
+        encrypt_keypair(keypair, PASSPHRASE, KdfParams::minimum())
+            .expect("encrypt failed")
+    }
+
+    #[test]
+    fn round_trip() {
+        let keypair = KeyPair::random();
+        let path = std::env::temp_dir()
+            .join(format!("drop-keyfile-{}", keypair.public()));
+
+        encrypt_keypair_to_file(
+            &keypair,
+            &path,
+            PASSPHRASE,
+            KdfParams::minimum(),
+        )
+        .expect("write failed");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path).expect("stat failed").permissions();
+
+            assert_eq!(
+                mode.mode() & 0o777,
+                0o600,
+                "keyfile readable by others"
+            );
+        }
+
+        let keyfile = fs::read(&path).expect("read failed");
+        let decrypted =
+            decrypt_keypair_from_file(&path, PASSPHRASE).expect("decrypt");
+
+        fs::remove_file(&path).expect("remove failed");
+
+        assert_eq!(keyfile.len(), FILE_SIZE);
+        assert_eq!(decrypted.public(), keypair.public());
+        assert!(
+            !keyfile
+                .windows(SIZE)
+                .any(|w| w == keypair.secret().to_bytes()),
+            "secret stored in plaintext"
+        );
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let keyfile = keyfile(&KeyPair::random());
+
+        assert!(matches!(
+            decrypt_keypair(&keyfile, b"incorrect horse"),
+            Err(KeyfileError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        let keyfile = keyfile(&KeyPair::random());
+
+        assert!(matches!(
+            decrypt_keypair(&keyfile[..FILE_SIZE - 1], PASSPHRASE),
+            Err(KeyfileError::Truncated { actual, .. }) if actual == FILE_SIZE - 1
+        ));
+        assert!(matches!(
+            decrypt_keypair(&keyfile[..4], PASSPHRASE),
+            Err(KeyfileError::Truncated { .. })
+        ));
+        assert!(matches!(
+            decrypt_keypair(&[0; FILE_SIZE], PASSPHRASE),
+            Err(KeyfileError::NotKeyfile)
+        ));
+
+        let mut future = keyfile;
+
+        future[MAGIC.len()] = VERSION + 1;
+
+        assert!(matches!(
+            decrypt_keypair(&future, PASSPHRASE),
+            Err(KeyfileError::UnsupportedVersion { version }) if version == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn kdf_params() {
+        let keypair = KeyPair::random();
+        let weak = KdfParams {
+            memory: MIN_KDF_MEMORY / 2,
+            ..KdfParams::minimum()
+        };
+
+        assert!(matches!(
+            encrypt_keypair(&keypair, PASSPHRASE, weak),
+            Err(KeyfileError::WeakKdf { .. })
+        ));
+        assert!(matches!(
+            encrypt_keypair(
+                &keypair,
+                PASSPHRASE,
+                KdfParams {
+                    parallelism: 0,
+                    ..KdfParams::minimum()
+                }
+            ),
+            Err(KeyfileError::InvalidKdf { .. })
+        ));
+
+        let memory = MAGIC.len() + 1;
+        let mut downgraded = keyfile(&keypair);
+
+        downgraded[memory..memory + 4].copy_from_slice(&1024u32.to_le_bytes());
+
+        assert!(matches!(
+            decrypt_keypair(&downgraded, PASSPHRASE),
+            Err(KeyfileError::WeakKdf { memory: 1024, .. })
+        ));
+
+        let mut costly = keyfile(&keypair);
+
+        costly[memory..memory + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            decrypt_keypair(&costly, PASSPHRASE),
+            Err(KeyfileError::CostlyKdf {
+                memory: u32::MAX,
+                ..
+            })
+        ));
+
+        // stronger parameters are accepted but do not match the file
+        let mut tampered = keyfile(&keypair);
+
+        tampered[memory..memory + 4]
+            .copy_from_slice(&(MIN_KDF_MEMORY * 2).to_le_bytes());
+
+        assert!(matches!(
+            decrypt_keypair(&tampered, PASSPHRASE),
+            Err(KeyfileError::WrongPassphrase)
+        ));
+    }
+}
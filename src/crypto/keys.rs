@@ -0,0 +1,439 @@
+use std::{fmt, hash::Hash, str::FromStr};
+
+use curve25519_dalek::{
+    edwards::CompressedEdwardsY, montgomery::MontgomeryPoint,
+};
+use ed25519_dalek::PublicKey as DalekPublicKey;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+#[cfg(feature = "blst")]
+use super::bls::PublicKey as BlsPublicKey;
+use super::{key::exchange::PublicKey as ExchangePublicKey, sign};
+
+/// Kind of a public key
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+)]
+pub enum KeyType {
+    /// An X25519 key from [`exchange`] used to secure `Connection`s
+    ///
+    /// [`exchange`]: super::key::exchange
+    Exchange,
+    /// An Ed25519 key from [`sign`] used to verify signatures
+    ///
+    /// [`sign`]: super::sign
+    Sign,
+    /// A BLS12-381 key used to verify aggregated signatures
+    Bls,
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Exchange => write!(f, "x25519"),
+            Self::Sign => write!(f, "ed25519"),
+            Self::Bls => write!(f, "bls12-381"),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Error encountered when parsing or converting public keys
+pub enum KeyError {
+    #[snafu(display("malformed {} key: {}", key_type, source))]
+    /// The key was not valid hexadecimal
+    MalformedKey {
+        /// Type of the key
+        key_type: KeyType,
+        /// Error source
+        source: hex::FromHexError,
+    },
+
+    #[snafu(display(
+        "{} key must be {} bytes long, got {}",
+        key_type,
+        expected,
+        actual
+    ))]
+    /// The key did not have the right size
+    KeyLength {
+        /// Type of the key
+        key_type: KeyType,
+        /// Size of keys of this type
+        expected: usize,
+        /// Size of the rejected key
+        actual: usize,
+    },
+
+    #[snafu(display("invalid {} key", key_type))]
+    /// The bytes did not encode a key of this type
+    InvalidKey {
+        /// Type of the key
+        key_type: KeyType,
+    },
+
+    #[snafu(display("{} key has no equivalent {} key", from, to))]
+    /// The key can not be converted to the requested type
+    Conversion {
+        /// Type of the converted key
+        from: KeyType,
+        /// Requested type
+        to: KeyType,
+    },
+}
+
+/// A public key of any type. `Display` and `FromStr` use the canonical
+/// representation of the key as lowercase hexadecimal. Keys are serialized
+/// in this representation by human readable formats, and as raw bytes
+/// otherwise.
+pub trait Keyed:
+    Clone
+    + Eq
+    + Hash
+    + Ord
+    + fmt::Display
+    + FromStr<Err = KeyError>
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+{
+    /// Type of this key
+    fn key_type() -> KeyType;
+
+    /// Get the raw bytes of this key
+    fn as_bytes(&self) -> &[u8];
+
+    /// Read a key of this type from its raw bytes
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError>;
+}
+
+/// Write the canonical representation of `key`, used by `Display`
+pub(crate) fn display(key: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    for b in key {
+        write!(f, "{:02x}", b)?;
+    }
+
+    Ok(())
+}
+
+/// Parse the canonical representation of a key, used by `FromStr`
+pub(crate) fn parse<K: Keyed>(s: &str) -> Result<K, KeyError> {
+    let bytes = hex::decode(s).context(MalformedKey {
+        key_type: K::key_type(),
+    })?;
+
+    K::from_bytes(&bytes)
+}
+
+/// Copy `bytes` to an array after checking its size
+pub(crate) fn to_array<const N: usize>(
+    bytes: &[u8],
+    key_type: KeyType,
+) -> Result<[u8; N], KeyError> {
+    ensure!(
+        bytes.len() == N,
+        KeyLength {
+            key_type,
+            expected: N,
+            actual: bytes.len(),
+        }
+    );
+
+    let mut array = [0u8; N];
+    array.copy_from_slice(bytes);
+
+    Ok(array)
+}
+
+/// Deserialize a key from its canonical representation, used by human
+/// readable formats
+pub(crate) fn deserialize_str<'de, K, D>(deserializer: D) -> Result<K, D::Error>
+where
+    K: Keyed,
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+impl Keyed for ExchangePublicKey {
+    fn key_type() -> KeyType {
+        KeyType::Exchange
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_ref()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        let array = to_array(bytes, KeyType::Exchange)?;
+
+        Ok(Self::from(crypto_kx::PublicKey::from(array)))
+    }
+}
+
+impl Keyed for sign::PublicKey {
+    fn key_type() -> KeyType {
+        KeyType::Sign
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_ref()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        let array: [u8; sign::PUBLICKEYBYTES] = to_array(bytes, KeyType::Sign)?;
+
+        DalekPublicKey::from_bytes(&array)
+            .ok()
+            .map(Self::from)
+            .context(InvalidKey {
+                key_type: KeyType::Sign,
+            })
+    }
+}
+
+#[cfg(feature = "blst")]
+impl Keyed for BlsPublicKey {
+    fn key_type() -> KeyType {
+        KeyType::Bls
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_ref()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        Self::from_compressed(bytes).ok().context(InvalidKey {
+            key_type: KeyType::Bls,
+        })
+    }
+}
+
+/// Convert an Ed25519 signing key to the X25519 key of the same identity.
+/// This uses the birational map of libsodium's
+/// `crypto_sign_ed25519_pk_to_curve25519`, and rejects keys of small order.
+impl TryFrom<&sign::PublicKey> for ExchangePublicKey {
+    type Error = KeyError;
+
+    fn try_from(key: &sign::PublicKey) -> Result<Self, Self::Error> {
+        let edwards = CompressedEdwardsY(*key.as_bytes())
+            .decompress()
+            .filter(|point| !point.is_small_order())
+            .context(Conversion {
+                from: KeyType::Sign,
+                to: KeyType::Exchange,
+            })?;
+
+        Ok(Self::from(crypto_kx::PublicKey::from(
+            edwards.to_montgomery().to_bytes(),
+        )))
+    }
+}
+
+/// Convert an X25519 key to the Ed25519 key of the same identity, choosing
+/// the positive sign as specified by XEdDSA
+impl TryFrom<&ExchangePublicKey> for sign::PublicKey {
+    type Error = KeyError;
+
+    fn try_from(key: &ExchangePublicKey) -> Result<Self, Self::Error> {
+        let montgomery =
+            MontgomeryPoint(to_array(key.as_ref(), KeyType::Exchange)?);
+
+        montgomery
+            .to_edwards(0)
+            .and_then(|edwards| {
+                DalekPublicKey::from_bytes(edwards.compress().as_bytes()).ok()
+            })
+            .map(Self::from)
+            .context(Conversion {
+                from: KeyType::Exchange,
+                to: KeyType::Sign,
+            })
+    }
+}
+
+/// The public keys of a node, one of each type, that can be distributed as a
+/// whole
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyBundle {
+    exchange: ExchangePublicKey,
+    sign: sign::PublicKey,
+    #[cfg(feature = "blst")]
+    bls: Option<BlsPublicKey>,
+}
+
+impl KeyBundle {
+    /// Group the given keys in a `KeyBundle`
+    pub fn new(exchange: ExchangePublicKey, sign: sign::PublicKey) -> Self {
+        Self {
+            exchange,
+            sign,
+            #[cfg(feature = "blst")]
+            bls: None,
+        }
+    }
+
+    /// Create a `KeyBundle` for a node that signs using its exchange key, as
+    /// done by `exchange::KeyPair::sign`
+    pub fn from_exchange(
+        exchange: ExchangePublicKey,
+    ) -> Result<Self, KeyError> {
+        let sign = sign::PublicKey::try_from(&exchange)?;
+
+        Ok(Self::new(exchange, sign))
+    }
+
+    /// Add a BLS key to this `KeyBundle`
+    #[cfg(feature = "blst")]
+    pub fn with_bls(mut self, bls: BlsPublicKey) -> Self {
+        self.bls = Some(bls);
+        self
+    }
+
+    /// Get the key used to secure `Connection`s with this node
+    pub fn exchange(&self) -> &ExchangePublicKey {
+        &self.exchange
+    }
+
+    /// Get the key used to verify signatures from this node
+    pub fn sign(&self) -> &sign::PublicKey {
+        &self.sign
+    }
+
+    /// Get the key used to verify aggregated signatures from this node, if
+    /// it has one
+    #[cfg(feature = "blst")]
+    pub fn bls(&self) -> Option<&BlsPublicKey> {
+        self.bls.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::key::exchange::KeyPair;
+
+    /// Ed25519 public key and its X25519 equivalent, from libsodium's
+    /// `ed25519_convert` test
+    const CONVERSION: (&str, &str) = (
+        "b5076a8474a832daee4dd5b4040983b6623b5f344aca57d4d6ee4baf3f259e6e",
+        "f1814f0e8ff1043d8a44d25babff3cedcae6c22c3edaa48f857ae70de2baae50",
+    );
+
+    fn round_trip<K: Keyed + fmt::Debug>(key: K) {
+        let json = serde_json::to_string(&key).expect("serialize failed");
+
+        assert_eq!(json, format!("\"{}\"", key), "not canonical");
+        assert_eq!(serde_json::from_str::<K>(&json).unwrap(), key);
+
+        let binary = bincode::serialize(&key).expect("serialize failed");
+
+        assert_eq!(bincode::deserialize::<K>(&binary).unwrap(), key);
+        assert_eq!(key.to_string().parse::<K>().unwrap(), key);
+        assert_eq!(K::from_bytes(key.as_bytes()).unwrap(), key);
+    }
+
+    #[test]
+    fn exchange_round_trip() {
+        round_trip(*KeyPair::random().public());
+    }
+
+    #[test]
+    fn sign_round_trip() {
+        round_trip(sign::KeyPair::random().public());
+    }
+
+    #[cfg(feature = "blst")]
+    #[test]
+    fn bls_round_trip() {
+        round_trip(crate::crypto::bls::PrivateKey::random().unwrap().public());
+    }
+
+    #[test]
+    fn binary_unchanged() {
+        let key = *KeyPair::random().public();
+        let inner = crypto_kx::PublicKey::from(
+            to_array::<32>(key.as_ref(), KeyType::Exchange).unwrap(),
+        );
+
+        assert_eq!(
+            bincode::serialize(&key).unwrap(),
+            bincode::serialize(&inner).unwrap(),
+            "binary format changed"
+        );
+    }
+
+    #[test]
+    fn conversion_vectors() {
+        let sign: sign::PublicKey = CONVERSION.0.parse().unwrap();
+        let exchange: ExchangePublicKey = CONVERSION.1.parse().unwrap();
+
+        assert_eq!(ExchangePublicKey::try_from(&sign).unwrap(), exchange);
+        assert_eq!(sign::PublicKey::try_from(&exchange).unwrap(), sign);
+    }
+
+    #[test]
+    fn small_order_rejected() {
+        // the identity point has order 1
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+
+        let key = sign::PublicKey::from_bytes(&identity).unwrap();
+
+        assert!(matches!(
+            ExchangePublicKey::try_from(&key),
+            Err(KeyError::Conversion { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            "zz".parse::<ExchangePublicKey>(),
+            Err(KeyError::MalformedKey { .. })
+        ));
+        assert!(matches!(
+            "abcd".parse::<sign::PublicKey>(),
+            Err(KeyError::KeyLength {
+                key_type: KeyType::Sign,
+                expected: 32,
+                actual: 2,
+            })
+        ));
+    }
+
+    #[test]
+    fn bundle_serde() {
+        let exchange = *KeyPair::random().public();
+        let bundle = KeyBundle::from_exchange(exchange).unwrap();
+        let json = serde_json::to_string(&bundle).unwrap();
+
+        assert_eq!(serde_json::from_str::<KeyBundle>(&json).unwrap(), bundle);
+        assert_eq!(
+            bincode::deserialize::<KeyBundle>(
+                &bincode::serialize(&bundle).unwrap()
+            )
+            .unwrap(),
+            bundle
+        );
+        assert_eq!(
+            ExchangePublicKey::try_from(bundle.sign()).unwrap(),
+            exchange
+        );
+    }
+}
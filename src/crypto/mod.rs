@@ -3,6 +3,14 @@ pub mod hash;
 
 /// Cryptographic primitives for secure network exchange
 pub mod key;
+
+/// Passphrase protected storage of `KeyPair`s
+#[cfg(feature = "keyfile")]
+#[cfg_attr(docsrs, doc(cfg(feature = "keyfile")))]
+pub mod keyfile;
+
+/// Common interface to all types of public keys
+pub mod keys;
 mod parse;
 
 /// Signature computation and verification utilities
@@ -11,11 +19,12 @@ pub mod sign;
 /// Secure network stream utilities
 pub mod stream;
 
-#[cfg(features = "blst")]
+#[cfg(feature = "blst")]
 pub mod bls;
 
-pub use hash::{authenticate, hash, Digest};
+pub use hash::{authenticate, hash, hash_with, Digest, GenericDigest};
 pub use key::Key;
+pub use keys::{KeyBundle, KeyError, KeyType, Keyed};
 pub use parse::ParseHexError;
 
 /// Type alias for serializer errors
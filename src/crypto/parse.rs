@@ -2,7 +2,7 @@ use hex::FromHex;
 use snafu::{ResultExt, Snafu};
 
 use super::{
-    hash::{self, Digest},
+    hash::{GenericDigest, HashAlgorithm},
     key::{self, exchange, Key},
     sign,
 };
@@ -41,9 +41,19 @@ macro_rules! from_hex_with_slice_impl {
     )*)
 }
 
-from_hex_with_slice_impl!(Digest, hash::SIZE);
 from_hex_with_slice_impl!(Key, key::SIZE);
 
+impl<A: HashAlgorithm> FromHex for GenericDigest<A> {
+    type Error = ParseHexError;
+
+    fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
+        let mut output = A::Output::default();
+        hex::decode_to_slice(hex, output.as_mut()).context(MalformedHex)?;
+
+        Ok(Self(output))
+    }
+}
+
 impl FromHex for exchange::PublicKey {
     type Error = ParseHexError;
 
@@ -90,6 +100,7 @@ impl FromHex for sign::PrivateKey {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::Digest;
 
     const TABLE: [(&str, [u8; 32]); 2] = [
         (
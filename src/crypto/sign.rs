@@ -1,9 +1,10 @@
 use std::{
     fmt,
     hash::{Hash, Hasher},
+    str::FromStr,
 };
 
-use bincode::serialize_into;
+use bincode::Options;
 use ed25519_dalek::{
     Keypair as DalekKeyPair, PublicKey as DalekPublicKey,
     SecretKey as DalekPrivateKey, Signature as DalekSignature, Signer as _,
@@ -11,13 +12,17 @@ use ed25519_dalek::{
 };
 pub use ed25519_dalek::{
     KEYPAIR_LENGTH as KEYPAIRBYTES, PUBLIC_KEY_LENGTH as PUBLICKEYBYTES,
-    SECRET_KEY_LENGTH as PRIVATEKEYBYTES,
+    SECRET_KEY_LENGTH as PRIVATEKEYBYTES, SIGNATURE_LENGTH as SIGNATUREBYTES,
 };
 use rand::{rngs::OsRng, RngCore};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use snafu::{ResultExt, Snafu};
 
-use super::BincodeError;
+use super::{
+    keys::{self, KeyError},
+    BincodeError,
+};
+use crate::codec::bincode_options;
 
 #[derive(Debug, Snafu)]
 /// Error encountered when attempting to sign data using [`PrivateKey`]
@@ -27,6 +32,7 @@ use super::BincodeError;
 /// [`KeyPair`]: self::KeyPair
 pub enum SignError {
     #[snafu(display("failed to sign data: {}", source))]
+    #[snafu(visibility(pub(crate)))]
     /// The data could not be serialized for signing
     SignSerialize {
         /// Serializer error
@@ -45,6 +51,7 @@ pub enum VerifyError {
     },
 
     #[snafu(display("signature verification error: {}", source))]
+    #[snafu(visibility(pub(crate)))]
     /// The signature was invalid
     Dalek {
         /// Error backtrace
@@ -53,7 +60,7 @@ pub enum VerifyError {
 }
 
 /// A `PublicKey` used for verifying messages
-#[derive(Copy, Clone, Eq, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Eq, Debug)]
 pub struct PublicKey(DalekPublicKey);
 
 impl PublicKey {
@@ -94,10 +101,41 @@ impl Ord for PublicKey {
 
 impl fmt::Display for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for b in self.0.as_ref() {
-            write!(f, "{:02x}", b)?;
+        keys::display(self.as_ref(), f)
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        keys::parse(s)
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            keys::deserialize_str(deserializer)
+        } else {
+            DalekPublicKey::deserialize(deserializer).map(Self)
         }
-        Ok(())
     }
 }
 
@@ -208,7 +246,9 @@ impl KeyPair {
     ) -> Result<Signature, SignError> {
         let mut buffer = Vec::new();
 
-        serialize_into(&mut buffer, message).context(SignSerialize)?;
+        bincode_options()
+            .serialize_into(&mut buffer, message)
+            .context(SignSerialize)?;
 
         Ok(self.0.sign(&buffer).into())
     }
@@ -238,10 +278,20 @@ impl From<PrivateKey> for KeyPair {
 }
 
 /// A signature that can be used to verify the authenticity of a message
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Signature(DalekSignature);
 
 impl Signature {
+    /// Create a `Signature` from its raw bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VerifyError> {
+        DalekSignature::try_from(bytes).map(Self).context(Dalek)
+    }
+
+    /// Convert this `Signature` to raw bytes
+    pub fn to_bytes(&self) -> [u8; SIGNATUREBYTES] {
+        self.0.to_bytes()
+    }
+
     /// Verify that this `Signature` is valid for the given message
     pub fn verify<T: Serialize>(
         &self,
@@ -250,7 +300,9 @@ impl Signature {
     ) -> Result<(), VerifyError> {
         let mut buffer = Vec::new();
 
-        serialize_into(&mut buffer, message).context(VerifySerialize)?;
+        bincode_options()
+            .serialize_into(&mut buffer, message)
+            .context(VerifySerialize)?;
 
         pkey.0.verify(&buffer, &self.0).context(Dalek)
     }
@@ -333,9 +385,9 @@ mod tests {
                 let mut buffer = Vec::new();
                 let value = ($value);
 
-                serialize_into(&mut buffer, &value).expect("serialize failed");
+                bincode_options().serialize_into(&mut buffer, &value).expect("serialize failed");
 
-                let output: $tp = bincode::deserialize_from(Cursor::new(buffer)).expect("deserialize failed");
+                let output: $tp = bincode_options().deserialize_from(Cursor::new(buffer)).expect("deserialize failed");
 
                 assert_eq!(output, value, "different value");
            )*)
@@ -1,12 +1,27 @@
 use std::{convert::TryFrom, fmt};
 
-use bincode::{deserialize, serialize_into};
+use bincode::Options;
 use crypto_secretstream::{Header, PullStream, PushStream, Tag};
-use rand::rngs::OsRng;
+use rand::{
+    rngs::{OsRng, StdRng},
+    SeedableRng,
+};
 use serde::{Deserialize, Serialize};
 use snafu::{ensure, Backtrace, OptionExt, ResultExt, Snafu};
 
 use super::{key::Key, BincodeError};
+use crate::codec::bincode_options;
+
+/// Number of bytes added by `Push::encrypt` to every serialized message: one
+/// byte of stream tag and a 16 bytes authentication code
+pub const ENCRYPTION_OVERHEAD: usize = 17;
+
+/// Number of bytes of the stream header appended to the first message
+/// encrypted by a `Push`
+pub const HEADER_SIZE: usize = Header::BYTES;
+
+/// Number of bytes used to store the actual length of a padded message
+pub const PADDING_OVERHEAD: usize = std::mem::size_of::<u32>();
 
 #[derive(Debug, Snafu)]
 /// Error encountered when decyphering data
@@ -35,6 +50,10 @@ pub enum DecryptError {
     #[snafu(display("decryption failed"))]
     /// Error while pulling from the underlying strem
     CryptoDecrypt,
+
+    #[snafu(display("invalid padding"))]
+    /// A padded message did not contain a valid length
+    InvalidPadding,
 }
 
 #[derive(Debug, Snafu)]
@@ -50,6 +69,15 @@ pub enum EncryptError {
     #[snafu(display("encryption failed"))]
     /// Error while pushing to the underlying strem
     CryptoEncrypt,
+
+    #[snafu(display("cannot pad {} bytes to {} bytes", len, padded))]
+    /// The requested padded size cannot hold the message and its length
+    PaddingTooSmall {
+        /// Size of the message
+        len: usize,
+        /// Requested size after padding
+        padded: usize,
+    },
 }
 
 enum PushState {
@@ -80,7 +108,7 @@ impl fmt::Debug for PullState {
 /// The sending end of an encrypted channel
 pub struct Push {
     state: PushState,
-    buffer: Vec<u8>,
+    seed: Option<u64>,
 }
 
 impl Push {
@@ -88,44 +116,149 @@ impl Push {
     pub fn new(key: Key) -> Self {
         Push {
             state: PushState::Setup(key),
-            buffer: Vec::new(),
+            seed: None,
         }
     }
 
+    /// Generate the stream header from the given seed instead of the OS
+    /// random generator so that the encrypted output is reproducible. This
+    /// has no effect once the first message has been encrypted.
+    #[cfg(any(test, feature = "test"))]
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
     /// Encrypt an arbitrary message into a slice of bytes. <br />
     /// The resulting slice of bytes is allocated and returned as a `Vec<u8>`
     pub fn encrypt<T>(&mut self, message: &T) -> Result<Vec<u8>, EncryptError>
     where
         T: Serialize,
     {
-        let encrypt = |stream: &mut PushStream, mut buffer: &mut Vec<u8>| {
-            buffer.clear();
-            serialize_into(&mut buffer, message).context(SerializeEncrypt)?;
+        let mut output = Vec::new();
 
-            stream
-                .push(buffer, &[], Tag::Message)
-                .ok()
-                .context(CryptoEncrypt)?;
+        self.encrypt_into(message, &mut output)?;
 
-            Ok(())
+        Ok(output)
+    }
+
+    /// Encrypt an arbitrary message into `output`, replacing its content.
+    /// The message is serialized and encrypted in place so that no memory is
+    /// allocated if `output` is large enough.
+    pub fn encrypt_into<T>(
+        &mut self,
+        message: &T,
+        output: &mut Vec<u8>,
+    ) -> Result<(), EncryptError>
+    where
+        T: Serialize,
+    {
+        output.clear();
+        bincode_options()
+            .serialize_into(&mut *output, message)
+            .context(SerializeEncrypt)?;
+
+        self.seal(output, Tag::Message)
+    }
+
+    /// Encrypt exactly the given bytes without serializing them first
+    pub fn encrypt_bytes(
+        &mut self,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, EncryptError> {
+        let mut output = Vec::with_capacity(Self::sealed_len(plaintext.len()));
+
+        self.encrypt_bytes_into(plaintext, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Encrypt exactly the given bytes into `output`, replacing its content,
+    /// see `Push::encrypt_into`
+    pub fn encrypt_bytes_into(
+        &mut self,
+        plaintext: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), EncryptError> {
+        output.clear();
+        output.extend_from_slice(plaintext);
+
+        self.seal(output, Tag::Message)
+    }
+
+    /// Encrypt the given bytes after padding them with zeroes to `padded`
+    /// bytes. The actual length is stored in the padded message so that
+    /// `Pull::decrypt_bytes` strips the padding without further information.
+    pub fn encrypt_padded(
+        &mut self,
+        plaintext: &[u8],
+        padded: usize,
+    ) -> Result<Vec<u8>, EncryptError> {
+        let mut output = Vec::new();
+
+        self.encrypt_padded_into(plaintext, padded, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Pad and encrypt the given bytes into `output`, replacing its content,
+    /// see `Push::encrypt_padded`
+    pub fn encrypt_padded_into(
+        &mut self,
+        plaintext: &[u8],
+        padded: usize,
+        output: &mut Vec<u8>,
+    ) -> Result<(), EncryptError> {
+        let len = plaintext.len();
+
+        ensure!(
+            len <= u32::MAX as usize && len + PADDING_OVERHEAD <= padded,
+            PaddingTooSmall { len, padded }
+        );
+
+        output.clear();
+        output.extend_from_slice(&(len as u32).to_le_bytes());
+        output.extend_from_slice(plaintext);
+        output.resize(padded, 0);
+
+        // padded messages are told apart by their tag
+        self.seal(output, Tag::Push)
+    }
+
+    /// Number of bytes needed to encrypt `len` bytes of plaintext, including
+    /// the stream header in case this is the first message
+    fn sealed_len(len: usize) -> usize {
+        len + ENCRYPTION_OVERHEAD + HEADER_SIZE
+    }
+
+    /// Encrypt the content of `buffer` in place
+    fn seal(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        tag: Tag,
+    ) -> Result<(), EncryptError> {
+        let push = |stream: &mut PushStream, buffer: &mut Vec<u8>| {
+            stream.push(buffer, &[], tag).ok().context(CryptoEncrypt)
         };
 
         match &mut self.state {
             PushState::Setup(key) => {
-                let (header, mut stream) =
-                    PushStream::init(&mut OsRng, &key.clone().into());
+                let key = key.clone().into();
+                let (header, mut stream) = match self.seed.take() {
+                    Some(seed) => {
+                        PushStream::init(StdRng::seed_from_u64(seed), &key)
+                    }
+                    None => PushStream::init(OsRng, &key),
+                };
 
-                encrypt(&mut stream, &mut self.buffer)?;
-                self.buffer.extend_from_slice(header.as_ref());
+                push(&mut stream, buffer)?;
+                buffer.extend_from_slice(header.as_ref());
 
                 self.state = PushState::Run(stream);
             }
-            PushState::Run(ref mut stream) => {
-                encrypt(stream, &mut self.buffer)?
-            }
+            PushState::Run(ref mut stream) => push(stream, buffer)?,
         }
 
-        Ok(self.buffer.clone()) // TODO clearly inefficient, modify in place
+        Ok(())
     }
 }
 
@@ -156,53 +289,97 @@ impl Pull {
     where
         T: Deserialize<'de>,
     {
+        let plaintext = self.decrypt_bytes(ciphertext)?;
+
+        bincode_options()
+            .deserialize(plaintext)
+            .context(SerializeDecrypt)
+    }
+
+    /// Decrypts a slice of bytes without deserializing the result, which is
+    /// borrowed from the internal buffer of this `Pull`. Padding added by
+    /// `Push::encrypt_padded` is removed.
+    pub fn decrypt_bytes(
+        &mut self,
+        ciphertext: &[u8],
+    ) -> Result<&[u8], DecryptError> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let result = self.decrypt_into(ciphertext, &mut buffer);
+
+        self.buffer = buffer;
+        result?;
+
+        Ok(&self.buffer)
+    }
+
+    /// Decrypts a slice of bytes into `output`, replacing its content, so
+    /// that no memory is allocated if `output` is large enough. Padding
+    /// added by `Push::encrypt_padded` is removed.
+    pub fn decrypt_into(
+        &mut self,
+        ciphertext: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), DecryptError> {
         let pull = |stream: &mut PullStream,
                     ciphertext: &[u8],
                     buffer: &mut Vec<u8>| {
             buffer.clear();
             buffer.extend_from_slice(ciphertext);
 
-            stream
+            let tag = stream
                 .pull(buffer, &[])
                 .map_err(|_| CryptoDecrypt.build())?;
 
+            if tag == Tag::Push {
+                unpad(buffer)?;
+            }
+
             Ok(())
         };
 
         match &mut self.state {
             PullState::Setup(key) => {
-                ensure!(ciphertext.len() >= Header::BYTES, MissingHeader);
+                ensure!(ciphertext.len() >= HEADER_SIZE, MissingHeader);
 
                 let (ciphertext, header) =
-                    ciphertext.split_at(ciphertext.len() - Header::BYTES);
+                    ciphertext.split_at(ciphertext.len() - HEADER_SIZE);
 
                 let mut stream = PullStream::init(
                     Header::try_from(header).unwrap(), // already checked
                     &key.clone().into(),
                 );
 
-                pull(&mut stream, ciphertext, &mut self.buffer).map_err(
-                    |err| {
-                        self.state = PullState::Broken;
-                        err
-                    },
-                )?;
+                pull(&mut stream, ciphertext, output).inspect_err(|_| {
+                    self.state = PullState::Broken;
+                })?;
 
                 self.state = PullState::Run(stream);
             }
             PullState::Run(ref mut stream) => {
-                pull(stream, ciphertext, &mut self.buffer).map_err(|err| {
+                pull(stream, ciphertext, output).inspect_err(|_| {
                     self.state = PullState::Broken;
-                    err
                 })?;
             }
             PullState::Broken => BrokenStream.fail()?,
         }
 
-        deserialize(&self.buffer).context(SerializeDecrypt)
+        Ok(())
     }
 }
 
+/// Remove the padding added by `Push::encrypt_padded` from `buffer`
+fn unpad(buffer: &mut Vec<u8>) -> Result<(), DecryptError> {
+    let prefix = buffer.get(..PADDING_OVERHEAD).context(InvalidPadding)?;
+    let len = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+
+    ensure!(len <= buffer.len() - PADDING_OVERHEAD, InvalidPadding);
+
+    buffer.truncate(PADDING_OVERHEAD + len);
+    buffer.drain(..PADDING_OVERHEAD);
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -243,6 +420,71 @@ mod tests {
         }
     }
 
+    #[test]
+    fn buffers_reused() {
+        let (mut transmitter, mut receiver) = setup_test_stream();
+        let (mut ciphertext, mut plaintext) = (Vec::new(), Vec::new());
+
+        transmitter
+            .encrypt_into(&0u64, &mut ciphertext)
+            .expect("failed to encrypt");
+        receiver
+            .decrypt_into(&ciphertext, &mut plaintext)
+            .expect("failed to decrypt");
+
+        let buffers = (ciphertext.as_ptr(), plaintext.as_ptr());
+
+        for message in 1u64..128u64 {
+            transmitter
+                .encrypt_into(&message, &mut ciphertext)
+                .expect("failed to encrypt");
+            receiver
+                .decrypt_into(&ciphertext, &mut plaintext)
+                .expect("failed to decrypt");
+
+            assert_eq!(plaintext, message.to_le_bytes(), "wrong plaintext");
+            assert_eq!(
+                (ciphertext.as_ptr(), plaintext.as_ptr()),
+                buffers,
+                "buffer reallocated"
+            );
+        }
+    }
+
+    #[test]
+    fn encryption_overhead() {
+        let (mut transmitter, _) = setup_test_stream();
+        let message = vec![0u8; 100];
+        let plain =
+            bincode_options().serialized_size(&message).unwrap() as usize;
+
+        let first = transmitter.encrypt(&message).expect("failed to encrypt");
+
+        assert_eq!(first.len(), plain + ENCRYPTION_OVERHEAD + HEADER_SIZE);
+
+        let next = transmitter.encrypt(&message).expect("failed to encrypt");
+
+        assert_eq!(next.len(), plain + ENCRYPTION_OVERHEAD);
+    }
+
+    #[test]
+    fn bytes_interoperate() {
+        let (mut transmitter, mut receiver) = setup_test_stream();
+
+        let ciphertext = transmitter.encrypt(&7u32).expect("failed to encrypt");
+        let plaintext = receiver
+            .decrypt_bytes(&ciphertext)
+            .expect("failed to decrypt");
+
+        assert_eq!(plaintext, bincode_options().serialize(&7u32).unwrap());
+
+        let ciphertext = transmitter
+            .encrypt_bytes(&bincode_options().serialize(&8u32).unwrap())
+            .expect("failed to encrypt");
+
+        assert_eq!(receiver.decrypt::<u32>(&ciphertext).unwrap(), 8);
+    }
+
     #[test]
     fn corrupted_mac() {
         let (mut transmitter, mut receiver) = setup_test_stream();
@@ -260,6 +502,46 @@ mod tests {
             .expect_err("decrypted corrupted message");
     }
 
+    #[test]
+    fn padded_interoperate() {
+        let (mut transmitter, mut receiver) = setup_test_stream();
+
+        for (message, padded) in [(&b"first"[..], 64), (b"", 4), (b"x", 0)] {
+            let ciphertext = if padded == 0 {
+                transmitter.encrypt_bytes(message)
+            } else {
+                transmitter.encrypt_padded(message, padded)
+            }
+            .expect("failed to encrypt");
+
+            let plaintext = receiver
+                .decrypt_bytes(&ciphertext)
+                .expect("failed to decrypt");
+
+            assert_eq!(plaintext, message, "wrong plaintext");
+        }
+
+        transmitter
+            .encrypt_padded(b"long", 7)
+            .expect_err("padded to a smaller size");
+    }
+
+    #[test]
+    fn invalid_padding() {
+        let (mut transmitter, mut receiver) = setup_test_stream();
+
+        let mut ciphertext = 5u32.to_le_bytes().to_vec();
+
+        ciphertext.extend_from_slice(b"four");
+        transmitter
+            .seal(&mut ciphertext, Tag::Push)
+            .expect("failed to seal");
+
+        receiver
+            .decrypt_bytes(&ciphertext)
+            .expect_err("accepted invalid padding");
+    }
+
     #[test]
     fn empty_message() {
         let (_, mut receiver) = setup_test_stream();
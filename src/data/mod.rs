@@ -1,5 +1,9 @@
 #![allow(missing_docs)]
 
+/// Maps that evict their entries according to a `RetentionPolicy`
+pub mod retention;
 pub mod syncset;
 
+pub use retention::{BoundedMap, Eviction, RetentionPolicy};
+
 pub use syncset::SyncSet;
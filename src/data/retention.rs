@@ -0,0 +1,516 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Limits on the content of a [`BoundedMap`]. Limits that are not set do not
+/// apply, so that the default `RetentionPolicy` keeps every entry.
+///
+/// [`BoundedMap`]: self::BoundedMap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    max_entries: Option<usize>,
+    max_age: Option<Duration>,
+    max_weight: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Keep at most `max` entries
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Evict entries that were not inserted or touched for `max`
+    pub fn max_age(mut self, max: Duration) -> Self {
+        self.max_age = Some(max);
+        self
+    }
+
+    /// Keep the total weight of the entries at or below `max`
+    pub fn max_weight(mut self, max: u64) -> Self {
+        self.max_weight = Some(max);
+        self
+    }
+
+    /// Get the maximum number of entries, if any
+    pub fn entries(&self) -> Option<usize> {
+        self.max_entries
+    }
+
+    /// Get the maximum age of entries, if any
+    pub fn age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Get the maximum total weight of entries, if any
+    pub fn weight(&self) -> Option<u64> {
+        self.max_weight
+    }
+
+    fn expired(&self, touched: Instant, now: Instant) -> bool {
+        self.max_age
+            .is_some_and(|age| now.saturating_duration_since(touched) >= age)
+    }
+}
+
+/// The limit of a [`RetentionPolicy`] that caused an entry to be evicted
+///
+/// [`RetentionPolicy`]: self::RetentionPolicy
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Eviction {
+    /// The entry was older than the maximum age
+    Expired,
+    /// There were too many entries
+    Capacity,
+    /// The entries were too heavy
+    Weight,
+}
+
+impl fmt::Display for Eviction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::Expired => "expired",
+            Self::Capacity => "over capacity",
+            Self::Weight => "over weight",
+        };
+
+        write!(f, "{}", reason)
+    }
+}
+
+type Weigher<K, V> = Box<dyn Fn(&K, &V) -> u64 + Send + Sync>;
+type Callback<K, V> = Box<dyn FnMut(K, V, Eviction) + Send>;
+
+struct Entry<V> {
+    value: V,
+    touched: Instant,
+    weight: u64,
+    seq: u64,
+}
+
+/// A map whose content is kept within the limits of a [`RetentionPolicy`].
+/// Entries are evicted least recently touched first, either when inserting
+/// or when the owner calls [`sweep`]. <br />
+/// `BoundedMap` does not read the time itself: every method that needs it
+/// takes the current `Instant`, which must never go backwards.
+///
+/// [`RetentionPolicy`]: self::RetentionPolicy
+/// [`sweep`]: self::BoundedMap::sweep
+pub struct BoundedMap<K, V> {
+    policy: RetentionPolicy,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys ordered from least to most recently touched
+    order: BTreeMap<u64, K>,
+    next: u64,
+    weight: u64,
+    weigher: Weigher<K, V>,
+    on_evict: Option<Callback<K, V>>,
+}
+
+impl<K, V> BoundedMap<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Create an empty `BoundedMap` enforcing the given `RetentionPolicy`.
+    /// Every entry weighs 1 unless a weigher is set using `with_weigher`.
+    pub fn new(policy: RetentionPolicy) -> Self {
+        Self {
+            policy,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next: 0,
+            weight: 0,
+            weigher: Box::new(|_, _| 1),
+            on_evict: None,
+        }
+    }
+
+    /// Use `weigher` to compute the weight of each entry when it is inserted
+    pub fn with_weigher<F>(mut self, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> u64 + Send + Sync + 'static,
+    {
+        self.weigher = Box::new(weigher);
+        self
+    }
+
+    /// Call `callback` with every evicted entry, in the order in which they
+    /// are evicted. Entries that are removed or replaced are not reported.
+    pub fn on_evict<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(K, V, Eviction) + Send + 'static,
+    {
+        self.on_evict = Some(Box::new(callback));
+        self
+    }
+
+    /// Get the `RetentionPolicy` of this `BoundedMap`
+    pub fn policy(&self) -> &RetentionPolicy {
+        &self.policy
+    }
+
+    /// Change the `RetentionPolicy` of this `BoundedMap`. The new limits are
+    /// enforced on the next insertion or sweep.
+    pub fn set_policy(&mut self, policy: RetentionPolicy) {
+        self.policy = policy;
+    }
+
+    /// Insert an entry, replacing and returning the previous value for `key`
+    /// if any. The entry counts as touched at `now` and the limits are then
+    /// enforced, which may evict the new entry itself if it is too heavy.
+    pub fn insert(&mut self, key: K, value: V, now: Instant) -> Option<V> {
+        let previous = self.remove(&key);
+        let weight = (self.weigher)(&key, &value);
+        let seq = self.bump();
+
+        self.order.insert(seq, key.clone());
+        self.weight = self.weight.saturating_add(weight);
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                touched: now,
+                weight,
+                seq,
+            },
+        );
+
+        self.sweep(now);
+
+        previous
+    }
+
+    /// Mark `key` as used at `now`, which resets its age and makes it the
+    /// last entry to be evicted. Returns `false` if `key` is not present.
+    pub fn touch(&mut self, key: &K, now: Instant) -> bool {
+        let seq = self.bump();
+
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                let key = self.order.remove(&entry.seq).expect("untracked key");
+
+                entry.seq = seq;
+                entry.touched = now;
+                self.order.insert(seq, key);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the value for `key` without touching it
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+
+    /// Get a mutable reference to the value for `key` without touching it.
+    /// The weight of the entry is not updated.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|entry| &mut entry.value)
+    }
+
+    /// Check whether this `BoundedMap` contains `key`
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Remove the entry for `key` without reporting it as evicted
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+
+        self.order.remove(&entry.seq);
+        self.weight = self.weight.saturating_sub(entry.weight);
+
+        Some(entry.value)
+    }
+
+    /// Evict entries until this `BoundedMap` is within the limits of its
+    /// `RetentionPolicy` at `now`, returning the number of evicted entries.
+    /// Owners should call this periodically when using a maximum age.
+    pub fn sweep(&mut self, now: Instant) -> usize {
+        let mut evicted = 0;
+
+        while let Some(reason) = self.overflow(now) {
+            let (_, key) = self.order.pop_first().expect("empty but over");
+            let entry = self.entries.remove(&key).expect("untracked key");
+
+            self.weight = self.weight.saturating_sub(entry.weight);
+            evicted += 1;
+
+            if let Some(callback) = self.on_evict.as_mut() {
+                callback(key, entry.value, reason);
+            }
+        }
+
+        evicted
+    }
+
+    /// Number of entries in this `BoundedMap`
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if this `BoundedMap` is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total weight of the entries in this `BoundedMap`
+    pub fn weight(&self) -> u64 {
+        self.weight
+    }
+
+    /// Iterate over the entries from least to most recently touched
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.order
+            .values()
+            .map(move |key| (key, &self.entries[key].value))
+    }
+
+    /// Get the reason why the least recently touched entry must be evicted
+    /// at `now`, if it must
+    fn overflow(&self, now: Instant) -> Option<Eviction> {
+        let (_, oldest) = self.order.first_key_value()?;
+        let policy = &self.policy;
+
+        if policy.expired(self.entries[oldest].touched, now) {
+            Some(Eviction::Expired)
+        } else if policy.max_entries.is_some_and(|max| self.len() > max) {
+            Some(Eviction::Capacity)
+        } else if policy.max_weight.is_some_and(|max| self.weight > max) {
+            Some(Eviction::Weight)
+        } else {
+            None
+        }
+    }
+
+    fn bump(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+}
+
+impl<K, V> fmt::Debug for BoundedMap<K, V>
+where
+    K: Hash + Eq + Clone + fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    type Log = Arc<Mutex<Vec<(u32, Eviction)>>>;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// Create a `BoundedMap` that logs its evictions
+    fn logged(policy: RetentionPolicy) -> (BoundedMap<u32, u64>, Log) {
+        let log = Log::default();
+        let map = BoundedMap::new(policy).on_evict({
+            let log = log.clone();
+
+            move |key, _, reason| log.lock().unwrap().push((key, reason))
+        });
+
+        (map, log)
+    }
+
+    fn keys(map: &BoundedMap<u32, u64>) -> Vec<u32> {
+        map.iter().map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn unbounded() {
+        let (mut map, log) = logged(RetentionPolicy::default());
+        let now = Instant::now();
+
+        for i in 0..100 {
+            map.insert(i, 0, now);
+        }
+
+        assert_eq!(map.sweep(now + secs(1 << 20)), 0);
+        assert_eq!(map.len(), 100);
+        assert!(log.lock().unwrap().is_empty(), "spurious eviction");
+    }
+
+    #[test]
+    fn max_entries() {
+        let (mut map, log) = logged(RetentionPolicy::default().max_entries(3));
+        let now = Instant::now();
+
+        for i in 0..3 {
+            map.insert(i, 0, now);
+        }
+
+        assert!(map.touch(&0, now), "entry missing");
+        assert_eq!(map.insert(1, 1, now), Some(0), "value not replaced");
+
+        map.insert(3, 0, now);
+
+        assert_eq!(keys(&map), [0, 1, 3]);
+        assert_eq!(*log.lock().unwrap(), [(2, Eviction::Capacity)]);
+    }
+
+    #[test]
+    fn max_age() {
+        let policy = RetentionPolicy::default().max_age(secs(10));
+        let (mut map, log) = logged(policy);
+        let start = Instant::now();
+
+        map.insert(0, 0, start);
+        map.insert(1, 0, start + secs(5));
+        map.insert(2, 0, start + secs(6));
+        map.touch(&1, start + secs(9));
+
+        assert_eq!(map.sweep(start + secs(9)), 0, "fresh entries evicted");
+        assert_eq!(map.sweep(start + secs(16)), 2);
+        assert_eq!(keys(&map), [1]);
+
+        // insertion also gets rid of expired entries
+        map.insert(3, 0, start + secs(19));
+
+        assert_eq!(keys(&map), [3]);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (0, Eviction::Expired),
+                (2, Eviction::Expired),
+                (1, Eviction::Expired)
+            ]
+        );
+    }
+
+    #[test]
+    fn max_weight() {
+        let policy = RetentionPolicy::default().max_weight(10);
+        let (map, log) = logged(policy);
+        let mut map = map.with_weigher(|_, v| *v);
+        let now = Instant::now();
+
+        map.insert(0, 4, now);
+        map.insert(1, 4, now);
+
+        assert_eq!(map.weight(), 8);
+
+        map.insert(2, 3, now);
+
+        assert_eq!(keys(&map), [1, 2]);
+        assert_eq!(map.weight(), 7);
+
+        // replacing an entry uses its new weight
+        map.insert(1, 1, now);
+
+        assert_eq!(map.weight(), 4);
+
+        // an entry heavier than the limit evicts everything, itself included
+        map.insert(3, 11, now);
+
+        assert!(map.is_empty(), "heavy entry kept");
+        assert_eq!(map.weight(), 0);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (0, Eviction::Weight),
+                (2, Eviction::Weight),
+                (1, Eviction::Weight),
+                (3, Eviction::Weight)
+            ]
+        );
+    }
+
+    #[test]
+    fn weight_and_age() {
+        let policy =
+            RetentionPolicy::default().max_weight(10).max_age(secs(10));
+        let (map, log) = logged(policy);
+        let mut map = map.with_weigher(|_, v| *v);
+        let start = Instant::now();
+
+        map.insert(0, 5, start);
+        map.insert(1, 5, start + secs(8));
+
+        // the expired entry makes room, no entry is evicted for its weight
+        map.insert(2, 5, start + secs(12));
+
+        assert_eq!(keys(&map), [1, 2]);
+
+        // age is checked first even when the entries are also too heavy
+        map.insert(3, 8, start + secs(19));
+
+        assert_eq!(keys(&map), [3]);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (0, Eviction::Expired),
+                (1, Eviction::Expired),
+                (2, Eviction::Weight)
+            ]
+        );
+    }
+
+    #[test]
+    fn burst_ordering() {
+        let policy = RetentionPolicy::default()
+            .max_entries(50)
+            .max_weight(100)
+            .max_age(secs(100));
+        let (mut map, log) = logged(policy);
+        let start = Instant::now();
+
+        for i in 0..50 {
+            map.insert(i, 0, start + secs(u64::from(i)));
+        }
+
+        for i in (0..50).step_by(2) {
+            map.touch(&i, start + secs(60));
+        }
+
+        map.set_policy(policy.max_entries(5));
+
+        // odd keys are the least recently touched, the oldest of them expire
+        // and the others are evicted over capacity before the even ones
+        assert_eq!(map.sweep(start + secs(110)), 45);
+
+        let expected: Vec<_> = [1, 3, 5, 7, 9]
+            .map(|i| (i, Eviction::Expired))
+            .into_iter()
+            .chain(
+                (11..50)
+                    .step_by(2)
+                    .chain((0..40).step_by(2))
+                    .map(|i| (i, Eviction::Capacity)),
+            )
+            .collect();
+
+        assert_eq!(*log.lock().unwrap(), expected);
+        assert_eq!(keys(&map), [40, 42, 44, 46, 48]);
+    }
+
+    #[test]
+    fn removal_is_not_eviction() {
+        let (mut map, log) = logged(RetentionPolicy::default().max_entries(1));
+        let now = Instant::now();
+
+        map.insert(0, 7, now);
+
+        assert_eq!(map.remove(&0), Some(7));
+        assert!(!map.touch(&0, now), "removed entry touched");
+
+        map.insert(1, 0, now);
+
+        assert!(log.lock().unwrap().is_empty(), "removal reported");
+    }
+}
@@ -1,3 +1,5 @@
+use std::io::Error as IoError;
+
 use crate::crypto::hash::HashError;
 
 use snafu::Snafu;
@@ -13,4 +15,12 @@ pub enum SyncError {
     Hash { source: HashError },
     #[snafu(display("path length error: {}", what))]
     PathLength { what: &'static str },
+    #[snafu(display("node store error: {}", source))]
+    Store { source: IoError },
+    #[snafu(display("serialization error: {}", source))]
+    Serialization { source: bincode::Error },
+    #[snafu(display("synchronization still running after {} rounds", max))]
+    TooManyRounds { max: usize },
+    #[snafu(display("synchronization already finished"))]
+    Finished,
 }
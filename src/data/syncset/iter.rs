@@ -0,0 +1,324 @@
+use std::iter::FusedIterator;
+
+use super::{
+    errors::SyncError,
+    node::Node,
+    path::{Direction, Path},
+    Syncable,
+};
+use crate::crypto::hash::{Blake3, GenericDigest, HashAlgorithm};
+
+/// Iterator over references to the elements of a `SyncSet`, in the order of
+/// their hashes, see `SyncSet::iter`. Iteration stops after yielding an
+/// error if a subtree can not be loaded from the `NodeStore`.
+pub struct Iter<'a, Data: Syncable, A: HashAlgorithm = Blake3> {
+    stack: Vec<&'a Node<Data, A>>,
+    remaining: usize,
+}
+
+impl<'a, Data: Syncable, A: HashAlgorithm> Iter<'a, Data, A> {
+    pub(super) fn new(root: &'a Node<Data, A>) -> Self {
+        Self {
+            stack: vec![root],
+            remaining: root.size(),
+        }
+    }
+}
+
+impl<'a, Data: Syncable, A: HashAlgorithm> Iterator for Iter<'a, Data, A> {
+    type Item = Result<&'a Data, SyncError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            match node {
+                Node::Empty => (),
+                Node::Leaf { item, .. } => {
+                    self.remaining -= 1;
+                    return Some(Ok(item));
+                }
+                Node::Internal { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+                Node::Stored { .. } => match node.loaded() {
+                    Ok(loaded) => self.stack.push(loaded),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // a failed load ends iteration early with a single error
+        let error = usize::from(!self.stack.is_empty());
+
+        (0, Some(self.remaining.max(error)))
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> FusedIterator for Iter<'_, Data, A> {}
+
+/// Iterator over the elements moved out of a `SyncSet`, in the order of
+/// their hashes, see `SyncSet::into_iter`. Iteration stops after yielding an
+/// error if a subtree can not be loaded from the `NodeStore`.
+pub struct IntoIter<Data: Syncable, A: HashAlgorithm = Blake3> {
+    stack: Vec<Node<Data, A>>,
+    remaining: usize,
+}
+
+impl<Data: Syncable, A: HashAlgorithm> IntoIter<Data, A> {
+    pub(super) fn new(root: Node<Data, A>) -> Self {
+        Self {
+            remaining: root.size(),
+            stack: vec![root],
+        }
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> Iterator for IntoIter<Data, A> {
+    type Item = Result<Data, SyncError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(mut node) = self.stack.pop() {
+            match node {
+                Node::Empty => (),
+                Node::Leaf { item, .. } => {
+                    self.remaining -= 1;
+                    return Some(Ok(item));
+                }
+                Node::Internal { left, right, .. } => {
+                    self.stack.push(*right);
+                    self.stack.push(*left);
+                }
+                Node::Stored { .. } => match node.take_loaded() {
+                    Ok(loaded) => self.stack.push(loaded),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // a failed load ends iteration early with a single error
+        let error = usize::from(!self.stack.is_empty());
+
+        (0, Some(self.remaining.max(error)))
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> FusedIterator for IntoIter<Data, A> {}
+
+/// Set operation computed by `Walk`
+#[derive(Clone, Copy)]
+pub(super) enum Operation {
+    Intersection,
+    Union,
+    Difference,
+}
+
+/// A subtree of a `Walk`, `None` being empty
+type Subtree<'a, Data, A> = Option<&'a Node<Data, A>>;
+
+/// Both children of a subtree of a `Walk`
+type Children<'a, Data, A> = (Subtree<'a, Data, A>, Subtree<'a, Data, A>);
+
+/// Work left to do by a `Walk`
+enum Task<'a, Data: Syncable, A: HashAlgorithm> {
+    /// Compare two subtrees at the same prefix and depth
+    Compare(Subtree<'a, Data, A>, Subtree<'a, Data, A>, usize),
+    /// Yield every element of a subtree
+    Yield(&'a Node<Data, A>),
+}
+
+/// Iterator computing an `Operation` between two `SyncSet`s by walking both
+/// trees at once, skipping subtrees whose labels are equal. Elements are
+/// returned in the order of their hashes, and iteration stops after yielding
+/// an error if a subtree can not be loaded or hashed.
+pub(super) struct Walk<'a, Data: Syncable, A: HashAlgorithm> {
+    operation: Operation,
+    stack: Vec<Task<'a, Data, A>>,
+}
+
+impl<'a, Data: Syncable, A: HashAlgorithm> Walk<'a, Data, A> {
+    pub(super) fn new(
+        operation: Operation,
+        left: &'a Node<Data, A>,
+        right: &'a Node<Data, A>,
+    ) -> Self {
+        Self {
+            operation,
+            stack: vec![Task::Compare(
+                Self::present(left),
+                Self::present(right),
+                0,
+            )],
+        }
+    }
+
+    fn present(node: &'a Node<Data, A>) -> Subtree<'a, Data, A> {
+        match node {
+            Node::Empty => None,
+            _ => Some(node),
+        }
+    }
+
+    fn label(node: &Node<Data, A>) -> Result<GenericDigest<A>, SyncError> {
+        node.label()
+    }
+
+    /// Returns both children of a subtree, or `None` for a leaf
+    fn children(
+        node: &'a Node<Data, A>,
+    ) -> Result<Option<Children<'a, Data, A>>, SyncError> {
+        match node {
+            Node::Internal { left, right, .. } => {
+                Ok(Some((Self::present(left), Self::present(right))))
+            }
+            Node::Stored { .. } => Self::children(node.loaded()?),
+            _ => Ok(None),
+        }
+    }
+
+    /// Split a leaf at the given depth as if it were a subtree, returning
+    /// the leaf on the side of its path and nothing on the other side
+    fn split(
+        leaf: &'a Node<Data, A>,
+        depth: usize,
+    ) -> Result<Children<'a, Data, A>, SyncError> {
+        let path = Path(Self::label(leaf)?);
+
+        Ok(match path.at(depth).expect("leaf split at maximum depth") {
+            Direction::Left => (Some(leaf), None),
+            Direction::Right => (None, Some(leaf)),
+        })
+    }
+
+    fn compare(
+        &mut self,
+        left: Subtree<'a, Data, A>,
+        right: Subtree<'a, Data, A>,
+        depth: usize,
+    ) -> Result<Option<&'a Data>, SyncError> {
+        use Operation::*;
+
+        let (left, right) = match (left, right) {
+            (None, None) => return Ok(None),
+            (Some(node), None) => {
+                if let Union | Difference = self.operation {
+                    self.stack.push(Task::Yield(node));
+                }
+
+                return Ok(None);
+            }
+            (None, Some(node)) => {
+                if let Union = self.operation {
+                    self.stack.push(Task::Yield(node));
+                }
+
+                return Ok(None);
+            }
+            (Some(left), Some(right)) => (left, right),
+        };
+
+        let (left_label, right_label) =
+            (Self::label(left)?, Self::label(right)?);
+
+        if left_label == right_label {
+            if let Intersection | Union = self.operation {
+                self.stack.push(Task::Yield(left));
+            }
+
+            return Ok(None);
+        }
+
+        let (lefts, rights) =
+            match (Self::children(left)?, Self::children(right)?) {
+                (None, None) => {
+                    // two different leaves
+                    return Ok(match self.operation {
+                        Intersection => None,
+                        Difference => Self::item(left),
+                        Union if left_label < right_label => {
+                            self.stack.push(Task::Yield(right));
+                            Self::item(left)
+                        }
+                        Union => {
+                            self.stack.push(Task::Yield(left));
+                            Self::item(right)
+                        }
+                    });
+                }
+                (Some(lefts), Some(rights)) => (lefts, rights),
+                (Some(lefts), None) => (lefts, Self::split(right, depth)?),
+                (None, Some(rights)) => (Self::split(left, depth)?, rights),
+            };
+
+        self.stack.push(Task::Compare(lefts.1, rights.1, depth + 1));
+        self.stack.push(Task::Compare(lefts.0, rights.0, depth + 1));
+
+        Ok(None)
+    }
+
+    fn item(node: &'a Node<Data, A>) -> Option<&'a Data> {
+        match node {
+            Node::Leaf { item, .. } => Some(item),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, Data: Syncable, A: HashAlgorithm> Iterator for Walk<'a, Data, A> {
+    type Item = Result<&'a Data, SyncError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(task) = self.stack.pop() {
+            let item = match task {
+                Task::Compare(left, right, depth) => {
+                    self.compare(left, right, depth)
+                }
+                Task::Yield(node) => {
+                    match node {
+                        Node::Empty => (),
+                        Node::Leaf { item, .. } => return Some(Ok(item)),
+                        Node::Internal { left, right, .. } => {
+                            self.stack.push(Task::Yield(right));
+                            self.stack.push(Task::Yield(left));
+                        }
+                        Node::Stored { .. } => match node.loaded() {
+                            Ok(loaded) => self.stack.push(Task::Yield(loaded)),
+                            Err(e) => {
+                                self.stack.clear();
+                                return Some(Err(e));
+                            }
+                        },
+                    }
+
+                    Ok(None)
+                }
+            };
+
+            match item {
+                Ok(None) => (),
+                Ok(Some(item)) => return Some(Ok(item)),
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> FusedIterator for Walk<'_, Data, A> {}
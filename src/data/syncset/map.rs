@@ -0,0 +1,379 @@
+use std::{collections::HashMap, fmt, hash::Hash as StdHash, sync::Arc};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use snafu::ResultExt;
+
+use super::{errors::*, SyncSet, Synchronizer};
+use crate::crypto::hash::{hash_with, Blake3, GenericDigest, HashAlgorithm};
+
+/// A key along with its value, the elements of the `SyncSet` underlying a
+/// `SyncMap`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Entry<K, V> {
+    /// Key of the entry
+    pub key: K,
+    /// Value of the entry
+    pub value: V,
+}
+
+/// A value along with the version it was written at. Merging two
+/// `Versioned` values keeps the one with the greatest version, so that the
+/// last writer wins.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Versioned<V> {
+    /// Version of the value, usually a timestamp or a counter
+    pub version: u64,
+    /// The value itself
+    pub value: V,
+}
+
+impl<V> Versioned<V> {
+    /// Wrap `value` written at `version`
+    pub fn new(version: u64, value: V) -> Self {
+        Self { version, value }
+    }
+}
+
+/// How a `SyncMap` resolves a key that has a different value on each
+/// replica. Merging must be deterministic and commutative for replicas to
+/// converge.
+pub trait Merge: Sized {
+    /// Merge the `local` value with the `remote` one
+    fn merge(local: &Self, remote: &Self) -> Self;
+}
+
+impl<V: Serialize + Clone> Merge for Versioned<V> {
+    /// Keep the value with the greatest version, or the one with the
+    /// greatest hash for equal versions
+    fn merge(local: &Self, remote: &Self) -> Self {
+        let key = |v: &Self| (v.version, hash_with::<Blake3, _>(&v.value).ok());
+
+        if key(remote) > key(local) {
+            remote.clone()
+        } else {
+            local.clone()
+        }
+    }
+}
+
+type MergeFn<K, V> = Arc<dyn Fn(&K, &V, &V) -> V + Send + Sync>;
+
+/// `Synchronizer` of the entries of a `SyncMap`, see `SyncMap::synchronizer`
+pub type MapSynchronizer<'a, K, V, A = Blake3> =
+    Synchronizer<Entry<K, V>, A, &'a SyncSet<Entry<K, V>, A>>;
+
+/// A map replicated using a `SyncSet` of its `Entry`s. Replacing the value
+/// of a key removes the previous `Entry` from the set, so that each key has
+/// a single value. <br />
+/// Maps are synchronized like sets using the `Synchronizer` returned by
+/// `SyncMap::synchronizer`, the entries it finds missing are then given to
+/// `SyncMap::apply`. Keys with a different value on each side are resolved
+/// using the merge function of the map, which defaults to `Merge::merge`.
+pub struct SyncMap<K, V, A: HashAlgorithm = Blake3>
+where
+    Entry<K, V>: Serialize + PartialEq,
+{
+    set: SyncSet<Entry<K, V>, A>,
+    /// Digest of the `Entry` of each key in `set`
+    index: HashMap<K, GenericDigest<A>>,
+    merge: MergeFn<K, V>,
+}
+
+impl<K, V> SyncMap<K, V>
+where
+    K: Serialize + PartialEq + Eq + StdHash + Clone,
+    V: Serialize + PartialEq + Merge,
+{
+    /// Create an empty map resolving conflicts using `Merge::merge`
+    pub fn new() -> Self {
+        Self::with_algorithm()
+    }
+}
+
+impl<K, V, A> SyncMap<K, V, A>
+where
+    K: Serialize + PartialEq + Eq + StdHash + Clone,
+    V: Serialize + PartialEq,
+    A: HashAlgorithm,
+{
+    /// Create an empty map using the algorithm `A`, resolving conflicts
+    /// using `Merge::merge`
+    pub fn with_algorithm() -> Self
+    where
+        V: Merge,
+    {
+        Self::with_merge(|_, local, remote| V::merge(local, remote))
+    }
+
+    /// Create an empty map resolving conflicts using `merge`, which is given
+    /// the key along with the local value and the remote one
+    pub fn with_merge<F>(merge: F) -> Self
+    where
+        F: Fn(&K, &V, &V) -> V + Send + Sync + 'static,
+    {
+        Self {
+            set: SyncSet::with_algorithm(),
+            index: HashMap::new(),
+            merge: Arc::new(merge),
+        }
+    }
+
+    /// Set the value of `key`, returning its previous value if any
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, SyncError> {
+        let previous = self.remove(&key)?;
+        let entry = Entry { key, value };
+        let digest = hash_with::<A, _>(&entry).context(Hash)?;
+        let key = entry.key.clone();
+
+        self.set.insert(entry)?;
+        self.index.insert(key, digest);
+
+        Ok(previous)
+    }
+
+    /// Get the value of `key`, if any
+    pub fn get(&self, key: &K) -> Result<Option<&V>, SyncError> {
+        match self.index.get(key) {
+            Some(digest) => {
+                Ok(self.set.get_by_digest(digest)?.map(|entry| &entry.value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Remove `key` from the map, returning its value if any
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, SyncError> {
+        match self.index.remove(key) {
+            Some(digest) => {
+                Ok(self.set.delete_by_digest(&digest)?.map(|entry| entry.value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether `key` has a value
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Returns the number of keys in the map
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Checks whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns the `SyncSet` of the entries of the map
+    pub fn set(&self) -> &SyncSet<Entry<K, V>, A> {
+        &self.set
+    }
+
+    /// Returns a `Synchronizer` for the entries of the map. Once it is done,
+    /// the entries it found missing should be given to `SyncMap::apply`.
+    pub fn synchronizer(&self) -> MapSynchronizer<'_, K, V, A>
+    where
+        K: DeserializeOwned,
+        V: Clone + DeserializeOwned,
+    {
+        Synchronizer::new(&self.set)
+    }
+
+    /// Apply the entries of a remote replica that this map is missing,
+    /// merging the values of keys that also have a local value. Returns the
+    /// keys whose value changed.
+    pub fn apply(
+        &mut self,
+        remote: impl IntoIterator<Item = Entry<K, V>>,
+    ) -> Result<Vec<K>, SyncError> {
+        let mut changed = Vec::new();
+
+        for Entry { key, value } in remote {
+            let value = match self.get(&key)? {
+                Some(local) => {
+                    let merged = (self.merge)(&key, local, &value);
+
+                    if &merged == local {
+                        continue;
+                    }
+
+                    merged
+                }
+                None => value,
+            };
+
+            self.insert(key.clone(), value)?;
+            changed.push(key);
+        }
+
+        Ok(changed)
+    }
+}
+
+impl<K, V> Default for SyncMap<K, V>
+where
+    K: Serialize + PartialEq + Eq + StdHash + Clone,
+    V: Serialize + PartialEq + Merge,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, A> fmt::Debug for SyncMap<K, V, A>
+where
+    Entry<K, V>: Serialize + PartialEq,
+    A: HashAlgorithm,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyncMap")
+            .field("len", &self.index.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::syncset::SyncStep;
+
+    type Map = SyncMap<u32, Versioned<String>>;
+
+    /// Run a synchronization between `initiator` and `responder` and apply
+    /// its outcome on both sides
+    fn round_trip<V>(
+        initiator: &mut SyncMap<u32, V>,
+        responder: &mut SyncMap<u32, V>,
+    ) where
+        V: Serialize + DeserializeOwned + PartialEq + Clone,
+    {
+        let (from_responder, from_initiator) = {
+            let mut local = initiator.synchronizer();
+            let mut remote = responder.synchronizer();
+            let mut message = local.initial_message().expect("no message");
+            let mut outcomes = [None, None];
+            let mut turn = 1;
+
+            while outcomes.iter().any(Option::is_none) {
+                let step = if turn == 0 {
+                    local.handle_message(&message)
+                } else {
+                    remote.handle_message(&message)
+                };
+
+                match step {
+                    SyncStep::Reply(reply) => message = reply,
+                    SyncStep::Done { to_add, reply, .. } => {
+                        outcomes[turn] = Some(to_add);
+
+                        match reply {
+                            Some(reply) => message = reply,
+                            None => break,
+                        }
+                    }
+                    SyncStep::Error(e) => panic!("sync failed: {}", e),
+                }
+
+                turn = 1 - turn;
+            }
+
+            let [local, remote] = outcomes.map(Option::unwrap_or_default);
+
+            (local, remote)
+        };
+
+        initiator.apply(from_responder).expect("apply failed");
+        responder.apply(from_initiator).expect("apply failed");
+    }
+
+    fn value(version: u64, value: &str) -> Versioned<String> {
+        Versioned::new(version, value.to_string())
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut map = Map::new();
+
+        assert_eq!(map.insert(1, value(0, "a")).unwrap(), None);
+        assert_eq!(map.insert(1, value(1, "b")).unwrap(), Some(value(0, "a")));
+        assert_eq!(map.get(&1).unwrap(), Some(&value(1, "b")));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.set().size(), 1, "previous entry lingers");
+        assert_eq!(map.remove(&1).unwrap(), Some(value(1, "b")));
+        assert!(map.is_empty() && map.set().size() == 0);
+    }
+
+    #[test]
+    fn divergent_updates_converge() {
+        let mut alice = Map::new();
+        let mut bob = Map::new();
+
+        for key in 0..100 {
+            alice.insert(key, value(0, "base")).unwrap();
+            bob.insert(key, value(0, "base")).unwrap();
+        }
+
+        // both update the same key concurrently, bob writes last
+        alice.insert(7, value(1, "alice")).unwrap();
+        bob.insert(7, value(2, "bob")).unwrap();
+        alice.insert(200, value(0, "only alice")).unwrap();
+
+        round_trip(&mut alice, &mut bob);
+
+        for map in [&alice, &bob] {
+            assert_eq!(map.get(&7).unwrap(), Some(&value(2, "bob")));
+            assert_eq!(map.get(&200).unwrap(), Some(&value(0, "only alice")));
+            assert_eq!(map.len(), 101);
+            assert_eq!(map.set().size(), 101);
+        }
+
+        // identical maps have nothing left to exchange
+        let before = alice.len();
+
+        round_trip(&mut alice, &mut bob);
+
+        assert_eq!(alice.len(), before);
+    }
+
+    #[test]
+    fn pairs_in_a_set_linger() {
+        let mut naive = SyncSet::new();
+
+        naive.insert((1u32, 0u64)).unwrap();
+        naive.insert((1u32, 1u64)).unwrap();
+
+        // the stale pair is still an element of the set
+        assert_eq!(naive.size(), 2);
+
+        let mut map = SyncMap::<u32, Versioned<u64>>::new();
+        let mut replica = SyncMap::<u32, Versioned<u64>>::new();
+
+        map.insert(1, Versioned::new(0, 0)).unwrap();
+        map.insert(1, Versioned::new(1, 1)).unwrap();
+        replica.insert(1, Versioned::new(1, 1)).unwrap();
+
+        let message = map.synchronizer().initial_message().unwrap();
+
+        assert!(matches!(
+            replica.synchronizer().handle_message(&message),
+            SyncStep::Done { to_add, to_remove, .. }
+                if to_add.is_empty() && to_remove.is_empty()
+        ));
+    }
+
+    #[test]
+    fn custom_merge() {
+        let mut alice = SyncMap::<u32, u64>::with_merge(|_, a, b| *a.max(b));
+        let mut bob = SyncMap::<u32, u64>::with_merge(|_, a, b| *a.max(b));
+
+        alice.insert(0, 5).unwrap();
+        bob.insert(0, 9).unwrap();
+
+        round_trip(&mut alice, &mut bob);
+
+        assert_eq!(alice.get(&0).unwrap(), Some(&9));
+        assert_eq!(bob.get(&0).unwrap(), Some(&9));
+    }
+}
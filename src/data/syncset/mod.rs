@@ -1,60 +1,148 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, sync::Arc};
 
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 
 mod errors;
+mod iter;
+mod map;
 mod node;
 mod path;
 mod set;
+mod store;
+mod synchronizer;
 
 pub use errors::*;
+pub use iter::{IntoIter, Iter};
+use iter::{Operation, Walk};
+pub use map::{Entry, MapSynchronizer, Merge, SyncMap, Versioned};
 use node::Node;
 pub use path::*;
 pub use set::Set;
 use snafu::ResultExt;
+use store::Backend;
+#[cfg(feature = "file-store")]
+pub use store::FileStore;
+pub use store::{InMemoryStore, NodeId, NodeStore};
+pub use synchronizer::{SyncStep, Synchronizer, DEFAULT_MAX_ROUNDS};
 
-use crate::crypto::hash;
+use crate::crypto::hash::{hash_with, Blake3, GenericDigest, HashAlgorithm};
 
 pub trait Syncable: Serialize + PartialEq {}
 impl<T: Serialize + PartialEq> Syncable for T {}
 
 const DUMP_THRESHOLD: usize = 5;
 
+/// Depth at which subtrees are moved to the `NodeStore` by `SyncSet::with_store`
+pub const DEFAULT_SPILL_DEPTH: usize = 16;
+
 /// A Set based on Merkle trees, with efficient (O(K log N), K number of differences,
 /// N number of total items) symmetric difference computation.
 /// Note that the SyncErrors returned by most of the functions here
 /// can almost always only happen due to Read errors. Thus, if you're using the tree to store
 /// something simple like integers, it is safe to assume the operations on this tree will
 /// never return errors (ignoring edge cases like hash collisions)
-pub struct SyncSet<Data: Syncable> {
-    root: Node<Data>,
+/// Items and labels are hashed using the algorithm `A`, sets can only be
+/// synchronized with sets using the same algorithm.
+pub struct SyncSet<Data: Syncable, A: HashAlgorithm = Blake3> {
+    root: Node<Data, A>,
+    backend: Option<Arc<Backend<Data, A>>>,
 }
 
 // Round, the structure used to sync Syncsets
 #[derive(Debug, Clone)]
-pub struct Round<'a, 'b, Data: Syncable> {
-    pub view: Vec<Set<&'a Data>>,
+pub struct Round<'a, 'b, Data: Syncable, A: HashAlgorithm = Blake3> {
+    pub view: Vec<Set<&'a Data, A>>,
     pub add: Vec<&'b Data>,
     pub remove: Vec<&'a Data>,
 }
 
 impl<Data: Syncable> SyncSet<Data> {
+    /// Creates a new Set with an empty root
+    pub fn new() -> SyncSet<Data> {
+        Self::with_algorithm()
+    }
+
+    /// Creates a new Set that keeps subtrees below `DEFAULT_SPILL_DEPTH` in
+    /// the given `NodeStore`, only their labels are kept in memory.
+    pub fn with_store<S>(store: S) -> SyncSet<Data>
+    where
+        S: NodeStore + 'static,
+        Data: DeserializeOwned,
+    {
+        Self::with_store_at_depth(store, DEFAULT_SPILL_DEPTH)
+    }
+
+    /// Creates a new Set that keeps subtrees rooted at the given depth in the
+    /// given `NodeStore`. Stored subtrees are loaded on demand and written back
+    /// whenever they are modified.
+    pub fn with_store_at_depth<S>(store: S, depth: usize) -> SyncSet<Data>
+    where
+        S: NodeStore + 'static,
+        Data: DeserializeOwned,
+    {
+        Self::with_store_and_algorithm(store, depth)
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> SyncSet<Data, A> {
     /// Attempts to insert the given element into the set.
     /// Returns Ok(true) if the element was successfully inserted,
     /// Ok(false) if it was already present
     /// Note that unlike all of the other functions implemented here, this can
     /// also fail when a hash collision occurs
     pub fn insert(&mut self, data: Data) -> Result<bool, SyncError> {
-        let path = Path::new(&data).context(Hash)?;
-        self.root.insert(data, 0, path)
+        let path = Path::hashed(&data).context(Hash)?;
+        let inserted = self.root.insert(data, 0, path.clone())?;
+
+        if let Some(backend) = &self.backend {
+            self.root.spill(&path, 0, backend)?;
+        }
+
+        Ok(inserted)
     }
 
     /// Attempts to delete the given element from the set, and
     /// returns Ok(true) if the element was contained in the
     /// syncset, Ok(false) if it wasn't
     pub fn delete(&mut self, data_to_delete: &Data) -> Result<bool, SyncError> {
-        let path = Path::new(data_to_delete).context(Hash)?;
-        Ok(self.root.delete(data_to_delete, path, 0))
+        let path = Path::hashed(data_to_delete).context(Hash)?;
+        self.root.delete(data_to_delete, path, 0)
+    }
+
+    /// Deletes the element whose hash is the given digest from the set
+    /// without hashing anything. Returns Ok(Some(element)) with the removed
+    /// element if it was contained in the syncset, Ok(None) if it wasn't
+    pub fn delete_by_digest(
+        &mut self,
+        digest: &GenericDigest<A>,
+    ) -> Result<Option<Data>, SyncError> {
+        self.root.remove(Path(*digest), 0, &|_| true)
+    }
+
+    /// Returns the element whose hash is the given digest, if it is contained
+    /// in the set
+    pub fn get_by_digest(
+        &self,
+        digest: &GenericDigest<A>,
+    ) -> Result<Option<&Data>, SyncError> {
+        use Node::*;
+        let path = Path(*digest).prefix(Path::<A>::NUM_BITS);
+        match self.root.node_at(&path, 0)? {
+            Leaf { item, hash } if hash == digest => Ok(Some(item)),
+            Leaf { .. } | Empty => Ok(None),
+            Internal { .. } | Stored { .. } => {
+                panic!("Branch at maximum depth!")
+            }
+        }
+    }
+
+    /// Checks if the element whose hash is the given digest is contained in
+    /// the set
+    pub fn contains_digest(
+        &self,
+        digest: &GenericDigest<A>,
+    ) -> Result<bool, SyncError> {
+        Ok(self.get_by_digest(digest)?.is_some())
     }
 
     /// Returns the Set of nodes at the Path, the dump parameter determines
@@ -63,12 +151,12 @@ impl<Data: Syncable> SyncSet<Data> {
     /// will return all the elements of the set.
     pub fn get(
         &self,
-        prefix: &Prefix,
+        prefix: &Prefix<A>,
         dump: bool,
-    ) -> Result<Set<&Data>, SyncError> {
+    ) -> Result<Set<&Data, A>, SyncError> {
         use Node::*;
 
-        let node_at_prefix = self.root.node_at(prefix, 0);
+        let node_at_prefix = self.root.node_at(prefix, 0)?;
         match node_at_prefix {
             Leaf { hash, .. } => {
                 // Because this is a leaf, its hash is that of its data element, thus label == path
@@ -76,15 +164,15 @@ impl<Data: Syncable> SyncSet<Data> {
 
                 // Check if the prefix actually matches, or if we just ran out of nodes
                 if prefix.is_prefix_of(&leaf_path) {
-                    Ok(Set::new_dataset(prefix.clone(), node_at_prefix, dump))
+                    Set::new_dataset(prefix.clone(), node_at_prefix, dump)
                 } else {
                     Ok(Set::new_empty_dataset(prefix.clone(), dump))
                 }
             }
-            Internal { .. } => {
+            Internal { .. } | Stored { .. } => {
                 // We either dump the branch, or we return the corresponding Label/Path structure
                 if dump || node_at_prefix.size() <= DUMP_THRESHOLD {
-                    Ok(Set::new_dataset(prefix.clone(), node_at_prefix, dump))
+                    Set::new_dataset(prefix.clone(), node_at_prefix, dump)
                 } else {
                     Ok(Set::LabelSet {
                         label: node_at_prefix.label()?,
@@ -101,20 +189,44 @@ impl<Data: Syncable> SyncSet<Data> {
     /// Checks if the element is contained in the set
     pub fn contains(&self, data: &Data) -> Result<bool, SyncError> {
         use Node::*;
-        let path = Prefix::new(data, Path::NUM_BITS).context(Hash)?;
-        let node_at_path = self.root.node_at(&path, 0);
+        let path = Prefix::hashed(data, Path::<A>::NUM_BITS).context(Hash)?;
+        let node_at_path = self.root.node_at(&path, 0)?;
         match node_at_path {
             Leaf {
                 item: leaf_data, ..
             } => Ok(data == leaf_data),
             Empty => Ok(false),
-            Internal { .. } => panic!("Branch at maximum depth!"),
+            Internal { .. } | Stored { .. } => {
+                panic!("Branch at maximum depth!")
+            }
         }
     }
 
-    /// Creates a new Set with an empty root
-    pub fn new() -> SyncSet<Data> {
-        SyncSet { root: Node::Empty }
+    /// Creates a new Set with an empty root using the algorithm `A`
+    pub fn with_algorithm() -> Self {
+        SyncSet {
+            root: Node::Empty,
+            backend: None,
+        }
+    }
+
+    /// Creates a new Set using the algorithm `A` that keeps subtrees rooted
+    /// at the given depth in the given `NodeStore`
+    pub fn with_store_and_algorithm<S>(store: S, depth: usize) -> Self
+    where
+        S: NodeStore + 'static,
+        Data: DeserializeOwned,
+    {
+        SyncSet {
+            root: Node::Empty,
+            backend: Some(Arc::new(Backend::new(store, depth))),
+        }
+    }
+
+    /// Drops the subtrees that were loaded from the `NodeStore` by read
+    /// operations, releasing their memory
+    pub fn evict(&mut self) {
+        self.root.evict();
     }
 
     /// Returns the number of elements contained in the set
@@ -122,8 +234,79 @@ impl<Data: Syncable> SyncSet<Data> {
         self.root.size()
     }
 
+    /// Returns an iterator over the elements of the set, in the order of
+    /// their hashes like the elements of a dumped `Set`.
+    ///
+    /// # Errors
+    /// Iteration ends with an error if a subtree can not be loaded from the
+    /// `NodeStore`
+    pub fn iter(&self) -> Iter<'_, Data, A> {
+        Iter::new(&self.root)
+    }
+
+    /// Returns an iterator over the elements contained in both sets, in the
+    /// order of their hashes. Subtrees with equal labels are not compared.
+    ///
+    /// # Errors
+    /// Iteration ends with an error if a subtree can not be loaded from the
+    /// `NodeStore`
+    pub fn intersection<'a>(
+        &'a self,
+        other: &'a SyncSet<Data, A>,
+    ) -> impl Iterator<Item = Result<&'a Data, SyncError>> + 'a {
+        Walk::new(Operation::Intersection, &self.root, &other.root)
+    }
+
+    /// Returns an iterator over the elements contained in either set, in the
+    /// order of their hashes. Subtrees with equal labels are not compared.
+    ///
+    /// # Errors
+    /// Iteration ends with an error if a subtree can not be loaded from the
+    /// `NodeStore`
+    pub fn union<'a>(
+        &'a self,
+        other: &'a SyncSet<Data, A>,
+    ) -> impl Iterator<Item = Result<&'a Data, SyncError>> + 'a {
+        Walk::new(Operation::Union, &self.root, &other.root)
+    }
+
+    /// Returns an iterator over the elements of this set that are not
+    /// contained in `other`, in the order of their hashes. Subtrees with
+    /// equal labels are skipped, so this takes O(K log N) steps with K the
+    /// number of differences between both sets.
+    ///
+    /// # Errors
+    /// Iteration ends with an error if a subtree can not be loaded from the
+    /// `NodeStore`
+    pub fn difference<'a>(
+        &'a self,
+        other: &'a SyncSet<Data, A>,
+    ) -> impl Iterator<Item = Result<&'a Data, SyncError>> + 'a {
+        Walk::new(Operation::Difference, &self.root, &other.root)
+    }
+
+    /// Checks whether every element of this set is contained in `other`
+    pub fn is_subset(
+        &self,
+        other: &SyncSet<Data, A>,
+    ) -> Result<bool, SyncError> {
+        if self.size() > other.size() {
+            return Ok(false);
+        }
+
+        Ok(self.difference(other).next().transpose()?.is_none())
+    }
+
+    /// Checks whether every element of `other` is contained in this set
+    pub fn is_superset(
+        &self,
+        other: &SyncSet<Data, A>,
+    ) -> Result<bool, SyncError> {
+        other.is_subset(self)
+    }
+
     /// Returns the inital Round
-    pub fn start_sync(&self) -> Result<Round<Data>, SyncError> {
+    pub fn start_sync(&self) -> Result<Round<'_, '_, Data, A>, SyncError> {
         let root_view = self.get(&Prefix::empty(), false)?;
         Ok(Round {
             view: vec![root_view],
@@ -139,8 +322,8 @@ impl<Data: Syncable> SyncSet<Data> {
     /// the view, but not in this set.
     pub fn sync<'a, 'b>(
         &'a self,
-        view: &'b [Set<Data>],
-    ) -> Result<Round<'a, 'b, Data>, SyncError> {
+        view: &'b [Set<Data, A>],
+    ) -> Result<Round<'a, 'b, Data, A>, SyncError> {
         // Figure out how much to pre-allocate
         let mut elem_count = 0;
         for set in view {
@@ -149,7 +332,8 @@ impl<Data: Syncable> SyncSet<Data> {
             }
         }
 
-        let mut new_view: Vec<Set<&Data>> = Vec::with_capacity(view.len() * 2);
+        let mut new_view: Vec<Set<&Data, A>> =
+            Vec::with_capacity(view.len() * 2);
         let mut add: Vec<&Data> = Vec::with_capacity(elem_count);
         let mut remove: Vec<&Data> = Vec::with_capacity(elem_count);
 
@@ -227,18 +411,18 @@ impl<Data: Syncable> SyncSet<Data> {
                             while i < remote_data.len() && j < local_data.len()
                             {
                                 // Update hashes
-                                if local_hash_opt == None {
+                                if local_hash_opt.is_none() {
                                     local_hash_opt = Some(
-                                        hash(unsafe {
+                                        hash_with::<A, _>(unsafe {
                                             local_data.get_unchecked(j)
                                         })
                                         .context(Hash)?,
                                     );
                                 };
 
-                                if remote_hash_opt == None {
+                                if remote_hash_opt.is_none() {
                                     remote_hash_opt = Some(
-                                        hash(unsafe {
+                                        hash_with::<A, _>(unsafe {
                                             remote_data.get_unchecked(i)
                                         })
                                         .context(Hash)?,
@@ -312,9 +496,34 @@ impl<Data: Syncable> SyncSet<Data> {
     }
 }
 
-impl<Data: Syncable> Default for SyncSet<Data> {
-    fn default() -> SyncSet<Data> {
-        SyncSet::new()
+impl<Data: Syncable, A: HashAlgorithm> Default for SyncSet<Data, A> {
+    fn default() -> Self {
+        Self::with_algorithm()
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> IntoIterator for SyncSet<Data, A> {
+    type Item = Result<Data, SyncError>;
+    type IntoIter = IntoIter<Data, A>;
+
+    /// Moves the elements out of the set, in the order of their hashes.
+    ///
+    /// # Errors
+    /// Iteration ends with an error if a subtree can not be loaded from the
+    /// `NodeStore`
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self.root)
+    }
+}
+
+impl<'a, Data: Syncable, A: HashAlgorithm> IntoIterator
+    for &'a SyncSet<Data, A>
+{
+    type Item = Result<&'a Data, SyncError>;
+    type IntoIter = Iter<'a, Data, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
@@ -325,6 +534,7 @@ mod tests {
     use rand::Rng;
 
     use super::*;
+    use crate::crypto::hash::hash;
 
     const NUM_ITERS: u32 = 50000;
     #[test]
@@ -341,7 +551,7 @@ mod tests {
             set.get(&Prefix::empty(), true).unwrap()
         {
             let mut previous =
-                hash(underlying.get(0).expect("get() returns no elements"))
+                hash(underlying.first().expect("get() returns no elements"))
                     .unwrap();
             for i in 1..NUM_ITERS {
                 let current = hash(underlying.get(i as usize).unwrap())
@@ -370,7 +580,7 @@ mod tests {
                 );
                 assert_eq!(prefix, expected_prefix);
                 if let n @ Node::Internal { .. } =
-                    syncset.root.node_at(prefix, 0)
+                    syncset.root.node_at(prefix, 0).unwrap()
                 {
                     assert_eq!(
                         &n.label().unwrap(),
@@ -439,7 +649,7 @@ mod tests {
             "The extremely unlikely case of a hash collision has occurred"
         );
 
-        for depth in 0..Path::NUM_BITS {
+        for depth in 0..<Path>::NUM_BITS {
             {
                 // Test if the element is contained
                 let expected_prefix = arbitrary_elem_path.prefix(depth);
@@ -536,12 +746,479 @@ mod tests {
         }
     }
 
+    #[test]
+    fn add_find_digest() {
+        let mut set = HashSet::new();
+        let mut syncset = SyncSet::new();
+        let mut generator = rand::thread_rng();
+        for i in 0..NUM_ITERS {
+            if generator.gen() {
+                set.insert(i);
+                syncset.insert(i).unwrap();
+            }
+        }
+
+        for i in 0..2 * NUM_ITERS {
+            let digest = hash(&i).unwrap();
+            let should_find = set.contains(&i);
+
+            assert_eq!(
+                syncset.contains_digest(&digest).unwrap(),
+                should_find,
+                "Element {} present in only one of the sets",
+                i
+            );
+            assert_eq!(
+                syncset.get_by_digest(&digest).unwrap(),
+                set.get(&i),
+                "Element {} fetched by digest doesn't match",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn remove_find_digest() {
+        let mut expected_size = NUM_ITERS as usize;
+        let mut set = HashSet::new();
+        let mut syncset = SyncSet::new();
+        let mut generator = rand::thread_rng();
+        for i in 0..NUM_ITERS {
+            set.insert(i);
+            syncset.insert(i).unwrap();
+        }
+
+        for i in 0..NUM_ITERS {
+            if generator.gen() {
+                set.remove(&i);
+                assert_eq!(
+                    syncset.delete_by_digest(&hash(&i).unwrap()).unwrap(),
+                    Some(i),
+                    "Deletion of {} returned the wrong element",
+                    i
+                );
+                expected_size -= 1;
+            }
+        }
+
+        assert_eq!(syncset.size(), expected_size, "Syncset has wrong size");
+
+        for i in 0..2 * NUM_ITERS {
+            let digest = hash(&i).unwrap();
+
+            assert_eq!(
+                set.contains(&i),
+                syncset.contains_digest(&digest).unwrap(),
+                "Element {} present in only one of the sets",
+                i
+            );
+            assert_eq!(
+                syncset.delete_by_digest(&digest).unwrap(),
+                set.get(&i).copied(),
+                "Deleting {} twice returned the wrong element",
+                i
+            );
+        }
+
+        assert_eq!(syncset.size(), 0, "Syncset isn't empty");
+    }
+
+    /// Checks that the labels of `set` match those of a set freshly built
+    /// from `expected` along the path of every element of `probes`
+    fn check_labels(set: &SyncSet<u32>, expected: &HashSet<u32>, probes: u32) {
+        let mut fresh = SyncSet::new();
+        for i in expected {
+            fresh.insert(*i).unwrap();
+        }
+
+        assert_eq!(set.size(), fresh.size(), "sizes differ");
+        assert_eq!(
+            set.root.label().unwrap(),
+            fresh.root.label().unwrap(),
+            "root labels differ"
+        );
+
+        for i in 0..probes {
+            let path = Path::new(&i).unwrap();
+
+            for depth in 0..8 {
+                let prefix = path.prefix(depth);
+
+                assert_eq!(
+                    set.get(&prefix, false).unwrap(),
+                    fresh.get(&prefix, false).unwrap(),
+                    "sets differ at depth {} on the path of {}",
+                    depth,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn delete_invalidates_labels() {
+        const ITERS: u32 = 1000;
+
+        let mut set: HashSet<_> = (0..ITERS).collect();
+        let mut syncset = SyncSet::new();
+        for i in 0..ITERS {
+            syncset.insert(i).unwrap();
+        }
+
+        // populate the cached labels and sizes along every path
+        check_labels(&syncset, &set, ITERS);
+
+        for i in (0..ITERS).step_by(3) {
+            set.remove(&i);
+            assert!(syncset.delete(&i).unwrap());
+        }
+
+        check_labels(&syncset, &set, ITERS);
+
+        for i in (1..ITERS).step_by(3) {
+            set.remove(&i);
+            assert_eq!(
+                syncset.delete_by_digest(&hash(&i).unwrap()).unwrap(),
+                Some(i)
+            );
+        }
+
+        check_labels(&syncset, &set, ITERS);
+    }
+
+    const STORE_DEPTH: usize = 8;
+    const STORE_ITERS: u32 = 5000;
+
     #[test]
     fn sync() {
+        sync_sets(SyncSet::new(), SyncSet::new(), NUM_ITERS);
+    }
+
+    #[cfg(feature = "sha256")]
+    #[test]
+    fn sync_sha256() {
+        use crate::crypto::hash::{hash_with, GenericDigest, Sha256};
+
+        let mut syncset: SyncSet<u32, Sha256> = SyncSet::with_algorithm();
+
+        syncset.insert(42).unwrap();
+
+        let digest = hash_with::<Sha256, _>(&42u32).unwrap();
+        // the bytes of a blake3 digest don't address anything
+        let foreign =
+            GenericDigest::<Sha256>::from(*hash(&42u32).unwrap().as_bytes());
+
+        assert_eq!(syncset.get_by_digest(&digest).unwrap(), Some(&42));
+        assert!(!syncset.contains_digest(&foreign).unwrap());
+
+        sync_sets(
+            SyncSet::<u32, Sha256>::with_algorithm(),
+            SyncSet::with_store_and_algorithm(
+                InMemoryStore::new(),
+                STORE_DEPTH,
+            ),
+            STORE_ITERS,
+        );
+    }
+
+    #[test]
+    fn sync_in_memory_store() {
+        sync_sets(
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH),
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH),
+            STORE_ITERS,
+        );
+    }
+
+    #[cfg(feature = "file-store")]
+    #[test]
+    fn sync_file_store() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!(
+                "drop-syncset-{}-{}",
+                std::process::id(),
+                name
+            ))
+        };
+        let store = |name| {
+            FileStore::create(path(name)).expect("failed to create store")
+        };
+
+        sync_sets(
+            SyncSet::with_store_at_depth(store("alice"), STORE_DEPTH),
+            SyncSet::with_store_at_depth(store("bob"), STORE_DEPTH),
+            STORE_ITERS,
+        );
+
+        let _ = std::fs::remove_file(path("alice"));
+        let _ = std::fs::remove_file(path("bob"));
+    }
+
+    #[test]
+    fn store_matches_memory() {
+        let mut memory = SyncSet::new();
+        let mut stored =
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH);
+
+        for i in 0..STORE_ITERS {
+            assert!(memory.insert(i).unwrap());
+            assert!(stored.insert(i).unwrap());
+        }
+
+        for i in (0..STORE_ITERS).step_by(3) {
+            assert!(memory.delete(&i).unwrap());
+            assert!(stored.delete(&i).unwrap());
+        }
+
+        assert_eq!(stored.size(), memory.size(), "sizes differ");
+        assert_eq!(
+            stored.root.label().unwrap(),
+            memory.root.label().unwrap(),
+            "root labels differ"
+        );
+
+        let path = Path::new(&1u32).unwrap();
+
+        for depth in 0..=2 * STORE_DEPTH {
+            let prefix = path.prefix(depth);
+
+            assert_eq!(
+                stored.get(&prefix, false).unwrap(),
+                memory.get(&prefix, false).unwrap(),
+                "sets differ at depth {}",
+                depth
+            );
+        }
+
+        stored.evict();
+
+        assert_eq!(
+            stored.get(&Prefix::empty(), true).unwrap(),
+            memory.get(&Prefix::empty(), true).unwrap(),
+            "dumps differ"
+        );
+
+        for i in 0..STORE_ITERS {
+            assert_eq!(stored.contains(&i).unwrap(), i % 3 != 0);
+        }
+    }
+
+    #[test]
+    fn iter_matches_dump() {
+        let mut memory = SyncSet::new();
+        let mut stored =
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH);
+
+        for i in 0..STORE_ITERS {
+            memory.insert(i).unwrap();
+            stored.insert(i).unwrap();
+        }
+
+        let dump = match memory.get(&Prefix::empty(), true).unwrap() {
+            Set::ListSet { underlying, .. } => underlying,
+            Set::LabelSet { .. } => panic!("get() returns a LabelSet"),
+        };
+
+        assert_eq!(memory.iter().count(), STORE_ITERS as usize);
+        assert_eq!(memory.iter().collect::<Result<Vec<_>, _>>().unwrap(), dump);
+        assert_eq!(stored.iter().collect::<Result<Vec<_>, _>>().unwrap(), dump);
+
+        let owned: Vec<u32> = dump.into_iter().copied().collect();
+
+        assert_eq!(
+            memory.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            owned
+        );
+        assert_eq!(
+            stored.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            owned
+        );
+        assert!(SyncSet::<u32>::new().into_iter().next().is_none());
+    }
+
+    fn check_operations(left: &SyncSet<u32>, right: &SyncSet<u32>) {
+        let expected_left: HashSet<u32> =
+            left.iter().map(|item| *item.unwrap()).collect();
+        let expected_right: HashSet<u32> =
+            right.iter().map(|item| *item.unwrap()).collect();
+
+        let intersection: Vec<_> =
+            left.intersection(right).collect::<Result<_, _>>().unwrap();
+        let union: Vec<_> =
+            left.union(right).collect::<Result<_, _>>().unwrap();
+        let difference: Vec<_> =
+            left.difference(right).collect::<Result<_, _>>().unwrap();
+
+        // every operation returns elements in the order of their hashes
+        let ordered = |items: &[&u32]| {
+            assert!(
+                items
+                    .windows(2)
+                    .all(|w| hash(w[0]).unwrap() < hash(w[1]).unwrap()),
+                "elements out of order"
+            );
+        };
+
+        ordered(&intersection);
+        ordered(&union);
+        ordered(&difference);
+
+        assert_eq!(
+            intersection.into_iter().copied().collect::<HashSet<_>>(),
+            &expected_left & &expected_right
+        );
+        assert_eq!(
+            union.into_iter().copied().collect::<HashSet<_>>(),
+            &expected_left | &expected_right
+        );
+        assert_eq!(
+            difference.into_iter().copied().collect::<HashSet<_>>(),
+            &expected_left - &expected_right
+        );
+        assert_eq!(
+            left.is_subset(right).unwrap(),
+            expected_left.is_subset(&expected_right)
+        );
+        assert_eq!(
+            left.is_superset(right).unwrap(),
+            expected_left.is_superset(&expected_right)
+        );
+    }
+
+    #[test]
+    fn set_operations() {
+        let mut left = SyncSet::new();
+        let mut right = SyncSet::new();
+        let mut stored =
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH);
+
+        for i in 0..STORE_ITERS {
+            left.insert(i).unwrap();
+            stored.insert(i).unwrap();
+            right.insert(i + STORE_ITERS / 2).unwrap();
+        }
+
+        check_operations(&left, &right);
+        check_operations(&right, &left);
+        check_operations(&stored, &right);
+        check_operations(&left, &stored);
+        check_operations(&left, &SyncSet::new());
+        check_operations(&SyncSet::new(), &left);
+
+        let mut subset = SyncSet::new();
+
+        for i in (0..STORE_ITERS).step_by(7) {
+            subset.insert(i).unwrap();
+        }
+
+        check_operations(&subset, &left);
+        assert!(subset.is_subset(&left).unwrap());
+        assert!(left.is_superset(&subset).unwrap());
+        assert!(left.is_subset(&stored).unwrap());
+        assert!(stored.is_subset(&left).unwrap());
+        assert_eq!(left.difference(&stored).count(), 0);
+    }
+
+    /// A `NodeStore` whose reads can be made to fail
+    #[derive(Default)]
+    struct FailingStore {
+        inner: InMemoryStore,
+        failing: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl NodeStore for FailingStore {
+        fn get(&self, id: NodeId) -> std::io::Result<Vec<u8>> {
+            if self.failing.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(std::io::ErrorKind::Other.into());
+            }
+
+            self.inner.get(id)
+        }
+
+        fn put(&mut self, id: NodeId, bytes: Vec<u8>) -> std::io::Result<()> {
+            self.inner.put(id, bytes)
+        }
+
+        fn remove(&mut self, id: NodeId) -> std::io::Result<()> {
+            self.inner.remove(id)
+        }
+    }
+
+    #[test]
+    fn failed_load_ends_iteration() {
+        let store = FailingStore::default();
+        let failing = store.failing.clone();
+        let mut stored = SyncSet::with_store_at_depth(store, STORE_DEPTH);
+        let mut memory = SyncSet::new();
+
+        for i in 0..STORE_ITERS {
+            stored.insert(i).unwrap();
+            memory.insert(i + 1).unwrap();
+        }
+
+        failing.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let mut iter = stored.iter();
+
+        assert!(matches!(iter.next(), Some(Err(SyncError::Store { .. }))));
+        assert!(iter.next().is_none());
+
+        let mut difference = stored.difference(&memory);
+
+        assert!(matches!(
+            difference.next(),
+            Some(Err(SyncError::Store { .. }))
+        ));
+        assert!(difference.next().is_none());
+        drop(difference);
+
+        assert!(stored.is_subset(&memory).is_err());
+        assert!(memory.is_superset(&stored).is_err());
+        assert!(stored.into_iter().any(|item| item.is_err()));
+    }
+
+    #[test]
+    fn digest_store_matches_memory() {
+        let mut memory = SyncSet::new();
+        let mut stored =
+            SyncSet::with_store_at_depth(InMemoryStore::new(), STORE_DEPTH);
+
+        for i in 0..STORE_ITERS {
+            assert!(memory.insert(i).unwrap());
+            assert!(stored.insert(i).unwrap());
+        }
+
+        for i in (0..STORE_ITERS).step_by(3) {
+            let digest = hash(&i).unwrap();
+
+            assert_eq!(memory.delete_by_digest(&digest).unwrap(), Some(i));
+            assert_eq!(stored.delete_by_digest(&digest).unwrap(), Some(i));
+        }
+
+        assert_eq!(stored.size(), memory.size(), "sizes differ");
+        assert_eq!(
+            stored.root.label().unwrap(),
+            memory.root.label().unwrap(),
+            "root labels differ"
+        );
+
+        stored.evict();
+
+        for i in 0..STORE_ITERS {
+            let digest = hash(&i).unwrap();
+            let expected = if i % 3 != 0 { Some(&i) } else { None };
+
+            assert_eq!(stored.get_by_digest(&digest).unwrap(), expected);
+        }
+    }
+
+    fn sync_sets<A: HashAlgorithm>(
+        mut alice: SyncSet<u32, A>,
+        mut bob: SyncSet<u32, A>,
+        count: u32,
+    ) {
         type Set = HashSet<u32>;
-        let mut alice = SyncSet::new();
-        let mut bob = SyncSet::new();
-        for i in 0..NUM_ITERS {
+        for i in 0..count {
             assert!(alice.insert(i).unwrap(), "Inserting element {} fails", i);
             assert!(bob.insert(i).unwrap(), "Inserting element {} fails", i);
         }
@@ -556,7 +1233,7 @@ mod tests {
         let mut elems_bob_thinks_alice_has = Set::new();
         let mut elems_bob_thinks_alice_hasnt = Set::new();
 
-        for i in NUM_ITERS..num_extra_elems + NUM_ITERS {
+        for i in count..num_extra_elems + count {
             if generator.gen() {
                 expected_diff_alice.insert(i);
                 assert!(
@@ -1,16 +1,22 @@
 use std::{
-    cell::{Cell, RefCell},
+    cell::{Cell, OnceCell, RefCell},
     mem,
+    sync::Arc,
 };
 
+use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 
-use super::{errors::*, path::*, Syncable};
-use crate::crypto::hash::{hash, Digest};
+use super::{errors::*, path::*, store::*, Syncable};
+use crate::crypto::hash::{hash_with, Blake3, GenericDigest, HashAlgorithm};
 
 /// Private type used for the binary tree
-#[derive(Debug)]
-pub(super) enum Node<Data: Syncable> {
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Data: Serialize",
+    deserialize = "Data: serde::de::DeserializeOwned"
+))]
+pub(super) enum Node<Data: Syncable, A: HashAlgorithm = Blake3> {
     // Empty leaf
     Empty,
 
@@ -19,60 +25,86 @@ pub(super) enum Node<Data: Syncable> {
         // Data contained in the leaf
         item: Data,
         // Potentially empty cached hash
-        hash: Digest,
+        hash: GenericDigest<A>,
     },
 
     Internal {
         // Pointer to the child nodes
-        right: Box<Node<Data>>,
-        left: Box<Node<Data>>,
+        right: Box<Node<Data, A>>,
+        left: Box<Node<Data, A>>,
 
         // Pre-computed values for label and size
-        cached_label: RefCell<Option<Digest>>,
+        cached_label: RefCell<Option<GenericDigest<A>>>,
         cached_size: Cell<Option<usize>>,
     },
+
+    // Internal node whose subtree lives in a `NodeStore`
+    #[serde(skip)]
+    Stored {
+        // Identifier of the subtree in the store
+        id: NodeId,
+        // Label and size of the stored subtree
+        label: GenericDigest<A>,
+        size: usize,
+        // Subtree loaded by read accesses, dropped on the next mutation
+        loaded: OnceCell<Box<Node<Data, A>>>,
+        backend: Arc<Backend<Data, A>>,
+    },
 }
 
-impl<Data: Syncable> Node<Data> {
+impl<Data: Syncable, A: HashAlgorithm> Node<Data, A> {
     /// Finds the first node at a given path. If a (potentially empty) Leaf node is encountered
     /// prior to the path's max depth, a reference to that node is returned.
     /// Otherwise, if the end of the path is reached, then then the iterated node will be returned
     /// by reference.
-    pub fn node_at(&self, prefix: &Prefix, depth: usize) -> &Node<Data> {
+    pub fn node_at(
+        &self,
+        prefix: &Prefix<A>,
+        depth: usize,
+    ) -> Result<&Node<Data, A>, SyncError> {
         if let Some(dir) = prefix.at(depth) {
-            if let Node::Internal { left, right, .. } = &self {
-                // Fork -> recurse into left or right
-                if dir == Direction::Left {
-                    left.node_at(prefix, depth + 1)
-                } else {
-                    right.node_at(prefix, depth + 1)
+            match self {
+                Node::Internal { left, right, .. } => {
+                    // Fork -> recurse into left or right
+                    if dir == Direction::Left {
+                        left.node_at(prefix, depth + 1)
+                    } else {
+                        right.node_at(prefix, depth + 1)
+                    }
                 }
-            } else {
+                // Stored fork -> load it and recurse
+                Node::Stored { .. } => self.loaded()?.node_at(prefix, depth),
                 // Leaf -> this is the node wanted
-                self
+                _ => Ok(self),
             }
         } else {
             // End of path reached, this is the node wanted
-            self
+            Ok(self)
         }
     }
 
-    pub fn dump(&self) -> Vec<&Data> {
+    pub fn dump(&self) -> Result<Vec<&Data>, SyncError> {
         let mut result = Vec::with_capacity(self.size());
-        self.dump_recursive(&mut result);
+        self.dump_recursive(&mut result)?;
         debug_assert_eq!(result.len(), self.size());
-        result
+        Ok(result)
     }
 
-    fn dump_recursive<'a>(&'a self, result: &mut Vec<&'a Data>) {
+    fn dump_recursive<'a>(
+        &'a self,
+        result: &mut Vec<&'a Data>,
+    ) -> Result<(), SyncError> {
         match self {
             Node::Leaf { item, .. } => result.push(item),
             Node::Empty => (),
             Node::Internal { left, right, .. } => {
-                left.dump_recursive(result);
-                right.dump_recursive(result);
+                left.dump_recursive(result)?;
+                right.dump_recursive(result)?;
             }
+            Node::Stored { .. } => self.loaded()?.dump_recursive(result)?,
         }
+
+        Ok(())
     }
 
     /// Returns the number of children (including itself) a node has.
@@ -102,6 +134,9 @@ impl<Data: Syncable> Node<Data> {
 
             // A non-empty leaf has one element
             Leaf { .. } => 1,
+
+            // Size of stored subtrees is always known
+            Stored { size, .. } => *size,
         }
     }
 
@@ -109,12 +144,38 @@ impl<Data: Syncable> Node<Data> {
     pub fn delete(
         &mut self,
         item_to_delete: &Data,
-        path: Path,
+        path: Path<A>,
         depth: usize,
-    ) -> bool {
-        let deletion_successful = match self {
+    ) -> Result<bool, SyncError> {
+        let removed =
+            self.remove(path, depth, &|item| item == item_to_delete)?;
+
+        Ok(removed.is_some())
+    }
+
+    /// Removes the leaf whose hash is the given path if its item satisfies
+    /// `matches`, recursively on Nodes, and returns the removed item
+    pub fn remove<F>(
+        &mut self,
+        path: Path<A>,
+        depth: usize,
+        matches: &F,
+    ) -> Result<Option<Data>, SyncError>
+    where
+        F: Fn(&Data) -> bool,
+    {
+        if let Node::Stored { .. } = self {
+            let mut inner = self.take_loaded()?;
+            let removed = inner.remove(path, depth, matches)?;
+
+            self.write_back(inner, removed.is_some())?;
+
+            return Ok(removed);
+        }
+
+        let removed = match self {
             // Can't delete what's not there
-            Node::Empty => false,
+            Node::Empty => None,
 
             // Branch - recurse
             Node::Internal {
@@ -125,18 +186,29 @@ impl<Data: Syncable> Node<Data> {
                 if path.at(depth).expect("Recursion at max depth happened")
                     == Direction::Left
                 {
-                    left.delete(item_to_delete, path, depth + 1)
+                    left.remove(path, depth + 1, matches)?
                 } else {
-                    right.delete(item_to_delete, path, depth + 1)
+                    right.remove(path, depth + 1, matches)?
                 }
             }
 
-            // Check for potential collision, and delete if elmnt matches
-            Node::Leaf { ref item, .. } => item == item_to_delete,
+            // Check for potential collision, and take the item if it matches
+            Node::Leaf { ref item, hash } => {
+                if *hash == path.0 && matches(item) {
+                    match self.swap(Node::Empty) {
+                        Node::Leaf { item, .. } => Some(item),
+                        _ => unreachable!("leaf already matched"),
+                    }
+                } else {
+                    None
+                }
+            }
+
+            Node::Stored { .. } => unreachable!("stored node already handled"),
         };
 
         // Pull up the tree's elements
-        if deletion_successful {
+        if removed.is_some() {
             // Acquire ownership and delete/clean up
             let tmp = self.swap(Node::Empty);
             let new = tmp.pull_up_delete();
@@ -145,14 +217,14 @@ impl<Data: Syncable> Node<Data> {
             self.swap(new);
         };
 
-        deletion_successful
+        Ok(removed)
     }
 
     // Helper function for delete()
     // Cleans up branches, and transforms leaves into Empty leaves
     // Note that this is meant to be used recursively starting at
     // the bottom
-    fn pull_up_delete(self) -> Node<Data> {
+    fn pull_up_delete(self) -> Node<Data, A> {
         use Node::*;
         match self {
             Internal { left, right, .. } => {
@@ -179,7 +251,7 @@ impl<Data: Syncable> Node<Data> {
         &mut self,
         item: Data,
         depth: usize,
-        path: Path,
+        path: Path<A>,
     ) -> Result<bool, SyncError> {
         match self {
             // Trivial case
@@ -238,9 +310,136 @@ impl<Data: Syncable> Node<Data> {
 
                 Ok(success)
             }
+            Node::Stored { .. } => {
+                let mut inner = self.take_loaded()?;
+                let success = inner.insert(item, depth, path);
+
+                self.write_back(inner, matches!(success, Ok(true)))?;
+
+                success
+            }
         }
     }
 
+    /// Moves the internal node found at the backend's depth along the given
+    /// path to the backend's store
+    pub fn spill(
+        &mut self,
+        path: &Path<A>,
+        depth: usize,
+        backend: &Arc<Backend<Data, A>>,
+    ) -> Result<(), SyncError> {
+        match self {
+            Node::Internal { left, right, .. } if depth < backend.depth() => {
+                if path.at(depth)? == Direction::Left {
+                    left.spill(path, depth + 1, backend)
+                } else {
+                    right.spill(path, depth + 1, backend)
+                }
+            }
+            Node::Internal { .. } => {
+                let id = backend.next_id();
+                let label = self.label()?;
+                let size = self.size();
+
+                backend.save(id, self).context(Store)?;
+
+                self.swap(Node::Stored {
+                    id,
+                    label,
+                    size,
+                    loaded: OnceCell::new(),
+                    backend: backend.clone(),
+                });
+
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops all subtrees that were loaded from the store
+    pub fn evict(&mut self) {
+        match self {
+            Node::Internal { left, right, .. } => {
+                left.evict();
+                right.evict();
+            }
+            Node::Stored { loaded, .. } => {
+                loaded.take();
+            }
+            _ => (),
+        }
+    }
+
+    // Returns the subtree of a stored node, loading it if needed
+    pub(super) fn loaded(&self) -> Result<&Node<Data, A>, SyncError> {
+        match self {
+            Node::Stored {
+                id,
+                loaded,
+                backend,
+                ..
+            } => {
+                if let Some(node) = loaded.get() {
+                    return Ok(node);
+                }
+
+                let node = backend.load(*id).context(Store)?;
+
+                Ok(loaded.get_or_init(|| Box::new(node)))
+            }
+            _ => Ok(self),
+        }
+    }
+
+    // Takes ownership of the subtree of a stored node, loading it if needed
+    pub(super) fn take_loaded(&mut self) -> Result<Node<Data, A>, SyncError> {
+        match self {
+            Node::Stored {
+                id,
+                loaded,
+                backend,
+                ..
+            } => match loaded.take() {
+                Some(node) => Ok(*node),
+                None => backend.load(*id).context(Store),
+            },
+            _ => Ok(self.swap(Node::Empty)),
+        }
+    }
+
+    // Puts back a subtree taken from a stored node, writing it to the store if
+    // it was modified. Subtrees that no longer contain an internal node are
+    // kept in memory instead.
+    fn write_back(
+        &mut self,
+        inner: Node<Data, A>,
+        dirty: bool,
+    ) -> Result<(), SyncError> {
+        if let Node::Stored {
+            id,
+            label,
+            size,
+            loaded,
+            backend,
+        } = self
+        {
+            if !dirty {
+                let _ = loaded.set(Box::new(inner));
+            } else if let Node::Internal { .. } = inner {
+                *label = inner.label()?;
+                *size = inner.size();
+                backend.save(*id, &inner).context(Store)?;
+            } else {
+                backend.remove(*id).context(Store)?;
+                self.swap(inner);
+            }
+        }
+
+        Ok(())
+    }
+
     // Compare the item. Returns false for non-leaves
     fn cmp_item(&self, other: &Data) -> bool {
         match self {
@@ -266,11 +465,11 @@ impl<Data: Syncable> Node<Data> {
     // Makes a tree with 2 leaves. Do not call with path0=path1
     fn make_tree(
         item0: Data,
-        path0: Path,
+        path0: Path<A>,
         item1: Data,
-        path1: Path,
+        path1: Path<A>,
         depth: usize,
-    ) -> Node<Data> {
+    ) -> Node<Data, A> {
         use Direction::*;
         debug_assert_ne!(path0, path1);
         if path0.at(depth).expect(
@@ -308,16 +507,16 @@ impl<Data: Syncable> Node<Data> {
     }
 
     // Convenience constructors
-    fn new_leaf(item: Data, hash: Digest) -> Node<Data> {
+    fn new_leaf(item: Data, hash: GenericDigest<A>) -> Node<Data, A> {
         Node::Leaf { item, hash }
     }
 
     fn new_internal_from_items(
         left_item: Data,
-        left_hash: Digest,
+        left_hash: GenericDigest<A>,
         right_item: Data,
-        right_hash: Digest,
-    ) -> Node<Data> {
+        right_hash: GenericDigest<A>,
+    ) -> Node<Data, A> {
         let left_node = Node::Leaf {
             item: left_item,
             hash: left_hash,
@@ -330,7 +529,10 @@ impl<Data: Syncable> Node<Data> {
     }
 
     // Shorthand for creating a new branch
-    fn new_internal(left: Node<Data>, right: Node<Data>) -> Node<Data> {
+    fn new_internal(
+        left: Node<Data, A>,
+        right: Node<Data, A>,
+    ) -> Node<Data, A> {
         Node::Internal {
             left: Box::new(left),
             right: Box::new(right),
@@ -340,7 +542,7 @@ impl<Data: Syncable> Node<Data> {
     }
 
     /// Mutates the node into the argument, and returns the old node
-    pub fn swap(&mut self, mut new: Node<Data>) -> Node<Data> {
+    pub fn swap(&mut self, mut new: Node<Data, A>) -> Node<Data, A> {
         mem::swap(self, &mut new);
         new
     }
@@ -352,7 +554,7 @@ impl<Data: Syncable> Node<Data> {
 
     /// Returns the node's label. This is a hash of the hashes for a branch,
     /// and the item's hash for Leaves. Empty leaves have no hash.
-    pub fn label(&self) -> Result<Digest, SyncError> {
+    pub fn label(&self) -> Result<GenericDigest<A>, SyncError> {
         match self {
             // Error: hash of an empty leaf (should this be a hash of unit instead?)
             Node::Empty => EmptyHash.fail(),
@@ -360,6 +562,9 @@ impl<Data: Syncable> Node<Data> {
             // Non-empty leaf: label == path == hash
             Node::Leaf { hash, .. } => Ok(*hash),
 
+            // Stored node: label is computed before storing
+            Node::Stored { label, .. } => Ok(*label),
+
             Node::Internal {
                 left,
                 right,
@@ -382,7 +587,7 @@ impl<Data: Syncable> Node<Data> {
                         let left_hash = left.label()?;
                         let right_hash = right.label()?;
                         let concat = ConcatDigest(left_hash, right_hash);
-                        hash(&concat).context(Hash)?
+                        hash_with(&concat).context(Hash)?
                     };
 
                     // Update cache, return
@@ -395,11 +600,13 @@ impl<Data: Syncable> Node<Data> {
 }
 
 #[derive(serde::Serialize)]
-struct ConcatDigest(Digest, Digest);
+#[serde(bound = "")]
+struct ConcatDigest<A: HashAlgorithm>(GenericDigest<A>, GenericDigest<A>);
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::hash::hash;
 
     const NUM_ITERS: u32 = 50000;
 
@@ -475,10 +682,13 @@ mod tests {
 
         for i in 0..NUM_ITERS {
             let elem_path = Path::new(&i).unwrap();
-            assert!(root.delete(&i, elem_path.clone(), 0), "Deletion fails");
+            assert!(
+                root.delete(&i, elem_path.clone(), 0).unwrap(),
+                "Deletion fails"
+            );
 
             let mut nav = &root;
-            for idx in 0..Path::NUM_BITS {
+            for idx in 0..<Path>::NUM_BITS {
                 match nav {
                     Node::Empty => break,
                     Node::Leaf { item, .. } => {
@@ -499,6 +709,7 @@ mod tests {
                             nav = right
                         }
                     }
+                    Node::Stored { .. } => panic!("Stored node without store"),
                 }
             }
         }
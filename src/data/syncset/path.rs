@@ -1,20 +1,22 @@
 use serde::{Deserialize, Serialize};
 
 use super::errors::{PathLength, SyncError};
-use crate::crypto::hash::{hash, Digest, HashError, SIZE as HASH_SIZE};
+use crate::crypto::hash::{
+    hash_with, Blake3, GenericDigest, HashAlgorithm, HashError,
+};
 
 const BITS_IN_BYTE: usize = 8;
 
 /// Navigator wrapper for Digest
-/// Guaranteed to have HASH_SIZE * 8 bits of depth
+/// Guaranteed to have A::SIZE * 8 bits of depth
 #[derive(Clone, Debug, PartialEq)]
-pub struct Path(pub(super) Digest);
+pub struct Path<A: HashAlgorithm = Blake3>(pub(super) GenericDigest<A>);
 
 /// Navigator
-/// Guaranteed to have 0 <= n <= HASH_SIZE * 8 bits of depth
+/// Guaranteed to have 0 <= n <= A::SIZE * 8 bits of depth
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Prefix {
-    inner: [u8; HASH_SIZE],
+pub struct Prefix<A: HashAlgorithm = Blake3> {
+    inner: A::Output,
     depth: usize,
 }
 
@@ -43,17 +45,24 @@ impl Direction {
 }
 
 impl Path {
+    /// Standard constructor
+    pub fn new<Data: Serialize>(data: &Data) -> Result<Path, HashError> {
+        Self::hashed(data)
+    }
+}
+
+impl<A: HashAlgorithm> Path<A> {
     /// The number of bits in a hash digest
-    pub const NUM_BITS: usize = HASH_SIZE * BITS_IN_BYTE;
+    pub const NUM_BITS: usize = A::SIZE * BITS_IN_BYTE;
 
     /// Returns the direction at a given bit index
     /// Note that this function will panic if given an index
     /// greater or equal to the number of bits in a hash digest
     pub fn at(&self, idx: usize) -> Result<Direction, SyncError> {
-        if idx < Path::NUM_BITS {
+        if idx < Self::NUM_BITS {
             let (byte_idx, bit_idx) = split_bits(idx);
 
-            let byte = (self.0).as_bytes()[byte_idx as usize];
+            let byte = (self.0).as_bytes().as_ref()[byte_idx];
 
             Ok(Direction::from_bit(byte, bit_idx))
         } else {
@@ -65,31 +74,33 @@ impl Path {
     }
 
     /// Takes the i-th first bits of the digest and turn them into a Prefix
-    pub fn prefix(&self, depth: usize) -> Prefix {
+    pub fn prefix(&self, depth: usize) -> Prefix<A> {
         Prefix::from_digest(&self.0, depth)
     }
 
-    /// Standard constructor
-    pub fn new<Data: Serialize>(data: &Data) -> Result<Path, HashError> {
-        let digest = hash(data)?;
+    /// Hashes a data element using the algorithm `A`
+    pub fn hashed<Data: Serialize>(data: &Data) -> Result<Self, HashError> {
+        let digest = hash_with(data)?;
         Ok(Path(digest))
     }
 }
 
-impl PartialEq for Prefix {
-    fn eq(&self, other: &Prefix) -> bool {
+impl<A: HashAlgorithm> PartialEq for Prefix<A> {
+    fn eq(&self, other: &Prefix<A>) -> bool {
         if self.depth == other.depth {
             let (num_full_bytes, overflow_bits) = split_bits(self.depth);
 
             // Check all full bytes for equality
-            if self.inner[0..num_full_bytes] != other.inner[0..num_full_bytes] {
+            if self.inner.as_ref()[0..num_full_bytes]
+                != other.inner.as_ref()[0..num_full_bytes]
+            {
                 return false;
             }
 
             // Check all the additional bits for equality
             if overflow_bits > 0 {
-                let last_byte_self = self.inner[num_full_bytes];
-                let last_byte_other = self.inner[num_full_bytes];
+                let last_byte_self = self.inner.as_ref()[num_full_bytes];
+                let last_byte_other = self.inner.as_ref()[num_full_bytes];
                 let shift_amount = BITS_IN_BYTE - overflow_bits;
 
                 let masked_self = last_byte_self >> shift_amount;
@@ -107,8 +118,18 @@ impl PartialEq for Prefix {
 }
 
 impl Prefix {
-    fn add_one(&self, dir: Direction) -> Result<Prefix, SyncError> {
-        if self.depth >= Path::NUM_BITS {
+    /// Hashes a data element, and creates a path out of the digest
+    pub fn new<Data: Serialize>(
+        data: &Data,
+        depth: usize,
+    ) -> Result<Prefix, HashError> {
+        Self::hashed(data, depth)
+    }
+}
+
+impl<A: HashAlgorithm> Prefix<A> {
+    fn add_one(&self, dir: Direction) -> Result<Prefix<A>, SyncError> {
+        if self.depth >= Path::<A>::NUM_BITS {
             return PathLength {
                 what: "Cannot add depth to max-depth Prefix",
             }
@@ -121,7 +142,7 @@ impl Prefix {
 
         // Prepare to modify last bit of new
         let (byte_idx, bit_idx) = split_bits(new_depth - 1);
-        let current_byte = new_inner.get_mut(byte_idx as usize).unwrap();
+        let current_byte = new_inner.as_mut().get_mut(byte_idx).unwrap();
 
         let new_bit = dir.to_bit();
 
@@ -141,19 +162,24 @@ impl Prefix {
     }
 
     /// Extends the path with a 0
-    pub fn left(&self) -> Result<Prefix, SyncError> {
+    pub fn left(&self) -> Result<Prefix<A>, SyncError> {
         self.add_one(Direction::Left)
     }
 
     /// Extends the path with a 1
-    pub fn right(&self) -> Result<Prefix, SyncError> {
+    pub fn right(&self) -> Result<Prefix<A>, SyncError> {
         self.add_one(Direction::Right)
     }
 
+    /// Returns the number of bits of this Prefix
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
     pub fn at(&self, idx: usize) -> Option<Direction> {
         if idx < self.depth {
             let (byte_idx, bit_idx) = split_bits(idx);
-            let byte = self.inner[byte_idx as usize];
+            let byte = self.inner.as_ref()[byte_idx];
             let dir = Direction::from_bit(byte, bit_idx);
             Some(dir)
         } else {
@@ -162,43 +188,44 @@ impl Prefix {
     }
 
     /// Creates a Prefix of depth 0
-    pub fn empty() -> Prefix {
+    pub fn empty() -> Prefix<A> {
         Prefix {
-            inner: [0; HASH_SIZE],
+            inner: A::Output::default(),
             depth: 0,
         }
     }
 
-    fn from_digest(digest: &Digest, depth: usize) -> Prefix {
+    fn from_digest(digest: &GenericDigest<A>, depth: usize) -> Prefix<A> {
         Prefix {
             inner: *digest.as_bytes(),
             depth,
         }
     }
 
-    /// Hashes a data element, and creates a path out of the digest
-    pub fn new<Data: Serialize>(
+    /// Hashes a data element using the algorithm `A`, and creates a path
+    /// out of the digest
+    pub fn hashed<Data: Serialize>(
         data: &Data,
         depth: usize,
-    ) -> Result<Prefix, HashError> {
-        let digest = hash(data)?;
+    ) -> Result<Prefix<A>, HashError> {
+        let digest = hash_with(data)?;
         Ok(Prefix::from_digest(&digest, depth))
     }
 
     /// Checks if the Prefix is the prefix of the full path
-    pub fn is_prefix_of(&self, rhs: &Path) -> bool {
+    pub fn is_prefix_of(&self, rhs: &Path<A>) -> bool {
         let (num_full_bytes, overflow_bits) = split_bits(self.depth);
 
-        if self.inner[0..num_full_bytes]
-            != (rhs.0).as_bytes()[0..num_full_bytes]
+        if self.inner.as_ref()[0..num_full_bytes]
+            != (rhs.0).as_bytes().as_ref()[0..num_full_bytes]
         {
             return false;
         }
 
         // If there are some bits left to individually compare in the last byte
         if overflow_bits > 0 {
-            let last_byte_left = self.inner[num_full_bytes];
-            let last_byte_right = (rhs.0).as_bytes()[num_full_bytes];
+            let last_byte_left = self.inner.as_ref()[num_full_bytes];
+            let last_byte_right = (rhs.0).as_bytes().as_ref()[num_full_bytes];
             let shift_amount = BITS_IN_BYTE - overflow_bits;
 
             // Right shift to truncate irrelevant bits
@@ -233,6 +260,7 @@ mod tests {
     use hex::FromHex;
 
     use super::*;
+    use crate::crypto::hash::{Digest, SIZE as HASH_SIZE};
 
     // Direction tests
 
@@ -302,7 +330,7 @@ mod tests {
     #[test]
     fn depth_overflow() {
         let full = Path::new(&15092).unwrap();
-        if full.at(Path::NUM_BITS).is_ok() {
+        if full.at(<Path>::NUM_BITS).is_ok() {
             panic!("Path returns Ok at max depth")
         }
     }
@@ -313,7 +341,7 @@ mod tests {
     fn extension_prefixes() {
         let mut prefix = Prefix::empty();
         let full = Path::new(&15092).unwrap();
-        for depth in 0..Path::NUM_BITS {
+        for depth in 0..<Path>::NUM_BITS {
             assert!(
                 prefix.is_prefix_of(&full),
                 "Prefix isn't prefix of full path"
@@ -383,7 +411,7 @@ mod tests {
 
     #[test]
     fn add_one_errors() {
-        let pref = Prefix::new(&15092, Path::NUM_BITS).unwrap();
+        let pref = Prefix::new(&15092, <Path>::NUM_BITS).unwrap();
 
         if pref.add_one(Direction::Left).is_ok() {
             panic!("Expected an error in adding one to direction")
@@ -396,7 +424,7 @@ mod tests {
         inner[0] = 0xAA;
         inner[1] = 0x55;
         let inner_len = 2;
-        let path = Prefix { inner, depth: 16 };
+        let path: Prefix = Prefix { inner, depth: 16 };
         for i in 0..inner_len {
             for j in 0..BITS_IN_BYTE {
                 let expected_bit = (i + j) % 2 == 0;
@@ -408,7 +436,7 @@ mod tests {
 
     #[test]
     fn indices() {
-        let prefix = Prefix {
+        let prefix: Prefix = Prefix {
             inner: [0b1000_0000; HASH_SIZE],
             depth: 2,
         };
@@ -1,41 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use super::errors::SyncError;
 use super::node::Node;
 use super::path::Prefix;
 use super::Syncable;
-use crate::crypto::hash::Digest;
+use crate::crypto::hash::{Blake3, GenericDigest, HashAlgorithm};
 
 /// Data structure used to synchronize two SyncSets
-#[derive(Debug, PartialEq, Clone)]
-pub enum Set<Data> {
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Data: Serialize",
+    deserialize = "Data: serde::de::DeserializeOwned"
+))]
+pub enum Set<Data, A: HashAlgorithm = Blake3> {
     /// Lightweight alternative, only contains the hash of
     /// the sub-tree at prefix
-    LabelSet { prefix: Prefix, label: Digest },
+    LabelSet {
+        prefix: Prefix<A>,
+        label: GenericDigest<A>,
+    },
 
     /// Heavy alternative, contains all the data of a sub-tree at
     /// a given prefix
     ListSet {
         underlying: Vec<Data>,
-        prefix: Prefix,
+        prefix: Prefix<A>,
         dump: bool,
     },
 }
 
-impl<Data: Syncable> Set<Data> {
+impl<Data: Syncable, A: HashAlgorithm> Set<Data, A> {
     // Constructors, for ease of use
     pub(super) fn new_dataset(
-        prefix: Prefix,
-        node: &Node<Data>,
+        prefix: Prefix<A>,
+        node: &Node<Data, A>,
         dump: bool,
-    ) -> Set<&Data> {
-        let underlying = node.dump();
+    ) -> Result<Set<&Data, A>, SyncError> {
+        let underlying = node.dump()?;
 
-        Set::ListSet {
+        Ok(Set::ListSet {
             underlying,
             prefix,
             dump,
-        }
+        })
     }
 
-    pub(super) fn new_empty_dataset(prefix: Prefix, dump: bool) -> Set<Data> {
+    pub(super) fn new_empty_dataset(
+        prefix: Prefix<A>,
+        dump: bool,
+    ) -> Set<Data, A> {
         let underlying = Vec::new();
         Set::ListSet {
             underlying,
@@ -45,9 +58,9 @@ impl<Data: Syncable> Set<Data> {
     }
 }
 
-impl<Data: Syncable + Clone> Set<&Data> {
+impl<Data: Syncable + Clone, A: HashAlgorithm> Set<&Data, A> {
     /// Clones the inner elements to obtain a Set that owns its data
-    pub fn obtain_ownership(&self) -> Set<Data> {
+    pub fn obtain_ownership(&self) -> Set<Data, A> {
         use Set::*;
         match self {
             LabelSet { prefix, label } => LabelSet {
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind, Result},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+
+use super::{node::Node, Syncable};
+use crate::codec::bincode_options;
+use crate::crypto::hash::HashAlgorithm;
+
+/// Compact identifier of a node spilled to a `NodeStore`
+pub type NodeId = u64;
+
+/// Storage backend for the subtrees of a `SyncSet` that are kept out of memory.
+/// Nodes are handed to the store already serialized.
+pub trait NodeStore: Send {
+    /// Retrieve the serialized node with the given id
+    fn get(&self, id: NodeId) -> Result<Vec<u8>>;
+
+    /// Store or replace the serialized node with the given id
+    fn put(&mut self, id: NodeId, bytes: Vec<u8>) -> Result<()>;
+
+    /// Remove the node with the given id from this store
+    fn remove(&mut self, id: NodeId) -> Result<()>;
+}
+
+/// A `NodeStore` that keeps serialized nodes in memory
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    nodes: HashMap<NodeId, Vec<u8>>,
+}
+
+impl InMemoryStore {
+    /// Create a new empty `InMemoryStore`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of nodes currently held in this store
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if this store holds any node
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl NodeStore for InMemoryStore {
+    fn get(&self, id: NodeId) -> Result<Vec<u8>> {
+        self.nodes.get(&id).cloned().ok_or_else(|| missing(id))
+    }
+
+    fn put(&mut self, id: NodeId, bytes: Vec<u8>) -> Result<()> {
+        self.nodes.insert(id, bytes);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: NodeId) -> Result<()> {
+        self.nodes.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "file-store")]
+pub use file::FileStore;
+
+#[cfg(feature = "file-store")]
+mod file {
+    use std::{
+        collections::HashMap,
+        fs::{File, OpenOptions},
+        io::{Read, Result, Seek, SeekFrom, Write},
+        path::Path,
+    };
+
+    use super::{missing, NodeId, NodeStore};
+
+    /// A `NodeStore` backed by a single append-only file. The offset of each
+    /// node in the file is kept in memory, space used by replaced or removed
+    /// nodes is not reclaimed.
+    #[derive(Debug)]
+    pub struct FileStore {
+        file: File,
+        index: HashMap<NodeId, (u64, usize)>,
+    }
+
+    impl FileStore {
+        /// Create a new `FileStore` at the given path, truncating any
+        /// existing file
+        pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+
+            Ok(Self {
+                file,
+                index: HashMap::new(),
+            })
+        }
+    }
+
+    impl NodeStore for FileStore {
+        fn get(&self, id: NodeId) -> Result<Vec<u8>> {
+            let (offset, len) =
+                *self.index.get(&id).ok_or_else(|| missing(id))?;
+            let mut file = &self.file;
+            let mut buffer = vec![0; len];
+
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+
+            Ok(buffer)
+        }
+
+        fn put(&mut self, id: NodeId, bytes: Vec<u8>) -> Result<()> {
+            let file = &mut self.file;
+            let offset = file.seek(SeekFrom::End(0))?;
+
+            file.write_all(&bytes)?;
+            self.index.insert(id, (offset, bytes.len()));
+
+            Ok(())
+        }
+
+        fn remove(&mut self, id: NodeId) -> Result<()> {
+            self.index.remove(&id);
+            Ok(())
+        }
+    }
+}
+
+fn missing(id: NodeId) -> Error {
+    Error::new(ErrorKind::NotFound, format!("no node with id {}", id))
+}
+
+/// Shared state used by stored nodes to load themselves on demand
+pub(super) struct Backend<Data: Syncable, A: HashAlgorithm> {
+    store: Mutex<Box<dyn NodeStore>>,
+    decode: fn(&[u8]) -> bincode::Result<Node<Data, A>>,
+    depth: usize,
+    next_id: AtomicU64,
+}
+
+impl<Data: Syncable, A: HashAlgorithm> Backend<Data, A> {
+    pub fn new<S>(store: S, depth: usize) -> Self
+    where
+        S: NodeStore + 'static,
+        Data: DeserializeOwned,
+    {
+        Self {
+            store: Mutex::new(Box::new(store)),
+            decode: |bytes| bincode_options().deserialize(bytes),
+            depth,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Depth at which subtrees are spilled to the store
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn next_id(&self) -> NodeId {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn load(&self, id: NodeId) -> Result<Node<Data, A>> {
+        let bytes = self.store.lock().expect("store poisoned").get(id)?;
+
+        (self.decode)(&bytes).map_err(invalid)
+    }
+
+    pub fn save(&self, id: NodeId, node: &Node<Data, A>) -> Result<()> {
+        let bytes = bincode_options().serialize(node).map_err(invalid)?;
+
+        self.store.lock().expect("store poisoned").put(id, bytes)
+    }
+
+    pub fn remove(&self, id: NodeId) -> Result<()> {
+        self.store.lock().expect("store poisoned").remove(id)
+    }
+}
+
+impl<Data: Syncable, A: HashAlgorithm> fmt::Debug for Backend<Data, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backend")
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+fn invalid(error: bincode::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,325 @@
+use std::{borrow::Borrow, marker::PhantomData};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use snafu::{ensure, ResultExt};
+
+use super::errors::*;
+use super::path::Path;
+use super::set::Set;
+use super::{SyncSet, Syncable};
+use crate::codec::bincode_options;
+use crate::crypto::hash::{Blake3, HashAlgorithm};
+
+/// Default number of messages a `Synchronizer` handles before giving up
+pub const DEFAULT_MAX_ROUNDS: usize = 1024;
+
+/// What to do after handling a message with a `Synchronizer`
+#[derive(Debug)]
+pub enum SyncStep<Data> {
+    /// Send these bytes to the remote peer and wait for its answer
+    Reply(Vec<u8>),
+    /// The synchronization is over
+    Done {
+        /// Elements the remote peer has that the local set does not
+        to_add: Vec<Data>,
+        /// Elements the local set has that the remote peer does not
+        to_remove: Vec<Data>,
+        /// Last message to send to the remote peer, which does not answer it
+        reply: Option<Vec<u8>>,
+    },
+    /// The remote peer sent invalid bytes, or the synchronization failed.
+    /// The `Synchronizer` can not be used anymore.
+    Error(SyncError),
+}
+
+/// A state machine running the synchronization of a `SyncSet` with a remote
+/// one, one message at a time, without doing any I/O. <br />
+/// The initiator sends the result of `initial_message` to the responder and
+/// both then feed the bytes they receive to `handle_message` until it returns
+/// `SyncStep::Done`. The set is either borrowed or owned, see `S`.
+pub struct Synchronizer<
+    Data: Syncable,
+    A: HashAlgorithm = Blake3,
+    S = SyncSet<Data, A>,
+> {
+    set: S,
+    rounds: usize,
+    max_rounds: usize,
+    to_add: Vec<Data>,
+    to_remove: Vec<Data>,
+    done: bool,
+    _algorithm: PhantomData<A>,
+}
+
+impl<Data, A, S> Synchronizer<Data, A, S>
+where
+    Data: Syncable + Clone + DeserializeOwned,
+    A: HashAlgorithm,
+    S: Borrow<SyncSet<Data, A>>,
+{
+    /// Create a `Synchronizer` for the given `SyncSet`, which can either be
+    /// a reference or an owned snapshot of the set
+    pub fn new(set: S) -> Self {
+        Self {
+            set,
+            rounds: 0,
+            max_rounds: DEFAULT_MAX_ROUNDS,
+            to_add: Vec::new(),
+            to_remove: Vec::new(),
+            done: false,
+            _algorithm: PhantomData,
+        }
+    }
+
+    /// Fail with `SyncError::TooManyRounds` once more than `rounds` messages
+    /// were handled
+    pub fn max_rounds(mut self, rounds: usize) -> Self {
+        self.max_rounds = rounds;
+        self
+    }
+
+    /// Returns the message the initiator sends to start the synchronization
+    pub fn initial_message(&self) -> Result<Vec<u8>, SyncError> {
+        let round = self.set.borrow().start_sync()?;
+
+        bincode_options()
+            .serialize(&round.view)
+            .context(Serialization)
+    }
+
+    /// Handle a message from the remote peer, returning what to do next
+    pub fn handle_message(&mut self, bytes: &[u8]) -> SyncStep<Data> {
+        self.step(bytes).unwrap_or_else(|e| {
+            self.done = true;
+            SyncStep::Error(e)
+        })
+    }
+
+    /// Returns the number of messages handled so far
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// Checks if the synchronization is over
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the set being synchronized
+    pub fn set(&self) -> &SyncSet<Data, A> {
+        self.set.borrow()
+    }
+
+    fn step(&mut self, bytes: &[u8]) -> Result<SyncStep<Data>, SyncError> {
+        ensure!(!self.done, Finished);
+        ensure!(
+            self.rounds < self.max_rounds,
+            TooManyRounds {
+                max: self.max_rounds
+            }
+        );
+
+        self.rounds += 1;
+
+        let view: Vec<Set<Data, A>> = bincode_options()
+            .deserialize(bytes)
+            .context(Serialization)?;
+
+        // prefixes deeper than a path would make the set panic
+        ensure!(
+            view.iter().all(|set| depth(set) <= Path::<A>::NUM_BITS),
+            PathLength {
+                what: "Remote prefix deeper than a path"
+            }
+        );
+
+        if view.is_empty() {
+            return Ok(self.finish(None));
+        }
+
+        let round = self.set.borrow().sync(&view)?;
+
+        self.to_add.extend(round.add.into_iter().cloned());
+        self.to_remove.extend(round.remove.into_iter().cloned());
+
+        let reply = bincode_options()
+            .serialize(&round.view)
+            .context(Serialization)?;
+
+        if round.view.is_empty() {
+            Ok(self.finish(Some(reply)))
+        } else {
+            Ok(SyncStep::Reply(reply))
+        }
+    }
+
+    fn finish(&mut self, reply: Option<Vec<u8>>) -> SyncStep<Data> {
+        self.done = true;
+
+        SyncStep::Done {
+            to_add: std::mem::take(&mut self.to_add),
+            to_remove: std::mem::take(&mut self.to_remove),
+            reply,
+        }
+    }
+}
+
+fn depth<Data, A: HashAlgorithm>(set: &Set<Data, A>) -> usize {
+    match set {
+        Set::LabelSet { prefix, .. } | Set::ListSet { prefix, .. } => {
+            prefix.depth()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::crypto::hash::hash;
+
+    const COMMON: u32 = 5000;
+    const EXTRA: u32 = 5;
+
+    type Outcome = (HashSet<u32>, HashSet<u32>);
+
+    /// Exchange bytes between two `Synchronizer`s until both are done,
+    /// returning what each of them computed
+    fn loopback<S, T>(
+        mut initiator: Synchronizer<u32, Blake3, S>,
+        mut responder: Synchronizer<u32, Blake3, T>,
+    ) -> (Outcome, Outcome)
+    where
+        S: Borrow<SyncSet<u32>>,
+        T: Borrow<SyncSet<u32>>,
+    {
+        let mut message = initiator.initial_message().unwrap();
+        let mut outcomes = [None, None];
+        let mut turn = 1;
+
+        while outcomes.iter().any(Option::is_none) {
+            let step = if turn == 0 {
+                initiator.handle_message(&message)
+            } else {
+                responder.handle_message(&message)
+            };
+
+            match step {
+                SyncStep::Reply(reply) => message = reply,
+                SyncStep::Done {
+                    to_add,
+                    to_remove,
+                    reply,
+                } => {
+                    outcomes[turn] = Some((
+                        to_add.into_iter().collect(),
+                        to_remove.into_iter().collect(),
+                    ));
+
+                    match reply {
+                        Some(reply) => message = reply,
+                        None => break,
+                    }
+                }
+                SyncStep::Error(e) => panic!("synchronization failed: {}", e),
+            }
+
+            turn = 1 - turn;
+        }
+
+        let [initiator, responder] = outcomes.map(|o| o.expect("not done"));
+
+        (initiator, responder)
+    }
+
+    #[test]
+    fn loopback_sync() {
+        let mut alice = SyncSet::new();
+        let mut bob = SyncSet::new();
+        let mut only_alice = HashSet::new();
+        let mut only_bob = HashSet::new();
+
+        for i in 0..COMMON {
+            alice.insert(i).unwrap();
+            bob.insert(i).unwrap();
+        }
+
+        for i in COMMON..COMMON + 2 * EXTRA {
+            if i % 2 == 0 {
+                alice.insert(i).unwrap();
+                only_alice.insert(i);
+            } else {
+                bob.insert(i).unwrap();
+                only_bob.insert(i);
+            }
+        }
+
+        let (from_alice, from_bob) =
+            loopback(Synchronizer::new(&alice), Synchronizer::new(bob));
+
+        assert_eq!(from_alice, (only_bob.clone(), only_alice.clone()));
+        assert_eq!(from_bob, (only_alice, only_bob));
+    }
+
+    #[test]
+    fn identical_sets() {
+        let mut set = SyncSet::new();
+
+        for i in 0..COMMON {
+            set.insert(i).unwrap();
+        }
+
+        let outcome =
+            loopback(Synchronizer::new(&set), Synchronizer::new(&set));
+
+        assert!(outcome.0 .0.is_empty() && outcome.0 .1.is_empty());
+        assert!(outcome.1 .0.is_empty() && outcome.1 .1.is_empty());
+    }
+
+    #[test]
+    fn malicious_bytes() {
+        let set = SyncSet::<u32>::new();
+        let mut synchronizer = Synchronizer::new(&set);
+
+        assert!(matches!(
+            synchronizer.handle_message(&[0xff; 13]),
+            SyncStep::Error(SyncError::Serialization { .. })
+        ));
+        assert!(matches!(
+            synchronizer.handle_message(&[]),
+            SyncStep::Error(SyncError::Finished)
+        ));
+
+        let deep = Set::<u32>::LabelSet {
+            prefix: Path::new(&0u32).unwrap().prefix(10_000),
+            label: hash(&0u32).unwrap(),
+        };
+        let bytes = bincode_options().serialize(&vec![deep]).unwrap();
+
+        assert!(matches!(
+            Synchronizer::new(&set).handle_message(&bytes),
+            SyncStep::Error(SyncError::PathLength { .. })
+        ));
+    }
+
+    #[test]
+    fn rounds_are_capped() {
+        let mut set = SyncSet::new();
+
+        for i in 0..COMMON {
+            set.insert(i).unwrap();
+        }
+
+        let message = Synchronizer::new(&set).initial_message().unwrap();
+        let mut synchronizer =
+            Synchronizer::new(SyncSet::<u32>::new()).max_rounds(0);
+
+        assert!(matches!(
+            synchronizer.handle_message(&message),
+            SyncStep::Error(SyncError::TooManyRounds { max: 0 })
+        ));
+        assert_eq!(synchronizer.rounds(), 0);
+    }
+}
@@ -12,14 +12,31 @@
 //! Drop also provides a convenient way to implement distributed algorithms and managing connections to a lot
 //! of remote peers in the [`system`] module.
 //!
+//! Events and spans are emitted using [`tracing`] with one target per subsystem, documented along with a way to
+//! change their verbosity at runtime in the [`telemetry`] module.
+//!
 //! Lastly drop provides a lot of testing utilites that makes it easier to test your application in the [`test`]
 //! module.
 //!
+//! Every fallible operation returns a dedicated error `enum` built using [`snafu`], such as [`ConnectError`] or
+//! [`SystemError`]. These all implement [`std::error::Error`] and expose their underlying cause through `source`,
+//! so they compose with `?` and with other error handling libraries.
 //!
 //! [`crypto`]: self::crypto
 //! [`net`]: self::net
 //! [`system`]: self::system
 //! [`test`]: self::test
+//! [`tracing`]: https://docs.rs/tracing
+//! [`telemetry`]: self::telemetry
+//! [`snafu`]: https://docs.rs/snafu
+//! [`ConnectError`]: self::net::ConnectError
+//! [`SystemError`]: self::system::SystemError
+
+// allow the `message` attribute to refer to this crate as `drop`
+extern crate self as drop;
+
+/// Serialization format used for everything drop sends on the wire
+pub mod codec;
 
 /// Cryptographic primitives
 pub mod crypto;
@@ -30,6 +47,9 @@ pub mod data;
 mod message;
 pub use message::*;
 
+/// Versioned formats of the artifacts persisted to disk
+pub mod persist;
+
 /// Asynchronous secure network utilities
 #[cfg(feature = "net")]
 #[cfg_attr(docsrs, doc(cfg(feature = "net")))]
@@ -40,6 +60,11 @@ pub mod net;
 #[cfg_attr(docsrs, doc(cfg(feature = "system")))]
 pub mod system;
 
+/// Tracing targets and runtime control over their verbosity
+#[cfg(feature = "net")]
+#[cfg_attr(docsrs, doc(cfg(feature = "net")))]
+pub mod telemetry;
+
 #[cfg(any(test, feature = "test"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "test")))]
 /// Test utilities that are used all across the framework
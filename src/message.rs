@@ -4,6 +4,8 @@ use std::fmt;
 pub use drop_derive::message;
 use serde::{Deserialize, Serialize};
 
+use crate::crypto::{hash, Digest};
+
 /// A trait bound for types that can be used as messages
 pub trait Message:
     for<'de> Deserialize<'de> + Serialize + fmt::Debug + Send + Sync + Clone
@@ -14,3 +16,106 @@ impl<T> Message for T where
     T: for<'de> Deserialize<'de> + Serialize + fmt::Debug + Send + Sync + Clone
 {
 }
+
+/// A `Message` that can be identified using a `Digest`, allowing to recognize
+/// messages without requiring them to implement `Hash` or `Eq`.
+/// This is implemented by the `message` attribute unless `no_hash` is given.
+pub trait IdentifiableMessage: Message {
+    /// Get the identifier of this message. By default this is the hash of the
+    /// whole message, implementations may use a cheaper identifier.
+    ///
+    /// # Panics
+    /// The default implementation panics if the message can't be serialized
+    fn id(&self) -> Digest {
+        hash(self).expect("failed to serialize message")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[message]
+    struct Measure {
+        value: f64,
+        labels: HashMap<String, f64>,
+    }
+
+    #[message(id = "id")]
+    struct Blob {
+        id: Digest,
+        payload: Vec<u8>,
+    }
+
+    #[message(id = "0")]
+    struct Indexed(u64, Vec<u8>);
+
+    #[message]
+    enum Generic<M> {
+        Single(M),
+        Many(Vec<M>),
+    }
+
+    #[message(no_hash)]
+    struct Custom(f64);
+
+    impl IdentifiableMessage for Custom {
+        fn id(&self) -> Digest {
+            hash(&self.0.to_bits()).unwrap()
+        }
+    }
+
+    fn identify<M: IdentifiableMessage>(message: &M) -> Digest {
+        message.id()
+    }
+
+    #[test]
+    fn float_message() {
+        let measure = Measure {
+            value: 0.5,
+            labels: HashMap::new(),
+        };
+        let other = Measure {
+            value: 1.5,
+            ..measure.clone()
+        };
+
+        assert_eq!(identify(&measure), hash(&measure).unwrap());
+        assert_ne!(identify(&measure), identify(&other));
+    }
+
+    #[test]
+    fn id_field() {
+        let id = hash(&"blob").unwrap();
+        let blob = Blob {
+            id,
+            payload: vec![0; 1024],
+        };
+        let other = Blob {
+            payload: Vec::new(),
+            ..blob.clone()
+        };
+
+        assert_eq!(identify(&blob), hash(&id).unwrap());
+        assert_eq!(identify(&blob), identify(&other), "payload was hashed");
+        assert_eq!(
+            identify(&Indexed(3, vec![1])),
+            identify(&Indexed(3, vec![2]))
+        );
+    }
+
+    #[test]
+    fn generic_message() {
+        assert_ne!(
+            identify(&Generic::Single(1u8)),
+            identify(&Generic::Many(vec![1u8]))
+        );
+    }
+
+    #[test]
+    fn manual_identity() {
+        assert_eq!(identify(&Custom(2.0)), hash(&2f64.to_bits()).unwrap());
+    }
+}
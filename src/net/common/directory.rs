@@ -1,12 +1,147 @@
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::net::SocketAddr;
-use serde::{Serialize, Deserialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::crypto::key::exchange::PublicKey;
+use crate::crypto::key::exchange::{KeyPair, PublicKey};
+use crate::crypto::sign::{SignError, Signature, VerifyError};
 use crate::message;
+use crate::net::{Connection, ContactCard};
+use crate::telemetry::targets;
+
+use tracing::debug;
+
+/// Version of the directory protocol implemented by this crate
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Version used with peers that predate `Hello` and start directly with a
+/// `Request`
+pub const LEGACY_VERSION: u16 = 0;
+
+/// Optional parts of the directory protocol, negotiated using `Hello`. Only
+/// `Request::Add`, `Request::Fetch` and `Request::Wait` are always available.
+pub mod features {
+    /// `Request::AddSigned` and `Response::FoundSigned`
+    pub const SIGNED: u32 = 1;
+    /// `Request::Remove`
+    pub const REMOVE: u32 = 1 << 1;
+    /// `Request::ProbeMe` and `Response::Probed`
+    pub const PROBE: u32 = 1 << 2;
+    /// `Response::Conflict`
+    pub const CONFLICT: u32 = 1 << 3;
+    /// `Request::WaitPaged` and `Response::Page`
+    pub const PAGED: u32 = 1 << 4;
+    /// `Request::Subscribe`, `Request::ConnectBack` and
+    /// `Response::ConnectBack`
+    pub const REVERSAL: u32 = 1 << 5;
+    /// `Response::Found` with every address of a peer, instead of
+    /// `Response::FoundOne`, as well as `Request::AddCard` and
+    /// `Response::FoundCard` along with `SIGNED`
+    pub const ADDRS: u32 = 1 << 6;
+    /// `Request::Renew`, and `Response::Expired` sent to clients waiting
+    /// using `Request::WaitPaged`
+    pub const RENEW: u32 = 1 << 7;
+    /// Features supported by peers that predate `Hello`
+    pub const LEGACY: u32 = SIGNED | REMOVE;
+    /// Features supported by this crate
+    pub const ALL: u32 =
+        SIGNED | REMOVE | PROBE | CONFLICT | PAGED | REVERSAL | ADDRS | RENEW;
+}
 
 #[message]
 #[derive(Copy, Eq, PartialEq)]
+/// First message sent by each side of a directory connection, the client
+/// sends its own first and the server answers with its own.
+pub struct Hello {
+    /// Protocol version spoken by the sender
+    pub version: u16,
+    /// Bitset of the `features` supported by the sender
+    pub features: u32,
+}
+
+impl Hello {
+    /// The `Hello` for the protocol implemented by this crate
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: features::ALL,
+        }
+    }
+
+    /// The protocol spoken by peers that do not send a `Hello`
+    pub fn legacy() -> Self {
+        Self {
+            version: LEGACY_VERSION,
+            features: features::LEGACY,
+        }
+    }
+
+    /// Compute the protocol both sides agree on after exchanging `Hello`s
+    pub fn negotiate(&self, other: &Self) -> Self {
+        Self {
+            version: self.version.min(other.version),
+            features: self.features & other.features,
+        }
+    }
+
+    /// Check whether all of the given `features` are supported
+    pub fn supports(&self, features: u32) -> bool {
+        self.features & features == features
+    }
+
+    /// Check whether `request` is allowed by this `Hello`
+    pub fn allows(&self, request: &Request) -> bool {
+        match request {
+            Request::AddSigned(_) => self.supports(features::SIGNED),
+            Request::Remove(_) => self.supports(features::REMOVE),
+            Request::ProbeMe(_) => self.supports(features::PROBE),
+            Request::WaitPaged { .. } => self.supports(features::PAGED),
+            Request::Subscribe(_) | Request::ConnectBack { .. } => {
+                self.supports(features::REVERSAL)
+            }
+            Request::Renew(_) => self.supports(features::RENEW),
+            Request::AddCard(_) => {
+                self.supports(features::SIGNED | features::ADDRS)
+            }
+            Request::Add(_) | Request::Fetch(_) | Request::Wait(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Hello {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{} with features {:#x}", self.version, self.features)
+    }
+}
+
+/// Send our `Hello` to a directory server and wait for its own. Returns the
+/// negotiated protocol, or `None` if the directory does not understand
+/// `Hello`, in which case it closes the `Connection`.
+pub(crate) async fn negotiate(connection: &mut Connection) -> Option<Hello> {
+    let ours = Hello::current();
+
+    if let Err(e) = connection.send_plain(&ours).await {
+        debug!(
+            target: targets::DIRECTORY,
+            "failed to send hello to directory: {}", e
+        );
+        return None;
+    }
+
+    match connection.receive_plain::<Hello>().await {
+        Ok(theirs) => Some(ours.negotiate(&theirs)),
+        Err(e) => {
+            debug!(
+                target: targets::DIRECTORY,
+                "directory did not answer hello: {}", e
+            );
+            None
+        }
+    }
+}
+
+#[message]
+#[derive(Eq, PartialEq)]
 pub enum Request {
     /// Add this peer to the directory
     Add(Info),
@@ -14,6 +149,46 @@ pub enum Request {
     Fetch(PublicKey),
     /// Wait for a number of peer to be registered on the directory
     Wait(usize),
+    /// Add this peer to the directory using a signed record
+    AddSigned(SignedInfo),
+    /// Remove a peer that was added using the same connection
+    Remove(PublicKey),
+    /// Try connecting to this address, which must be on the host the
+    /// request comes from, to tell whether it is reachable from outside
+    ProbeMe(SocketAddr),
+    /// Wait for a number of peers to be registered on the directory, then
+    /// list the directory in `Response::Page`s followed by `Response::Ok`
+    WaitPaged {
+        /// Number of peers to wait for
+        count: usize,
+        /// Maximum number of peers in each `Response::Page`
+        page_size: u16,
+        /// Only list peers whose key is greater than this one, to resume an
+        /// interrupted listing from the last key received
+        after: Option<PublicKey>,
+    },
+    /// Receive the `Response::ConnectBack`s addressed to the key of this
+    /// record, which must be signed for an address on the host the request
+    /// comes from. The directory sends nothing but `Response::ConnectBack`s
+    /// on the `Connection` once it answered this request.
+    Subscribe(SignedInfo),
+    /// Ask the peer subscribed with key `target` to dial `requester` at one
+    /// of `addrs`, which must be on the host the request comes from
+    ConnectBack {
+        /// Key of the peer that should dial
+        target: PublicKey,
+        /// Key of the peer asking to be dialed
+        requester: PublicKey,
+        /// Addresses the requester can be reached at
+        addrs: Vec<SocketAddr>,
+    },
+    /// Extend the time to live of a peer that was added using the same
+    /// connection, without registering it again
+    Renew(PublicKey),
+    /// Add this peer to the directory at every candidate of a `ContactCard`
+    /// signed by its key, which must have an expiry. The candidates replace
+    /// the addresses the peer registered before.
+    AddCard(ContactCard),
 }
 
 #[message]
@@ -22,10 +197,39 @@ pub enum Request {
 pub enum Response {
     /// Add request was a success
     Ok,
-    /// Requested peer was found in directory
-    Found(PublicKey, SocketAddr),
+    /// Requested peer was found in directory, only the address it registered
+    /// last is given to clients that did not negotiate `features::ADDRS`
+    FoundOne(PublicKey, SocketAddr),
     /// Requested peer is unknown in the directory
     NotFound(PublicKey),
+    /// Requested peer was found with a record signed by its own key
+    FoundSigned(SignedInfo),
+    /// The request was rejected by the directory
+    Error(String),
+    /// Outcome of a `Request::ProbeMe` for the given address
+    Probed {
+        /// Address that was probed
+        addr: SocketAddr,
+        /// Whether a connection to `addr` could be opened
+        reachable: bool,
+    },
+    /// The registered key is already registered by another client at the
+    /// given address
+    Conflict(SocketAddr),
+    /// A page of the listing requested by `Request::WaitPaged`, in
+    /// increasing order of `PublicKey`
+    Page(Vec<Info>),
+    /// A `Request::ConnectBack` relayed to its target: the peer with this
+    /// key asks to be dialed at one of these addresses
+    ConnectBack(PublicKey, Vec<SocketAddr>),
+    /// Requested peer was found in directory at all of these addresses, in
+    /// the order they should be tried
+    Found(PublicKey, Vec<SocketAddr>),
+    /// The peer with this key was not renewed in time and was removed from
+    /// the directory
+    Expired(PublicKey),
+    /// Requested peer was found with a `ContactCard` signed by its own key
+    FoundCard(ContactCard),
 }
 
 impl fmt::Display for Response {
@@ -35,9 +239,25 @@ impl fmt::Display for Response {
             "{}",
             match self {
                 Self::Ok => "success".to_string(),
-                Self::Found(pkey, addr) =>
+                Self::FoundOne(pkey, addr) =>
                     format!("found {} at {}", pkey, addr),
                 Self::NotFound(_) => "not found".to_string(),
+                Self::FoundSigned(info) => format!("found signed {}", info),
+                Self::Error(reason) => format!("error: {}", reason),
+                Self::Probed {
+                    addr,
+                    reachable: true,
+                } => format!("{} is reachable", addr),
+                Self::Probed { addr, .. } => format!("{} is unreachable", addr),
+                Self::Conflict(addr) =>
+                    format!("already registered at {}", addr),
+                Self::Page(peers) => format!("page of {} peers", peers.len()),
+                Self::ConnectBack(pkey, addrs) =>
+                    format!("{} asks to be dialed at {:?}", pkey, addrs),
+                Self::Found(pkey, addrs) =>
+                    format!("found {} at {:?}", pkey, addrs),
+                Self::Expired(pkey) => format!("{} expired", pkey),
+                Self::FoundCard(card) => format!("found card {}", card),
             }
         )
     }
@@ -76,14 +296,90 @@ impl From<(PublicKey, SocketAddr)> for Info {
     }
 }
 
+#[message]
+#[derive(Copy, Eq, PartialEq)]
+/// An `Info` signed by the key it contains along with an expiry date, so
+/// that anyone can check the record was produced by the owner of the key.
+pub struct SignedInfo {
+    info: Info,
+    expiry: u64,
+    signature: Signature,
+}
+
+impl SignedInfo {
+    /// Sign a new record for `addr` using the given `KeyPair`. The record
+    /// will be considered stale once `expiry` is reached.
+    pub fn new(
+        keypair: &KeyPair,
+        addr: SocketAddr,
+        expiry: SystemTime,
+    ) -> Result<Self, SignError> {
+        let info: Info = (*keypair.public(), addr).into();
+        let expiry = expiry
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let signature = keypair.sign(&(info.pkey, info.addr, expiry))?;
+
+        Ok(Self {
+            info,
+            expiry,
+            signature,
+        })
+    }
+
+    /// Get the `Info` contained in this record
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// Get the `PublicKey` that signed this record
+    pub fn public(&self) -> &PublicKey {
+        self.info.public()
+    }
+
+    /// Get the `SocketAddr` contained in this record
+    pub fn addr(&self) -> SocketAddr {
+        self.info.addr()
+    }
+
+    /// Get the time at which this record expires
+    pub fn expiry(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.expiry)
+    }
+
+    /// Check if this record has expired
+    pub fn is_expired(&self) -> bool {
+        self.expiry() <= SystemTime::now()
+    }
+
+    /// Verify that this record was signed by the key it contains. This does
+    /// not check for expiry, see `is_expired`.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        self.info.pkey.verify(
+            &(self.info.pkey, self.info.addr, self.expiry),
+            &self.signature,
+        )
+    }
+}
+
+impl fmt::Display for SignedInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} until {}", self.info, self.expiry)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::net::{Ipv4Addr, SocketAddr};
 
     use super::*;
 
+    use crate::codec::bincode_options;
     use crate::crypto::key::exchange::Exchanger;
 
+    use bincode::Options;
+
     #[test]
     fn response_fmt() {
         let pkey = *Exchanger::random().keypair().public();
@@ -91,8 +387,73 @@ mod test {
         assert_eq!(format!("{}", Response::Ok), "success");
         assert_eq!(format!("{}", Response::NotFound(pkey)), "not found");
         assert_eq!(
-            format!("{}", Response::Found(pkey, addr)),
+            format!("{}", Response::FoundOne(pkey, addr)),
             format!("found {} at {}", pkey, addr)
         );
+        assert_eq!(
+            format!("{}", Response::Found(pkey, vec![addr])),
+            format!("found {} at [{}]", pkey, addr)
+        );
+        assert_eq!(
+            format!("{}", Response::Expired(pkey)),
+            format!("{} expired", pkey)
+        );
+    }
+
+    #[test]
+    fn hello_negotiation() {
+        let old = Hello {
+            version: 1,
+            features: features::SIGNED,
+        };
+        let new = Hello {
+            version: 3,
+            features: features::ALL | 1 << 8,
+        };
+        let common = new.negotiate(&old);
+
+        assert_eq!(common, old.negotiate(&new), "negotiation not symmetric");
+        assert_eq!(common.version, 1);
+        assert!(common.supports(features::SIGNED));
+        assert!(!common.supports(features::REMOVE));
+        assert!(!common.allows(&Request::Remove(*KeyPair::random().public())));
+        assert!(common.allows(&Request::Wait(1)));
+    }
+
+    #[test]
+    fn hello_is_not_a_request() {
+        let hello = bincode_options()
+            .serialize(&Hello::current())
+            .expect("serialize failed");
+
+        bincode_options()
+            .deserialize::<Request>(&hello)
+            .expect_err("hello parsed as a legacy request");
+    }
+
+    #[test]
+    fn signed_info() {
+        let keypair = KeyPair::random();
+        let addr: SocketAddr = (Ipv4Addr::LOCALHOST, 1234).into();
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+        let signed =
+            SignedInfo::new(&keypair, addr, expiry).expect("sign failed");
+
+        signed.verify().expect("valid record rejected");
+        assert!(!signed.is_expired(), "fresh record expired");
+
+        let mut forged = signed;
+        forged.info =
+            (*forged.public(), (Ipv4Addr::LOCALHOST, 1).into()).into();
+        forged.verify().expect_err("forged address accepted");
+
+        let mut forged = signed;
+        forged.expiry += 1;
+        forged.verify().expect_err("forged expiry accepted");
+
+        let stale =
+            SignedInfo::new(&keypair, addr, UNIX_EPOCH).expect("sign failed");
+        stale.verify().expect("stale record has a valid signature");
+        assert!(stale.is_expired(), "stale record not expired");
     }
 }
@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+use super::connector::Other;
+use super::{ConnectError, Connection, Connector};
+use crate::crypto::key::exchange::PublicKey;
+use crate::telemetry::targets;
+
+use snafu::ensure;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Instant};
+
+use tracing::debug;
+
+/// Default time after which an unused pooled `Connection` is closed
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default maximum number of unused `Connection`s kept by a pool
+pub const DEFAULT_POOL_CAPACITY: usize = 64;
+
+/// Limits of a [`ConnectionPool`]
+///
+/// [`ConnectionPool`]: self::ConnectionPool
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolLimits {
+    idle_timeout: Duration,
+    capacity: usize,
+    per_peer: usize,
+}
+
+impl PoolLimits {
+    /// Close `Connection`s that were not used for `timeout`
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Keep at most `capacity` unused `Connection`s, closing the least
+    /// recently used ones first
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Open at most `count` `Connection`s to the same peer. Checking out a
+    /// `Connection` waits while `count` of them are in use, so that a single
+    /// `Connection` is used by one caller at a time when `count` is 1.
+    pub fn per_peer(mut self, count: usize) -> Self {
+        self.per_peer = count.max(1);
+        self
+    }
+
+    /// Get the time after which unused `Connection`s are closed
+    pub fn idle(&self) -> Duration {
+        self.idle_timeout
+    }
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            capacity: DEFAULT_POOL_CAPACITY,
+            per_peer: 1,
+        }
+    }
+}
+
+/// A `Connection` waiting in the pool along with when it was last used
+struct Idle {
+    connection: Connection,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    idle: HashMap<PublicKey, Vec<Idle>>,
+    /// Bounds the number of `Connection`s to each peer
+    slots: HashMap<PublicKey, Arc<Semaphore>>,
+    reaping: bool,
+}
+
+impl State {
+    fn idle_count(&self) -> usize {
+        self.idle.values().map(Vec::len).sum()
+    }
+
+    /// Close `Connection`s unused since before `deadline` and forget peers
+    /// that have no `Connection` left
+    fn evict_before(&mut self, deadline: Instant) {
+        self.idle.retain(|pkey, idle| {
+            idle.retain(|idle| idle.since > deadline);
+
+            if idle.is_empty() {
+                debug!(
+                    target: targets::CONNECTOR,
+                    "closed idle connections to {}", pkey
+                );
+            }
+
+            !idle.is_empty()
+        });
+
+        let idle = &self.idle;
+
+        // no one is waiting on or holding a slot if its only owner is the pool
+        self.slots.retain(|pkey, slots| {
+            idle.contains_key(pkey) || Arc::strong_count(slots) > 1
+        });
+    }
+
+    /// Close the least recently used `Connection`s until at most `capacity`
+    /// are left
+    fn evict_lru(&mut self, capacity: usize) {
+        while self.idle_count() > capacity {
+            let oldest = self
+                .idle
+                .iter()
+                .filter_map(|(pkey, idle)| {
+                    idle.first().map(|idle| (idle.since, *pkey))
+                })
+                .min();
+
+            if let Some((_, pkey)) = oldest {
+                if let Some(idle) = self.idle.get_mut(&pkey) {
+                    idle.remove(0);
+
+                    if idle.is_empty() {
+                        self.idle.remove(&pkey);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Inner<C> {
+    connector: C,
+    limits: PoolLimits,
+    state: Mutex<State>,
+}
+
+impl<C> Inner<C> {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("connection pool poisoned")
+    }
+}
+
+/// A pool of `Connection`s for clients that talk to many peers
+/// occasionally. `Connection`s are kept open between requests so that only
+/// the first request to a peer pays for the handshake, and closed once
+/// unused for a while. <br />
+/// Cloning a `ConnectionPool` gives another handle to the same pool.
+pub struct ConnectionPool<C: Connector> {
+    inner: Arc<Inner<C>>,
+}
+
+impl<C> ConnectionPool<C>
+where
+    C: Connector + 'static,
+{
+    /// Create a `ConnectionPool` opening `Connection`s using `connector`
+    pub fn new(connector: C) -> Self {
+        Self::with_limits(connector, PoolLimits::default())
+    }
+
+    /// Create a `ConnectionPool` enforcing the given `PoolLimits`
+    pub fn with_limits(connector: C, limits: PoolLimits) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                connector,
+                limits,
+                state: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Get the `Connector` used to open new `Connection`s
+    pub fn connector(&self) -> &C {
+        &self.inner.connector
+    }
+
+    /// Get the `PoolLimits` of this `ConnectionPool`
+    pub fn limits(&self) -> &PoolLimits {
+        &self.inner.limits
+    }
+
+    /// Number of unused `Connection`s currently kept open
+    pub fn idle(&self) -> usize {
+        self.inner.state().idle_count()
+    }
+
+    /// Get a `Connection` to the peer with the given `PublicKey`, reusing an
+    /// unused one if it is still usable or connecting to any of the
+    /// `candidates` otherwise. This waits while the maximum number of
+    /// `Connection`s to this peer are checked out.
+    pub async fn get(
+        &self,
+        pkey: &PublicKey,
+        candidates: &[C::Candidate],
+    ) -> Result<PooledConnection<C>, ConnectError> {
+        let slots = self
+            .inner
+            .state()
+            .slots
+            .entry(*pkey)
+            .or_insert_with(|| {
+                Arc::new(Semaphore::new(self.inner.limits.per_peer))
+            })
+            .clone();
+        let permit = slots.acquire_owned().await.expect("pool slots closed");
+
+        if let Some(connection) = self.checkout(pkey) {
+            return Ok(self.guard(*pkey, connection, permit));
+        }
+
+        ensure!(
+            !candidates.is_empty(),
+            Other {
+                reason: format!("no candidate to connect to {}", pkey),
+            }
+        );
+
+        debug!(
+            target: targets::CONNECTOR,
+            "opening new pooled connection to {}", pkey
+        );
+
+        let connection = if candidates.len() == 1 {
+            self.inner.connector.connect(pkey, &candidates[0]).await?
+        } else {
+            self.inner
+                .connector
+                .connect_any(pkey, candidates, None)
+                .await?
+        };
+
+        Ok(self.guard(*pkey, connection, permit))
+    }
+
+    /// Close all unused `Connection`s
+    pub fn clear(&self) {
+        let mut state = self.inner.state();
+
+        state.idle.clear();
+        state.evict_before(Instant::now());
+    }
+
+    /// Take the most recently used `Connection` to `pkey` that is still
+    /// usable, closing the ones that are not
+    fn checkout(&self, pkey: &PublicKey) -> Option<Connection> {
+        let deadline =
+            Instant::now().checked_sub(self.inner.limits.idle_timeout);
+        let mut state = self.inner.state();
+        let idle = state.idle.get_mut(pkey)?;
+        let mut found = None;
+
+        while let Some(candidate) = idle.pop() {
+            let fresh =
+                deadline.is_none_or(|deadline| candidate.since > deadline);
+
+            if fresh && !candidate.connection.is_broken() {
+                found = Some(candidate.connection);
+                break;
+            }
+
+            debug!(
+                target: targets::CONNECTOR,
+                "discarding stale pooled connection to {}", pkey
+            );
+        }
+
+        if idle.is_empty() {
+            state.idle.remove(pkey);
+        }
+
+        found
+    }
+
+    fn guard(
+        &self,
+        pkey: PublicKey,
+        connection: Connection,
+        permit: OwnedSemaphorePermit,
+    ) -> PooledConnection<C> {
+        PooledConnection {
+            connection: Some(connection),
+            pkey,
+            pool: Arc::downgrade(&self.inner),
+            broken: false,
+            _permit: permit,
+        }
+    }
+}
+
+impl<C: Connector> Clone for ConnectionPool<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C: Connector> fmt::Debug for ConnectionPool<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("limits", &self.inner.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Give back a `Connection` to its pool, starting the task closing idle
+/// `Connection`s if it is not running yet
+fn release<C>(inner: Arc<Inner<C>>, pkey: PublicKey, connection: Connection)
+where
+    C: Connector + 'static,
+{
+    let mut state = inner.state();
+
+    state.idle.entry(pkey).or_default().push(Idle {
+        connection,
+        since: Instant::now(),
+    });
+    state.evict_lru(inner.limits.capacity);
+
+    if !state.reaping {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            state.reaping = true;
+            runtime.spawn(reap(Arc::downgrade(&inner)));
+        }
+    }
+}
+
+/// Periodically close idle `Connection`s until the pool is dropped or empty
+async fn reap<C>(pool: Weak<Inner<C>>)
+where
+    C: Connector + 'static,
+{
+    loop {
+        let period = match pool.upgrade() {
+            Some(inner) => inner.limits.idle_timeout / 2,
+            None => return,
+        };
+
+        time::sleep(period.max(Duration::from_millis(1))).await;
+
+        let inner = match pool.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut state = inner.state();
+
+        if let Some(deadline) =
+            Instant::now().checked_sub(inner.limits.idle_timeout)
+        {
+            state.evict_before(deadline);
+        }
+
+        if state.idle.is_empty() {
+            state.reaping = false;
+            return;
+        }
+    }
+}
+
+/// A `Connection` checked out of a [`ConnectionPool`], given back to it
+/// when dropped unless it is broken or was marked as such
+///
+/// [`ConnectionPool`]: self::ConnectionPool
+pub struct PooledConnection<C: Connector + 'static> {
+    connection: Option<Connection>,
+    pkey: PublicKey,
+    pool: Weak<Inner<C>>,
+    broken: bool,
+    /// Released after the `Connection` is back in the pool
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<C: Connector + 'static> PooledConnection<C> {
+    /// Get the `PublicKey` of the peer this `Connection` goes to
+    pub fn public(&self) -> &PublicKey {
+        &self.pkey
+    }
+
+    /// Prevent this `Connection` from being reused, for instance after a
+    /// protocol error that left it in an unknown state
+    pub fn mark_broken(&mut self) {
+        self.broken = true;
+    }
+
+    /// Take the `Connection` out of the pool for good
+    pub fn detach(mut self) -> Connection {
+        self.connection.take().expect("connection already taken")
+    }
+}
+
+impl<C: Connector + 'static> Deref for PooledConnection<C> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().expect("connection already taken")
+    }
+}
+
+impl<C: Connector + 'static> DerefMut for PooledConnection<C> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("connection already taken")
+    }
+}
+
+impl<C: Connector + 'static> fmt::Debug for PooledConnection<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledConnection")
+            .field("public", &self.pkey)
+            .field("broken", &self.broken)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Connector + 'static> Drop for PooledConnection<C> {
+    fn drop(&mut self) {
+        let connection = match self.connection.take() {
+            Some(connection) if !self.broken && !connection.is_broken() => {
+                connection
+            }
+            _ => return,
+        };
+
+        if let Some(inner) = self.pool.upgrade() {
+            release(inner, self.pkey, connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::Socket;
+    use crate::test::*;
+
+    use tokio::task;
+
+    /// A `Connector` opening in-memory `Connection`s that can only be written
+    /// to, counting how many it opened
+    struct Memory {
+        exchanger: Exchanger,
+        dials: AtomicUsize,
+    }
+
+    impl Memory {
+        fn pool(limits: PoolLimits) -> ConnectionPool<Self> {
+            ConnectionPool::with_limits(
+                Self {
+                    exchanger: Exchanger::random(),
+                    dials: AtomicUsize::new(0),
+                },
+                limits,
+            )
+        }
+    }
+
+    #[async_trait]
+    impl Connector for Memory {
+        type Candidate = SocketAddr;
+
+        fn exchanger(&self) -> &Exchanger {
+            &self.exchanger
+        }
+
+        async fn establish(
+            &self,
+            _: &PublicKey,
+            _: &SocketAddr,
+        ) -> Result<Box<dyn Socket>, ConnectError> {
+            self.dials.fetch_add(1, Ordering::SeqCst);
+
+            Ok(Box::new(WireSocket::new(Vec::new()).0))
+        }
+    }
+
+    fn dials(pool: &ConnectionPool<Memory>) -> usize {
+        pool.connector().dials.load(Ordering::SeqCst)
+    }
+
+    fn peer() -> (PublicKey, [SocketAddr; 1]) {
+        (
+            *Exchanger::random().keypair().public(),
+            [(Ipv4Addr::LOCALHOST, 9600).into()],
+        )
+    }
+
+    #[tokio::test]
+    async fn sequential_reuse() {
+        let pool = Memory::pool(PoolLimits::default());
+        let (pkey, candidates) = peer();
+
+        for _ in 0..5 {
+            let mut connection =
+                pool.get(&pkey, &candidates).await.expect("get failed");
+
+            connection.send(&0u32).await.expect("send failed");
+        }
+
+        assert_eq!(dials(&pool), 1, "connection not reused");
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_checkout() {
+        let pool = Memory::pool(PoolLimits::default());
+        let (pkey, candidates) = peer();
+        let first = pool.get(&pkey, &candidates).await.expect("get failed");
+        let waiter = {
+            let pool = pool.clone();
+
+            task::spawn(async move {
+                pool.get(&pkey, &candidates).await.map(|c| *c.public())
+            })
+        };
+
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert!(!waiter.is_finished(), "connection checked out twice");
+
+        drop(first);
+
+        waiter.await.unwrap().expect("get failed");
+
+        assert_eq!(dials(&pool), 1, "waiter did not reuse connection");
+
+        let pool = Memory::pool(PoolLimits::default().per_peer(2));
+        let (pkey, candidates) = peer();
+        let first = pool.get(&pkey, &candidates).await.expect("get failed");
+        let second = pool.get(&pkey, &candidates).await.expect("get failed");
+
+        assert_eq!(dials(&pool), 2, "second connection not opened");
+
+        drop((first, second));
+
+        assert_eq!(pool.idle(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_eviction() {
+        let timeout = Duration::from_secs(30);
+        let pool = Memory::pool(PoolLimits::default().idle_timeout(timeout));
+        let (pkey, candidates) = peer();
+
+        drop(pool.get(&pkey, &candidates).await.expect("get failed"));
+
+        time::sleep(timeout / 2).await;
+
+        assert_eq!(pool.idle(), 1, "evicted too early");
+
+        drop(pool.get(&pkey, &candidates).await.expect("get failed"));
+
+        time::sleep(timeout + timeout / 2).await;
+
+        assert_eq!(pool.idle(), 0, "idle connection not closed");
+
+        drop(pool.get(&pkey, &candidates).await.expect("get failed"));
+
+        assert_eq!(dials(&pool), 2, "evicted connection reused");
+    }
+
+    #[tokio::test]
+    async fn lru_capacity() {
+        let pool = Memory::pool(PoolLimits::default().capacity(2));
+        let peers = (0..3).map(|_| peer()).collect::<Vec<_>>();
+
+        for (pkey, candidates) in &peers {
+            drop(pool.get(pkey, candidates).await.expect("get failed"));
+        }
+
+        assert_eq!(pool.idle(), 2, "capacity exceeded");
+
+        let (pkey, candidates) = &peers[0];
+
+        drop(pool.get(pkey, candidates).await.expect("get failed"));
+
+        assert_eq!(dials(&pool), 4, "least recently used was kept");
+    }
+
+    #[tokio::test]
+    async fn broken_replaced() {
+        let pool = Memory::pool(PoolLimits::default());
+        let (pkey, candidates) = peer();
+        let mut connection =
+            pool.get(&pkey, &candidates).await.expect("get failed");
+
+        // the in-memory socket has nothing to read
+        connection.receive::<u32>().await.expect_err("received");
+
+        assert!(connection.is_broken());
+
+        drop(connection);
+
+        assert_eq!(pool.idle(), 0, "broken connection kept");
+
+        let mut connection =
+            pool.get(&pkey, &candidates).await.expect("get failed");
+
+        connection.mark_broken();
+        drop(connection);
+
+        pool.get(&pkey, &candidates).await.expect("get failed");
+
+        assert_eq!(dials(&pool), 3, "broken connection reused");
+    }
+
+    #[tokio::test]
+    async fn no_candidates() {
+        let pool = Memory::pool(PoolLimits::default());
+        let (pkey, _) = peer();
+
+        pool.get(&pkey, &[])
+            .await
+            .expect_err("connected to nothing");
+    }
+}
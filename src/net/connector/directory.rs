@@ -1,10 +1,13 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
     fmt,
     future::Future,
     io::{Error, ErrorKind},
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
@@ -18,19 +21,21 @@ use tokio::{
         broadcast::{channel, Receiver, Sender},
         Mutex,
     },
-    task,
+    task, time,
 };
-use tracing::{debug, error, info, trace_span};
+use tracing::{debug, error, info, trace_span, warn};
 use tracing_futures::Instrument;
 
 use super::{
     super::{
-        common::directory::{Info, Request, Response},
+        common::directory::{self, Hello, Info, Request, Response},
         Connection, ReceiveError, SendError, Socket,
     },
-    Other as ConnectOther, *,
+    Other as ConnectOther, Timeout as ConnectTimeout, *,
 };
 use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::data::{BoundedMap, RetentionPolicy};
+use crate::telemetry::targets;
 
 #[derive(Debug, Snafu)]
 pub enum DirectoryError {
@@ -51,20 +56,72 @@ pub enum DirectoryError {
         when: &'static str,
         source: ReceiveError,
     },
+    #[snafu(display("directory timed out after {:?} when {}", after, when))]
+    Timeout { when: &'static str, after: Duration },
     #[snafu(display("{}", reason))]
     Other { reason: String },
 }
 
 type ChannelPair = (Sender<Response>, Sender<Request>);
 
+/// Default number of directories a `DirectoryConnector` keeps a `Connection`
+/// open with
+pub const DEFAULT_MAX_DIRECTORIES: usize = 8;
+
+/// Default time a `DirectoryConnector` waits for each response from a
+/// directory server
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of peer addresses each directory handler of a
+/// `DirectoryConnector` remembers
+pub const DEFAULT_PEER_CACHE_SIZE: usize = 1024;
+
+/// Default time during which a `DirectoryConnector` remembers a peer address
+/// learned from a directory, this matches the renewal period of
+/// `DirectoryListener`
+pub const DEFAULT_PEER_CACHE_AGE: Duration = Duration::from_secs(600);
+
+/// Statistics about the usage of a `DirectoryConnector`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirectoryStats {
+    /// Number of handlers that still have a `Connection` to a directory
+    pub active_handlers: usize,
+    /// Number of requests sent to directory servers
+    pub requests: u64,
+    /// Number of requests that were not answered in time
+    pub timeouts: u64,
+    /// Number of handlers evicted to stay within the directory limits
+    pub evictions: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    active_handlers: Arc<AtomicUsize>,
+    requests: AtomicU64,
+    timeouts: AtomicU64,
+    evictions: Arc<AtomicU64>,
+}
+
+/// Channels of a running handler
+struct HandlerEntry {
+    channels: ChannelPair,
+    /// Protocol negotiated with the directory
+    hello: Hello,
+}
+
 /// A `Connector` that makes use of a centralized directory in order
 /// to discover peers by their `PublicKey`. This `Connector` uses `PublicKey`s
 /// as `Candidate` and finds out the actual address from the directory server.
 pub struct DirectoryConnector {
     /// `Connector` that will be used to open `Connection`s to peers
     connector: Arc<dyn Connector<Candidate = SocketAddr>>,
-    /// Channels for requests to handlers
-    handlers: Mutex<HashMap<Info, ChannelPair>>,
+    /// Channels for requests to handlers, least recently used first
+    handlers: Mutex<BoundedMap<Info, HandlerEntry>>,
+    /// Limits on the peer addresses cached by each handler
+    peer_cache: RetentionPolicy,
+    /// Time to wait for each response from a directory
+    request_timeout: Duration,
+    counters: Counters,
 }
 
 impl DirectoryConnector {
@@ -73,13 +130,103 @@ impl DirectoryConnector {
     ///
     /// # Arguments
     /// * `connector` the `Connector` that will be used to establish all
-    /// `Connection`s including the `Connection` to the directory server
+    ///   `Connection`s including the `Connection` to the directory server
     pub fn new<C: Connector<Candidate = SocketAddr> + 'static>(
         connector: C,
     ) -> Self {
+        let counters = Counters::default();
+        let evictions = counters.evictions.clone();
+        let policy =
+            RetentionPolicy::default().max_entries(DEFAULT_MAX_DIRECTORIES);
+
+        // pending requests hold their own sender, so an evicted handler only
+        // exits once they are done
+        let handlers =
+            BoundedMap::new(policy).on_evict(move |info: Info, _, why| {
+                debug!(
+                    target: targets::DIRECTORY,
+                    "evicting handler for directory {}: {}",
+                    info.addr(),
+                    why
+                );
+                evictions.fetch_add(1, Ordering::Relaxed);
+            });
+
         Self {
             connector: Arc::new(connector),
-            handlers: Mutex::new(HashMap::new()),
+            handlers: Mutex::new(handlers),
+            peer_cache: RetentionPolicy::default()
+                .max_entries(DEFAULT_PEER_CACHE_SIZE)
+                .max_age(DEFAULT_PEER_CACHE_AGE),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            counters,
+        }
+    }
+
+    /// Wait at most `timeout` for each response from a directory server
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Keep a `Connection` open with at most `max` directory servers. The
+    /// least recently used `Connection` is closed once it has no pending
+    /// request when this limit is exceeded.
+    pub fn with_max_directories(mut self, max: usize) -> Self {
+        let handlers = self.handlers.get_mut();
+        let policy = handlers.policy().max_entries(max.max(1));
+
+        handlers.set_policy(policy);
+        self
+    }
+
+    /// Use the given `RetentionPolicy` for the `Connection`s to directory
+    /// servers, a `Connection` is used whenever a request is sent on it.
+    /// This replaces the limit set by `with_max_directories`.
+    pub fn with_directory_retention(mut self, policy: RetentionPolicy) -> Self {
+        self.handlers.get_mut().set_policy(policy);
+        self
+    }
+
+    /// Use the given `RetentionPolicy` for the addresses of peers cached from
+    /// each directory server
+    pub fn with_peer_cache(mut self, policy: RetentionPolicy) -> Self {
+        self.peer_cache = policy;
+        self
+    }
+
+    /// Get statistics about the usage of this `DirectoryConnector`
+    pub fn stats(&self) -> DirectoryStats {
+        let counters = &self.counters;
+
+        DirectoryStats {
+            active_handlers: counters.active_handlers.load(Ordering::Relaxed),
+            requests: counters.requests.load(Ordering::Relaxed),
+            timeouts: counters.timeouts.load(Ordering::Relaxed),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Get the directory protocol version negotiated with the given
+    /// directory server, or `None` if there is no open `Connection` to it.
+    /// Directory servers that predate versioning use `LEGACY_VERSION`.
+    pub async fn protocol_version(&self, directory: &Info) -> Option<u16> {
+        let handlers = self.handlers.lock().await;
+
+        handlers.get(directory).map(|entry| entry.hello.version)
+    }
+
+    /// Wait for the next response from a directory handler
+    async fn next_response(
+        &self,
+        rx: &mut Receiver<Response>,
+    ) -> Result<Option<Response>, Duration> {
+        match time::timeout(self.request_timeout, rx.recv()).await {
+            Ok(response) => Ok(response.ok()),
+            Err(_) => {
+                self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(self.request_timeout)
+            }
         }
     }
 
@@ -91,7 +238,7 @@ impl DirectoryConnector {
     /// # Arguments
     /// * `nr_peer` The number of peers to wait before returning
     /// * `info` The information (public key and address) needed to contact the
-    /// directory server
+    ///   directory server
     pub async fn wait(
         &mut self,
         nr_peer: usize,
@@ -105,25 +252,62 @@ impl DirectoryConnector {
         let req = Request::Wait(nr_peer);
         let mut i = 0;
 
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+
         tx.send(req)
             .map_err(|_| {
-                error!("failed to send message, handler died");
+                error!(
+                    target: targets::DIRECTORY,
+                    "failed to send message, handler died"
+                );
                 Error::new(ErrorKind::NotConnected, "")
             })
             .context(DirectoryIo {
                 when: "sending request",
             })?;
 
-        debug!("waiting for {} peers in the directory", nr_peer);
+        debug!(
+            target: targets::DIRECTORY,
+            "waiting for {} peers in the directory", nr_peer
+        );
 
         loop {
-            if let Ok(peer) = rx.recv().await {
-                if let Response::Found(pkey, addr) = peer {
-                    info!("found peer {} at {}", pkey, addr);
-                    peers.push((pkey, addr).into());
+            let response = match self.next_response(&mut rx).await {
+                Ok(response) => response,
+                Err(after) => {
+                    return Timeout {
+                        when: "waiting for peers",
+                        after,
+                    }
+                    .fail()
+                }
+            };
+
+            if let Some(peer) = response {
+                match peer {
+                    Response::FoundOne(pkey, addr) => {
+                        info!(
+                            target: targets::DIRECTORY,
+                            "found peer {} at {}", pkey, addr
+                        );
+                        peers.push((pkey, addr).into());
+                    }
+                    Response::Found(pkey, addrs) => {
+                        info!(
+                            target: targets::DIRECTORY,
+                            "found peer {} at {:?}", pkey, addrs
+                        );
+                        peers.extend(
+                            addrs.into_iter().map(|a| Info::from((pkey, a))),
+                        );
+                    }
+                    _ => {}
                 }
             } else {
-                error!("handler died, while waiting for directory");
+                error!(
+                    target: targets::DIRECTORY,
+                    "handler died, while waiting for directory"
+                );
             }
 
             i += 1;
@@ -133,7 +317,10 @@ impl DirectoryConnector {
             }
         }
 
-        info!("got {} peers from directory", nr_peer);
+        info!(
+            target: targets::DIRECTORY,
+            "got {} peers from directory", nr_peer
+        );
         Ok(peers)
     }
 
@@ -144,31 +331,64 @@ impl DirectoryConnector {
         let dir_addr = info.addr();
         let pkey = info.public();
 
-        match self.handlers.lock().await.entry(*info) {
-            Entry::Occupied(e) => {
-                let (bsender, sender) = e.get();
-                Ok((bsender.subscribe(), sender.clone()))
-            }
-            Entry::Vacant(e) => {
-                let connection = self
-                    .connector
-                    .connect(pkey, &dir_addr)
-                    .instrument(trace_span!("directory_connect"))
-                    .await?;
-                let (resp_tx, _) = channel(32);
-                let (req_tx, req_rx) = channel(32);
-                let handler =
-                    Handler::spawn(req_rx, resp_tx.clone(), connection);
+        let mut handlers = self.handlers.lock().await;
+        let now = Instant::now();
 
-                task::spawn(handler);
+        handlers.sweep(now);
 
-                let tuple = (resp_tx, req_tx);
+        if handlers.touch(info, now) {
+            let entry = handlers.get(info).expect("touched handler missing");
+            let (bsender, sender) = &entry.channels;
 
-                e.insert(tuple.clone());
+            return Ok((bsender.subscribe(), sender.clone()));
+        }
 
-                Ok((tuple.0.subscribe(), tuple.1))
+        let (connection, hello) = Handler::negotiate(
+            self.connector.as_ref(),
+            pkey,
+            dir_addr,
+            self.request_timeout,
+        )
+        .instrument(
+            trace_span!(target: targets::DIRECTORY, "directory_connect"),
+        )
+        .await
+        .inspect_err(|e| {
+            if matches!(e, ConnectError::Timeout { .. }) {
+                self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
             }
-        }
+        })?;
+        let (resp_tx, _) = channel(32);
+        let (req_tx, req_rx) = channel(32);
+        let handler = Handler::spawn(
+            req_rx,
+            resp_tx.clone(),
+            connection,
+            hello,
+            self.peer_cache,
+        );
+        let active = self.counters.active_handlers.clone();
+
+        active.fetch_add(1, Ordering::Relaxed);
+
+        task::spawn(async move {
+            let result = handler.await;
+
+            active.fetch_sub(1, Ordering::Relaxed);
+
+            result
+        });
+
+        let channels = (resp_tx, req_tx);
+        let subscribed = (channels.0.subscribe(), channels.1.clone());
+
+        handlers.insert(
+            *info,
+            HandlerEntry { channels, hello },
+            Instant::now(),
+        );
+
+        Ok(subscribed)
     }
 }
 
@@ -191,10 +411,15 @@ impl Connector for DirectoryConnector {
         pkey: &PublicKey,
         directory_info: &Self::Candidate,
     ) -> Result<Box<dyn Socket>, ConnectError> {
-        info!("finding peer address for public key {}", pkey);
+        info!(
+            target: targets::DIRECTORY,
+            "finding peer address for public key {}", pkey
+        );
 
         let (mut rx, tx) = self.find_directory_handler(directory_info).await?;
 
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+
         if tx.send(Request::Fetch(*pkey)).is_err() {
             ConnectOther {
                 reason: "no handler for directory",
@@ -202,11 +427,40 @@ impl Connector for DirectoryConnector {
             .fail()?;
         }
 
-        while let Ok(response) = rx.recv().await {
+        loop {
+            let response = match self.next_response(&mut rx).await {
+                Ok(Some(response)) => response,
+                Ok(None) => break,
+                Err(after) => return ConnectTimeout { after }.fail(),
+            };
+
             match response {
-                Response::Found(recvd_pkey, addr) if recvd_pkey == *pkey => {
+                Response::FoundOne(recvd_pkey, addr) if recvd_pkey == *pkey => {
                     return self.connector.establish(pkey, &addr).await;
                 }
+                Response::Found(recvd_pkey, addrs) if recvd_pkey == *pkey => {
+                    return self.establish_any(pkey, &addrs).await;
+                }
+                Response::FoundSigned(info) if info.public() == pkey => {
+                    if info.verify().is_err() || info.is_expired() {
+                        ConnectOther {
+                            reason: "directory returned an invalid record",
+                        }
+                        .fail()?;
+                    }
+
+                    return self.connector.establish(pkey, &info.addr()).await;
+                }
+                Response::FoundCard(card) if card.public() == pkey => {
+                    if !card.is_signed() || card.check().is_err() {
+                        ConnectOther {
+                            reason: "directory returned an invalid record",
+                        }
+                        .fail()?;
+                    }
+
+                    return self.establish_any(pkey, card.candidates()).await;
+                }
                 Response::NotFound(_) => ConnectOther {
                     reason: "peer not found in directory",
                 }
@@ -224,15 +478,91 @@ impl Connector for DirectoryConnector {
     }
 }
 
+impl DirectoryConnector {
+    /// Try each address the directory returned for `pkey` in turn, in the
+    /// order the peer registered them, returning the first `Socket` opened or
+    /// the error from the last address
+    async fn establish_any(
+        &self,
+        pkey: &PublicKey,
+        addrs: &[SocketAddr],
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        let mut last = None;
+
+        for addr in addrs {
+            match self.connector.establish(pkey, addr).await {
+                Ok(socket) => return Ok(socket),
+                Err(e) => {
+                    debug!(
+                        target: targets::DIRECTORY,
+                        "failed to reach {} at {}: {}", pkey, addr, e
+                    );
+                    last = Some(e);
+                }
+            }
+        }
+
+        match last {
+            Some(e) => Err(e),
+            None => ConnectOther {
+                reason: "directory did not provide an address",
+            }
+            .fail(),
+        }
+    }
+}
+
 /// This is an agent that takes care of sending requests to one directory server
 /// and updating the local peer cache accordingly
 struct Handler;
 
 impl Handler {
+    /// Open a `Connection` to a directory server and negotiate the protocol
+    /// to use. Directory servers that predate `Hello` close the `Connection`
+    /// when receiving it, in which case a new `Connection` is opened and the
+    /// legacy protocol is used.
+    async fn negotiate(
+        connector: &dyn Connector<Candidate = SocketAddr>,
+        pkey: &PublicKey,
+        dir_addr: SocketAddr,
+        timeout: Duration,
+    ) -> Result<(Connection, Hello), ConnectError> {
+        // the directory protocol is plain text, see `DirectoryServer`
+        let mut connection =
+            Connection::new(connector.establish(pkey, &dir_addr).await?);
+
+        match time::timeout(timeout, directory::negotiate(&mut connection))
+            .await
+        {
+            Ok(Some(hello)) => {
+                debug!(
+                    target: targets::DIRECTORY,
+                    "directory {} negotiated {}", dir_addr, hello
+                );
+                Ok((connection, hello))
+            }
+            Ok(None) => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "directory {} uses the legacy protocol", dir_addr
+                );
+
+                let connection = Connection::new(
+                    connector.establish(pkey, &dir_addr).await?,
+                );
+
+                Ok((connection, Hello::legacy()))
+            }
+            Err(_) => ConnectTimeout { after: timeout }.fail(),
+        }
+    }
+
     fn spawn(
         mut receiver: Receiver<Request>,
         mut notifier: Sender<Response>,
         mut connection: Connection,
+        hello: Hello,
+        policy: RetentionPolicy,
     ) -> impl Future<Output = Result<(), DirectoryError>> {
         let peer_addr = connection
             .peer_addr()
@@ -252,7 +582,7 @@ impl Handler {
         });
 
         async move {
-            let mut cache = HashMap::new();
+            let mut cache = BoundedMap::new(policy);
             let mut request_opt = None;
 
             loop {
@@ -273,14 +603,32 @@ impl Handler {
                         Either::Right((result, _)) => {
                             if let Some(request) = result {
                                 match request {
+                                    _ if !hello.allows(&request) => {
+                                        let response = Response::Error(
+                                            "feature not negotiated".into(),
+                                        );
+
+                                        if notifier.send(response).is_err() {
+                                            error!(
+                                                target: targets::DIRECTORY,
+                                                "connector died, exiting handler"
+                                            );
+                                            return Ok(());
+                                        }
+                                    }
                                     Request::Fetch(pkey) => {
                                         request_opt = Some(request);
 
-                                        if let Some(peer) = cache.get(&pkey) {
+                                        cache.sweep(Instant::now());
+
+                                        if let Some(addrs) = cache.get(&pkey) {
                                             if notifier.send(Response::Found(
-                                                pkey, *peer,
+                                                pkey, addrs.clone(),
                                             )).is_err() {
-                                                error!("connector died, exiting handler");
+                                                error!(
+                                                    target: targets::DIRECTORY,
+                                                    "connector died, exiting handler"
+                                                );
                                                 return Ok(());
                                             };
                                         }
@@ -288,7 +636,10 @@ impl Handler {
                                     _ => request_opt = Some(request),
                                 }
                             } else {
-                                info!("exiting handler");
+                                info!(
+                                    target: targets::DIRECTORY,
+                                    "exiting handler"
+                                );
                                 return Ok(());
                             }
                         }
@@ -300,17 +651,41 @@ impl Handler {
                 }
             }
         }
-        .instrument(trace_span!("directory_handler", server=%peer_addr))
+        .instrument(trace_span!(
+            target: targets::DIRECTORY,
+            "directory_handler",
+            server = %peer_addr
+        ))
     }
 }
 
 async fn process_response(
     response: Result<Response, ReceiveError>,
-    cache: &mut HashMap<PublicKey, SocketAddr>,
+    cache: &mut BoundedMap<PublicKey, Vec<SocketAddr>>,
     notifier: &mut Sender<Response>,
 ) -> Result<(), DirectoryError> {
-    if let Ok(Response::Found(pkey, addr)) = response {
-        cache.insert(pkey, addr);
+    match response {
+        Ok(Response::FoundOne(pkey, addr)) => {
+            cache.insert(pkey, vec![addr], Instant::now());
+        }
+        Ok(Response::Found(pkey, ref addrs)) => {
+            cache.insert(pkey, addrs.clone(), Instant::now());
+        }
+        Ok(Response::FoundSigned(ref info))
+            if info.verify().is_ok() && !info.is_expired() =>
+        {
+            cache.insert(*info.public(), vec![info.addr()], Instant::now());
+        }
+        Ok(Response::FoundCard(ref card))
+            if card.is_signed() && card.check().is_ok() =>
+        {
+            cache.insert(
+                *card.public(),
+                card.candidates().to_vec(),
+                Instant::now(),
+            );
+        }
+        _ => {}
     }
 
     let response = response.context(Receive {
@@ -318,7 +693,7 @@ async fn process_response(
     })?;
 
     if notifier.send(response).is_err() {
-        error!("no one waiting for response");
+        error!(target: targets::DIRECTORY, "no one waiting for response");
         Other {
             reason: "no connector waiting for response",
         }
@@ -391,8 +766,11 @@ mod test {
         let handle = task::spawn(async move {
             let peers = peers_copy;
 
-            let mut connection =
-                listener.accept().await.expect("accept failed");
+            let mut connection = Connection::new(
+                listener.establish().await.expect("accept failed"),
+            );
+
+            greet(&mut connection).await;
 
             assert_eq!(
                 connection
@@ -407,7 +785,7 @@ mod test {
                 connection
                     .send_plain(&Response::Found(
                         *exchanger.keypair().public(),
-                        addr,
+                        vec![addr],
                     ))
                     .await
                     .expect("send failed");
@@ -465,8 +843,11 @@ mod test {
         });
 
         let dir_handle = task::spawn(async move {
-            let mut connection =
-                dir_listener.accept().await.expect("dir accept failed");
+            let mut connection = Connection::new(
+                dir_listener.establish().await.expect("dir accept failed"),
+            );
+
+            greet(&mut connection).await;
 
             let msg = connection
                 .receive_plain::<Request>()
@@ -476,7 +857,7 @@ mod test {
             assert_eq!(msg, Request::Fetch(server_public));
 
             connection
-                .send_plain(&Response::Found(server_public, server))
+                .send_plain(&Response::Found(server_public, vec![server]))
                 .await
                 .expect("dir send failed");
         });
@@ -491,4 +872,244 @@ mod test {
         handle.await.expect("listener failed");
         dir_handle.await.expect("dir listener failed");
     }
+
+    #[tokio::test]
+    async fn establish_tries_every_address() {
+        let unreachable = next_test_ip4();
+        let server = next_test_ip4();
+        let server_exchanger = Exchanger::random();
+        let server_public = *server_exchanger.keypair().public();
+        let connector =
+            DirectoryConnector::new(TcpConnector::new(Exchanger::random()));
+        let mut listener = TcpListener::new(server, server_exchanger)
+            .await
+            .expect("listen failed");
+        let (dir_info, mut dir_listener) = directory().await;
+
+        let handle = task::spawn(async move {
+            let mut connection =
+                listener.accept().await.expect("accept failed");
+
+            let msg = connection.receive::<u32>().await.expect("recv failed");
+            assert_eq!(msg, 0u32, "wrong message received");
+        });
+
+        let dir_handle = task::spawn(async move {
+            let mut connection = Connection::new(
+                dir_listener.establish().await.expect("dir accept failed"),
+            );
+
+            greet(&mut connection).await;
+
+            assert_eq!(
+                connection
+                    .receive_plain::<Request>()
+                    .await
+                    .expect("dir recv failed"),
+                Request::Fetch(server_public)
+            );
+
+            let addrs = vec![unreachable, server];
+
+            connection
+                .send_plain(&Response::Found(server_public, addrs))
+                .await
+                .expect("dir send failed");
+        });
+
+        let mut connection = connector
+            .connect(&server_public, &dir_info)
+            .await
+            .expect("second address not tried");
+
+        connection.send(&0u32).await.expect("send failed");
+
+        handle.await.expect("listener failed");
+        dir_handle.await.expect("dir listener failed");
+    }
+
+    #[tokio::test]
+    async fn silent_directory_timeout() {
+        let directory_server = next_test_ip4();
+        let directory_exchanger = Exchanger::random();
+        let timeout = Duration::from_millis(100);
+        let connector =
+            DirectoryConnector::new(TcpConnector::new(Exchanger::random()))
+                .with_request_timeout(timeout);
+        let mut dir_listener =
+            TcpListener::new(directory_server, directory_exchanger.clone())
+                .await
+                .expect("dir listen failed");
+        let dir_info =
+            (*directory_exchanger.keypair().public(), directory_server).into();
+
+        let dir_handle = task::spawn(async move {
+            let mut connection = Connection::new(
+                dir_listener.establish().await.expect("dir accept failed"),
+            );
+
+            greet(&mut connection).await;
+
+            connection
+                .receive_plain::<Request>()
+                .await
+                .expect("dir recv failed");
+
+            // never answer and keep the connection open until the end
+            connection
+        });
+
+        let err = connector
+            .connect(Exchanger::random().keypair().public(), &dir_info)
+            .await
+            .expect_err("silent directory answered");
+
+        assert!(
+            matches!(err, ConnectError::Timeout { after } if after == timeout),
+            "wrong error: {}",
+            err
+        );
+
+        let stats = connector.stats();
+
+        assert_eq!(stats.requests, 1, "wrong request count");
+        assert_eq!(stats.timeouts, 1, "wrong timeout count");
+
+        dir_handle.await.expect("dir listener failed");
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used() {
+        use tokio::sync::oneshot;
+
+        let connector = Arc::new(
+            DirectoryConnector::new(TcpConnector::new(Exchanger::random()))
+                .with_max_directories(1),
+        );
+        let target = *Exchanger::random().keypair().public();
+        let (first_info, mut first_listener) = directory().await;
+        let (second_info, mut second_listener) = directory().await;
+        let (received_tx, received_rx) = oneshot::channel();
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+
+        let first_handle = task::spawn(async move {
+            let mut connection = Connection::new(
+                first_listener.establish().await.expect("accept failed"),
+            );
+
+            greet(&mut connection).await;
+            let request = connection
+                .receive_plain::<Request>()
+                .await
+                .expect("recv failed");
+
+            received_tx.send(()).expect("test failed");
+            release_rx.await.expect("test failed");
+
+            connection
+                .send_plain(&Response::NotFound(target))
+                .await
+                .expect("send failed");
+
+            // the handler closes the connection once it is drained
+            connection
+                .receive_plain::<Request>()
+                .await
+                .expect_err("evicted handler is still running");
+
+            request
+        });
+
+        let second_handle = task::spawn(async move {
+            let mut connection = Connection::new(
+                second_listener.establish().await.expect("accept failed"),
+            );
+
+            greet(&mut connection).await;
+
+            connection
+                .receive_plain::<Request>()
+                .await
+                .expect("recv failed");
+            connection
+                .send_plain(&Response::NotFound(target))
+                .await
+                .expect("send failed");
+
+            connection
+        });
+
+        let pending = {
+            let connector = connector.clone();
+
+            task::spawn(async move {
+                connector.connect(&target, &first_info).await.map(|_| ())
+            })
+        };
+
+        received_rx.await.expect("first request not received");
+
+        connector
+            .connect(&target, &second_info)
+            .await
+            .expect_err("peer found in second directory");
+
+        assert_eq!(connector.stats().evictions, 1, "no eviction");
+
+        release_tx.send(()).expect("test failed");
+
+        let err = pending
+            .await
+            .expect("connect task failed")
+            .expect_err("peer found in first directory");
+
+        assert_eq!(
+            err.to_string(),
+            "underlying connector error: peer not found in directory",
+            "in-flight request broken by eviction"
+        );
+
+        assert_eq!(
+            first_handle.await.expect("first directory failed"),
+            Request::Fetch(target)
+        );
+
+        let _second = second_handle.await.expect("second directory failed");
+
+        assert_eq!(connector.stats().requests, 2, "wrong request count");
+
+        // the handler task is accounted for right after closing the connection
+        for _ in 0..100 {
+            if connector.stats().active_handlers == 1 {
+                return;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("evicted handler still active");
+    }
+
+    /// Answer the `Hello` of a `DirectoryConnector` like a current directory
+    async fn greet(connection: &mut Connection) {
+        connection
+            .receive_plain::<Hello>()
+            .await
+            .expect("hello failed");
+        connection
+            .send_plain(&Hello::current())
+            .await
+            .expect("hello failed");
+    }
+
+    async fn directory() -> (Info, TcpListener) {
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let info = (*exchanger.keypair().public(), addr).into();
+        let listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("dir listen failed");
+
+        (info, listener)
+    }
 }
@@ -0,0 +1,112 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::super::{
+    ConnectionLimits, ConnectionObserver, HandshakeMode, MemoryRegistry, Socket,
+};
+use super::{ConnectError, Connector, Io};
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tracing::debug;
+
+/// A `Connector` that opens in-process `Connection`s to the `MemoryListener`s
+/// of a `MemoryRegistry`
+pub struct MemoryConnector {
+    exchanger: Exchanger,
+    registry: MemoryRegistry,
+    limits: Option<Arc<ConnectionLimits>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    handshake: HandshakeMode,
+    local: Option<SocketAddr>,
+}
+
+impl MemoryConnector {
+    /// Create a new `MemoryConnector` reaching the listeners of `registry`
+    /// and using the given `Exchanger` to secure `Connection`s
+    pub fn new(registry: MemoryRegistry, exchanger: Exchanger) -> Self {
+        Self {
+            exchanger,
+            registry,
+            limits: None,
+            observer: None,
+            handshake: HandshakeMode::Plain,
+            local: None,
+        }
+    }
+
+    /// Open every `Connection` from `addr` instead of a fresh address, so
+    /// that they follow the `LinkProfile` set for `addr` in the registry
+    pub fn with_local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local = Some(addr);
+        self
+    }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` opened by
+    /// this `MemoryConnector`
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Notify `observer` of every `Connection` opened by this
+    /// `MemoryConnector` instead of the process-wide `ConnectionObserver`
+    pub fn with_observer<O: ConnectionObserver + 'static>(
+        mut self,
+        observer: O,
+    ) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Secure every `Connection` opened by this `MemoryConnector` using
+    /// `HandshakeMode::Private`
+    pub fn private_handshake(mut self) -> Self {
+        self.handshake = HandshakeMode::Private;
+        self
+    }
+}
+
+#[async_trait]
+impl Connector for MemoryConnector {
+    type Candidate = SocketAddr;
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.limits.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.handshake
+    }
+
+    async fn establish(
+        &self,
+        _: &PublicKey,
+        candidate: &Self::Candidate,
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        debug!(
+            target: targets::CONNECTOR,
+            "establishing memory connection to {}", candidate
+        );
+
+        let socket = self
+            .registry
+            .dial(candidate, self.local)
+            .await
+            .context(Io)?;
+
+        Ok(Box::new(socket))
+    }
+}
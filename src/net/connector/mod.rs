@@ -1,6 +1,20 @@
 /// Connector that uses a central directory server to find peers
 mod directory;
-pub use directory::DirectoryConnector;
+pub use directory::{
+    DirectoryConnector, DirectoryStats, DEFAULT_MAX_DIRECTORIES,
+    DEFAULT_PEER_CACHE_AGE, DEFAULT_PEER_CACHE_SIZE, DEFAULT_REQUEST_TIMEOUT,
+};
+
+/// Limits on how fast many peers are dialed
+mod pacing;
+pub use pacing::{
+    DialProgress, Pacer, Pacing, DEFAULT_DIAL_INTERVAL,
+    DEFAULT_MAX_CONCURRENT_DIALS,
+};
+
+/// Connector that reaches peers through a SOCKS5 or HTTP proxy
+mod proxy;
+pub use proxy::{ProxiedConnector, ProxyKind, ProxyTarget};
 
 /// Connector that can use anything that resolves to a `SocketAddr`
 mod resolve;
@@ -10,25 +24,56 @@ pub use resolve::ResolveConnector;
 mod tcp;
 pub use tcp::TcpConnector;
 
+/// Udp connector sending one datagram per frame
+mod udp;
+pub use udp::UdpConnector;
+
+/// Connector using Unix domain sockets
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use self::unix::{UnixConnector, UnixPath};
+
+/// In-process connector
+mod memory;
+pub use memory::MemoryConnector;
+
 /// uTP connector
 #[cfg(feature = "unstable")]
 mod utp;
 #[cfg(feature = "unstable")]
 pub use self::utp::UtpConnector;
 
+/// QUIC connector
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "quic")]
+pub use self::quic::QuicConnector;
+
 use std::fmt;
 use std::io::{Error, ErrorKind};
-
-use super::{Connection, SecureError, Socket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{
+    recovery::Phase, Connection, ConnectionLimits, ConnectionObserver,
+    Decision, Direction, HandshakeMode, SecureError, SecureReceive, SecureSend,
+    Socket,
+};
 use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
 
 use async_trait::async_trait;
 
+use backoff::ExponentialBackoff;
+
 use futures::future;
 
-use snafu::{ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use tokio::time;
 
-use tracing::{debug_span, info};
+use tracing::{debug_span, info, warn};
 use tracing_futures::Instrument;
 
 #[derive(Debug, Snafu)]
@@ -51,6 +96,37 @@ pub enum ConnectError {
         /// Underlying error cause
         source: SecureError,
     },
+    #[snafu(display("proxy error {}: {}", code, reason))]
+    #[snafu(visibility(pub))]
+    /// The proxy used to reach the remote peer refused the `Connection`
+    Proxy {
+        /// Reply code sent by the proxy
+        code: u16,
+        /// Meaning of the reply code
+        reason: &'static str,
+    },
+    #[snafu(display("timed out after {:?}", after))]
+    #[snafu(visibility(pub))]
+    /// The remote end did not answer in time
+    Timeout {
+        /// Time waited before giving up
+        after: Duration,
+    },
+    #[snafu(display("handshake timed out after {:?}", after))]
+    #[snafu(visibility(pub))]
+    /// The remote end did not complete the handshake within the timeout of
+    /// the `ConnectionLimits` of the `Connector`
+    HandshakeTimeout {
+        /// Time waited before giving up
+        after: Duration,
+    },
+    #[snafu(display("rejected by remote peer: {}", reason))]
+    #[snafu(visibility(pub))]
+    /// The `Authorizer` of the remote peer refused this `Connection`
+    Rejected {
+        /// Reason given by the remote peer
+        reason: String,
+    },
     #[snafu(display("underlying connector error: {}", reason))]
     #[snafu(visibility(pub))]
     /// Any other kind of error
@@ -60,6 +136,21 @@ pub enum ConnectError {
     },
 }
 
+impl ConnectError {
+    /// Get the step of connecting during which this error happened, if it
+    /// can be attributed to one
+    pub fn phase(&self) -> Option<Phase> {
+        match self {
+            Self::Io { .. } | Self::Proxy { .. } => Some(Phase::Establish),
+            Self::Secure { .. } | Self::HandshakeTimeout { .. } => {
+                Some(Phase::Handshake)
+            }
+            Self::Rejected { .. } => Some(Phase::Authorize),
+            Self::Timeout { .. } | Self::Other { .. } => None,
+        }
+    }
+}
+
 impl From<ErrorKind> for ConnectError {
     fn from(kind: ErrorKind) -> Self {
         use snafu::IntoError;
@@ -87,22 +178,119 @@ pub trait Connector: Send + Sync {
         pkey: &PublicKey,
         candidate: &Self::Candidate,
     ) -> Result<Connection, ConnectError> {
+        let start = Instant::now();
+
         let socket = self
             .establish(pkey, candidate)
-            .instrument(debug_span!("establish"))
+            .instrument(debug_span!(target: targets::CONNECTOR, "establish"))
             .await;
 
+        let dial_time = start.elapsed();
         let mut connection = Connection::new(socket?);
 
-        info!("connected to {}, exchanging keys", candidate);
-
-        connection
-            .secure_server(self.exchanger(), pkey)
-            .instrument(debug_span!("key_exchange"))
-            .await
+        info!(
+            target: targets::CONNECTOR,
+            "connected to {}, exchanging keys", candidate
+        );
+
+        let start = Instant::now();
+        let limits = self.connection_limits().cloned();
+        let handshake = connection
+            .secure_as_dialer_with(
+                self.exchanger(),
+                pkey,
+                self.handshake_mode(),
+            )
+            .instrument(
+                debug_span!(target: targets::CONNECTOR, "key_exchange"),
+            );
+
+        match &limits {
+            Some(limits) => {
+                let after = limits.handshake();
+
+                time::timeout(after, handshake)
+                    .await
+                    .ok()
+                    .context(HandshakeTimeout { after })?
+            }
+            None => handshake.await,
+        }
+        .context(Secure)?;
+
+        if self.expects_decision() {
+            let decision = connection.receive::<Decision>().instrument(
+                debug_span!(target: targets::CONNECTOR, "authorization"),
+            );
+            let decision = match &limits {
+                Some(limits) => {
+                    let after = limits.handshake();
+
+                    time::timeout(after, decision)
+                        .await
+                        .ok()
+                        .context(HandshakeTimeout { after })?
+                }
+                None => decision.await,
+            }
+            .context(SecureReceive)
             .context(Secure)?;
 
-        info!("secure connection established with {}", candidate);
+            match decision {
+                Decision::Accept => {}
+                Decision::AcceptReadOnly => connection.set_read_only(true),
+                Decision::Reject { reason } => {
+                    let _ = connection.close().await;
+
+                    return Rejected { reason }.fail();
+                }
+            }
+        }
+
+        if let Some(limits) = limits {
+            connection.set_limits(limits);
+        }
+
+        info!(
+            target: targets::CONNECTOR,
+            "secure connection established with {}", candidate
+        );
+
+        connection.notify_established(
+            self.observer(),
+            Direction::Outbound,
+            dial_time,
+            start.elapsed(),
+        );
+
+        Ok(connection)
+    }
+
+    /// Connect like `Connector::connect` and send `early_data` as the first
+    /// frame before returning, to be received using
+    /// `Connection::receive_frame`. <br />
+    /// Securing a `Connection` takes no round trip since the dialer derives
+    /// the session keys from the remote `PublicKey` it already knows, so the
+    /// frame is sent in the same flight as the key announcement. Session keys
+    /// only depend on both static keys: the frame, like everything the dialer
+    /// sends, is not forward secret and can be replayed to the remote peer
+    /// by anyone recording the `Connection`. Early data should therefore be
+    /// idempotent.
+    async fn connect_with_early_data(
+        &self,
+        pkey: &PublicKey,
+        candidate: &Self::Candidate,
+        early_data: Option<&[u8]>,
+    ) -> Result<Connection, ConnectError> {
+        let mut connection = self.connect(pkey, candidate).await?;
+
+        if let Some(data) = early_data {
+            connection
+                .send_frame(data)
+                .await
+                .context(SecureSend)
+                .context(Secure)?;
+        }
 
         Ok(connection)
     }
@@ -111,6 +299,33 @@ pub trait Connector: Send + Sync {
     /// secure `Connection`s
     fn exchanger(&self) -> &Exchanger;
 
+    /// Return the `ConnectionLimits` applied to `Connection`s opened by this
+    /// `Connector`, if it has any. Their handshake timeout bounds the time
+    /// spent securing each `Connection`.
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        None
+    }
+
+    /// Return the `ConnectionObserver` notified of the `Connection`s opened by
+    /// this `Connector` instead of the process-wide one, if it has one
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        None
+    }
+
+    /// Return the `HandshakeMode` used to secure `Connection`s opened by this
+    /// `Connector`, which must match the one of the remote `Listener`
+    fn handshake_mode(&self) -> HandshakeMode {
+        HandshakeMode::Plain
+    }
+
+    /// Check whether the remote `Listener` sends its `Decision` after the
+    /// handshake, in which case `Connector::connect` waits for it and fails
+    /// with `ConnectError::Rejected` if the remote peer refused the
+    /// `Connection`. Both sides must agree on this.
+    fn expects_decision(&self) -> bool {
+        false
+    }
+
     /// Establish a `Socket` to the given `Candidate` destination.
     /// This function should only open the connection and not send any data
     /// after the connection has been established in order not to make the
@@ -121,29 +336,86 @@ pub trait Connector: Send + Sync {
         candidate: &Self::Candidate,
     ) -> Result<Box<dyn Socket>, ConnectError>;
 
+    /// Connect like `Connector::connect`, giving up with
+    /// `ConnectError::Timeout` if the `Connection` is not ready after
+    /// `timeout`
+    async fn connect_with_timeout(
+        &self,
+        pkey: &PublicKey,
+        candidate: &Self::Candidate,
+        timeout: Duration,
+    ) -> Result<Connection, ConnectError> {
+        time::timeout(timeout, self.connect(pkey, candidate))
+            .await
+            .ok()
+            .context(Timeout { after: timeout })?
+    }
+
     /// Connect to any of the provided `Candidate` that advertise the
     /// given `PublicKey`. Only returns a `Connection` to the fastest
-    /// responding `Candidate`
+    /// responding `Candidate`. <br />
+    /// When a `timeout` is given, each `Candidate` that did not answer
+    /// within it is given up on, which bounds the time spent in this call
+    /// even if some `Candidate`s never answer.
     async fn connect_any(
         &self,
         pkey: &PublicKey,
         candidates: &[Self::Candidate],
+        timeout: Option<Duration>,
     ) -> Result<Connection, ConnectError> {
-        let futures = candidates.iter().map(|x| self.connect(pkey, x));
+        let futures = candidates
+            .iter()
+            .map(|x| Box::pin(connect_within(self, pkey, x, timeout)));
 
         future::select_ok(futures).await.map(|x| x.0)
     }
 
     /// Connect to many different peers using this `Connector`. All the
-    /// `Connection`s will be established in parallel.
+    /// `Connection`s will be established in parallel. When a `timeout` is
+    /// given, it applies to each peer separately.
     async fn connect_many(
         &self,
         peers: &[(Self::Candidate, PublicKey)],
+        timeout: Option<Duration>,
     ) -> Vec<Result<Connection, ConnectError>> {
-        let futures = peers.iter().map(|(addr, pkey)| self.connect(pkey, addr));
+        let futures = peers
+            .iter()
+            .map(|(addr, pkey)| connect_within(self, pkey, addr, timeout));
 
         future::join_all(futures).await
     }
+
+    /// Connect to many different peers like `Connector::connect_many`,
+    /// starting each dial when `pacer` allows it
+    async fn connect_many_paced(
+        &self,
+        peers: &[(Self::Candidate, PublicKey)],
+        pacer: &Pacer,
+    ) -> Vec<Result<Connection, ConnectError>> {
+        let futures = peers
+            .iter()
+            .map(|(addr, pkey)| pacer.dial(self.connect(pkey, addr)));
+
+        future::join_all(futures).await
+    }
+}
+
+/// Connect using `Connector::connect_with_timeout` if a `timeout` is given
+/// and `Connector::connect` otherwise
+async fn connect_within<C: Connector + ?Sized>(
+    connector: &C,
+    pkey: &PublicKey,
+    candidate: &C::Candidate,
+    timeout: Option<Duration>,
+) -> Result<Connection, ConnectError> {
+    match timeout {
+        Some(timeout) => {
+            connector
+                .connect_with_timeout(pkey, candidate, timeout)
+                .await
+        }
+        None => connector.connect(pkey, candidate).await,
+    }
 }
 
 /// An extension trait for [`Connector`]s
@@ -165,7 +437,8 @@ pub trait ConnectorExt: Connector + Sized {
 
 impl<C> ConnectorExt for C where C: Connector {}
 
-/// Retry a [`Connector`] using exponential backoff
+/// Retry a [`Connector`] using exponential backoff, logging a warning
+/// every time an attempt fails
 ///
 /// [`Connector`]: self::Connector
 pub struct BackoffConnector<C>
@@ -173,6 +446,7 @@ where
     C: Connector,
 {
     connector: C,
+    backoff: BackoffConnectorBuilder,
 }
 
 impl<C> BackoffConnector<C>
@@ -180,7 +454,106 @@ where
     C: Connector,
 {
     fn new(connector: C) -> Self {
-        Self { connector }
+        BackoffConnectorBuilder::default().build(connector)
+    }
+
+    /// Retry `connector` according to `backoff` instead of the default
+    /// `ExponentialBackoff`, see also [`BackoffConnectorBuilder`]
+    ///
+    /// [`BackoffConnectorBuilder`]: self::BackoffConnectorBuilder
+    pub fn with_backoff(connector: C, backoff: ExponentialBackoff) -> Self {
+        Self {
+            connector,
+            backoff: backoff.into(),
+        }
+    }
+}
+
+/// Builds a [`BackoffConnector`] retrying with the given parameters,
+/// defaulting to those of `backoff::ExponentialBackoff`
+///
+/// [`BackoffConnector`]: self::BackoffConnector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffConnectorBuilder {
+    initial_interval: Duration,
+    randomization_factor: f64,
+    multiplier: f64,
+    max_interval: Duration,
+    max_elapsed_time: Option<Duration>,
+}
+
+impl BackoffConnectorBuilder {
+    /// Wait `interval` after the first failed attempt
+    pub fn initial_interval(mut self, interval: Duration) -> Self {
+        self.initial_interval = interval;
+        self
+    }
+
+    /// Randomize each delay by up to `factor` times its value in either
+    /// direction
+    pub fn randomization_factor(mut self, factor: f64) -> Self {
+        self.randomization_factor = factor;
+        self
+    }
+
+    /// Multiply the delay by `multiplier` after each failed attempt
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Never wait more than `interval` between two attempts
+    pub fn max_interval(mut self, interval: Duration) -> Self {
+        self.max_interval = interval;
+        self
+    }
+
+    /// Give up once `elapsed` passed since the first attempt, or never if
+    /// `None`
+    pub fn max_elapsed_time(mut self, elapsed: Option<Duration>) -> Self {
+        self.max_elapsed_time = elapsed;
+        self
+    }
+
+    /// Wrap `connector` into a [`BackoffConnector`] using these parameters
+    ///
+    /// [`BackoffConnector`]: self::BackoffConnector
+    pub fn build<C: Connector>(self, connector: C) -> BackoffConnector<C> {
+        BackoffConnector {
+            connector,
+            backoff: self,
+        }
+    }
+
+    /// Start a new `ExponentialBackoff` with these parameters
+    fn start(&self) -> ExponentialBackoff {
+        ExponentialBackoff {
+            current_interval: self.initial_interval,
+            initial_interval: self.initial_interval,
+            randomization_factor: self.randomization_factor,
+            multiplier: self.multiplier,
+            max_interval: self.max_interval,
+            max_elapsed_time: self.max_elapsed_time,
+            ..ExponentialBackoff::default()
+        }
+    }
+}
+
+impl Default for BackoffConnectorBuilder {
+    fn default() -> Self {
+        ExponentialBackoff::default().into()
+    }
+}
+
+impl From<ExponentialBackoff> for BackoffConnectorBuilder {
+    fn from(backoff: ExponentialBackoff) -> Self {
+        Self {
+            initial_interval: backoff.initial_interval,
+            randomization_factor: backoff.randomization_factor,
+            multiplier: backoff.multiplier,
+            max_interval: backoff.max_interval,
+            max_elapsed_time: backoff.max_elapsed_time,
+        }
     }
 }
 
@@ -195,18 +568,89 @@ where
         self.connector.exchanger()
     }
 
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.connector.connection_limits()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.connector.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.connector.handshake_mode()
+    }
+
+    fn expects_decision(&self) -> bool {
+        self.connector.expects_decision()
+    }
+
     async fn establish(
         &self,
         pkey: &PublicKey,
         candidate: &Self::Candidate,
     ) -> Result<Box<dyn Socket>, ConnectError> {
-        backoff::future::retry(
-            backoff::ExponentialBackoff::default(),
+        backoff::future::retry_notify(
+            self.backoff.start(),
             || async {
                 let stream = self.connector.establish(pkey, candidate).await?;
                 Ok(stream)
             },
+            |error, delay| {
+                warn!(
+                    target: targets::CONNECTOR,
+                    "failed to connect to {}, retrying in {:?}: {}",
+                    candidate,
+                    delay,
+                    error
+                );
+            },
         )
         .await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::net::{Listener, MemoryListener, MemoryRegistry};
+
+    #[tokio::test]
+    async fn backoff_parameters() {
+        let registry = MemoryRegistry::new();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let addr = (Ipv4Addr::LOCALHOST, 4000).into();
+        let connector = BackoffConnectorBuilder::default()
+            .initial_interval(Duration::from_millis(10))
+            .randomization_factor(0.0)
+            .max_interval(Duration::from_millis(10))
+            .max_elapsed_time(Some(Duration::from_millis(50)))
+            .build(MemoryConnector::new(registry.clone(), Exchanger::random()));
+
+        connector
+            .connect(&public, &addr)
+            .await
+            .expect_err("connected without listener");
+
+        let retried = connector.connect(&public, &addr);
+        let listening = async {
+            time::sleep(Duration::from_millis(20)).await;
+
+            let mut listener =
+                MemoryListener::new(&registry, addr, exchanger.clone())
+                    .expect("bind failed");
+
+            listener.accept().await.expect("accept failed")
+        };
+
+        let (connection, accepted) = futures::join!(retried, listening);
+
+        assert_eq!(
+            connection.expect("retry failed").remote_key(),
+            Some(public)
+        );
+        assert!(accepted.remote_key().is_some());
+    }
+}
@@ -0,0 +1,376 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::Rng;
+
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Instant};
+
+/// Default maximum number of dials in flight for a paced `Connector`, see
+/// `Pacing::recommended`
+pub const DEFAULT_MAX_CONCURRENT_DIALS: usize = 32;
+
+/// Default minimum time between the start of two dials, see
+/// `Pacing::recommended`
+pub const DEFAULT_DIAL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Limits on how fast a [`Pacer`] starts dials
+///
+/// [`Pacer`]: self::Pacer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pacing {
+    max_concurrent: Option<usize>,
+    min_interval: Duration,
+    jitter: Duration,
+}
+
+impl Pacing {
+    /// Allow at most `max_concurrent` dials in flight and start them at least
+    /// `min_interval` apart. A random delay of up to `min_interval` is added
+    /// before each start unless changed using `Pacing::jitter`.
+    pub fn new(max_concurrent: usize, min_interval: Duration) -> Self {
+        Self {
+            max_concurrent: Some(max_concurrent.max(1)),
+            min_interval,
+            jitter: min_interval,
+        }
+    }
+
+    /// Pacing suitable for reconnecting to many peers at once, using
+    /// `DEFAULT_MAX_CONCURRENT_DIALS` and `DEFAULT_DIAL_INTERVAL`
+    pub fn recommended() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT_DIALS, DEFAULT_DIAL_INTERVAL)
+    }
+
+    /// Start all dials right away, which is the default
+    pub fn unlimited() -> Self {
+        Self {
+            max_concurrent: None,
+            min_interval: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Delay each start by a random duration of up to `jitter` so that nodes
+    /// restarting together do not dial in lockstep
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Get the maximum number of dials in flight, if any
+    pub fn max_concurrent(&self) -> Option<usize> {
+        self.max_concurrent
+    }
+
+    /// Get the minimum time between the start of two dials
+    pub fn min_interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    fn spaces(&self) -> bool {
+        !self.min_interval.is_zero() || !self.jitter.is_zero()
+    }
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// Number of dials a [`Pacer`] went through so far
+///
+/// [`Pacer`]: self::Pacer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DialProgress {
+    /// Dials that were started
+    pub dialed: usize,
+    /// Dials that resulted in a `Connection`
+    pub connected: usize,
+    /// Dials that failed
+    pub failed: usize,
+}
+
+impl DialProgress {
+    /// Number of dials that were started and did not finish yet
+    pub fn pending(&self) -> usize {
+        self.dialed - self.connected - self.failed
+    }
+}
+
+/// Schedules dials according to some [`Pacing`]. A `Pacer` is cheap to clone
+/// and all its clones share the same limits, so that it can be shared by all
+/// components dialing peers.
+///
+/// [`Pacing`]: self::Pacing
+#[derive(Clone, Debug)]
+pub struct Pacer {
+    pacing: Pacing,
+    permits: Option<Arc<Semaphore>>,
+    next: Arc<Mutex<Option<Instant>>>,
+    progress: Arc<watch::Sender<DialProgress>>,
+}
+
+impl Pacer {
+    /// Create a `Pacer` enforcing the given `Pacing`
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            permits: pacing
+                .max_concurrent
+                .map(|max| Arc::new(Semaphore::new(max))),
+            next: Default::default(),
+            progress: Arc::new(watch::channel(DialProgress::default()).0),
+        }
+    }
+
+    /// Get the `Pacing` enforced by this `Pacer`
+    pub fn pacing(&self) -> &Pacing {
+        &self.pacing
+    }
+
+    /// Watch the number of dials started and finished by this `Pacer`
+    pub fn progress(&self) -> watch::Receiver<DialProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Run `dial` once a dial is allowed to start, counting whether it
+    /// succeeded in the progress of this `Pacer`
+    pub async fn dial<T, E, F>(&self, dial: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let slot = self.slot().await;
+
+        self.dial_in(slot, dial).await
+    }
+
+    /// Wait until fewer than the maximum number of dials are in flight. The
+    /// returned `DialSlot` counts as an in flight dial until dropped.
+    pub(crate) async fn slot(&self) -> DialSlot {
+        // waiting for a permit before picking a start time keeps starts
+        // spaced out even when dials finish together
+        let permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore closed"),
+            ),
+            None => None,
+        };
+
+        DialSlot { _permit: permit }
+    }
+
+    /// Run `dial` in an already acquired `DialSlot` once the spacing between
+    /// starts allows it
+    pub(crate) async fn dial_in<T, E, F>(
+        &self,
+        _slot: DialSlot,
+        dial: F,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        if self.pacing.spaces() {
+            time::sleep_until(self.reserve()).await;
+        }
+
+        self.progress.send_modify(|p| p.dialed += 1);
+
+        let result = dial.await;
+
+        self.progress.send_modify(|p| match result {
+            Ok(_) => p.connected += 1,
+            Err(_) => p.failed += 1,
+        });
+
+        result
+    }
+
+    /// Reserve the next start time, at least `min_interval` after the
+    /// previous one
+    fn reserve(&self) -> Instant {
+        let jitter = if self.pacing.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=self.pacing.jitter)
+        };
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let start = match *next {
+            Some(next) if next > now => next,
+            _ => now,
+        } + jitter;
+
+        *next = Some(start + self.pacing.min_interval);
+
+        start
+    }
+}
+
+/// One of the dials allowed in flight by a `Pacer`
+pub(crate) struct DialSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new(Pacing::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::crypto::key::exchange::{Exchanger, PublicKey};
+    use crate::net::{ConnectError, Connector, Socket};
+    use crate::test::*;
+
+    /// Time spent by `Slow` establishing each `Socket`
+    const DIAL: Duration = Duration::from_millis(50);
+
+    /// A `Connector` that takes `DIAL` to establish in-memory `Socket`s,
+    /// recording when each dial started and how many were in flight. Every
+    /// fourth dial fails.
+    struct Slow {
+        exchanger: Exchanger,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        starts: Mutex<Vec<Instant>>,
+    }
+
+    impl Slow {
+        fn new() -> Self {
+            Self {
+                exchanger: Exchanger::random(),
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+                starts: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Connector for Slow {
+        type Candidate = SocketAddr;
+
+        fn exchanger(&self) -> &Exchanger {
+            &self.exchanger
+        }
+
+        async fn establish(
+            &self,
+            _: &PublicKey,
+            _: &SocketAddr,
+        ) -> Result<Box<dyn Socket>, ConnectError> {
+            let index = {
+                let mut starts = self.starts.lock().unwrap();
+
+                starts.push(Instant::now());
+                starts.len()
+            };
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            time::sleep(DIAL).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            if index % 4 == 0 {
+                return Err(ConnectError::Io {
+                    source: std::io::ErrorKind::Other.into(),
+                });
+            }
+
+            Ok(Box::new(WireSocket::new(Vec::new()).0))
+        }
+    }
+
+    fn peers(count: usize) -> Vec<(SocketAddr, PublicKey)> {
+        (0..count)
+            .map(|_| {
+                (
+                    (Ipv4Addr::LOCALHOST, 9600).into(),
+                    *Exchanger::random().keypair().public(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_dials_at_once() {
+        let connector = Slow::new();
+        let pacer = Pacer::default();
+        let results = connector.connect_many_paced(&peers(40), &pacer).await;
+
+        assert_eq!(results.len(), 40);
+        assert_eq!(connector.max_in_flight.load(Ordering::SeqCst), 40);
+        assert_eq!(
+            *pacer.progress().borrow(),
+            DialProgress {
+                dialed: 40,
+                connected: 30,
+                failed: 10,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dials_are_paced() {
+        const MAX: usize = 4;
+        const INTERVAL: Duration = Duration::from_millis(10);
+        const JITTER: Duration = Duration::from_millis(5);
+
+        let connector = Slow::new();
+        let pacer = Pacer::new(Pacing::new(MAX, INTERVAL).jitter(JITTER));
+        let mut progress = pacer.progress();
+        let peers = peers(40);
+        let begin = Instant::now();
+        let results = {
+            let dials = connector.connect_many_paced(&peers, &pacer);
+            tokio::pin!(dials);
+
+            // progress is reported while dials go on
+            tokio::select! {
+                _ = &mut dials => panic!("dials finished too early"),
+                _ = progress.wait_for(|p| p.dialed >= MAX && p.pending() > 0)
+                    => {}
+            }
+
+            dials.await
+        };
+
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 10);
+        assert!(connector.max_in_flight.load(Ordering::SeqCst) <= MAX);
+
+        let starts = connector.starts.lock().unwrap();
+
+        assert!(starts[0] - begin <= JITTER, "first dial too late");
+
+        for pair in starts.windows(2) {
+            let gap = pair[1] - pair[0];
+
+            assert!(gap >= INTERVAL, "dials {:?} apart", gap);
+            // dials may also wait for an earlier one to finish
+            assert!(gap <= DIAL + INTERVAL + JITTER, "dials {:?} apart", gap);
+        }
+
+        assert_eq!(
+            *progress.borrow(),
+            DialProgress {
+                dialed: 40,
+                connected: 30,
+                failed: 10,
+            }
+        );
+    }
+}
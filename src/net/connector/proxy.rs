@@ -0,0 +1,716 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use super::super::Socket;
+use super::{ConnectError, Connector, Io, Other, Proxy};
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::{ensure, OptionExt, ResultExt};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use tracing::{debug, info};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_PASSWORD_AUTH: u8 = 2;
+const SOCKS_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+/// Maximum size of the headers of an HTTP CONNECT response
+const MAX_HTTP_HEADERS: usize = 8192;
+
+/// The protocol spoken by a proxy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// A SOCKS version 5 proxy
+    Socks5,
+    /// An HTTP proxy that supports the CONNECT method
+    HttpConnect,
+}
+
+/// Destination of a `Connection` established through a proxy. Hostnames are
+/// sent unresolved to the proxy, so that name resolution happens on the
+/// proxy's side.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProxyTarget {
+    /// A known socket address
+    Addr(SocketAddr),
+    /// A hostname and port that the proxy will resolve
+    Domain(String, u16),
+}
+
+impl From<SocketAddr> for ProxyTarget {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Addr(addr)
+    }
+}
+
+impl FromStr for ProxyTarget {
+    type Err = ConnectError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Self::Addr(addr));
+        }
+
+        let (host, port) = s.rsplit_once(':').context(Other {
+            reason: format!("missing port in {}", s),
+        })?;
+        let port = port.parse().ok().context(Other {
+            reason: format!("invalid port in {}", s),
+        })?;
+
+        ensure!(
+            !host.is_empty() && host.len() <= u8::MAX as usize,
+            Other {
+                reason: format!("invalid hostname in {}", s),
+            }
+        );
+
+        Ok(Self::Domain(host.to_string(), port))
+    }
+}
+
+impl fmt::Display for ProxyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Addr(addr) => write!(f, "{}", addr),
+            Self::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// A [`Connector`] that reaches remote peers through a SOCKS5 or HTTP CONNECT
+/// proxy. The proxy only relays the encrypted stream: the key exchange is
+/// still performed with the remote peer, so the proxy is not trusted.
+/// Hostnames should be given as [`ProxyTarget`]s instead of wrapping this
+/// `Connector` in a [`ResolveConnector`], which would resolve them locally.
+///
+/// [`Connector`]: super::Connector
+/// [`ProxyTarget`]: self::ProxyTarget
+/// [`ResolveConnector`]: super::ResolveConnector
+pub struct ProxiedConnector {
+    exchanger: Exchanger,
+    proxy: SocketAddr,
+    kind: ProxyKind,
+    credentials: Option<(String, String)>,
+}
+
+impl ProxiedConnector {
+    /// Create a new `ProxiedConnector` using the proxy at the given address
+    ///
+    /// # Arguments
+    /// * `exchanger` - The key exchanger to be used when handshaking with
+    ///   remote peers
+    /// * `proxy` - Address of the proxy
+    /// * `kind` - Protocol spoken by the proxy
+    ///
+    /// # Example
+    /// ```
+    /// use drop::crypto::key::exchange::Exchanger;
+    /// use drop::net::{ProxiedConnector, ProxyKind};
+    ///
+    /// let proxy = "127.0.0.1:1080".parse().unwrap();
+    /// let connector =
+    ///     ProxiedConnector::new(Exchanger::random(), proxy, ProxyKind::Socks5)
+    ///         .credentials("user", "password");
+    /// ```
+    pub fn new(
+        exchanger: Exchanger,
+        proxy: SocketAddr,
+        kind: ProxyKind,
+    ) -> Self {
+        Self {
+            exchanger,
+            proxy,
+            kind,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate with the proxy using the given username and password
+    pub fn credentials<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    async fn socks5<S>(
+        &self,
+        stream: &mut S,
+        target: &ProxyTarget,
+    ) -> Result<(), ConnectError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let method = match self.credentials {
+            Some(_) => SOCKS_PASSWORD_AUTH,
+            None => SOCKS_NO_AUTH,
+        };
+
+        stream
+            .write_all(&[SOCKS_VERSION, 1, method])
+            .await
+            .context(Io)?;
+
+        let mut reply = [0u8; 2];
+
+        stream.read_exact(&mut reply).await.context(Io)?;
+        check_version(reply[0], SOCKS_VERSION)?;
+
+        ensure!(
+            reply[1] == method,
+            Proxy {
+                code: reply[1] as u16,
+                reason: "no acceptable authentication method",
+            }
+        );
+
+        if let Some((username, password)) = &self.credentials {
+            let mut request = vec![1];
+
+            push_string(&mut request, username)?;
+            push_string(&mut request, password)?;
+
+            stream.write_all(&request).await.context(Io)?;
+            stream.read_exact(&mut reply).await.context(Io)?;
+            check_version(reply[0], 1)?;
+
+            ensure!(
+                reply[1] == 0,
+                Proxy {
+                    code: reply[1] as u16,
+                    reason: "authentication failed",
+                }
+            );
+        }
+
+        let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+        let port = match target {
+            ProxyTarget::Addr(addr) => {
+                match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        request.push(SOCKS_IPV4);
+                        request.extend_from_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        request.push(SOCKS_IPV6);
+                        request.extend_from_slice(&ip.octets());
+                    }
+                }
+                addr.port()
+            }
+            ProxyTarget::Domain(host, port) => {
+                request.push(SOCKS_DOMAIN);
+                push_string(&mut request, host)?;
+                *port
+            }
+        };
+
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await.context(Io)?;
+
+        let mut reply = [0u8; 4];
+
+        stream.read_exact(&mut reply).await.context(Io)?;
+        check_version(reply[0], SOCKS_VERSION)?;
+
+        ensure!(
+            reply[1] == 0,
+            Proxy {
+                code: reply[1] as u16,
+                reason: socks_reply(reply[1]),
+            }
+        );
+
+        // the address the proxy bound for us is of no use
+        let len = match reply[3] {
+            SOCKS_IPV4 => 4,
+            SOCKS_IPV6 => 16,
+            SOCKS_DOMAIN => stream.read_u8().await.context(Io)? as usize,
+            _ => {
+                return Other {
+                    reason: "invalid address type in proxy reply",
+                }
+                .fail()
+            }
+        };
+        let mut bound = vec![0u8; len + 2];
+
+        stream.read_exact(&mut bound).await.context(Io)?;
+
+        Ok(())
+    }
+
+    async fn http_connect<S>(
+        &self,
+        stream: &mut S,
+        target: &ProxyTarget,
+    ) -> Result<(), ConnectError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = format!(
+            "CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n",
+            target = target
+        );
+
+        if let Some((username, password)) = &self.credentials {
+            let token = base64(format!("{}:{}", username, password).as_bytes());
+
+            request
+                .push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.context(Io)?;
+
+        // read one byte at a time to leave the tunneled data in the stream
+        let mut response = Vec::new();
+
+        while !response.ends_with(b"\r\n\r\n") {
+            ensure!(
+                response.len() < MAX_HTTP_HEADERS,
+                Other {
+                    reason: "proxy response is too large",
+                }
+            );
+
+            response.push(stream.read_u8().await.context(Io)?);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+        let code = status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .context(Other {
+                reason: format!("invalid proxy response: {}", status),
+            })?;
+
+        ensure!(
+            (200..300).contains(&code),
+            Proxy {
+                code,
+                reason: "proxy refused CONNECT request",
+            }
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Connector for ProxiedConnector {
+    /// This `Connector` can use hostnames that are resolved by the proxy
+    type Candidate = ProxyTarget;
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    /// Open a `Socket` to the specified destination through the proxy.
+    /// The addresses of the returned `Socket` are those of the connection
+    /// with the proxy.
+    async fn establish(
+        &self,
+        _: &PublicKey,
+        candidate: &Self::Candidate,
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        info!(
+            target: targets::CONNECTOR,
+            "establishing connection to {} through {}",
+            candidate, self.proxy
+        );
+
+        let mut stream = TcpStream::connect(self.proxy).await.context(Io)?;
+
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5(&mut stream, candidate).await?,
+            ProxyKind::HttpConnect => {
+                self.http_connect(&mut stream, candidate).await?
+            }
+        }
+
+        debug!(
+            target: targets::CONNECTOR,
+            "proxy {} tunneled connection to {}", self.proxy, candidate
+        );
+
+        Ok(Box::new(stream))
+    }
+}
+
+fn check_version(version: u8, expected: u8) -> Result<(), ConnectError> {
+    ensure!(
+        version == expected,
+        Other {
+            reason: format!("unexpected proxy protocol version {}", version),
+        }
+    );
+
+    Ok(())
+}
+
+fn push_string(buf: &mut Vec<u8>, s: &str) -> Result<(), ConnectError> {
+    let len: u8 = s.len().try_into().ok().context(Other {
+        reason: "string too long for proxy protocol",
+    })?;
+
+    buf.push(len);
+    buf.extend_from_slice(s.as_bytes());
+
+    Ok(())
+}
+
+fn socks_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "ttl expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        SOCKS_NO_ACCEPTABLE => "no acceptable authentication method",
+        _ => "unknown error",
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(
+                    ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char,
+                );
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::{Listener, TcpListener};
+    use crate::test::next_test_ip4;
+
+    use tokio::io;
+    use tokio::net::TcpListener as TokioListener;
+    use tokio::task;
+
+    /// A minimal SOCKS5 server, optionally requiring the given credentials
+    async fn socks5_server(
+        credentials: Option<(&'static str, &'static str)>,
+    ) -> SocketAddr {
+        let listener = TokioListener::bind(next_test_ip4())
+            .await
+            .expect("proxy bind failed");
+        let addr = listener.local_addr().unwrap();
+
+        task::spawn(async move {
+            loop {
+                let (stream, _) =
+                    listener.accept().await.expect("accept failed");
+
+                task::spawn(socks5_session(stream, credentials));
+            }
+        });
+
+        addr
+    }
+
+    async fn socks5_session(
+        mut stream: TcpStream,
+        credentials: Option<(&'static str, &'static str)>,
+    ) -> io::Result<()> {
+        let mut header = [0u8; 2];
+
+        stream.read_exact(&mut header).await?;
+
+        let mut methods = vec![0u8; header[1] as usize];
+
+        stream.read_exact(&mut methods).await?;
+
+        let method = match credentials {
+            Some(_) => SOCKS_PASSWORD_AUTH,
+            None => SOCKS_NO_AUTH,
+        };
+
+        if !methods.contains(&method) {
+            return stream
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE])
+                .await;
+        }
+
+        stream.write_all(&[SOCKS_VERSION, method]).await?;
+
+        if let Some((username, password)) = credentials {
+            let _version = stream.read_u8().await?;
+            let user = read_string(&mut stream).await?;
+            let pass = read_string(&mut stream).await?;
+            let status = (user != username || pass != password) as u8;
+
+            stream.write_all(&[1, status]).await?;
+
+            if status != 0 {
+                return Ok(());
+            }
+        }
+
+        let mut request = [0u8; 4];
+
+        stream.read_exact(&mut request).await?;
+
+        let host = match request[3] {
+            SOCKS_IPV4 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await?;
+                IpAddr::from(ip).to_string()
+            }
+            SOCKS_DOMAIN => read_string(&mut stream).await?,
+            _ => unreachable!("unsupported address type in test"),
+        };
+        let port = stream.read_u16().await?;
+
+        match TcpStream::connect((host.as_str(), port)).await {
+            Ok(mut target) => {
+                stream
+                    .write_all(&[
+                        SOCKS_VERSION,
+                        0,
+                        0,
+                        SOCKS_IPV4,
+                        0,
+                        0,
+                        0,
+                        0,
+                        0,
+                        0,
+                    ])
+                    .await?;
+                io::copy_bidirectional(&mut stream, &mut target).await?;
+            }
+            Err(_) => {
+                stream
+                    .write_all(&[
+                        SOCKS_VERSION,
+                        5,
+                        0,
+                        SOCKS_IPV4,
+                        0,
+                        0,
+                        0,
+                        0,
+                        0,
+                        0,
+                    ])
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn read_string(stream: &mut TcpStream) -> io::Result<String> {
+        let len = stream.read_u8().await?;
+        let mut buf = vec![0u8; len as usize];
+
+        stream.read_exact(&mut buf).await?;
+
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    async fn proxied_exchange(
+        connector: ProxiedConnector,
+        target: impl Fn(SocketAddr) -> ProxyTarget,
+    ) {
+        let server = Exchanger::random();
+        let public = *server.keypair().public();
+        let mut listener = TcpListener::new(next_test_ip4(), server)
+            .await
+            .expect("listen failed");
+        let addr = listener.local_addr().unwrap();
+
+        let handle = task::spawn(async move {
+            let mut connection =
+                listener.accept().await.expect("accept failed");
+
+            connection.receive::<u32>().await.expect("receive failed")
+        });
+
+        let mut connection = connector
+            .connect(&public, &target(addr))
+            .await
+            .expect("connect failed");
+
+        assert!(connection.is_secured(), "connection is not secured");
+
+        connection.send(&42u32).await.expect("send failed");
+
+        assert_eq!(handle.await.expect("listener failure"), 42);
+    }
+
+    #[tokio::test]
+    async fn socks5_connect() {
+        let proxy = socks5_server(None).await;
+        let connector = ProxiedConnector::new(
+            Exchanger::random(),
+            proxy,
+            ProxyKind::Socks5,
+        );
+
+        proxied_exchange(connector, ProxyTarget::from).await;
+    }
+
+    #[tokio::test]
+    async fn socks5_hostname() {
+        let proxy = socks5_server(Some(("user", "pass"))).await;
+        let connector = ProxiedConnector::new(
+            Exchanger::random(),
+            proxy,
+            ProxyKind::Socks5,
+        )
+        .credentials("user", "pass");
+
+        proxied_exchange(connector, |addr| {
+            format!("localhost:{}", addr.port())
+                .parse()
+                .expect("invalid target")
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn socks5_auth_failure() {
+        let proxy = socks5_server(Some(("user", "pass"))).await;
+        let connector = ProxiedConnector::new(
+            Exchanger::random(),
+            proxy,
+            ProxyKind::Socks5,
+        )
+        .credentials("user", "wrong");
+        let target = next_test_ip4().into();
+
+        let err = connector
+            .connect(Exchanger::random().keypair().public(), &target)
+            .await
+            .expect_err("authenticated with wrong password");
+
+        assert!(
+            matches!(err, ConnectError::Proxy { code: 1, .. }),
+            "wrong error: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn socks5_refused_target() {
+        let proxy = socks5_server(None).await;
+        let connector = ProxiedConnector::new(
+            Exchanger::random(),
+            proxy,
+            ProxyKind::Socks5,
+        );
+        let target = next_test_ip4().into();
+
+        let err = connector
+            .connect(Exchanger::random().keypair().public(), &target)
+            .await
+            .expect_err("connected to closed port");
+
+        assert!(
+            matches!(err, ConnectError::Proxy { code: 5, .. }),
+            "wrong error: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn http_connect() {
+        let listener = TokioListener::bind(next_test_ip4())
+            .await
+            .expect("proxy bind failed");
+        let proxy = listener.local_addr().unwrap();
+
+        task::spawn(async move {
+            let (mut stream, _) =
+                listener.accept().await.expect("accept failed");
+            let mut request = Vec::new();
+
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.expect("read failed"));
+            }
+
+            let request = String::from_utf8(request).expect("invalid request");
+            let target = request
+                .split_whitespace()
+                .nth(1)
+                .expect("no target")
+                .to_string();
+
+            assert!(
+                request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"),
+                "missing credentials"
+            );
+
+            let mut target =
+                TcpStream::connect(target).await.expect("connect failed");
+
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .expect("write failed");
+
+            let _ = io::copy_bidirectional(&mut stream, &mut target).await;
+        });
+
+        let connector = ProxiedConnector::new(
+            Exchanger::random(),
+            proxy,
+            ProxyKind::HttpConnect,
+        )
+        .credentials("user", "pass");
+
+        proxied_exchange(connector, ProxyTarget::from).await;
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+}
@@ -0,0 +1,175 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::super::socket::quic::{self, QuicSocket, SERVER_NAME};
+use super::*;
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use quinn::Endpoint;
+
+use tracing::info;
+
+/// A [`Connector`] using QUIC. Every `Connection` uses its own stream on a
+/// fresh QUIC connection and is still secured by the usual handshake since
+/// certificates presented by listeners are self-signed.
+///
+/// [`Connector`]: super::Connector
+pub struct QuicConnector {
+    exchanger: Exchanger,
+}
+
+impl QuicConnector {
+    /// Create a new [`Connector`] using QUIC
+    ///
+    /// [`Connector`]: super::Connector
+    pub fn new(exchanger: Exchanger) -> Self {
+        Self { exchanger }
+    }
+}
+
+#[async_trait]
+impl Connector for QuicConnector {
+    type Candidate = SocketAddr;
+
+    async fn establish(
+        &self,
+        _: &PublicKey,
+        candidate: &Self::Candidate,
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        let local: SocketAddr = match *candidate {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let mut endpoint = Endpoint::client(local).context(Io)?;
+
+        endpoint.set_default_client_config(quic::client_config().context(Io)?);
+
+        info!(
+            target: targets::CONNECTOR,
+            "connecting {} -> {} using QUIC",
+            endpoint.local_addr().context(Io)?,
+            candidate
+        );
+
+        let connection = endpoint
+            .connect(*candidate, SERVER_NAME)
+            .map_err(|e| IoError::new(ErrorKind::InvalidInput, e))
+            .context(Io)?
+            .await
+            .map_err(IoError::other)
+            .context(Io)?;
+        let (send, recv) = connection
+            .open_bi()
+            .await
+            .map_err(IoError::other)
+            .context(Io)?;
+
+        info!(
+            target: targets::CONNECTOR,
+            "connection to {} established", candidate
+        );
+
+        let socket =
+            QuicSocket::new(&endpoint, connection, send, recv).context(Io)?;
+
+        Ok(Box::new(socket))
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Connection;
+    use super::*;
+    use crate::net::{HandshakeGuard, HandshakeLimits, Listener, QuicListener};
+    use crate::test::*;
+    use crate::{exchange_data_and_compare, generate_connection};
+
+    async fn setup_quic() -> (Connection, Connection) {
+        generate_connection!(QuicListener, QuicConnector);
+    }
+
+    #[tokio::test]
+    async fn quic_u64_exchange() {
+        exchange_data_and_compare!(0u64, u64, setup_quic);
+    }
+
+    #[tokio::test]
+    async fn quic_string_exchange() {
+        exchange_data_and_compare!(String::from("quic"), String, setup_quic);
+    }
+
+    #[tokio::test]
+    async fn quic_addresses() {
+        let (client, listener) = setup_quic().await;
+
+        assert_eq!(
+            client.peer_addr().expect("no peer address"),
+            listener.local_addr().expect("no local address")
+        );
+        assert_eq!(
+            listener.peer_addr().expect("no peer address").port(),
+            client.local_addr().expect("no local address").port()
+        );
+    }
+
+    #[tokio::test]
+    async fn quic_stalled_peer() {
+        use std::time::Duration;
+
+        use tokio::time;
+
+        let timeout = Duration::from_secs(2);
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let guard =
+            HandshakeGuard::new(HandshakeLimits::default().timeout(timeout));
+        let mut listener =
+            QuicListener::with_guard(addr, exchanger.clone(), guard)
+                .await
+                .expect("listen failed");
+
+        // completes the QUIC handshake but never opens a stream
+        let mut stalled = Endpoint::client((Ipv4Addr::LOCALHOST, 0).into())
+            .expect("bind failed");
+
+        stalled.set_default_client_config(
+            quic::client_config().expect("no client config"),
+        );
+
+        let stalled = stalled
+            .connect(addr, SERVER_NAME)
+            .expect("connect failed")
+            .await
+            .expect("quic handshake failed");
+
+        let public = *exchanger.keypair().public();
+        let connecting = tokio::spawn(async move {
+            QuicConnector::new(Exchanger::random())
+                .connect(&public, &addr)
+                .await
+                .expect("connect failed")
+        });
+
+        let mut accepted = time::timeout(timeout / 2, listener.accept())
+            .await
+            .expect("stalled peer delayed accept")
+            .expect("accept failed");
+        let mut connected = connecting.await.expect("connector failed");
+
+        connected.send(&7u32).await.expect("send failed");
+
+        assert_eq!(accepted.receive::<u32>().await.expect("recv failed"), 7);
+
+        time::timeout(timeout * 5, stalled.closed())
+            .await
+            .expect("stalled connection not dropped");
+    }
+}
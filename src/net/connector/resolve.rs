@@ -65,6 +65,22 @@ where
     fn exchanger(&self) -> &Exchanger {
         self.connector.exchanger()
     }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.connector.connection_limits()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.connector.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.connector.handshake_mode()
+    }
+
+    fn expects_decision(&self) -> bool {
+        self.connector.expects_decision()
+    }
 }
 
 #[cfg(test)]
@@ -85,7 +101,8 @@ mod test {
             .await
             .expect("listen failed");
 
-        let connector = ResolveConnector::new(TcpConnector::new(Exchanger::random()));
+        let connector =
+            ResolveConnector::new(TcpConnector::new(Exchanger::random()));
 
         let handle = task::spawn(async move {
             listener.accept().await.expect("accept failed");
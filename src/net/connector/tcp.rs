@@ -1,20 +1,29 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use super::super::Socket;
-use super::{ConnectError, Connector, Io};
+use super::super::{
+    ConnectionLimits, ConnectionObserver, HandshakeMode, Socket,
+};
+use super::{ConnectError, Connector, Io, Other};
 use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
 
 use async_trait::async_trait;
 
 use snafu::ResultExt;
 
-use tokio::net::TcpStream;
+use tokio::net::{TcpSocket, TcpStream};
 
 use tracing::info;
 
 /// A `Connector` that uses direct TCP connections to a remote peer
 pub struct TcpConnector {
     exchanger: Exchanger,
+    limits: Option<Arc<ConnectionLimits>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    handshake: HandshakeMode,
+    decision: bool,
+    bind: Option<SocketAddr>,
 }
 
 impl TcpConnector {
@@ -23,9 +32,86 @@ impl TcpConnector {
     ///
     /// # Arguments
     /// * `exchanger` - The key exchanger to be used when handshaking with
-    /// remote peers
+    ///   remote peers
     pub fn new(exchanger: Exchanger) -> Self {
-        Self { exchanger }
+        Self {
+            exchanger,
+            limits: None,
+            observer: None,
+            handshake: HandshakeMode::Plain,
+            decision: false,
+            bind: None,
+        }
+    }
+
+    /// Create a new `TcpConnector` whose outgoing connections are made from
+    /// the given local address instead of one picked by the OS
+    ///
+    /// # Arguments
+    /// * `exchanger` - The key exchanger to be used when handshaking with
+    ///   remote peers
+    /// * `local_addr` - The local address every `Connection` is bound to,
+    ///   use port 0 to let the OS pick a port on the chosen interface
+    pub fn new_with_bind(exchanger: Exchanger, local_addr: SocketAddr) -> Self {
+        Self {
+            bind: Some(local_addr),
+            ..Self::new(exchanger)
+        }
+    }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` opened by
+    /// this `TcpConnector`
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Notify `observer` of every `Connection` opened by this `TcpConnector`
+    /// instead of the process-wide `ConnectionObserver`
+    pub fn with_observer<O: ConnectionObserver + 'static>(
+        mut self,
+        observer: O,
+    ) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Secure every `Connection` opened by this `TcpConnector` using
+    /// `HandshakeMode::Private`
+    pub fn private_handshake(mut self) -> Self {
+        self.handshake = HandshakeMode::Private;
+        self
+    }
+
+    /// Wait for the `Decision` of the remote `Authorizer` after the
+    /// handshake, see `Connector::expects_decision`
+    pub fn expect_decision(mut self) -> Self {
+        self.decision = true;
+        self
+    }
+
+    /// Connect to `candidate` from a socket bound to `local`
+    async fn connect_from(
+        local: SocketAddr,
+        candidate: &SocketAddr,
+    ) -> Result<TcpStream, ConnectError> {
+        let socket = match (local, candidate) {
+            (SocketAddr::V4(_), SocketAddr::V4(_)) => TcpSocket::new_v4(),
+            (SocketAddr::V6(_), SocketAddr::V6(_)) => TcpSocket::new_v6(),
+            _ => {
+                return Other {
+                    reason: format!(
+                        "cannot reach {} from {}: address families differ",
+                        candidate, local
+                    ),
+                }
+                .fail()
+            }
+        }
+        .context(Io)?;
+
+        socket.bind(local).context(Io)?;
+        socket.connect(*candidate).await.context(Io)
     }
 }
 
@@ -39,16 +125,38 @@ impl Connector for TcpConnector {
         &self.exchanger
     }
 
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.limits.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.handshake
+    }
+
+    fn expects_decision(&self) -> bool {
+        self.decision
+    }
+
     /// Open a `Socket` to the specified destination using TCP
     async fn establish(
         &self,
         _: &PublicKey,
         candidate: &Self::Candidate,
     ) -> Result<Box<dyn Socket>, ConnectError> {
-        info!("establishing tcp connection to {}", candidate);
+        info!(
+            target: targets::CONNECTOR,
+            "establishing tcp connection to {}", candidate
+        );
 
-        let stream: Box<dyn Socket> =
-            Box::new(TcpStream::connect(candidate).await.context(Io)?);
+        let stream = match self.bind {
+            Some(local) => Self::connect_from(local, candidate).await?,
+            None => TcpStream::connect(candidate).await.context(Io)?,
+        };
+        let stream: Box<dyn Socket> = Box::new(stream);
 
         Ok(stream)
     }
@@ -67,6 +175,8 @@ mod test {
 
     use futures::future;
 
+    use std::time::Duration;
+
     use tokio::task;
 
     const NR_CONN: usize = 10;
@@ -189,9 +299,10 @@ mod test {
         assert_eq!(
             format!("{:?}", client),
             format!(
-                "secure connection {} -> {}",
+                "secure connection {} -> {} with {}",
                 client.socket.local_addr().unwrap(),
-                client.socket.peer_addr().unwrap()
+                client.socket.peer_addr().unwrap(),
+                client.remote_key().unwrap().fingerprint()
             )
         );
     }
@@ -208,6 +319,60 @@ mod test {
             .expect_err("connected to non-existent listener");
     }
 
+    #[tokio::test]
+    async fn tcp_bind_local_addr() {
+        use std::net::Ipv4Addr;
+
+        let srv = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(srv, exchanger)
+            .await
+            .expect("listen failed");
+        let local: SocketAddr = (Ipv4Addr::new(127, 0, 0, 2), 0).into();
+        let connector = TcpConnector::new_with_bind(Exchanger::random(), local);
+
+        let handle = task::spawn(async move {
+            listener.accept().await.expect("accept failed").peer_addr()
+        });
+
+        let connection = connector
+            .connect(&public, &srv)
+            .await
+            .expect("connect failed");
+        let bound = connection.local_addr().expect("no local address");
+
+        assert_eq!(bound.ip(), local.ip(), "wrong local interface");
+        assert_eq!(
+            handle
+                .await
+                .expect("listener failed")
+                .expect("no peer address"),
+            bound,
+            "listener saw a different address"
+        );
+    }
+
+    #[tokio::test]
+    async fn tcp_bind_family_mismatch() {
+        use std::net::Ipv6Addr;
+
+        let local: SocketAddr = (Ipv6Addr::LOCALHOST, 0).into();
+        let connector = TcpConnector::new_with_bind(Exchanger::random(), local);
+        let exchanger = Exchanger::random();
+
+        let error = connector
+            .connect(exchanger.keypair().public(), &next_test_ip4())
+            .await
+            .expect_err("connected across address families");
+
+        assert!(
+            matches!(error, ConnectError::Other { .. }),
+            "wrong error {}",
+            error
+        );
+    }
+
     #[tokio::test]
     async fn corrupted_connection() {
         let srv = next_test_ip4();
@@ -270,7 +435,7 @@ mod test {
             .expect("connect failed");
 
         socket
-            .write(&[0u8; std::mem::size_of::<PublicKey>()])
+            .write_all(&[0u8; std::mem::size_of::<PublicKey>()])
             .await
             .expect("write failed");
 
@@ -319,7 +484,7 @@ mod test {
 
         let connector = TcpConnector::new(Exchanger::random());
         let mut connection = connector
-            .connect_any(exchanger.keypair().public(), addrs.as_slice())
+            .connect_any(exchanger.keypair().public(), addrs.as_slice(), None)
             .await
             .expect("connect failed");
 
@@ -370,7 +535,7 @@ mod test {
             .collect::<Vec<_>>();
 
         let connections: Result<Vec<Connection>, ConnectError> = connector
-            .connect_many(candidates.as_slice())
+            .connect_many(candidates.as_slice(), None)
             .await
             .into_iter()
             .collect();
@@ -388,4 +553,95 @@ mod test {
 
         handle.await.expect("listeners failed");
     }
+
+    /// Bind a listener that accepts `Connection`s but never answers the
+    /// handshake
+    async fn stalled_listener() -> SocketAddr {
+        let addr = next_test_ip4();
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .expect("bind failed");
+
+        task::spawn(async move {
+            let mut sockets = Vec::new();
+
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn connect_timeout() {
+        init_logger();
+        let timeout = Duration::from_millis(100);
+        let stalled = stalled_listener().await;
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed")
+            .private_handshake();
+
+        let handle = task::spawn(async move {
+            for _ in 0..2 {
+                listener.accept().await.expect("accept failed");
+            }
+        });
+
+        let connector =
+            TcpConnector::new(Exchanger::random()).private_handshake();
+
+        assert!(matches!(
+            connector.connect_with_timeout(&pkey, &stalled, timeout).await,
+            Err(ConnectError::Timeout { after }) if after == timeout
+        ));
+        assert!(matches!(
+            connector
+                .connect_any(&pkey, &[stalled, stalled], Some(timeout))
+                .await,
+            Err(ConnectError::Timeout { .. })
+        ));
+
+        connector
+            .connect_any(&pkey, &[stalled, addr], Some(timeout))
+            .await
+            .expect("connect failed");
+
+        let results = connector
+            .connect_many(&[(stalled, pkey), (addr, pkey)], Some(timeout))
+            .await;
+
+        assert!(matches!(results[0], Err(ConnectError::Timeout { .. })));
+        assert!(results[1].is_ok(), "connect failed");
+
+        handle.await.expect("listener failed");
+    }
+
+    #[tokio::test]
+    async fn early_data() {
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+
+        let handle = task::spawn(async move {
+            let mut connection =
+                listener.accept().await.expect("accept failed");
+
+            connection.receive_frame().await.expect("receive failed")
+        });
+
+        let _connection = TcpConnector::new(Exchanger::random())
+            .connect_with_early_data(&pkey, &addr, Some(b"early"))
+            .await
+            .expect("connect failed");
+
+        assert_eq!(handle.await.unwrap(), b"early", "wrong early data");
+    }
 }
@@ -0,0 +1,333 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use super::super::socket::udp::UdpSocket;
+use super::super::{
+    ConnectionLimits, ConnectionObserver, HandshakeMode, Socket,
+};
+use super::{ConnectError, Connector, Io};
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tokio::net::UdpSocket as TokioSocket;
+
+use tracing::info;
+
+/// A `Connector` that sends each frame to a remote peer as a single UDP
+/// datagram. Frames that do not fit in one datagram once encrypted are
+/// rejected with `SendError::TooLargeForDatagram`, larger messages can be sent
+/// by enabling `Fragmentation` in the `ConnectionLimits`.
+pub struct UdpConnector {
+    exchanger: Exchanger,
+    limits: Option<Arc<ConnectionLimits>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    handshake: HandshakeMode,
+    decision: bool,
+}
+
+impl UdpConnector {
+    /// Create a new `UdpConnector` using the given `Exchanger` to compute
+    /// shared secrets
+    ///
+    /// # Arguments
+    /// * `exchanger` - The key exchanger to be used when handshaking with
+    ///   remote peers
+    pub fn new(exchanger: Exchanger) -> Self {
+        Self {
+            exchanger,
+            limits: None,
+            observer: None,
+            handshake: HandshakeMode::Plain,
+            decision: false,
+        }
+    }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` opened by
+    /// this `UdpConnector`
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Notify `observer` of every `Connection` opened by this `UdpConnector`
+    /// instead of the process-wide `ConnectionObserver`
+    pub fn with_observer<O: ConnectionObserver + 'static>(
+        mut self,
+        observer: O,
+    ) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Secure every `Connection` opened by this `UdpConnector` using
+    /// `HandshakeMode::Private`
+    pub fn private_handshake(mut self) -> Self {
+        self.handshake = HandshakeMode::Private;
+        self
+    }
+
+    /// Wait for the `Decision` of the remote `Authorizer` after the
+    /// handshake, see `Connector::expects_decision`
+    pub fn expect_decision(mut self) -> Self {
+        self.decision = true;
+        self
+    }
+}
+
+#[async_trait]
+impl Connector for UdpConnector {
+    /// This `Connector` uses a pair of `IpAddr` and port as destination
+    type Candidate = SocketAddr;
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.limits.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.handshake
+    }
+
+    fn expects_decision(&self) -> bool {
+        self.decision
+    }
+
+    /// Bind an ephemeral UDP port and connect it to the specified destination
+    async fn establish(
+        &self,
+        _: &PublicKey,
+        candidate: &Self::Candidate,
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        info!(
+            target: targets::CONNECTOR,
+            "establishing udp connection to {}", candidate
+        );
+
+        let local: SocketAddr = if candidate.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = TokioSocket::bind(local).await.context(Io)?;
+
+        socket.connect(candidate).await.context(Io)?;
+
+        let socket: Box<dyn Socket> =
+            Box::new(UdpSocket::connected(socket).context(Io)?);
+
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Connection;
+    use super::*;
+    use crate::net::socket::udp::PEER_BACKLOG;
+    use crate::net::{
+        DatagramOverflow, Listener, ReceiveError, SendError, UdpListener,
+        MAX_DATAGRAM_SIZE,
+    };
+    use crate::test::*;
+    use crate::{exchange_data_and_compare, generate_connection};
+
+    use serde::{Deserialize, Serialize};
+
+    pub async fn setup_udp() -> (Connection, Connection) {
+        generate_connection!(UdpListener, UdpConnector);
+    }
+
+    #[tokio::test]
+    async fn udp_u8_exchange() {
+        exchange_data_and_compare!(0, u8, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_u16_exchange() {
+        exchange_data_and_compare!(0, u16, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_u32_exchange() {
+        exchange_data_and_compare!(0, u32, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_u64_exchange() {
+        exchange_data_and_compare!(0, u64, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_i8_exchange() {
+        exchange_data_and_compare!(0, i8, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_i16_exchange() {
+        exchange_data_and_compare!(0, i16, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_i32_exchange() {
+        exchange_data_and_compare!(0, i32, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_i64_exchange() {
+        exchange_data_and_compare!(0, i64, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_struct_exchange() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct T {
+            a: u32,
+            b: u64,
+            c: A,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct A {
+            a: u8,
+            b: u16,
+        }
+
+        let data = T {
+            a: 258,
+            b: 30567,
+            c: A { a: 66, b: 245 },
+        };
+
+        exchange_data_and_compare!(data, T, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_hashmap_exchange() {
+        use std::collections::HashMap;
+
+        let mut hashmap: HashMap<u32, u128> = HashMap::default();
+
+        for _ in 0..rand::random::<usize>() % 2048 {
+            hashmap.insert(rand::random(), rand::random());
+        }
+
+        exchange_data_and_compare!(hashmap, HashMap<u32, u128>, setup_udp);
+    }
+
+    #[tokio::test]
+    async fn udp_too_large_for_datagram() {
+        let (mut client, _listener) = setup_udp().await;
+
+        let error = client
+            .send(&vec![0u8; MAX_DATAGRAM_SIZE])
+            .await
+            .expect_err("sent more than one datagram");
+
+        assert!(
+            matches!(
+                error,
+                SendError::TooLargeForDatagram { max, .. }
+                    if max == MAX_DATAGRAM_SIZE
+            ),
+            "wrong error {}",
+            error
+        );
+
+        assert!(client.is_broken(), "connection still usable");
+    }
+
+    #[tokio::test]
+    async fn udp_close_ends_stream() {
+        let (mut client, mut listener) = setup_udp().await;
+
+        client.close().await.expect("close failed");
+
+        let error = listener
+            .receive::<u32>()
+            .await
+            .expect_err("received after close");
+
+        assert!(
+            matches!(error, ReceiveError::Closed),
+            "wrong error {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn udp_overflow_closes_stream() {
+        let (mut client, mut listener) = setup_udp().await;
+
+        for i in 0..PEER_BACKLOG as u32 * 2 {
+            client.send(&i).await.expect("send failed");
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let error = listener
+            .receive::<u32>()
+            .await
+            .expect_err("received from overflowed peer");
+
+        assert!(
+            matches!(
+                &error,
+                ReceiveError::ReceiveIo { source }
+                    if DatagramOverflow::from_io(source).is_some()
+            ),
+            "wrong error {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn udp_many_peers() {
+        let exchanger = Exchanger::random();
+        let addr = next_test_ip4();
+        let mut listener = UdpListener::new(addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+
+        let handle = tokio::spawn(async move {
+            let mut connections = Vec::new();
+
+            for _ in 0..2 {
+                connections.push(listener.accept().await.expect("accept"));
+            }
+
+            for mut connection in connections {
+                let value = connection.receive::<u32>().await.expect("recv");
+
+                connection.send(&(value + 1)).await.expect("send failed");
+            }
+        });
+
+        let mut first = UdpConnector::new(Exchanger::random())
+            .connect(exchanger.keypair().public(), &addr)
+            .await
+            .expect("connect failed");
+        let mut second = UdpConnector::new(Exchanger::random())
+            .connect(exchanger.keypair().public(), &addr)
+            .await
+            .expect("connect failed");
+
+        first.send(&1u32).await.expect("send failed");
+        second.send(&2u32).await.expect("send failed");
+
+        assert_eq!(first.receive::<u32>().await.expect("recv failed"), 2);
+        assert_eq!(second.receive::<u32>().await.expect("recv failed"), 3);
+
+        handle.await.expect("listener failed");
+    }
+}
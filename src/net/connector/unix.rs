@@ -0,0 +1,188 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::super::socket::unix::UnixSocket;
+use super::super::Socket;
+use super::{ConnectError, Connector, Io};
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tokio::net::UnixStream;
+
+use tracing::info;
+
+/// Filesystem path of a Unix domain socket, used as the `Candidate` of
+/// `UnixConnector` and `UnixListener`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnixPath(PathBuf);
+
+impl UnixPath {
+    /// Get the filesystem path of the socket
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl From<PathBuf> for UnixPath {
+    fn from(path: PathBuf) -> Self {
+        Self(path)
+    }
+}
+
+impl From<&Path> for UnixPath {
+    fn from(path: &Path) -> Self {
+        Self(path.to_path_buf())
+    }
+}
+
+impl AsRef<Path> for UnixPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Display for UnixPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+/// A `Connector` that reaches peers on the same host through Unix domain
+/// sockets
+pub struct UnixConnector {
+    exchanger: Exchanger,
+}
+
+impl UnixConnector {
+    /// Create a new `UnixConnector` using the given `Exchanger` to compute
+    /// shared secrets
+    pub fn new(exchanger: Exchanger) -> Self {
+        Self { exchanger }
+    }
+}
+
+#[async_trait]
+impl Connector for UnixConnector {
+    /// This `Connector` uses the path a `UnixListener` is bound to
+    type Candidate = UnixPath;
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    /// Open a `Socket` to the Unix domain socket at `candidate`
+    async fn establish(
+        &self,
+        _: &PublicKey,
+        candidate: &Self::Candidate,
+    ) -> Result<Box<dyn Socket>, ConnectError> {
+        info!(
+            target: targets::CONNECTOR,
+            "establishing unix connection to {}", candidate
+        );
+
+        let stream = UnixStream::connect(candidate).await.context(Io)?;
+
+        Ok(Box::new(UnixSocket::new(stream)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::Connection;
+    use super::*;
+    use crate::crypto::key::exchange::KeyPair;
+    use crate::exchange_data_and_compare;
+    use crate::net::{Listener, UnixListener};
+    use crate::test::*;
+
+    use serde::{Deserialize, Serialize};
+
+    use tokio::task;
+
+    fn next_test_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "drop-unix-{}-{}",
+            std::process::id(),
+            next_test_port()
+        ))
+    }
+
+    async fn setup_unix() -> (Connection, Connection) {
+        let server = KeyPair::random();
+        let path = next_test_path();
+        let mut listener =
+            UnixListener::new(&path, Exchanger::new(server.clone()))
+                .expect("listen failed");
+        let connector = UnixConnector::new(Exchanger::random());
+
+        let handle = task::spawn(async move {
+            listener.accept().await.expect("failed to accept")
+        });
+
+        let outgoing = connector
+            .connect(server.public(), &path.into())
+            .await
+            .expect("failed to connect");
+        let incoming = handle.await.expect("task failure");
+
+        assert!(incoming.is_secured(), "server couldn't secure connection");
+        assert!(outgoing.is_secured(), "client couldn't secure connection");
+
+        (outgoing, incoming)
+    }
+
+    #[tokio::test]
+    async fn unix_u8_exchange() {
+        exchange_data_and_compare!(0, u8, setup_unix);
+    }
+
+    #[tokio::test]
+    async fn unix_u64_exchange() {
+        exchange_data_and_compare!(0, u64, setup_unix);
+    }
+
+    #[tokio::test]
+    async fn unix_string_exchange() {
+        exchange_data_and_compare!(String::from("unix"), String, setup_unix);
+    }
+
+    #[tokio::test]
+    async fn unix_struct_exchange() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+        struct T {
+            a: u32,
+            b: u64,
+            c: Vec<u8>,
+        }
+
+        let data = T {
+            a: 1,
+            b: 2,
+            c: vec![3; 1024],
+        };
+
+        exchange_data_and_compare!(data, T, setup_unix);
+    }
+
+    #[tokio::test]
+    async fn unix_listener_removes_path() {
+        let path = next_test_path();
+        let listener =
+            UnixListener::new(&path, Exchanger::random()).expect("bind failed");
+
+        assert_eq!(
+            listener.candidates().await.expect("no candidates"),
+            vec![UnixPath::from(path.clone())]
+        );
+        assert!(path.exists(), "socket file not created");
+
+        drop(listener);
+
+        assert!(!path.exists(), "socket file not removed");
+    }
+}
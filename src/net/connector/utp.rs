@@ -3,6 +3,7 @@ use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use super::super::socket::utp::BufferedUtpStream;
 use super::*;
 use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
 
 use async_trait::async_trait;
 
@@ -46,6 +47,7 @@ impl Connector for UtpConnector {
         let socket = UtpSocket::bind(local).await.context(Io)?;
 
         info!(
+            target: targets::CONNECTOR,
             "connecting {} -> {} using uTp",
             socket.local_addr(),
             candidate
@@ -53,9 +55,14 @@ impl Connector for UtpConnector {
 
         let (stream, driver) = socket.connect(*candidate).await.context(Io)?;
 
-        info!("connection to {} established", candidate);
+        info!(
+            target: targets::CONNECTOR,
+            "connection to {} established", candidate
+        );
 
-        task::spawn(driver.instrument(debug_span!("stream_driver")));
+        task::spawn(driver.instrument(
+            debug_span!(target: targets::CONNECTOR, "stream_driver"),
+        ));
 
         Ok(Box::new(BufferedUtpStream::new(stream)))
     }
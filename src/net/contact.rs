@@ -0,0 +1,641 @@
+use std::fmt;
+use std::net::{AddrParseError, SocketAddr};
+use std::num::ParseIntError;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{ConnectError, Connection, Connector, Listener, ListenerError};
+use crate::crypto::key::exchange::{KeyPair, PublicKey};
+use crate::crypto::sign::{SignError, Signature, VerifyError};
+use crate::persist::{self, PersistError, Versioned};
+use crate::telemetry::targets;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+use tracing::debug;
+
+/// Scheme used by the URI encoding of a [`ContactCard`]
+///
+/// [`ContactCard`]: self::ContactCard
+pub const CONTACT_SCHEME: &str = "drop://";
+
+#[derive(Debug, Snafu)]
+/// Errors encountered when creating, parsing or using a [`ContactCard`]
+///
+/// [`ContactCard`]: self::ContactCard
+pub enum ContactError {
+    #[snafu(display("contact card does not start with {}", CONTACT_SCHEME))]
+    /// The URI does not use the `drop://` scheme
+    Scheme,
+
+    #[snafu(display("missing '@' between public key and candidates"))]
+    /// The URI does not separate the key from the candidates
+    MissingKey,
+
+    #[snafu(display("invalid base58 in {}: {}", field, source))]
+    /// A base58 field of the URI could not be decoded
+    Base58 {
+        /// Name of the invalid field
+        field: &'static str,
+        /// Underlying error cause
+        source: bs58::decode::Error,
+    },
+
+    #[snafu(display("public key is {} bytes long instead of 32", len))]
+    /// The decoded public key has the wrong length
+    KeyLength {
+        /// Length of the decoded key
+        len: usize,
+    },
+
+    #[snafu(display("invalid candidate {}: {}", addr, source))]
+    /// A candidate is not a valid socket address
+    Candidate {
+        /// The invalid candidate
+        addr: String,
+        /// Underlying error cause
+        source: AddrParseError,
+    },
+
+    #[snafu(display("candidate {} uses port 0", addr))]
+    /// A candidate can not be dialed because it uses port 0
+    ZeroPort {
+        /// The invalid candidate
+        addr: SocketAddr,
+    },
+
+    #[snafu(display("contact card has no candidate"))]
+    /// The card does not contain any address to dial
+    NoCandidates,
+
+    #[snafu(display("unknown or repeated parameter {}", param))]
+    /// The URI contains a parameter that is not understood
+    Parameter {
+        /// The offending parameter
+        param: String,
+    },
+
+    #[snafu(display("invalid value for {}: {}", param, source))]
+    /// A numeric parameter could not be parsed
+    Number {
+        /// Name of the parameter
+        param: &'static str,
+        /// Underlying error cause
+        source: ParseIntError,
+    },
+
+    #[snafu(display("invalid signature: {}", source))]
+    /// The signature of the card is malformed or does not match its content
+    InvalidSignature {
+        /// Underlying error cause
+        source: VerifyError,
+    },
+
+    #[snafu(display("contact card is not signed"))]
+    /// Attempted to verify a card that has no signature
+    Unsigned,
+
+    #[snafu(display("failed to sign contact card: {}", source))]
+    /// The card could not be signed
+    Sign {
+        /// Underlying error cause
+        source: SignError,
+    },
+
+    #[snafu(display("signing key does not match the key of the card"))]
+    /// Attempted to sign a card using another key than the one it contains
+    ForeignKey,
+
+    #[snafu(display("contact card has expired"))]
+    /// The card is past its expiry date
+    Expired,
+
+    #[snafu(display("could not list candidates: {}", source))]
+    /// The `Listener` used to build the card has no candidates
+    Candidates {
+        /// Underlying error cause
+        source: ListenerError,
+    },
+
+    #[snafu(display("no listener to build a contact card from"))]
+    /// No `Listener` is known to provide the key and candidates of a card
+    NoIdentity,
+
+    #[snafu(display("no candidate reachable: {}", source))]
+    /// None of the candidates of the card could be connected to
+    Unreachable {
+        /// Error from the last candidate that was tried
+        source: ConnectError,
+    },
+}
+
+/// Everything needed to reach a peer out of band: its `PublicKey`, an ordered
+/// list of candidate addresses and an optional expiry and protocol version.
+/// A `ContactCard` can be signed by the key it contains so that tampering in
+/// transit can be detected. <br />
+/// Cards are exchanged as URIs such as
+/// `drop://<base58 key>@192.0.2.1:4000,[2001:db8::1]:4000?v=1&exp=<seconds>`
+/// where `exp` is a UNIX timestamp, followed by `&sig=<base58 signature>` if
+/// the card is signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContactCard {
+    pkey: PublicKey,
+    candidates: Vec<SocketAddr>,
+    version: Option<u32>,
+    expiry: Option<u64>,
+    signature: Option<Signature>,
+}
+
+impl ContactCard {
+    /// Create a new unsigned `ContactCard` for the given key and candidates
+    pub fn new(pkey: PublicKey, candidates: Vec<SocketAddr>) -> Self {
+        Self {
+            pkey,
+            candidates,
+            version: None,
+            expiry: None,
+            signature: None,
+        }
+    }
+
+    /// Create a new unsigned `ContactCard` using the key and candidates of a
+    /// [`Listener`] followed by the given external addresses. Unspecified
+    /// addresses and duplicates are left out.
+    ///
+    /// [`Listener`]: super::Listener
+    pub async fn from_listener<L>(
+        listener: &L,
+        external: &[SocketAddr],
+    ) -> Result<Self, ContactError>
+    where
+        L: Listener<Candidate = SocketAddr> + ?Sized,
+    {
+        let pkey = *listener.exchanger().keypair().public();
+        let listened = listener.candidates().await.context(Candidates)?;
+        let mut candidates: Vec<SocketAddr> = Vec::new();
+
+        for addr in listened.into_iter().chain(external.iter().copied()) {
+            if dialable(&addr) && !candidates.contains(&addr) {
+                candidates.push(addr);
+            }
+        }
+
+        Ok(Self::new(pkey, candidates))
+    }
+
+    /// Set the protocol version advertised by this card. This removes any
+    /// existing signature.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self.signature = None;
+        self
+    }
+
+    /// Set the time after which this card should not be used anymore. This
+    /// removes any existing signature.
+    pub fn with_expiry(mut self, expiry: SystemTime) -> Self {
+        let expiry = expiry
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.expiry = Some(expiry);
+        self.signature = None;
+        self
+    }
+
+    /// Sign this card using the `KeyPair` matching the key it contains
+    pub fn sign(mut self, keypair: &KeyPair) -> Result<Self, ContactError> {
+        ensure!(*keypair.public() == self.pkey, ForeignKey);
+
+        self.signature = Some(keypair.sign(&self.content()).context(Sign)?);
+
+        Ok(self)
+    }
+
+    /// Verify that this card was signed by the key it contains. This does not
+    /// check for expiry, see `is_expired`.
+    pub fn verify(&self) -> Result<(), ContactError> {
+        let signature = self.signature.as_ref().context(Unsigned)?;
+
+        self.pkey
+            .verify(&self.content(), signature)
+            .context(InvalidSignature)
+    }
+
+    /// Check that this card can be used to reach its peer: it must have at
+    /// least one candidate, must not be expired and must carry a valid
+    /// signature if it is signed
+    pub fn check(&self) -> Result<(), ContactError> {
+        ensure!(!self.candidates.is_empty(), NoCandidates);
+        ensure!(!self.is_expired(), Expired);
+
+        if self.is_signed() {
+            self.verify()?;
+        }
+
+        Ok(())
+    }
+
+    /// Export `cards` to `path` so that they can be imported by another
+    /// node, see `persist::save_versioned`
+    pub fn export<P: AsRef<Path>>(
+        path: P,
+        cards: &[ContactCard],
+    ) -> Result<(), PersistError> {
+        persist::save_versioned(path, &cards.to_vec())
+    }
+
+    /// Import the cards exported to `path` using `export`. Cards are not
+    /// checked, see `check`.
+    pub fn import<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<Vec<ContactCard>, PersistError> {
+        persist::load_versioned(path)
+    }
+
+    /// Connect to the peer described by this card after checking it. The
+    /// candidates are tried in order until one of them can be connected to.
+    /// The key exchange ensures that only the holder of the key of the card
+    /// can use the returned `Connection`.
+    pub async fn connect<C>(
+        &self,
+        connector: &C,
+    ) -> Result<Connection, ContactError>
+    where
+        C: Connector<Candidate = SocketAddr>,
+    {
+        self.check()?;
+
+        let mut last = None;
+
+        for candidate in self.candidates.iter() {
+            match connector.connect(&self.pkey, candidate).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    debug!(
+                        target: targets::CONNECTOR,
+                        "{} unreachable at {}: {}", self.pkey, candidate, e
+                    );
+                    last = Some(e);
+                }
+            }
+        }
+
+        Err(last.expect("checked cards have candidates")).context(Unreachable)
+    }
+
+    /// Get the `PublicKey` of the peer described by this card
+    pub fn public(&self) -> &PublicKey {
+        &self.pkey
+    }
+
+    /// Get the candidates of this card in the order they should be tried
+    pub fn candidates(&self) -> &[SocketAddr] {
+        &self.candidates
+    }
+
+    /// Get the protocol version advertised by this card, if any
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Get the time after which this card should not be used, if any
+    pub fn expiry(&self) -> Option<SystemTime> {
+        self.expiry
+            .map(|expiry| UNIX_EPOCH + Duration::from_secs(expiry))
+    }
+
+    /// Check if this card has expired
+    pub fn is_expired(&self) -> bool {
+        self.expiry().is_some_and(|e| e <= SystemTime::now())
+    }
+
+    /// Check if this card carries a signature
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// The content covered by the signature of this card
+    fn content(&self) -> (&PublicKey, &[SocketAddr], Option<u32>, Option<u64>) {
+        (&self.pkey, &self.candidates, self.version, self.expiry)
+    }
+}
+
+/// Check whether `addr` can be used as a candidate
+fn dialable(addr: &SocketAddr) -> bool {
+    addr.port() != 0 && !addr.ip().is_unspecified()
+}
+
+impl fmt::Display for ContactCard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}@",
+            CONTACT_SCHEME,
+            bs58::encode(self.pkey.as_ref()).into_string()
+        )?;
+
+        for (i, addr) in self.candidates.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", addr)?;
+        }
+
+        let mut separator = '?';
+        let mut param =
+            |f: &mut fmt::Formatter, name, value: &dyn fmt::Display| {
+                let result = write!(f, "{}{}={}", separator, name, value);
+
+                separator = '&';
+                result
+            };
+
+        if let Some(version) = self.version {
+            param(f, "v", &version)?;
+        }
+
+        if let Some(expiry) = self.expiry {
+            param(f, "exp", &expiry)?;
+        }
+
+        if let Some(signature) = self.signature {
+            let signature = bs58::encode(signature.to_bytes()).into_string();
+
+            param(f, "sig", &signature)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ContactCard {
+    type Err = ContactError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s.strip_prefix(CONTACT_SCHEME).context(Scheme)?;
+        let (key, rest) = rest.split_once('@').context(MissingKey)?;
+        let (candidates, params) = match rest.split_once('?') {
+            Some((candidates, params)) => (candidates, Some(params)),
+            None => (rest, None),
+        };
+
+        let key = bs58::decode(key)
+            .into_vec()
+            .context(Base58 { field: "key" })?;
+        let key: [u8; 32] = key
+            .as_slice()
+            .try_into()
+            .ok()
+            .context(KeyLength { len: key.len() })?;
+        let mut card =
+            Self::new(crypto_kx::PublicKey::from(key).into(), Vec::new());
+
+        for addr in candidates.split(',').filter(|x| !x.is_empty()) {
+            let addr: SocketAddr = addr.parse().context(Candidate { addr })?;
+
+            ensure!(addr.port() != 0, ZeroPort { addr });
+
+            card.candidates.push(addr);
+        }
+
+        ensure!(!card.candidates.is_empty(), NoCandidates);
+
+        for param in params.into_iter().flat_map(|p| p.split('&')) {
+            match param.split_once('=') {
+                Some(("v", value)) if card.version.is_none() => {
+                    card.version =
+                        Some(value.parse().context(Number { param: "v" })?);
+                }
+                Some(("exp", value)) if card.expiry.is_none() => {
+                    card.expiry =
+                        Some(value.parse().context(Number { param: "exp" })?);
+                }
+                Some(("sig", value)) if card.signature.is_none() => {
+                    let bytes = bs58::decode(value)
+                        .into_vec()
+                        .context(Base58 { field: "sig" })?;
+
+                    card.signature = Some(
+                        Signature::from_bytes(&bytes)
+                            .context(InvalidSignature)?,
+                    );
+                }
+                _ => return Parameter { param }.fail(),
+            }
+        }
+
+        Ok(card)
+    }
+}
+
+/// A list of `ContactCard`s, as exported to share known peers
+impl Versioned for Vec<ContactCard> {
+    const MAGIC: [u8; 8] = *b"drop-pex";
+
+    const CURRENT_VERSION: u16 = 1;
+}
+
+impl Serialize for ContactCard {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContactCard {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::{TcpConnector, TcpListener};
+    use crate::test::next_test_ip4;
+
+    use tokio::task;
+
+    fn card(keypair: &KeyPair) -> ContactCard {
+        ContactCard::new(
+            *keypair.public(),
+            vec![
+                (Ipv4Addr::new(192, 0, 2, 1), 4000).into(),
+                (Ipv6Addr::LOCALHOST, 4001).into(),
+            ],
+        )
+        .with_version(1)
+        .with_expiry(SystemTime::now() + Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn uri_round_trip() {
+        let keypair = KeyPair::random();
+        let unsigned = card(&keypair);
+        let signed = unsigned.clone().sign(&keypair).expect("sign failed");
+        let minimal = ContactCard::new(
+            *keypair.public(),
+            vec![(Ipv4Addr::LOCALHOST, 1).into()],
+        );
+
+        for card in [unsigned, signed, minimal] {
+            let uri = card.to_string();
+
+            assert!(uri.starts_with(CONTACT_SCHEME), "wrong scheme");
+            assert_eq!(uri.parse::<ContactCard>().unwrap(), card, "{}", uri);
+
+            let bytes = bincode::serialize(&card).expect("serialize failed");
+            let decoded: ContactCard =
+                bincode::deserialize(&bytes).expect("deserialize failed");
+
+            assert_eq!(decoded, card, "serde round trip failed");
+        }
+    }
+
+    #[test]
+    fn strict_parsing() {
+        let keypair = KeyPair::random();
+        let uri = card(&keypair).to_string();
+        let (prefix, _) = uri.split_once('?').unwrap();
+        let key = bs58::encode(keypair.public().as_ref()).into_string();
+
+        let check = |uri: &str, valid: fn(&ContactError) -> bool| {
+            let err = uri.parse::<ContactCard>().expect_err(uri);
+
+            assert!(valid(&err), "unexpected error for {}: {}", uri, err);
+        };
+
+        check(&uri.replacen("drop", "http", 1), |e| {
+            matches!(e, ContactError::Scheme)
+        });
+        check(&format!("drop://{}", key), |e| {
+            matches!(e, ContactError::MissingKey)
+        });
+        check("drop://0OIl@127.0.0.1:1", |e| {
+            matches!(e, ContactError::Base58 { .. })
+        });
+        check("drop://abc@127.0.0.1:1", |e| {
+            matches!(e, ContactError::KeyLength { .. })
+        });
+        check(&format!("drop://{}@127.0.0.1:0", key), |e| {
+            matches!(e, ContactError::ZeroPort { .. })
+        });
+        check(&format!("drop://{}@localhost:80", key), |e| {
+            matches!(e, ContactError::Candidate { .. })
+        });
+        check(&format!("drop://{}@", key), |e| {
+            matches!(e, ContactError::NoCandidates)
+        });
+        check(&format!("{}?v=1&v=2", prefix), |e| {
+            matches!(e, ContactError::Parameter { .. })
+        });
+        check(&format!("{}?ttl=1", prefix), |e| {
+            matches!(e, ContactError::Parameter { .. })
+        });
+        check(&format!("{}?exp=soon", prefix), |e| {
+            matches!(e, ContactError::Number { .. })
+        });
+    }
+
+    #[test]
+    fn signature() {
+        let keypair = KeyPair::random();
+        let signed = card(&keypair).sign(&keypair).expect("sign failed");
+
+        signed.verify().expect("valid card rejected");
+        signed.check().expect("valid card unusable");
+
+        card(&keypair)
+            .sign(&KeyPair::random())
+            .expect_err("signed with foreign key");
+        card(&keypair).verify().expect_err("unsigned card verified");
+
+        let tampered = signed
+            .to_string()
+            .replace("192.0.2.1:4000", "192.0.2.66:4000")
+            .parse::<ContactCard>()
+            .expect("parse failed");
+
+        assert!(matches!(
+            tampered.check(),
+            Err(ContactError::InvalidSignature { .. })
+        ));
+
+        let stale = card(&keypair)
+            .with_expiry(UNIX_EPOCH)
+            .sign(&keypair)
+            .expect("sign failed");
+
+        assert!(matches!(stale.check(), Err(ContactError::Expired)));
+    }
+
+    #[tokio::test]
+    async fn from_listener() {
+        let exchanger = Exchanger::random();
+        let listener = TcpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed");
+        let local = listener.local_addr().unwrap();
+        let external: SocketAddr = (Ipv4Addr::new(192, 0, 2, 1), 80).into();
+        let card = ContactCard::from_listener(
+            &listener,
+            &[external, local, (Ipv4Addr::UNSPECIFIED, 80).into()],
+        )
+        .await
+        .expect("no card");
+
+        assert_eq!(card.public(), exchanger.keypair().public());
+        assert_eq!(card.candidates(), [local, external]);
+    }
+
+    #[tokio::test]
+    async fn connect_in_order() {
+        let exchanger = Exchanger::random();
+        let mut listener = TcpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed");
+        let live = listener.local_addr().unwrap();
+        let dead = next_test_ip4();
+        let card =
+            ContactCard::new(*exchanger.keypair().public(), vec![dead, live]);
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let handle = task::spawn(async move {
+            let mut connection =
+                listener.accept().await.expect("accept failed");
+
+            connection.receive::<u32>().await.expect("receive failed")
+        });
+
+        let mut connection =
+            card.connect(&connector).await.expect("connect failed");
+
+        assert_eq!(connection.peer_addr().unwrap(), live, "wrong candidate");
+
+        connection.send(&7u32).await.expect("send failed");
+
+        assert_eq!(handle.await.unwrap(), 7, "wrong message");
+
+        let unreachable =
+            ContactCard::new(*exchanger.keypair().public(), vec![dead]);
+
+        assert!(matches!(
+            unreachable.connect(&connector).await,
+            Err(ContactError::Unreachable { .. })
+        ));
+    }
+}
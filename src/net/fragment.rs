@@ -0,0 +1,555 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt};
+use tokio::time::{Duration, Instant};
+
+use super::{
+    BadFragment, ConnectionLimits, DeserializeReceive, FrameTooLarge,
+    OversizedReceive, PaddingPolicy, ReceiveError, SendError,
+};
+use crate::codec;
+
+/// Default maximum size of a message reassembled from fragments
+pub const DEFAULT_MAX_REASSEMBLED_SIZE: usize = 256 * 1024 * 1024;
+
+/// Default time allowed to receive all fragments of a message
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of messages that may be reassembled at once on a single
+/// `Connection`
+pub const DEFAULT_MAX_REASSEMBLIES: usize = 4;
+
+/// Number of discarded messages whose late fragments are silently dropped
+const DISCARDED: usize = 16;
+
+/// Encoded size of the largest `Envelope`
+const ENVELOPE_SIZE: usize = 4 + 8 + 4 + 4;
+
+/// Transparent splitting of messages larger than the frame limit of a
+/// `Connection` into fragments that are reassembled by the remote peer. <br />
+/// Every frame then carries a small header telling whether it holds a whole
+/// message or a fragment, so both ends of a `Connection` must enable it: there
+/// is no negotiation and a peer that does not expect fragments fails to
+/// decode anything sent by one that does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fragmentation {
+    max_message_size: usize,
+    timeout: Duration,
+    max_reassemblies: usize,
+}
+
+impl Fragmentation {
+    /// Refuse to send or reassemble messages larger than `size` bytes once
+    /// serialized
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Discard messages whose fragments were not all received within
+    /// `timeout` of the first one
+    pub fn reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reassemble at most `count` messages at once, the remote peer is
+    /// misbehaving if it interleaves more
+    pub fn max_reassemblies(mut self, count: usize) -> Self {
+        self.max_reassemblies = count.max(1);
+        self
+    }
+
+    /// Get the maximum size of a reassembled message
+    pub fn message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Get the time allowed to receive all fragments of a message
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get the maximum number of messages reassembled at once
+    pub fn reassemblies(&self) -> usize {
+        self.max_reassemblies
+    }
+}
+
+impl Default for Fragmentation {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_REASSEMBLED_SIZE,
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            max_reassemblies: DEFAULT_MAX_REASSEMBLIES,
+        }
+    }
+}
+
+/// Header of every frame sent on a `Connection` using `Fragmentation`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Envelope {
+    /// The rest of the frame is a whole message
+    Whole,
+    /// The rest of the frame is one fragment of a larger message
+    Fragment {
+        /// Identifier of the message, unique on each `Connection`
+        id: u64,
+        /// Position of this fragment in the message
+        index: u32,
+        /// Number of fragments the message was split into
+        total: u32,
+    },
+}
+
+enum Kind {
+    /// Sent as is on a `Connection` without `Fragmentation`
+    Plain,
+    /// Sent in one frame after an `Envelope::Whole`
+    Whole,
+    /// Split in fragments of at most `chunk` bytes
+    Fragmented { id: u64, chunk: usize, total: u32 },
+}
+
+/// A serialized message being sent one frame at a time
+pub(crate) struct Outgoing<'a> {
+    data: Cow<'a, [u8]>,
+    kind: Kind,
+    next: u32,
+    done: bool,
+}
+
+impl<'a> Outgoing<'a> {
+    /// Prepare `data` to be sent on a `Connection` using `limits`, assigning
+    /// it the identifier in `next_id` if it needs to be fragmented.
+    /// Fragments are small enough to fit the maximum message size once
+    /// padded using `padding`.
+    pub(crate) fn new(
+        data: Cow<'a, [u8]>,
+        limits: &ConnectionLimits,
+        padding: &PaddingPolicy,
+        next_id: &mut u64,
+    ) -> Result<Self, SendError> {
+        let size = data.len();
+        let kind = match limits.fragmentation() {
+            None => {
+                ensure!(size <= limits.message_size(), FrameTooLarge { size });
+
+                Kind::Plain
+            }
+            Some(fragmentation) => {
+                let chunk = padding
+                    .max_unpadded(limits.message_size())
+                    .context(FrameTooLarge { size })?
+                    .saturating_sub(ENVELOPE_SIZE)
+                    .max(1);
+
+                ensure!(
+                    size <= fragmentation.message_size(),
+                    FrameTooLarge { size }
+                );
+
+                if size <= chunk {
+                    Kind::Whole
+                } else {
+                    let total = u32::try_from(size.div_ceil(chunk))
+                        .ok()
+                        .context(FrameTooLarge { size })?;
+                    let id = *next_id;
+
+                    *next_id += 1;
+
+                    Kind::Fragmented { id, chunk, total }
+                }
+            }
+        };
+
+        Ok(Self {
+            data,
+            kind,
+            next: 0,
+            done: false,
+        })
+    }
+
+    /// Check whether this message is sent in more than one frame
+    #[cfg(any(test, feature = "system"))]
+    pub(crate) fn is_fragmented(&self) -> bool {
+        matches!(self.kind, Kind::Fragmented { .. })
+    }
+
+    /// Check whether every frame of this message was produced
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Produce the plaintext of the next frame, if any
+    pub(crate) fn next_frame(&mut self) -> Option<Cow<'_, [u8]>> {
+        if self.done {
+            return None;
+        }
+
+        let index = self.next;
+
+        self.next += 1;
+
+        match self.kind {
+            Kind::Plain => {
+                self.done = true;
+
+                Some(Cow::Borrowed(&self.data))
+            }
+            Kind::Whole => {
+                self.done = true;
+
+                Some(Cow::Owned(enveloped(Envelope::Whole, &self.data)))
+            }
+            Kind::Fragmented { id, chunk, total } => {
+                let start = index as usize * chunk;
+                let end = (start + chunk).min(self.data.len());
+                let envelope = Envelope::Fragment { id, index, total };
+
+                self.done = self.next == total;
+
+                Some(Cow::Owned(enveloped(envelope, &self.data[start..end])))
+            }
+        }
+    }
+}
+
+/// Prepend `envelope` to `payload`
+fn enveloped(envelope: Envelope, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ENVELOPE_SIZE + payload.len());
+
+    codec::serialize_into(&mut frame, &envelope)
+        .expect("envelopes always serialize");
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+/// A message some fragments of which were received
+struct Partial {
+    data: Vec<u8>,
+    next: u32,
+    total: u32,
+    deadline: Instant,
+}
+
+/// Reassembles messages from the fragments received on a `Connection`
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    partial: HashMap<u64, Partial>,
+    /// Recently discarded messages, whose late fragments are ignored
+    discarded: VecDeque<u64>,
+}
+
+impl Reassembler {
+    /// Handle the decrypted content of a frame, returning the message it
+    /// completes if any
+    pub(crate) fn push(
+        &mut self,
+        plaintext: &[u8],
+        fragmentation: &Fragmentation,
+    ) -> Result<Option<Vec<u8>>, ReceiveError> {
+        let (envelope, offset) =
+            codec::deserialize_prefix::<Envelope>(plaintext)
+                .context(DeserializeReceive)?;
+        let payload = &plaintext[offset..];
+
+        let (id, index, total) = match envelope {
+            Envelope::Whole => return Ok(Some(payload.to_vec())),
+            Envelope::Fragment { id, index, total } => (id, index, total),
+        };
+
+        if self.discarded.contains(&id) {
+            return Ok(None);
+        }
+
+        if index == 0 {
+            ensure!(
+                total > 1 && !self.partial.contains_key(&id),
+                BadFragment {
+                    id,
+                    reason: "invalid first fragment",
+                }
+            );
+            ensure!(
+                self.partial.len() < fragmentation.reassemblies(),
+                BadFragment {
+                    id,
+                    reason: "too many messages reassembled at once",
+                }
+            );
+
+            self.partial.insert(
+                id,
+                Partial {
+                    data: Vec::new(),
+                    next: 0,
+                    total,
+                    deadline: Instant::now() + fragmentation.timeout(),
+                },
+            );
+        }
+
+        let partial = self.partial.get_mut(&id).context(BadFragment {
+            id,
+            reason: "fragment of an unknown message",
+        })?;
+
+        ensure!(
+            index == partial.next && total == partial.total,
+            BadFragment {
+                id,
+                reason: "fragment out of order",
+            }
+        );
+
+        let size = partial.data.len() + payload.len();
+
+        ensure!(
+            size <= fragmentation.message_size(),
+            OversizedReceive {
+                size,
+                max: fragmentation.message_size(),
+            }
+        );
+
+        partial.data.extend_from_slice(payload);
+        partial.next += 1;
+
+        if partial.next < partial.total {
+            return Ok(None);
+        }
+
+        Ok(self.partial.remove(&id).map(|partial| partial.data))
+    }
+
+    /// Get the time by which the oldest incomplete message expires, if any
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.partial.values().map(|partial| partial.deadline).min()
+    }
+
+    /// Discard the oldest incomplete message if it expired, returning its
+    /// identifier
+    pub(crate) fn expire(&mut self, now: Instant) -> Option<u64> {
+        let id = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| partial.deadline <= now)
+            .min_by_key(|(_, partial)| partial.deadline)
+            .map(|(id, _)| *id)?;
+
+        self.partial.remove(&id);
+
+        if self.discarded.len() == DISCARDED {
+            self.discarded.pop_front();
+        }
+
+        self.discarded.push_back(id);
+
+        Some(id)
+    }
+
+    /// Number of messages currently being reassembled
+    #[cfg(test)]
+    pub(crate) fn pending(&self) -> usize {
+        self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits(frame: usize) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .max_message_size(frame)
+            .fragment_messages(Fragmentation::default().max_reassemblies(2))
+    }
+
+    fn frames(outgoing: &mut Outgoing) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+
+        while let Some(frame) = outgoing.next_frame() {
+            frames.push(frame.into_owned());
+        }
+
+        frames
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let limits = limits(64);
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let mut next_id = 0;
+        let mut outgoing = Outgoing::new(
+            Cow::Borrowed(&data),
+            &limits,
+            &PaddingPolicy::None,
+            &mut next_id,
+        )
+        .expect("fragmenting failed");
+        let frames = frames(&mut outgoing);
+        let mut reassembler = Reassembler::default();
+        let fragmentation = limits.fragmentation().unwrap();
+
+        assert!(outgoing.is_fragmented() && outgoing.is_done());
+        assert_eq!(next_id, 1);
+        assert!(frames.iter().all(|frame| frame.len() <= 64));
+
+        let (last, first) = frames.split_last().unwrap();
+
+        for frame in first {
+            assert_eq!(reassembler.push(frame, fragmentation).unwrap(), None);
+        }
+
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.push(last, fragmentation).unwrap(), Some(data));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn padded_fragments_fit() {
+        let frame = limits(200);
+        let padding = PaddingPolicy::PadToMultiple(64);
+        let data = vec![5; 1000];
+        let mut next_id = 0;
+        let mut outgoing =
+            Outgoing::new(Cow::Borrowed(&data), &frame, &padding, &mut next_id)
+                .expect("fragmenting failed");
+
+        for frame in frames(&mut outgoing) {
+            assert!(padding.padded_len(frame.len()) <= 200, "frame too large");
+        }
+
+        assert!(matches!(
+            Outgoing::new(
+                Cow::Borrowed(&data),
+                &limits(32),
+                &padding,
+                &mut next_id
+            ),
+            Err(SendError::FrameTooLarge { size: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn small_messages_whole() {
+        let limits = limits(64);
+        let mut next_id = 0;
+        let mut outgoing = Outgoing::new(
+            Cow::Borrowed(&[1, 2, 3]),
+            &limits,
+            &PaddingPolicy::None,
+            &mut next_id,
+        )
+        .expect("enveloping failed");
+        let frames = frames(&mut outgoing);
+
+        assert_eq!(next_id, 0);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            Reassembler::default()
+                .push(&frames[0], limits.fragmentation().unwrap())
+                .unwrap(),
+            Some(vec![1, 2, 3])
+        );
+
+        let plain = ConnectionLimits::default().max_message_size(2);
+
+        assert!(matches!(
+            Outgoing::new(
+                Cow::Borrowed(&[1, 2, 3]),
+                &plain,
+                &PaddingPolicy::None,
+                &mut next_id
+            ),
+            Err(SendError::FrameTooLarge { size: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn reject_misbehaviour() {
+        let limits = limits(32);
+        let fragmentation = limits.fragmentation().unwrap();
+        let data = vec![7; 100];
+        let mut next_id = 0;
+        let messages = (0..3)
+            .map(|_| {
+                let mut outgoing = Outgoing::new(
+                    Cow::Borrowed(&data),
+                    &limits,
+                    &PaddingPolicy::None,
+                    &mut next_id,
+                )
+                .unwrap();
+
+                frames(&mut outgoing)
+            })
+            .collect::<Vec<_>>();
+        let mut reassembler = Reassembler::default();
+
+        reassembler.push(&messages[0][0], fragmentation).unwrap();
+
+        assert!(matches!(
+            reassembler.push(&messages[0][2], fragmentation),
+            Err(ReceiveError::BadFragment { id: 0, .. })
+        ));
+
+        reassembler.push(&messages[1][0], fragmentation).unwrap();
+
+        assert!(matches!(
+            reassembler.push(&messages[2][0], fragmentation),
+            Err(ReceiveError::BadFragment { id: 2, .. })
+        ));
+
+        let small = fragmentation.clone().max_message_size(50);
+        let mut reassembler = Reassembler::default();
+        let oversized = messages[2]
+            .iter()
+            .map(|frame| reassembler.push(frame, &small))
+            .find_map(Result::err);
+
+        assert!(matches!(
+            oversized,
+            Some(ReceiveError::OversizedReceive { max: 50, .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expire_incomplete() {
+        let limits = limits(32);
+        let fragmentation = limits.fragmentation().unwrap();
+        let mut next_id = 0;
+        let mut outgoing = Outgoing::new(
+            Cow::Owned(vec![1; 100]),
+            &limits,
+            &PaddingPolicy::None,
+            &mut next_id,
+        )
+        .unwrap();
+        let frames = frames(&mut outgoing);
+        let mut reassembler = Reassembler::default();
+
+        reassembler.push(&frames[0], fragmentation).unwrap();
+
+        assert_eq!(reassembler.expire(Instant::now()), None);
+
+        tokio::time::advance(DEFAULT_REASSEMBLY_TIMEOUT).await;
+
+        assert_eq!(reassembler.deadline(), Some(Instant::now()));
+        assert_eq!(reassembler.expire(Instant::now()), Some(0));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(
+            reassembler.push(&frames[1], fragmentation).unwrap(),
+            None,
+            "late fragment not ignored"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    io::{Error as IoError, ErrorKind},
+    mem,
+};
+
+use bincode::Options;
+use snafu::{ensure, ResultExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug_span;
+use tracing_futures::Instrument;
+
+use super::{
+    Closed, DeserializeReceive, OversizedReceive, ReceiveError, ReceiveIo,
+};
+use crate::codec::bincode_options;
+use crate::telemetry::targets;
+
+/// Number of bytes used to encode the size of a frame
+const SIZE: usize = mem::size_of::<u32>();
+
+/// Reads size prefixed frames from a `Socket`. Progress on the current frame
+/// is kept between calls so that reading can be cancelled and resumed later
+/// without losing any data.
+#[derive(Default)]
+pub(crate) struct FrameReader {
+    size: [u8; SIZE],
+    /// Number of bytes of the current frame read so far, including its size
+    read: usize,
+    /// Whether the current frame has been read entirely
+    complete: bool,
+    data: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Read the next frame from `socket`, its content is then available using
+    /// `FrameReader::data`. Cancelling this call before it completes does
+    /// not lose any data as long as the next call uses the same `socket`.
+    #[cfg(test)]
+    pub(crate) async fn read<R: AsyncRead + Unpin + ?Sized>(
+        &mut self,
+        socket: &mut R,
+    ) -> Result<(), ReceiveError> {
+        self.read_bounded(socket, u32::MAX as usize).await
+    }
+
+    /// Read the next frame from `socket`, failing before allocating any
+    /// memory for it if its announced size is larger than `max` bytes
+    pub(crate) async fn read_bounded<R: AsyncRead + Unpin + ?Sized>(
+        &mut self,
+        socket: &mut R,
+        max: usize,
+    ) -> Result<(), ReceiveError> {
+        if self.complete {
+            self.read = 0;
+            self.complete = false;
+        }
+
+        while self.read < SIZE {
+            let read = socket
+                .read(&mut self.size[self.read..])
+                .instrument(
+                    debug_span!(target: targets::CONNECTION, "read_size"),
+                )
+                .await
+                .context(ReceiveIo)?;
+
+            if read == 0 {
+                ensure!(self.read > 0, Closed);
+
+                return Err(IoError::from(ErrorKind::UnexpectedEof))
+                    .context(ReceiveIo);
+            }
+
+            self.read += read;
+
+            if self.read == SIZE {
+                let size: u32 = bincode_options()
+                    .deserialize(&self.size)
+                    .context(DeserializeReceive)?;
+
+                ensure!(
+                    size as usize <= max,
+                    OversizedReceive {
+                        size: size as usize,
+                        max
+                    }
+                );
+
+                self.data.resize(size as usize, 0);
+            }
+        }
+
+        while self.read < SIZE + self.data.len() {
+            let read = socket
+                .read(&mut self.data[self.read - SIZE..])
+                .instrument(
+                    debug_span!(target: targets::CONNECTION, "read_data"),
+                )
+                .await
+                .context(ReceiveIo)?;
+
+            if read == 0 {
+                return Err(IoError::from(ErrorKind::UnexpectedEof))
+                    .context(ReceiveIo);
+            }
+
+            self.read += read;
+        }
+
+        self.complete = true;
+
+        Ok(())
+    }
+
+    /// Whether part of a frame was read but not all of it
+    pub(crate) fn in_progress(&self) -> bool {
+        !self.complete && self.read > 0
+    }
+
+    /// Content of the last frame that was read
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take the content of the last frame that was read
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        mem::take(&mut self.data)
+    }
+
+    /// Give back a buffer obtained using `FrameReader::take` so that it can
+    /// be reused for the next frame
+    pub(crate) fn restore(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resumes_after_cancel() {
+        let (mut write, mut read) = tokio::io::duplex(64);
+        let mut frame = FrameReader::default();
+
+        write.write_all(&[3, 0]).await.unwrap();
+
+        assert!(frame.read(&mut read).now_or_never().is_none());
+
+        write.write_all(&[0, 0, 1, 2]).await.unwrap();
+
+        assert!(frame.read(&mut read).now_or_never().is_none());
+
+        write.write_all(&[3, 1, 0, 0, 0, 4]).await.unwrap();
+
+        frame.read(&mut read).await.expect("read failed");
+        assert_eq!(frame.data(), [1, 2, 3]);
+
+        frame.read(&mut read).await.expect("read failed");
+        assert_eq!(frame.data(), [4]);
+    }
+
+    #[tokio::test]
+    async fn closed_between_frames() {
+        let (write, mut read) = tokio::io::duplex(64);
+        let mut frame = FrameReader::default();
+
+        drop(write);
+
+        assert!(matches!(
+            frame.read(&mut read).await,
+            Err(ReceiveError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn closed_within_frame() {
+        let (mut write, mut read) = tokio::io::duplex(64);
+        let mut frame = FrameReader::default();
+
+        write.write_all(&[2, 0, 0, 0, 1]).await.unwrap();
+        drop(write);
+
+        assert!(matches!(
+            frame.read(&mut read).await,
+            Err(ReceiveError::ReceiveIo { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_not_allocated() {
+        let (mut write, mut read) = tokio::io::duplex(64);
+        let mut frame = FrameReader::default();
+
+        write.write_all(&u32::MAX.to_le_bytes()).await.unwrap();
+
+        assert!(matches!(
+            frame.read_bounded(&mut read, 16).await,
+            Err(ReceiveError::OversizedReceive { max: 16, .. })
+        ));
+        assert!(frame.data().is_empty(), "oversized frame allocated");
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use tracing::info;
+
+use super::{
+    Connection, Decrypt, Encrypt, HandshakeMismatch, IdentityProof,
+    ProveIdentity, ReceiveError, SecureError, SecureReceive, SecureSend,
+    MAX_HANDSHAKE_SIZE,
+};
+use crate::codec::bincode_options;
+use crate::crypto::{
+    key::exchange::{Exchanger, PublicKey},
+    sign::Signature,
+    stream::{Pull, Push},
+};
+use crate::telemetry::targets;
+
+/// How the dialer of a `Connection` reveals its `PublicKey` while securing
+/// it. Both ends must agree on the mode beforehand: the accepting end refuses
+/// `Connection`s secured using another mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HandshakeMode {
+    /// The dialer announces its `PublicKey` in the clear
+    #[default]
+    Plain,
+    /// The dialer only reveals its `PublicKey` once both ends have exchanged
+    /// ephemeral keys and encrypted the `Connection`, so that observers can't
+    /// tell which node dialed. This costs one round trip, and the session
+    /// keys are forward secret.
+    Private,
+}
+
+impl fmt::Display for HandshakeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Plain => write!(f, "plain"),
+            Self::Private => write!(f, "private"),
+        }
+    }
+}
+
+/// Tag opening the first frame of a private handshake, which sets it apart
+/// from the `PublicKey` sent by dialers using the plain handshake
+const PRIVATE_TAG: u32 = u32::from_le_bytes(*b"drop");
+
+/// Context of the signature proving the identity of the dialer during a
+/// private handshake
+const PROOF_CONTEXT: &str = "drop private handshake identity";
+
+/// First frame sent by the dialer during a private handshake
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    tag: u32,
+    ephemeral: PublicKey,
+}
+
+/// Identity of the dialer, sent encrypted during a private handshake
+#[derive(Serialize, Deserialize)]
+struct Identity {
+    key: PublicKey,
+    /// Signature of the handshake transcript using `key`
+    proof: Signature,
+}
+
+/// The part of a private handshake signed by the dialer, binding its proof
+/// of identity to this handshake and to the accepting peer
+fn transcript<'a>(
+    dialer: &'a PublicKey,
+    acceptor: &'a PublicKey,
+    remote: &'a PublicKey,
+) -> (&'static str, &'a PublicKey, &'a PublicKey, &'a PublicKey) {
+    (PROOF_CONTEXT, dialer, acceptor, remote)
+}
+
+impl Connection {
+    /// Secure this `Connection` like `Connection::secure_as_dialer` using the
+    /// given `HandshakeMode`
+    pub async fn secure_as_dialer_with(
+        &mut self,
+        local: &Exchanger,
+        remote: &PublicKey,
+        mode: HandshakeMode,
+    ) -> Result<(), SecureError> {
+        match mode {
+            HandshakeMode::Plain => self.secure_as_dialer(local, remote).await,
+            HandshakeMode::Private => {
+                self.secure_privately_as_dialer(local, remote).await
+            }
+        }
+    }
+
+    /// Secure this `Connection` like `Connection::secure_as_acceptor` using
+    /// the given `HandshakeMode`
+    pub async fn secure_as_acceptor_with(
+        &mut self,
+        exchanger: &Exchanger,
+        mode: HandshakeMode,
+    ) -> Result<(), SecureError> {
+        match mode {
+            HandshakeMode::Plain => self.secure_as_acceptor(exchanger).await,
+            HandshakeMode::Private => {
+                self.secure_privately_as_acceptor(exchanger).await
+            }
+        }
+    }
+
+    /// Secure this `Connection` from the side that dialed the remote peer
+    /// using `HandshakeMode::Private`, only revealing our `PublicKey` to the
+    /// holder of `remote`
+    pub async fn secure_privately_as_dialer(
+        &mut self,
+        local: &Exchanger,
+        remote: &PublicKey,
+    ) -> Result<(), SecureError> {
+        let ephemeral = Exchanger::random();
+        let hello = Hello {
+            tag: PRIVATE_TAG,
+            ephemeral: *ephemeral.keypair().public(),
+        };
+
+        info!(target: targets::CONNECTION, "sending ephemeral key to peer");
+        self.send_plain(&hello).await.context(SecureSend)?;
+
+        let remote_ephemeral = self
+            .receive_plain_bounded::<PublicKey>(MAX_HANDSHAKE_SIZE)
+            .await
+            .context(SecureReceive)?;
+        let ephemerals = ephemeral.exchange(&remote_ephemeral);
+        // only the holder of `remote` can decrypt our identity
+        let (mut push, _): (Push, Pull) =
+            ephemerals.bind(&ephemeral.exchange(remote)).into();
+        let proof = local
+            .keypair()
+            .sign(&transcript(&hello.ephemeral, &remote_ephemeral, remote))
+            .context(ProveIdentity)?;
+        let identity = Identity {
+            key: *local.keypair().public(),
+            proof,
+        };
+        let sealed = push
+            .encrypt(&identity)
+            .context(Encrypt)
+            .context(SecureSend)?;
+
+        Self::write_data(&mut self.socket, &sealed)
+            .await
+            .context(SecureSend)?;
+
+        self.establish(local.exchange(remote).bind(&ephemerals));
+        self.remote_pkey = Some(*remote);
+        self.initiator = Some(*local.keypair().public());
+
+        Ok(())
+    }
+
+    /// Secure this `Connection` from the side that accepted it using
+    /// `HandshakeMode::Private`, learning the `PublicKey` of the remote peer
+    /// once it proved to hold it
+    pub async fn secure_privately_as_acceptor(
+        &mut self,
+        exchanger: &Exchanger,
+    ) -> Result<(), SecureError> {
+        info!(target: targets::CONNECTION, "waiting for peer's ephemeral key");
+
+        let hello = match self
+            .receive_plain_bounded::<Hello>(MAX_HANDSHAKE_SIZE)
+            .await
+        {
+            Ok(hello) if hello.tag == PRIVATE_TAG => hello,
+            Ok(_) | Err(ReceiveError::DeserializeReceive { .. }) => {
+                return HandshakeMismatch {
+                    expected: HandshakeMode::Private,
+                }
+                .fail()
+            }
+            Err(e) => return Err(e).context(SecureReceive),
+        };
+        let ephemeral = Exchanger::random();
+        let local_ephemeral = *ephemeral.keypair().public();
+
+        self.send_plain(&local_ephemeral)
+            .await
+            .context(SecureSend)?;
+
+        let ephemerals = ephemeral.exchange(&hello.ephemeral);
+        let (_, mut pull): (Push, Pull) = ephemerals
+            .bind(&exchanger.exchange(&hello.ephemeral))
+            .into();
+
+        self.frame
+            .read_bounded(&mut self.socket, MAX_HANDSHAKE_SIZE)
+            .await
+            .context(SecureReceive)?;
+
+        let identity = pull
+            .decrypt::<Identity>(self.frame.data())
+            .context(Decrypt)
+            .context(SecureReceive)?;
+
+        identity
+            .key
+            .verify(
+                &transcript(
+                    &hello.ephemeral,
+                    &local_ephemeral,
+                    exchanger.keypair().public(),
+                ),
+                &identity.proof,
+            )
+            .context(IdentityProof)?;
+
+        self.establish(exchanger.exchange(&identity.key).bind(&ephemerals));
+        self.remote_pkey = Some(identity.key);
+        self.initiator = Some(identity.key);
+
+        Ok(())
+    }
+
+    /// Check whether `frame` opens a private handshake
+    pub(super) fn is_private_hello(frame: &[u8]) -> bool {
+        bincode_options()
+            .deserialize::<Hello>(frame)
+            .is_ok_and(|hello| hello.tag == PRIVATE_TAG)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        net::SocketAddr,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::*;
+    use crate::net::{socket::MemorySocket, Socket};
+
+    /// A `Socket` recording everything written to it in a transcript shared
+    /// by both ends of a `Connection`
+    struct Tap {
+        socket: MemorySocket,
+        transcript: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Socket for Tap {
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.socket.local_addr()
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.socket.peer_addr()
+        }
+    }
+
+    impl AsyncRead for Tap {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.socket).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Tap {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let written = Pin::new(&mut self.socket).poll_write(cx, buf);
+
+            if let Poll::Ready(Ok(count)) = written {
+                self.transcript
+                    .lock()
+                    .unwrap()
+                    .extend_from_slice(&buf[..count]);
+            }
+
+            written
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.socket).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.socket).poll_shutdown(cx)
+        }
+    }
+
+    /// Both ends of a tapped `Connection` along with its transcript
+    fn tapped() -> (Connection, Connection, Arc<Mutex<Vec<u8>>>) {
+        let transcript = Arc::new(Mutex::new(Vec::new()));
+        let (dialer, acceptor) = MemorySocket::pair(
+            ([127, 0, 0, 1], 1).into(),
+            ([127, 0, 0, 1], 2).into(),
+        );
+        let tap = |socket| {
+            Connection::new(Box::new(Tap {
+                socket,
+                transcript: transcript.clone(),
+            }))
+        };
+
+        (tap(dialer), tap(acceptor), transcript)
+    }
+
+    fn contains(haystack: &[u8], needle: &PublicKey) -> bool {
+        haystack
+            .windows(needle.as_ref().len())
+            .any(|window| window == needle.as_ref())
+    }
+
+    #[tokio::test]
+    async fn private_hides_keys() {
+        let (local, remote) = (Exchanger::random(), Exchanger::random());
+        let (local_key, remote_key) =
+            (*local.keypair().public(), *remote.keypair().public());
+        let (mut dialer, mut acceptor, transcript) = tapped();
+
+        let (dialed, accepted) = futures::join!(
+            dialer.secure_privately_as_dialer(&local, &remote_key),
+            acceptor.secure_privately_as_acceptor(&remote)
+        );
+
+        dialed.expect("dialer failed");
+        accepted.expect("acceptor failed");
+
+        assert_eq!(dialer.remote_key(), Some(remote_key));
+        assert_eq!(acceptor.remote_key(), Some(local_key));
+        assert_eq!(acceptor.initiator(), Some(local_key));
+
+        dialer.send(&1u32).await.expect("send failed");
+        acceptor.send(&2u32).await.expect("send failed");
+
+        assert_eq!(acceptor.receive::<u32>().await.expect("receive"), 1);
+        assert_eq!(dialer.receive::<u32>().await.expect("receive"), 2);
+        assert_eq!(
+            dialer.export_keying_material(b"test", b"", 32).unwrap(),
+            acceptor.export_keying_material(b"test", b"", 32).unwrap(),
+            "different sessions"
+        );
+
+        let transcript = transcript.lock().unwrap().clone();
+
+        assert!(!contains(&transcript, &local_key), "dialer key revealed");
+        assert!(!contains(&transcript, &remote_key), "acceptor key revealed");
+
+        let (mut dialer, mut acceptor, plain) = tapped();
+        let (dialed, accepted) = futures::join!(
+            dialer.secure_as_dialer(&local, &remote_key),
+            acceptor.secure_as_acceptor(&remote)
+        );
+
+        dialed.and(accepted).expect("plain handshake failed");
+        assert!(
+            contains(&plain.lock().unwrap(), &local_key),
+            "plain handshake not recorded"
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatch_refused() {
+        let (local, remote) = (Exchanger::random(), Exchanger::random());
+        let remote_key = *remote.keypair().public();
+        let timeout = Duration::from_secs(5);
+
+        let (mut dialer, mut acceptor, _) = tapped();
+        let (dialed, accepted) = tokio::time::timeout(timeout, async {
+            futures::join!(
+                dialer.secure_privately_as_dialer(&local, &remote_key),
+                async {
+                    let accepted = acceptor.secure_as_acceptor(&remote).await;
+
+                    drop(acceptor);
+
+                    accepted
+                }
+            )
+        })
+        .await
+        .expect("private dialer hung");
+
+        assert!(dialed.is_err(), "private dialer secured");
+        assert!(matches!(
+            accepted,
+            Err(SecureError::HandshakeMismatch {
+                expected: HandshakeMode::Plain
+            })
+        ));
+
+        let (mut dialer, mut acceptor, _) = tapped();
+        let (dialed, accepted) = tokio::time::timeout(
+            timeout,
+            futures::future::join(
+                dialer.secure_as_dialer(&local, &remote_key),
+                acceptor.secure_privately_as_acceptor(&remote),
+            ),
+        )
+        .await
+        .expect("private acceptor hung");
+
+        dialed.expect("plain dialer does not wait");
+        assert!(matches!(
+            accepted,
+            Err(SecureError::HandshakeMismatch {
+                expected: HandshakeMode::Private
+            })
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use super::{Fragmentation, MAX_FRAME_SIZE};
+use crate::crypto::stream::{ENCRYPTION_OVERHEAD, HEADER_SIZE};
+
+/// Default time allowed to secure a `Connection`
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of messages from a peer that may wait for processing
+pub const DEFAULT_MAX_INFLIGHT_RECEIVES: usize = 1024;
+
+/// Default maximum size of a message, so that a peer announcing a large
+/// frame can't make us allocate up to `MAX_FRAME_SIZE` bytes
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Limits applied to a [`Connection`] and the peer on the other end of it.
+/// The default limits only bound what the wire format already bounds, apart
+/// from the size of messages, the time allowed to secure a `Connection` and
+/// the number of messages from the same peer waiting for processing.
+///
+/// [`Connection`]: super::Connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    max_message_size: usize,
+    idle_timeout: Option<Duration>,
+    send_timeout: Option<Duration>,
+    keepalive: Option<Duration>,
+    max_inflight_receives: usize,
+    send_queue_bound: Option<usize>,
+    handshake_timeout: Duration,
+    fragmentation: Option<Fragmentation>,
+}
+
+impl ConnectionLimits {
+    /// Refuse to send or receive messages larger than `size` bytes once
+    /// serialized instead of `DEFAULT_MAX_MESSAGE_SIZE`, at most
+    /// `MAX_FRAME_SIZE`. Padding added by the remote peer counts towards the
+    /// limit when receiving. Oversized frames are refused before allocating
+    /// any memory for them, and break the `Connection`.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size.min(MAX_FRAME_SIZE);
+        self
+    }
+
+    /// Fail receiving if nothing was received for `timeout`. The
+    /// `Connection` can still be used if the remote peer had not started
+    /// sending a frame, but is broken if it stalled in the middle of one.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail sending if writing a frame takes longer than `timeout`, which
+    /// breaks the `Connection` since the remote peer may have received only
+    /// part of the frame
+    pub fn send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = Some(timeout);
+        self
+    }
+
+    /// Enable TCP keepalive, probing the remote peer after `idle` without
+    /// traffic. This has no effect on other transports.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Stop receiving from a peer while `count` of its messages wait for
+    /// processing by a `SystemManager`
+    pub fn max_inflight_receives(mut self, count: usize) -> Self {
+        self.max_inflight_receives = count.max(1);
+        self
+    }
+
+    /// Refuse to queue more than `bound` outgoing messages for a peer in a
+    /// `NetworkSender`
+    pub fn send_queue_bound(mut self, bound: usize) -> Self {
+        self.send_queue_bound = Some(bound);
+        self
+    }
+
+    /// Give up securing a `Connection` after `timeout`
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Split messages larger than the maximum message size into fragments
+    /// according to `fragmentation` instead of refusing them. The maximum
+    /// message size then only bounds single frames. The remote peer must
+    /// use `Fragmentation` as well, see its documentation.
+    pub fn fragment_messages(mut self, fragmentation: Fragmentation) -> Self {
+        self.fragmentation = Some(fragmentation);
+        self
+    }
+
+    /// Get the maximum size of a message, or of a single frame when using
+    /// `Fragmentation`
+    pub fn message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Get the time after which receiving fails if nothing was received, if
+    /// any
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Get the time allowed to write a frame, if any
+    pub fn send_time(&self) -> Option<Duration> {
+        self.send_timeout
+    }
+
+    /// Get the idle time after which TCP keepalive probes are sent, if
+    /// enabled
+    pub fn keepalive_idle(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    /// Get the maximum number of messages from a peer waiting for processing
+    pub fn inflight_receives(&self) -> usize {
+        self.max_inflight_receives
+    }
+
+    /// Get the maximum number of queued outgoing messages, if any
+    pub fn send_queue(&self) -> Option<usize> {
+        self.send_queue_bound
+    }
+
+    /// Get the time allowed to secure a `Connection`
+    pub fn handshake(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    /// Get the `Fragmentation` used to send and receive large messages, if
+    /// enabled
+    pub fn fragmentation(&self) -> Option<&Fragmentation> {
+        self.fragmentation.as_ref()
+    }
+
+    /// Size of the largest frame that can be received, accounting for the
+    /// encryption overhead and the stream header sent with the first message
+    pub(crate) fn frame_size(&self) -> usize {
+        (self.max_message_size + ENCRYPTION_OVERHEAD + HEADER_SIZE)
+            .min(u32::MAX as usize)
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            idle_timeout: None,
+            send_timeout: None,
+            keepalive: None,
+            max_inflight_receives: DEFAULT_MAX_INFLIGHT_RECEIVES,
+            send_queue_bound: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            fragmentation: None,
+        }
+    }
+}
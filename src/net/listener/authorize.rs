@@ -0,0 +1,404 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ListenerError, Secure, Unauthorized};
+use crate::crypto::key::exchange::PublicKey;
+use crate::message;
+use crate::net::{Connection, SecureSend};
+use crate::telemetry::targets;
+
+use serde::{Deserialize, Serialize};
+
+use async_trait::async_trait;
+
+use snafu::{OptionExt, ResultExt};
+
+use tokio::time;
+
+use tracing::{debug, warn};
+
+/// Time given to an [`Authorizer`] to decide before applying the default
+/// decision of its [`Authorization`]
+///
+/// [`Authorizer`]: self::Authorizer
+/// [`Authorization`]: self::Authorization
+pub const DEFAULT_AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Identity of a remote peer that completed the handshake with a `Listener`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ConnectionIdentity {
+    public: PublicKey,
+    reversed: bool,
+}
+
+impl ConnectionIdentity {
+    /// Create a `ConnectionIdentity` for the peer using the given `PublicKey`
+    pub fn new(public: PublicKey) -> Self {
+        Self {
+            public,
+            reversed: false,
+        }
+    }
+
+    /// Create a `ConnectionIdentity` for a peer whose `Connection` was
+    /// reversed, see `is_reversed`
+    pub fn reversed(public: PublicKey) -> Self {
+        Self {
+            public,
+            reversed: true,
+        }
+    }
+
+    /// Get the `PublicKey` the remote peer proved it owns
+    pub fn public(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Check whether the `Connection` was dialed by the peer that was asked
+    /// to connect instead of the one that asked, using
+    /// `Request::ConnectBack`. The roles of its handshake, and thus
+    /// `Connection::initiator`, follow who dialed, which is the opposite of
+    /// who logically connected to whom.
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+}
+
+impl fmt::Display for ConnectionIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.public)
+    }
+}
+
+#[message]
+#[derive(Eq, PartialEq)]
+/// Outcome of authorizing a remote peer. It is also sent to the dialer as
+/// the first encrypted frame of the `Connection` unless disabled using
+/// `Authorization::notify`.
+pub enum Decision {
+    /// The peer may use the `Connection` as usual
+    Accept,
+    /// The peer is refused and the `Connection` closed
+    Reject {
+        /// Why the peer was refused
+        reason: String,
+    },
+    /// The peer may only observe: the accepting side ignores anything it
+    /// sends, see `Connection::is_read_only`
+    AcceptReadOnly,
+}
+
+impl Decision {
+    /// Create a `Decision::Reject` for the given reason
+    pub fn reject(reason: impl Into<String>) -> Self {
+        Self::Reject {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Accept => write!(f, "accept"),
+            Self::Reject { reason } => write!(f, "reject: {}", reason),
+            Self::AcceptReadOnly => write!(f, "accept read-only"),
+        }
+    }
+}
+
+/// An application callback deciding whether a remote peer may connect right
+/// now, consulted once the handshake completed and before the `Connection`
+/// is handed over
+#[async_trait]
+pub trait Authorizer: Send + Sync {
+    /// Decide whether the peer with the given `identity` connecting from
+    /// `addr` is authorized
+    async fn authorize(
+        &self,
+        identity: &ConnectionIdentity,
+        addr: SocketAddr,
+    ) -> Decision;
+}
+
+#[async_trait]
+impl<F> Authorizer for F
+where
+    F: Fn(&ConnectionIdentity, SocketAddr) -> Decision + Send + Sync,
+{
+    async fn authorize(
+        &self,
+        identity: &ConnectionIdentity,
+        addr: SocketAddr,
+    ) -> Decision {
+        self(identity, addr)
+    }
+}
+
+/// An [`Authorizer`] installed on a `Listener` along with the way its
+/// decisions are applied
+///
+/// [`Authorizer`]: self::Authorizer
+#[derive(Clone)]
+pub struct Authorization {
+    authorizer: Arc<dyn Authorizer>,
+    timeout: Duration,
+    on_timeout: Decision,
+    notify: bool,
+}
+
+impl Authorization {
+    /// Consult `authorizer` for every incoming `Connection`, rejecting peers
+    /// it does not decide about within `DEFAULT_AUTHORIZE_TIMEOUT`
+    pub fn new<A: Authorizer + 'static>(authorizer: A) -> Self {
+        Self {
+            authorizer: Arc::new(authorizer),
+            timeout: DEFAULT_AUTHORIZE_TIMEOUT,
+            on_timeout: Decision::reject("authorization timed out"),
+            notify: true,
+        }
+    }
+
+    /// Give the `Authorizer` at most `timeout` to decide
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Apply `decision` when the `Authorizer` does not decide in time
+    pub fn on_timeout(mut self, decision: Decision) -> Self {
+        self.on_timeout = decision;
+        self
+    }
+
+    /// Choose whether the `Decision` is sent to the dialer before closing or
+    /// using the `Connection`. Dialers only expect it if they were
+    /// configured to, see `Connector::expects_decision`.
+    pub fn notify(mut self, notify: bool) -> Self {
+        self.notify = notify;
+        self
+    }
+
+    /// Ask the `Authorizer` about the peer with the given `identity`
+    /// connecting from `addr`, bounded by the timeout
+    pub async fn decide(
+        &self,
+        identity: &ConnectionIdentity,
+        addr: SocketAddr,
+    ) -> Decision {
+        let decision = time::timeout(
+            self.timeout,
+            self.authorizer.authorize(identity, addr),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            warn!(
+                target: targets::LISTENER,
+                "authorizing {} from {} timed out after {:?}",
+                identity, addr, self.timeout
+            );
+            self.on_timeout.clone()
+        });
+
+        debug!(
+            target: targets::LISTENER,
+            "authorization of {} from {}: {}", identity, addr, decision
+        );
+
+        decision
+    }
+
+    /// Authorize the remote peer of a freshly secured `Connection`, closing
+    /// it if the peer is rejected
+    pub(crate) async fn apply(
+        &self,
+        connection: &mut Connection,
+        remote: SocketAddr,
+    ) -> Result<(), ListenerError> {
+        let identity = connection.identity().context(Unauthorized {
+            remote,
+            reason: "unsecured connection",
+        })?;
+        let decision = self.decide(&identity, remote).await;
+
+        if self.notify {
+            connection
+                .send(&decision)
+                .await
+                .context(SecureSend)
+                .context(Secure { remote })?;
+        }
+
+        match decision {
+            Decision::Accept => Ok(()),
+            Decision::AcceptReadOnly => {
+                connection.set_read_only(true);
+                Ok(())
+            }
+            Decision::Reject { reason } => {
+                let _ = connection.close().await;
+
+                Unauthorized { remote, reason }.fail()
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Authorization")
+            .field("timeout", &self.timeout)
+            .field("on_timeout", &self.on_timeout)
+            .field("notify", &self.notify)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::{
+        ConnectError, Connector, Listener, TcpConnector, TcpListener,
+    };
+    use crate::test::*;
+
+    use tokio::task;
+
+    /// An `Authorizer` that never decides
+    struct Stalled;
+
+    #[async_trait]
+    impl Authorizer for Stalled {
+        async fn authorize(
+            &self,
+            _: &ConnectionIdentity,
+            _: SocketAddr,
+        ) -> Decision {
+            time::sleep(Duration::from_secs(3600)).await;
+            Decision::Accept
+        }
+    }
+
+    fn reject_key(banned: PublicKey) -> impl Authorizer {
+        move |identity: &ConnectionIdentity, _: SocketAddr| {
+            if *identity.public() == banned {
+                Decision::reject("banned")
+            } else {
+                Decision::Accept
+            }
+        }
+    }
+
+    /// Connect to a `TcpListener` using `authorization` with a new key,
+    /// returning the results on both sides
+    async fn authorize_with(
+        authorization: impl FnOnce(PublicKey) -> Authorization,
+    ) -> (
+        Result<Connection, ConnectError>,
+        Result<Connection, ListenerError>,
+    ) {
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let dialer = TcpConnector::new(Exchanger::random()).expect_decision();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed")
+            .with_authorization(authorization(
+                *dialer.exchanger().keypair().public(),
+            ));
+        let accepted = task::spawn(async move { listener.accept().await });
+        let connected = dialer.connect(&public, &addr).await;
+
+        (connected, accepted.await.expect("listener panicked"))
+    }
+
+    #[tokio::test]
+    async fn reject_key_both_sides() {
+        let (connected, accepted) =
+            authorize_with(|banned| Authorization::new(reject_key(banned)))
+                .await;
+
+        match connected {
+            Err(ConnectError::Rejected { reason }) => {
+                assert_eq!(reason, "banned")
+            }
+            other => panic!("dialer was not rejected: {:?}", other.err()),
+        }
+
+        match accepted {
+            Err(e @ ListenerError::Unauthorized { .. }) => {
+                assert_eq!(e.phase(), Some(crate::net::Phase::Authorize));
+            }
+            other => panic!("listener accepted: {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_other_keys() {
+        let stranger = *Exchanger::random().keypair().public();
+        let (connected, accepted) =
+            authorize_with(|_| Authorization::new(reject_key(stranger))).await;
+        let mut connected = connected.expect("dialer rejected");
+        let mut accepted = accepted.expect("listener rejected");
+
+        connected.send(&7u32).await.expect("send failed");
+
+        assert_eq!(accepted.receive::<u32>().await.expect("recv failed"), 7);
+        assert!(!connected.is_read_only() && !accepted.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn read_only_both_sides() {
+        let (connected, accepted) = authorize_with(|_| {
+            Authorization::new(|_: &ConnectionIdentity, _: SocketAddr| {
+                Decision::AcceptReadOnly
+            })
+        })
+        .await;
+
+        assert!(connected.expect("dialer rejected").is_read_only());
+        assert!(accepted.expect("listener rejected").is_read_only());
+    }
+
+    #[tokio::test]
+    async fn timeout_rejects_by_default() {
+        let (connected, accepted) = authorize_with(|_| {
+            Authorization::new(Stalled).timeout(Duration::from_millis(50))
+        })
+        .await;
+
+        assert!(
+            matches!(connected, Err(ConnectError::Rejected { .. })),
+            "stalled authorizer did not reject"
+        );
+        assert!(accepted.is_err(), "stalled authorizer accepted");
+
+        let (connected, accepted) = authorize_with(|_| {
+            Authorization::new(Stalled)
+                .timeout(Duration::from_millis(50))
+                .on_timeout(Decision::Accept)
+        })
+        .await;
+
+        connected.expect("default decision not applied");
+        accepted.expect("default decision not applied");
+    }
+
+    #[tokio::test]
+    async fn silent_rejection() {
+        let (connected, accepted) = authorize_with(|banned| {
+            Authorization::new(reject_key(banned)).notify(false)
+        })
+        .await;
+
+        assert!(
+            matches!(connected, Err(ConnectError::Secure { .. })),
+            "dialer got a decision"
+        );
+        assert!(accepted.is_err(), "listener accepted");
+    }
+}
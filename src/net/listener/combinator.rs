@@ -0,0 +1,487 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::super::{
+    socket::Socket, ConnectionLimits, ConnectionObserver, HandshakeMode,
+};
+use super::{Authorization, HandshakeGuard, Listener, ListenerError, Other};
+use crate::crypto::key::exchange::Exchanger;
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use futures::future;
+
+use snafu::ensure;
+
+use tracing::debug;
+
+/// A [`Listener`] that accepts incoming connections from two underlying
+/// [`Listener`]s at once.
+///
+/// Both inner [`Listener`]s are raced on every call to `establish` and
+/// the first one to produce a [`Socket`] wins. The inner [`Listener`] that is
+/// polled first alternates between calls so that neither is starved.
+/// An error from one inner [`Listener`] is returned to the caller but leaves
+/// the other one untouched. An inner [`Listener`] that fails after losing its
+/// local address, such as a used up [`UtpListener`], is no longer polled.
+/// The losing `establish` future is dropped, so inner [`Listener`]s should be
+/// cancel-safe.
+///
+/// [`Listener`]: super::Listener
+/// [`Socket`]: crate::net::socket::Socket
+/// [`UtpListener`]: super::UtpListener
+pub struct ChainListener<C> {
+    first: Chained<C>,
+    second: Chained<C>,
+    first_turn: bool,
+}
+
+/// One of the two sides of a [`ChainListener`]
+struct Chained<C> {
+    listener: Box<dyn Listener<Candidate = C>>,
+    bound: bool,
+    exhausted: bool,
+}
+
+impl<C> Chained<C>
+where
+    C: Send + Sync + fmt::Display + 'static,
+{
+    fn new<L>(listener: L) -> Self
+    where
+        L: Listener<Candidate = C> + 'static,
+    {
+        Self {
+            bound: listener.local_addr().is_some(),
+            listener: Box::new(listener),
+            exhausted: false,
+        }
+    }
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        if self.exhausted {
+            return future::pending().await;
+        }
+
+        let result = self.listener.establish().await;
+
+        if result.is_err() && self.bound {
+            self.exhausted = self.listener.local_addr().is_none();
+        }
+
+        result
+    }
+}
+
+impl<C> ChainListener<C>
+where
+    C: Send + Sync + fmt::Display + 'static,
+{
+    /// Create a new `ChainListener` from two [`Listener`]s. Incoming
+    /// `Connection`s are secured using the `Exchanger` and handshake limits
+    /// from `first`.
+    ///
+    /// [`Listener`]: super::Listener
+    pub fn new<L1, L2>(first: L1, second: L2) -> Self
+    where
+        L1: Listener<Candidate = C> + 'static,
+        L2: Listener<Candidate = C> + 'static,
+    {
+        Self {
+            first: Chained::new(first),
+            second: Chained::new(second),
+            first_turn: true,
+        }
+    }
+}
+
+#[async_trait]
+impl<C> Listener for ChainListener<C>
+where
+    C: Send + Sync + fmt::Display + 'static,
+{
+    type Candidate = C;
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.first
+            .listener
+            .local_addr()
+            .or_else(|| self.second.listener.local_addr())
+    }
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        ensure!(
+            !self.first.exhausted || !self.second.exhausted,
+            Other {
+                reason: "all chained listeners are exhausted"
+            }
+        );
+
+        let first_turn = self.first_turn;
+
+        self.first_turn = !first_turn;
+
+        let first = Box::pin(self.first.establish());
+        let second = Box::pin(self.second.establish());
+
+        if first_turn {
+            future::select(first, second).await.factor_first().0
+        } else {
+            future::select(second, first).await.factor_first().0
+        }
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        self.first.listener.exchanger()
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        self.first.listener.handshake_guard()
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.first.listener.connection_limits()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.first.listener.authorization()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.first.listener.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.first.listener.handshake_mode()
+    }
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        let (first, second) = future::join(
+            self.first.listener.candidates(),
+            self.second.listener.candidates(),
+        )
+        .await;
+
+        match (first, second) {
+            (Ok(mut first), Ok(second)) => {
+                first.extend(second);
+                Ok(first)
+            }
+            (Ok(candidates), Err(e)) | (Err(e), Ok(candidates)) => {
+                debug!(
+                    target: targets::LISTENER,
+                    "ignoring listener without candidates: {}", e
+                );
+                Ok(candidates)
+            }
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+}
+
+/// A [`Listener`] that only accepts incoming connections whose remote address
+/// satisfies a predicate. Rejected connections are closed immediately and
+/// the [`Listener`] keeps accepting.
+///
+/// [`Listener`]: super::Listener
+pub struct FilterListener<C> {
+    listener: Box<dyn Listener<Candidate = C>>,
+    filter: Box<dyn Fn(&SocketAddr) -> bool + Send + Sync>,
+}
+
+impl<C> FilterListener<C>
+where
+    C: Send + Sync + fmt::Display + 'static,
+{
+    /// Create a new `FilterListener` that only accepts remote addresses for
+    /// which `filter` returns true.
+    pub fn new<L, F>(listener: L, filter: F) -> Self
+    where
+        L: Listener<Candidate = C> + 'static,
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            listener: Box::new(listener),
+            filter: Box::new(filter),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> Listener for FilterListener<C>
+where
+    C: Send + Sync + fmt::Display + 'static,
+{
+    type Candidate = C;
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        loop {
+            let socket = self.listener.establish().await?;
+
+            match socket.peer_addr() {
+                Ok(addr) if (self.filter)(&addr) => return Ok(socket),
+                Ok(addr) => {
+                    debug!(
+                        target: targets::LISTENER,
+                        "rejected incoming connection from {}", addr
+                    )
+                }
+                Err(e) => {
+                    debug!(
+                        target: targets::LISTENER,
+                        "rejected connection with no address: {}", e
+                    )
+                }
+            }
+        }
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        self.listener.exchanger()
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        self.listener.handshake_guard()
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.listener.connection_limits()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.listener.authorization()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.listener.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.listener.handshake_mode()
+    }
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        self.listener.candidates().await
+    }
+}
+
+/// A [`Listener`] that rewrites the `Candidate`s advertised by another
+/// [`Listener`], for instance to advertise an external address when behind
+/// a NAT.
+///
+/// [`Listener`]: super::Listener
+pub struct MapListener<I, C> {
+    listener: Box<dyn Listener<Candidate = I>>,
+    map: Box<dyn Fn(I) -> C + Send + Sync>,
+}
+
+impl<I, C> MapListener<I, C>
+where
+    I: Send + Sync + fmt::Display + 'static,
+    C: Send + Sync + fmt::Display,
+{
+    /// Create a new `MapListener` that advertises the `Candidate`s of
+    /// `listener` transformed by `map`
+    pub fn new<L, F>(listener: L, map: F) -> Self
+    where
+        L: Listener<Candidate = I> + 'static,
+        F: Fn(I) -> C + Send + Sync + 'static,
+    {
+        Self {
+            listener: Box::new(listener),
+            map: Box::new(map),
+        }
+    }
+}
+
+#[async_trait]
+impl<I, C> Listener for MapListener<I, C>
+where
+    I: Send + Sync + fmt::Display + 'static,
+    C: Send + Sync + fmt::Display,
+{
+    type Candidate = C;
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        self.listener.establish().await
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        self.listener.exchanger()
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        self.listener.handshake_guard()
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.listener.connection_limits()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.listener.authorization()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.listener.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.listener.handshake_mode()
+    }
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        let candidates = self.listener.candidates().await?;
+
+        Ok(candidates.into_iter().map(&self.map).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::super::{ListenerExt, TcpListener};
+    use super::*;
+    use crate::net::{Connector, TcpConnector};
+    use crate::test::next_test_ip4;
+
+    use tokio::task;
+
+    async fn tcp_listener(exchanger: &Exchanger) -> TcpListener {
+        TcpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed")
+    }
+
+    #[tokio::test]
+    async fn chain_accepts_from_both() {
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let first = tcp_listener(&exchanger).await;
+        let second = tcp_listener(&exchanger).await;
+        let addrs = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let mut listener = first.chain(second);
+
+        let candidates = listener.candidates().await.expect("no candidates");
+
+        assert_eq!(candidates, addrs, "wrong candidates");
+
+        let handle = task::spawn(async move {
+            for _ in 0..addrs.len() {
+                listener.accept().await.expect("accept failed");
+            }
+        });
+
+        let connector = TcpConnector::new(Exchanger::random());
+
+        for addr in addrs.iter() {
+            connector
+                .connect(&public, addr)
+                .await
+                .expect("connect failed");
+        }
+
+        handle.await.expect("listener failure");
+    }
+
+    #[tokio::test]
+    async fn filter_rejects_address() {
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let listener = tcp_listener(&exchanger).await;
+        let addr = listener.local_addr().unwrap();
+        let mut listener =
+            listener.filter_addr(|addr| !addr.ip().is_loopback());
+
+        let handle = task::spawn(async move { listener.accept().await });
+
+        let mut connection = TcpConnector::new(Exchanger::random())
+            .connect(&public, &addr)
+            .await
+            .expect("connect failed");
+
+        connection
+            .receive::<u32>()
+            .await
+            .expect_err("filtered connection was not closed");
+
+        assert!(!handle.is_finished(), "listener stopped after rejection");
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn map_rewrites_candidates() {
+        let exchanger = Exchanger::random();
+        let listener = tcp_listener(&exchanger).await;
+        let port = listener.local_addr().unwrap().port();
+        let external = Ipv4Addr::new(192, 0, 2, 1);
+        let listener = listener.map_candidate(move |mut addr: SocketAddr| {
+            addr.set_ip(external.into());
+            addr
+        });
+
+        let candidates = listener.candidates().await.expect("no candidates");
+
+        assert_eq!(candidates, vec![SocketAddr::from((external, port))]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[tokio::test]
+    async fn chain_tcp_and_utp() {
+        use crate::net::{UtpConnector, UtpListener};
+
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let tcp = tcp_listener(&exchanger).await;
+        let utp = UtpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed");
+        let tcp_addr = tcp.local_addr().unwrap();
+        let utp_addr = utp.local_addr().unwrap();
+        let mut listener = tcp.chain(utp);
+
+        let handle = task::spawn(async move {
+            let mut locals = Vec::new();
+
+            // the exhausted utp listener reports an error once
+            while locals.len() < 2 {
+                if let Ok(connection) = listener.accept().await {
+                    locals.push(
+                        connection.local_addr().expect("no local address"),
+                    );
+                }
+            }
+
+            locals.sort_unstable();
+            locals
+        });
+
+        UtpConnector::new(Exchanger::random())
+            .connect(&public, &utp_addr)
+            .await
+            .expect("utp connect failed");
+        TcpConnector::new(Exchanger::random())
+            .connect(&public, &tcp_addr)
+            .await
+            .expect("tcp connect failed");
+
+        let locals = handle.await.expect("listener failure");
+        let mut expected = vec![tcp_addr, utp_addr];
+
+        expected.sort_unstable();
+
+        assert_eq!(locals, expected, "wrong accepting listeners");
+    }
+}
@@ -1,47 +1,204 @@
 use std::{
-    fmt,
+    fmt, io,
     io::{Error, ErrorKind},
-    net::SocketAddr,
-    time::Duration,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
 };
 
 use async_trait::async_trait;
+use futures::future::{self, Either};
 use snafu::{ResultExt, Snafu};
 use tokio::{
-    net::ToSocketAddrs,
-    sync::oneshot::{channel, Receiver, Sender},
+    net::{ToSocketAddrs, UdpSocket},
+    sync::{
+        broadcast,
+        oneshot::{channel, Receiver, Sender},
+        watch,
+    },
     task::{self, JoinHandle},
-    time::{interval, Interval},
+    time::{interval, interval_at, Instant},
 };
-use tracing::{error, info, trace_span};
+use tracing::{error, info, trace_span, warn};
 use tracing_futures::Instrument;
 
 use super::{
     super::{
-        common::directory::{Request, Response},
+        common::directory::{
+            features, negotiate, Hello, Request, Response, SignedInfo,
+        },
         connector::{ConnectError, Connector},
         socket::Socket,
         utils::resolve_addr,
-        Connection, ReceiveError,
+        Connection, ContactCard, ReceiveError,
     },
+    verify::{CandidateVerifier, Reachability, Verification},
     *,
 };
 use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::telemetry::targets;
+
+/// Time between two renewals of the entry of a `DirectoryListener`
+const RENEWAL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Default time between two checks of the address of a `DirectoryListener`
+pub const DEFAULT_ADDRESS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of `AddressChanged` events kept for slow subscribers
+const ADDRESS_EVENTS: usize = 16;
+
+/// Number of `RegistrationRejected` events kept for slow subscribers
+const REJECTION_EVENTS: usize = 16;
 
 #[derive(Debug, Snafu)]
 enum DirectoryError {
     #[snafu(display("protocol error: {}", reason))]
     Protocol { reason: String },
 
+    #[snafu(display("already registered at {}", existing))]
+    Conflict { existing: SocketAddr },
+
     #[snafu(display("network error: {}", source))]
     Network { source: ReceiveError },
 }
 
-/// A `Listener` that registers its local address with a given directory server.
+/// Source of the address a `DirectoryListener` registers with its directory
+/// server, checked periodically to notice when it changes
+#[async_trait]
+pub trait AddressSource: Send + Sync {
+    /// Get the address remote peers can currently use to reach a `Listener`
+    /// bound to `bound`
+    async fn current(&self, bound: SocketAddr) -> io::Result<SocketAddr>;
+}
+
+/// The default `AddressSource`. `Listener`s bound to a specific address
+/// keep it, those bound to the unspecified address are reached using the
+/// local address the system routes packets to the target from.
+#[derive(Clone, Copy, Debug)]
+pub struct RouteAddress {
+    target: SocketAddr,
+}
+
+impl RouteAddress {
+    /// Use the local address that routes packets to `target`, usually the
+    /// directory server
+    pub fn new(target: SocketAddr) -> Self {
+        Self { target }
+    }
+}
+
+#[async_trait]
+impl AddressSource for RouteAddress {
+    async fn current(&self, bound: SocketAddr) -> io::Result<SocketAddr> {
+        if !bound.ip().is_unspecified() {
+            return Ok(bound);
+        }
+
+        let any: SocketAddr = match self.target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        // connecting a datagram socket sends nothing but picks the route
+        let socket = UdpSocket::bind(any).await?;
+
+        socket.connect(self.target).await?;
+
+        Ok(SocketAddr::new(socket.local_addr()?.ip(), bound.port()))
+    }
+}
+
+/// How often and using which `AddressSource` a `DirectoryListener` checks
+/// whether its address changed. A change triggers an immediate
+/// registration with the new address along with an `AddressChanged` event.
+/// <br />
+/// A `Listener` bound to a specific address stops accepting once that
+/// address goes away, bind it to the unspecified address instead for it to
+/// survive address changes, for instance on DHCP or mobile nodes.
+#[derive(Clone)]
+pub struct AddressWatch {
+    source: Option<Arc<dyn AddressSource>>,
+    interval: Duration,
+    verifier: Option<CandidateVerifier>,
+}
+
+impl AddressWatch {
+    /// Check the address using the given `AddressSource`
+    pub fn new<S: AddressSource + 'static>(source: S) -> Self {
+        Self {
+            source: Some(Arc::new(source)),
+            interval: DEFAULT_ADDRESS_CHECK_INTERVAL,
+            verifier: None,
+        }
+    }
+
+    /// Check the address every `interval`
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Have the directory server probe the candidates before each
+    /// registration using `verifier`, and register the first verified one
+    pub fn verify(mut self, verifier: CandidateVerifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+}
+
+impl Default for AddressWatch {
+    /// Check the address every `DEFAULT_ADDRESS_CHECK_INTERVAL` using a
+    /// `RouteAddress` to the directory server
+    fn default() -> Self {
+        Self {
+            source: None,
+            interval: DEFAULT_ADDRESS_CHECK_INTERVAL,
+            verifier: None,
+        }
+    }
+}
+
+impl fmt::Debug for AddressWatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AddressWatch")
+            .field("interval", &self.interval)
+            .field("verifier", &self.verifier)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Event emitted by a `DirectoryListener` when the address it registered
+/// with its directory server changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressChanged {
+    /// Previously registered address
+    pub old: SocketAddr,
+    /// Address now registered
+    pub new: SocketAddr,
+}
+
+/// Event emitted by a `DirectoryListener` when its directory server refused
+/// to register it. The registration is attempted again at the next renewal
+/// or change of address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationRejected {
+    /// Address the `DirectoryListener` tried to register
+    pub addr: SocketAddr,
+    /// Address already registered for the same `PublicKey` by another
+    /// client, if the rejection was due to a conflict
+    pub conflict: Option<SocketAddr>,
+    /// Reason given by the directory server
+    pub reason: String,
+}
+
+/// A `Listener` that registers its local address with a given directory
+/// server, and registers again as soon as that address changes.
 pub struct DirectoryListener {
     listener: Box<dyn Listener<Candidate = SocketAddr>>,
     directory_addr: SocketAddr,
-    exit_tx: Sender<()>,
+    registration: Option<DirectoryRegistration>,
+    changes: broadcast::Sender<AddressChanged>,
+    rejections: broadcast::Sender<RegistrationRejected>,
+    registered: watch::Receiver<SocketAddr>,
+    verification: watch::Receiver<Verification>,
 }
 
 impl DirectoryListener {
@@ -75,6 +232,28 @@ impl DirectoryListener {
         connector: C,
         directory: A,
     ) -> Result<Self, ListenerError>
+    where
+        A: ToSocketAddrs + fmt::Display,
+        C: Connector<Candidate = SocketAddr> + 'static,
+        L: Listener<Candidate = SocketAddr> + 'static,
+    {
+        Self::with_watch(
+            listener,
+            connector,
+            directory,
+            AddressWatch::default(),
+        )
+        .await
+    }
+
+    /// Create a new `DirectoryListener` that checks its address using the
+    /// given `AddressWatch` instead of the default one
+    pub async fn with_watch<A, C, L>(
+        listener: L,
+        connector: C,
+        directory: A,
+        address_watch: AddressWatch,
+    ) -> Result<Self, ListenerError>
     where
         A: ToSocketAddrs + fmt::Display,
         C: Connector<Candidate = SocketAddr> + 'static,
@@ -84,18 +263,55 @@ impl DirectoryListener {
         let (exit_tx, exit_rx) = channel();
         let listener = Box::new(listener);
         let connector = Box::new(connector);
+        let (changes, _) = broadcast::channel(ADDRESS_EVENTS);
+        let (rejections, _) = broadcast::channel(REJECTION_EVENTS);
+        let bound = listener
+            .local_addr()
+            .ok_or_else(|| {
+                Error::new(ErrorKind::AddrNotAvailable, "local address unknown")
+            })
+            .context(Io)?;
+        let source = address_watch
+            .source
+            .clone()
+            .unwrap_or_else(|| Arc::new(RouteAddress::new(directory_addr)));
+        let local = source.current(bound).await.context(Io)?;
+        let (registered_tx, registered) = watch::channel(local);
+        let (verification_tx, verification) =
+            watch::channel(Default::default());
 
         let mut listener = Self {
             listener,
             directory_addr,
-            exit_tx,
+            registration: None,
+            changes,
+            rejections,
+            registered,
+            verification,
         };
 
-        listener
-            .register(connector, directory_addr, exit_rx)
-            .instrument(trace_span!("register"))
+        let watcher = Watcher {
+            source,
+            interval: address_watch.interval,
+            bound,
+            registered: registered_tx,
+            changes: listener.changes.clone(),
+            rejections: listener.rejections.clone(),
+            verifier: address_watch.verifier,
+            candidates: listener.listener.candidates().await?,
+            verification: verification_tx,
+        };
+
+        let renewal = listener
+            .register(connector, directory_addr, exit_rx, watcher)
+            .instrument(trace_span!(target: targets::DIRECTORY, "register"))
             .await?;
 
+        listener.registration = Some(DirectoryRegistration {
+            exit: exit_tx,
+            renewal,
+        });
+
         Ok(listener)
     }
 
@@ -103,78 +319,416 @@ impl DirectoryListener {
     /// This function will register this `Listener`'s address with the
     /// directory server.
     /// This will also schedule a task that will periodically renew the entry
-    /// in the directory to prevent us being evicted.
+    /// in the directory to prevent us being evicted, and register again
+    /// whenever the `Watcher` notices a change of address. If the `Watcher`
+    /// has a `CandidateVerifier`, the candidates are probed by the directory
+    /// before each registration and the first verified one is registered
+    /// instead of the local address. If the directory refuses the
+    /// registration, a `RegistrationRejected` event is emitted and the
+    /// registration is only attempted again at the next renewal or change of
+    /// address. The entry is
+    /// removed from the directory once the exit notice is received, the
+    /// returned `JoinHandle` tells whether the directory acknowledged the
+    /// removal.
     ///
     /// # Arguments
     /// `connector` The `Connector` used when connecting to directory
     /// `directory` Address of the directory server
     /// `exit_rx` The receiving of the channel for exit notice
+    /// `watcher` The `Watcher` checking the address to register
     async fn register(
         &mut self,
         mut connector: Box<dyn Connector<Candidate = SocketAddr>>,
         directory: SocketAddr,
         mut exit_rx: Receiver<()>,
-    ) -> Result<JoinHandle<()>, ListenerError> {
-        let local = self
-            .listener
-            .local_addr()
-            .ok_or_else(|| {
-                Error::new(ErrorKind::AddrNotAvailable, "local address unknown")
-            })
-            .context(Io)?;
-        let self_pkey = *self.listener.exchanger().keypair().public();
+        watcher: Watcher,
+    ) -> Result<JoinHandle<bool>, ListenerError> {
+        let mut local = *self.registered.borrow();
+        let keypair = self.listener.exchanger().keypair().clone();
+        let self_pkey = *keypair.public();
 
         Ok(task::spawn(
             async move {
-                let req = Request::Add((self_pkey, local).into());
-                let mut connection = connector
-                    .connect(&self_pkey, &directory)
-                    .instrument(trace_span!("connect"))
-                    .await
-                    .expect("failed to connect to directory");
-                let duration = Duration::from_secs(600);
-                let mut timer = interval(duration);
+                // the directory protocol is plain text, see `DirectoryServer`
+                let (mut connection, mut hello) =
+                    connect(connector.as_mut(), &self_pkey, directory)
+                        .instrument(
+                            trace_span!(target: targets::DIRECTORY, "connect"),
+                        )
+                        .await
+                        .expect("failed to connect to directory");
+                let duration = RENEWAL_INTERVAL;
+                let mut timer =
+                    interval_at(Instant::now() + duration, duration);
+                let mut check = interval_at(
+                    Instant::now() + watcher.interval,
+                    watcher.interval,
+                );
 
-                info!("connected to directory!");
+                info!(target: targets::DIRECTORY, "connected to directory!");
+
+                'register: loop {
+                    // records stay valid for two renewal periods so that a
+                    // single missed renewal does not evict us
+                    let expiry = SystemTime::now() + duration * 2;
+                    let addrs =
+                        watcher.addresses(&mut connection, &hello, local).await;
+                    let addr = addrs[0];
+                    let req = if hello
+                        .supports(features::SIGNED | features::ADDRS)
+                    {
+                        ContactCard::new(self_pkey, addrs)
+                            .with_expiry(expiry)
+                            .sign(&keypair)
+                            .map(Request::AddCard)
+                            .map_err(|e| e.to_string())
+                    } else {
+                        match SignedInfo::new(&keypair, addr, expiry) {
+                            Ok(signed) if hello.supports(features::SIGNED) => {
+                                Ok(Request::AddSigned(signed))
+                            }
+                            Ok(_) => Ok(Request::Add((self_pkey, addr).into())),
+                            Err(e) => Err(e.to_string()),
+                        }
+                    };
+                    let req = match req {
+                        Ok(req) => req,
+                        Err(e) => {
+                            error!(
+                                target: targets::DIRECTORY,
+                                "failed to sign directory record: {}", e
+                            );
+                            return false;
+                        }
+                    };
 
-                loop {
-                    if exit_rx.try_recv().is_ok() {
-                        info!("listener is dead, stopping renewal");
-                        return;
-                    }
                     send_request(
                         &mut connection,
+                        &mut hello,
                         req,
                         connector.as_mut(),
                         &self_pkey,
                         directory,
                     )
                     .await;
-                    info!("registering with directory server");
+                    info!(
+                        target: targets::DIRECTORY,
+                        "registering with directory server"
+                    );
                     let resp = connection.receive_plain::<Response>().await;
+                    let registered = match handle_response(resp, &duration) {
+                        Ok(()) => true,
+                        Err(DirectoryError::Network { source }) => {
+                            error!(
+                                target: targets::DIRECTORY,
+                                "no answer from directory: {}", source
+                            );
+                            false
+                        }
+                        Err(e) => {
+                            watcher.reject(addr, e);
+                            true
+                        }
+                    };
 
-                    if handle_response(resp, &mut timer, &duration)
-                        .await
-                        .is_err()
-                    {
-                        continue;
+                    if registered {
+                        // wait for the next renewal or a change of address
+                        loop {
+                            let renew = Box::pin(timer.tick());
+                            let checked = Box::pin(check.tick());
+                            let wake = future::select(renew, checked);
+
+                            match future::select(&mut exit_rx, wake).await {
+                                Either::Left(_) => break 'register,
+                                Either::Right((Either::Left(_), _)) => break,
+                                Either::Right((Either::Right(_), _)) => {}
+                            }
+
+                            if let Some(new) = watcher.check(local).await {
+                                local = new;
+                                timer.reset();
+                                break;
+                            }
+                        }
+                    } else if exit_rx.try_recv().is_ok() {
+                        break;
                     }
                 }
+
+                info!(
+                    target: targets::DIRECTORY,
+                    "listener is closing, removing directory entry"
+                );
+
+                deregister(&mut connection, &hello, &self_pkey).await
             }
-            .instrument(
-                trace_span!("directory_renew", local=%local, server=%directory),
-            ),
+            .instrument(trace_span!(
+                target: targets::DIRECTORY,
+                "directory_renew", local=%local, server=%directory
+            )),
         ))
     }
 
-    /// Close this `Listener` and stops the renewing of the directory entry.
-    pub async fn close(self) {
-        let _ = self.exit_tx.send(());
+    /// Get the information identifying the directory server this `Listener`
+    /// registered with. Peers should use `candidates` to reach this
+    /// `Listener` directly.
+    pub fn directory_info(&self) -> DirectoryCandidate {
+        DirectoryCandidate::new(
+            self.directory_addr,
+            *self.exchanger().keypair().public(),
+        )
+    }
+
+    /// Get the address currently registered with the directory server
+    pub fn registered_addr(&self) -> SocketAddr {
+        *self.registered.borrow()
+    }
+
+    /// Get the outcome of the last verification of the candidates of this
+    /// `Listener`, which is empty unless its `AddressWatch` has a
+    /// `CandidateVerifier`
+    pub fn verification(&self) -> Verification {
+        self.verification.borrow().clone()
+    }
+
+    /// Subscribe to the changes of the address registered with the
+    /// directory server, for instance to update a `ContactCard`
+    pub fn address_changes(&self) -> broadcast::Receiver<AddressChanged> {
+        self.changes.subscribe()
+    }
+
+    /// Subscribe to the refusals of the directory server to register this
+    /// `Listener`, for instance when another client registered the same
+    /// `PublicKey`
+    pub fn registration_errors(
+        &self,
+    ) -> broadcast::Receiver<RegistrationRejected> {
+        self.rejections.subscribe()
+    }
+
+    /// Take the handle to the registration of this `Listener`. This allows
+    /// withdrawing from the directory after this `Listener` has been handed
+    /// to a `System`. Returns `None` if the handle was already taken.
+    pub fn registration(&mut self) -> Option<DirectoryRegistration> {
+        self.registration.take()
+    }
+
+    /// Close this `Listener`, stop renewing the directory entry and remove
+    /// it from the directory.
+    pub async fn close(mut self) {
+        if let Some(registration) = self.registration.take() {
+            registration.deregister().await;
+        }
+    }
+}
+
+/// Periodic check of the address registered by a `DirectoryListener`
+struct Watcher {
+    source: Arc<dyn AddressSource>,
+    interval: Duration,
+    /// Address the wrapped `Listener` is bound to
+    bound: SocketAddr,
+    registered: watch::Sender<SocketAddr>,
+    changes: broadcast::Sender<AddressChanged>,
+    rejections: broadcast::Sender<RegistrationRejected>,
+    verifier: Option<CandidateVerifier>,
+    /// Candidates of the wrapped `Listener`
+    candidates: Vec<SocketAddr>,
+    verification: watch::Sender<Verification>,
+}
+
+impl Watcher {
+    /// Addresses to register when reachable at `local`, in the order they
+    /// should be tried. These are the verified candidates if there is a
+    /// `CandidateVerifier`, falling back to `local` if none was verified, or
+    /// `local` followed by the other candidates otherwise.
+    async fn addresses(
+        &self,
+        connection: &mut Connection,
+        hello: &Hello,
+        local: SocketAddr,
+    ) -> Vec<SocketAddr> {
+        let mut addrs = vec![local];
+
+        match self.verify(connection, hello, local).await {
+            Some(verified) if !verified.is_empty() => return verified,
+            Some(_) => {}
+            None => {
+                for candidate in advertised(&self.candidates, local) {
+                    if !addrs.contains(&candidate) {
+                        addrs.push(candidate);
+                    }
+                }
+            }
+        }
+
+        addrs
+    }
+
+    /// Have the directory probe the candidates when reachable at `local`,
+    /// returning the verified ones if there is a `CandidateVerifier`
+    async fn verify(
+        &self,
+        connection: &mut Connection,
+        hello: &Hello,
+        local: SocketAddr,
+    ) -> Option<Vec<SocketAddr>> {
+        let verifier = self.verifier.as_ref()?;
+        let candidates = advertised(&self.candidates, local);
+        let verification = verifier.probe(connection, hello, &candidates).await;
+        let verified = verification
+            .candidates()
+            .iter()
+            .filter(|(_, reachability)| *reachability == Reachability::Verified)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        self.registered
+            .send_replace(verified.first().copied().unwrap_or(local));
+        self.verification.send_replace(verification);
+
+        Some(verified)
+    }
+
+    /// Notify subscribers that the directory refused to register `addr`
+    fn reject(&self, addr: SocketAddr, error: DirectoryError) {
+        warn!(
+            target: targets::DIRECTORY,
+            "directory refused to register {}: {}", addr, error
+        );
+
+        let conflict = match error {
+            DirectoryError::Conflict { existing } => Some(existing),
+            _ => None,
+        };
+        let _ = self.rejections.send(RegistrationRejected {
+            addr,
+            conflict,
+            reason: error.to_string(),
+        });
+    }
+
+    /// Check whether the address changed from `old`, returning the new one
+    /// after notifying subscribers if it did
+    async fn check(&self, old: SocketAddr) -> Option<SocketAddr> {
+        let new = match self.source.current(self.bound).await {
+            Ok(new) if new != old => new,
+            Ok(_) => return None,
+            Err(e) => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "failed to check local address: {}", e
+                );
+                return None;
+            }
+        };
+
+        info!(
+            target: targets::DIRECTORY,
+            "local address changed from {} to {}", old, new
+        );
+
+        self.registered.send_replace(new);
+        let _ = self.changes.send(AddressChanged { old, new });
+
+        Some(new)
+    }
+}
+
+/// A handle to the entry of a `DirectoryListener` in its directory server.
+/// Dropping both this handle and the `DirectoryListener` also removes the
+/// entry, but without waiting for the directory to acknowledge it.
+pub struct DirectoryRegistration {
+    exit: Sender<()>,
+    renewal: JoinHandle<bool>,
+}
+
+impl DirectoryRegistration {
+    /// Stop renewing the entry and remove it from the directory server.
+    /// Returns `true` if the directory server acknowledged the removal.
+    pub async fn deregister(self) -> bool {
+        let _ = self.exit.send(());
+
+        self.renewal.await.unwrap_or_default()
+    }
+}
+
+async fn deregister(
+    connection: &mut Connection,
+    hello: &Hello,
+    pkey: &PublicKey,
+) -> bool {
+    if !hello.supports(features::REMOVE) {
+        warn!(
+            target: targets::DIRECTORY,
+            "directory does not support removal, entry will expire"
+        );
+        return false;
+    }
+
+    if let Err(e) = connection.send_plain(&Request::Remove(*pkey)).await {
+        error!(
+            target: targets::DIRECTORY,
+            "failed to send removal to directory: {}", e
+        );
+        return false;
+    }
+
+    match connection.receive_plain::<Response>().await {
+        Ok(Response::Ok) => true,
+        Ok(other) => {
+            error!(
+                target: targets::DIRECTORY,
+                "directory refused removal: {}", other
+            );
+            false
+        }
+        Err(e) => {
+            error!(
+                target: targets::DIRECTORY,
+                "no answer to removal from directory: {}", e
+            );
+            false
+        }
+    }
+}
+
+/// Open a `Connection` to the directory and negotiate the protocol to use,
+/// falling back to the legacy protocol if the directory closes the
+/// `Connection` upon receiving our `Hello`
+pub(super) async fn connect(
+    connector: &mut dyn Connector<Candidate = SocketAddr>,
+    pkey: &PublicKey,
+    directory: SocketAddr,
+) -> Result<(Connection, Hello), ConnectError> {
+    let mut connection =
+        Connection::new(connector.establish(pkey, &directory).await?);
+
+    match negotiate(&mut connection).await {
+        Some(hello) => {
+            info!(
+                target: targets::DIRECTORY,
+                "negotiated {} with directory", hello
+            );
+            Ok((connection, hello))
+        }
+        None => {
+            warn!(
+                target: targets::DIRECTORY,
+                "directory uses the legacy protocol"
+            );
+
+            let connection =
+                Connection::new(connector.establish(pkey, &directory).await?);
+
+            Ok((connection, Hello::legacy()))
+        }
     }
 }
 
 async fn send_request(
     connection: &mut Connection,
+    hello: &mut Hello,
     req: Request,
     connector: &mut dyn Connector<Candidate = SocketAddr>,
     pkey: &PublicKey,
@@ -186,12 +740,16 @@ async fn send_request(
     let mut timer = interval(retry_delay);
 
     if let Err(e) = connection.send_plain(&req).await {
-        error!("failed to send message: {}", e);
+        error!(target: targets::DIRECTORY, "failed to send message: {}", e);
 
         while let Err(e) =
-            check_connection(connector, connection, pkey, directory).await
+            check_connection(connector, connection, hello, pkey, directory)
+                .await
         {
-            error!("failed to re-establish connection to directory: {}", e);
+            error!(
+                target: targets::DIRECTORY,
+                "failed to re-establish connection to directory: {}", e
+            );
             timer.tick().await;
         }
     }
@@ -200,19 +758,22 @@ async fn send_request(
 async fn check_connection(
     connector: &mut dyn Connector<Candidate = SocketAddr>,
     connection: &mut Connection,
+    hello: &mut Hello,
     pkey: &PublicKey,
     dir_addr: SocketAddr,
 ) -> Result<(), ConnectError> {
-    error!("lost connection to directory, reconnecting");
+    error!(
+        target: targets::DIRECTORY,
+        "lost connection to directory, reconnecting"
+    );
 
-    *connection = connector.connect(pkey, &dir_addr).await?;
+    (*connection, *hello) = connect(connector, pkey, dir_addr).await?;
 
     Ok(())
 }
 
-async fn handle_response(
+fn handle_response(
     resp: Result<Response, ReceiveError>,
-    timer: &mut Interval,
     duration: &Duration,
 ) -> Result<(), DirectoryError> {
     let resp = resp.context(Network)?;
@@ -220,12 +781,14 @@ async fn handle_response(
     match resp {
         Response::Ok => {
             info!(
+                target: targets::DIRECTORY,
                 "renewed lease successfully, next renew in {} seconds",
                 duration.as_secs(),
             );
-            timer.tick().await;
             Ok(())
         }
+        Response::Conflict(existing) => Conflict { existing }.fail(),
+        Response::Error(reason) => Protocol { reason }.fail(),
         other => Protocol {
             reason: format!("expected Response::Ok response got {}", other),
         }
@@ -233,9 +796,27 @@ async fn handle_response(
     }
 }
 
+/// Replace unspecified `candidates` by the address `local` registered with
+/// the directory server
+pub(super) fn advertised(
+    candidates: &[SocketAddr],
+    local: SocketAddr,
+) -> Vec<SocketAddr> {
+    candidates
+        .iter()
+        .map(|candidate| {
+            if candidate.ip().is_unspecified() {
+                local
+            } else {
+                *candidate
+            }
+        })
+        .collect()
+}
+
 #[async_trait]
 impl Listener for DirectoryListener {
-    type Candidate = DirectoryCandidate;
+    type Candidate = SocketAddr;
 
     async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
         Ok(self.listener.establish().await?)
@@ -249,35 +830,79 @@ impl Listener for DirectoryListener {
         self.listener.exchanger()
     }
 
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        self.listener.handshake_guard()
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.listener.connection_limits()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.listener.authorization()
+    }
+
+    /// Returns the `Candidate`s of the wrapped `Listener`, which remote peers
+    /// can use to reach this `Listener` directly. Unspecified addresses are
+    /// replaced by the address registered with the directory server.
     async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
-        Ok(vec![DirectoryCandidate::new(
-            self.directory_addr,
-            *self.exchanger().keypair().public(),
-        )])
+        let verification = self.verification.borrow().clone();
+
+        if !verification.is_empty() {
+            return Ok(verification.advertised());
+        }
+
+        let candidates = self.listener.candidates().await?;
+
+        Ok(advertised(&candidates, self.registered_addr()))
     }
 }
 
 impl fmt::Display for DirectoryListener {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "directory listener at {}", self.local_addr().unwrap(),)
+        match self.local_addr() {
+            Some(addr) => write!(
+                f,
+                "directory listener at {} registered with {}",
+                addr, self.directory_addr
+            ),
+            None => write!(
+                f,
+                "directory listener registered with {}",
+                self.directory_addr
+            ),
+        }
     }
 }
 
 #[derive(Copy, Clone, Debug)]
-/// Candidate for `DirectoryListener` that contains the address of the directory
-/// as well as the `PublicKey` to look for in the `DirectoryServer`
+/// Rendezvous information of a `DirectoryListener` that contains the address
+/// of the directory as well as the `PublicKey` to look for in the
+/// `DirectoryServer`
 pub struct DirectoryCandidate {
     dir_addr: SocketAddr,
     local_key: PublicKey,
 }
 
 impl DirectoryCandidate {
+    /// Create a new `DirectoryCandidate` from the directory address and the
+    /// registered `PublicKey`
     pub fn new(dir_addr: SocketAddr, local_key: PublicKey) -> Self {
         Self {
             dir_addr,
             local_key,
         }
     }
+
+    /// Address of the directory server
+    pub fn directory_addr(&self) -> SocketAddr {
+        self.dir_addr
+    }
+
+    /// `PublicKey` registered in the directory server
+    pub fn public(&self) -> &PublicKey {
+        &self.local_key
+    }
 }
 
 impl fmt::Display for DirectoryCandidate {
@@ -292,12 +917,18 @@ impl fmt::Display for DirectoryCandidate {
 
 #[cfg(test)]
 mod test {
-    use tokio::task;
+    use std::sync::Mutex;
+
+    use tokio::{sync::mpsc, task, time};
 
     use super::*;
     use crate::{
         crypto::key::exchange::Exchanger,
-        net::{Connector, Listener, TcpConnector, TcpListener},
+        net::{
+            server::{ConflictPolicy, DirectoryServer},
+            ConnectError, Connector, DirectoryConnector, Listener,
+            TcpConnector, TcpListener,
+        },
         test::*,
     };
 
@@ -319,19 +950,34 @@ mod test {
                 .await
                 .expect("listen failed");
 
-            let mut connection =
-                listener.accept().await.expect("accept failed");
+            let mut connection = Connection::new(
+                listener.establish().await.expect("accept failed"),
+            );
+
+            connection
+                .receive_plain::<Hello>()
+                .await
+                .expect("read hello failed");
+            connection
+                .send_plain(&Hello::current())
+                .await
+                .expect("hello failed");
 
             let request = connection
                 .receive_plain::<Request>()
                 .await
                 .expect("read request failed");
 
-            assert_eq!(
-                request,
-                Request::Add((srv_pub, list_addr).into()),
-                "bad request"
-            );
+            match request {
+                Request::AddCard(card) => {
+                    assert_eq!(card.public(), &srv_pub, "bad key");
+                    assert_eq!(card.candidates(), [list_addr], "bad address");
+                    card.verify().expect("invalid signature");
+                    assert!(card.expiry().is_some(), "card without expiry");
+                    assert!(!card.is_expired(), "card already expired");
+                }
+                other => panic!("expected signed card, got {:?}", other),
+            }
 
             connection
                 .send_plain(&Response::Ok)
@@ -356,4 +1002,345 @@ mod test {
 
         handle.await.expect("dir server failed");
     }
+
+    #[tokio::test]
+    async fn directory_candidates() {
+        init_logger();
+        let dir_addr = next_test_ip4();
+        let list_addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+
+        let dir_listener = TcpListener::new(dir_addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(dir_listener));
+        let server = task::spawn(server.serve());
+
+        let listener = TcpListener::new(list_addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let mut listener = DirectoryListener::new(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+        )
+        .await
+        .expect("dir_bind failed");
+
+        let info = listener.directory_info();
+
+        assert_eq!(info.directory_addr(), dir_addr, "wrong directory");
+        assert_eq!(info.public(), &pkey, "wrong public key");
+
+        let candidates =
+            listener.candidates().await.expect("no candidates found");
+
+        assert_eq!(candidates, vec![list_addr], "wrong candidates");
+
+        let handle = task::spawn(async move {
+            listener.accept().await.expect("accept failed");
+        });
+
+        TcpConnector::new(Exchanger::random())
+            .connect_any(&pkey, &candidates, None)
+            .await
+            .expect("connect failed");
+
+        handle.await.expect("accept failed");
+
+        exit.send(()).expect("server already stopped");
+        server
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn candidates_are_verified() {
+        let dir_addr = next_test_ip4();
+        let list_addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+
+        let dir_listener = TcpListener::new(dir_addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(dir_listener));
+        let server = task::spawn(server.serve());
+
+        let listener = TcpListener::new(list_addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let listener = DirectoryListener::with_watch(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+            AddressWatch::default().verify(CandidateVerifier::new()),
+        )
+        .await
+        .expect("dir_bind failed");
+
+        let mut verification = listener.verification.clone();
+
+        time::timeout(
+            Duration::from_secs(5),
+            verification.wait_for(|v| !v.is_empty()),
+        )
+        .await
+        .expect("no verification")
+        .expect("registration stopped");
+
+        assert_eq!(
+            listener.verification().candidates(),
+            [(list_addr, Reachability::Verified)]
+        );
+        assert_eq!(listener.candidates().await.unwrap(), vec![list_addr]);
+
+        listener.close().await;
+        exit.send(()).expect("server already stopped");
+        server
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn close_removes_entry() {
+        init_logger();
+        let dir_addr = next_test_ip4();
+        let dir_exchanger = Exchanger::random();
+        let directory = (*dir_exchanger.keypair().public(), dir_addr).into();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+
+        let dir_listener = TcpListener::new(dir_addr, dir_exchanger)
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(dir_listener));
+        let server = task::spawn(server.serve());
+
+        let listener = TcpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed");
+        let listener = DirectoryListener::new(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+        )
+        .await
+        .expect("dir_bind failed");
+        let fetch = || async {
+            DirectoryConnector::new(TcpConnector::new(Exchanger::random()))
+                .establish(&pkey, &directory)
+                .await
+        };
+
+        // registration happens in the background
+        time::timeout(Duration::from_secs(5), async {
+            while fetch().await.is_err() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("peer not registered");
+
+        listener.close().await;
+
+        assert!(
+            matches!(
+                fetch().await,
+                Err(ConnectError::Other { ref reason })
+                    if reason == "peer not found in directory"
+            ),
+            "closed listener still registered"
+        );
+
+        exit.send(()).expect("server already stopped");
+        server
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    /// An `AddressSource` whose address is changed by the test
+    #[derive(Clone)]
+    struct Switch(Arc<Mutex<SocketAddr>>);
+
+    #[async_trait]
+    impl AddressSource for Switch {
+        async fn current(&self, _: SocketAddr) -> io::Result<SocketAddr> {
+            Ok(*self.0.lock().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn address_change_registers() {
+        const CHECK: Duration = Duration::from_millis(200);
+
+        let dir_addr = next_test_ip4();
+        let list_addr = next_test_ip4();
+        let moved: SocketAddr = (Ipv4Addr::new(192, 0, 2, 7), 9600).into();
+        let exchanger = Exchanger::random();
+        let switch = Switch(Arc::new(Mutex::new(list_addr)));
+        let mut directory = TcpListener::new(dir_addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (registered_tx, mut registered) = mpsc::unbounded_channel();
+
+        task::spawn(async move {
+            let mut connection = Connection::new(
+                directory.establish().await.expect("accept failed"),
+            );
+
+            connection.receive_plain::<Hello>().await.expect("no hello");
+            connection
+                .send_plain(&Hello::current())
+                .await
+                .expect("hello failed");
+
+            while let Ok(request) = connection.receive_plain::<Request>().await
+            {
+                if let Request::AddCard(card) = request {
+                    let _ = registered_tx.send(card.candidates()[0]);
+                }
+
+                connection
+                    .send_plain(&Response::Ok)
+                    .await
+                    .expect("response failed");
+            }
+        });
+
+        let listener = TcpListener::new(list_addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let listener = DirectoryListener::with_watch(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+            AddressWatch::new(switch.clone()).interval(CHECK),
+        )
+        .await
+        .expect("dir_bind failed");
+        let mut changes = listener.address_changes();
+
+        assert_eq!(registered.recv().await, Some(list_addr));
+
+        time::sleep(CHECK * 3 + CHECK / 2).await;
+
+        assert!(registered.try_recv().is_err(), "registered without change");
+
+        *switch.0.lock().unwrap() = moved;
+
+        let addr = time::timeout(CHECK * 2, registered.recv())
+            .await
+            .expect("no registration after the change");
+
+        assert_eq!(addr, Some(moved));
+        assert_eq!(
+            changes.try_recv().expect("no event"),
+            AddressChanged {
+                old: list_addr,
+                new: moved,
+            }
+        );
+        assert_eq!(listener.registered_addr(), moved);
+        assert_eq!(listener.candidates().await.unwrap(), vec![list_addr]);
+    }
+
+    #[tokio::test]
+    async fn conflict_is_reported() {
+        const CHECK: Duration = Duration::from_millis(200);
+
+        init_logger();
+        let dir_addr = next_test_ip4();
+        let list_addr = next_test_ip4();
+        let elsewhere: SocketAddr = (Ipv4Addr::new(10, 0, 0, 1), 9600).into();
+        let moved: SocketAddr = (Ipv4Addr::new(192, 0, 2, 7), 9600).into();
+        let exchanger = Exchanger::random();
+        let switch = Switch(Arc::new(Mutex::new(list_addr)));
+
+        let dir_listener = TcpListener::new(dir_addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(dir_listener));
+        let server = server.conflict_policy(ConflictPolicy::Reject);
+        let conflicts = server.conflicts();
+        let server = task::spawn(server.serve());
+
+        // someone else already registered our key
+        let connector = TcpConnector::new(Exchanger::random());
+        let mut other = Connection::new(
+            connector
+                .establish(exchanger.keypair().public(), &dir_addr)
+                .await
+                .expect("connect failed"),
+        );
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+        let record = SignedInfo::new(exchanger.keypair(), elsewhere, expiry)
+            .expect("sign failed");
+
+        negotiate(&mut other).await.expect("no hello");
+        other
+            .send_plain(&Request::AddSigned(record))
+            .await
+            .expect("send failed");
+        assert_eq!(
+            other.receive_plain::<Response>().await.ok(),
+            Some(Response::Ok)
+        );
+
+        let listener = TcpListener::new(list_addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let mut listener = DirectoryListener::with_watch(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+            AddressWatch::new(switch.clone()).interval(CHECK),
+        )
+        .await
+        .expect("dir_bind failed");
+        let mut rejections = listener.registration_errors();
+
+        time::timeout(Duration::from_secs(5), async {
+            while conflicts.count() == 0 {
+                time::sleep(CHECK / 10).await;
+            }
+        })
+        .await
+        .expect("registration not attempted");
+
+        // a rejected registration waits for the next renewal or change
+        time::sleep(CHECK * 3).await;
+
+        assert_eq!(conflicts.count(), 1, "rejected registration retried");
+
+        // the first rejection may have been sent before we subscribed
+        while rejections.try_recv().is_ok() {}
+
+        *switch.0.lock().unwrap() = moved;
+
+        let rejected = time::timeout(Duration::from_secs(5), rejections.recv())
+            .await
+            .expect("no rejection")
+            .expect("rejections closed");
+
+        assert_eq!(rejected.addr, moved, "wrong address rejected");
+        assert_eq!(rejected.conflict, Some(elsewhere), "wrong conflict");
+
+        let registration = listener.registration().expect("no registration");
+        let _ = registration.exit.send(());
+
+        assert!(
+            !registration.renewal.await.expect("renewal panicked"),
+            "removed an entry we do not own"
+        );
+
+        exit.send(()).expect("server already stopped");
+        server
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
 }
@@ -0,0 +1,642 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{ListenerError, Throttled};
+use crate::net::DEFAULT_HANDSHAKE_TIMEOUT;
+use crate::telemetry::targets;
+
+use snafu::ensure;
+
+use tracing::debug;
+
+/// Limits applied by a [`Listener`] before performing the key exchange with
+/// an incoming connection. Key exchanges are expensive, so these limits
+/// prevent remote peers from making a node spend all its time on them.
+/// The default limits are generous enough not to affect well-behaved peers.
+///
+/// [`Listener`]: super::Listener
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HandshakeLimits {
+    rate: u32,
+    burst: u32,
+    per_ip: usize,
+    offenders: usize,
+    amplification: f64,
+    timeout: Duration,
+}
+
+impl HandshakeLimits {
+    /// Allow `rate` handshakes per second with at most `burst` handshakes
+    /// started at once.
+    pub fn rate(mut self, rate: u32, burst: u32) -> Self {
+        self.rate = rate;
+        self.burst = burst;
+        self
+    }
+
+    /// Allow at most `count` concurrent handshakes from the same IP address
+    pub fn per_ip(mut self, count: usize) -> Self {
+        self.per_ip = count;
+        self
+    }
+
+    /// Remember at most `count` offending IP addresses in the statistics, for
+    /// both rejected connections and failed handshakes
+    pub fn offenders(mut self, count: usize) -> Self {
+        self.offenders = count;
+        self
+    }
+
+    /// Never send more than `factor` times the number of bytes received from
+    /// a source that has not been verified yet. This only applies to
+    /// transports where the source address can be spoofed, such as uTP.
+    pub fn amplification(mut self, factor: f64) -> Self {
+        self.amplification = factor;
+        self
+    }
+
+    /// Get the maximum amplification factor for unverified sources
+    pub fn amplification_factor(&self) -> f64 {
+        self.amplification
+    }
+
+    /// Give up on incoming connections whose transport did not become ready
+    /// for the key exchange within `after`. This only applies to transports
+    /// that perform their own handshake first, such as QUIC.
+    pub fn timeout(mut self, after: Duration) -> Self {
+        self.timeout = after;
+        self
+    }
+
+    /// Get the time allowed for the transport handshake of incoming
+    /// connections
+    pub fn handshake_timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Default for HandshakeLimits {
+    fn default() -> Self {
+        Self {
+            rate: 1024,
+            burst: 1024,
+            per_ip: 64,
+            offenders: 16,
+            amplification: 3.0,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+/// Statistics about the handshakes performed by a [`Listener`]
+///
+/// [`Listener`]: super::Listener
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandshakeStats {
+    /// Number of incoming connections that were checked against the limits
+    pub attempted: u64,
+    /// Number of handshakes that completed successfully
+    pub completed: u64,
+    /// Number of connections that were closed because of the limits
+    pub rejected: u64,
+    /// Number of admitted connections whose handshake failed
+    pub failed: u64,
+    /// Recent offending IP addresses with their number of rejected
+    /// connections, most rejected first
+    pub offenders: Vec<(IpAddr, u64)>,
+    /// Recent IP addresses with their number of failed handshakes, most
+    /// failures first
+    pub failures: Vec<(IpAddr, u64)>,
+}
+
+struct State {
+    tokens: f64,
+    last: Instant,
+    in_flight: HashMap<IpAddr, usize>,
+    offenders: VecDeque<(IpAddr, u64)>,
+    failures: VecDeque<(IpAddr, u64)>,
+}
+
+impl State {
+    fn refill(&mut self, rate: u32, burst: u32) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.last = now;
+    }
+
+    fn offend(&mut self, ip: IpAddr, capacity: usize) {
+        count(&mut self.offenders, ip, capacity);
+    }
+
+    fn fail(&mut self, ip: IpAddr, capacity: usize) {
+        count(&mut self.failures, ip, capacity);
+    }
+}
+
+/// Increment the counter of `ip` in a ring of at most `capacity` addresses
+fn count(ring: &mut VecDeque<(IpAddr, u64)>, ip: IpAddr, capacity: usize) {
+    if let Some(entry) = ring.iter_mut().find(|x| x.0 == ip) {
+        entry.1 += 1;
+        return;
+    }
+
+    if capacity == 0 {
+        return;
+    }
+
+    if ring.len() >= capacity {
+        ring.pop_front();
+    }
+
+    ring.push_back((ip, 1));
+}
+
+/// Copy the content of a ring of counters, highest count first
+fn sorted(ring: &VecDeque<(IpAddr, u64)>) -> Vec<(IpAddr, u64)> {
+    let mut entries: Vec<_> = ring.iter().copied().collect();
+
+    entries.sort_by_key(|x| Reverse(x.1));
+    entries
+}
+
+struct Shared {
+    limits: HandshakeLimits,
+    state: Mutex<State>,
+    attempted: AtomicU64,
+    completed: AtomicU64,
+    rejected: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Enforces [`HandshakeLimits`] for one or more [`Listener`]s. Cloning a
+/// `HandshakeGuard` yields a handle to the same limits, which allows
+/// sharing a single handshake budget between several [`Listener`]s.
+///
+/// [`HandshakeLimits`]: self::HandshakeLimits
+/// [`Listener`]: super::Listener
+#[derive(Clone)]
+pub struct HandshakeGuard {
+    shared: Arc<Shared>,
+}
+
+impl HandshakeGuard {
+    /// Create a new `HandshakeGuard` enforcing the given limits
+    pub fn new(limits: HandshakeLimits) -> Self {
+        let state = State {
+            tokens: limits.burst as f64,
+            last: Instant::now(),
+            in_flight: HashMap::new(),
+            offenders: VecDeque::with_capacity(limits.offenders),
+            failures: VecDeque::with_capacity(limits.offenders),
+        };
+
+        Self {
+            shared: Arc::new(Shared {
+                limits,
+                state: Mutex::new(state),
+                attempted: AtomicU64::new(0),
+                completed: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                failed: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Get the limits enforced by this `HandshakeGuard`
+    pub fn limits(&self) -> &HandshakeLimits {
+        &self.shared.limits
+    }
+
+    /// Check whether a handshake with `remote` can be started. The returned
+    /// `HandshakePermit` must be kept for the duration of the handshake.
+    pub fn admit(
+        &self,
+        remote: SocketAddr,
+    ) -> Result<HandshakePermit, ListenerError> {
+        let limits = &self.shared.limits;
+        let ip = remote.ip();
+        let mut state = self.shared.state.lock().expect("guard lock poisoned");
+
+        self.shared.attempted.fetch_add(1, Ordering::Relaxed);
+        state.refill(limits.rate, limits.burst);
+
+        let in_flight = state.in_flight.get(&ip).copied().unwrap_or_default();
+        let reason = if in_flight >= limits.per_ip {
+            Some("too many concurrent handshakes")
+        } else if state.tokens < 1.0 {
+            Some("handshake rate exceeded")
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            debug!(
+                target: targets::LISTENER,
+                "rejecting handshake from {}: {}", remote, reason
+            );
+            self.shared.rejected.fetch_add(1, Ordering::Relaxed);
+            state.offend(ip, limits.offenders);
+        }
+
+        ensure!(reason.is_none(), Throttled { remote });
+
+        state.tokens -= 1.0;
+        *state.in_flight.entry(ip).or_default() += 1;
+
+        Ok(HandshakePermit {
+            guard: self.clone(),
+            ip,
+        })
+    }
+
+    /// Get the current handshake statistics
+    pub fn stats(&self) -> HandshakeStats {
+        let state = self.shared.state.lock().expect("guard lock poisoned");
+
+        HandshakeStats {
+            attempted: self.shared.attempted.load(Ordering::Relaxed),
+            completed: self.shared.completed.load(Ordering::Relaxed),
+            rejected: self.shared.rejected.load(Ordering::Relaxed),
+            failed: self.shared.failed.load(Ordering::Relaxed),
+            offenders: sorted(&state.offenders),
+            failures: sorted(&state.failures),
+        }
+    }
+}
+
+impl Default for HandshakeGuard {
+    fn default() -> Self {
+        Self::new(HandshakeLimits::default())
+    }
+}
+
+/// Permission to perform one handshake, returned by
+/// [`HandshakeGuard::admit`]. Dropping it releases the handshake slot of
+/// the remote IP address.
+///
+/// [`HandshakeGuard::admit`]: self::HandshakeGuard::admit
+pub struct HandshakePermit {
+    guard: HandshakeGuard,
+    ip: IpAddr,
+}
+
+impl HandshakePermit {
+    /// Record that the handshake completed successfully
+    pub fn complete(self) {
+        self.guard.shared.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the handshake failed, counting it against the remote IP
+    /// address
+    pub fn fail(self) {
+        let shared = &self.guard.shared;
+        let mut state = shared.state.lock().expect("guard lock poisoned");
+
+        shared.failed.fetch_add(1, Ordering::Relaxed);
+        state.fail(self.ip, shared.limits.offenders);
+    }
+}
+
+impl fmt::Debug for HandshakePermit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandshakePermit")
+            .field("ip", &self.ip)
+            .finish()
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        let mut state =
+            self.guard.shared.state.lock().expect("guard lock poisoned");
+
+        if let Some(count) = state.in_flight.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                state.in_flight.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+pub(crate) use self::amplification::AmplificationGuard;
+
+#[cfg(feature = "unstable")]
+mod amplification {
+    use std::io::{Error, ErrorKind, Result};
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use super::super::super::socket::Socket;
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    /// A `Socket` that refuses to send more than a given factor of the bytes
+    /// it received until the remote end has sent `verified` bytes, which
+    /// proves that it is able to receive our traffic.
+    pub(crate) struct AmplificationGuard<S> {
+        socket: S,
+        factor: f64,
+        verified: u64,
+        received: u64,
+        sent: u64,
+    }
+
+    impl<S: Socket> AmplificationGuard<S> {
+        pub(crate) fn new(socket: S, factor: f64, verified: u64) -> Self {
+            Self {
+                socket,
+                factor,
+                verified,
+                received: 0,
+                sent: 0,
+            }
+        }
+
+        /// Number of bytes that can still be sent to the remote end
+        fn budget(&self) -> Option<u64> {
+            if self.received >= self.verified {
+                None
+            } else {
+                let allowed = (self.received as f64 * self.factor) as u64;
+
+                Some(allowed.saturating_sub(self.sent))
+            }
+        }
+    }
+
+    impl<S: Socket> AsyncRead for AmplificationGuard<S> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<()>> {
+            let before = buf.filled().len();
+            let result = Pin::new(&mut self.socket).poll_read(cx, buf);
+
+            self.received += (buf.filled().len() - before) as u64;
+
+            result
+        }
+    }
+
+    impl<S: Socket> AsyncWrite for AmplificationGuard<S> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            let len = match self.budget() {
+                None => buf.len(),
+                Some(0) if !buf.is_empty() => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        "amplification limit reached for unverified source",
+                    )));
+                }
+                Some(budget) => buf.len().min(budget as usize),
+            };
+
+            let result = Pin::new(&mut self.socket).poll_write(cx, &buf[..len]);
+
+            if let Poll::Ready(Ok(written)) = result {
+                self.sent += written as u64;
+            }
+
+            result
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<()>> {
+            Pin::new(&mut self.socket).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<()>> {
+            Pin::new(&mut self.socket).poll_shutdown(cx)
+        }
+    }
+
+    impl<S: Socket> Socket for AmplificationGuard<S> {
+        fn peer_addr(&self) -> Result<SocketAddr> {
+            self.socket.peer_addr()
+        }
+
+        fn local_addr(&self) -> Result<SocketAddr> {
+            self.socket.local_addr()
+        }
+
+        fn transport(&self) -> &'static str {
+            self.socket.transport()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::super::{Listener, TcpListener};
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::{Connection, Connector, TcpConnector};
+    use crate::test::next_test_ip4;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpSocket;
+    use tokio::sync::mpsc;
+    use tokio::task;
+
+    fn addr(last: u8) -> SocketAddr {
+        (Ipv4Addr::new(127, 0, 0, last), 4000).into()
+    }
+
+    #[test]
+    fn per_ip_cap() {
+        let guard = HandshakeGuard::new(HandshakeLimits::default().per_ip(2));
+
+        let permits: Vec<_> = (0..2)
+            .map(|_| guard.admit(addr(2)).expect("admit failed"))
+            .collect();
+
+        guard.admit(addr(2)).expect_err("cap not enforced");
+        guard
+            .admit(addr(3))
+            .expect("other address rejected")
+            .complete();
+
+        drop(permits);
+
+        guard.admit(addr(2)).expect("slot not released");
+
+        let stats = guard.stats();
+
+        assert_eq!(stats.attempted, 5, "wrong attempt count");
+        assert_eq!(stats.completed, 1, "wrong completion count");
+        assert_eq!(stats.rejected, 1, "wrong rejection count");
+        assert_eq!(stats.offenders, vec![(addr(2).ip(), 1)]);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let guard = HandshakeGuard::new(HandshakeLimits::default().rate(1, 2));
+
+        guard.admit(addr(2)).expect("admit failed");
+        guard.admit(addr(3)).expect("admit failed");
+        guard.admit(addr(4)).expect_err("rate not enforced");
+
+        assert_eq!(guard.stats().rejected, 1, "wrong rejection count");
+    }
+
+    #[test]
+    fn offenders_ring() {
+        let guard = HandshakeGuard::new(
+            HandshakeLimits::default().per_ip(0).offenders(2),
+        );
+
+        for last in [2, 3, 3, 4, 4, 4] {
+            guard.admit(addr(last)).expect_err("cap not enforced");
+        }
+
+        assert_eq!(
+            guard.stats().offenders,
+            vec![(addr(4).ip(), 3), (addr(3).ip(), 2)],
+            "wrong offenders"
+        );
+    }
+
+    #[tokio::test]
+    async fn burst_from_one_address() {
+        let server = Exchanger::random();
+        let public = *server.keypair().public();
+        let guard = HandshakeGuard::new(HandshakeLimits::default().per_ip(2));
+        let mut listener =
+            TcpListener::with_guard(next_test_ip4(), server.clone(), guard)
+                .await
+                .expect("listen failed");
+        let addr = listener.local_addr().unwrap();
+        let guard = listener.handshake_guard().unwrap().clone();
+        let stats = guard.clone();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        task::spawn(async move {
+            loop {
+                let socket = listener.establish().await.expect("accept failed");
+                let remote = socket.peer_addr().expect("no peer address");
+                let permit = match guard.admit(remote) {
+                    Ok(permit) => permit,
+                    Err(_) => continue,
+                };
+                let exchanger = server.clone();
+                let tx = tx.clone();
+
+                task::spawn(async move {
+                    let mut connection = Connection::new(socket);
+
+                    if connection.secure_as_acceptor(&exchanger).await.is_ok() {
+                        permit.complete();
+                        let _ = tx.send(connection);
+                    }
+                });
+            }
+        });
+
+        let attacker = Ipv4Addr::new(127, 0, 0, 2);
+        let mut stalled = Vec::new();
+
+        for _ in 0..5 {
+            let socket = TcpSocket::new_v4().expect("socket failed");
+
+            socket.bind((attacker, 0).into()).expect("bind failed");
+            stalled.push(socket.connect(addr).await.expect("connect failed"));
+        }
+
+        let mut closed = 0;
+
+        for stream in stalled.iter_mut() {
+            let mut buf = [0u8; 1];
+            let read = tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                stream.read(&mut buf),
+            )
+            .await;
+
+            if let Ok(Ok(0)) = read {
+                closed += 1;
+            }
+        }
+
+        assert_eq!(closed, 3, "cap did not engage");
+
+        TcpConnector::new(Exchanger::random())
+            .connect(&public, &addr)
+            .await
+            .expect("connect failed");
+
+        rx.recv().await.expect("well-behaved peer was not accepted");
+
+        let stats = stats.stats();
+
+        assert_eq!(stats.attempted, 6, "wrong attempt count");
+        assert_eq!(stats.completed, 1, "wrong completion count");
+        assert_eq!(stats.rejected, 3, "wrong rejection count");
+        assert_eq!(stats.offenders, vec![(attacker.into(), 3)]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[tokio::test]
+    async fn amplification_limit() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener as TokioListener, TcpStream};
+
+        let listener = TokioListener::bind(next_test_ip4())
+            .await
+            .expect("listen failed");
+        let addr = listener.local_addr().unwrap();
+        let mut remote =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let (local, _) = listener.accept().await.expect("accept failed");
+        let mut guarded = AmplificationGuard::new(local, 2.0, 8);
+        let mut buf = [0u8; 4];
+
+        guarded
+            .write_all(&[0])
+            .await
+            .expect_err("wrote without budget");
+
+        remote.write_all(&[1, 2]).await.expect("write failed");
+        guarded
+            .read_exact(&mut buf[..2])
+            .await
+            .expect("read failed");
+        guarded
+            .write_all(&buf)
+            .await
+            .expect("write within budget failed");
+        guarded
+            .write_all(&[0])
+            .await
+            .expect_err("amplification not limited");
+
+        remote.write_all(&[0; 6]).await.expect("write failed");
+        guarded.read_exact(&mut [0; 6]).await.expect("read failed");
+        guarded
+            .write_all(&[0; 64])
+            .await
+            .expect("verified source limited");
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::super::{
+    socket::{MemorySocket, Socket},
+    ConnectionLimits, ConnectionObserver, HandshakeMode, MemoryRegistry,
+};
+use super::{Authorization, Io, Listener, ListenerError};
+use crate::crypto::key::exchange::Exchanger;
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tokio::sync::mpsc;
+
+use tracing::{debug, info};
+
+/// A `Listener` accepting in-process `Connection`s opened by
+/// `MemoryConnector`s sharing its `MemoryRegistry`
+pub struct MemoryListener {
+    registry: MemoryRegistry,
+    addr: SocketAddr,
+    incoming: mpsc::Receiver<MemorySocket>,
+    exchanger: Exchanger,
+    limits: Option<Arc<ConnectionLimits>>,
+    authorization: Option<Authorization>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    handshake: HandshakeMode,
+}
+
+impl MemoryListener {
+    /// Bind a new `MemoryListener` to `addr` in `registry`, or to a free
+    /// address if the port of `addr` is 0
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{Ipv4Addr, SocketAddr};
+    /// use drop::crypto::key::exchange::Exchanger;
+    /// use drop::net::{Listener, MemoryListener, MemoryRegistry};
+    ///
+    /// let registry = MemoryRegistry::new();
+    /// let addr: SocketAddr = (Ipv4Addr::LOCALHOST, 0).into();
+    /// let listener = MemoryListener::new(&registry, addr, Exchanger::random())
+    ///     .expect("bind failed");
+    ///
+    /// assert_eq!(registry.len(), 1);
+    /// assert!(listener.local_addr().is_some());
+    /// ```
+    pub fn new(
+        registry: &MemoryRegistry,
+        addr: SocketAddr,
+        exchanger: Exchanger,
+    ) -> Result<Self, ListenerError> {
+        let (addr, incoming) = registry.bind(addr).context(Io)?;
+
+        debug!(
+            target: targets::LISTENER,
+            "listening in memory on {} with {}",
+            addr,
+            exchanger.keypair().public()
+        );
+
+        Ok(Self {
+            registry: registry.clone(),
+            addr,
+            incoming,
+            exchanger,
+            limits: None,
+            authorization: None,
+            observer: None,
+            handshake: HandshakeMode::Plain,
+        })
+    }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` accepted by
+    /// this `MemoryListener`
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Apply the given `Authorization` to every `Connection` accepted by this
+    /// `MemoryListener`
+    pub fn with_authorization(mut self, authorization: Authorization) -> Self {
+        self.authorization = Some(authorization);
+        self
+    }
+
+    /// Notify `observer` of every `Connection` accepted by this
+    /// `MemoryListener` instead of the process-wide `ConnectionObserver`
+    pub fn with_observer<O: ConnectionObserver + 'static>(
+        mut self,
+        observer: O,
+    ) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Secure every `Connection` accepted by this `MemoryListener` using
+    /// `HandshakeMode::Private`
+    pub fn private_handshake(mut self) -> Self {
+        self.handshake = HandshakeMode::Private;
+        self
+    }
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    type Candidate = SocketAddr;
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        Ok(vec![self.addr])
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.addr)
+    }
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        let socket = self
+            .incoming
+            .recv()
+            .await
+            .ok_or_else(|| Error::from(ErrorKind::NotConnected))
+            .context(Io)?;
+
+        info!(
+            target: targets::LISTENER,
+            "incoming memory connection from {}",
+            socket.peer_addr().context(Io)?
+        );
+
+        Ok(Box::new(socket))
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.limits.as_ref()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.handshake
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.registry.unbind(&self.addr);
+    }
+}
+
+impl fmt::Display for MemoryListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "memory listener on {}", self.addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::net::{
+        ConnectionEstablished, Connector, Direction, MemoryConnector,
+    };
+
+    fn any() -> SocketAddr {
+        (std::net::Ipv4Addr::LOCALHOST, 0).into()
+    }
+
+    #[tokio::test]
+    async fn registries_are_isolated() {
+        let (one, two) = (MemoryRegistry::new(), MemoryRegistry::new());
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let mut listener =
+            MemoryListener::new(&one, any(), exchanger).expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+
+        MemoryConnector::new(two, Exchanger::random())
+            .connect(&pkey, &addr)
+            .await
+            .expect_err("reached listener of another registry");
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collector = events.clone();
+        let connector = MemoryConnector::new(one.clone(), Exchanger::random())
+            .with_observer(move |event: &ConnectionEstablished| {
+                collector.lock().unwrap().push(event.direction);
+            });
+
+        let (accepted, connected) =
+            futures::join!(listener.accept(), connector.connect(&pkey, &addr));
+        let (mut accepted, mut connected) = (
+            accepted.expect("accept failed"),
+            connected.expect("connect failed"),
+        );
+
+        connected.send(&7u32).await.expect("send failed");
+        assert_eq!(accepted.receive::<u32>().await.expect("receive"), 7);
+        assert_eq!(*events.lock().unwrap(), vec![Direction::Outbound]);
+
+        assert!(
+            MemoryListener::new(&one, addr, Exchanger::random()).is_err(),
+            "bound twice"
+        );
+
+        drop(listener);
+
+        assert!(one.is_empty(), "address not released");
+    }
+}
@@ -2,27 +2,84 @@ mod tcp;
 /// Listeners that use TCP as a transport protocol
 pub use tcp::TcpListener;
 
+mod udp;
+/// Listeners that receive UDP datagrams
+pub use udp::UdpListener;
+
+#[cfg(unix)]
+mod unix;
+/// Listeners that use Unix domain sockets as a transport protocol
+#[cfg(unix)]
+pub use self::unix::UnixListener;
+
+mod memory;
+/// Listener accepting in-process connections
+pub use memory::MemoryListener;
+
 #[cfg(feature = "unstable")]
 mod utp;
 /// Listeners that use µTP as a transport protocol
 #[cfg(feature = "unstable")]
 pub use self::utp::UtpListener;
 
+#[cfg(feature = "quic")]
+mod quic;
+/// Listeners that use QUIC as a transport protocol
+#[cfg(feature = "quic")]
+pub use self::quic::QuicListener;
+
 mod directory;
 /// Directory listener
-pub use directory::DirectoryListener;
+pub use directory::{
+    AddressChanged, AddressSource, AddressWatch, DirectoryCandidate,
+    DirectoryListener, DirectoryRegistration, RegistrationRejected,
+    RouteAddress, DEFAULT_ADDRESS_CHECK_INTERVAL,
+};
+
+mod reversal;
+/// Connection reversal through a directory server
+pub use reversal::{ReversalDialer, ReversalListener};
+
+mod verify;
+/// Verification of the candidates advertised by `Listener`s
+pub use verify::{CandidateVerifier, Reachability, Verification};
+
+mod limit;
+/// Handshake cost controls for `Listener`s
+pub use limit::{
+    HandshakeGuard, HandshakeLimits, HandshakePermit, HandshakeStats,
+};
+
+mod combinator;
+/// Combinators for `Listener`s
+pub use combinator::{ChainListener, FilterListener, MapListener};
+
+mod authorize;
+/// Dynamic authorization of peers connecting to `Listener`s
+pub use authorize::{
+    Authorization, Authorizer, ConnectionIdentity, Decision,
+    DEFAULT_AUTHORIZE_TIMEOUT,
+};
 
 use std::fmt;
 use std::io::Error;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::recovery::{self, Phase};
 use super::socket::Socket;
-use super::{Connection, SecureError};
+use super::{
+    Connection, ConnectionLimits, ConnectionObserver, Direction, HandshakeMode,
+    SecureError,
+};
 use crate::crypto::key::exchange::Exchanger;
 
 use async_trait::async_trait;
 
-use snafu::{ResultExt, Snafu};
+use snafu::{IntoError, ResultExt, Snafu};
+
+use tokio::time;
 
 #[derive(Debug, Snafu)]
 /// Error encountered by [`Listener`]s when accepting incoming [`Connection`]s
@@ -44,13 +101,58 @@ pub enum ListenerError {
     NoAddress,
 
     #[snafu(visibility(pub))]
-    #[snafu(display("could not secure connection: {}", source))]
+    #[snafu(display(
+        "could not secure connection from {}: {}",
+        remote,
+        source
+    ))]
     /// Error during handshake
     Secure {
+        /// Address of the remote end
+        remote: SocketAddr,
         /// Underlying error cause
         source: SecureError,
     },
 
+    #[snafu(visibility(pub))]
+    #[snafu(display("handshake limit reached for {}", remote))]
+    /// The connection was closed before the handshake because of the
+    /// `HandshakeLimits` of this `Listener`
+    Throttled {
+        /// Address of the remote end
+        remote: SocketAddr,
+    },
+
+    #[snafu(visibility(pub))]
+    #[snafu(display("handshake with {} timed out after {:?}", remote, after))]
+    /// The remote end did not complete the handshake within the timeout of
+    /// the `ConnectionLimits` of this `Listener`
+    HandshakeTimeout {
+        /// Address of the remote end
+        remote: SocketAddr,
+        /// Time waited before giving up
+        after: Duration,
+    },
+
+    #[snafu(visibility(pub))]
+    #[snafu(display("{} was not authorized: {}", remote, reason))]
+    /// The `Authorizer` of this `Listener` rejected the remote peer
+    Unauthorized {
+        /// Address of the remote end
+        remote: SocketAddr,
+        /// Reason given by the `Authorizer`
+        reason: String,
+    },
+
+    #[snafu(visibility(pub))]
+    #[snafu(display("connection reversal failed: {}", reason))]
+    /// The directory server did not relay a request to dial this `Listener`
+    /// or stopped relaying them
+    Reversal {
+        /// Reason given by the directory server
+        reason: String,
+    },
+
     #[snafu(display("{}", reason))]
     #[snafu(visibility(pub))]
     /// Any other type of error
@@ -60,6 +162,35 @@ pub enum ListenerError {
     },
 }
 
+impl ListenerError {
+    /// Get the step of accepting a `Connection` during which this error
+    /// happened, if it happened while accepting one
+    pub fn phase(&self) -> Option<Phase> {
+        match self {
+            Self::Io { .. } => Some(Phase::Establish),
+            Self::Secure { .. }
+            | Self::Throttled { .. }
+            | Self::HandshakeTimeout { .. } => Some(Phase::Handshake),
+            Self::Unauthorized { .. } => Some(Phase::Authorize),
+            Self::NoAddress | Self::Reversal { .. } | Self::Other { .. } => {
+                None
+            }
+        }
+    }
+
+    /// Check whether accepting can be attempted again right away
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Io { source } if recovery::is_retryable(source))
+    }
+
+    /// Check whether this error was caused by running out of file
+    /// descriptors or memory, in which case accepting again right away will
+    /// most likely fail again
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self, Self::Io { source } if recovery::is_exhausted(source))
+    }
+}
+
 /// A trait used to accept incoming `Connection`s from other peers
 #[async_trait]
 pub trait Listener: Send + Sync {
@@ -78,13 +209,63 @@ pub trait Listener: Send + Sync {
 
     /// Accept and secure an incoming `Connection`
     async fn accept(&mut self) -> Result<Connection, ListenerError> {
+        let start = Instant::now();
         let socket = self.establish().await?;
+        let dial_time = start.elapsed();
+        let remote = socket.peer_addr().context(Io)?;
+        let permit = match self.handshake_guard() {
+            Some(guard) => Some(guard.admit(remote)?),
+            None => None,
+        };
         let mut connection = Connection::new(socket);
+        let limits = self.connection_limits().cloned();
+
+        let start = Instant::now();
+        let handshake = connection
+            .secure_as_acceptor_with(self.exchanger(), self.handshake_mode());
+        let secured = match &limits {
+            Some(limits) => {
+                let after = limits.handshake();
+
+                time::timeout(after, handshake)
+                    .await
+                    .or_else(|_| HandshakeTimeout { remote, after }.fail())
+            }
+            None => Ok(handshake.await),
+        };
+
+        let error = match secured {
+            Ok(Ok(())) => None,
+            Ok(Err(source)) => Some(Secure { remote }.into_error(source)),
+            Err(timeout) => Some(timeout),
+        };
 
-        connection
-            .secure_client(self.exchanger())
-            .await
-            .context(Secure)?;
+        if let Some(error) = error {
+            if let Some(permit) = permit {
+                permit.fail();
+            }
+
+            return Err(error);
+        }
+
+        if let Some(limits) = limits {
+            connection.set_limits(limits);
+        }
+
+        if let Some(permit) = permit {
+            permit.complete();
+        }
+
+        if let Some(authorization) = self.authorization() {
+            authorization.apply(&mut connection, remote).await?;
+        }
+
+        connection.notify_established(
+            self.observer(),
+            Direction::Inbound,
+            dial_time,
+            start.elapsed(),
+        );
 
         Ok(connection)
     }
@@ -93,6 +274,85 @@ pub trait Listener: Send + Sync {
     /// `Connection`s
     fn exchanger(&self) -> &Exchanger;
 
+    /// Return the `HandshakeGuard` enforcing the handshake limits of this
+    /// `Listener`, if it has any
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        None
+    }
+
+    /// Return the `ConnectionLimits` applied to `Connection`s accepted by
+    /// this `Listener`, if it has any. Their handshake timeout bounds the
+    /// time spent securing each `Connection`.
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        None
+    }
+
+    /// Return the `Authorization` consulted before handing over each
+    /// `Connection` accepted by this `Listener`, if it has one
+    fn authorization(&self) -> Option<&Authorization> {
+        None
+    }
+
+    /// Return the `ConnectionObserver` notified of the `Connection`s accepted
+    /// by this `Listener` instead of the process-wide one, if it has one
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        None
+    }
+
+    /// Return the `HandshakeMode` expected from the peers dialing this
+    /// `Listener`, which refuses `Connection`s secured using any other
+    fn handshake_mode(&self) -> HandshakeMode {
+        HandshakeMode::Plain
+    }
+
+    /// Get statistics about the handshakes performed by this `Listener`
+    fn handshake_stats(&self) -> Option<HandshakeStats> {
+        self.handshake_guard().map(HandshakeGuard::stats)
+    }
+
     /// Get a slice of `Candidate`s on which this `Listener` can be reached
     async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError>;
 }
+
+/// An extension trait for [`Listener`]s
+///
+/// [`Listener`]: self::Listener
+pub trait ListenerExt: Listener + Sized + 'static
+where
+    Self::Candidate: 'static,
+{
+    /// Accept incoming connections from both this [`Listener`] and `other`
+    /// using a [`ChainListener`]
+    fn chain<L>(self, other: L) -> ChainListener<Self::Candidate>
+    where
+        L: Listener<Candidate = Self::Candidate> + 'static,
+    {
+        ChainListener::new(self, other)
+    }
+
+    /// Only accept incoming connections from remote addresses for which
+    /// `filter` returns true using a [`FilterListener`]
+    fn filter_addr<F>(self, filter: F) -> FilterListener<Self::Candidate>
+    where
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        FilterListener::new(self, filter)
+    }
+
+    /// Rewrite the `Candidate`s advertised by this [`Listener`] using a
+    /// [`MapListener`]
+    fn map_candidate<C, F>(self, map: F) -> MapListener<Self::Candidate, C>
+    where
+        C: Send + Sync + fmt::Display,
+        F: Fn(Self::Candidate) -> C + Send + Sync + 'static,
+    {
+        MapListener::new(self, map)
+    }
+}
+
+impl<L> ListenerExt for L
+where
+    L: Listener + 'static,
+    L::Candidate: 'static,
+{
+}
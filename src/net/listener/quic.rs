@@ -0,0 +1,189 @@
+use std::fmt;
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::super::socket::Socket;
+use super::*;
+use crate::crypto::key::exchange::Exchanger;
+use crate::net::socket::quic::{self, QuicSocket};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use quinn::{Endpoint, Incoming};
+
+use snafu::OptionExt;
+
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+use tracing::{debug, info};
+
+/// Number of QUIC connections ready for the key exchange that can wait for
+/// `Listener::establish` before new ones wait for room
+const READY_BACKLOG: usize = 32;
+
+/// A `Listener` that accepts QUIC connections on a given address. Each
+/// incoming QUIC connection yields a single `Connection` over its first
+/// bidirectional stream.
+///
+/// QUIC handshakes run concurrently in the background so that a peer that
+/// stalls before opening its stream does not delay other peers. Handshakes
+/// that take longer than `HandshakeLimits::handshake_timeout` are abandoned.
+pub struct QuicListener {
+    endpoint: Endpoint,
+    ready: mpsc::Receiver<QuicSocket>,
+    acceptor: JoinHandle<()>,
+    exchanger: Exchanger,
+    guard: HandshakeGuard,
+}
+
+impl QuicListener {
+    /// Create a new `QuicListener` listening on the given local address and
+    /// presenting a freshly generated self-signed certificate
+    pub async fn new(
+        addr: SocketAddr,
+        exchanger: Exchanger,
+    ) -> Result<Self, ListenerError> {
+        Self::with_guard(addr, exchanger, HandshakeGuard::default()).await
+    }
+
+    /// Create a new `QuicListener` that enforces the handshake limits of the
+    /// given `HandshakeGuard`
+    pub async fn with_guard(
+        addr: SocketAddr,
+        exchanger: Exchanger,
+        guard: HandshakeGuard,
+    ) -> Result<Self, ListenerError> {
+        debug!(
+            target: targets::LISTENER,
+            "listening with QUIC on {} with {}",
+            addr,
+            exchanger.keypair().public()
+        );
+
+        let config = quic::server_config().context(Io)?;
+        let endpoint = Endpoint::server(config, addr).context(Io)?;
+        let (sender, ready) = mpsc::channel(READY_BACKLOG);
+        let acceptor = task::spawn(Self::accept_all(
+            endpoint.clone(),
+            guard.limits().handshake_timeout(),
+            sender,
+        ));
+
+        Ok(Self {
+            endpoint,
+            ready,
+            acceptor,
+            exchanger,
+            guard,
+        })
+    }
+
+    /// Accept every incoming QUIC connection, completing each handshake in
+    /// its own task and queueing the ones that open a stream within `timeout`
+    async fn accept_all(
+        endpoint: Endpoint,
+        timeout: Duration,
+        ready: mpsc::Sender<QuicSocket>,
+    ) {
+        while let Some(incoming) = endpoint.accept().await {
+            let endpoint = endpoint.clone();
+            let ready = ready.clone();
+
+            task::spawn(async move {
+                let remote = incoming.remote_address();
+                let socket =
+                    time::timeout(timeout, Self::incoming(&endpoint, incoming))
+                        .await;
+
+                match socket {
+                    Ok(Ok(socket)) => {
+                        let _ = ready.send(socket).await;
+                    }
+                    Ok(Err(e)) => debug!(
+                        target: targets::LISTENER,
+                        "failed to accept QUIC connection from {}: {}",
+                        remote,
+                        e
+                    ),
+                    Err(_) => debug!(
+                        target: targets::LISTENER,
+                        "QUIC connection from {} not ready within {:?}",
+                        remote,
+                        timeout
+                    ),
+                }
+            });
+        }
+    }
+
+    async fn incoming(
+        endpoint: &Endpoint,
+        incoming: Incoming,
+    ) -> std::io::Result<QuicSocket> {
+        let connection = incoming.await.map_err(IoError::other)?;
+        let (send, recv) =
+            connection.accept_bi().await.map_err(IoError::other)?;
+
+        QuicSocket::new(endpoint, connection, send, recv)
+    }
+}
+
+#[async_trait]
+impl Listener for QuicListener {
+    type Candidate = SocketAddr;
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        Ok(vec![self.local_addr().context(NoAddress)?])
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.endpoint.local_addr().ok()
+    }
+
+    /// Accept a QUIC `Connection` on this `Listener`.
+    /// Connections are accepted in the background so dropping this future
+    /// before it completes does not lose an incoming connection.
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        let socket = self
+            .ready
+            .recv()
+            .await
+            .ok_or_else(|| std::io::ErrorKind::NotConnected.into())
+            .context(Io)?;
+
+        info!(
+            target: targets::LISTENER,
+            "incoming QUIC connection from {}",
+            socket.peer_addr().context(Io)?
+        );
+
+        Ok(Box::new(socket))
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        Some(&self.guard)
+    }
+}
+
+impl fmt::Display for QuicListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.local_addr() {
+            None => write!(f, "closed quic listener"),
+            Some(addr) => write!(f, "quic listener on {}", addr),
+        }
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
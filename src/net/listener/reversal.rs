@@ -0,0 +1,395 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::directory::{advertised, connect};
+use super::{
+    Authorization, HandshakeGuard, Io, Listener, ListenerError, Other,
+    Unauthorized,
+};
+use crate::crypto::key::exchange::{Exchanger, PublicKey};
+use crate::net::common::directory::{features, Request, Response, SignedInfo};
+use crate::net::socket::Socket;
+use crate::net::utils::resolve_addr;
+use crate::net::{
+    Connection, ConnectionLimits, ConnectionObserver, Connector, HandshakeMode,
+};
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tokio::net::ToSocketAddrs;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{self, JoinHandle};
+
+use tracing::{debug, info, warn};
+
+/// Time during which the record proving the key of a `ReversalDialer` to
+/// its directory server is valid
+const SUBSCRIPTION_VALIDITY: Duration = Duration::from_secs(60);
+
+/// Maximum number of peers a `ReversalDialer` dials at once
+const MAX_REVERSAL_DIALS: usize = 8;
+
+/// Number of dialed `Connection`s waiting to be accepted from a
+/// `ReversalDialer`
+const DIALED_QUEUE: usize = 16;
+
+/// A `Listener` for peers that can not be dialed: it asks a peer to dial it
+/// by sending a `Request::ConnectBack` through a directory server, and
+/// yields the resulting `Connection` as if the peer had connected on its
+/// own. <br />
+/// The wrapped `Listener` accepts the `Connection` from the addresses given
+/// in the request, which only needs to be reachable from the dialing peer.
+/// The handshake is performed as the accepting side since the remote peer
+/// dials, but the `ConnectionIdentity` of the `Connection` is reversed to
+/// record that we asked for it. Peers other than the one that was asked
+/// are refused.
+pub struct ReversalListener {
+    listener: Box<dyn Listener<Candidate = SocketAddr>>,
+    target: PublicKey,
+}
+
+impl ReversalListener {
+    /// Ask the peer with key `target`, subscribed to the directory server at
+    /// `directory` using a `ReversalDialer`, to dial the candidates of
+    /// `listener`
+    ///
+    /// # Arguments
+    /// * `listener` The `Listener` accepting the `Connection` of `target`
+    /// * `connector` The `Connector` to use when connecting to the directory
+    /// * `directory` The address of the directory server
+    /// * `target` The `PublicKey` of the peer that should dial
+    pub async fn new<A, C, L>(
+        listener: L,
+        mut connector: C,
+        directory: A,
+        target: PublicKey,
+    ) -> Result<Self, ListenerError>
+    where
+        A: ToSocketAddrs + fmt::Display,
+        C: Connector<Candidate = SocketAddr>,
+        L: Listener<Candidate = SocketAddr> + 'static,
+    {
+        let directory = resolve_addr(directory).await.context(Io)?;
+        let requester = *listener.exchanger().keypair().public();
+        let (mut connection, hello) =
+            connect(&mut connector, &requester, directory)
+                .await
+                .map_err(|e| reversal(e.to_string()))?;
+
+        if !hello.supports(features::REVERSAL) {
+            return Err(reversal("directory does not relay requests"));
+        }
+
+        let local = connection.local_addr().context(Io)?;
+        let addrs = advertised(&listener.candidates().await?, local);
+        let request = Request::ConnectBack {
+            target,
+            requester,
+            addrs,
+        };
+
+        info!(
+            target: targets::DIRECTORY,
+            "asking {} to dial {:?}",
+            target.fingerprint(),
+            listener.local_addr()
+        );
+
+        connection
+            .send_plain(&request)
+            .await
+            .map_err(|e| reversal(e.to_string()))?;
+
+        match connection.receive_plain::<Response>().await {
+            Ok(Response::Ok) => {}
+            Ok(Response::NotFound(_)) => {
+                return Err(reversal("target is not subscribed"))
+            }
+            Ok(Response::Error(reason)) => return Err(reversal(reason)),
+            Ok(other) => return Err(reversal(format!("got {}", other))),
+            Err(e) => return Err(reversal(e.to_string())),
+        }
+
+        let _ = connection.close().await;
+
+        Ok(Self {
+            listener: Box::new(listener),
+            target,
+        })
+    }
+
+    /// Get the `PublicKey` of the peer that was asked to dial
+    pub fn target(&self) -> &PublicKey {
+        &self.target
+    }
+}
+
+#[async_trait]
+impl Listener for ReversalListener {
+    type Candidate = SocketAddr;
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        self.listener.establish().await
+    }
+
+    /// Accept the `Connection` of the peer that was asked to dial, refusing
+    /// any other one
+    async fn accept(&mut self) -> Result<Connection, ListenerError> {
+        let mut connection = self.listener.accept().await?;
+        let remote = connection.peer_addr().context(Io)?;
+
+        if connection.remote_key() != Some(self.target) {
+            warn!(
+                target: targets::LISTENER,
+                "refusing {} that was not asked to dial", remote
+            );
+
+            let _ = connection.close().await;
+
+            return Unauthorized {
+                remote,
+                reason: "peer was not asked to dial",
+            }
+            .fail();
+        }
+
+        connection.set_reversed(true);
+
+        Ok(connection)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        self.listener.exchanger()
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        self.listener.handshake_guard()
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.listener.connection_limits()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.listener.authorization()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.listener.observer()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.listener.handshake_mode()
+    }
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        self.listener.candidates().await
+    }
+}
+
+impl fmt::Display for ReversalListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "reversal listener waiting for {}", self.target)
+    }
+}
+
+/// A `Listener` for peers that dial others on their behalf: it subscribes
+/// to a directory server and dials every peer that asks for it using a
+/// `ReversalListener`. The dialed `Connection`s are yielded by `accept` as
+/// if the remote peers had connected on their own, so that a `System`
+/// treats them like any other incoming `Connection`. <br />
+/// The handshake is performed as the dialing side, but the
+/// `ConnectionIdentity` of the `Connection`s is reversed to record that the
+/// remote peer asked for them. Since nothing is accepted from the network,
+/// `establish` always fails.
+pub struct ReversalDialer {
+    exchanger: Exchanger,
+    dialed: mpsc::Receiver<Connection>,
+    subscription: JoinHandle<()>,
+}
+
+impl ReversalDialer {
+    /// Subscribe to the requests relayed by the directory server at
+    /// `directory`, dialing peers using `connector`
+    pub async fn new<A, C>(
+        mut connector: C,
+        directory: A,
+    ) -> Result<Self, ListenerError>
+    where
+        A: ToSocketAddrs + fmt::Display,
+        C: Connector<Candidate = SocketAddr> + 'static,
+    {
+        let directory = resolve_addr(directory).await.context(Io)?;
+        let exchanger = connector.exchanger().clone();
+        let keypair = exchanger.keypair();
+        let (mut connection, hello) =
+            connect(&mut connector, keypair.public(), directory)
+                .await
+                .map_err(|e| reversal(e.to_string()))?;
+
+        if !hello.supports(features::REVERSAL) {
+            return Err(reversal("directory does not relay requests"));
+        }
+
+        // the directory only accepts records for the address we are seen at
+        let local = connection.local_addr().context(Io)?;
+        let expiry = SystemTime::now() + SUBSCRIPTION_VALIDITY;
+        let record = SignedInfo::new(keypair, local, expiry)
+            .map_err(|e| reversal(e.to_string()))?;
+
+        connection
+            .send_plain(&Request::Subscribe(record))
+            .await
+            .map_err(|e| reversal(e.to_string()))?;
+
+        match connection.receive_plain::<Response>().await {
+            Ok(Response::Ok) => {}
+            Ok(Response::Error(reason)) => return Err(reversal(reason)),
+            Ok(other) => return Err(reversal(format!("got {}", other))),
+            Err(e) => return Err(reversal(e.to_string())),
+        }
+
+        info!(
+            target: targets::DIRECTORY,
+            "subscribed to connect back requests at {}", directory
+        );
+
+        let (tx, dialed) = mpsc::channel(DIALED_QUEUE);
+        let subscription =
+            task::spawn(Self::subscription(connection, connector, tx));
+
+        Ok(Self {
+            exchanger,
+            dialed,
+            subscription,
+        })
+    }
+
+    /// Dial every peer whose request is relayed on `connection`
+    async fn subscription<C>(
+        mut connection: Connection,
+        connector: C,
+        dialed: mpsc::Sender<Connection>,
+    ) where
+        C: Connector<Candidate = SocketAddr> + 'static,
+    {
+        let connector = Arc::new(connector);
+        let dials = Arc::new(Semaphore::new(MAX_REVERSAL_DIALS));
+
+        loop {
+            let (requester, addrs) =
+                match connection.receive_plain::<Response>().await {
+                    Ok(Response::ConnectBack(requester, addrs)) => {
+                        (requester, addrs)
+                    }
+                    Ok(other) => {
+                        warn!(
+                            target: targets::DIRECTORY,
+                            "unexpected message from directory: {}", other
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(
+                            target: targets::DIRECTORY,
+                            "lost subscription to directory: {}", e
+                        );
+                        return;
+                    }
+                };
+
+            let permit = match dials.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!(
+                        target: targets::DIRECTORY,
+                        "too many dials, ignoring request from {}",
+                        requester.fingerprint()
+                    );
+                    continue;
+                }
+            };
+            let connector = connector.clone();
+            let dialed = dialed.clone();
+
+            task::spawn(async move {
+                debug!(
+                    target: targets::DIRECTORY,
+                    "dialing {} at {:?} on its request", requester, addrs
+                );
+
+                match connector.connect_any(&requester, &addrs, None).await {
+                    Ok(mut connection) => {
+                        connection.set_reversed(true);
+                        let _ = dialed.send(connection).await;
+                    }
+                    Err(e) => warn!(
+                        target: targets::DIRECTORY,
+                        "failed to dial {} on its request: {}", requester, e
+                    ),
+                }
+
+                drop(permit);
+            });
+        }
+    }
+}
+
+impl Drop for ReversalDialer {
+    fn drop(&mut self) {
+        self.subscription.abort();
+    }
+}
+
+#[async_trait]
+impl Listener for ReversalDialer {
+    type Candidate = SocketAddr;
+
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        Other {
+            reason: "reversal dialer only yields secured connections",
+        }
+        .fail()
+    }
+
+    /// Wait for the next `Connection` dialed on the request of a peer
+    async fn accept(&mut self) -> Result<Connection, ListenerError> {
+        match self.dialed.recv().await {
+            Some(connection) => Ok(connection),
+            None => Err(reversal("subscription to directory lost")),
+        }
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        Ok(Vec::new())
+    }
+}
+
+impl fmt::Display for ReversalDialer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "reversal dialer for {}",
+            self.exchanger.keypair().public()
+        )
+    }
+}
+
+fn reversal(reason: impl Into<String>) -> ListenerError {
+    ListenerError::Reversal {
+        reason: reason.into(),
+    }
+}
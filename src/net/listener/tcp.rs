@@ -1,9 +1,15 @@
 use std::fmt;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use super::super::socket::Socket;
-use super::{Io, Listener, ListenerError};
+use super::super::{
+    socket::Socket, ConnectionLimits, ConnectionObserver, HandshakeMode,
+};
+use super::{
+    Authorization, Authorizer, HandshakeGuard, Io, Listener, ListenerError,
+};
 use crate::crypto::key::exchange::Exchanger;
+use crate::telemetry::targets;
 
 use async_trait::async_trait;
 
@@ -19,6 +25,11 @@ use tracing_futures::Instrument;
 pub struct TcpListener {
     listener: TokioListener,
     exchanger: Exchanger,
+    guard: HandshakeGuard,
+    limits: Option<Arc<ConnectionLimits>>,
+    authorization: Option<Authorization>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    handshake: HandshakeMode,
 }
 
 impl TcpListener {
@@ -28,7 +39,7 @@ impl TcpListener {
     ///
     /// * `candidate` The target address to listen on
     /// * `exchanger` A key `Exchanger` to be used when handshaking with the
-    /// remote end
+    ///   remote end
     ///
     /// # Example
     /// ```
@@ -42,8 +53,30 @@ impl TcpListener {
     pub async fn new<A: ToSocketAddrs + fmt::Display>(
         candidate: A,
         exchanger: Exchanger,
+    ) -> Result<Self, ListenerError> {
+        Self::with_guard(candidate, exchanger, HandshakeGuard::default()).await
+    }
+
+    /// Create a new `TcpListener` that enforces the handshake limits of the
+    /// given `HandshakeGuard`
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{Ipv4Addr, SocketAddr};
+    /// use drop::crypto::key::exchange::Exchanger;
+    /// use drop::net::{HandshakeGuard, HandshakeLimits, TcpListener};
+    ///
+    /// let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, 0).into();
+    /// let guard = HandshakeGuard::new(HandshakeLimits::default().per_ip(4));
+    /// let listener = TcpListener::with_guard(addr, Exchanger::random(), guard);
+    /// ```
+    pub async fn with_guard<A: ToSocketAddrs + fmt::Display>(
+        candidate: A,
+        exchanger: Exchanger,
+        guard: HandshakeGuard,
     ) -> Result<Self, ListenerError> {
         debug!(
+            target: targets::LISTENER,
             "listening with TCP on {} with {}",
             candidate,
             exchanger.keypair().public()
@@ -53,9 +86,54 @@ impl TcpListener {
             .map(|listener| Self {
                 listener,
                 exchanger,
+                guard,
+                limits: None,
+                authorization: None,
+                observer: None,
+                handshake: HandshakeMode::Plain,
             })
             .context(Io)
     }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` accepted by
+    /// this `TcpListener`
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Ask `authorizer` whether each remote peer may connect once the
+    /// handshake completed, using the defaults of `Authorization`
+    pub fn with_authorizer<A: Authorizer + 'static>(
+        self,
+        authorizer: A,
+    ) -> Self {
+        self.with_authorization(Authorization::new(authorizer))
+    }
+
+    /// Apply the given `Authorization` to every `Connection` accepted by this
+    /// `TcpListener`
+    pub fn with_authorization(mut self, authorization: Authorization) -> Self {
+        self.authorization = Some(authorization);
+        self
+    }
+
+    /// Notify `observer` of every `Connection` accepted by this `TcpListener`
+    /// instead of the process-wide `ConnectionObserver`
+    pub fn with_observer<O: ConnectionObserver + 'static>(
+        mut self,
+        observer: O,
+    ) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Secure every `Connection` accepted by this `TcpListener` using
+    /// `HandshakeMode::Private`
+    pub fn private_handshake(mut self) -> Self {
+        self.handshake = HandshakeMode::Private;
+        self
+    }
 }
 
 #[cfg(unix)]
@@ -89,11 +167,14 @@ impl Listener for TcpListener {
         let (stream, remote) = self
             .listener
             .accept()
-            .instrument(debug_span!("tcp_accept"))
+            .instrument(debug_span!(target: targets::LISTENER, "tcp_accept"))
             .await
             .context(Io)?;
 
-        info!("incoming tcp connection from {}", remote);
+        info!(
+            target: targets::LISTENER,
+            "incoming tcp connection from {}", remote
+        );
 
         Ok(Box::new(stream))
     }
@@ -101,11 +182,31 @@ impl Listener for TcpListener {
     fn exchanger(&self) -> &Exchanger {
         &self.exchanger
     }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        Some(&self.guard)
+    }
+
+    fn connection_limits(&self) -> Option<&Arc<ConnectionLimits>> {
+        self.limits.as_ref()
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Arc<dyn ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    fn handshake_mode(&self) -> HandshakeMode {
+        self.handshake
+    }
 }
 
 impl fmt::Display for TcpListener {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let addr = self.local_addr().map_or(Err(fmt::Error), Ok)?;
+        let addr = self.local_addr().ok_or(fmt::Error)?;
 
         write!(f, "tcp listener on {}", addr)
     }
@@ -125,7 +226,9 @@ mod tests {
             .await
             .expect("failed to bind");
 
-        let two = TcpListener::new(addr, exchanger).await.expect("failed to bind");
+        let two = TcpListener::new(addr, exchanger)
+            .await
+            .expect("failed to bind");
 
         assert_eq!(one.local_addr().unwrap(), two.local_addr().unwrap());
     }
@@ -143,4 +246,118 @@ mod tests {
             "wrong listen address"
         );
     }
+
+    #[tokio::test]
+    async fn pre_handshake_garbage() {
+        use crate::net::{
+            Connector, ReceiveError, SecureError, TcpConnector,
+            MAX_HANDSHAKE_SIZE,
+        };
+
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpStream;
+
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(next_test_ip4(), exchanger)
+            .await
+            .expect("bind failed");
+        let addr = listener.local_addr().unwrap();
+        let oversized = (MAX_HANDSHAKE_SIZE as u32 + 1).to_le_bytes();
+        type Check = fn(&ReceiveError) -> bool;
+
+        let attempts: [(&[u8], Check); 3] = [
+            // truncated public key
+            (&[32, 0, 0, 0, 1, 2, 3], |e| {
+                matches!(e, ReceiveError::ReceiveIo { .. })
+            }),
+            (&oversized, |e| {
+                matches!(e, ReceiveError::OversizedReceive { .. })
+            }),
+            // too short to be a public key
+            (&[3, 0, 0, 0, 1, 2, 3], |e| {
+                matches!(e, ReceiveError::DeserializeReceive { .. })
+            }),
+        ];
+
+        for (payload, expected) in attempts.iter() {
+            let mut stream =
+                TcpStream::connect(addr).await.expect("connect failed");
+            let local = stream.local_addr().unwrap();
+
+            stream.write_all(payload).await.expect("write failed");
+            stream.shutdown().await.expect("shutdown failed");
+
+            match listener.accept().await {
+                Err(ListenerError::Secure {
+                    remote,
+                    source: SecureError::SecureReceive { source },
+                }) => {
+                    assert_eq!(remote, local, "wrong remote address");
+                    assert!(expected(&source), "unexpected error: {}", source);
+                }
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("garbage accepted"),
+            }
+        }
+
+        let handle = tokio::spawn(async move {
+            TcpConnector::new(Exchanger::random())
+                .connect(&public, &addr)
+                .await
+                .expect("connect failed")
+        });
+
+        listener.accept().await.expect("listener unhealthy");
+        handle.await.expect("connector panicked");
+
+        let stats = listener.handshake_stats().expect("no stats");
+
+        assert_eq!(stats.failed, 3, "wrong failure count");
+        assert_eq!(stats.completed, 1, "wrong completion count");
+        assert_eq!(stats.failures, vec![(addr.ip(), 3)]);
+    }
+
+    #[tokio::test]
+    async fn tcp_listener_limits() {
+        use std::time::Duration;
+
+        use tokio::net::TcpStream;
+
+        use crate::net::{Connector, ReceiveError, TcpConnector};
+
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let limits = ConnectionLimits::default()
+            .max_message_size(32)
+            .handshake_timeout(Duration::from_millis(50));
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("bind failed")
+            .with_limits(limits.clone());
+
+        // a peer that never completes its handshake is given up on
+        let _idle = TcpStream::connect(addr).await.expect("connect failed");
+
+        match listener.accept().await {
+            Err(ListenerError::HandshakeTimeout { .. }) => {}
+            other => panic!("unexpected accept result {:?}", other),
+        }
+
+        let connector = TcpConnector::new(Exchanger::random());
+        let mut client = connector.connect(&pkey, &addr).await.unwrap();
+        let mut accepted = listener.accept().await.expect("accept failed");
+
+        assert_eq!(accepted.limits().as_ref(), &limits);
+
+        client.send(&[0u8; 16]).await.expect("send failed");
+        client.send(&vec![0u8; 64]).await.expect("send failed");
+
+        assert_eq!(accepted.receive::<[u8; 16]>().await.unwrap(), [0; 16]);
+        assert!(matches!(
+            accepted.receive::<Vec<u8>>().await,
+            Err(ReceiveError::OversizedReceive { .. })
+        ));
+    }
 }
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+use super::super::socket::udp::Demultiplexer;
+use super::super::socket::Socket;
+use super::*;
+use crate::crypto::key::exchange::Exchanger;
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use tokio::net::{ToSocketAddrs, UdpSocket as TokioSocket};
+
+use tracing::{debug, info};
+
+/// A `Listener` that receives UDP datagrams on a given address. Datagrams
+/// are demultiplexed by remote address so that each remote peer gets its own
+/// `Connection`, the first datagram from a new address starts a handshake.
+pub struct UdpListener {
+    demux: Demultiplexer,
+    exchanger: Exchanger,
+    guard: HandshakeGuard,
+}
+
+impl UdpListener {
+    /// Create a new `UdpListener` that will listen on the candidate address
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{Ipv4Addr, SocketAddr};
+    /// use drop::crypto::key::exchange::Exchanger;
+    /// use drop::net::UdpListener;
+    ///
+    /// let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, 0).into();
+    /// let listener = UdpListener::new(addr, Exchanger::random());
+    /// ```
+    pub async fn new<A: ToSocketAddrs + fmt::Display>(
+        candidate: A,
+        exchanger: Exchanger,
+    ) -> Result<Self, ListenerError> {
+        Self::with_guard(candidate, exchanger, HandshakeGuard::default()).await
+    }
+
+    /// Create a new `UdpListener` that enforces the handshake limits of the
+    /// given `HandshakeGuard`
+    pub async fn with_guard<A: ToSocketAddrs + fmt::Display>(
+        candidate: A,
+        exchanger: Exchanger,
+        guard: HandshakeGuard,
+    ) -> Result<Self, ListenerError> {
+        debug!(
+            target: targets::LISTENER,
+            "listening with UDP on {} with {}",
+            candidate,
+            exchanger.keypair().public()
+        );
+
+        let socket = TokioSocket::bind(candidate).await.context(Io)?;
+        let demux = Demultiplexer::new(socket).context(Io)?;
+
+        Ok(Self {
+            demux,
+            exchanger,
+            guard,
+        })
+    }
+}
+
+#[async_trait]
+impl Listener for UdpListener {
+    type Candidate = SocketAddr;
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        Ok(vec![self.demux.local_addr()])
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.demux.local_addr())
+    }
+
+    /// Wait for a datagram from a remote address that has no `Connection` yet
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        let socket = self.demux.accept().await.context(Io)?;
+
+        info!(
+            target: targets::LISTENER,
+            "incoming UDP peer {}",
+            socket.peer_addr().context(Io)?
+        );
+
+        Ok(Box::new(socket))
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        Some(&self.guard)
+    }
+}
+
+impl fmt::Display for UdpListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "udp listener on {}", self.demux.local_addr())
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::super::socket::{unix::UnixSocket, Socket};
+use super::super::UnixPath;
+use super::{HandshakeGuard, Io, Listener, ListenerError};
+use crate::crypto::key::exchange::Exchanger;
+use crate::telemetry::targets;
+
+use async_trait::async_trait;
+
+use snafu::ResultExt;
+
+use tokio::net::UnixListener as TokioListener;
+
+use tracing::{debug, info};
+
+/// A `Listener` that accepts `Connection`s from the same host on a Unix
+/// domain socket. The socket file is removed when the `UnixListener` is
+/// dropped.
+pub struct UnixListener {
+    listener: TokioListener,
+    path: PathBuf,
+    exchanger: Exchanger,
+    guard: HandshakeGuard,
+}
+
+impl UnixListener {
+    /// Create a new `UnixListener` bound to `path`, which must not exist yet
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        exchanger: Exchanger,
+    ) -> Result<Self, ListenerError> {
+        Self::with_guard(path, exchanger, HandshakeGuard::default())
+    }
+
+    /// Create a new `UnixListener` that enforces the handshake limits of the
+    /// given `HandshakeGuard`
+    pub fn with_guard<P: AsRef<Path>>(
+        path: P,
+        exchanger: Exchanger,
+        guard: HandshakeGuard,
+    ) -> Result<Self, ListenerError> {
+        let path = path.as_ref().to_path_buf();
+
+        debug!(
+            target: targets::LISTENER,
+            "listening with unix socket on {} with {}",
+            path.display(),
+            exchanger.keypair().public()
+        );
+
+        let listener = TokioListener::bind(&path).context(Io)?;
+
+        Ok(Self {
+            listener,
+            path,
+            exchanger,
+            guard,
+        })
+    }
+
+    /// Get the path this `UnixListener` is bound to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl Listener for UnixListener {
+    type Candidate = UnixPath;
+
+    async fn candidates(&self) -> Result<Vec<Self::Candidate>, ListenerError> {
+        Ok(vec![self.path.clone().into()])
+    }
+
+    /// Accept an incoming `Connection` on the Unix domain socket
+    async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
+        let (stream, _) = self.listener.accept().await.context(Io)?;
+
+        info!(
+            target: targets::LISTENER,
+            "incoming unix connection on {}",
+            self.path.display()
+        );
+
+        Ok(Box::new(UnixSocket::new(stream)))
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        Some(&self.guard)
+    }
+}
+
+impl fmt::Display for UnixListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unix listener on {}", self.path.display())
+    }
+}
+
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -3,26 +3,36 @@ use std::io::ErrorKind;
 use std::net::SocketAddr;
 
 use super::super::socket::Socket;
+use super::limit::AmplificationGuard;
 use super::*;
+use crate::codec::bincode_options;
 use crate::crypto::key::exchange::Exchanger;
 use crate::net::socket::utp::BufferedUtpStream;
+use crate::telemetry::targets;
 
 use async_trait::async_trait;
 
+use bincode::Options;
+
 use snafu::OptionExt;
 
 use tokio::net::ToSocketAddrs;
-use tokio::task;
+use tokio::task::{self, JoinHandle};
 
 use tracing::{debug_span, info};
 use tracing_futures::Instrument;
 
-use ::utp::UtpSocket;
+use ::utp::{UtpSocket, UtpStream, UtpStreamDriver};
+
+type PendingAccept = JoinHandle<std::io::Result<(UtpStream, UtpStreamDriver)>>;
 
 /// A `Listener` that uses the micro transport protocol (μTp)
 pub struct UtpListener {
     socket: Option<UtpSocket>,
+    pending: Option<PendingAccept>,
+    addr: SocketAddr,
     exchanger: Exchanger,
+    guard: HandshakeGuard,
 }
 
 impl UtpListener {
@@ -32,11 +42,35 @@ impl UtpListener {
         addr: A,
         exchanger: Exchanger,
     ) -> Result<Self, ListenerError> {
+        Self::with_guard(addr, exchanger, HandshakeGuard::default()).await
+    }
+
+    /// Create a new `UtpListener` that enforces the handshake limits of the
+    /// given `HandshakeGuard`, including its amplification factor.
+    pub async fn with_guard<A: ToSocketAddrs>(
+        addr: A,
+        exchanger: Exchanger,
+        guard: HandshakeGuard,
+    ) -> Result<Self, ListenerError> {
+        let socket = UtpSocket::bind(addr).await.context(Io)?;
+
         Ok(Self {
-            socket: Some(UtpSocket::bind(addr).await.context(Io)?),
+            addr: socket.local_addr(),
+            socket: Some(socket),
+            pending: None,
             exchanger,
+            guard,
         })
     }
+
+    /// Number of bytes a dialer sends to secure a `Connection`. A source
+    /// that sent that much over the uTp stream is able to receive from us.
+    fn handshake_size(&self) -> u64 {
+        let pkey = self.exchanger.keypair().public();
+        let size = bincode_options().serialized_size(pkey).unwrap_or_default();
+
+        size + std::mem::size_of::<u32>() as u64
+    }
 }
 
 #[async_trait]
@@ -51,42 +85,72 @@ impl Listener for UtpListener {
     /// a one-use `Listener` and that after accepting a `Connection` this will
     /// return `None`
     fn local_addr(&self) -> Option<SocketAddr> {
-        self.socket.as_ref().map(|x| x.local_addr())
+        if self.socket.is_some() || self.pending.is_some() {
+            Some(self.addr)
+        } else {
+            None
+        }
     }
 
     /// Accept a Utp `Connection` on this `Listener`. This `Listener` is no
     /// longer usable after succesfully accepting an incoming `Connection` and
     /// will always return an error.
+    /// The accept runs in the background so dropping this future before it
+    /// completes does not lose the underlying socket.
     async fn establish(&mut self) -> Result<Box<dyn Socket>, ListenerError> {
-        let opt: Option<UtpSocket> = self.socket.take();
-        let socket: Result<UtpSocket, ListenerError> = opt
+        if let Some(socket) = self.socket.take() {
+            self.pending = Some(task::spawn(socket.accept()));
+        }
+
+        let pending = self
+            .pending
+            .as_mut()
             .ok_or_else(|| ErrorKind::AddrNotAvailable.into())
-            .context(Io);
+            .context(Io)?;
 
-        let (stream, driver) = socket?.accept().await.context(Io)?;
+        let accepted = pending.await;
+
+        self.pending = None;
+
+        let (stream, driver) = accepted
+            .map_err(|_| ErrorKind::Interrupted.into())
+            .context(Io)?
+            .context(Io)?;
         let remote = stream.peer_addr();
 
-        info!("incoming uTp connection from {}", remote);
+        info!(
+            target: targets::LISTENER,
+            "incoming uTp connection from {}", remote
+        );
 
-        task::spawn(driver.instrument(debug_span!("stream_driver")));
+        task::spawn(driver.instrument(
+            debug_span!(target: targets::LISTENER, "stream_driver"),
+        ));
 
         let buffered = BufferedUtpStream::new(stream);
+        let guarded = AmplificationGuard::new(
+            buffered,
+            self.guard.limits().amplification_factor(),
+            self.handshake_size(),
+        );
 
-        Ok(Box::new(buffered))
+        Ok(Box::new(guarded))
     }
 
     fn exchanger(&self) -> &Exchanger {
         &self.exchanger
     }
+
+    fn handshake_guard(&self) -> Option<&HandshakeGuard> {
+        Some(&self.guard)
+    }
 }
 
 impl fmt::Display for UtpListener {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.socket {
+        match self.local_addr() {
             None => write!(f, "exhausted utp listener"),
-            Some(ref socket) => {
-                write!(f, "utp listener on {}", socket.local_addr())
-            }
+            Some(addr) => write!(f, "utp listener on {}", addr),
         }
     }
 }
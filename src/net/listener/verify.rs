@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+
+use tracing::{debug, info, warn};
+
+use super::super::{
+    common::directory::{features, negotiate, Hello, Request, Response},
+    connector::{ConnectError, Connector},
+    Connection,
+};
+use crate::telemetry::targets;
+
+/// Whether a candidate address was found to be reachable from outside
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reachability {
+    /// A remote peer managed to connect to the candidate
+    Verified,
+    /// The candidate could not be verified, either because it is
+    /// unreachable or because no remote peer could probe it
+    Unverified,
+}
+
+/// Outcome of a [`CandidateVerifier`], telling which candidates should be
+/// advertised to remote peers
+///
+/// [`CandidateVerifier`]: self::CandidateVerifier
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    candidates: Vec<(SocketAddr, Reachability)>,
+    exclusive: bool,
+}
+
+impl Verification {
+    /// Create a `Verification` where each candidate has the given
+    /// `Reachability`, keeping verified candidates first
+    pub fn new(
+        candidates: impl IntoIterator<Item = (SocketAddr, Reachability)>,
+        exclusive: bool,
+    ) -> Self {
+        let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+
+        // stable, so candidates keep their order otherwise
+        candidates.sort_by_key(|(_, r)| *r != Reachability::Verified);
+
+        Self {
+            candidates,
+            exclusive,
+        }
+    }
+
+    /// Get all candidates tagged with their `Reachability`, verified ones
+    /// first
+    pub fn candidates(&self) -> &[(SocketAddr, Reachability)] {
+        &self.candidates
+    }
+
+    /// Get the `Reachability` of `addr` if it was verified
+    pub fn reachability(&self, addr: &SocketAddr) -> Option<Reachability> {
+        self.candidates
+            .iter()
+            .find(|(candidate, _)| candidate == addr)
+            .map(|(_, reachability)| *reachability)
+    }
+
+    /// Get the candidates that should be advertised, verified ones first.
+    /// Unverified candidates are left out when the verification was
+    /// exclusive, unless none of the candidates could be verified.
+    pub fn advertised(&self) -> Vec<SocketAddr> {
+        let verified = self
+            .candidates
+            .iter()
+            .filter(|(_, r)| *r == Reachability::Verified)
+            .count();
+
+        let count = if self.exclusive && verified > 0 {
+            verified
+        } else {
+            self.candidates.len()
+        };
+
+        self.candidates[..count].iter().map(|(a, _)| *a).collect()
+    }
+
+    /// Check whether this `Verification` has no candidates
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Apply this `Verification` to a new set of `candidates`, those that
+    /// were not probed being unverified
+    #[cfg(feature = "system")]
+    pub(crate) fn apply(&self, candidates: &[SocketAddr]) -> Self {
+        Self::new(
+            candidates.iter().map(|addr| {
+                let reachability =
+                    self.reachability(addr).unwrap_or(Reachability::Unverified);
+
+                (*addr, reachability)
+            }),
+            self.exclusive,
+        )
+    }
+}
+
+/// Checks which candidate addresses of a `Listener` are reachable from
+/// outside before they are advertised, by asking a directory server to
+/// connect back to each of them using `Request::ProbeMe`. <br />
+/// Directory servers only probe addresses on the host the request comes
+/// from, other candidates are always unverified.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CandidateVerifier {
+    exclusive: bool,
+}
+
+impl CandidateVerifier {
+    /// Create a `CandidateVerifier` that advertises verified candidates
+    /// before unverified ones
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only advertise verified candidates, unless none of them could be
+    /// verified
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Connect to the directory server at `helper` using `connector` and
+    /// ask it to probe each of the `candidates`
+    pub async fn verify<C>(
+        &self,
+        connector: &C,
+        helper: SocketAddr,
+        candidates: &[SocketAddr],
+    ) -> Result<Verification, ConnectError>
+    where
+        C: Connector<Candidate = SocketAddr> + ?Sized,
+    {
+        let pkey = *connector.exchanger().keypair().public();
+        let mut connection =
+            Connection::new(connector.establish(&pkey, &helper).await?);
+        let hello = negotiate(&mut connection)
+            .await
+            .unwrap_or_else(Hello::legacy);
+
+        Ok(self.probe(&mut connection, &hello, candidates).await)
+    }
+
+    /// Ask the directory server on the other end of `connection`, which
+    /// negotiated `hello`, to probe each of the `candidates`
+    pub(crate) async fn probe(
+        &self,
+        connection: &mut Connection,
+        hello: &Hello,
+        candidates: &[SocketAddr],
+    ) -> Verification {
+        let mut results = candidates
+            .iter()
+            .map(|addr| (*addr, Reachability::Unverified))
+            .collect::<Vec<_>>();
+
+        if !hello.supports(features::PROBE) {
+            warn!(
+                target: targets::DIRECTORY,
+                "directory can not probe candidates, none verified"
+            );
+            return Verification::new(results, self.exclusive);
+        }
+
+        for (addr, reachability) in results.iter_mut() {
+            if let Err(e) =
+                connection.send_plain(&Request::ProbeMe(*addr)).await
+            {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "failed to request probe of {}: {}", addr, e
+                );
+                break;
+            }
+
+            match connection.receive_plain::<Response>().await {
+                Ok(Response::Probed {
+                    addr: probed,
+                    reachable: true,
+                }) if probed == *addr => {
+                    *reachability = Reachability::Verified;
+                }
+                Ok(response) => {
+                    debug!(
+                        target: targets::DIRECTORY,
+                        "candidate {} not verified: {}", addr, response
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        target: targets::DIRECTORY,
+                        "no answer to probe of {}: {}", addr, e
+                    );
+                    break;
+                }
+            }
+        }
+
+        info!(
+            target: targets::DIRECTORY,
+            "verified {} of {} candidates",
+            results
+                .iter()
+                .filter(|(_, r)| *r == Reachability::Verified)
+                .count(),
+            results.len()
+        );
+
+        Verification::new(results, self.exclusive)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::super::Listener;
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::{server::DirectoryServer, TcpConnector, TcpListener};
+    use crate::test::*;
+
+    #[tokio::test]
+    async fn bogus_candidate_is_demoted() {
+        let server = next_test_ip4();
+        let listener = TcpListener::new(server, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (dir_server, exit) = DirectoryServer::new(Box::new(listener));
+        let handle = tokio::spawn(async move { dir_server.serve().await });
+
+        // never accepts but the kernel completes connections to it
+        let open = TcpListener::new(next_test_ip4(), Exchanger::random())
+            .await
+            .expect("listen failed");
+        let reachable = open.local_addr().expect("no address");
+        let bogus = next_test_ip4();
+        let elsewhere = (Ipv4Addr::new(192, 0, 2, 1), 9600).into();
+        let candidates = [bogus, elsewhere, reachable];
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let verification = CandidateVerifier::new()
+            .verify(&connector, server, &candidates)
+            .await
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.candidates(),
+            [
+                (reachable, Reachability::Verified),
+                (bogus, Reachability::Unverified),
+                (elsewhere, Reachability::Unverified),
+            ]
+        );
+        assert_eq!(
+            verification.advertised(),
+            vec![reachable, bogus, elsewhere]
+        );
+
+        let verification = CandidateVerifier::new()
+            .exclusive(true)
+            .verify(&connector, server, &candidates)
+            .await
+            .expect("verification failed");
+
+        assert_eq!(verification.advertised(), vec![reachable]);
+
+        exit.send(()).expect("exit failed");
+        handle
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    #[test]
+    fn exclusive_falls_back_to_unverified() {
+        let addr = (Ipv4Addr::LOCALHOST, 9600).into();
+        let verification =
+            Verification::new(vec![(addr, Reachability::Unverified)], true);
+
+        assert_eq!(verification.advertised(), vec![addr]);
+        assert_eq!(
+            verification.reachability(&addr),
+            Some(Reachability::Unverified)
+        );
+    }
+}
@@ -1,40 +1,158 @@
+pub use crate::codec;
+
 /// Common data shared between `Listener`s and `Connector`s
 pub(crate) mod common;
 pub use common::directory::Info as DirectoryInfo;
+pub use common::directory::SignedInfo as SignedDirectoryInfo;
+pub use common::directory::LEGACY_VERSION as DIRECTORY_LEGACY_VERSION;
+pub use common::directory::PROTOCOL_VERSION as DIRECTORY_PROTOCOL_VERSION;
 
 /// Utilities to connect to other peers in a secure fashion
 mod connector;
-pub use connector::*;
+#[cfg(feature = "quic")]
+pub use connector::QuicConnector;
+#[cfg(feature = "unstable")]
+pub use connector::UtpConnector;
+pub use connector::{
+    BackoffConnector, BackoffConnectorBuilder, ConnectError, Connector,
+    ConnectorExt, DialProgress, DirectoryConnector, DirectoryStats,
+    MemoryConnector, Pacer, Pacing, ProxiedConnector, Proxy, ProxyKind,
+    ProxyTarget, Rejected, ResolveConnector, TcpConnector, Timeout,
+    UdpConnector, DEFAULT_DIAL_INTERVAL, DEFAULT_MAX_CONCURRENT_DIALS,
+    DEFAULT_MAX_DIRECTORIES, DEFAULT_PEER_CACHE_AGE, DEFAULT_PEER_CACHE_SIZE,
+    DEFAULT_REQUEST_TIMEOUT,
+};
+#[cfg(unix)]
+pub use connector::{UnixConnector, UnixPath};
 
 /// Utilities to accept incoming connections from peers
 mod listener;
-pub use listener::*;
+#[cfg(feature = "quic")]
+pub use listener::QuicListener;
+#[cfg(unix)]
+pub use listener::UnixListener;
+#[cfg(feature = "unstable")]
+pub use listener::UtpListener;
+pub use listener::{
+    AddressChanged, AddressSource, AddressWatch, Authorization, Authorizer,
+    CandidateVerifier, ChainListener, ConnectionIdentity, Decision,
+    DirectoryCandidate, DirectoryListener, DirectoryRegistration,
+    FilterListener, HandshakeGuard, HandshakeLimits, HandshakePermit,
+    HandshakeStats, Listener, ListenerError, ListenerExt, MapListener,
+    MemoryListener, NoAddress, Reachability, RegistrationRejected, Reversal,
+    ReversalDialer, ReversalListener, RouteAddress, TcpListener, Throttled,
+    UdpListener, Unauthorized, Verification, DEFAULT_ADDRESS_CHECK_INTERVAL,
+    DEFAULT_AUTHORIZE_TIMEOUT,
+};
 
 /// Socket implementation for various types
 mod socket;
 
+/// Alternative handshakes used to secure connections
+mod handshake;
+pub use handshake::HandshakeMode;
+
+/// Observability hooks for connection establishment
+mod observer;
+pub use observer::{
+    clear_connection_observer, set_connection_observer, ConnectionEstablished,
+    ConnectionObserver, Direction,
+};
+
+/// Cancel safe reading of framed messages
+mod frame;
+use frame::FrameReader;
+
+/// Dedicated threads for cryptographic operations
+mod pool;
+pub use pool::CryptoPool;
+
+/// Reuse of `Connection`s by clients of many peers
+mod connection_pool;
+pub use connection_pool::{
+    ConnectionPool, PoolLimits, PooledConnection, DEFAULT_POOL_CAPACITY,
+    DEFAULT_POOL_IDLE_TIMEOUT,
+};
+
+/// Splitting of large messages into fragments
+mod fragment;
+#[cfg(test)]
+pub(crate) use fragment::Envelope;
+pub(crate) use fragment::Outgoing;
+use fragment::Reassembler;
+pub use fragment::{
+    Fragmentation, DEFAULT_MAX_REASSEMBLED_SIZE, DEFAULT_MAX_REASSEMBLIES,
+    DEFAULT_REASSEMBLY_TIMEOUT,
+};
+
+/// Padding of outgoing frames
+mod padding;
+pub use padding::PaddingPolicy;
+
+/// Limits applied to `Connection`s
+mod limits;
+pub use limits::{
+    ConnectionLimits, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_INFLIGHT_RECEIVES,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+
+/// Recovery from failures when establishing `Connection`s
+mod recovery;
+pub(crate) use recovery::AcceptBackoff;
+pub use recovery::{
+    is_exhausted, is_retryable, Phase, MAX_ACCEPT_BACKOFF, MIN_ACCEPT_BACKOFF,
+};
+
+/// Out of band exchange of contact information
+mod contact;
+pub use contact::{ContactCard, ContactError, CONTACT_SCHEME};
+
+/// Typed sessions generated by the `protocol` attribute
+mod session;
+pub use drop_derive::protocol;
+#[doc(hidden)]
+pub use session::{receive_expected, send_message};
+pub use session::{Protocol, ProtocolError};
+
+/// Traffic statistics of `Connection`s
+mod stats;
+pub use stats::ConnectionStats;
+use stats::Counters;
+
 /// Pre-made servers that accomplish common tasks
 pub mod server;
 
-pub(self) mod utils;
+mod utils;
 
-use std::{fmt, io::Error as IoError, mem, net::SocketAddr};
+use std::{
+    borrow::Cow, fmt, io::Error as IoError, mem, net::SocketAddr, sync::Arc,
+    time::Duration,
+};
 
-use bincode::{deserialize, serialize, ErrorKind as BincodeErrorKind};
+use bincode::{ErrorKind as BincodeErrorKind, Options};
 use serde::{Deserialize, Serialize};
-use snafu::{Backtrace, ResultExt, Snafu};
-use tokio::io::{
-    split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf,
-    WriteHalf,
+use snafu::{ensure, Backtrace, IntoError, OptionExt, ResultExt, Snafu};
+use tokio::{
+    io::{split, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    time,
 };
-use tracing::{debug, debug_span, info};
-use tracing_futures::Instrument;
+use tracing::{debug, info, warn};
 
-use self::socket::Socket;
+use self::socket::udp::DatagramTooLarge;
+pub use self::socket::{
+    DatagramOverflow, LinkProfile, LinkStats, MemoryRegistry, NetworkProfile,
+    Socket, MAX_DATAGRAM_SIZE, MIN_RETRANSMISSION_TIMEOUT,
+};
+use crate::codec::bincode_options;
 use crate::crypto::{
-    key::exchange::{Exchanger, PublicKey},
-    stream::{DecryptError, EncryptError, Pull, Push},
+    key::exchange::{Exchanger, Exporter, PublicKey, Session},
+    sign::{SignError, VerifyError},
+    stream::{
+        DecryptError, EncryptError, Pull, Push, ENCRYPTION_OVERHEAD,
+        HEADER_SIZE,
+    },
 };
+use crate::telemetry::targets;
 
 /// Type of errors returned when serializing/deserializing
 pub type SerializerError = Box<BincodeErrorKind>;
@@ -76,6 +194,38 @@ pub enum SendError {
         /// Underlying error cause
         backtrace: Backtrace,
     },
+
+    #[snafu(display("frame not sent within {:?}", after))]
+    /// Writing a frame took longer than the send timeout of the
+    /// `ConnectionLimits`
+    SendTimeout {
+        /// Time waited before giving up
+        after: Duration,
+    },
+
+    #[snafu(display("frame of {} bytes is too large", size))]
+    /// Attempted to send more than `MAX_FRAME_SIZE` bytes in a single frame,
+    /// or a message larger than allowed by the `ConnectionLimits`
+    FrameTooLarge {
+        /// Size of the rejected frame
+        size: usize,
+        /// Error backtrace
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "frame of {} bytes does not fit in a {} bytes datagram",
+        size,
+        max
+    ))]
+    /// Attempted to send a frame larger than a single datagram on a
+    /// datagram based `Socket` such as the one used by `UdpConnector`
+    TooLargeForDatagram {
+        /// Size of the rejected frame including its size prefix
+        size: usize,
+        /// Largest frame that fits in a single datagram
+        max: usize,
+    },
 }
 
 #[derive(Debug, Snafu)]
@@ -115,6 +265,62 @@ pub enum ReceiveError {
         /// Underlying error cause
         source: IoError,
     },
+
+    #[snafu(display("oversized frame: {} bytes over {} limit", size, max))]
+    /// The remote peer announced a frame larger than allowed
+    OversizedReceive {
+        /// Announced size of the frame
+        size: usize,
+        /// Maximum allowed size
+        max: usize,
+        /// Error backtrace
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("connection closed by remote peer"))]
+    /// The remote peer closed the `Connection` in between two messages
+    Closed,
+
+    #[snafu(display("nothing received for {:?}", after))]
+    /// Nothing was received for longer than the idle timeout of the
+    /// `ConnectionLimits`. The `Connection` is still usable unless part of
+    /// a frame was already received.
+    IdleTimeout {
+        /// Time waited before giving up
+        after: Duration,
+        /// Whether the remote peer stalled in the middle of a frame
+        partial: bool,
+    },
+
+    #[snafu(display("message {} was not received entirely in time", id))]
+    /// Some fragments of a message were not received within the reassembly
+    /// timeout of the `Fragmentation`. The message is discarded but the
+    /// `Connection` is still usable.
+    IncompleteMessage {
+        /// Identifier of the discarded message
+        id: u64,
+    },
+
+    #[snafu(display("invalid fragment of message {}: {}", id, reason))]
+    /// The remote peer sent a fragment that does not fit in the message it
+    /// belongs to
+    BadFragment {
+        /// Identifier of the message
+        id: u64,
+        /// What was wrong with the fragment
+        reason: &'static str,
+    },
+}
+
+impl ReceiveError {
+    /// Check whether the `Connection` can still be used after this error
+    fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            Self::IncompleteMessage { .. }
+                | Self::IdleTimeout { partial: false, .. }
+        )
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -140,6 +346,43 @@ pub enum SecureError {
         /// Underlying error cause
         source: SendError,
     },
+
+    #[snafu(display("peer does not use the {} handshake", expected))]
+    /// The remote peer secured the `Connection` using another `HandshakeMode`
+    HandshakeMismatch {
+        /// The `HandshakeMode` expected by the local end
+        expected: HandshakeMode,
+    },
+
+    #[snafu(display("could not prove identity: {}", source))]
+    /// The proof of our identity could not be produced during a private
+    /// handshake
+    ProveIdentity {
+        /// Underlying error cause
+        source: SignError,
+    },
+
+    #[snafu(display("invalid proof of identity: {}", source))]
+    /// The remote peer failed to prove its identity during a private
+    /// handshake
+    IdentityProof {
+        /// Underlying error cause
+        source: VerifyError,
+    },
+
+    #[snafu(display("unsecured connection"))]
+    /// Attempted to export keying material from an unsecured `Connection`
+    UnsecuredExport {
+        /// Error backtrace
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("connection is broken"))]
+    /// Attempted to export keying material from a broken `Connection`
+    BrokenExport {
+        /// Error backtrace
+        backtrace: Backtrace,
+    },
 }
 
 /// Encrypted connection state
@@ -152,13 +395,85 @@ enum ConnectionState {
     Broken,
 }
 
+/// Number of bytes used to frame every message sent on a `Connection`
+const FRAME_OVERHEAD: u64 = mem::size_of::<u32>() as u64;
+
+/// Maximum size of the plain frame announcing the dialer's public key that is
+/// accepted while securing a `Connection`
+pub const MAX_HANDSHAKE_SIZE: usize = 1024;
+
+/// Maximum number of bytes of plaintext that can be sent as a single frame on
+/// a secured `Connection`, so that its encrypted size still fits in the frame
+/// header
+pub const MAX_FRAME_SIZE: usize =
+    u32::MAX as usize - ENCRYPTION_OVERHEAD - HEADER_SIZE;
+
+/// Largest capacity of the buffers a `Connection` keeps between sends.
+/// Messages that fit are serialized and encrypted without allocating once
+/// the buffers have grown to their size, larger ones use new buffers.
+pub const SCRATCH_CAPACITY: usize = 64 * 1024;
+
+/// Buffers reused by a `Connection` and its `ConnectionWrite` across sends
+#[derive(Default)]
+struct Scratch {
+    /// Serialized message
+    plain: Vec<u8>,
+    /// Encrypted frame
+    sealed: Vec<u8>,
+}
+
+impl Scratch {
+    /// Take the serialization buffer, emptied
+    fn take_plain(&mut self) -> Vec<u8> {
+        let mut plain = mem::take(&mut self.plain);
+
+        plain.clear();
+        plain
+    }
+
+    /// Give back a buffer obtained using `Scratch::take_plain`
+    fn restore_plain(&mut self, plain: Vec<u8>) {
+        self.plain = plain;
+        self.trim();
+    }
+
+    /// Release buffers that grew larger than `SCRATCH_CAPACITY`
+    fn trim(&mut self) {
+        for buffer in [&mut self.plain, &mut self.sealed] {
+            if buffer.capacity() > SCRATCH_CAPACITY {
+                *buffer = Vec::new();
+            }
+        }
+    }
+}
+
+/// Compute the number of bytes that sending the given message on a secured
+/// `Connection` will write to the underlying `Socket`. This accounts for
+/// framing and encryption but not for the stream header that is sent once
+/// along with the first message of each `Connection`. <br />
+/// This assumes frames are not padded, see `PaddingPolicy::wire_size`
+/// otherwise.
+pub fn wire_size<T: Serialize + ?Sized>(message: &T) -> Result<u64, SendError> {
+    PaddingPolicy::None.wire_size(message)
+}
+
 /// A `Connection` is a two way encrypted and authenticated communication
 /// channel between two peers.
 pub struct Connection {
     socket: Box<dyn Socket>,
     state: ConnectionState,
-    buffer: Vec<u8>,
+    frame: FrameReader,
     remote_pkey: Option<PublicKey>,
+    initiator: Option<PublicKey>,
+    reversed: bool,
+    exporter: Option<Exporter>,
+    padding: PaddingPolicy,
+    limits: Arc<ConnectionLimits>,
+    read_only: bool,
+    next_id: u64,
+    reassembly: Reassembler,
+    scratch: Scratch,
+    stats: Arc<Counters>,
 }
 
 impl Connection {
@@ -167,13 +482,71 @@ impl Connection {
         Self {
             socket,
             state: ConnectionState::Connected,
-            buffer: Vec::new(),
+            frame: FrameReader::default(),
             remote_pkey: None,
+            initiator: None,
+            reversed: false,
+            exporter: None,
+            padding: PaddingPolicy::None,
+            limits: Arc::default(),
+            read_only: false,
+            next_id: 0,
+            reassembly: Reassembler::default(),
+            scratch: Scratch::default(),
+            stats: Arc::default(),
         }
     }
 
+    /// Apply the given `ConnectionLimits` to this `Connection` and to both
+    /// halves it is split into
+    pub fn set_limits(&mut self, limits: Arc<ConnectionLimits>) {
+        if let Err(e) = self.socket.set_keepalive(limits.keepalive_idle()) {
+            warn!(
+                target: targets::CONNECTION,
+                "failed to configure keepalive: {}", e
+            );
+        }
+
+        self.limits = limits;
+    }
+
+    /// Give up on receiving after nothing was read for `duration`, see
+    /// `ConnectionLimits::idle_timeout`
+    pub fn set_read_timeout(&mut self, duration: Duration) {
+        self.limits = Arc::new((*self.limits).clone().idle_timeout(duration));
+    }
+
+    /// Give up on sending after a frame could not be written for
+    /// `duration`, see `ConnectionLimits::send_timeout`
+    pub fn set_write_timeout(&mut self, duration: Duration) {
+        self.limits = Arc::new((*self.limits).clone().send_timeout(duration));
+    }
+
+    /// Get the `ConnectionLimits` applied to this `Connection`
+    pub fn limits(&self) -> &Arc<ConnectionLimits> {
+        &self.limits
+    }
+
+    /// Get the traffic that went through this `Connection` once secured.
+    /// Both halves obtained from `Connection::split` share the same counters.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+
+    /// Set the `PaddingPolicy` applied to frames sent on this `Connection`.
+    /// The remote peer does not need to use the same policy to receive them.
+    pub fn set_padding(&mut self, padding: PaddingPolicy) {
+        self.padding = padding;
+    }
+
+    /// Get the `PaddingPolicy` applied to frames sent on this `Connection`
+    pub fn padding(&self) -> &PaddingPolicy {
+        &self.padding
+    }
+
     /// Receive `Deserialize` message on this `Connection` without using
-    /// encryption
+    /// encryption. Messages larger than the maximum message size of the
+    /// `ConnectionLimits` are refused.
     ///
     /// # Example
     /// ```ignore
@@ -184,27 +557,59 @@ impl Connection {
     where
         T: for<'de> Deserialize<'de> + Sized,
     {
-        let size = Self::read_size(&mut self.socket).await? as usize;
+        self.receive_plain_bounded(self.limits.message_size()).await
+    }
 
-        self.buffer.resize(size, 0);
+    /// Receive a plain message of at most `max` bytes. A `Connection` on which
+    /// a plain receive failed is broken and refuses further plain receives
+    /// since a partially read frame could otherwise be misinterpreted.
+    async fn receive_plain_bounded<T>(
+        &mut self,
+        max: usize,
+    ) -> Result<T, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de> + Sized,
+    {
+        ensure!(
+            !matches!(self.state, ConnectionState::Broken),
+            CorruptedReceive
+        );
 
-        self.socket
-            .read_exact(&mut self.buffer[..size])
+        self.frame
+            .read_bounded(&mut self.socket, max)
             .await
-            .map_err(|e| {
+            .inspect_err(|_| {
                 self.state = ConnectionState::Broken;
-                e
-            })
-            .context(ReceiveIo)?;
+            })?;
 
-        deserialize(&self.buffer)
+        bincode_options()
+            .deserialize(self.frame.data())
             .context(DeserializeReceive)
-            .map_err(|e| {
+            .inspect_err(|_| {
                 self.state = ConnectionState::Broken;
-                e
             })
     }
 
+    /// Receive the content of the next plain frame without deserializing it.
+    /// This allows trying several message types on the same frame.
+    pub async fn receive_plain_frame(
+        &mut self,
+    ) -> Result<Vec<u8>, ReceiveError> {
+        ensure!(
+            !matches!(self.state, ConnectionState::Broken),
+            CorruptedReceive
+        );
+
+        self.frame
+            .read_bounded(&mut self.socket, self.limits.message_size())
+            .await
+            .inspect_err(|_| {
+                self.state = ConnectionState::Broken;
+            })?;
+
+        Ok(self.frame.data().to_vec())
+    }
+
     /// Send a `Serialize` message on this `Connection` without using decryption
     ///
     /// # Example
@@ -216,43 +621,46 @@ impl Connection {
     where
         T: Serialize,
     {
-        let serialized = serialize(message).context(SerializeSend)?;
+        let mut serialized = self.scratch.take_plain();
 
-        debug!("sending {} bytes as plain data", serialized.len());
+        Self::serialize_into(&mut serialized, message)?;
 
-        Self::write_size(&mut self.socket, serialized.len() as u32)
-            .await
-            .map_err(|e| {
-                self.state = ConnectionState::Broken;
-                e
-            })?;
+        debug!(
+            target: targets::CONNECTION,
+            "sending {} bytes as plain data", serialized.len()
+        );
 
-        self.socket
-            .write_all(&serialized)
-            .await
-            .map_err(|e| {
-                self.state = ConnectionState::Broken;
-                e
-            })
-            .context(SendIo)
-    }
+        let result =
+            Self::write_limited(&mut self.socket, &serialized, &self.limits)
+                .await;
 
-    async fn read_size<R: AsyncRead + Unpin + ?Sized>(
-        socket: &mut R,
-    ) -> Result<u32, ReceiveError> {
-        let mut buf = [0u8; mem::size_of::<u32>()];
-        socket.read_exact(&mut buf).await.context(ReceiveIo)?;
+        self.scratch.restore_plain(serialized);
 
-        deserialize(&buf[..]).context(DeserializeReceive)
+        result.inspect_err(|_| {
+            self.state = ConnectionState::Broken;
+        })
     }
 
+    /// Write the size prefix of a frame, encoded the same way as bincode
+    /// encodes a `u32` using `bincode_options`
     async fn write_size<W: AsyncWrite + Unpin>(
         socket: &mut W,
         size: u32,
     ) -> Result<(), SendError> {
-        let data = serialize(&size).context(SerializeSend)?;
+        socket
+            .write_all(&size.to_le_bytes())
+            .await
+            .map_err(Self::send_io)
+    }
 
-        socket.write_all(&data).await.context(SendIo)
+    /// Report frames rejected by datagram `Socket`s as `TooLargeForDatagram`
+    fn send_io(error: IoError) -> SendError {
+        match DatagramTooLarge::from_io(&error) {
+            Some(DatagramTooLarge { size, max }) => {
+                TooLargeForDatagram { size, max }.build()
+            }
+            None => SendIo.into_error(error),
+        }
     }
 
     /// Receive a `Deserialize` message from the underlying `Connection`.
@@ -267,12 +675,17 @@ impl Connection {
                 Self::receive_internal(
                     pull,
                     self.socket.as_mut(),
-                    &mut self.buffer,
+                    &mut self.frame,
+                    &self.limits,
+                    &mut self.reassembly,
+                    &self.stats,
                 )
                 .await
-                .map_err(|e| {
-                    self.state = ConnectionState::Broken;
-                    e
+                .and_then(|data| Self::deserialize(&data))
+                .inspect_err(|e| {
+                    if !e.is_recoverable() {
+                        self.state = ConnectionState::Broken;
+                    }
                 })
             }
             ConnectionState::Connected => UnsecuredReceive.fail(),
@@ -280,28 +693,125 @@ impl Connection {
         }
     }
 
-    async fn receive_internal<
-        T: Sized + for<'de> Deserialize<'de> + Send + fmt::Debug,
-        R: AsyncRead + Unpin + ?Sized,
-    >(
-        pull: &mut Pull,
+    /// Receive the decrypted content of the next frame sent by the remote
+    /// peer, without deserializing it. This is the building block on top of
+    /// which `Connection::receive` is implemented, and can be used to
+    /// receive data sent using `Connection::send_frame`.
+    pub async fn receive_frame(&mut self) -> Result<Vec<u8>, ReceiveError> {
+        match &mut self.state {
+            ConnectionState::Secured(ref mut pull, _) => {
+                Self::receive_internal(
+                    pull,
+                    self.socket.as_mut(),
+                    &mut self.frame,
+                    &self.limits,
+                    &mut self.reassembly,
+                    &self.stats,
+                )
+                .await
+                .map(Cow::into_owned)
+                .inspect_err(|e| {
+                    if !e.is_recoverable() {
+                        self.state = ConnectionState::Broken;
+                    }
+                })
+            }
+            ConnectionState::Connected => UnsecuredReceive.fail(),
+            ConnectionState::Broken => CorruptedReceive.fail(),
+        }
+    }
+
+    /// Read frames from `socket` and decrypt them until a whole message was
+    /// received, reassembling it from fragments if needed
+    async fn receive_internal<'a, R: AsyncRead + Unpin + ?Sized>(
+        pull: &'a mut Pull,
         socket: &mut R,
-        mut buffer: &mut Vec<u8>,
-    ) -> Result<T, ReceiveError> {
-        let size = Connection::read_size(socket)
-            .instrument(debug_span!("read_size"))
-            .await? as usize;
+        frame: &mut FrameReader,
+        limits: &ConnectionLimits,
+        reassembly: &mut Reassembler,
+        stats: &Counters,
+    ) -> Result<Cow<'a, [u8]>, ReceiveError> {
+        let Some(fragmentation) = limits.fragmentation() else {
+            Self::read_limited(socket, frame, limits, reassembly, stats)
+                .await?;
 
-        // FIXME: avoid trusting network input and run out of memory
-        buffer.resize(size, 0);
+            let message = pull.decrypt_bytes(frame.data()).context(Decrypt)?;
 
-        socket
-            .read_exact(&mut buffer)
-            .instrument(debug_span!("read_data"))
-            .await
-            .context(ReceiveIo)?;
+            stats.received_message();
+
+            return Ok(Cow::Borrowed(message));
+        };
+
+        loop {
+            Self::read_limited(socket, frame, limits, reassembly, stats)
+                .await?;
+
+            let plaintext =
+                pull.decrypt_bytes(frame.data()).context(Decrypt)?;
+
+            if let Some(message) = reassembly.push(plaintext, fragmentation)? {
+                stats.received_message();
 
-        pull.decrypt(buffer).context(Decrypt)
+                return Ok(Cow::Owned(message));
+            }
+        }
+    }
+
+    /// Read the next frame from `socket` within the given `limits`, failing
+    /// if a message being reassembled expires first. This is cancel safe like
+    /// `FrameReader::read`.
+    async fn read_limited<R: AsyncRead + Unpin + ?Sized>(
+        socket: &mut R,
+        frame: &mut FrameReader,
+        limits: &ConnectionLimits,
+        reassembly: &mut Reassembler,
+        stats: &Counters,
+    ) -> Result<(), ReceiveError> {
+        let max = limits.frame_size();
+        let read = async {
+            let Some(after) = limits.idle() else {
+                return frame.read_bounded(socket, max).await;
+            };
+
+            match time::timeout(after, frame.read_bounded(socket, max)).await {
+                Ok(read) => read,
+                Err(_) => IdleTimeout {
+                    after,
+                    partial: frame.in_progress(),
+                }
+                .fail(),
+            }
+        };
+
+        let result = match reassembly.deadline() {
+            Some(deadline) => match time::timeout_at(deadline, read).await {
+                Ok(read) => read,
+                Err(_) => {
+                    let id = reassembly
+                        .expire(time::Instant::now())
+                        .expect("no message expired");
+
+                    warn!(
+                        target: targets::CONNECTION,
+                        "discarding incomplete message {}", id
+                    );
+
+                    IncompleteMessage { id }.fail()
+                }
+            },
+            None => read.await,
+        };
+
+        result.inspect(|_| stats.received_frame(frame.data().len()))
+    }
+
+    fn deserialize<T>(data: &[u8]) -> Result<T, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        bincode_options()
+            .deserialize(data)
+            .context(DeserializeReceive)
     }
 
     /// Send a `Serialize` message using the underlying `Connection`.
@@ -309,33 +819,121 @@ impl Connection {
     where
         T: Serialize + Send + fmt::Debug,
     {
-        match &mut self.state {
-            ConnectionState::Secured(_, ref mut push) => {
-                Self::send_internal(message, &mut self.socket, push)
-                    .await
-                    .map_err(|e| {
-                        self.state = ConnectionState::Broken;
-                        e
-                    })
+        let mut plaintext = self.scratch.take_plain();
+        let result = match Self::serialize_into(&mut plaintext, message) {
+            Ok(()) => self.send_frame(&plaintext).await,
+            Err(e) => Err(e),
+        };
+
+        self.scratch.restore_plain(plaintext);
+
+        result
+    }
+
+    /// Encrypt and send exactly the given bytes as a single frame, without
+    /// serializing them. This is the building block on top of which
+    /// `Connection::send` is implemented: the remote peer can use
+    /// `Connection::receive` if `plaintext` is a serialized message, or
+    /// `Connection::receive_frame` to get back the same bytes. <br />
+    /// Frames larger than the maximum message size of the `ConnectionLimits`
+    /// once padded are rejected, unless the `ConnectionLimits` enable
+    /// `Fragmentation`, in which case they are sent as many frames and
+    /// reassembled by the remote peer.
+    pub async fn send_frame(
+        &mut self,
+        plaintext: &[u8],
+    ) -> Result<(), SendError> {
+        let push = match &mut self.state {
+            ConnectionState::Secured(_, ref mut push) => push,
+            ConnectionState::Connected => return UnsecuredSend.fail(),
+            ConnectionState::Broken => return CorruptedSend.fail(),
+        };
+        let padding = &self.padding;
+        let mut outgoing = Outgoing::new(
+            Cow::Borrowed(plaintext),
+            &self.limits,
+            padding,
+            &mut self.next_id,
+        )?;
+        let mut result = Ok(());
+
+        while let Some(frame) = outgoing.next_frame() {
+            result = Self::send_internal(
+                &frame,
+                &mut self.socket,
+                push,
+                padding,
+                &self.limits,
+                &mut self.scratch.sealed,
+                &self.stats,
+            )
+            .await;
+
+            if result.is_err() {
+                self.state = ConnectionState::Broken;
+                break;
             }
-            ConnectionState::Connected => UnsecuredSend.fail(),
-            ConnectionState::Broken => CorruptedSend.fail(),
         }
+
+        self.scratch.trim();
+
+        result.inspect(|_| self.stats.sent_message())
     }
 
-    async fn send_internal<
-        T: Serialize + Send + fmt::Debug,
-        W: AsyncWrite + Unpin,
-    >(
+    fn serialize<T: Serialize>(message: &T) -> Result<Vec<u8>, SendError> {
+        bincode_options().serialize(message).context(SerializeSend)
+    }
+
+    /// Serialize `message` at the end of `buffer`
+    fn serialize_into<T: Serialize>(
+        buffer: &mut Vec<u8>,
         message: &T,
+    ) -> Result<(), SendError> {
+        codec::serialize_into(buffer, message).context(SerializeSend)
+    }
+
+    /// Pad and encrypt `plaintext` into `sealed` and write it to `socket` as
+    /// one frame
+    async fn send_internal<W: AsyncWrite + Unpin>(
+        plaintext: &[u8],
         socket: &mut W,
         push: &mut Push,
+        padding: &PaddingPolicy,
+        limits: &ConnectionLimits,
+        sealed: &mut Vec<u8>,
+        stats: &Counters,
+    ) -> Result<(), SendError> {
+        padding.seal_into(plaintext, limits.message_size(), push, sealed)?;
+
+        Self::write_limited(socket, sealed, limits)
+            .await
+            .inspect(|_| stats.sent_frame(sealed.len()))
+    }
+
+    /// Write `data` to the socket as one size prefixed frame within the
+    /// send timeout of `limits`
+    async fn write_limited<W: AsyncWrite + Unpin>(
+        socket: &mut W,
+        data: &[u8],
+        limits: &ConnectionLimits,
     ) -> Result<(), SendError> {
-        let data = push.encrypt(message).context(Encrypt)?;
+        match limits.send_time() {
+            Some(after) => time::timeout(after, Self::write_data(socket, data))
+                .await
+                .ok()
+                .context(SendTimeout { after })?,
+            None => Self::write_data(socket, data).await,
+        }
+    }
 
+    /// Write `data` to the socket as one size prefixed frame
+    async fn write_data<W: AsyncWrite + Unpin>(
+        socket: &mut W,
+        data: &[u8],
+    ) -> Result<(), SendError> {
         Connection::write_size(socket, data.len() as u32).await?;
 
-        socket.write_all(&data).await.context(SendIo)
+        socket.write_all(data).await.map_err(Self::send_io)
     }
 
     /// Perform the key exchange and create a new `Session`
@@ -344,12 +942,17 @@ impl Connection {
         exchanger: &Exchanger,
         remote: &PublicKey,
     ) -> Result<(), SecureError> {
-        let session = exchanger.exchange(remote);
+        self.establish(exchanger.exchange(remote));
+
+        Ok(())
+    }
+
+    /// Secure this `Connection` using the given `Session`
+    fn establish(&mut self, session: Session) {
+        self.exporter = Some(session.exporter().clone());
         let (push, pull): (Push, Pull) = session.into();
 
         self.state = ConnectionState::Secured(pull, push);
-
-        Ok(())
     }
 
     /// Returns the remote end's `PublicKey`. Returns `None` if key exchange
@@ -358,42 +961,160 @@ impl Connection {
         self.remote_pkey
     }
 
-    /// Secures the `Connection` to a server
-    pub async fn secure_server(
+    /// Report the establishment of this `Connection` to `observer`, or to the
+    /// process-wide `ConnectionObserver` if there is none
+    pub(crate) fn notify_established(
+        &self,
+        observer: Option<&Arc<dyn ConnectionObserver>>,
+        direction: Direction,
+        dial_time: Duration,
+        handshake_time: Duration,
+    ) {
+        match self.socket.peer_addr() {
+            Ok(remote_addr) => observer::notify_established(
+                observer,
+                ConnectionEstablished {
+                    peer: self.remote_pkey,
+                    remote_addr,
+                    direction,
+                    transport: self.socket.transport(),
+                    dial_time,
+                    handshake_time,
+                },
+            ),
+            Err(e) => {
+                debug!(
+                    target: targets::CONNECTION,
+                    "not reporting connection without address: {}", e
+                )
+            }
+        }
+    }
+
+    /// Secures this `Connection` from the side that dialed the remote peer,
+    /// sending our `PublicKey` to the remote peer whose key must be known in
+    /// advance
+    pub async fn secure_as_dialer(
         &mut self,
         local: &Exchanger,
-        server: &PublicKey,
+        remote: &PublicKey,
     ) -> Result<(), SecureError> {
-        info!("sending public key to peer");
+        info!(target: targets::CONNECTION, "sending public key to peer");
         self.send_plain(local.keypair().public())
             .await
             .context(SecureSend)?;
 
-        self.exchange(local, server)?;
+        self.exchange(local, remote)?;
 
-        self.remote_pkey = Some(*server);
+        self.remote_pkey = Some(*remote);
+        self.initiator = Some(*local.keypair().public());
 
         Ok(())
     }
 
-    /// Secures this `Connection` from a client
-    pub async fn secure_client(
+    /// Secures this `Connection` from the side that accepted it, learning
+    /// the `PublicKey` of the remote peer
+    pub async fn secure_as_acceptor(
         &mut self,
         exchanger: &Exchanger,
     ) -> Result<(), SecureError> {
-        info!("waiting for peer's public key");
-        let pkey = self
-            .receive_plain::<PublicKey>()
+        info!(target: targets::CONNECTION, "waiting for peer's public key");
+        let pkey = match self
+            .receive_plain_bounded::<PublicKey>(MAX_HANDSHAKE_SIZE)
             .await
-            .context(SecureReceive)?;
+        {
+            Err(ReceiveError::DeserializeReceive { .. })
+                if Self::is_private_hello(self.frame.data()) =>
+            {
+                return HandshakeMismatch {
+                    expected: HandshakeMode::Plain,
+                }
+                .fail();
+            }
+            received => received.context(SecureReceive)?,
+        };
 
         self.exchange(exchanger, &pkey)?;
 
         self.remote_pkey = Some(pkey);
+        self.initiator = Some(pkey);
 
         Ok(())
     }
 
+    /// Secures the `Connection` to a server
+    #[deprecated(note = "use `secure_as_dialer` instead")]
+    pub async fn secure_server(
+        &mut self,
+        local: &Exchanger,
+        server: &PublicKey,
+    ) -> Result<(), SecureError> {
+        self.secure_as_dialer(local, server).await
+    }
+
+    /// Secures this `Connection` from a client
+    #[deprecated(note = "use `secure_as_acceptor` instead")]
+    pub async fn secure_client(
+        &mut self,
+        exchanger: &Exchanger,
+    ) -> Result<(), SecureError> {
+        self.secure_as_acceptor(exchanger).await
+    }
+
+    /// Make the encrypted output of this `Connection` reproducible by seeding
+    /// the generation of the stream header. This must be called after
+    /// securing the `Connection` and before sending anything on it.
+    #[cfg(any(test, feature = "test"))]
+    pub fn set_stream_seed(&mut self, seed: u64) {
+        if let ConnectionState::Secured(_, push) = &mut self.state {
+            push.set_seed(seed);
+        }
+    }
+
+    /// Derive `len` bytes of keying material from the session established when
+    /// securing this `Connection`, bound to the given `label` and `context`.
+    /// Both ends of the `Connection` derive the same output, which stays the
+    /// same for the whole lifetime of the `Connection` regardless of the
+    /// messages exchanged, and does not reveal the keys used to encrypt them.
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, SecureError> {
+        match (&self.state, &self.exporter) {
+            (ConnectionState::Broken, _) => BrokenExport.fail(),
+            (ConnectionState::Secured(_, _), Some(exporter)) => {
+                Ok(exporter.export(label, context, len))
+            }
+            _ => UnsecuredExport.fail(),
+        }
+    }
+
+    /// Returns the `PublicKey` of the peer that dialed this `Connection`.
+    /// Returns `None` if the `Connection` has not been secured
+    pub fn initiator(&self) -> Option<PublicKey> {
+        self.initiator
+    }
+
+    /// Returns the `ConnectionIdentity` of the remote end, which tells
+    /// whether this `Connection` was dialed at the request of the peer
+    /// that accepted it. Returns `None` if the `Connection` has not been
+    /// secured
+    pub fn identity(&self) -> Option<ConnectionIdentity> {
+        self.remote_pkey.map(|public| {
+            if self.reversed {
+                ConnectionIdentity::reversed(public)
+            } else {
+                ConnectionIdentity::new(public)
+            }
+        })
+    }
+
+    pub(crate) fn set_reversed(&mut self, reversed: bool) {
+        self.reversed = reversed;
+    }
+
     /// Gracefully closes this `Connection` ensuring that any data sent has been
     /// received by the remote peer.
     pub async fn close(&mut self) -> Result<(), IoError> {
@@ -407,6 +1128,17 @@ impl Connection {
         self.socket.flush().await
     }
 
+    /// Checks whether the dialing side of this `Connection` was only admitted
+    /// as an observer by the `Authorizer` of the accepting side, which then
+    /// ignores anything the dialing side sends
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
     /// Checks whether this `Connection` is secured
     pub fn is_secured(&self) -> bool {
         matches!(&self.state, ConnectionState::Secured(_, _))
@@ -435,16 +1167,30 @@ impl Connection {
         match self.state {
             ConnectionState::Secured(pull, push) => {
                 let (read, write) = split(self.socket);
+                let exporter = self.exporter.expect("secured without exporter");
                 let writer = ConnectionWrite {
                     write,
-                    push,
+                    push: Some(push),
                     remote: self.remote_pkey.unwrap(),
+                    exporter: exporter.clone(),
+                    padding: self.padding,
+                    limits: self.limits.clone(),
+                    next_id: self.next_id,
+                    scratch: self.scratch,
+                    stats: self.stats.clone(),
                 };
                 let reader = ConnectionRead {
                     read,
-                    pull,
-                    buffer: Vec::with_capacity(4096),
+                    pull: Some(pull),
+                    frame: self.frame,
                     remote: self.remote_pkey.unwrap(),
+                    initiator: self.initiator,
+                    reversed: self.reversed,
+                    exporter,
+                    limits: self.limits,
+                    read_only: self.read_only,
+                    reassembly: self.reassembly,
+                    stats: self.stats,
                 };
 
                 Some((reader, writer))
@@ -454,7 +1200,25 @@ impl Connection {
     }
 }
 
+/// Write the `Fingerprint` of `key`, or the full key using the alternate
+/// format `{:#}`
+fn peer_id(key: &PublicKey, f: &mut fmt::Formatter) -> fmt::Result {
+    if f.alternate() {
+        write!(f, "{}", key)
+    } else {
+        write!(f, "{}", key.fingerprint())
+    }
+}
+
 impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Shows the addresses of both ends along with the `Fingerprint` of the
+/// remote `PublicKey` once secured, or the full key with `{:#}`
+impl fmt::Display for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (local, remote) = match (self.local_addr(), self.peer_addr()) {
             (Ok(local), Ok(remote)) => {
@@ -475,48 +1239,325 @@ impl fmt::Debug for Connection {
             "insecure"
         };
 
-        write!(f, "{} connection {} -> {}", sec, local, remote)
+        write!(f, "{} connection {} -> {}", sec, local, remote)?;
+
+        if let Some(key) = self.remote_pkey {
+            write!(f, " with ")?;
+            peer_id(&key, f)?;
+        }
+
+        Ok(())
     }
 }
 
 /// The read end of a `Connection` resulting from `Connection::split`
 pub struct ConnectionRead {
     read: ReadHalf<Box<dyn Socket>>,
-    pull: Pull,
+    /// `None` if a decryption job panicked while holding the `Pull`
+    pull: Option<Pull>,
     remote: PublicKey,
-    buffer: Vec<u8>,
+    initiator: Option<PublicKey>,
+    reversed: bool,
+    frame: FrameReader,
+    exporter: Exporter,
+    limits: Arc<ConnectionLimits>,
+    read_only: bool,
+    reassembly: Reassembler,
+    stats: Arc<Counters>,
 }
 
 impl ConnectionRead {
-    /// See `Connection::receive`for more details
+    /// See `Connection::receive`for more details. <br />
+    /// This is cancel safe: if the returned `Future` is dropped before
+    /// completing, no data is lost and the message that was being received
+    /// is returned by the next call.
     pub async fn receive<T: for<'de> Deserialize<'de> + fmt::Debug + Send>(
         &mut self,
     ) -> Result<T, ReceiveError> {
-        Connection::receive_internal(
-            &mut self.pull,
+        let pull = self.pull.as_mut().context(CorruptedReceive)?;
+        let result = Connection::receive_internal(
+            pull,
             &mut self.read,
-            &mut self.buffer,
+            &mut self.frame,
+            &self.limits,
+            &mut self.reassembly,
+            &self.stats,
         )
         .await
+        .and_then(|data| Connection::deserialize(&data));
+
+        self.received(result)
     }
 
-    /// Get the `PublicKey` associated with this `ConnectionRead`
+    /// See `Connection::receive_frame` for more details. <br />
+    /// This is cancel safe in the same way as `ConnectionRead::receive`.
+    pub async fn receive_frame(&mut self) -> Result<Vec<u8>, ReceiveError> {
+        let pull = self.pull.as_mut().context(CorruptedReceive)?;
+        let result = Connection::receive_internal(
+            pull,
+            &mut self.read,
+            &mut self.frame,
+            &self.limits,
+            &mut self.reassembly,
+            &self.stats,
+        )
+        .await
+        .map(Cow::into_owned);
+
+        self.received(result)
+    }
+
+    /// Receive a message from this `ConnectionRead`, decrypting and
+    /// deserializing it on the given `CryptoPool`. Reading from the
+    /// underlying `Socket` still happens on the current task.
+    pub async fn receive_on<T>(
+        &mut self,
+        pool: &CryptoPool,
+    ) -> Result<T, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de> + fmt::Debug + Send + 'static,
+    {
+        loop {
+            self.read_frame().await?;
+
+            if let Some(message) = self.decrypt_on(pool).await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Read the next frame from the underlying `Socket` without decrypting
+    /// it. This is cancel safe, unlike `ConnectionRead::decrypt_on`.
+    pub(crate) async fn read_frame(&mut self) -> Result<(), ReceiveError> {
+        ensure!(self.pull.is_some(), CorruptedReceive);
+
+        let result = Connection::read_limited(
+            &mut self.read,
+            &mut self.frame,
+            &self.limits,
+            &mut self.reassembly,
+            &self.stats,
+        )
+        .await;
+
+        self.received(result)
+    }
+
+    /// Stop receiving if the remote peer stalled in the middle of a frame,
+    /// since the rest of it may never arrive
+    fn received<T>(
+        &mut self,
+        result: Result<T, ReceiveError>,
+    ) -> Result<T, ReceiveError> {
+        if matches!(
+            result,
+            Err(ReceiveError::IdleTimeout { partial: true, .. })
+        ) {
+            self.pull = None;
+        }
+
+        result
+    }
+
+    /// Decrypt the frame read by the last call to
+    /// `ConnectionRead::read_frame`, returning `None` if it was a fragment
+    /// that did not complete a message
+    #[cfg(feature = "system")]
+    pub(crate) fn decrypt<T>(&mut self) -> Result<Option<T>, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de> + fmt::Debug + Send,
+    {
+        let pull = self.pull.as_mut().context(CorruptedReceive)?;
+
+        let message = match self.limits.fragmentation() {
+            None => pull.decrypt(self.frame.data()).map(Some).context(Decrypt),
+            Some(fragmentation) => {
+                let plaintext =
+                    pull.decrypt_bytes(self.frame.data()).context(Decrypt)?;
+
+                self.reassembly
+                    .push(plaintext, fragmentation)?
+                    .map(|data| Self::decode(&data))
+                    .transpose()
+            }
+        }?;
+
+        if message.is_some() {
+            self.stats.received_message();
+        }
+
+        Ok(message)
+    }
+
+    /// Decrypt the frame read by the last call to
+    /// `ConnectionRead::read_frame` on the given `CryptoPool`, see
+    /// `ConnectionRead::decrypt`
+    pub(crate) async fn decrypt_on<T>(
+        &mut self,
+        pool: &CryptoPool,
+    ) -> Result<Option<T>, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de> + fmt::Debug + Send + 'static,
+    {
+        let mut pull = self.pull.take().context(CorruptedReceive)?;
+        let buffer = self.frame.take();
+
+        let Some(fragmentation) = self.limits.fragmentation().cloned() else {
+            let (pull, buffer, message) = pool
+                .run(move || {
+                    let message = pull.decrypt(&buffer);
+
+                    (pull, buffer, message)
+                })
+                .await;
+
+            self.pull = Some(pull);
+            self.frame.restore(buffer);
+
+            let message = message.context(Decrypt)?;
+
+            self.stats.received_message();
+
+            return Ok(Some(message));
+        };
+
+        let (pull, buffer, plaintext) = pool
+            .run(move || {
+                let plaintext = pull.decrypt_bytes(&buffer).map(<[u8]>::to_vec);
+
+                (pull, buffer, plaintext)
+            })
+            .await;
+
+        self.pull = Some(pull);
+        self.frame.restore(buffer);
+
+        let message = self
+            .reassembly
+            .push(&plaintext.context(Decrypt)?, &fragmentation)?
+            .map(|data| Self::decode(&data))
+            .transpose()?;
+
+        if message.is_some() {
+            self.stats.received_message();
+        }
+
+        Ok(message)
+    }
+
+    /// Decode a reassembled message, failing the same way as
+    /// `Pull::decrypt` does for messages sent in a single frame
+    fn decode<T>(data: &[u8]) -> Result<T, ReceiveError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        bincode_options()
+            .deserialize(data)
+            .map_err(|source| DecryptError::SerializeDecrypt { source })
+            .context(Decrypt)
+    }
+
+    /// Checks whether this `ConnectionRead` and the given `ConnectionWrite`
+    /// were obtained by splitting the same `Connection`
+    pub fn is_pair_of(&self, write: &ConnectionWrite) -> bool {
+        self.read.is_pair_of(&write.write)
+    }
+
+    /// Reunite this `ConnectionRead` with the `ConnectionWrite` it was split
+    /// from. The resulting `Connection` is broken if either half was.
+    ///
+    /// # Panics
+    /// This panics if both halves do not come from the same `Connection`, see
+    /// `ConnectionRead::is_pair_of`
+    pub fn unsplit(self, write: ConnectionWrite) -> Connection {
+        assert!(self.is_pair_of(&write), "unrelated connection halves");
+
+        let state = match (self.pull, write.push) {
+            (Some(pull), Some(push)) => ConnectionState::Secured(pull, push),
+            _ => ConnectionState::Broken,
+        };
+
+        Connection {
+            socket: self.read.unsplit(write.write),
+            state,
+            frame: self.frame,
+            remote_pkey: Some(self.remote),
+            initiator: self.initiator,
+            reversed: self.reversed,
+            exporter: Some(self.exporter),
+            padding: write.padding,
+            limits: self.limits,
+            read_only: self.read_only,
+            next_id: write.next_id,
+            reassembly: self.reassembly,
+            scratch: write.scratch,
+            stats: self.stats,
+        }
+    }
+
+    /// See `Connection::is_read_only` for more details
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// See `Connection::export_keying_material` for more details
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, SecureError> {
+        ensure!(self.pull.is_some(), BrokenExport);
+
+        Ok(self.exporter.export(label, context, len))
+    }
+
+    /// Replace the `ConnectionLimits` of this `ConnectionRead`, they apply
+    /// from the next message on
+    pub fn set_limits(&mut self, limits: Arc<ConnectionLimits>) {
+        self.limits = limits;
+    }
+
+    /// See `Connection::set_read_timeout` for more details
+    pub fn set_read_timeout(&mut self, duration: Duration) {
+        self.limits = Arc::new((*self.limits).clone().idle_timeout(duration));
+    }
+
+    /// Get the `ConnectionLimits` applied to this `ConnectionRead`
+    pub fn limits(&self) -> &Arc<ConnectionLimits> {
+        &self.limits
+    }
+
+    /// Get the `PublicKey` associated with this `ConnectionRead`
     pub fn remote_pkey(&self) -> &PublicKey {
         &self.remote
     }
+
+    /// See `Connection::stats` for more details
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
 }
 
 impl fmt::Display for ConnectionRead {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "connection read end for {}", self.remote)
+        write!(f, "connection read end for ")?;
+        peer_id(&self.remote, f)
     }
 }
 
 /// The write end of `Connection` resulting from `Connection::split`
 pub struct ConnectionWrite {
     write: WriteHalf<Box<dyn Socket>>,
-    push: Push,
+    /// `None` if an encryption job panicked while holding the `Push`
+    push: Option<Push>,
     remote: PublicKey,
+    exporter: Exporter,
+    padding: PaddingPolicy,
+    limits: Arc<ConnectionLimits>,
+    next_id: u64,
+    scratch: Scratch,
+    stats: Arc<Counters>,
 }
 
 impl ConnectionWrite {
@@ -525,18 +1566,915 @@ impl ConnectionWrite {
         &mut self,
         message: &M,
     ) -> Result<(), SendError> {
-        Connection::send_internal(message, &mut self.write, &mut self.push)
-            .await
+        let mut plaintext = self.scratch.take_plain();
+        let result = match Connection::serialize_into(&mut plaintext, message) {
+            Ok(()) => self.send_frame(&plaintext).await,
+            Err(e) => Err(e),
+        };
+
+        self.scratch.restore_plain(plaintext);
+
+        result
+    }
+
+    /// See `Connection::send_frame` for more details
+    pub async fn send_frame(
+        &mut self,
+        plaintext: &[u8],
+    ) -> Result<(), SendError> {
+        let mut outgoing = Outgoing::new(
+            Cow::Borrowed(plaintext),
+            &self.limits,
+            &self.padding,
+            &mut self.next_id,
+        )?;
+
+        while !outgoing.is_done() {
+            self.send_next(&mut outgoing, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize `message` so that it can be sent one frame at a time using
+    /// `ConnectionWrite::send_next`
+    pub(crate) fn outgoing<M: Serialize>(
+        &mut self,
+        message: &M,
+    ) -> Result<Outgoing<'static>, SendError> {
+        let plaintext = Connection::serialize(message)?;
+
+        Outgoing::new(
+            Cow::Owned(plaintext),
+            &self.limits,
+            &self.padding,
+            &mut self.next_id,
+        )
+    }
+
+    /// Send the next frame of `outgoing`, encrypting it on the given
+    /// `CryptoPool` if any
+    pub(crate) async fn send_next(
+        &mut self,
+        outgoing: &mut Outgoing<'_>,
+        pool: Option<&CryptoPool>,
+    ) -> Result<(), SendError> {
+        let Some(frame) = outgoing.next_frame() else {
+            return Ok(());
+        };
+
+        match pool {
+            Some(pool) => {
+                let mut push = self.push.take().context(CorruptedSend)?;
+                let padding = self.padding.clone();
+                let limit = self.limits.message_size();
+                let frame = frame.into_owned();
+
+                let (push, data) = pool
+                    .run(move || {
+                        let data = padding.seal(&frame, limit, &mut push);
+
+                        (push, data)
+                    })
+                    .await;
+
+                self.push = Some(push);
+
+                let data = data?;
+                let result = Connection::write_limited(
+                    &mut self.write,
+                    &data,
+                    &self.limits,
+                )
+                .await;
+
+                self.written(data.len(), outgoing.is_done(), result)
+            }
+            None => {
+                let push = self.push.as_mut().context(CorruptedSend)?;
+                let sealed = &mut self.scratch.sealed;
+
+                let limit = self.limits.message_size();
+
+                self.padding.seal_into(&frame, limit, push, sealed)?;
+
+                let result = Connection::write_limited(
+                    &mut self.write,
+                    sealed,
+                    &self.limits,
+                )
+                .await;
+                let len = sealed.len();
+
+                self.scratch.trim();
+
+                self.written(len, outgoing.is_done(), result)
+            }
+        }
+    }
+
+    /// Send a message using this `ConnectionWrite`, serializing and
+    /// encrypting it on the given `CryptoPool`. Writing to the underlying
+    /// `Socket` still happens on the current task.
+    pub async fn send_on<M>(
+        &mut self,
+        message: M,
+        pool: &CryptoPool,
+    ) -> Result<(), SendError>
+    where
+        M: Serialize + fmt::Debug + Send + 'static,
+    {
+        if self.limits.fragmentation().is_some() {
+            let mut outgoing = self.outgoing(&message)?;
+
+            while !outgoing.is_done() {
+                self.send_next(&mut outgoing, Some(pool)).await?;
+            }
+
+            return Ok(());
+        }
+
+        let mut push = self.push.take().context(CorruptedSend)?;
+        let padding = self.padding.clone();
+        let limits = self.limits.clone();
+
+        let (push, data) = pool
+            .run(move || {
+                let data = Connection::serialize(&message).and_then(|plain| {
+                    ensure!(
+                        plain.len() <= limits.message_size(),
+                        FrameTooLarge { size: plain.len() }
+                    );
+
+                    padding.seal(&plain, limits.message_size(), &mut push)
+                });
+
+                (push, data)
+            })
+            .await;
+
+        self.push = Some(push);
+
+        let data = data?;
+        let result =
+            Connection::write_limited(&mut self.write, &data, &self.limits)
+                .await;
+
+        self.written(data.len(), true, result)
+    }
+
+    /// Account for a frame of `len` encrypted bytes, that was the last one of
+    /// its message if `last` is set, and stop sending if writing it timed
+    /// out, since the remote peer could otherwise receive a truncated frame
+    /// followed by another one
+    fn written(
+        &mut self,
+        len: usize,
+        last: bool,
+        result: Result<(), SendError>,
+    ) -> Result<(), SendError> {
+        match &result {
+            Ok(()) => {
+                self.stats.sent_frame(len);
+
+                if last {
+                    self.stats.sent_message();
+                }
+            }
+            Err(SendError::SendTimeout { .. })
+            | Err(SendError::TooLargeForDatagram { .. }) => self.push = None,
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// Gracefully close the write end of this `Connection`, the remote peer
+    /// will still be able to send data until it closes its own write end
+    pub async fn close(&mut self) -> Result<(), IoError> {
+        self.write.shutdown().await
+    }
+
+    /// See `Connection::export_keying_material` for more details
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, SecureError> {
+        ensure!(self.push.is_some(), BrokenExport);
+
+        Ok(self.exporter.export(label, context, len))
+    }
+
+    /// See `Connection::set_padding` for more details
+    pub fn set_padding(&mut self, padding: PaddingPolicy) {
+        self.padding = padding;
+    }
+
+    /// Replace the `ConnectionLimits` of this `ConnectionWrite`, they apply
+    /// from the next message on
+    pub fn set_limits(&mut self, limits: Arc<ConnectionLimits>) {
+        self.limits = limits;
+    }
+
+    /// See `Connection::set_write_timeout` for more details
+    pub fn set_write_timeout(&mut self, duration: Duration) {
+        self.limits = Arc::new((*self.limits).clone().send_timeout(duration));
+    }
+
+    /// Get the `ConnectionLimits` applied to this `ConnectionWrite`
+    pub fn limits(&self) -> &Arc<ConnectionLimits> {
+        &self.limits
     }
 
     /// Get the remote `PublicKey` associated with this `ConnectionWrite`
     pub fn remote_pkey(&self) -> &PublicKey {
         &self.remote
     }
+
+    /// See `Connection::stats` for more details
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
 }
 
 impl fmt::Display for ConnectionWrite {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "connection write end for {}", self.remote)
+        write!(f, "connection write end for ")?;
+        peer_id(&self.remote, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::{crypto::stream::HEADER_SIZE, test::next_test_ip4};
+
+    #[test]
+    fn size_prefix_pinned() {
+        for size in [0, 1, 255, 256, 0x1234_5678, u32::MAX] {
+            let encoded =
+                bincode_options().serialize(&size).expect("encode failed");
+
+            assert_eq!(encoded, size.to_le_bytes(), "size prefix changed");
+        }
+    }
+
+    #[tokio::test]
+    async fn wire_size_matches_socket() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+
+        let reader = tokio::spawn(async move {
+            let (mut stream, _) =
+                listener.accept().await.expect("accept failed");
+            let mut received = Vec::new();
+
+            stream
+                .read_to_end(&mut received)
+                .await
+                .expect("read failed");
+
+            received.len() as u64
+        });
+
+        let socket = TcpStream::connect(addr).await.expect("connect failed");
+        let mut connection = Connection::new(Box::new(socket));
+        let exchanger = Exchanger::random();
+
+        connection
+            .exchange(&exchanger, exchanger.keypair().public())
+            .expect("exchange failed");
+
+        let mut expected = HEADER_SIZE as u64;
+
+        expected += wire_size(&0u8).unwrap();
+        connection.send(&0u8).await.expect("send failed");
+
+        expected += wire_size(&u64::MAX).unwrap();
+        connection.send(&u64::MAX).await.expect("send failed");
+
+        let string = "some string payload".to_string();
+        expected += wire_size(&string).unwrap();
+        connection.send(&string).await.expect("send failed");
+
+        let large = vec![42u32; 4096];
+        expected += wire_size(&large).unwrap();
+        connection.send(&large).await.expect("send failed");
+
+        drop(connection);
+
+        assert_eq!(reader.await.unwrap(), expected, "wrong wire size");
+    }
+
+    #[tokio::test]
+    async fn trailing_bytes_rejected() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+
+        let writer = tokio::spawn(async move {
+            let mut socket =
+                TcpStream::connect(addr).await.expect("connect failed");
+            let mut payload = bincode_options()
+                .serialize(&7u32)
+                .expect("serialize failed");
+
+            payload.push(0);
+
+            let size = bincode_options()
+                .serialize(&(payload.len() as u32))
+                .expect("serialize failed");
+
+            socket.write_all(&size).await.expect("write failed");
+            socket.write_all(&payload).await.expect("write failed");
+        });
+
+        let (socket, _) = listener.accept().await.expect("accept failed");
+        let mut connection = Connection::new(Box::new(socket));
+
+        writer.await.expect("writer panicked");
+
+        assert!(
+            matches!(
+                connection.receive_plain::<u32>().await,
+                Err(ReceiveError::DeserializeReceive { .. })
+            ),
+            "frame with trailing bytes accepted"
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_plain_refused() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+
+        let writer = tokio::spawn(async move {
+            let mut socket =
+                TcpStream::connect(addr).await.expect("connect failed");
+
+            // announce a frame that would take 4GiB to receive
+            socket
+                .write_all(&u32::MAX.to_le_bytes())
+                .await
+                .expect("write failed");
+
+            socket
+        });
+
+        let (socket, _) = listener.accept().await.expect("accept failed");
+        let mut connection = Connection::new(Box::new(socket));
+        let _socket = writer.await.expect("writer panicked");
+
+        assert!(matches!(
+            connection.receive_plain::<Vec<u8>>().await,
+            Err(ReceiveError::OversizedReceive {
+                size,
+                max: DEFAULT_MAX_MESSAGE_SIZE,
+                ..
+            }) if size == u32::MAX as usize
+        ));
+        assert!(matches!(
+            connection.receive_plain_frame().await,
+            Err(ReceiveError::CorruptedReceive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_breaks_connection() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let limits = Arc::new(ConnectionLimits::default().max_message_size(64));
+
+        acceptor.set_limits(limits.clone());
+
+        dialer.send_frame(&[0; 32]).await.expect("send failed");
+        dialer.send_frame(&[0; 128]).await.expect("send failed");
+
+        assert_eq!(acceptor.receive_frame().await.unwrap(), [0; 32]);
+        assert!(matches!(
+            acceptor.receive_frame().await,
+            Err(ReceiveError::OversizedReceive { .. })
+        ));
+        assert!(matches!(
+            acceptor.receive_frame().await,
+            Err(ReceiveError::CorruptedReceive { .. })
+        ));
+
+        // the limits carry over to the halves of a split `Connection`
+        let (mut dialer, mut acceptor) = secured_pair().await;
+
+        acceptor.set_limits(limits);
+
+        let (mut read, _) = acceptor.split().expect("not secured");
+
+        dialer.send_frame(&[0; 128]).await.expect("send failed");
+
+        assert_eq!(read.limits().message_size(), 64);
+        assert!(matches!(
+            read.receive_frame().await,
+            Err(ReceiveError::OversizedReceive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_between_frames() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let after = Duration::from_millis(50);
+
+        acceptor.set_limits(Arc::new(
+            ConnectionLimits::default().idle_timeout(after),
+        ));
+
+        assert!(matches!(
+            acceptor.receive::<u32>().await,
+            Err(ReceiveError::IdleTimeout { partial: false, .. })
+        ));
+
+        dialer.send(&7u32).await.expect("send failed");
+
+        assert_eq!(acceptor.receive::<u32>().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_within_frame() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+        let mut writer =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let (socket, _) = listener.accept().await.expect("accept failed");
+        let mut connection = Connection::new(Box::new(socket));
+        let exchanger = Exchanger::random();
+
+        connection
+            .exchange(&exchanger, exchanger.keypair().public())
+            .expect("exchange failed");
+        connection.set_limits(Arc::new(
+            ConnectionLimits::default().idle_timeout(Duration::from_millis(50)),
+        ));
+
+        // announce 64 bytes but only send part of them
+        writer
+            .write_all(&[64, 0, 0, 0, 1, 2, 3])
+            .await
+            .expect("write failed");
+
+        assert!(matches!(
+            connection.receive::<u32>().await,
+            Err(ReceiveError::IdleTimeout { partial: true, .. })
+        ));
+        assert!(matches!(
+            connection.receive::<u32>().await,
+            Err(ReceiveError::CorruptedReceive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_timeout_breaks_read_half() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+        let mut writer =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let (socket, _) = listener.accept().await.expect("accept failed");
+        let mut connection = Connection::new(Box::new(socket));
+        let exchanger = Exchanger::random();
+
+        connection
+            .exchange(&exchanger, exchanger.keypair().public())
+            .expect("exchange failed");
+        connection.remote_pkey = Some(*exchanger.keypair().public());
+
+        let (mut read, _write) = connection.split().expect("not secured");
+
+        read.set_read_timeout(Duration::from_millis(50));
+
+        assert!(matches!(
+            read.receive::<u32>().await,
+            Err(ReceiveError::IdleTimeout { partial: false, .. })
+        ));
+
+        writer
+            .write_all(&[64, 0, 0, 0, 1, 2, 3])
+            .await
+            .expect("write failed");
+
+        assert!(matches!(
+            read.receive::<u32>().await,
+            Err(ReceiveError::IdleTimeout { partial: true, .. })
+        ));
+        assert!(matches!(
+            read.receive::<u32>().await,
+            Err(ReceiveError::CorruptedReceive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_timeout_breaks_connection() {
+        const FRAME: usize = 1024 * 1024;
+
+        let after = Duration::from_millis(50);
+        let limits = Arc::new(ConnectionLimits::default().send_timeout(after));
+        let frame = vec![0; FRAME];
+
+        // the remote peer never reads so that socket buffers fill up
+        let (mut dialer, _acceptor) = secured_pair().await;
+
+        dialer.set_limits(limits.clone());
+
+        let error = loop {
+            if let Err(e) = dialer.send_frame(&frame).await {
+                break e;
+            }
+        };
+
+        assert!(matches!(error, SendError::SendTimeout { .. }));
+        assert!(matches!(
+            dialer.send_frame(&[1]).await,
+            Err(SendError::CorruptedSend { .. })
+        ));
+
+        let (dialer, _acceptor) = secured_pair().await;
+        let (_, mut write) = dialer.split().expect("not secured");
+
+        write.set_write_timeout(after);
+
+        let error = loop {
+            if let Err(e) = write.send_frame(&frame).await {
+                break e;
+            }
+        };
+
+        assert!(matches!(error, SendError::SendTimeout { .. }));
+        assert!(matches!(
+            write.send_frame(&[1]).await,
+            Err(SendError::CorruptedSend { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn crypto_pool_keeps_order() {
+        const COUNT: usize = 200;
+
+        let addr = next_test_ip4();
+        let (server, client) = (Exchanger::random(), Exchanger::random());
+        let server_key = *server.keypair().public();
+        let mut listener = crate::net::TcpListener::new(addr, server)
+            .await
+            .expect("listen failed");
+
+        let accept = tokio::spawn(async move {
+            listener.accept().await.expect("accept failed")
+        });
+
+        let (_, mut write) = crate::net::TcpConnector::new(client)
+            .connect(&server_key, &addr)
+            .await
+            .expect("connect failed")
+            .split()
+            .expect("connection not secured");
+        let (mut read, _) = accept
+            .await
+            .expect("accept panicked")
+            .split()
+            .expect("connection not secured");
+
+        let pool = CryptoPool::new(4);
+        let messages = (0..COUNT)
+            .map(|x| vec![x as u8; x * 101])
+            .collect::<Vec<_>>();
+        let expected = messages.clone();
+
+        let sender = tokio::spawn(async move {
+            // pooled and inline sends share the same framing and stream
+            for (idx, message) in messages.into_iter().enumerate() {
+                if idx % 3 == 0 {
+                    write.send(&message).await.expect("send failed");
+                } else {
+                    write.send_on(message, &pool).await.expect("send failed");
+                }
+            }
+        });
+
+        let pool = CryptoPool::new(2);
+
+        for (idx, expected) in expected.into_iter().enumerate() {
+            let received: Vec<u8> = if idx % 2 == 0 {
+                read.receive_on(&pool).await.expect("receive failed")
+            } else {
+                read.receive().await.expect("receive failed")
+            };
+
+            assert_eq!(received, expected, "wrong message {}", idx);
+        }
+
+        sender.await.expect("sender panicked");
+    }
+
+    async fn secured_pair() -> (Connection, Connection) {
+        let addr = next_test_ip4();
+        let server = Exchanger::random();
+        let server_key = *server.keypair().public();
+        let mut listener = crate::net::TcpListener::new(addr, server)
+            .await
+            .expect("listen failed");
+
+        let accept = tokio::spawn(async move {
+            listener.accept().await.expect("accept failed")
+        });
+
+        let dialer = crate::net::TcpConnector::new(Exchanger::random())
+            .connect(&server_key, &addr)
+            .await
+            .expect("connect failed");
+        let acceptor = accept.await.expect("accept panicked");
+
+        (dialer, acceptor)
+    }
+
+    #[tokio::test]
+    async fn frame_round_trip() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let frames = vec![
+            Vec::new(),
+            vec![0],
+            (0..=255).collect::<Vec<u8>>(),
+            vec![0xff; 64 * 1024],
+            Vec::new(),
+        ];
+
+        for frame in &frames {
+            dialer.send_frame(frame).await.expect("send failed");
+            assert_eq!(
+                &acceptor.receive_frame().await.expect("receive failed"),
+                frame,
+                "wrong frame"
+            );
+        }
+
+        let (mut read, _) = dialer.split().expect("not secured");
+        let (_, mut write) = acceptor.split().expect("not secured");
+
+        for frame in &frames {
+            write.send_frame(frame).await.expect("send failed");
+            assert_eq!(
+                &read.receive_frame().await.expect("receive failed"),
+                frame,
+                "wrong frame from halves"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_count_traffic() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let initial = dialer.stats();
+
+        dialer.send(&0u8).await.expect("send failed");
+        dialer.send(&vec![1u32; 128]).await.expect("send failed");
+        acceptor.receive::<u8>().await.expect("receive failed");
+        acceptor
+            .receive::<Vec<u32>>()
+            .await
+            .expect("receive failed");
+
+        let sent = dialer.stats();
+        let received = acceptor.stats();
+
+        assert_eq!(sent.messages_sent, initial.messages_sent + 2);
+        assert_eq!(sent.messages_received, initial.messages_received);
+        assert_eq!(received.messages_received, sent.messages_sent);
+        assert_eq!(received.bytes_received, sent.bytes_sent);
+        assert!(sent.bytes_sent - initial.bytes_sent > 128 * 4);
+
+        let (mut read, mut write) = dialer.split().expect("not secured");
+        let (mut remote_read, mut remote_write) =
+            acceptor.split().expect("not secured");
+        let pool = CryptoPool::new(1);
+
+        assert_eq!(read.stats(), sent, "halves do not share counters");
+
+        remote_write.send(&7u64).await.expect("send failed");
+        remote_write
+            .send_on(8u64, &pool)
+            .await
+            .expect("send failed");
+        read.receive::<u64>().await.expect("receive failed");
+        read.receive_on::<u64>(&pool).await.expect("receive failed");
+        write.send(&9u64).await.expect("send failed");
+        remote_read.receive::<u64>().await.expect("receive failed");
+
+        let local = read.unsplit(write).stats();
+        let remote = remote_write.stats();
+
+        assert_eq!(remote, remote_read.stats(), "halves do not share counters");
+        assert_eq!(local.messages_sent, sent.messages_sent + 1);
+        assert_eq!(local.messages_received, sent.messages_received + 2);
+        assert_eq!(local.bytes_sent, remote.bytes_received);
+        assert_eq!(local.bytes_received, remote.bytes_sent);
+    }
+
+    /// Enable `Fragmentation` on both ends with frames of at most `frame`
+    /// bytes
+    fn fragment(
+        connections: [&mut Connection; 2],
+        frame: usize,
+        timeout: Duration,
+    ) {
+        let limits = ConnectionLimits::default()
+            .max_message_size(frame)
+            .fragment_messages(
+                Fragmentation::default().reassembly_timeout(timeout),
+            );
+        let limits = Arc::new(limits);
+
+        for connection in connections {
+            connection.set_limits(limits.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn fragmented_round_trip() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let large = (0..100_000).map(|x| x as u32).collect::<Vec<_>>();
+
+        fragment([&mut dialer, &mut acceptor], 1024, Duration::from_secs(30));
+
+        dialer.send(&large).await.expect("send failed");
+        dialer.send(&7u32).await.expect("send failed");
+        dialer.send_frame(&[1; 5000]).await.expect("send failed");
+
+        assert_eq!(acceptor.receive::<Vec<u32>>().await.unwrap(), large);
+        assert_eq!(acceptor.receive::<u32>().await.unwrap(), 7);
+        assert_eq!(acceptor.receive_frame().await.unwrap(), [1; 5000]);
+
+        let (mut read, _) = acceptor.split().expect("not secured");
+        let (_, mut write) = dialer.split().expect("not secured");
+        let pool = CryptoPool::new(2);
+
+        write
+            .send_on(large.clone(), &pool)
+            .await
+            .expect("send failed");
+        write.send(&large).await.expect("send failed");
+
+        assert_eq!(read.receive_on::<Vec<u32>>(&pool).await.unwrap(), large);
+        assert_eq!(read.receive::<Vec<u32>>().await.unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn incomplete_message_expires() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+
+        fragment([&mut dialer, &mut acceptor], 64, Duration::from_millis(100));
+
+        let (_, mut write) = dialer.split().expect("not secured");
+        let mut outgoing = write.outgoing(&vec![0u8; 1000]).unwrap();
+
+        assert!(outgoing.is_fragmented());
+
+        let receiver = tokio::spawn(async move {
+            let received = acceptor.receive::<Vec<u8>>().await;
+
+            (received, acceptor)
+        });
+
+        write
+            .send_next(&mut outgoing, None)
+            .await
+            .expect("send failed");
+        time::sleep(Duration::from_millis(200)).await;
+        write
+            .send_next(&mut outgoing, None)
+            .await
+            .expect("send failed");
+        write.send(&7u32).await.expect("send failed");
+
+        let (received, mut acceptor) = receiver.await.unwrap();
+
+        assert!(matches!(
+            received,
+            Err(ReceiveError::IncompleteMessage { id: 0 })
+        ));
+        assert_eq!(acceptor.receive::<u32>().await.unwrap(), 7);
+        assert!(!acceptor.is_broken(), "incomplete message broke connection");
+    }
+
+    #[tokio::test]
+    async fn frame_interop() {
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let encoded = bincode_options().serialize(&7u32).unwrap();
+
+        dialer.send_frame(&encoded).await.expect("send failed");
+        assert_eq!(acceptor.receive::<u32>().await.unwrap(), 7);
+
+        acceptor.send(&7u32).await.expect("send failed");
+        assert_eq!(dialer.receive_frame().await.unwrap(), encoded);
+    }
+
+    #[tokio::test]
+    async fn frame_unsecured() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+        let socket = TcpStream::connect(addr).await.expect("connect failed");
+        let mut connection = Connection::new(Box::new(socket));
+
+        drop(listener);
+
+        assert!(matches!(
+            connection.send_frame(&[1, 2, 3]).await,
+            Err(SendError::UnsecuredSend { .. })
+        ));
+        assert!(matches!(
+            connection.receive_frame().await,
+            Err(ReceiveError::UnsecuredReceive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn export_keying_material() {
+        const LEN: usize = 64;
+
+        let (mut dialer, mut acceptor) = secured_pair().await;
+        let export = |connection: &Connection, label: &[u8]| {
+            connection
+                .export_keying_material(label, b"context", LEN)
+                .expect("export failed")
+        };
+
+        let exported = export(&dialer, b"label");
+
+        assert_eq!(exported.len(), LEN, "wrong length");
+        assert_eq!(exported, export(&acceptor, b"label"), "ends disagree");
+        assert_ne!(exported, export(&dialer, b"other"), "label ignored");
+
+        dialer.send(&0u64).await.expect("send failed");
+        acceptor.receive::<u64>().await.expect("receive failed");
+
+        assert_eq!(exported, export(&dialer, b"label"), "traffic changed it");
+
+        let (read, _) = dialer.split().expect("not secured");
+        let (_, write) = acceptor.split().expect("not secured");
+
+        for half in [
+            read.export_keying_material(b"label", b"context", LEN),
+            write.export_keying_material(b"label", b"context", LEN),
+        ] {
+            assert_eq!(half.expect("export failed"), exported, "halves differ");
+        }
+
+        let (other, _) = secured_pair().await;
+
+        assert_ne!(
+            export(&other, b"label"),
+            exported,
+            "distinct connections share material"
+        );
+    }
+
+    #[tokio::test]
+    async fn export_unsecured() {
+        let addr = next_test_ip4();
+        let listener = TcpListener::bind(addr).await.expect("bind failed");
+        let socket = TcpStream::connect(addr).await.expect("connect failed");
+        let connection = Connection::new(Box::new(socket));
+
+        drop(listener);
+
+        assert!(matches!(
+            connection.export_keying_material(b"label", b"", 32),
+            Err(SecureError::UnsecuredExport { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn display_fingerprint() {
+        let (dialer, acceptor) = secured_pair().await;
+        let remote = dialer.remote_key().expect("not secured");
+        let fingerprint = remote.fingerprint().to_string();
+        let debug = format!("{:?}", dialer);
+
+        assert!(debug.starts_with("secure connection"), "{}", debug);
+        assert!(debug.ends_with(&fingerprint), "{}", debug);
+        assert!(!debug.contains(&remote.to_string()), "full key shown");
+        assert!(
+            format!("{:#}", dialer).ends_with(&remote.to_string()),
+            "full key not shown in alternate format"
+        );
+
+        let (read, write) = acceptor.split().expect("split failed");
+        let dialer_key = read.remote_pkey().fingerprint().to_string();
+
+        assert_eq!(
+            read.to_string(),
+            format!("connection read end for {}", dialer_key)
+        );
+        assert_eq!(
+            write.to_string(),
+            format!("connection write end for {}", dialer_key)
+        );
     }
 }
@@ -0,0 +1,173 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::crypto::key::exchange::PublicKey;
+
+/// Direction of a `Connection` from the point of view of the local node
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The `Connection` was accepted by a `Listener`
+    Inbound,
+    /// The `Connection` was initiated by a `Connector`
+    Outbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Inbound => write!(f, "inbound"),
+            Self::Outbound => write!(f, "outbound"),
+        }
+    }
+}
+
+/// Event emitted every time a `Connection` is successfully established and
+/// secured
+#[derive(Clone, Debug)]
+pub struct ConnectionEstablished {
+    /// Public key of the remote peer if it is known
+    pub peer: Option<PublicKey>,
+    /// Address of the remote end of the `Connection`
+    pub remote_addr: SocketAddr,
+    /// Whether the `Connection` was accepted or initiated
+    pub direction: Direction,
+    /// Name of the transport used by the underlying `Socket`
+    pub transport: &'static str,
+    /// Time spent establishing the underlying `Socket`. For inbound
+    /// `Connection`s this includes the time spent waiting for the peer.
+    pub dial_time: Duration,
+    /// Time spent exchanging keys with the remote peer
+    pub handshake_time: Duration,
+}
+
+/// Trait for types that want to be notified of connection establishment.
+/// Observers are called synchronously from the connecting task and should
+/// return quickly.
+pub trait ConnectionObserver: Send + Sync {
+    /// Called once for every `Connection` that was successfully secured
+    fn established(&self, event: &ConnectionEstablished);
+}
+
+impl<F> ConnectionObserver for F
+where
+    F: Fn(&ConnectionEstablished) + Send + Sync,
+{
+    fn established(&self, event: &ConnectionEstablished) {
+        (self)(event)
+    }
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn ConnectionObserver>>> =
+    RwLock::new(None);
+
+/// Register a process-wide `ConnectionObserver` that will be notified of every
+/// `Connection` established by the default `Connector::connect` and
+/// `Listener::accept` implementations. This replaces any previously registered
+/// observer. <br />
+/// `Connector`s and `Listener`s with their own observer, see
+/// `Connector::observer`, do not notify this one, which should be preferred
+/// when several nodes run in the same process.
+pub fn set_connection_observer<O>(observer: O)
+where
+    O: ConnectionObserver + 'static,
+{
+    *OBSERVER.write().expect("observer lock poisoned") =
+        Some(Arc::new(observer));
+}
+
+/// Remove the currently registered `ConnectionObserver` if any
+pub fn clear_connection_observer() {
+    OBSERVER.write().expect("observer lock poisoned").take();
+}
+
+/// Notify `observer`, or the process-wide observer if there is none
+pub(crate) fn notify_established(
+    observer: Option<&Arc<dyn ConnectionObserver>>,
+    event: ConnectionEstablished,
+) {
+    let observer = observer
+        .cloned()
+        .or_else(|| OBSERVER.read().expect("observer lock poisoned").clone());
+
+    if let Some(observer) = observer {
+        observer.established(&event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        net::{Connector, Listener, TcpConnector, TcpListener},
+        test::*,
+    };
+
+    #[tokio::test]
+    async fn loopback_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collector = events.clone();
+
+        set_connection_observer(move |event: &ConnectionEstablished| {
+            collector.lock().unwrap().push(event.clone());
+        });
+
+        let addr = next_test_ip4();
+        let server = Exchanger::random();
+        let client = Exchanger::random();
+        let server_key = *server.keypair().public();
+        let client_key = *client.keypair().public();
+
+        let mut listener =
+            TcpListener::new(addr, server).await.expect("listen failed");
+
+        let accept =
+            tokio::spawn(async move { listener.accept().await.map(|_| ()) });
+
+        TcpConnector::new(client)
+            .connect(&server_key, &addr)
+            .await
+            .expect("connect failed");
+
+        accept
+            .await
+            .expect("listener panicked")
+            .expect("accept failed");
+
+        clear_connection_observer();
+
+        let events = events.lock().unwrap();
+
+        let outbound = events
+            .iter()
+            .find(|e| {
+                e.direction == Direction::Outbound && e.peer == Some(server_key)
+            })
+            .expect("no outbound event");
+
+        assert_eq!(outbound.remote_addr, addr, "wrong remote address");
+        assert_eq!(outbound.transport, "tcp", "wrong transport");
+
+        let inbound = events
+            .iter()
+            .find(|e| {
+                e.direction == Direction::Inbound && e.peer == Some(client_key)
+            })
+            .expect("no inbound event");
+
+        assert_eq!(inbound.transport, "tcp", "wrong transport");
+        assert_eq!(inbound.remote_addr.ip(), addr.ip(), "wrong remote ip");
+
+        for event in [outbound, inbound] {
+            assert!(event.dial_time < Duration::from_secs(5));
+            assert!(event.handshake_time < Duration::from_secs(5));
+            assert!(event.handshake_time > Duration::ZERO);
+        }
+    }
+}
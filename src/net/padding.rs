@@ -0,0 +1,312 @@
+use serde::Serialize;
+use snafu::{ensure, ResultExt};
+
+use super::{Encrypt, FrameTooLarge, SendError, SerializeSend, FRAME_OVERHEAD};
+use crate::codec::bincode_options;
+use crate::crypto::stream::{Push, ENCRYPTION_OVERHEAD, PADDING_OVERHEAD};
+
+use bincode::Options;
+
+/// Policy used by a `Connection` to pad outgoing frames before encrypting
+/// them, so that their size leaks less about their content. <br />
+/// Padded frames carry their actual length and are unpadded by the receiver
+/// regardless of its own `PaddingPolicy`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// Frames are sent as is
+    #[default]
+    None,
+    /// Frames are padded to the next multiple of the given number of bytes
+    PadToMultiple(usize),
+    /// Frames are padded to the smallest of the given sizes that fits them.
+    /// Frames larger than every bucket are padded to a multiple of the
+    /// largest one.
+    PadToBuckets(Vec<usize>),
+}
+
+impl PaddingPolicy {
+    /// Number of bytes of plaintext that will be encrypted when sending a
+    /// frame of `len` bytes using this policy, including the length prefix of
+    /// padded frames. This saturates at `usize::MAX`.
+    pub fn padded_len(&self, len: usize) -> usize {
+        let needed = len.saturating_add(PADDING_OVERHEAD);
+
+        match self {
+            Self::None => len,
+            Self::PadToMultiple(multiple) => round_up(needed, *multiple),
+            Self::PadToBuckets(buckets) => {
+                match buckets.iter().filter(|b| **b >= needed).min() {
+                    Some(bucket) => *bucket,
+                    None => round_up(
+                        needed,
+                        buckets.iter().copied().max().unwrap_or(1),
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Size of the largest frame that is at most `limit` bytes once padded
+    /// using this policy, if any
+    pub(crate) fn max_unpadded(&self, limit: usize) -> Option<usize> {
+        if self.padded_len(0) > limit {
+            return None;
+        }
+
+        // padded_len is non decreasing, look for the last length that fits
+        let (mut low, mut high) = (0, limit);
+
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+
+            if self.padded_len(middle) <= limit {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        Some(low)
+    }
+
+    /// Compute the number of bytes that sending the given message on a
+    /// `Connection` using this policy will write to the underlying `Socket`.
+    /// See [`wire_size`] for more details.
+    ///
+    /// [`wire_size`]: super::wire_size
+    pub fn wire_size<T: Serialize + ?Sized>(
+        &self,
+        message: &T,
+    ) -> Result<u64, SendError> {
+        let size = bincode_options()
+            .serialized_size(message)
+            .context(SerializeSend)?;
+        let padded = usize::try_from(size)
+            .map(|size| self.padded_len(size))
+            .unwrap_or(usize::MAX);
+
+        Ok(padded as u64 + ENCRYPTION_OVERHEAD as u64 + FRAME_OVERHEAD)
+    }
+
+    /// Check that `plaintext` is at most `limit` bytes once padded and
+    /// encrypt it using `push`. `limit` should be the maximum message size of
+    /// the `ConnectionLimits`, which the remote peer uses to bound the frames
+    /// it receives.
+    pub(crate) fn seal(
+        &self,
+        plaintext: &[u8],
+        limit: usize,
+        push: &mut Push,
+    ) -> Result<Vec<u8>, SendError> {
+        let mut output = Vec::new();
+
+        self.seal_into(plaintext, limit, push, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Same as `PaddingPolicy::seal` but encrypts into `output`, reusing its
+    /// memory
+    pub(crate) fn seal_into(
+        &self,
+        plaintext: &[u8],
+        limit: usize,
+        push: &mut Push,
+        output: &mut Vec<u8>,
+    ) -> Result<(), SendError> {
+        let padded = self.padded_len(plaintext.len());
+
+        ensure!(
+            padded <= limit,
+            FrameTooLarge {
+                size: plaintext.len()
+            }
+        );
+
+        match self {
+            Self::None => push.encrypt_bytes_into(plaintext, output),
+            _ => push.encrypt_padded_into(plaintext, padded, output),
+        }
+        .context(Encrypt)
+    }
+}
+
+/// Round `len` up to a multiple of `multiple`, saturating on overflow
+fn round_up(len: usize, multiple: usize) -> usize {
+    let multiple = multiple.max(1);
+
+    len.checked_next_multiple_of(multiple).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::{key::Key, stream::HEADER_SIZE};
+    use crate::net::{Connection, MAX_FRAME_SIZE};
+    use crate::test::*;
+
+    const BUCKETS: [usize; 9] =
+        [64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384];
+
+    fn buckets() -> PaddingPolicy {
+        PaddingPolicy::PadToBuckets(BUCKETS.to_vec())
+    }
+
+    /// Send `payloads` on a dialing `Connection` using `policy` and return
+    /// the recorded bytes
+    async fn record(policy: PaddingPolicy, payloads: &[Vec<u8>]) -> Vec<u8> {
+        let (socket, output) = WireSocket::new(Vec::new());
+        let mut connection = Connection::new(Box::new(socket));
+
+        connection
+            .secure_as_dialer(&dialer(), acceptor().keypair().public())
+            .await
+            .expect("handshake failed");
+        connection.set_padding(policy);
+
+        for payload in payloads {
+            connection.send(payload).await.expect("send failed");
+        }
+
+        let output = output.lock().expect("output poisoned").clone();
+
+        output
+    }
+
+    /// Split recorded bytes into the size of each encrypted frame, skipping
+    /// the plain handshake frame
+    fn frame_sizes(mut bytes: &[u8]) -> Vec<usize> {
+        let mut sizes = Vec::new();
+
+        while !bytes.is_empty() {
+            let (size, rest) = bytes.split_at(FRAME_OVERHEAD as usize);
+            let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+
+            sizes.push(size);
+            bytes = &rest[size..];
+        }
+
+        sizes.remove(0);
+        sizes
+    }
+
+    /// A mix of small control messages and larger data messages
+    fn message_mix() -> Vec<Vec<u8>> {
+        (0..200)
+            .map(|i| match i % 10 {
+                0 => vec![1; 3000 + i * 7],
+                1 | 2 => vec![2; 400 + i],
+                _ => vec![3; 8 + i % 48],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn padded_lengths() {
+        let multiple = PaddingPolicy::PadToMultiple(64);
+
+        assert_eq!(PaddingPolicy::None.padded_len(10), 10);
+        assert_eq!(multiple.padded_len(0), 64);
+        assert_eq!(multiple.padded_len(60), 64);
+        assert_eq!(multiple.padded_len(61), 128);
+        assert_eq!(buckets().padded_len(252), 256);
+        assert_eq!(buckets().padded_len(253), 512);
+        assert_eq!(buckets().padded_len(20000), 32768);
+        assert_eq!(PaddingPolicy::PadToMultiple(0).padded_len(5), 9);
+        assert_eq!(PaddingPolicy::PadToBuckets(vec![]).padded_len(5), 9);
+        assert_eq!(multiple.padded_len(usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn oversized_after_padding() {
+        let mut push = Push::new(Key::random());
+        let policy = PaddingPolicy::PadToMultiple(MAX_FRAME_SIZE + 1);
+
+        let err = policy
+            .seal(&[0; PADDING_OVERHEAD], MAX_FRAME_SIZE, &mut push)
+            .expect_err("oversized frame accepted");
+
+        assert!(matches!(err, SendError::FrameTooLarge { size: 4, .. }));
+
+        let err = PaddingPolicy::PadToMultiple(64)
+            .seal(&[0; 16], 60, &mut push)
+            .expect_err("frame over the limit accepted");
+
+        assert!(matches!(err, SendError::FrameTooLarge { size: 16, .. }));
+    }
+
+    #[test]
+    fn unpadded_lengths() {
+        let multiple = PaddingPolicy::PadToMultiple(64);
+
+        assert_eq!(PaddingPolicy::None.max_unpadded(100), Some(100));
+        assert_eq!(multiple.max_unpadded(200), Some(188));
+        assert_eq!(multiple.max_unpadded(63), None);
+        assert_eq!(buckets().max_unpadded(1000), Some(508));
+        assert_eq!(buckets().max_unpadded(40000), Some(32764));
+
+        for limit in [64, 100, 5000, 70000] {
+            let len = buckets().max_unpadded(limit).unwrap();
+
+            assert!(buckets().padded_len(len) <= limit);
+            assert!(buckets().padded_len(len + 1) > limit);
+        }
+    }
+
+    #[tokio::test]
+    async fn ciphertext_in_buckets() {
+        let policy = buckets();
+        let payloads = message_mix();
+        let bytes = record(policy.clone(), &payloads).await;
+        let sizes = frame_sizes(&bytes);
+
+        assert_eq!(sizes.len(), payloads.len(), "wrong number of frames");
+
+        for (i, (size, payload)) in sizes.iter().zip(&payloads).enumerate() {
+            let header = if i == 0 { HEADER_SIZE } else { 0 };
+            let padded = size - ENCRYPTION_OVERHEAD - header;
+
+            assert!(BUCKETS.contains(&padded), "{} not a bucket", padded);
+
+            assert_eq!(
+                *size as u64,
+                policy.wire_size(payload).unwrap() - FRAME_OVERHEAD
+                    + header as u64,
+                "wrong wire size"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn padded_round_trip() {
+        let payloads = message_mix();
+
+        for policy in [
+            PaddingPolicy::None,
+            PaddingPolicy::PadToMultiple(128),
+            buckets(),
+        ] {
+            let bytes = record(policy.clone(), &payloads).await;
+
+            let (_, received) = replay_as_acceptor::<Vec<u8>>(
+                &acceptor(),
+                &bytes,
+                payloads.len(),
+            )
+            .await;
+
+            assert_eq!(received, payloads, "corrupted with {:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn realistic_overhead() {
+        let payloads = message_mix();
+        let plain = record(PaddingPolicy::None, &payloads).await.len();
+        let padded = record(buckets(), &payloads).await.len();
+        let overhead = (padded - plain) as f64 / plain as f64;
+
+        // small messages dominate the count but not the volume
+        assert!(overhead < 0.5, "padding overhead is {:.2}", overhead);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::telemetry::targets;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A pool of dedicated threads used to run the CPU-bound encryption and
+/// decryption of `ConnectionRead`s and `ConnectionWrite`s outside of the
+/// async runtime. Each `Connection` submits at most one job at a time so the
+/// ordering of messages on a `Connection` is preserved.
+#[derive(Clone)]
+pub struct CryptoPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl CryptoPool {
+    /// Create a new `CryptoPool` using the given number of threads. Threads
+    /// exit once every clone of the `CryptoPool` has been dropped.
+    pub fn new(threads: usize) -> Self {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for idx in 0..threads.max(1) {
+            let rx = rx.clone();
+
+            thread::Builder::new()
+                .name(format!("drop-crypto-{}", idx))
+                .spawn(move || Self::work(rx))
+                .expect("failed to spawn crypto thread");
+        }
+
+        Self { jobs }
+    }
+
+    fn work(jobs: Arc<Mutex<Receiver<Job>>>) {
+        loop {
+            let job = jobs.lock().expect("crypto pool poisoned").recv();
+
+            match job {
+                Ok(job) => job(),
+                Err(_) => break,
+            }
+        }
+
+        debug!(target: targets::CONNECTION, "crypto thread exiting");
+    }
+
+    /// Run a closure on this `CryptoPool` and wait for its result
+    pub async fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = tx.send(f());
+        });
+
+        // run the job inline if all threads have exited
+        if let Err(mpsc::SendError(job)) = self.jobs.send(job) {
+            job();
+        }
+
+        rx.await.expect("crypto job panicked")
+    }
+}
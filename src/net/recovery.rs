@@ -0,0 +1,306 @@
+use std::{fmt, io, net::SocketAddr};
+
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, warn};
+
+use super::ListenerError;
+use crate::telemetry::targets;
+
+/// Delay before accepting again after resources were first exhausted
+pub const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Longest delay between two attempts to accept while resources are exhausted
+pub const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Minimum time between two warnings about exhausted resources
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// OS error codes for too many open files in the process or the system and
+/// for lack of memory
+#[cfg(unix)]
+const EXHAUSTION_CODES: &[i32] = &[24, 23, 12];
+
+/// OS error codes for too many open sockets and lack of buffer space
+#[cfg(windows)]
+const EXHAUSTION_CODES: &[i32] = &[10024, 10055];
+
+#[cfg(not(any(unix, windows)))]
+const EXHAUSTION_CODES: &[i32] = &[];
+
+/// Step of establishing a `Connection` during which an error happened
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Opening or accepting the underlying `Socket`
+    Establish,
+    /// Securing the `Connection` once its `Socket` is open
+    Handshake,
+    /// Deciding whether the remote peer is allowed to connect once it is
+    /// authenticated
+    Authorize,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Establish => write!(f, "establish"),
+            Self::Handshake => write!(f, "handshake"),
+            Self::Authorize => write!(f, "authorize"),
+        }
+    }
+}
+
+/// Check whether the operation that returned `error` can be attempted again
+/// right away
+pub fn is_retryable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Check whether `error` was caused by the process or the system running out
+/// of file descriptors or memory
+pub fn is_exhausted(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::OutOfMemory
+        || error
+            .raw_os_error()
+            .is_some_and(|code| EXHAUSTION_CODES.contains(&code))
+}
+
+/// Recovery of accept loops from errors that do not concern a single
+/// incoming `Connection`
+#[derive(Debug, Default)]
+pub(crate) struct AcceptBackoff {
+    delay: Option<Duration>,
+    warned: Option<Instant>,
+    suppressed: usize,
+}
+
+impl AcceptBackoff {
+    /// Recover from `error` returned when accepting on `local`, waiting
+    /// before the next attempt if resources are exhausted. Returns `false` if
+    /// the error can't be recovered from and should be reported.
+    pub(crate) async fn recover(
+        &mut self,
+        error: &ListenerError,
+        local: SocketAddr,
+    ) -> bool {
+        if error.is_retryable() {
+            debug!(
+                target: targets::LISTENER,
+                "retrying to accept on {} after {}", local, error
+            );
+            return true;
+        }
+
+        if !error.is_exhausted() {
+            return false;
+        }
+
+        let delay = self
+            .delay
+            .map_or(MIN_ACCEPT_BACKOFF, |delay| delay * 2)
+            .min(MAX_ACCEPT_BACKOFF);
+
+        self.delay = Some(delay);
+        self.warn(error, local, delay);
+
+        time::sleep(delay).await;
+
+        true
+    }
+
+    /// Forget previous failures after successfully accepting
+    pub(crate) fn reset(&mut self) {
+        self.delay = None;
+    }
+
+    fn warn(
+        &mut self,
+        error: &ListenerError,
+        local: SocketAddr,
+        delay: Duration,
+    ) {
+        let now = Instant::now();
+
+        if self
+            .warned
+            .is_some_and(|last| now.duration_since(last) < WARN_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+
+        warn!(
+            target: targets::LISTENER,
+            "out of resources accepting on {}: {}, retrying in {:?} \
+             ({} similar warnings suppressed)",
+            local, error, delay, self.suppressed
+        );
+
+        self.warned = Some(now);
+        self.suppressed = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::VecDeque, iter};
+
+    use async_trait::async_trait;
+    use snafu::ResultExt;
+
+    use super::*;
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        net::{listener::Io, ConnectError, Listener, Socket},
+        test::WireSocket,
+    };
+
+    const EMFILE: i32 = 24;
+
+    /// A `Listener` that returns the given results in order
+    struct Scripted {
+        results: VecDeque<io::Result<()>>,
+        exchanger: Exchanger,
+    }
+
+    impl Scripted {
+        fn new(results: impl IntoIterator<Item = io::Result<()>>) -> Self {
+            Self {
+                results: results.into_iter().collect(),
+                exchanger: Exchanger::random(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Listener for Scripted {
+        type Candidate = SocketAddr;
+
+        async fn establish(
+            &mut self,
+        ) -> Result<Box<dyn Socket>, ListenerError> {
+            self.results
+                .pop_front()
+                .expect("script exhausted")
+                .context(Io)?;
+
+            // no bytes to read: the handshake fails
+            Ok(Box::new(WireSocket::new(Vec::new()).0))
+        }
+
+        fn exchanger(&self) -> &Exchanger {
+            &self.exchanger
+        }
+
+        async fn candidates(&self) -> Result<Vec<SocketAddr>, ListenerError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn addr() -> SocketAddr {
+        ([127, 0, 0, 1], 0).into()
+    }
+
+    fn os(code: i32) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(code))
+    }
+
+    fn kind(kind: io::ErrorKind) -> io::Result<()> {
+        Err(kind.into())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(start_paused = true)]
+    async fn exhaustion_backoff() {
+        let mut listener = Scripted::new(
+            [os(EMFILE), os(23), os(12), os(EMFILE), Ok(()), os(EMFILE)]
+                .into_iter()
+                .chain(iter::repeat_with(|| os(EMFILE)).take(10)),
+        );
+        let mut backoff = AcceptBackoff::default();
+        let mut delays = Vec::new();
+
+        loop {
+            let start = Instant::now();
+
+            match listener.accept().await {
+                Err(e) if e.phase() == Some(Phase::Handshake) => {
+                    backoff.reset();
+                    break;
+                }
+                Err(e) => {
+                    assert!(backoff.recover(&e, addr()).await);
+                }
+                Ok(_) => unreachable!("handshake succeeded"),
+            }
+
+            delays.push(start.elapsed());
+        }
+
+        assert_eq!(
+            delays,
+            [10, 20, 40, 80].map(Duration::from_millis),
+            "bad backoff"
+        );
+        assert_eq!(backoff.suppressed, 3, "warnings not rate limited");
+
+        for _ in 0..8 {
+            let e = listener.accept().await.expect_err("accepted");
+
+            backoff.recover(&e, addr()).await;
+        }
+
+        assert_eq!(backoff.delay, Some(MAX_ACCEPT_BACKOFF), "unbounded");
+
+        let start = Instant::now();
+        let e = listener.accept().await.expect_err("accepted");
+
+        backoff.recover(&e, addr()).await;
+
+        assert_eq!(start.elapsed(), MAX_ACCEPT_BACKOFF);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_and_phase() {
+        let mut listener = Scripted::new([
+            kind(io::ErrorKind::Interrupted),
+            kind(io::ErrorKind::WouldBlock),
+            kind(io::ErrorKind::PermissionDenied),
+            Ok(()),
+        ]);
+        let mut backoff = AcceptBackoff::default();
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            let e = listener.accept().await.expect_err("accepted");
+
+            assert_eq!(e.phase(), Some(Phase::Establish));
+            assert!(backoff.recover(&e, addr()).await, "not retried");
+        }
+
+        assert_eq!(start.elapsed(), Duration::ZERO, "retry was delayed");
+
+        let fatal = listener.accept().await.expect_err("accepted");
+
+        assert_eq!(fatal.phase(), Some(Phase::Establish));
+        assert!(!backoff.recover(&fatal, addr()).await, "recovered");
+
+        let handshake = listener.accept().await.expect_err("accepted");
+
+        assert_eq!(handshake.phase(), Some(Phase::Handshake));
+        assert!(!backoff.recover(&handshake, addr()).await);
+    }
+
+    #[test]
+    fn connect_phase() {
+        let refused: ConnectError = io::ErrorKind::ConnectionRefused.into();
+        let timeout = ConnectError::HandshakeTimeout {
+            after: Duration::from_secs(1),
+        };
+
+        assert_eq!(refused.phase(), Some(Phase::Establish));
+        assert_eq!(timeout.phase(), Some(Phase::Handshake));
+    }
+}
@@ -1,12 +1,20 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use super::super::common::directory::*;
-use super::super::listener::{Listener, ListenerError};
-use super::super::Connection;
+use super::super::listener::{
+    Authorization, ConnectionIdentity, Decision, Listener, ListenerError,
+};
+use super::super::{AcceptBackoff, Connection, ContactCard, ReceiveError};
 use super::*;
+use crate::codec::bincode_options;
 use crate::crypto::key::exchange::PublicKey;
+use crate::telemetry::targets;
+
+use bincode::Options;
 
 use snafu::{IntoError, ResultExt};
 
@@ -16,23 +24,252 @@ use tokio::sync::broadcast::{
     channel as bcast_channel, Receiver as BcastReceiver, Sender as BcastSender,
 };
 
+use tokio::net::TcpStream;
 use tokio::sync::oneshot::{channel, Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock, Semaphore};
 use tokio::task;
+use tokio::time::{self, Instant};
 
 use tracing::{debug, error, info, trace, trace_span, warn};
 use tracing_futures::Instrument;
 
-type PeerDirectory = Arc<RwLock<HashMap<PublicKey, SocketAddr>>>;
+type PeerDirectory = Arc<RwLock<BTreeMap<PublicKey, Record>>>;
+
+/// A `Request::ConnectBack` on its way to its target: the key of the
+/// requester and its addresses
+type Reversal = (PublicKey, Vec<SocketAddr>);
+
+/// Subscriptions to `Request::ConnectBack`s by key, along with the
+/// identifier of the subscribed connection
+type Subscriptions =
+    Arc<Mutex<HashMap<PublicKey, (u64, mpsc::Sender<Reversal>)>>>;
+
+/// Number of `Request::ProbeMe` a client can send at once before being
+/// limited to one per `PROBE_REFILL`
+const PROBE_BURST: u32 = 8;
+
+/// Time after which a client can send one more `Request::ProbeMe`
+const PROBE_REFILL: Duration = Duration::from_secs(1);
+
+/// Maximum number of probes running at once across all clients
+const MAX_CONCURRENT_PROBES: usize = 16;
+
+/// Time after which a probed address is deemed unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of `Request::ConnectBack` a client can send at once before being
+/// limited to one per `CONNECT_BACK_REFILL`
+const CONNECT_BACK_BURST: u32 = 4;
+
+/// Time after which a client can send one more `Request::ConnectBack`
+const CONNECT_BACK_REFILL: Duration = Duration::from_secs(5);
+
+/// Maximum number of addresses in a `Request::ConnectBack`
+const MAX_CONNECT_BACK_ADDRS: usize = 8;
+
+/// Maximum number of candidates in the `ContactCard` of a `Request::AddCard`
+const MAX_CARD_ADDRS: usize = 16;
+
+/// Number of `Request::ConnectBack` waiting to be relayed to a subscriber
+/// before further ones are rejected
+const SUBSCRIPTION_QUEUE: usize = 16;
+
+/// Number of peers copied from the directory each time its lock is taken
+/// while answering `Request::Wait`
+const LIST_BATCH: usize = 256;
+
+/// Default number of peers listed to a client per second
+const DEFAULT_LISTING_RATE: u32 = 16_384;
+
+/// Number of `Conflict`s kept by a `ConflictLog`
+const RECENT_CONFLICTS: usize = 64;
+
+/// Shortest time to live of the peers of a `DirectoryServer`, shorter ones
+/// given to `DirectoryServer::new_with_ttl` are raised to this value
+pub const MIN_TTL: Duration = Duration::from_secs(1);
+
+/// Signed proof of a registration
+#[derive(Clone)]
+enum Signed {
+    /// Registered using `Request::AddSigned`
+    Info(SignedInfo),
+    /// Registered using `Request::AddCard`, which always has an expiry
+    Card(ContactCard),
+}
+
+impl Signed {
+    /// Time after which the registration is stale
+    fn expiry(&self) -> SystemTime {
+        match self {
+            Self::Info(info) => info.expiry(),
+            Self::Card(card) => card.expiry().expect("card without expiry"),
+        }
+    }
+}
+
+/// A record in the directory, along with its signature when registered
+/// using `Request::AddSigned` or `Request::AddCard`
+#[derive(Clone)]
+struct Record {
+    /// Addresses registered for this key by its last registration, in the
+    /// order they should be tried and without duplicates
+    addrs: Vec<SocketAddr>,
+    /// Signature of the last registration, if it was signed
+    signed: Option<Signed>,
+    /// Identifier of the connection that registered this record
+    owner: u64,
+    /// Whether the first address is on the host the record was registered
+    /// from
+    from_source: bool,
+    /// When this record was last registered
+    added: Instant,
+}
+
+impl Record {
+    /// Address that should be tried first for this key
+    fn addr(&self) -> SocketAddr {
+        *self.addrs.first().expect("record without address")
+    }
+
+    /// Check whether the signature of this record expired or, if the
+    /// directory has a `ttl`, whether it was not renewed in time
+    fn is_expired(&self, ttl: Option<Duration>) -> bool {
+        self.signed
+            .as_ref()
+            .is_some_and(|signed| signed.expiry() <= SystemTime::now())
+            || ttl.is_some_and(|ttl| self.added.elapsed() >= ttl)
+    }
+}
+
+/// Notification sent by a `DirectoryServer` to all of its `PeerServicer`s
+#[derive(Clone, Copy, Debug)]
+enum Notice {
+    /// A peer was added, leaving this many peers that have not expired
+    Added(usize),
+    /// The peer with this key expired and was evicted
+    Expired(PublicKey),
+}
+
+/// Number of records of `peers` that have not expired, records are only
+/// checked one by one when they expire after `ttl`
+fn live(peers: &BTreeMap<PublicKey, Record>, ttl: Option<Duration>) -> usize {
+    match ttl {
+        Some(ttl) => {
+            peers.values().filter(|r| !r.is_expired(Some(ttl))).count()
+        }
+        None => peers.len(),
+    }
+}
+
+/// How a `DirectoryServer` resolves the registration of a `PublicKey` that
+/// another client already registered at a different address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing registration and reject the new one
+    Reject,
+    /// Replace the existing registration only if the new one is for an
+    /// address on the host it was sent from while the existing one is not.
+    /// This is the default, since a node that moved registers from its new
+    /// address while a misconfigured clone usually registers the address
+    /// of the original.
+    #[default]
+    PreferSource,
+    /// Replace the existing registration only if both are signed and the
+    /// new one expires later
+    PreferNewest,
+}
+
+/// A registration that conflicted with an existing one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    /// The `PublicKey` registered twice
+    pub pkey: PublicKey,
+    /// Address of the existing registration
+    pub existing: SocketAddr,
+    /// Address of the new registration
+    pub contender: SocketAddr,
+    /// Whether the new registration replaced the existing one
+    pub replaced: bool,
+}
+
+#[derive(Default)]
+struct Conflicts {
+    count: u64,
+    recent: VecDeque<Conflict>,
+}
+
+/// The conflicting registrations seen by a `DirectoryServer`, see
+/// `DirectoryServer::conflicts`
+#[derive(Clone, Default)]
+pub struct ConflictLog {
+    conflicts: Arc<Mutex<Conflicts>>,
+}
+
+impl ConflictLog {
+    /// Number of conflicting registrations so far
+    pub fn count(&self) -> u64 {
+        self.lock().count
+    }
+
+    /// The last conflicting registrations, oldest first
+    pub fn recent(&self) -> Vec<Conflict> {
+        self.lock().recent.iter().copied().collect()
+    }
+
+    fn record(&self, conflict: Conflict) {
+        let mut conflicts = self.lock();
+
+        conflicts.count += 1;
+
+        if conflicts.recent.len() == RECENT_CONFLICTS {
+            conflicts.recent.pop_front();
+        }
+
+        conflicts.recent.push_back(conflict);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Conflicts> {
+        self.conflicts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
 
 /// A server that serves directory requests from peers. The incoming
 /// connection must be plain text to avoid having to know a public key for
-/// the directory server.
+/// the directory server. <br />
+/// If the `Listener` has an `Authorization`, it is consulted before
+/// registering a peer. Since directory connections are not secured, the
+/// identity it is given is the key being registered. <br />
+/// Clients may ask the server to check whether their addresses are
+/// reachable using `Request::ProbeMe`. Only addresses on the host the
+/// request comes from are probed and probes are rate limited, so that the
+/// server can not be used to scan other hosts. <br />
+/// Clients that can not be dialed may ask a subscribed peer to dial them
+/// using `Request::ConnectBack`. The request is only relayed to the peer
+/// subscribed with the target key, for addresses on the host the request
+/// comes from, and at a limited rate per client. <br />
+/// Registering a `PublicKey` that another client registered at a different
+/// address is a conflict, resolved according to a `ConflictPolicy` and
+/// recorded in the `ConflictLog` of the server. <br />
+/// The directory is listed in batches, without holding its lock while
+/// sending them, and at a limited rate per client so that listing a large
+/// directory to a slow client does not hold up registrations. <br />
+/// Servers created using `DirectoryServer::new_with_ttl` expire the peers
+/// that did not register again or use `Request::Renew` within their time to
+/// live, they are then neither found nor listed and are evicted in the
+/// background, which is announced to clients waiting for peers.
 pub struct DirectoryServer {
     peers: PeerDirectory,
     listener: Box<dyn Listener<Candidate = SocketAddr>>,
     exit: Receiver<()>,
-    sender: BcastSender<usize>,
+    sender: BcastSender<Notice>,
+    allow_unsigned: bool,
+    allow_legacy: bool,
+    probes: Arc<Semaphore>,
+    conflict_policy: ConflictPolicy,
+    conflicts: ConflictLog,
+    listing_rate: u32,
+    subscriptions: Subscriptions,
+    ttl: Option<Duration>,
 }
 
 impl DirectoryServer {
@@ -50,31 +287,155 @@ impl DirectoryServer {
                 peers: PeerDirectory::default(),
                 exit: rx,
                 sender,
+                allow_unsigned: false,
+                allow_legacy: true,
+                probes: Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES)),
+                conflict_policy: ConflictPolicy::default(),
+                conflicts: ConflictLog::default(),
+                listing_rate: DEFAULT_LISTING_RATE,
+                subscriptions: Subscriptions::default(),
+                ttl: None,
             },
             tx,
         )
     }
 
+    /// Create a new directory server like `DirectoryServer::new` that
+    /// expires peers that did not register again within `ttl`, which is at
+    /// least `MIN_TTL`
+    pub fn new_with_ttl(
+        listener: Box<dyn Listener<Candidate = SocketAddr>>,
+        ttl: Duration,
+    ) -> (Self, Sender<()>) {
+        let (mut server, tx) = Self::new(listener);
+
+        server.ttl = Some(ttl.max(MIN_TTL));
+
+        (server, tx)
+    }
+
+    /// Accept registrations using the unsigned `Request::Add` for
+    /// compatibility with older peers. Unsigned registrations are rejected
+    /// by default since anyone can register any `PublicKey` with them.
+    pub fn allow_unsigned(mut self, allow: bool) -> Self {
+        self.allow_unsigned = allow;
+        self
+    }
+
+    /// Serve clients that start without a `Hello` using the protocol that
+    /// predates it. Such clients are served by default during their
+    /// deprecation period.
+    pub fn allow_legacy(mut self, allow: bool) -> Self {
+        self.allow_legacy = allow;
+        self
+    }
+
+    /// Resolve conflicting registrations using `policy` instead of
+    /// `ConflictPolicy::PreferSource`
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// List at most `rate` peers per second to each client, or as fast as
+    /// clients read them if `rate` is 0
+    pub fn listing_rate(mut self, rate: u32) -> Self {
+        self.listing_rate = rate;
+        self
+    }
+
+    /// Get the `ConflictLog` of this server, which stays up to date while
+    /// the server runs
+    pub fn conflicts(&self) -> ConflictLog {
+        self.conflicts.clone()
+    }
+
     /// Serve requests according to parameters given at server creation
-    pub async fn serve(mut self) -> Result<(), ServerError> {
+    pub async fn serve(self) -> Result<(), ServerError> {
+        let eviction = self.ttl.map(|ttl| {
+            task::spawn(Self::evict(
+                self.peers.clone(),
+                self.sender.clone(),
+                ttl,
+            ))
+        });
+        let result = self.accept().await;
+
+        if let Some(eviction) = eviction {
+            eviction.abort();
+        }
+
+        result
+    }
+
+    /// Remove expired peers from the directory every half `ttl`, notifying
+    /// every `PeerServicer` of each of them
+    async fn evict(
+        peers: PeerDirectory,
+        sender: BcastSender<Notice>,
+        ttl: Duration,
+    ) {
+        let mut interval = time::interval(ttl / 2);
+
+        loop {
+            interval.tick().await;
+
+            let mut expired = Vec::new();
+
+            peers.write().await.retain(|pkey, record| {
+                let keep = !record.is_expired(Some(ttl));
+
+                if !keep {
+                    expired.push(*pkey);
+                }
+
+                keep
+            });
+
+            if !expired.is_empty() {
+                debug!(
+                    target: targets::DIRECTORY,
+                    "evicted {} expired peers",
+                    expired.len()
+                );
+            }
+
+            for pkey in expired {
+                let _ = sender.send(Notice::Expired(pkey));
+            }
+        }
+    }
+
+    /// Accept incoming `Connection`s until told to exit, serving each of
+    /// them on its own task
+    async fn accept(mut self) -> Result<(), ServerError> {
         let mut exit_fut = Some(self.exit);
+        let mut backoff = AcceptBackoff::default();
+        let mut next_id = 0;
 
         loop {
             let (exit, connection) = match Self::poll_incoming(
                 self.listener.as_mut(),
                 exit_fut.take().unwrap(),
+                &mut backoff,
             )
             .await
             {
                 PollResult::Error(e) => {
-                    error!("failed to accept incoming connection: {}", e);
+                    error!(
+                        target: targets::DIRECTORY,
+                        "failed to accept incoming connection: {}", e
+                    );
                     return Err(Accept.into_error(e));
                 }
                 PollResult::Exit => {
-                    info!("directory server exiting...");
+                    info!(
+                        target: targets::DIRECTORY,
+                        "directory server exiting..."
+                    );
                     return Ok(());
                 }
-                PollResult::Incoming(exit, connection) => (exit, connection),
+                PollResult::Incoming(exit, connection) => (exit, *connection),
             };
 
             exit_fut = Some(exit);
@@ -83,196 +444,1204 @@ impl DirectoryServer {
                 when: "accepting connection",
             })?;
 
-            info!("new directory connection from {}", peer_addr);
+            info!(
+                target: targets::DIRECTORY,
+                "new directory connection from {}", peer_addr
+            );
 
             let peers = self.peers.clone();
             let (tx, rx) = (self.sender.clone(), self.sender.subscribe());
+            let allow_unsigned = self.allow_unsigned;
+            let allow_legacy = self.allow_legacy;
+            let authorization = self.listener.authorization().cloned();
+            let probes = self.probes.clone();
+            let conflicts = (self.conflict_policy, self.conflicts.clone());
+            let listing_rate = self.listing_rate;
+            let subscriptions = self.subscriptions.clone();
+            let ttl = self.ttl;
+
+            next_id += 1;
+
+            let id = next_id;
 
             task::spawn(
                 async move {
-                    let servicer = PeerServicer::new(connection, peers, tx, rx);
+                    let servicer = PeerServicer::new(
+                        connection,
+                        peers,
+                        tx,
+                        rx,
+                        allow_unsigned,
+                    )
+                    .allow_legacy(allow_legacy)
+                    .authorization(authorization)
+                    .probes(probes)
+                    .conflicts(id, conflicts)
+                    .listing_rate(listing_rate)
+                    .subscriptions(subscriptions)
+                    .ttl(ttl);
 
                     if let Err(e) = servicer.serve().await {
-                        error!("failed to service peer: {}", e);
+                        error!(
+                            target: targets::DIRECTORY,
+                            "failed to service peer: {}", e
+                        );
                     }
                 }
-                .instrument(trace_span!("peer_service", client = %peer_addr)),
+                .instrument(trace_span!(
+                    target: targets::DIRECTORY,
+                    "peer_service",
+                    client = %peer_addr
+                )),
             );
 
-            info!("waiting for next connection");
+            info!(target: targets::DIRECTORY, "waiting for next connection");
         }
     }
 
+    /// Wait for the next incoming `Connection`, retrying after errors that
+    /// do not concern a single `Connection`
     async fn poll_incoming<L: Listener<Candidate = SocketAddr> + ?Sized>(
         listener: &mut L,
-        exit: Receiver<()>,
+        mut exit: Receiver<()>,
+        backoff: &mut AcceptBackoff,
     ) -> PollResult {
-        match future::select(exit, listener.establish()).await {
-            Either::Left(_) => PollResult::Exit,
-            Either::Right((Ok(connection), exit)) => {
-                PollResult::Incoming(exit, Connection::new(connection))
+        loop {
+            let error = match future::select(exit, listener.establish()).await {
+                Either::Left(_) => return PollResult::Exit,
+                Either::Right((Ok(connection), exit)) => {
+                    backoff.reset();
+
+                    return PollResult::Incoming(
+                        exit,
+                        Box::new(Connection::new(connection)),
+                    );
+                }
+                Either::Right((Err(e), next)) => {
+                    exit = next;
+                    e
+                }
+            };
+
+            let local = listener
+                .local_addr()
+                .unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, 0).into());
+            let recover = Box::pin(backoff.recover(&error, local));
+            let (recovered, next) = match future::select(exit, recover).await {
+                Either::Left(_) => return PollResult::Exit,
+                Either::Right(result) => result,
+            };
+
+            if !recovered {
+                return PollResult::Error(error);
             }
-            Either::Right((Err(e), _)) => PollResult::Error(e),
+
+            exit = next;
         }
     }
 }
 
 enum PollResult {
-    Incoming(Receiver<()>, Connection),
+    Incoming(Receiver<()>, Box<Connection>),
     Error(ListenerError),
     Exit,
 }
 
+/// Outcome of the first message sent by a client
+enum Greeting {
+    /// The client said hello and the protocol was negotiated
+    Hello,
+    /// The client predates `Hello` and started with this `Request`
+    Legacy(Request),
+    /// The client left or was rejected
+    Closed,
+}
+
 struct PeerServicer {
     peers: PeerDirectory,
     connection: Connection,
     /// Broadcast channel to let other `PeerService` know a peer was added
-    sender: BcastSender<usize>,
+    sender: BcastSender<Notice>,
     /// Broadcast receiver to receive notifications from other `PeerServicer`
-    receiver: BcastReceiver<usize>,
+    /// and from eviction
+    receiver: BcastReceiver<Notice>,
+    /// Whether unsigned `Request::Add` are accepted
+    allow_unsigned: bool,
+    /// Peers added through this connection, only those can be removed
+    registered: HashSet<PublicKey>,
+    /// Whether clients that do not send a `Hello` are served
+    allow_legacy: bool,
+    /// Protocol negotiated with the client
+    hello: Hello,
+    /// Authorization of the `Listener`, consulted before registering a peer
+    authorization: Option<Authorization>,
+    /// Probes that can run at once, shared by all `PeerServicer`s
+    probes: Arc<Semaphore>,
+    /// Rate limit of the probes requested by this client
+    probe_budget: Budget,
+    /// Subscriptions to `Request::ConnectBack`s, shared by all
+    /// `PeerServicer`s
+    subscriptions: Subscriptions,
+    /// Rate limit of the `Request::ConnectBack`s sent by this client
+    reversal_budget: Budget,
+    /// Identifier of the connection, unique within the server
+    id: u64,
+    conflict_policy: ConflictPolicy,
+    conflicts: ConflictLog,
+    /// Number of peers listed to this client per second, 0 if unlimited
+    listing_rate: u32,
+    /// Time after which peers that did not register again expire
+    ttl: Option<Duration>,
+}
+
+/// Paces a listing to a given number of peers per second
+struct Pacer {
+    rate: u32,
+    start: Instant,
+    listed: u32,
+}
+
+impl Pacer {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            start: Instant::now(),
+            listed: 0,
+        }
+    }
+
+    /// Count `count` peers as listed, waiting until the rate allows more
+    async fn pace(&mut self, count: usize) {
+        if self.rate == 0 {
+            return;
+        }
+
+        self.listed = self.listed.saturating_add(count as u32);
+
+        let due = Duration::from_secs(1) * self.listed / self.rate;
+
+        time::sleep_until(self.start + due).await;
+    }
+}
+
+/// A token bucket limiting the rate of some requests of one client
+struct Budget {
+    burst: u32,
+    refill: Duration,
+    tokens: u32,
+    refilled: Instant,
+}
+
+impl Budget {
+    /// Allow `burst` requests at once, then one more every `refill`
+    fn new(burst: u32, refill: Duration) -> Self {
+        Self {
+            burst,
+            refill,
+            tokens: burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Take a token if there is one left, refilling the bucket first
+    fn take(&mut self) -> bool {
+        let elapsed = self.refilled.elapsed().as_millis();
+        let earned = (elapsed / self.refill.as_millis()) as u32;
+
+        if earned > 0 {
+            self.tokens = self.tokens.saturating_add(earned).min(self.burst);
+            self.refilled += self.refill * earned;
+        }
+
+        if self.tokens == 0 {
+            return false;
+        }
+
+        self.tokens -= 1;
+
+        true
+    }
 }
 
 impl PeerServicer {
     fn new(
         connection: Connection,
         peers: PeerDirectory,
-        sender: BcastSender<usize>,
-        receiver: BcastReceiver<usize>,
+        sender: BcastSender<Notice>,
+        receiver: BcastReceiver<Notice>,
+        allow_unsigned: bool,
     ) -> Self {
         Self {
             peers,
             connection,
             sender,
             receiver,
+            allow_unsigned,
+            registered: HashSet::new(),
+            allow_legacy: true,
+            hello: Hello::legacy(),
+            authorization: None,
+            probes: Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES)),
+            probe_budget: Budget::new(PROBE_BURST, PROBE_REFILL),
+            subscriptions: Subscriptions::default(),
+            reversal_budget: Budget::new(
+                CONNECT_BACK_BURST,
+                CONNECT_BACK_REFILL,
+            ),
+            id: 0,
+            conflict_policy: ConflictPolicy::default(),
+            conflicts: ConflictLog::default(),
+            listing_rate: DEFAULT_LISTING_RATE,
+            ttl: None,
         }
     }
 
-    /// Notify other `PeerServicer` that a new peer has been added
+    fn allow_legacy(mut self, allow: bool) -> Self {
+        self.allow_legacy = allow;
+        self
+    }
+
+    fn authorization(mut self, authorization: Option<Authorization>) -> Self {
+        self.authorization = authorization;
+        self
+    }
+
+    fn probes(mut self, probes: Arc<Semaphore>) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    fn conflicts(
+        mut self,
+        id: u64,
+        (policy, conflicts): (ConflictPolicy, ConflictLog),
+    ) -> Self {
+        self.id = id;
+        self.conflict_policy = policy;
+        self.conflicts = conflicts;
+        self
+    }
+
+    fn listing_rate(mut self, rate: u32) -> Self {
+        self.listing_rate = rate;
+        self
+    }
+
+    fn subscriptions(mut self, subscriptions: Subscriptions) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    fn ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Notify other `PeerServicer` that a new peer has been added, along
+    /// with the number of peers that have not expired
     async fn notify(&mut self) -> Result<(), ()> {
+        let count = live(&*self.peers.read().await, self.ttl);
+
         self.sender
-            .send(self.peers.read().await.len())
+            .send(Notice::Added(count))
             .map(|_| ())
             .map_err(|_| ())
     }
 
+    /// Copy up to `size` peers whose key is greater than `after`, only
+    /// holding the directory lock while copying them
+    async fn page(&self, after: Option<PublicKey>, size: usize) -> Vec<Info> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+
+        self.peers
+            .read()
+            .await
+            .range((start, Bound::Unbounded))
+            .filter(|(_, record)| !record.is_expired(self.ttl))
+            .take(size)
+            .map(|(pkey, record)| (*pkey, record.addr()).into())
+            .collect()
+    }
+
     /// List current content of the directory to the remote peer
     async fn list_directory(&mut self) -> Result<(), ServerError> {
-        for peer in self.peers.read().await.iter() {
-            let peer: Info = (*peer.0, *peer.1).into();
-            self.connection.send_plain(&peer).await.context(Send {
-                when: "listing directory",
-            })?;
-        }
-        Ok(())
-    }
+        let mut pacer = Pacer::new(self.listing_rate);
+        let mut after = None;
 
-    /// Fetch and address from the directory by its `PublicKey`
-    async fn handle_fetch(&mut self, pkey: &PublicKey) -> Response {
-        info!("request for {}", pkey);
+        loop {
+            let batch = self.page(after, LIST_BATCH).await;
 
-        if let Some(addr) = self.peers.read().await.get(pkey) {
-            Response::Found(*pkey, *addr)
-        } else {
-            Response::NotFound(*pkey)
+            for peer in batch.iter() {
+                self.connection.send_plain(peer).await.context(Send {
+                    when: "listing directory",
+                })?;
+            }
+
+            match batch.last() {
+                Some(last) if batch.len() == LIST_BATCH => {
+                    after = Some(*last.public());
+                    pacer.pace(batch.len()).await;
+                }
+                _ => return Ok(()),
+            }
         }
     }
 
-    async fn handle_add(&mut self, peer: &Info) -> Response {
-        info!("request to add {}", peer);
+    /// List the directory in `Response::Page`s of `page_size` peers, starting
+    /// after the key `after`
+    async fn list_pages(
+        &mut self,
+        page_size: u16,
+        mut after: Option<PublicKey>,
+    ) -> Result<(), ServerError> {
+        let size = usize::from(page_size.max(1));
+        let mut pacer = Pacer::new(self.listing_rate);
 
-        self.peers.write().await.insert(*peer.public(), peer.addr());
+        loop {
+            let page = self.page(after, size).await;
+            let full = page.len() == size;
 
-        if self.notify().await.is_err() {
-            error!("no peer is waiting on directory listing");
-        }
+            after = page.last().map(|last| *last.public());
 
-        Response::Ok
-    }
+            if !page.is_empty() {
+                let count = page.len();
 
-    async fn handle_wait(&mut self, peer_nr: usize) {
-        debug!("peer wants to wait for {} total peers", peer_nr);
+                self.connection
+                    .send_plain(&Response::Page(page))
+                    .await
+                    .context(Send {
+                        when: "listing directory",
+                    })?;
 
-        if self.peers.read().await.len() < peer_nr {
-            info!("not enough peers, waiting for more...");
-            loop {
-                if let Ok(count) = self.receiver.recv().await {
-                    if count == peer_nr {
-                        break;
-                    }
-                } else {
-                    warn!("all other peer died, stopping wait");
-                    task::yield_now().await;
-                }
+                pacer.pace(count).await;
+            }
+
+            if !full {
+                return Ok(());
             }
         }
     }
 
-    /// Serve directory request to the peer we are connected to.
-    async fn serve(mut self) -> Result<(), ServerError> {
-        info!("servicing directory request");
+    /// Fetch and address from the directory by its `PublicKey`
+    async fn handle_fetch(&mut self, pkey: &PublicKey) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request for {}", pkey.fingerprint()
+        );
 
-        while let Ok(request) = self.connection.receive_plain::<Request>().await
-        {
-            let response = match request {
-                Request::Fetch(ref pkey) => self.handle_fetch(pkey).await,
-                Request::Add(ref peer) => self.handle_add(peer).await,
-                Request::Wait(peer_nr) => {
-                    self.handle_wait(peer_nr).await;
-                    info!(
-                        "reached {} peers in the system, notifying...",
-                        peer_nr
-                    );
+        match self.peers.read().await.get(pkey) {
+            Some(record) if record.is_expired(self.ttl) => {
+                Response::NotFound(*pkey)
+            }
+            Some(Record {
+                signed: Some(Signed::Card(card)),
+                ..
+            }) if self.hello.supports(features::SIGNED | features::ADDRS) => {
+                Response::FoundCard(card.clone())
+            }
+            Some(Record {
+                signed: Some(Signed::Info(signed)),
+                ..
+            }) if self.hello.supports(features::SIGNED) => {
+                Response::FoundSigned(*signed)
+            }
+            Some(record) if self.hello.supports(features::ADDRS) => {
+                Response::Found(*pkey, record.addrs.clone())
+            }
+            Some(record) => Response::FoundOne(*pkey, record.addr()),
+            None => Response::NotFound(*pkey),
+        }
+    }
 
-                    self.list_directory().await?;
+    async fn handle_add(&mut self, peer: &Info) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request to add {} at {}",
+            peer.public().fingerprint(),
+            peer.addr()
+        );
 
-                    Response::Ok
-                }
-            };
+        if !self.allow_unsigned {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected unsigned registration for {}",
+                peer.public().fingerprint()
+            );
+            return Response::Error("unsigned registration".to_string());
+        }
 
-            trace!("sending response {:?}", response);
+        self.insert(*peer.public(), vec![peer.addr()], None).await
+    }
 
-            self.connection.send_plain(&response).await.context(Send {
-                when: "responding to request",
-            })?;
+    async fn handle_add_signed(&mut self, peer: &SignedInfo) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request to add signed {} at {}",
+            peer.public().fingerprint(),
+            peer.addr()
+        );
+
+        if let Err(e) = peer.verify() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected forged registration for {}: {}",
+                peer.public().fingerprint(),
+                e
+            );
+            return Response::Error(format!("invalid signature: {}", e));
         }
 
-        error!("end of client connection");
+        if peer.is_expired() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected expired registration for {}",
+                peer.public().fingerprint()
+            );
+            return Response::Error("expired registration".to_string());
+        }
 
-        Ok(())
+        self.insert(
+            *peer.public(),
+            vec![peer.addr()],
+            Some(Signed::Info(*peer)),
+        )
+        .await
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::super::super::TcpListener;
-    use super::super::super::{Connector, TcpConnector};
-    use super::*;
-    use crate::crypto::key::exchange::Exchanger;
-    use crate::test::*;
+    async fn handle_add_card(&mut self, card: &ContactCard) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request to add card {} at {:?}",
+            card.public().fingerprint(),
+            card.candidates()
+        );
 
-    use tokio::task::{self, JoinHandle};
+        if let Err(e) = card.verify() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected forged card for {}: {}",
+                card.public().fingerprint(),
+                e
+            );
+            return Response::Error(format!("invalid card: {}", e));
+        }
 
-    static TOTAL: usize = 50;
+        let reason = if card.expiry().is_none() {
+            Some("card without expiry".to_string())
+        } else if card.candidates().len() > MAX_CARD_ADDRS {
+            Some(format!("more than {} candidates", MAX_CARD_ADDRS))
+        } else {
+            card.check().err().map(|e| e.to_string())
+        };
+
+        if let Some(reason) = reason {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected card for {}: {}",
+                card.public().fingerprint(),
+                reason
+            );
+            return Response::Error(reason);
+        }
 
-    async fn setup_server(server: SocketAddr) -> (Sender<()>, JoinHandle<()>) {
-        let server_exchanger = Exchanger::random();
-        let listener = Box::new(
-            TcpListener::new(server, server_exchanger)
-                .await
-                .expect("listen failed"),
-        );
-        let (dir_server, exit_tx) = DirectoryServer::new(listener);
+        let mut addrs = Vec::with_capacity(card.candidates().len());
 
-        let handle = task::spawn(async move {
-            dir_server.serve().await.expect("serve failed")
-        });
+        for addr in card.candidates() {
+            if !addrs.contains(addr) {
+                addrs.push(*addr);
+            }
+        }
 
-        (exit_tx, handle)
+        self.insert(*card.public(), addrs, Some(Signed::Card(card.clone())))
+            .await
     }
 
-    fn new_peer() -> (PublicKey, SocketAddr) {
-        let peer = next_test_ip4();
-        let pkey = *Exchanger::random().keypair().public();
+    /// Register `pkey` at `addrs`, replacing every address it registered
+    /// before so that stale ones do not linger
+    async fn insert(
+        &mut self,
+        pkey: PublicKey,
+        addrs: Vec<SocketAddr>,
+        signed: Option<Signed>,
+    ) -> Response {
+        if let Some(response) = self.authorize(pkey).await {
+            return response;
+        }
+
+        let from_source = self.connection.peer_addr().is_ok_and(|source| {
+            addrs.first().is_some_and(|addr| source.ip() == addr.ip())
+        });
+        let record = Record {
+            addrs,
+            signed,
+            owner: self.id,
+            from_source,
+            added: Instant::now(),
+        };
+
+        {
+            let mut peers = self.peers.write().await;
+
+            let existing = peers.get(&pkey).filter(|r| !r.is_expired(self.ttl));
+
+            // registering again from the same client or at a known address
+            // replaces the record, and the peer is still only counted once
+            let contested = existing.filter(|existing| {
+                existing.owner != self.id
+                    && !record.addrs.iter().any(|a| existing.addrs.contains(a))
+            });
+
+            if let Some(existing) = contested {
+                if !self.resolve(pkey, existing, &record) {
+                    return if self.hello.supports(features::CONFLICT) {
+                        Response::Conflict(existing.addr())
+                    } else {
+                        Response::Error(format!(
+                            "already registered at {}",
+                            existing.addr()
+                        ))
+                    };
+                }
+            }
+
+            peers.insert(pkey, record);
+        }
+
+        self.registered.insert(pkey);
+
+        if self.notify().await.is_err() {
+            error!(
+                target: targets::DIRECTORY,
+                "no peer is waiting on directory listing"
+            );
+        }
+
+        Response::Ok
+    }
+
+    /// Decide whether `contender` replaces the `existing` record of `pkey`
+    /// registered by another client, recording the conflict
+    fn resolve(
+        &self,
+        pkey: PublicKey,
+        existing: &Record,
+        contender: &Record,
+    ) -> bool {
+        let replaced = match self.conflict_policy {
+            ConflictPolicy::Reject => false,
+            ConflictPolicy::PreferSource => {
+                contender.from_source && !existing.from_source
+            }
+            ConflictPolicy::PreferNewest => {
+                match (&existing.signed, &contender.signed) {
+                    (Some(existing), Some(contender)) => {
+                        contender.expiry() > existing.expiry()
+                    }
+                    _ => false,
+                }
+            }
+        };
+
+        warn!(
+            target: targets::DIRECTORY,
+            "{} registered at {} conflicts with {}, {}",
+            pkey.fingerprint(),
+            contender.addr(),
+            existing.addr(),
+            if replaced { "replacing it" } else { "rejected" }
+        );
+
+        self.conflicts.record(Conflict {
+            pkey,
+            existing: existing.addr(),
+            contender: contender.addr(),
+            replaced,
+        });
+
+        replaced
+    }
+
+    /// Ask the `Authorizer` of the `Listener`, if any, whether `pkey` may be
+    /// registered, returning the response refusing it otherwise. Peers
+    /// admitted read-only may only query the directory.
+    async fn authorize(&self, pkey: PublicKey) -> Option<Response> {
+        let authorization = self.authorization.as_ref()?;
+        let addr = self
+            .connection
+            .peer_addr()
+            .unwrap_or_else(|_| (Ipv4Addr::UNSPECIFIED, 0).into());
+        let identity = ConnectionIdentity::new(pkey);
+
+        match authorization.decide(&identity, addr).await {
+            Decision::Accept => None,
+            Decision::AcceptReadOnly => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected registration of read-only {}",
+                    pkey.fingerprint()
+                );
+                Some(Response::Error("read-only client".to_string()))
+            }
+            Decision::Reject { reason } => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected unauthorized registration of {}",
+                    pkey.fingerprint()
+                );
+                Some(Response::Error(reason))
+            }
+        }
+    }
+
+    async fn handle_remove(&mut self, pkey: &PublicKey) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request to remove {}", pkey.fingerprint()
+        );
+
+        if !self.registered.remove(pkey) {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected removal of {} from another connection",
+                pkey.fingerprint()
+            );
+            return Response::Error("peer not added by this client".into());
+        }
+
+        let mut peers = self.peers.write().await;
+
+        match peers.get(pkey) {
+            Some(record) if record.owner != self.id => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected removal of {} registered again by another client",
+                    pkey.fingerprint()
+                );
+                Response::Error("peer registered by another client".into())
+            }
+            Some(_) => {
+                peers.remove(pkey);
+                Response::Ok
+            }
+            None => Response::NotFound(*pkey),
+        }
+    }
+
+    /// Extend the time to live of a peer registered by this client, telling
+    /// it to register again if the peer already expired
+    async fn handle_renew(&mut self, pkey: &PublicKey) -> Response {
+        debug!(
+            target: targets::DIRECTORY,
+            "request to renew {}", pkey.fingerprint()
+        );
+
+        if !self.registered.contains(pkey) {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected renewal of {} from another connection",
+                pkey.fingerprint()
+            );
+            return Response::Error("peer not added by this client".into());
+        }
+
+        let mut peers = self.peers.write().await;
+
+        match peers.get_mut(pkey) {
+            Some(record) if record.owner != self.id => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected renewal of {} registered again by another client",
+                    pkey.fingerprint()
+                );
+                Response::Error("peer registered by another client".into())
+            }
+            Some(record) if !record.is_expired(self.ttl) => {
+                record.added = Instant::now();
+                Response::Ok
+            }
+            _ => Response::NotFound(*pkey),
+        }
+    }
+
+    /// Check whether `addr` accepts TCP connections. Only addresses on the
+    /// host the request comes from are probed.
+    async fn handle_probe(&mut self, addr: SocketAddr) -> Response {
+        info!(target: targets::DIRECTORY, "request to probe {}", addr);
+
+        let source = match self.connection.peer_addr() {
+            Ok(source) => source,
+            Err(e) => return Response::Error(format!("unknown source: {}", e)),
+        };
+
+        if source.ip() != addr.ip() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected probe of {} requested from {}", addr, source
+            );
+            return Response::Error("probe target is not the source".into());
+        }
+
+        if !self.probe_budget.take() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected probe of {}, too many probes", addr
+            );
+            return Response::Error("too many probes".into());
+        }
+
+        let _permit = match self.probes.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected probe of {}, server busy", addr
+                );
+                return Response::Error("too many probes".into());
+            }
+        };
+
+        let reachable = matches!(
+            time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await,
+            Ok(Ok(_))
+        );
+
+        debug!(
+            target: targets::DIRECTORY,
+            "probed {}, reachable: {}", addr, reachable
+        );
+
+        Response::Probed { addr, reachable }
+    }
+
+    /// Check that the client subscribing with `record` owns its key and
+    /// registers its subscription, returning the `Receiver` of the
+    /// `Request::ConnectBack`s addressed to it
+    async fn handle_subscribe(
+        &mut self,
+        record: &SignedInfo,
+    ) -> Result<mpsc::Receiver<Reversal>, Response> {
+        let pkey = *record.public();
+
+        info!(
+            target: targets::DIRECTORY,
+            "request to subscribe {}",
+            pkey.fingerprint()
+        );
+
+        if let Err(e) = record.verify() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected forged subscription for {}: {}",
+                pkey.fingerprint(),
+                e
+            );
+            return Err(Response::Error(format!("invalid signature: {}", e)));
+        }
+
+        if record.is_expired() {
+            return Err(Response::Error("expired subscription".into()));
+        }
+
+        match self.connection.peer_addr() {
+            Ok(source) if source.ip() == record.addr().ip() => {}
+            _ => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected subscription of {} for another host",
+                    pkey.fingerprint()
+                );
+                return Err(Response::Error(
+                    "subscription is not for the source".into(),
+                ));
+            }
+        }
+
+        if let Some(response) = self.authorize(pkey).await {
+            return Err(response);
+        }
+
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_QUEUE);
+
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(pkey, (self.id, tx));
+
+        Ok(rx)
+    }
+
+    /// Relay a `Request::ConnectBack` to the peer subscribed with `target`,
+    /// if any, as long as `addrs` are on the host the request comes from
+    fn handle_connect_back(
+        &mut self,
+        target: PublicKey,
+        requester: PublicKey,
+        addrs: Vec<SocketAddr>,
+    ) -> Response {
+        info!(
+            target: targets::DIRECTORY,
+            "request from {} to be dialed by {}",
+            requester.fingerprint(),
+            target.fingerprint()
+        );
+
+        let source = match self.connection.peer_addr() {
+            Ok(source) => source,
+            Err(e) => return Response::Error(format!("unknown source: {}", e)),
+        };
+
+        if addrs.is_empty()
+            || addrs.len() > MAX_CONNECT_BACK_ADDRS
+            || addrs.iter().any(|addr| addr.ip() != source.ip())
+        {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected connect back to {:?} requested from {}",
+                addrs,
+                source
+            );
+            return Response::Error("addresses are not the source".into());
+        }
+
+        if !self.reversal_budget.take() {
+            warn!(
+                target: targets::DIRECTORY,
+                "rejected connect back from {}, too many requests", source
+            );
+            return Response::Error("too many connect back requests".into());
+        }
+
+        let subscriber = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&target)
+            .map(|(_, tx)| tx.clone());
+
+        match subscriber.map(|tx| tx.try_send((requester, addrs))) {
+            Some(Ok(())) => Response::Ok,
+            Some(Err(mpsc::error::TrySendError::Full(_))) => {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "rejected connect back, {} is busy",
+                    target.fingerprint()
+                );
+                Response::Error("target busy".into())
+            }
+            Some(Err(mpsc::error::TrySendError::Closed(_))) | None => {
+                Response::NotFound(target)
+            }
+        }
+    }
+
+    /// Relay `Request::ConnectBack`s to the subscribed client until it
+    /// leaves or another client subscribes with the same key
+    async fn relay(
+        &mut self,
+        pkey: PublicKey,
+        mut reversals: mpsc::Receiver<Reversal>,
+    ) -> Result<(), ServerError> {
+        loop {
+            let reversal = {
+                let next = Box::pin(reversals.recv());
+                let left = Box::pin(self.connection.receive_plain_frame());
+
+                match future::select(next, left).await {
+                    Either::Left((Some(reversal), _)) => reversal,
+                    Either::Left((None, _)) | Either::Right(_) => break,
+                }
+            };
+            let (requester, addrs) = reversal;
+
+            debug!(
+                target: targets::DIRECTORY,
+                "relaying connect back from {} to {}",
+                requester.fingerprint(),
+                pkey.fingerprint()
+            );
+
+            self.connection
+                .send_plain(&Response::ConnectBack(requester, addrs))
+                .await
+                .context(Send {
+                    when: "relaying connect back",
+                })?;
+        }
+
+        let mut subscriptions =
+            self.subscriptions.lock().unwrap_or_else(|e| e.into_inner());
+
+        if subscriptions
+            .get(&pkey)
+            .is_some_and(|(id, _)| *id == self.id)
+        {
+            subscriptions.remove(&pkey);
+        }
+
+        Ok(())
+    }
+
+    /// Wait until the directory holds `peer_nr` peers that have not expired.
+    /// Peers that expire meanwhile are announced using `Response::Expired`
+    /// if `announce` is set and the client negotiated `features::RENEW`.
+    async fn handle_wait(
+        &mut self,
+        peer_nr: usize,
+        announce: bool,
+    ) -> Result<(), ServerError> {
+        debug!(
+            target: targets::DIRECTORY,
+            "peer wants to wait for {} total peers", peer_nr
+        );
+
+        let announce = announce && self.hello.supports(features::RENEW);
+
+        if live(&*self.peers.read().await, self.ttl) < peer_nr {
+            info!(
+                target: targets::DIRECTORY,
+                "not enough peers, waiting for more..."
+            );
+            loop {
+                match self.receiver.recv().await {
+                    Ok(Notice::Added(count)) if count >= peer_nr => break,
+                    Ok(Notice::Added(_)) => {}
+                    Ok(Notice::Expired(pkey)) if announce => {
+                        self.connection
+                            .send_plain(&Response::Expired(pkey))
+                            .await
+                            .context(Send {
+                                when: "announcing expired peer",
+                            })?;
+                    }
+                    Ok(Notice::Expired(_)) => {}
+                    Err(_) => {
+                        warn!(
+                            target: targets::DIRECTORY,
+                            "all other peer died, stopping wait"
+                        );
+                        task::yield_now().await;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Negotiate the protocol with the client. Clients that predate `Hello`
+    /// start directly with a `Request`, which is returned so that it can be
+    /// served in legacy mode.
+    async fn greet(&mut self) -> Result<Greeting, ServerError> {
+        let frame = match self.connection.receive_plain_frame().await {
+            Ok(frame) => frame,
+            Err(e) => {
+                debug!(
+                    target: targets::DIRECTORY,
+                    "client left before saying hello: {}", e
+                );
+                return Ok(Greeting::Closed);
+            }
+        };
+
+        // a `Hello` is always shorter than any `Request`
+        if let Ok(request) = bincode_options().deserialize::<Request>(&frame) {
+            if !self.allow_legacy {
+                warn!(target: targets::DIRECTORY, "rejected legacy client");
+
+                let response =
+                    Response::Error("legacy protocol not supported".into());
+
+                self.connection.send_plain(&response).await.context(Send {
+                    when: "rejecting legacy client",
+                })?;
+
+                return Ok(Greeting::Closed);
+            }
+
+            warn!(
+                target: targets::DIRECTORY,
+                "serving legacy client, it should be upgraded"
+            );
+
+            return Ok(Greeting::Legacy(request));
+        }
+
+        let hello = bincode_options()
+            .deserialize::<Hello>(&frame)
+            .map_err(|source| ReceiveError::DeserializeReceive { source })
+            .context(Receive {
+                when: "waiting for hello",
+            })?;
+        let ours = Hello::current();
+
+        self.connection.send_plain(&ours).await.context(Send {
+            when: "answering hello",
+        })?;
+
+        self.hello = ours.negotiate(&hello);
+
+        info!(
+            target: targets::DIRECTORY,
+            "client said hello, using {}", self.hello
+        );
+
+        Ok(Greeting::Hello)
+    }
+
+    /// Serve directory request to the peer we are connected to.
+    async fn serve(mut self) -> Result<(), ServerError> {
+        info!(target: targets::DIRECTORY, "servicing directory request");
+
+        let mut pending = match self.greet().await? {
+            Greeting::Hello => None,
+            Greeting::Legacy(request) => Some(request),
+            Greeting::Closed => return Ok(()),
+        };
+
+        loop {
+            let request = match pending.take() {
+                Some(request) => request,
+                None => {
+                    match self.connection.receive_plain::<Request>().await {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    }
+                }
+            };
+
+            if !self.hello.allows(&request) {
+                warn!(
+                    target: targets::DIRECTORY,
+                    "client used a feature it did not negotiate"
+                );
+
+                let response = Response::Error("feature not negotiated".into());
+
+                self.connection.send_plain(&response).await.context(Send {
+                    when: "rejecting request",
+                })?;
+
+                continue;
+            }
+
+            let response = match request {
+                Request::Fetch(ref pkey) => self.handle_fetch(pkey).await,
+                Request::Add(ref peer) => self.handle_add(peer).await,
+                Request::AddSigned(ref peer) => {
+                    self.handle_add_signed(peer).await
+                }
+                Request::Remove(ref pkey) => self.handle_remove(pkey).await,
+                Request::Renew(ref pkey) => self.handle_renew(pkey).await,
+                Request::AddCard(ref card) => self.handle_add_card(card).await,
+                Request::ProbeMe(addr) => self.handle_probe(addr).await,
+                Request::Wait(peer_nr) => {
+                    self.handle_wait(peer_nr, false).await?;
+                    info!(
+                        target: targets::DIRECTORY,
+                        "reached {} peers in the system, notifying...",
+                        peer_nr
+                    );
+
+                    self.list_directory().await?;
+
+                    Response::Ok
+                }
+                Request::WaitPaged {
+                    count,
+                    page_size,
+                    after,
+                } => {
+                    self.handle_wait(count, true).await?;
+                    self.list_pages(page_size, after).await?;
+
+                    Response::Ok
+                }
+                Request::Subscribe(ref record) => {
+                    match self.handle_subscribe(record).await {
+                        Ok(reversals) => {
+                            self.connection
+                                .send_plain(&Response::Ok)
+                                .await
+                                .context(Send {
+                                    when: "accepting subscription",
+                                })?;
+
+                            return self
+                                .relay(*record.public(), reversals)
+                                .await;
+                        }
+                        Err(response) => response,
+                    }
+                }
+                Request::ConnectBack {
+                    target,
+                    requester,
+                    addrs,
+                } => self.handle_connect_back(target, requester, addrs),
+            };
+
+            trace!(
+                target: targets::DIRECTORY,
+                "sending response {:?}", response
+            );
+
+            self.connection.send_plain(&response).await.context(Send {
+                when: "responding to request",
+            })?;
+        }
+
+        error!(target: targets::DIRECTORY, "end of client connection");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::{
+        Connector, DirectoryConnector, DirectoryListener, MemoryConnector,
+        MemoryListener, MemoryRegistry, TcpConnector, TcpListener,
+    };
+    use super::*;
+    use crate::codec::bincode_options;
+    use crate::crypto::key::exchange::{Exchanger, KeyPair};
+    use crate::test::*;
+
+    use bincode::Options;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use tokio::task::{self, JoinHandle};
+    use tokio::time;
+
+    static TOTAL: usize = 50;
+
+    async fn setup_server(server: SocketAddr) -> (Sender<()>, JoinHandle<()>) {
+        setup_server_with(server, true).await
+    }
+
+    async fn setup_server_with(
+        server: SocketAddr,
+        allow_unsigned: bool,
+    ) -> (Sender<()>, JoinHandle<()>) {
+        let server_exchanger = Exchanger::random();
+        let listener = Box::new(
+            TcpListener::new(server, server_exchanger)
+                .await
+                .expect("listen failed"),
+        );
+        let (dir_server, exit_tx) = DirectoryServer::new(listener);
+        let dir_server = dir_server.allow_unsigned(allow_unsigned);
+
+        let handle = task::spawn(async move {
+            dir_server.serve().await.expect("serve failed")
+        });
+
+        (exit_tx, handle)
+    }
+
+    fn new_peer() -> (PublicKey, SocketAddr) {
+        let peer = next_test_ip4();
+        let pkey = *Exchanger::random().keypair().public();
 
         (pkey, peer)
     }
@@ -286,219 +1655,1429 @@ mod test {
         let peer = (pkey, addr).into();
         let req = Request::Add(peer);
 
-        let mut connection = Connection::new(
-            connector
-                .establish(&pkey, &server)
+        let mut connection = Connection::new(
+            connector
+                .establish(&pkey, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        connection.send_plain(&req).await.expect("send failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        assert_eq!(resp, Response::Ok, "invalid response");
+
+        connection
+    }
+
+    async fn request(
+        server: SocketAddr,
+        request: &Request,
+    ) -> (Connection, Response) {
+        let connector = TcpConnector::new(Exchanger::random());
+
+        request_with(&connector, server, request).await
+    }
+
+    async fn request_with(
+        connector: &dyn Connector<Candidate = SocketAddr>,
+        server: SocketAddr,
+        request: &Request,
+    ) -> (Connection, Response) {
+        let public = *connector.exchanger().keypair().public();
+        let mut connection = Connection::new(
+            connector
+                .establish(&public, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        connection.send_plain(request).await.expect("send failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        (connection, resp)
+    }
+
+    fn signed(keypair: &KeyPair, addr: SocketAddr) -> SignedInfo {
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+
+        SignedInfo::new(keypair, addr, expiry).expect("sign failed")
+    }
+
+    fn card(keypair: &KeyPair, addrs: Vec<SocketAddr>) -> ContactCard {
+        ContactCard::new(*keypair.public(), addrs)
+            .with_expiry(SystemTime::now() + Duration::from_secs(60))
+            .sign(keypair)
+            .expect("sign failed")
+    }
+
+    /// Send `request` after negotiating the protocol with the directory
+    async fn negotiated(
+        server: SocketAddr,
+        request: &Request,
+    ) -> (Connection, Response) {
+        let connector = TcpConnector::new(Exchanger::random());
+
+        negotiated_with(&connector, server, request).await
+    }
+
+    async fn negotiated_with(
+        connector: &dyn Connector<Candidate = SocketAddr>,
+        server: SocketAddr,
+        request: &Request,
+    ) -> (Connection, Response) {
+        let public = *connector.exchanger().keypair().public();
+        let mut connection = Connection::new(
+            connector
+                .establish(&public, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        negotiate(&mut connection).await.expect("no hello");
+        connection.send_plain(request).await.expect("send failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        (connection, resp)
+    }
+
+    async fn fetch(connection: &mut Connection, pkey: PublicKey) -> Response {
+        connection
+            .send_plain(&Request::Fetch(pkey))
+            .await
+            .expect("fetch failed");
+
+        connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed")
+    }
+
+    fn expiring(keypair: &KeyPair, addr: SocketAddr, secs: u64) -> SignedInfo {
+        let expiry = SystemTime::now() + Duration::from_secs(secs);
+
+        SignedInfo::new(keypair, addr, expiry).expect("sign failed")
+    }
+
+    async fn setup_policy(
+        server: SocketAddr,
+        policy: ConflictPolicy,
+    ) -> (Sender<()>, JoinHandle<()>, ConflictLog) {
+        let listener = TcpListener::new(server, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (dir_server, exit_tx) = DirectoryServer::new(Box::new(listener));
+        let dir_server = dir_server.conflict_policy(policy);
+        let conflicts = dir_server.conflicts();
+        let handle = task::spawn(async move {
+            dir_server.serve().await.expect("serve failed")
+        });
+
+        (exit_tx, handle, conflicts)
+    }
+
+    async fn wait_for_server(exit_tx: Sender<()>, handle: JoinHandle<()>) {
+        exit_tx.send(()).expect("exit_failed");
+        handle.await.expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn serve_many() {
+        init_logger();
+        let server = next_test_ip4();
+        let connector = TcpConnector::new(Exchanger::random());
+        let (exit_tx, handle) = setup_server(server).await;
+
+        for i in 1..10usize {
+            let (pkey, peer_addr) = new_peer();
+            let mut connection =
+                add_peer(server, peer_addr, pkey, &connector).await;
+            let req = Request::Wait(i);
+
+            connection.send_plain(&req).await.expect("send failed");
+            let mut peers = Vec::new();
+
+            while let Ok(peer) = connection.receive_plain::<Info>().await {
+                peers.push(peer);
+            }
+
+            assert_eq!(i, peers.len(), "incorrect number of peers");
+        }
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn single_wait() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server(server).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let (pkey, peer) = new_peer();
+        let mut w_connection = add_peer(server, peer, pkey, &connector).await;
+
+        let waiter = task::spawn(
+            async move {
+                w_connection
+                    .send_plain(&Request::Wait(TOTAL))
+                    .await
+                    .expect("wait failed");
+
+                for _ in 0..TOTAL {
+                    w_connection
+                        .receive_plain::<Info>()
+                        .await
+                        .expect("recv failed");
+                }
+            }
+            .instrument(trace_span!("waiter")),
+        );
+
+        for _ in 0..TOTAL {
+            let (pkey, peer) = new_peer();
+
+            add_peer(server, peer, pkey, &connector).await;
+        }
+
+        wait_for_server(exit_tx, handle).await;
+
+        waiter.await.expect("waiter failed");
+    }
+
+    #[tokio::test]
+    async fn multi_wait() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server(server).await;
+        const TOTAL: usize = 10;
+
+        let handles = (0..TOTAL)
+            .map(|_| {
+                task::spawn(async move {
+                    let exc = Exchanger::random();
+                    let public = *exc.keypair().public();
+                    let connector = TcpConnector::new(exc);
+
+                    let mut connection = Connection::new(
+                        connector
+                            .establish(&public, &server)
+                            .await
+                            .expect("connect failed"),
+                    );
+
+                    connection
+                        .send_plain(&Request::Wait(TOTAL))
+                        .await
+                        .expect("send failed");
+
+                    let mut peers = Vec::new();
+                    while let Ok(peer) =
+                        connection.receive_plain::<Info>().await
+                    {
+                        peers.push(peer);
+                    }
+
+                    assert_eq!(TOTAL, peers.len(), "wrong number of peers");
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let exch = Exchanger::random();
+        let public = *exch.keypair().public();
+
+        let mut connection = Connection::new(
+            TcpConnector::new(exch)
+                .establish(&public, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        for _ in 0..TOTAL {
+            let dir_peer = new_peer().into();
+            connection
+                .send_plain(&Request::Add(dir_peer))
+                .await
+                .expect("add failed");
+
+            let resp = connection
+                .receive_plain::<Response>()
+                .await
+                .expect("add failed");
+
+            assert_eq!(resp, Response::Ok, "bad response");
+        }
+
+        for handle in handles {
+            handle.await.expect("client failure");
+        }
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn add_then_fetch() {
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server(server).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let peer_addr = next_test_ip4();
+        let peer_pkey = *Exchanger::random().keypair().public();
+        let mut connection =
+            add_peer(server, peer_addr, peer_pkey, &connector).await;
+
+        connection
+            .send_plain(&Request::Fetch(peer_pkey))
+            .await
+            .expect("fetch failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        assert_eq!(
+            resp,
+            Response::FoundOne(peer_pkey, peer_addr),
+            "wrong directory entry"
+        );
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn empty_fetch() {
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server(server).await;
+
+        let connector = TcpConnector::new(Exchanger::random());
+        let public = *connector.exchanger().keypair().public();
+        let mut connection = Connection::new(
+            connector
+                .establish(&public, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        connection
+            .send_plain(&Request::Fetch(public))
+            .await
+            .expect("send failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        assert_eq!(resp, Response::NotFound(public), "wrong response");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn signed_add_then_fetch() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server_with(server, false).await;
+
+        let keypair = KeyPair::random();
+        let record = signed(&keypair, next_test_ip4());
+        let (mut connection, resp) =
+            request(server, &Request::AddSigned(record)).await;
+
+        assert_eq!(resp, Response::Ok, "signed add rejected");
+
+        connection
+            .send_plain(&Request::Fetch(*keypair.public()))
+            .await
+            .expect("fetch failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        match resp {
+            Response::FoundSigned(info) => {
+                assert_eq!(info, record, "wrong directory entry");
+                info.verify().expect("fetched record has invalid signature");
+            }
+            other => panic!("unexpected response {}", other),
+        }
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn forged_add_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server_with(server, false).await;
+
+        let victim = *KeyPair::random().public();
+        let attacker = KeyPair::random();
+        let mut forged = bincode_options()
+            .serialize(&signed(&attacker, server))
+            .expect("serialize failed");
+        forged[..32].copy_from_slice(victim.as_ref());
+        let forged: SignedInfo = bincode_options()
+            .deserialize(&forged)
+            .expect("deserialize failed");
+
+        assert_eq!(forged.public(), &victim, "forgery failed");
+
+        let (mut connection, resp) =
+            request(server, &Request::AddSigned(forged)).await;
+
+        assert!(matches!(resp, Response::Error(_)), "forged add accepted");
+
+        connection
+            .send_plain(&Request::Fetch(victim))
+            .await
+            .expect("fetch failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        assert_eq!(resp, Response::NotFound(victim), "forged entry stored");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn expired_add_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server_with(server, false).await;
+
+        let keypair = KeyPair::random();
+        let stale = SignedInfo::new(&keypair, next_test_ip4(), UNIX_EPOCH)
+            .expect("sign failed");
+        let (_, resp) = request(server, &Request::AddSigned(stale)).await;
+
+        assert!(matches!(resp, Response::Error(_)), "expired add accepted");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn unsigned_add_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server_with(server, false).await;
+
+        let (pkey, addr) = new_peer();
+        let (_, resp) =
+            request(server, &Request::Add((pkey, addr).into())).await;
+
+        assert!(matches!(resp, Response::Error(_)), "unsigned add accepted");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn unauthorized_add_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let banned = KeyPair::random();
+        let banned_key = *banned.public();
+        let listener = TcpListener::new(server, Exchanger::random())
+            .await
+            .expect("listen failed")
+            .with_authorizer(move |identity: &ConnectionIdentity, _| {
+                if *identity.public() == banned_key {
+                    Decision::reject("banned")
+                } else {
+                    Decision::Accept
+                }
+            });
+        let (dir_server, exit_tx) = DirectoryServer::new(Box::new(listener));
+        let handle = task::spawn(async move {
+            dir_server.serve().await.expect("serve failed")
+        });
+
+        let (mut connection, resp) = request(
+            server,
+            &Request::AddSigned(signed(&banned, next_test_ip4())),
+        )
+        .await;
+
+        assert_eq!(resp, Response::Error("banned".into()), "banned peer added");
+
+        connection
+            .send_plain(&Request::Fetch(banned_key))
+            .await
+            .expect("fetch failed");
+
+        let resp = connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed");
+
+        assert_eq!(resp, Response::NotFound(banned_key), "banned entry stored");
+
+        let record = signed(&KeyPair::random(), next_test_ip4());
+        let (_, resp) = request(server, &Request::AddSigned(record)).await;
+
+        assert_eq!(resp, Response::Ok, "authorized peer rejected");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn conflict_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle, conflicts) =
+            setup_policy(server, ConflictPolicy::Reject).await;
+
+        let keypair = KeyPair::random();
+        let first = next_test_ip4();
+        let (mut owner, resp) =
+            negotiated(server, &Request::AddSigned(signed(&keypair, first)))
+                .await;
+
+        assert_eq!(resp, Response::Ok, "first registration rejected");
+
+        let second = signed(&keypair, next_test_ip4());
+        let (_, resp) = negotiated(server, &Request::AddSigned(second)).await;
+
+        assert_eq!(resp, Response::Conflict(first), "conflict not reported");
+
+        // clients that do not know about conflicts get a plain error
+        let (_, resp) = request(server, &Request::AddSigned(second)).await;
+
+        assert!(matches!(resp, Response::Error(_)), "conflict accepted");
+
+        match fetch(&mut owner, *keypair.public()).await {
+            Response::FoundSigned(info) => {
+                assert_eq!(info.addr(), first, "existing entry replaced")
+            }
+            other => panic!("unexpected response {}", other),
+        }
+
+        // the owner can still move its own entry
+        let moved = signed(&keypair, next_test_ip4());
+
+        owner
+            .send_plain(&Request::AddSigned(moved))
+            .await
+            .expect("send failed");
+
+        let resp = owner.receive_plain::<Response>().await.expect("no resp");
+
+        assert_eq!(resp, Response::Ok, "owner could not move its entry");
+        assert_eq!(conflicts.count(), 2, "wrong number of conflicts");
+        assert_eq!(
+            conflicts.recent(),
+            vec![
+                Conflict {
+                    pkey: *keypair.public(),
+                    existing: first,
+                    contender: second.addr(),
+                    replaced: false,
+                };
+                2
+            ],
+            "wrong conflicts recorded"
+        );
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn conflict_prefers_source() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle, conflicts) =
+            setup_policy(server, ConflictPolicy::default()).await;
+
+        let keypair = KeyPair::random();
+        // registered from the loopback interface for another host
+        let elsewhere = (Ipv4Addr::new(10, 0, 0, 1), 9600).into();
+        let local = next_test_ip4();
+        let (mut owner, resp) = negotiated(
+            server,
+            &Request::AddSigned(signed(&keypair, elsewhere)),
+        )
+        .await;
+
+        assert_eq!(resp, Response::Ok, "first registration rejected");
+
+        let (_, resp) =
+            negotiated(server, &Request::AddSigned(signed(&keypair, local)))
+                .await;
+
+        assert_eq!(resp, Response::Ok, "registration from source rejected");
+
+        match fetch(&mut owner, *keypair.public()).await {
+            Response::FoundSigned(info) => {
+                assert_eq!(info.addr(), local, "entry not replaced")
+            }
+            other => panic!("unexpected response {}", other),
+        }
+
+        // the replaced owner no longer owns the entry
+        owner
+            .send_plain(&Request::Remove(*keypair.public()))
+            .await
+            .expect("send failed");
+
+        let resp = owner.receive_plain::<Response>().await.expect("no resp");
+
+        assert!(matches!(resp, Response::Error(_)), "replaced entry removed");
+
+        // both are now on their source host, the existing one stays
+        let (_, resp) = negotiated(
+            server,
+            &Request::AddSigned(signed(&keypair, next_test_ip4())),
+        )
+        .await;
+
+        assert_eq!(resp, Response::Conflict(local), "entry replaced");
+
+        let replaced = conflicts
+            .recent()
+            .iter()
+            .map(|c| c.replaced)
+            .collect::<Vec<_>>();
+
+        assert_eq!(replaced, vec![true, false], "wrong conflicts recorded");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn conflict_prefers_newest() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle, conflicts) =
+            setup_policy(server, ConflictPolicy::PreferNewest).await;
+
+        let keypair = KeyPair::random();
+        let first = expiring(&keypair, next_test_ip4(), 60);
+        let (mut owner, resp) =
+            negotiated(server, &Request::AddSigned(first)).await;
+
+        assert_eq!(resp, Response::Ok, "first registration rejected");
+
+        let older = expiring(&keypair, next_test_ip4(), 30);
+        let (_, resp) = negotiated(server, &Request::AddSigned(older)).await;
+
+        assert_eq!(resp, Response::Conflict(first.addr()), "older accepted");
+
+        let newer = expiring(&keypair, next_test_ip4(), 120);
+        let (_, resp) = negotiated(server, &Request::AddSigned(newer)).await;
+
+        assert_eq!(resp, Response::Ok, "newer registration rejected");
+        assert_eq!(
+            fetch(&mut owner, *keypair.public()).await,
+            Response::FoundSigned(newer),
+            "entry not replaced"
+        );
+        assert_eq!(conflicts.count(), 2, "wrong number of conflicts");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    /// Start a server whose directory already holds `count` peers
+    async fn setup_populated(
+        server: SocketAddr,
+        count: usize,
+    ) -> (Sender<()>, JoinHandle<()>, Vec<PublicKey>) {
+        let listener = TcpListener::new(server, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (dir_server, exit_tx) = DirectoryServer::new(Box::new(listener));
+        let dir_server = dir_server.allow_unsigned(true);
+        let mut keys = Vec::with_capacity(count);
+
+        {
+            let mut peers = dir_server.peers.write().await;
+
+            for _ in 0..count {
+                let (pkey, addr) = new_peer();
+                let record = Record {
+                    addrs: vec![addr],
+                    signed: None,
+                    owner: 0,
+                    from_source: false,
+                    added: Instant::now(),
+                };
+
+                peers.insert(pkey, record);
+                keys.push(pkey);
+            }
+        }
+
+        keys.sort();
+
+        let handle = task::spawn(async move {
+            dir_server.serve().await.expect("serve failed")
+        });
+
+        (exit_tx, handle, keys)
+    }
+
+    /// Read `Response::Page`s until `Response::Ok`, stopping early after
+    /// `limit` pages
+    async fn read_pages(
+        connection: &mut Connection,
+        first: Response,
+        limit: usize,
+    ) -> Vec<PublicKey> {
+        let mut keys = Vec::new();
+        let mut response = first;
+
+        for _ in 0..limit {
+            match response {
+                Response::Page(page) => {
+                    keys.extend(page.iter().map(|info| *info.public()))
+                }
+                Response::Ok => break,
+                other => panic!("unexpected response {}", other),
+            }
+
+            response = connection
+                .receive_plain::<Response>()
+                .await
+                .expect("recv failed");
+        }
+
+        keys
+    }
+
+    #[tokio::test]
+    async fn slow_wait_does_not_block_add() {
+        const PEERS: usize = 10_000;
+
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle, keys) = setup_populated(server, PEERS).await;
+        let connector = TcpConnector::new(Exchanger::random());
+        let public = *connector.exchanger().keypair().public();
+        let mut connection = Connection::new(
+            connector
+                .establish(&public, &server)
+                .await
+                .expect("connect failed"),
+        );
+
+        connection
+            .send_plain(&Request::Wait(PEERS))
+            .await
+            .expect("wait failed");
+
+        let waiter = task::spawn(async move {
+            let mut listed = Vec::new();
+
+            while let Ok(info) = connection.receive_plain::<Info>().await {
+                listed.push(*info.public());
+
+                if listed.len() % 100 == 0 {
+                    time::sleep(Duration::from_millis(5)).await;
+                }
+            }
+
+            listed
+        });
+
+        for _ in 0..10 {
+            let (pkey, addr) = new_peer();
+            let start = time::Instant::now();
+
+            add_peer(server, addr, pkey, &connector).await;
+
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "add blocked by listing for {:?}",
+                start.elapsed()
+            );
+            time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert!(!waiter.is_finished(), "listing finished too early");
+
+        let mut listed = waiter.await.expect("waiter failed");
+
+        listed.sort();
+        listed.dedup();
+
+        assert!(listed.len() >= PEERS, "listing is incomplete");
+        assert!(
+            keys.iter().all(|key| listed.binary_search(key).is_ok()),
+            "peer missing from listing"
+        );
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    #[tokio::test]
+    async fn paged_wait_resumes() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle, keys) = setup_populated(server, 1000).await;
+
+        let request = Request::WaitPaged {
+            count: 1000,
+            page_size: 100,
+            after: None,
+        };
+        let (mut connection, response) = negotiated(server, &request).await;
+        let mut listed = read_pages(&mut connection, response, 3).await;
+
+        assert_eq!(listed.len(), 300, "wrong page size");
+
+        drop(connection);
+
+        let request = Request::WaitPaged {
+            count: 1000,
+            page_size: 100,
+            after: listed.last().copied(),
+        };
+        let (mut connection, response) = negotiated(server, &request).await;
+
+        listed.extend(read_pages(&mut connection, response, usize::MAX).await);
+
+        assert_eq!(listed, keys, "resumed listing differs");
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    async fn ask(connection: &mut Connection, request: &Request) -> Response {
+        connection.send_plain(request).await.expect("send failed");
+
+        connection
+            .receive_plain::<Response>()
+            .await
+            .expect("recv failed")
+    }
+
+    #[tokio::test]
+    async fn connect_back_is_restricted() {
+        init_logger();
+        let server = next_test_ip4();
+        let (exit_tx, handle) = setup_server(server).await;
+        let target = KeyPair::random();
+        let foreign: SocketAddr = (Ipv4Addr::new(10, 0, 0, 1), 1).into();
+        let local: SocketAddr = (Ipv4Addr::LOCALHOST, 1).into();
+
+        let request = Request::Subscribe(signed(&target, foreign));
+        let (_, response) = negotiated(server, &request).await;
+
+        assert!(
+            matches!(response, Response::Error(_)),
+            "subscribed for another host"
+        );
+
+        let request = Request::Subscribe(signed(&target, local));
+        let (mut subscriber, response) = negotiated(server, &request).await;
+
+        assert_eq!(response, Response::Ok, "subscription refused");
+
+        let requester = *KeyPair::random().public();
+        let connect_back = |target, addrs| Request::ConnectBack {
+            target,
+            requester,
+            addrs,
+        };
+        let request = connect_back(*target.public(), vec![foreign]);
+        let (mut connection, response) = negotiated(server, &request).await;
+
+        assert!(
+            matches!(response, Response::Error(_)),
+            "relayed a request to dial another host"
+        );
+
+        let request = connect_back(*target.public(), vec![local]);
+
+        assert_eq!(ask(&mut connection, &request).await, Response::Ok);
+        assert_eq!(
+            subscriber
+                .receive_plain::<Response>()
+                .await
+                .expect("recv failed"),
+            Response::ConnectBack(requester, vec![local])
+        );
+
+        let other = *KeyPair::random().public();
+        let request = connect_back(other, vec![local]);
+
+        assert_eq!(
+            ask(&mut connection, &request).await,
+            Response::NotFound(other),
+            "relayed to a peer that did not subscribe"
+        );
+
+        let request = connect_back(*target.public(), vec![local]);
+
+        for _ in 2..CONNECT_BACK_BURST {
+            assert_eq!(ask(&mut connection, &request).await, Response::Ok);
+        }
+
+        assert!(
+            matches!(ask(&mut connection, &request).await, Response::Error(_)),
+            "connect back not rate limited"
+        );
+
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    /// Request handling of directory servers that predate `Hello`, frozen so
+    /// that current clients can be checked against it
+    mod legacy {
+        use super::*;
+        use std::collections::HashMap;
+
+        type Peers = Arc<RwLock<HashMap<PublicKey, Response>>>;
+
+        pub async fn serve(mut listener: TcpListener) {
+            let peers = Peers::default();
+
+            while let Ok(socket) = listener.establish().await {
+                task::spawn(service(Connection::new(socket), peers.clone()));
+            }
+        }
+
+        async fn service(mut connection: Connection, peers: Peers) {
+            while let Ok(request) = connection.receive_plain::<Request>().await
+            {
+                let response = match request {
+                    Request::Add(info) => {
+                        let found =
+                            Response::FoundOne(*info.public(), info.addr());
+
+                        peers.write().await.insert(*info.public(), found);
+                        Response::Ok
+                    }
+                    Request::AddSigned(info) => {
+                        let found = Response::FoundSigned(info);
+
+                        peers.write().await.insert(*info.public(), found);
+                        Response::Ok
+                    }
+                    Request::Fetch(pkey) => peers
+                        .read()
+                        .await
+                        .get(&pkey)
+                        .cloned()
+                        .unwrap_or(Response::NotFound(pkey)),
+                    Request::Remove(pkey) => {
+                        match peers.write().await.remove(&pkey) {
+                            Some(_) => Response::Ok,
+                            None => Response::NotFound(pkey),
+                        }
+                    }
+                    Request::Wait(_)
+                    | Request::ProbeMe(_)
+                    | Request::WaitPaged { .. }
+                    | Request::Subscribe(_)
+                    | Request::ConnectBack { .. }
+                    | Request::Renew(_)
+                    | Request::AddCard(_) => {
+                        Response::Error("unsupported".into())
+                    }
+                };
+
+                if connection.send_plain(&response).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        /// Register a peer and fetch it back without saying hello
+        pub async fn client(server: SocketAddr) {
+            let (pkey, addr) = new_peer();
+            let (mut connection, resp) =
+                request(server, &Request::Add((pkey, addr).into())).await;
+
+            assert_eq!(resp, Response::Ok, "legacy add rejected");
+
+            connection
+                .send_plain(&Request::Fetch(pkey))
+                .await
+                .expect("send failed");
+
+            let resp = connection
+                .receive_plain::<Response>()
                 .await
-                .expect("connect failed"),
-        );
+                .expect("recv failed");
 
-        connection.send_plain(&req).await.expect("send failed");
+            assert_eq!(resp, Response::FoundOne(pkey, addr), "wrong entry");
+        }
+    }
 
-        let resp = connection
-            .receive_plain::<Response>()
+    /// Start a current or a legacy directory server, which runs until the
+    /// returned task is aborted
+    async fn compat_server(legacy: bool) -> (Info, JoinHandle<()>) {
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let info = (*exchanger.keypair().public(), addr).into();
+        let listener = TcpListener::new(addr, exchanger)
             .await
-            .expect("recv failed");
+            .expect("listen failed");
 
-        assert_eq!(resp, Response::Ok, "invalid response");
+        let handle = if legacy {
+            task::spawn(legacy::serve(listener))
+        } else {
+            let (server, exit) = DirectoryServer::new(Box::new(listener));
+            let server = server.allow_unsigned(true);
 
-        connection
+            task::spawn(async move {
+                let _exit = exit;
+
+                server.serve().await.expect("serve failed");
+            })
+        };
+
+        (info, handle)
     }
 
-    async fn wait_for_server(exit_tx: Sender<()>, handle: JoinHandle<()>) {
-        exit_tx.send(()).expect("exit_failed");
-        handle.await.expect("server failed");
+    /// Register a peer using a `DirectoryListener` and reach it through a
+    /// `DirectoryConnector`, returning the negotiated protocol version
+    async fn current_client(directory: Info) -> Option<u16> {
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let listener = TcpListener::new(next_test_ip4(), exchanger.clone())
+            .await
+            .expect("listen failed");
+        let mut listener = DirectoryListener::new(
+            listener,
+            TcpConnector::new(exchanger),
+            directory.addr(),
+        )
+        .await
+        .expect("register failed");
+        let accepted = task::spawn(async move {
+            listener.accept().await.expect("accept failed");
+            listener.close().await;
+        });
+        let connector =
+            DirectoryConnector::new(TcpConnector::new(Exchanger::random()));
+
+        // registration happens in the background
+        time::timeout(Duration::from_secs(5), async {
+            while connector.connect(&pkey, &directory).await.is_err() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("peer not reachable through directory");
+
+        accepted.await.expect("listener failed");
+
+        connector.protocol_version(&directory).await
     }
 
     #[tokio::test]
-    async fn serve_many() {
+    async fn compatibility_matrix() {
         init_logger();
-        let server = next_test_ip4();
-        let connector = TcpConnector::new(Exchanger::random());
-        let (exit_tx, handle) = setup_server(server).await;
 
-        for i in 1..10usize {
-            let (pkey, peer_addr) = new_peer();
-            let mut connection =
-                add_peer(server, peer_addr, pkey, &connector).await;
-            let req = Request::Wait(i);
+        for legacy_server in [false, true] {
+            for legacy_client in [false, true] {
+                let (directory, server) = compat_server(legacy_server).await;
 
-            connection.send_plain(&req).await.expect("send failed");
-            let mut peers = Vec::new();
+                if legacy_client {
+                    legacy::client(directory.addr()).await;
+                } else {
+                    let expected = if legacy_server {
+                        LEGACY_VERSION
+                    } else {
+                        PROTOCOL_VERSION
+                    };
+
+                    assert_eq!(
+                        current_client(directory).await,
+                        Some(expected),
+                        "wrong version negotiated"
+                    );
+                }
 
-            while let Ok(peer) = connection.receive_plain::<Info>().await {
-                peers.push(peer);
+                server.abort();
             }
-
-            assert_eq!(i, peers.len(), "incorrect number of peers");
         }
-
-        wait_for_server(exit_tx, handle).await;
     }
 
     #[tokio::test]
-    async fn single_wait() {
+    async fn multiple_addresses() {
         init_logger();
         let server = next_test_ip4();
         let (exit_tx, handle) = setup_server(server).await;
-        let connector = TcpConnector::new(Exchanger::random());
+        let keypair = KeyPair::random();
+        let pkey = *keypair.public();
+        let (lan, public) = (next_test_ip4(), next_test_ip4());
+        let both = card(&keypair, vec![public, lan, public]);
 
-        let (pkey, peer) = new_peer();
-        let mut w_connection = add_peer(server, peer, pkey, &connector).await;
+        let (mut connection, resp) =
+            negotiated(server, &Request::AddCard(both.clone())).await;
 
-        let waiter = task::spawn(
-            async move {
-                w_connection
-                    .send_plain(&Request::Wait(TOTAL))
-                    .await
-                    .expect("wait failed");
+        assert_eq!(resp, Response::Ok, "card rejected");
+        assert_eq!(
+            fetch(&mut connection, pkey).await,
+            Response::FoundCard(both),
+            "card not returned"
+        );
 
-                for _ in 0..TOTAL {
-                    w_connection
-                        .receive_plain::<Info>()
-                        .await
-                        .expect("recv failed");
-                }
-            }
-            .instrument(trace_span!("waiter")),
+        let (_, resp) = request(server, &Request::Fetch(pkey)).await;
+
+        assert_eq!(
+            resp,
+            Response::FoundOne(pkey, public),
+            "legacy client not given the first address"
         );
 
-        for _ in 0..TOTAL {
-            let (pkey, peer) = new_peer();
+        let moved = card(&keypair, vec![lan]);
+        let (mut other, resp) =
+            negotiated(server, &Request::AddCard(moved.clone())).await;
 
-            add_peer(server, peer, pkey, &connector).await;
-        }
+        assert_eq!(resp, Response::Ok, "known address rejected");
+        assert_eq!(
+            fetch(&mut other, pkey).await,
+            Response::FoundCard(moved),
+            "stale address kept"
+        );
 
-        wait_for_server(exit_tx, handle).await;
+        let (mut waiting, _) = request(server, &Request::Fetch(pkey)).await;
 
-        waiter.await.expect("waiter failed");
+        waiting
+            .send_plain(&Request::Wait(2))
+            .await
+            .expect("send failed");
+
+        time::timeout(
+            Duration::from_millis(200),
+            waiting.receive_plain_frame(),
+        )
+        .await
+        .expect_err("peer registered twice counted twice");
+
+        let (other, addr) = new_peer();
+        let (mut unsigned, resp) =
+            negotiated(server, &Request::Add((other, addr).into())).await;
+
+        assert_eq!(resp, Response::Ok, "second peer rejected");
+
+        time::timeout(Duration::from_secs(5), waiting.receive_plain_frame())
+            .await
+            .expect("wait not completed")
+            .expect("recv failed");
+
+        let renumbered = next_test_ip4();
+        let request = Request::Add((other, renumbered).into());
+
+        assert_eq!(ask(&mut unsigned, &request).await, Response::Ok);
+        assert_eq!(
+            fetch(&mut unsigned, other).await,
+            Response::Found(other, vec![renumbered]),
+            "address not replaced"
+        );
+
+        wait_for_server(exit_tx, handle).await;
     }
 
     #[tokio::test]
-    async fn multi_wait() {
+    async fn invalid_card_rejected() {
         init_logger();
         let server = next_test_ip4();
         let (exit_tx, handle) = setup_server(server).await;
-        const TOTAL: usize = 10;
-
-        let handles = (0..TOTAL)
-            .map(|_| {
-                task::spawn(async move {
-                    let exc = Exchanger::random();
-                    let public = *exc.keypair().public();
-                    let connector = TcpConnector::new(exc);
+        let keypair = KeyPair::random();
+        let addr = next_test_ip4();
+        let eternal = ContactCard::new(*keypair.public(), vec![addr])
+            .sign(&keypair)
+            .expect("sign failed");
+        let unsigned = ContactCard::new(*keypair.public(), vec![addr])
+            .with_expiry(SystemTime::now() + Duration::from_secs(60));
+        let crowded = card(
+            &keypair,
+            (0..=MAX_CARD_ADDRS).map(|_| next_test_ip4()).collect(),
+        );
 
-                    let mut connection = Connection::new(
-                        connector
-                            .establish(&public, &server)
-                            .await
-                            .expect("connect failed"),
-                    );
+        for rejected in [eternal, unsigned, crowded] {
+            let (_, resp) =
+                negotiated(server, &Request::AddCard(rejected.clone())).await;
 
-                    connection
-                        .send_plain(&Request::Wait(TOTAL))
-                        .await
-                        .expect("send failed");
+            assert!(
+                matches!(resp, Response::Error(_)),
+                "{} accepted",
+                rejected
+            );
+        }
 
-                    let mut peers = Vec::new();
-                    while let Ok(peer) =
-                        connection.receive_plain::<Info>().await
-                    {
-                        peers.push(peer);
-                    }
+        let (_, resp) =
+            request(server, &Request::Fetch(*keypair.public())).await;
 
-                    assert_eq!(TOTAL, peers.len(), "wrong number of peers");
-                })
-            })
-            .collect::<Vec<_>>();
+        assert_eq!(resp, Response::NotFound(*keypair.public()));
 
-        let exch = Exchanger::random();
-        let public = *exch.keypair().public();
+        wait_for_server(exit_tx, handle).await;
+    }
 
-        let mut connection = Connection::new(
-            TcpConnector::new(exch)
-                .establish(&public, &server)
+    #[tokio::test]
+    async fn legacy_client_rejected() {
+        init_logger();
+        let server = next_test_ip4();
+        let listener = TcpListener::new(server, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (dir_server, exit_tx) = DirectoryServer::new(Box::new(listener));
+        let handle = task::spawn(async move {
+            dir_server
+                .allow_legacy(false)
+                .serve()
                 .await
-                .expect("connect failed"),
-        );
+                .expect("serve failed")
+        });
 
-        for _ in 0..TOTAL {
-            let dir_peer = new_peer().into();
-            connection
-                .send_plain(&Request::Add(dir_peer))
-                .await
-                .expect("add failed");
+        let (pkey, _) = new_peer();
+        let (_, resp) = request(server, &Request::Fetch(pkey)).await;
 
-            let resp = connection
-                .receive_plain::<Response>()
-                .await
-                .expect("add failed");
+        assert!(matches!(resp, Response::Error(_)), "legacy client served");
 
-            assert_eq!(resp, Response::Ok, "bad response");
+        wait_for_server(exit_tx, handle).await;
+    }
+
+    /// A server accepting unsigned registrations whose peers expire after
+    /// a ttl, reached in memory so that tests can run with paused time
+    struct TtlServer {
+        addr: SocketAddr,
+        connector: MemoryConnector,
+        peers: PeerDirectory,
+        exit_tx: Sender<()>,
+        handle: JoinHandle<()>,
+    }
+
+    impl TtlServer {
+        fn new(ttl: Duration) -> Self {
+            let registry = MemoryRegistry::new();
+            let listener = MemoryListener::new(
+                &registry,
+                next_test_ip4(),
+                Exchanger::random(),
+            )
+            .expect("listen failed");
+            let addr = listener.local_addr().expect("no address");
+            let (dir_server, exit_tx) =
+                DirectoryServer::new_with_ttl(Box::new(listener), ttl);
+            let dir_server = dir_server.allow_unsigned(true);
+            let peers = dir_server.peers.clone();
+            let handle = task::spawn(async move {
+                dir_server.serve().await.expect("serve failed")
+            });
+            let connector = MemoryConnector::new(registry, Exchanger::random());
+
+            Self {
+                addr,
+                connector,
+                peers,
+                exit_tx,
+                handle,
+            }
         }
 
-        for handle in handles {
-            handle.await.expect("client failure");
+        async fn add_peer(
+            &self,
+            addr: SocketAddr,
+            pkey: PublicKey,
+        ) -> Connection {
+            add_peer(self.addr, addr, pkey, &self.connector).await
+        }
+
+        async fn request(&self, request: &Request) -> (Connection, Response) {
+            request_with(&self.connector, self.addr, request).await
+        }
+
+        async fn negotiated(
+            &self,
+            request: &Request,
+        ) -> (Connection, Response) {
+            negotiated_with(&self.connector, self.addr, request).await
+        }
+
+        async fn stop(self) {
+            wait_for_server(self.exit_tx, self.handle).await;
         }
-        wait_for_server(exit_tx, handle).await;
     }
 
     #[tokio::test]
-    async fn add_then_fetch() {
-        let server = next_test_ip4();
-        let (exit_tx, handle) = setup_server(server).await;
-        let connector = TcpConnector::new(Exchanger::random());
+    async fn tiny_ttl_clamped() {
+        let listener = TcpListener::new(next_test_ip4(), Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (dir_server, _) =
+            DirectoryServer::new_with_ttl(Box::new(listener), Duration::ZERO);
 
-        let peer_addr = next_test_ip4();
-        let peer_pkey = *Exchanger::random().keypair().public();
-        let mut connection =
-            add_peer(server, peer_addr, peer_pkey, &connector).await;
+        assert_eq!(dir_server.ttl, Some(MIN_TTL), "ttl not clamped");
+    }
 
-        connection
-            .send_plain(&Request::Fetch(peer_pkey))
-            .await
-            .expect("fetch failed");
+    #[tokio::test(start_paused = true)]
+    async fn expired_peers_evicted() {
+        init_logger();
+        let ttl = Duration::from_secs(10);
+        let server = TtlServer::new(ttl);
+        let (pkey, addr) = new_peer();
 
-        let resp = connection
-            .receive_plain::<Response>()
-            .await
-            .expect("recv failed");
+        let _registered = server.add_peer(addr, pkey).await;
+        let (mut connection, resp) =
+            server.request(&Request::Fetch(pkey)).await;
+
+        assert_eq!(resp, Response::FoundOne(pkey, addr), "peer not found");
+
+        time::sleep(ttl).await;
 
         assert_eq!(
-            resp,
-            Response::Found(peer_pkey, peer_addr),
-            "wrong directory entry"
+            fetch(&mut connection, pkey).await,
+            Response::NotFound(pkey),
+            "expired peer found"
         );
 
-        wait_for_server(exit_tx, handle).await;
+        time::sleep(ttl).await;
+
+        assert!(
+            server.peers.read().await.is_empty(),
+            "expired peer not evicted"
+        );
+
+        server.stop().await;
     }
 
-    #[tokio::test]
-    async fn empty_fetch() {
-        let server = next_test_ip4();
-        let (exit_tx, handle) = setup_server(server).await;
+    #[tokio::test(start_paused = true)]
+    async fn renewed_peers_kept() {
+        init_logger();
+        let ttl = Duration::from_secs(10);
+        let server = TtlServer::new(ttl);
+        let (pkey, addr) = new_peer();
+        let mut registered = server.add_peer(addr, pkey).await;
 
-        let connector = TcpConnector::new(Exchanger::random());
-        let public = *connector.exchanger().keypair().public();
-        let mut connection = Connection::new(
-            connector
-                .establish(&public, &server)
+        for _ in 0..6 {
+            time::sleep(ttl / 4).await;
+
+            registered
+                .send_plain(&Request::Add((pkey, addr).into()))
                 .await
-                .expect("connect failed"),
+                .expect("send failed");
+
+            assert_eq!(
+                registered.receive_plain::<Response>().await.unwrap(),
+                Response::Ok,
+                "renewal failed"
+            );
+        }
+
+        assert_eq!(
+            fetch(&mut registered, pkey).await,
+            Response::FoundOne(pkey, addr),
+            "renewed peer expired"
+        );
+        assert_eq!(server.peers.read().await.len(), 1, "renewed peer evicted");
+
+        server.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn renew_extends_ttl() {
+        init_logger();
+        let ttl = Duration::from_secs(10);
+        let server = TtlServer::new(ttl);
+        let (pkey, addr) = new_peer();
+        let (mut registered, resp) =
+            server.negotiated(&Request::Add((pkey, addr).into())).await;
+
+        assert_eq!(resp, Response::Ok, "registration failed");
+
+        for _ in 0..6 {
+            time::sleep(ttl / 4).await;
+
+            assert_eq!(
+                ask(&mut registered, &Request::Renew(pkey)).await,
+                Response::Ok,
+                "renewal failed"
+            );
+        }
+
+        assert_eq!(
+            fetch(&mut registered, pkey).await,
+            Response::Found(pkey, vec![addr]),
+            "renewed peer expired"
+        );
+
+        let (_, resp) = server.negotiated(&Request::Renew(pkey)).await;
+
+        assert!(matches!(resp, Response::Error(_)), "foreign renewal");
+
+        time::sleep(ttl).await;
+
+        assert_eq!(
+            ask(&mut registered, &Request::Renew(pkey)).await,
+            Response::NotFound(pkey),
+            "expired peer renewed"
         );
 
+        server.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiry_announced_to_waiters() {
+        init_logger();
+        let ttl = Duration::from_secs(10);
+        let server = TtlServer::new(ttl);
+        let (pkey, addr) = new_peer();
+        let (_registered, resp) =
+            server.negotiated(&Request::Add((pkey, addr).into())).await;
+
+        assert_eq!(resp, Response::Ok, "registration failed");
+
+        let request = Request::WaitPaged {
+            count: 2,
+            page_size: 8,
+            after: None,
+        };
+        let (_, resp) = time::timeout(ttl * 4, server.negotiated(&request))
+            .await
+            .expect("expiry not announced");
+
+        assert_eq!(resp, Response::Expired(pkey), "wrong announcement");
+
+        server.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_peers_not_listed() {
+        init_logger();
+        let ttl = Duration::from_secs(10);
+        let server = TtlServer::new(ttl);
+        let (expired, addr) = new_peer();
+
+        server.add_peer(addr, expired).await;
+        time::sleep(ttl).await;
+
+        let (pkey, addr) = new_peer();
+        let mut connection = server.add_peer(addr, pkey).await;
+
         connection
-            .send_plain(&Request::Fetch(public))
+            .send_plain(&Request::Wait(1))
             .await
             .expect("send failed");
 
-        let resp = connection
-            .receive_plain::<Response>()
-            .await
-            .expect("recv failed");
+        let mut listed = Vec::new();
 
-        assert_eq!(resp, Response::NotFound(public), "wrong response");
+        while let Ok(peer) = connection.receive_plain::<Info>().await {
+            listed.push(peer);
+        }
 
-        wait_for_server(exit_tx, handle).await;
+        assert_eq!(listed, vec![(pkey, addr).into()], "expired peer listed");
+
+        server.stop().await;
     }
 }
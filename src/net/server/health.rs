@@ -0,0 +1,427 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ServerError, ServerIo};
+use crate::telemetry::targets;
+
+use snafu::ResultExt;
+
+use futures::future::{self, Either};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tokio::sync::Semaphore;
+use tokio::task;
+use tokio::time;
+
+use tracing::{debug, info, warn};
+
+/// Maximum size of an HTTP request accepted by the `HealthServer`
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Maximum lifetime of a connection to the `HealthServer`
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default maximum number of connections served at once by a `HealthServer`
+const MAX_CONNECTIONS: usize = 32;
+
+/// A source of health information for a `HealthServer`
+pub trait HealthSource: Send + Sync {
+    /// Check if the node is alive, used to answer `GET /healthz`
+    fn healthy(&self) -> bool;
+
+    /// Check if the node is ready to serve requests, used to answer
+    /// `GET /readyz`
+    fn ready(&self) -> bool;
+}
+
+type Probe = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// A minimal unauthenticated HTTP server answering `GET /healthz` and
+/// `GET /readyz` for load balancers and orchestrators. Both endpoints answer
+/// `200 OK` when all their probes succeed and `503 Service Unavailable`
+/// otherwise.
+pub struct HealthServer {
+    listener: TcpListener,
+    liveness: Vec<Probe>,
+    readiness: Vec<Probe>,
+    connections: usize,
+    exit: Receiver<()>,
+}
+
+impl HealthServer {
+    /// Create a new `HealthServer` listening on the given address. The
+    /// returned `Sender` can be used to stop the server.
+    pub async fn bind(
+        addr: SocketAddr,
+    ) -> Result<(Self, Sender<()>), ServerError> {
+        let listener = TcpListener::bind(addr).await.context(ServerIo {
+            when: "binding health server",
+        })?;
+        let (tx, rx) = channel();
+
+        Ok((
+            Self {
+                listener,
+                liveness: Vec::new(),
+                readiness: Vec::new(),
+                connections: MAX_CONNECTIONS,
+                exit: rx,
+            },
+            tx,
+        ))
+    }
+
+    /// Add a probe that must succeed for `/healthz` to report success
+    pub fn liveness<F>(mut self, probe: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.liveness.push(Arc::new(probe));
+        self
+    }
+
+    /// Add a probe that must succeed for `/readyz` to report success
+    pub fn readiness<F>(mut self, probe: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.readiness.push(Arc::new(probe));
+        self
+    }
+
+    /// Use a `HealthSource` for both liveness and readiness
+    pub fn source<S: HealthSource + 'static>(self, source: Arc<S>) -> Self {
+        let ready = source.clone();
+
+        self.liveness(move || source.healthy())
+            .readiness(move || ready.ready())
+    }
+
+    /// Set the maximum number of connections served at once, further
+    /// connections being closed without an answer until one finishes
+    pub fn max_connections(mut self, connections: usize) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Get the local address this `HealthServer` is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, ServerError> {
+        self.listener.local_addr().context(ServerIo {
+            when: "getting local address",
+        })
+    }
+
+    /// Serve health requests until the exit signal is received
+    pub async fn serve(self) -> Result<(), ServerError> {
+        let Self {
+            listener,
+            liveness,
+            readiness,
+            connections,
+            mut exit,
+        } = self;
+        let connections = Arc::new(Semaphore::new(connections));
+
+        loop {
+            let accepted =
+                match future::select(&mut exit, Box::pin(listener.accept()))
+                    .await
+                {
+                    Either::Left(_) => {
+                        info!(
+                            target: targets::HEALTH,
+                            "health server exiting..."
+                        );
+                        return Ok(());
+                    }
+                    Either::Right((accepted, _)) => accepted,
+                };
+
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(
+                        target: targets::HEALTH,
+                        "failed to accept health connection: {}", e
+                    );
+                    continue;
+                }
+            };
+
+            let permit = match connections.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!(
+                        target: targets::HEALTH,
+                        "rejected health connection from {}, server busy",
+                        peer
+                    );
+                    continue;
+                }
+            };
+
+            debug!(target: targets::HEALTH, "health request from {}", peer);
+
+            let (liveness, readiness) = (liveness.clone(), readiness.clone());
+
+            task::spawn(async move {
+                let serve = Self::respond(stream, &liveness, &readiness);
+
+                if time::timeout(CONNECTION_TIMEOUT, serve).await.is_err() {
+                    debug!(
+                        target: targets::HEALTH,
+                        "health connection from {} timed out", peer
+                    );
+                }
+
+                drop(permit);
+            });
+        }
+    }
+
+    async fn respond(
+        mut stream: TcpStream,
+        liveness: &[Probe],
+        readiness: &[Probe],
+    ) {
+        let status = match Self::read_request(&mut stream).await {
+            Some(request) => match parse_request_line(&request) {
+                Some(("GET", "/healthz")) => probe_status(liveness),
+                Some(("GET", "/readyz")) => probe_status(readiness),
+                Some(("GET", _)) => Status::NotFound,
+                Some(_) => Status::MethodNotAllowed,
+                None => Status::BadRequest,
+            },
+            None => Status::TooLarge,
+        };
+
+        if let Err(e) = stream.write_all(status.response().as_bytes()).await {
+            debug!(
+                target: targets::HEALTH,
+                "failed to write health response: {}", e
+            );
+        }
+
+        let _ = stream.shutdown().await;
+    }
+
+    /// Read the request head, returning `None` if it is too large
+    async fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(512);
+        let mut chunk = [0u8; 512];
+
+        loop {
+            if buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+                return Some(buffer);
+            }
+
+            if buffer.len() >= MAX_REQUEST_SIZE {
+                return None;
+            }
+
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return Some(buffer),
+                Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+/// Extract the method and path from an HTTP/1.x request
+fn parse_request_line(request: &[u8]) -> Option<(&str, &str)> {
+    let end = request.windows(2).position(|w| w == b"\r\n")?;
+    let line = std::str::from_utf8(&request[..end]).ok()?;
+    let mut parts = line.split(' ');
+
+    let method = parts.next()?;
+    let path = parts.next()?;
+    let version = parts.next()?;
+
+    if parts.next().is_some() || !version.starts_with("HTTP/1.") {
+        return None;
+    }
+
+    Some((method, path))
+}
+
+fn probe_status(probes: &[Probe]) -> Status {
+    if probes.iter().all(|probe| probe()) {
+        Status::Ok
+    } else {
+        Status::Unavailable
+    }
+}
+
+enum Status {
+    Ok,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    TooLarge,
+    Unavailable,
+}
+
+impl Status {
+    fn response(&self) -> String {
+        let (code, reason, body) = match self {
+            Self::Ok => (200, "OK", "ok\n"),
+            Self::BadRequest => (400, "Bad Request", "bad request\n"),
+            Self::NotFound => (404, "Not Found", "not found\n"),
+            Self::MethodNotAllowed => {
+                (405, "Method Not Allowed", "method not allowed\n")
+            }
+            Self::TooLarge => (
+                431,
+                "Request Header Fields Too Large",
+                "request too large\n",
+            ),
+            Self::Unavailable => (503, "Service Unavailable", "unavailable\n"),
+        };
+
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            reason,
+            body.len(),
+            body
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::test::*;
+
+    async fn request(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let mut response = String::new();
+
+        stream.write_all(request).await.expect("write failed");
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read failed");
+
+        response
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let head = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path);
+
+        request(addr, head.as_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn probe_transitions() {
+        init_logger();
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let probe = ready.clone();
+
+        let (server, exit) = HealthServer::bind(next_test_ip4())
+            .await
+            .expect("bind failed");
+        let server = server
+            .liveness(|| true)
+            .readiness(move || probe.load(Ordering::Acquire));
+        let addr = server.local_addr().expect("no local address");
+
+        let handle = task::spawn(server.serve());
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 503"));
+
+        ready.store(true, Ordering::Release);
+
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 200"));
+
+        ready.store(false, Ordering::Release);
+
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 503"));
+        assert!(get(addr, "/other").await.starts_with("HTTP/1.1 404"));
+
+        let response = request(addr, b"POST /healthz HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405"));
+
+        let response = request(addr, b"garbage\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        exit.send(()).expect("server died");
+        handle
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn oversized_request() {
+        let (server, exit) = HealthServer::bind(next_test_ip4())
+            .await
+            .expect("bind failed");
+        let addr = server.local_addr().expect("no local address");
+
+        let handle = task::spawn(server.serve());
+
+        let mut request = b"GET /healthz HTTP/1.1\r\n".to_vec();
+        request.extend(vec![b'a'; MAX_REQUEST_SIZE]);
+
+        let mut stream =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let _ = stream.write_all(&request).await;
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+        exit.send(()).expect("server died");
+        handle
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn connection_limit() {
+        init_logger();
+
+        let (server, exit) = HealthServer::bind(next_test_ip4())
+            .await
+            .expect("bind failed");
+        let server = server.liveness(|| true).max_connections(1);
+        let addr = server.local_addr().expect("no local address");
+
+        let handle = task::spawn(server.serve());
+
+        let idle = TcpStream::connect(addr).await.expect("connect failed");
+
+        time::sleep(Duration::from_millis(50)).await;
+
+        let mut stream =
+            TcpStream::connect(addr).await.expect("connect failed");
+        let mut response = String::new();
+        let _ = stream.write_all(b"GET /healthz HTTP/1.1\r\n\r\n").await;
+        let _ = stream.read_to_string(&mut response).await;
+
+        assert!(response.is_empty(), "{}", response);
+
+        drop(idle);
+
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+
+        exit.send(()).expect("server died");
+        handle
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+}
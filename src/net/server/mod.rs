@@ -1,6 +1,9 @@
 mod directory;
 pub use self::directory::*;
 
+mod health;
+pub use self::health::{HealthServer, HealthSource};
+
 use std::io::Error;
 
 use super::{ListenerError, ReceiveError, SendError};
@@ -0,0 +1,300 @@
+use super::{Connection, ReceiveError, SendError};
+use crate::message::Message;
+use crate::telemetry::targets;
+
+use snafu::{ResultExt, Snafu};
+
+use tracing::warn;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+/// Error encountered by the sessions generated by the `protocol` attribute
+pub enum ProtocolError {
+    #[snafu(display("failed to send {}: {}", variant, source))]
+    /// The message could not be sent
+    SendMessage {
+        /// Variant that was being sent
+        variant: &'static str,
+        /// Underlying error cause
+        source: SendError,
+    },
+
+    #[snafu(display("failed to receive message: {}", source))]
+    /// No message could be received
+    ReceiveMessage {
+        /// Underlying error cause
+        source: ReceiveError,
+    },
+
+    #[snafu(display(
+        "protocol violation: received {} instead of one of {:?}",
+        received,
+        expected
+    ))]
+    /// The remote peer sent a message that is not allowed at this point of
+    /// the session
+    ProtocolViolation {
+        /// Variants allowed at this point of the session
+        expected: &'static [&'static str],
+        /// Variant that was received
+        received: &'static str,
+    },
+}
+
+/// A message enum describing a session, implemented by the `protocol`
+/// attribute which generates typed sessions over a `Connection` that only
+/// let each side send the messages allowed at each point of the session:
+///
+/// ```
+/// use drop::net::{protocol, Connection, ProtocolError};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[protocol]
+/// enum Ping {
+///     #[from(client)]
+///     #[next(Pong)]
+///     #[repeat]
+///     Ping(u64),
+///     #[from(server)]
+///     #[next(Ping | Bye)]
+///     Pong(u64),
+///     #[from(client)]
+///     #[terminal]
+///     Bye,
+/// }
+///
+/// async fn ping(connection: Connection) -> Result<Connection, ProtocolError> {
+///     let session = ping::ClientSession::new(connection);
+///
+///     match session.send_ping(1).await?.receive().await? {
+///         ping::ClientAfterPing::Pong(session, _) => {
+///             Ok(session.send_bye().await?.into_inner())
+///         }
+///     }
+/// }
+/// # fn main() {}
+/// ```
+///
+/// Sending a message out of order does not compile:
+///
+/// ```compile_fail
+/// use drop::net::{protocol, Connection};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[protocol]
+/// enum Ping {
+///     #[from(client)]
+///     #[next(Pong)]
+///     #[repeat]
+///     Ping(u64),
+///     #[from(server)]
+///     #[next(Ping | Bye)]
+///     Pong(u64),
+///     #[from(client)]
+///     #[terminal]
+///     Bye,
+/// }
+///
+/// async fn ping(connection: Connection) {
+///     let session = ping::ClientSession::new(connection);
+///     let session = session.send_ping(1).await.unwrap();
+///
+///     // the client must wait for the pong before sending another ping
+///     session.send_ping(2).await.unwrap();
+/// }
+/// # fn main() {}
+/// ```
+pub trait Protocol: Message {
+    /// Get the name of the variant of this message
+    fn variant(&self) -> &'static str;
+}
+
+/// Send `message` on `connection`, used by the sessions generated by the
+/// `protocol` attribute
+#[doc(hidden)]
+pub async fn send_message<P: Protocol>(
+    connection: &mut Connection,
+    message: &P,
+) -> Result<(), ProtocolError> {
+    connection.send(message).await.context(SendMessage {
+        variant: message.variant(),
+    })
+}
+
+/// Receive a message from `connection`, failing if it is not one of the
+/// `expected` variants. Used by the sessions generated by the `protocol`
+/// attribute.
+#[doc(hidden)]
+pub async fn receive_expected<P: Protocol>(
+    connection: &mut Connection,
+    expected: &'static [&'static str],
+) -> Result<P, ProtocolError> {
+    let message = connection.receive::<P>().await.context(ReceiveMessage)?;
+    let received = message.variant();
+
+    if !expected.contains(&received) {
+        warn!(
+            target: targets::CONNECTION,
+            "received {} instead of one of {:?}", received, expected
+        );
+
+        return ProtocolViolation { expected, received }.fail();
+    }
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::key::exchange::Exchanger;
+    use crate::net::{
+        protocol, Connector, Listener, MemoryConnector, MemoryListener,
+        MemoryRegistry,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    #[protocol]
+    enum Transfer {
+        #[from(client)]
+        #[next(Accepted | Refused)]
+        Hello { name: String, version: u16 },
+        #[from(server)]
+        #[next(Chunk)]
+        Accepted,
+        #[from(server)]
+        #[terminal]
+        Refused(String),
+        #[from(client)]
+        #[next(Ack)]
+        #[repeat]
+        Chunk(u32, Vec<u8>),
+        #[from(server)]
+        #[next(Chunk | Done)]
+        Ack(u32),
+        #[from(client)]
+        #[terminal]
+        Done,
+    }
+
+    async fn pair() -> (Connection, Connection) {
+        let registry = MemoryRegistry::new();
+        let server = Exchanger::random();
+        let public = *server.keypair().public();
+        let mut listener =
+            MemoryListener::new(&registry, ([127, 0, 0, 1], 0).into(), server)
+                .expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+        let connector = MemoryConnector::new(registry, Exchanger::random());
+        let (client, server) =
+            tokio::join!(connector.connect(&public, &addr), listener.accept());
+
+        (
+            client.expect("connect failed"),
+            server.expect("accept failed"),
+        )
+    }
+
+    #[tokio::test]
+    async fn typed_session() {
+        let (client, server) = pair().await;
+        let client = tokio::spawn(async move {
+            let session = transfer::ClientSession::new(client);
+            let session = session
+                .send_hello("file".into(), 2)
+                .await
+                .expect("hello failed");
+            let mut session = match session.receive().await {
+                Ok(transfer::ClientAfterHello::Accepted(session, ())) => {
+                    session.send_chunk(0, vec![0; 16]).await
+                }
+                _ => panic!("transfer not accepted"),
+            }
+            .expect("chunk failed");
+
+            for seq in 1..=3 {
+                let transfer::ClientAfterChunk::Ack(next, acked) =
+                    session.receive().await.expect("no ack");
+
+                assert_eq!(acked, seq - 1, "wrong chunk acknowledged");
+
+                if seq == 3 {
+                    return next.send_done().await.expect("done failed");
+                }
+
+                session = next
+                    .send_chunk(seq, vec![0; 16])
+                    .await
+                    .expect("chunk failed");
+            }
+
+            unreachable!()
+        });
+
+        let session = transfer::ServerSession::new(server);
+        let (session, (name, version)) = match session.receive().await {
+            Ok(transfer::ServerInitial::Hello(session, hello)) => {
+                (session, hello)
+            }
+            _ => panic!("no hello"),
+        };
+
+        assert_eq!((name.as_str(), version), ("file", 2));
+
+        let session = session.send_accepted().await.expect("accept failed");
+        let transfer::ServerAfterAccepted::Chunk(mut session, mut chunk) =
+            session.receive().await.expect("no chunk");
+        let mut received = Vec::new();
+
+        loop {
+            let (seq, data) = chunk;
+
+            assert_eq!(data.len(), 16);
+            received.push(seq);
+
+            match session
+                .send_ack(seq)
+                .await
+                .expect("ack failed")
+                .receive()
+                .await
+                .expect("no chunk")
+            {
+                transfer::ServerAfterAck::Chunk(next, next_chunk) => {
+                    session = next;
+                    chunk = next_chunk;
+                }
+                transfer::ServerAfterAck::Done(session, ()) => {
+                    session.into_inner();
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(received, [0, 1, 2]);
+
+        client.await.expect("client failed").into_inner();
+    }
+
+    #[tokio::test]
+    async fn violation_is_reported() {
+        let (mut raw, server) = pair().await;
+
+        // a misbehaving client skipping the hello
+        raw.send(&Transfer::Chunk(0, Vec::new()))
+            .await
+            .expect("send failed");
+
+        let session = transfer::ServerSession::new(server);
+
+        match session.receive().await {
+            Err(ProtocolError::ProtocolViolation { expected, received }) => {
+                assert_eq!(expected, ["Hello"]);
+                assert_eq!(received, "Chunk");
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("accepted a chunk before the hello"),
+        }
+    }
+}
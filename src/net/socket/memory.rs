@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use super::shaping::{self, LinkKey, LinkProfile, LinkStats, Links, Shaper};
+use super::Socket;
+
+use tokio::io::{self, AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+/// Number of bytes buffered in each direction of a `MemorySocket`
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of pending connections a `MemoryListener` can have
+const BACKLOG: usize = 128;
+
+/// Number of ports handed out on each address of a `MemoryRegistry`
+const PORTS: u32 = u16::MAX as u32;
+
+/// A `Socket` connecting two ends in the same process, created by a
+/// `MemoryConnector` and accepted by a `MemoryListener`
+pub struct MemorySocket {
+    stream: DuplexStream,
+    /// Writes go through this when the link is shaped, `stream` being only
+    /// used for reading
+    shaper: Option<Shaper>,
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+impl MemorySocket {
+    /// Create both ends of a `MemorySocket` between `local` and `peer`
+    pub(crate) fn pair(local: SocketAddr, peer: SocketAddr) -> (Self, Self) {
+        let (near, far) = io::duplex(BUFFER_SIZE);
+
+        (
+            Self {
+                stream: near,
+                shaper: None,
+                local,
+                peer,
+            },
+            Self {
+                stream: far,
+                shaper: None,
+                local: peer,
+                peer: local,
+            },
+        )
+    }
+
+    /// Create both ends of a `MemorySocket` between `local` and `peer`
+    /// whose traffic is shaped according to the `LinkProfile` of `key`
+    fn shaped_pair(
+        local: SocketAddr,
+        peer: SocketAddr,
+        links: &shaping::SharedLinks,
+        key: LinkKey,
+    ) -> (Self, Self) {
+        let (near, to_near) = io::duplex(BUFFER_SIZE);
+        let (far, to_far) = io::duplex(BUFFER_SIZE);
+
+        (
+            Self {
+                stream: near,
+                shaper: Some(Shaper::new(links.clone(), key, to_far)),
+                local,
+                peer,
+            },
+            Self {
+                stream: far,
+                shaper: Some(Shaper::new(links.clone(), key, to_near)),
+                local: peer,
+                peer: local,
+            },
+        )
+    }
+}
+
+impl Socket for MemorySocket {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn transport(&self) -> &'static str {
+        "memory"
+    }
+}
+
+impl AsyncRead for MemorySocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemorySocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        match &mut self.shaper {
+            Some(shaper) => Poll::Ready(shaper.write(buf)),
+            None => Pin::new(&mut self.stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        match &mut self.shaper {
+            Some(shaper) => Poll::Ready(shaper.flush()),
+            None => Pin::new(&mut self.stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        match &mut self.shaper {
+            Some(shaper) => Poll::Ready(shaper.shutdown()),
+            None => Pin::new(&mut self.stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Endpoints {
+    listeners: HashMap<SocketAddr, mpsc::Sender<MemorySocket>>,
+    next: u32,
+}
+
+impl Endpoints {
+    /// Hand out an address that no `MemoryListener` is bound to
+    fn allocate(&mut self) -> SocketAddr {
+        loop {
+            let n = self.next;
+            let ip = Ipv4Addr::from(u32::from(Ipv4Addr::LOCALHOST) + n / PORTS);
+            let addr = SocketAddr::from((ip, (n % PORTS + 1) as u16));
+
+            self.next = self.next.wrapping_add(1);
+
+            if !self.listeners.contains_key(&addr) {
+                return addr;
+            }
+        }
+    }
+}
+
+/// The addresses of the `MemoryListener`s reachable by `MemoryConnector`s.
+/// Cloning a `MemoryRegistry` gives another handle to the same set of
+/// addresses, while independent registries never see each other's
+/// listeners, so that separate simulations can run in the same process.
+///
+/// The traffic between a listener and a dialer can be shaped according to a
+/// [`LinkProfile`], in which case it is delayed using the tokio clock so
+/// that simulations run with paused time. Losses and jitter are drawn from a
+/// generator seeded by the registry, making simulations reproducible.
+///
+/// [`LinkProfile`]: crate::net::LinkProfile
+#[derive(Clone)]
+pub struct MemoryRegistry {
+    endpoints: Arc<Mutex<Endpoints>>,
+    links: shaping::SharedLinks,
+}
+
+impl MemoryRegistry {
+    /// Create a new empty `MemoryRegistry`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new empty `MemoryRegistry` drawing losses and jitter from a
+    /// generator seeded with `seed`
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            endpoints: Default::default(),
+            links: Arc::new(Mutex::new(Links::seeded(seed))),
+        }
+    }
+
+    /// Shape the traffic between the listener bound to `listener` and the
+    /// `MemoryConnector`s dialing from `dialer`, see
+    /// `MemoryConnector::with_local_addr`. This applies to the
+    /// `MemorySocket`s that are already open on this link, but only those
+    /// opened once a profile was first set on this registry are shaped.
+    pub fn set_link(
+        &self,
+        listener: SocketAddr,
+        dialer: SocketAddr,
+        profile: LinkProfile,
+    ) {
+        shaping::lock(&self.links).set((listener, dialer), profile);
+    }
+
+    /// Shape the traffic of every link that has no `LinkProfile` of its own,
+    /// see `MemoryRegistry::set_link`
+    pub fn set_default_link(&self, profile: LinkProfile) {
+        shaping::lock(&self.links).set_default(profile);
+    }
+
+    /// Get the `LinkProfile` applied between `listener` and `dialer`
+    pub fn link(
+        &self,
+        listener: SocketAddr,
+        dialer: SocketAddr,
+    ) -> LinkProfile {
+        shaping::lock(&self.links).profile(&(listener, dialer))
+    }
+
+    /// Get the traffic that went through the shaped link between `listener`
+    /// and `dialer`
+    pub fn link_stats(
+        &self,
+        listener: SocketAddr,
+        dialer: SocketAddr,
+    ) -> LinkStats {
+        shaping::lock(&self.links).stats(&(listener, dialer))
+    }
+
+    /// Number of `MemoryListener`s currently bound in this registry
+    pub fn len(&self) -> usize {
+        self.endpoints().listeners.len()
+    }
+
+    /// Check whether no `MemoryListener` is bound in this registry
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bind to `addr`, or to a free address if its port is 0, returning the
+    /// bound address and the `Receiver` of incoming `MemorySocket`s
+    pub(crate) fn bind(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<(SocketAddr, mpsc::Receiver<MemorySocket>)> {
+        let mut endpoints = self.endpoints();
+        let addr = if addr.port() == 0 {
+            endpoints.allocate()
+        } else {
+            addr
+        };
+
+        if endpoints.listeners.contains_key(&addr) {
+            return Err(Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is already bound", addr),
+            ));
+        }
+
+        let (tx, rx) = mpsc::channel(BACKLOG);
+
+        endpoints.listeners.insert(addr, tx);
+
+        Ok((addr, rx))
+    }
+
+    /// Release `addr` so that no more `MemorySocket`s are accepted on it
+    pub(crate) fn unbind(&self, addr: &SocketAddr) {
+        self.endpoints().listeners.remove(addr);
+    }
+
+    /// Open a `MemorySocket` to the listener bound to `addr`, from `local` or
+    /// from a free address if `None`
+    pub(crate) async fn dial(
+        &self,
+        addr: &SocketAddr,
+        local: Option<SocketAddr>,
+    ) -> Result<MemorySocket> {
+        let refused = || {
+            Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("nothing listening on {}", addr),
+            )
+        };
+        let (listener, local) = {
+            let mut endpoints = self.endpoints();
+            let listener =
+                endpoints.listeners.get(addr).cloned().ok_or_else(refused)?;
+
+            (listener, local.unwrap_or_else(|| endpoints.allocate()))
+        };
+        let (near, far) = if shaping::lock(&self.links).enabled() {
+            MemorySocket::shaped_pair(local, *addr, &self.links, (*addr, local))
+        } else {
+            MemorySocket::pair(local, *addr)
+        };
+
+        listener.send(far).await.map_err(|_| refused())?;
+
+        Ok(near)
+    }
+
+    fn endpoints(&self) -> std::sync::MutexGuard<'_, Endpoints> {
+        self.endpoints.lock().expect("memory registry poisoned")
+    }
+}
+
+impl Default for MemoryRegistry {
+    fn default() -> Self {
+        Self::seeded(0)
+    }
+}
+
+impl fmt::Debug for MemoryRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryRegistry")
+            .field("listeners", &self.len())
+            .finish()
+    }
+}
@@ -1,11 +1,30 @@
+/// In-process `Socket` implementation
+mod memory;
+/// Simulated network conditions for in-process `Socket`s
+mod shaping;
 /// Tcp `Socket` implementation
 pub mod tcp;
+/// Udp `Socket` implementation
+pub mod udp;
+/// Unix domain socket `Socket` implementation
+#[cfg(unix)]
+pub mod unix;
+pub use memory::MemoryRegistry;
+pub(crate) use memory::MemorySocket;
+pub use shaping::{
+    LinkProfile, LinkStats, NetworkProfile, MIN_RETRANSMISSION_TIMEOUT,
+};
+pub use udp::{DatagramOverflow, MAX_DATAGRAM_SIZE};
+/// QUIC `Socket` implementation
+#[cfg(feature = "quic")]
+pub mod quic;
 /// uTp `Socket` implementation
 #[cfg(feature = "unstable")]
 pub mod utp;
 
 use std::io::Result;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -18,4 +37,17 @@ pub trait Socket: AsyncRead + AsyncWrite + Unpin + Send + Sync {
 
     /// Local address in use by this `Connection`
     fn local_addr(&self) -> Result<SocketAddr>;
+
+    /// Name of the transport protocol used by this `Socket`
+    fn transport(&self) -> &'static str {
+        "unknown"
+    }
+
+    /// Send keepalive probes after `idle` without traffic, or stop sending
+    /// them if `None`. Transports without keepalive ignore this.
+    fn set_keepalive(&self, idle: Option<Duration>) -> Result<()> {
+        let _ = idle;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,212 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::Socket;
+
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{
+    ClientConfig, Connection, Endpoint, RecvStream, SendStream, ServerConfig,
+};
+
+use rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime,
+};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Application protocol negotiated by both ends of a QUIC `Connection`
+const ALPN: &[u8] = b"drop";
+
+/// Server name used when generating and presenting certificates. Peers are
+/// identified by their exchange key and not by name.
+pub(crate) const SERVER_NAME: &str = "drop";
+
+/// A single bidirectional stream of a QUIC connection
+pub struct QuicSocket {
+    local: SocketAddr,
+    connection: Connection,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicSocket {
+    /// Wrap a bidirectional stream opened on the given QUIC `Connection`
+    /// that goes through `endpoint`
+    pub fn new(
+        endpoint: &Endpoint,
+        connection: Connection,
+        send: SendStream,
+        recv: RecvStream,
+    ) -> Result<Self> {
+        let mut local = endpoint.local_addr()?;
+
+        if let Some(ip) = connection.local_ip() {
+            local.set_ip(ip);
+        }
+
+        Ok(Self {
+            local,
+            connection,
+            send,
+            recv,
+        })
+    }
+}
+
+impl Socket for QuicSocket {
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.connection.remote_address())
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn transport(&self) -> &'static str {
+        "quic"
+    }
+}
+
+impl AsyncRead for QuicSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn tls_error(error: rustls::Error) -> Error {
+    Error::new(ErrorKind::InvalidInput, error)
+}
+
+/// Build the configuration used to dial QUIC peers. The certificate of the
+/// remote end is not checked against any authority since peers authenticate
+/// each other with the drop handshake that runs on top of the stream.
+pub(crate) fn client_config() -> Result<ClientConfig> {
+    let provider = provider();
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(tls)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    Ok(ClientConfig::new(Arc::new(crypto)))
+}
+
+/// Build the configuration used to accept QUIC peers, presenting a freshly
+/// generated self-signed certificate
+pub(crate) fn server_config() -> Result<ServerConfig> {
+    let certified =
+        rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()])
+            .map_err(Error::other)?;
+    let cert = CertificateDer::from(certified.cert);
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], PrivateKeyDer::Pkcs8(key))
+        .map_err(tls_error)?;
+
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(tls)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Accepts any certificate but still checks that the remote end owns the key
+/// it presents
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
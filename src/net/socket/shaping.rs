@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{self, Instant};
+
+/// Shortest delay before a lost frame is retransmitted, see
+/// `LinkProfile::loss_probability`
+pub const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Number of times a frame is lost in a row at most, so that a link losing
+/// every frame still eventually delivers them
+const MAX_RETRANSMISSIONS: u32 = 16;
+
+/// Number of bytes of the length prefix of a frame
+const PREFIX_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Conditions applied by a `MemoryRegistry` to the traffic between a
+/// `MemoryListener` and a dialer, in both directions. The default is a
+/// perfect link delivering everything right away.
+///
+/// Shaping applies to whole frames as written by `Connection`s, which are
+/// recognized by their length prefix. Since `Connection`s expect a reliable
+/// stream, and their encryption cannot skip a frame, a lost frame is not
+/// dropped but retransmitted after a timeout like TCP would. Frames are
+/// never reordered, so a frame waiting for retransmission delays the ones
+/// sent after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkProfile {
+    /// Time it takes for a frame to reach the other end once sent
+    pub latency: Duration,
+    /// Extra latency drawn uniformly between zero and this for every frame
+    pub jitter: Duration,
+    /// Rate at which frames are sent, frames wait for the previous ones to
+    /// be sent if the link is busy. `None` for unlimited bandwidth.
+    pub bandwidth_bytes_per_sec: Option<u64>,
+    /// Probability that a frame is lost, in which case it is sent again
+    /// after the larger of `MIN_RETRANSMISSION_TIMEOUT` and twice the
+    /// maximum latency, possibly more than once
+    pub loss_probability: f64,
+}
+
+impl LinkProfile {
+    /// Delay before a lost frame is sent again
+    pub fn retransmission_timeout(&self) -> Duration {
+        MIN_RETRANSMISSION_TIMEOUT.max((self.latency + self.jitter) * 2)
+    }
+
+    /// Time spent sending `len` bytes on this link
+    fn transmission(&self, len: usize) -> Duration {
+        match self.bandwidth_bytes_per_sec {
+            Some(rate) if rate > 0 => {
+                Duration::from_secs_f64(len as f64 / rate as f64)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Presets of `LinkProfile`s for typical networks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkProfile {
+    /// A local network: half a millisecond of latency, 1 Gbit/s and no loss
+    Lan,
+    /// A wide area network: 50 ms of latency with 5 ms of jitter,
+    /// 100 Mbit/s and 0.1% loss
+    Wan,
+    /// A congested mobile network: 150 ms of latency with 50 ms of jitter,
+    /// 2 Mbit/s and 5% loss
+    LossyMobile,
+}
+
+impl NetworkProfile {
+    /// Get the `LinkProfile` of this preset
+    pub fn link(self) -> LinkProfile {
+        match self {
+            Self::Lan => LinkProfile {
+                latency: Duration::from_micros(500),
+                jitter: Duration::ZERO,
+                bandwidth_bytes_per_sec: Some(125_000_000),
+                loss_probability: 0.0,
+            },
+            Self::Wan => LinkProfile {
+                latency: Duration::from_millis(50),
+                jitter: Duration::from_millis(5),
+                bandwidth_bytes_per_sec: Some(12_500_000),
+                loss_probability: 0.001,
+            },
+            Self::LossyMobile => LinkProfile {
+                latency: Duration::from_millis(150),
+                jitter: Duration::from_millis(50),
+                bandwidth_bytes_per_sec: Some(250_000),
+                loss_probability: 0.05,
+            },
+        }
+    }
+}
+
+impl From<NetworkProfile> for LinkProfile {
+    fn from(profile: NetworkProfile) -> Self {
+        profile.link()
+    }
+}
+
+/// Traffic that went through a shaped link, in both directions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Number of frames sent
+    pub frames: u64,
+    /// Number of bytes sent
+    pub bytes: u64,
+    /// Number of times a frame was lost and had to be sent again
+    pub retransmissions: u64,
+}
+
+/// A link between a listener and a dialer, identified by their addresses
+pub(super) type LinkKey = (SocketAddr, SocketAddr);
+
+/// The `LinkProfile`s of a `MemoryRegistry`
+pub(super) struct Links {
+    /// Whether new sockets should be shaped
+    enabled: bool,
+    default: LinkProfile,
+    profiles: HashMap<LinkKey, LinkProfile>,
+    stats: HashMap<LinkKey, LinkStats>,
+    rng: StdRng,
+}
+
+impl Links {
+    pub(super) fn seeded(seed: u64) -> Self {
+        Self {
+            enabled: false,
+            default: LinkProfile::default(),
+            profiles: HashMap::new(),
+            stats: HashMap::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn set(&mut self, key: LinkKey, profile: LinkProfile) {
+        self.enabled = true;
+        self.profiles.insert(key, profile);
+    }
+
+    pub(super) fn set_default(&mut self, profile: LinkProfile) {
+        self.enabled = true;
+        self.default = profile;
+    }
+
+    pub(super) fn profile(&self, key: &LinkKey) -> LinkProfile {
+        self.profiles.get(key).copied().unwrap_or(self.default)
+    }
+
+    pub(super) fn stats(&self, key: &LinkKey) -> LinkStats {
+        self.stats.get(key).copied().unwrap_or_default()
+    }
+
+    /// Draw the latency of a frame of `len` bytes sent on `key`, counting it
+    /// in the statistics of the link. Returns the time spent sending it
+    /// along with the time it takes to arrive afterwards.
+    fn frame(&mut self, key: &LinkKey, len: usize) -> (Duration, Duration) {
+        let profile = self.profile(key);
+        let mut latency = profile.latency;
+
+        if !profile.jitter.is_zero() {
+            latency += self.rng.gen_range(Duration::ZERO..=profile.jitter);
+        }
+
+        let loss = profile.loss_probability.clamp(0.0, 1.0);
+        let mut lost = 0;
+
+        while lost < MAX_RETRANSMISSIONS && self.rng.gen_bool(loss) {
+            lost += 1;
+        }
+
+        let stats = self.stats.entry(*key).or_default();
+
+        stats.frames += 1;
+        stats.bytes += len as u64;
+        stats.retransmissions += u64::from(lost);
+
+        (
+            profile.transmission(len),
+            latency + profile.retransmission_timeout() * lost,
+        )
+    }
+}
+
+/// Shared handle to the `Links` of a `MemoryRegistry`
+pub(super) type SharedLinks = Arc<Mutex<Links>>;
+
+pub(super) fn lock(links: &SharedLinks) -> MutexGuard<'_, Links> {
+    links.lock().expect("memory links poisoned")
+}
+
+/// Sending end of one direction of a shaped `MemorySocket`, which delays
+/// frames according to the `LinkProfile` of its link before writing them to
+/// the other end
+pub(super) struct Shaper {
+    links: SharedLinks,
+    key: LinkKey,
+    /// Bytes of the frame being written
+    pending: Vec<u8>,
+    /// Time at which the link is done sending the previous frames
+    busy: Instant,
+    /// Arrival time of the previous frame
+    last: Instant,
+    frames: Option<mpsc::UnboundedSender<(Instant, Vec<u8>)>>,
+}
+
+impl Shaper {
+    /// Shape the traffic written to `output` according to the `LinkProfile`
+    /// of `key`
+    pub(super) fn new(
+        links: SharedLinks,
+        key: LinkKey,
+        mut output: DuplexStream,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Vec<u8>)>();
+        let now = Instant::now();
+
+        task::spawn(async move {
+            while let Some((arrival, frame)) = rx.recv().await {
+                time::sleep_until(arrival).await;
+
+                if output.write_all(&frame).await.is_err() {
+                    return;
+                }
+            }
+
+            let _ = output.shutdown().await;
+        });
+
+        Self {
+            links,
+            key,
+            pending: Vec::new(),
+            busy: now,
+            last: now,
+            frames: Some(tx),
+        }
+    }
+
+    /// Buffer `buf`, sending every frame it completes
+    pub(super) fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        while let Some(len) = self.complete() {
+            let rest = self.pending.split_off(len);
+            let frame = std::mem::replace(&mut self.pending, rest);
+
+            self.send(frame)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Send the bytes of an incomplete frame right away, since the writer
+    /// expects them to arrive
+    pub(super) fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let frame = std::mem::take(&mut self.pending);
+
+        self.send(frame)
+    }
+
+    /// Close this direction once every frame has arrived
+    pub(super) fn shutdown(&mut self) -> Result<()> {
+        self.flush()?;
+        self.frames = None;
+
+        Ok(())
+    }
+
+    /// Length of the first frame of `pending` if it is complete
+    fn complete(&self) -> Option<usize> {
+        let prefix = self.pending.get(..PREFIX_SIZE)?;
+        let size = u32::from_le_bytes(prefix.try_into().ok()?) as usize;
+        let len = PREFIX_SIZE.checked_add(size)?;
+
+        (self.pending.len() >= len).then_some(len)
+    }
+
+    fn send(&mut self, frame: Vec<u8>) -> Result<()> {
+        let (transmission, latency) =
+            lock(&self.links).frame(&self.key, frame.len());
+
+        self.busy = self.busy.max(Instant::now()) + transmission;
+        self.last = self.last.max(self.busy + latency);
+
+        self.frames
+            .as_ref()
+            .and_then(|frames| frames.send((self.last, frame)).ok())
+            .ok_or_else(|| {
+                Error::new(ErrorKind::BrokenPipe, "memory link closed")
+            })
+    }
+}
@@ -1,8 +1,11 @@
 use std::io::Result;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use super::Socket;
 
+use socket2::{SockRef, TcpKeepalive};
+
 use tokio::net::TcpStream;
 
 impl Socket for TcpStream {
@@ -13,4 +16,19 @@ impl Socket for TcpStream {
     fn peer_addr(&self) -> Result<SocketAddr> {
         self.peer_addr()
     }
+
+    fn transport(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn set_keepalive(&self, idle: Option<Duration>) -> Result<()> {
+        let socket = SockRef::from(self);
+
+        match idle {
+            Some(idle) => {
+                socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))
+            }
+            None => socket.set_keepalive(false),
+        }
+    }
 }
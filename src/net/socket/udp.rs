@@ -0,0 +1,480 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::Socket;
+use crate::telemetry::targets;
+
+use futures::ready;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UdpSocket as TokioSocket;
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+
+use tracing::{debug, warn};
+
+/// Largest payload that fits in a single UDP datagram
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// Size of the length prefix of each frame
+const PREFIX_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Number of datagrams buffered for a remote peer before closing its stream
+pub(crate) const PEER_BACKLOG: usize = 64;
+
+/// Number of new remote peers waiting to be accepted before ignoring new ones
+const ACCEPT_BACKLOG: usize = 32;
+
+/// Error returned by `UdpSocket` when a frame does not fit in one datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatagramTooLarge {
+    /// Size of the rejected frame including its length prefix
+    pub size: usize,
+    /// Largest size that fits in a single datagram
+    pub max: usize,
+}
+
+impl fmt::Display for DatagramTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame of {} bytes does not fit in a {} bytes datagram",
+            self.size, self.max
+        )
+    }
+}
+
+impl StdError for DatagramTooLarge {}
+
+impl DatagramTooLarge {
+    /// Extract a `DatagramTooLarge` from an i/o error returned by a `Socket`
+    pub fn from_io(error: &Error) -> Option<Self> {
+        error.get_ref()?.downcast_ref::<Self>().copied()
+    }
+}
+
+/// Error returned when reading from a `UdpSocket` accepted by a listener
+/// after its remote peer sent more datagrams than could be buffered, found in
+/// the source of `ReceiveError::ReceiveIo`. Since datagrams are never
+/// retransmitted the stream can't go on after one was dropped, so it is
+/// closed instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatagramOverflow {
+    /// Number of datagrams that were waiting to be read
+    pub backlog: usize,
+}
+
+impl fmt::Display for DatagramOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "remote peer sent datagrams faster than they were read, \
+             {} were already waiting",
+            self.backlog
+        )
+    }
+}
+
+impl StdError for DatagramOverflow {}
+
+impl DatagramOverflow {
+    /// Extract a `DatagramOverflow` from an i/o error returned by a `Socket`
+    pub fn from_io(error: &Error) -> Option<Self> {
+        error.get_ref()?.downcast_ref::<Self>().copied()
+    }
+}
+
+/// Where a `UdpSocket` gets its incoming datagrams from
+enum Incoming {
+    /// Datagrams are read from a connected socket
+    Direct,
+    /// Datagrams are forwarded by the `Demultiplexer` of a listener, which
+    /// sets the flag if it had to drop one
+    Demuxed(mpsc::Receiver<Vec<u8>>, Arc<AtomicBool>),
+}
+
+/// A `Socket` that sends each frame written to it as a single UDP datagram.
+///
+/// The length prefix of each frame is only used to find datagram boundaries
+/// when writing: datagrams are neither retransmitted nor reordered so this is
+/// only suitable for reliable links. An empty datagram is sent when shutting
+/// down and is read as the end of the stream by the remote end.
+pub struct UdpSocket {
+    socket: Arc<TokioSocket>,
+    peer: SocketAddr,
+    local: SocketAddr,
+    incoming: Incoming,
+    _demux: Option<Arc<DemuxTask>>,
+    read: Vec<u8>,
+    read_pos: usize,
+    eof: bool,
+    write: Vec<u8>,
+    expected: Option<usize>,
+}
+
+impl UdpSocket {
+    /// Wrap a tokio `UdpSocket` that is already connected to its remote peer
+    pub fn connected(socket: TokioSocket) -> Result<Self> {
+        let peer = socket.peer_addr()?;
+        let local = socket.local_addr()?;
+
+        Ok(Self::new(Arc::new(socket), peer, local, Incoming::Direct))
+    }
+
+    fn new(
+        socket: Arc<TokioSocket>,
+        peer: SocketAddr,
+        local: SocketAddr,
+        incoming: Incoming,
+    ) -> Self {
+        Self {
+            socket,
+            peer,
+            local,
+            incoming,
+            _demux: None,
+            read: Vec::new(),
+            read_pos: 0,
+            eof: false,
+            write: Vec::new(),
+            expected: None,
+        }
+    }
+
+    fn poll_datagram(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match &mut self.incoming {
+            Incoming::Direct => {
+                self.read.resize(MAX_DATAGRAM_SIZE + 1, 0);
+
+                let mut buf = ReadBuf::new(&mut self.read);
+                let result = self.socket.poll_recv(cx, &mut buf);
+                let filled = buf.filled().len();
+
+                self.read.truncate(filled);
+                ready!(result)?;
+            }
+            Incoming::Demuxed(_, overflowed)
+                if overflowed.load(Ordering::Acquire) =>
+            {
+                let error = DatagramOverflow {
+                    backlog: PEER_BACKLOG,
+                };
+
+                return Poll::Ready(Err(Error::other(error)));
+            }
+            Incoming::Demuxed(datagrams, _) => {
+                self.read = ready!(datagrams.poll_recv(cx)).unwrap_or_default();
+            }
+        }
+
+        self.read_pos = 0;
+        self.eof = self.read.is_empty();
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_send(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<()>> {
+        let sent = match self.incoming {
+            Incoming::Direct => ready!(self.socket.poll_send(cx, data)),
+            Incoming::Demuxed(..) => {
+                ready!(self.socket.poll_send_to(cx, data, self.peer))
+            }
+        }?;
+
+        if sent == data.len() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Ready(Err(ErrorKind::WriteZero.into()))
+        }
+    }
+
+    /// Send the buffered frame if it is complete
+    fn poll_send_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.expected != Some(self.write.len()) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let result = ready!(self.poll_send(cx, &self.write));
+
+        self.write.clear();
+        self.expected = None;
+
+        Poll::Ready(result)
+    }
+}
+
+impl Socket for UdpSocket {
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn transport(&self) -> &'static str {
+        "udp"
+    }
+}
+
+impl AsyncRead for UdpSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        while !self.eof && self.read_pos >= self.read.len() {
+            ready!(self.poll_datagram(cx))?;
+        }
+
+        let available = &self.read[self.read_pos..];
+        let len = available.len().min(buf.remaining());
+
+        buf.put_slice(&available[..len]);
+        self.read_pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for UdpSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        let (buffered, expected) = (self.write.len(), self.expected);
+        let taken = match self.expected {
+            None => {
+                let taken = (PREFIX_SIZE - self.write.len()).min(buf.len());
+
+                self.write.extend_from_slice(&buf[..taken]);
+
+                if self.write.len() == PREFIX_SIZE {
+                    let mut prefix = [0u8; PREFIX_SIZE];
+
+                    prefix.copy_from_slice(&self.write);
+
+                    let size =
+                        u32::from_le_bytes(prefix) as usize + PREFIX_SIZE;
+
+                    if size > MAX_DATAGRAM_SIZE {
+                        self.write.clear();
+
+                        let error = DatagramTooLarge {
+                            size,
+                            max: MAX_DATAGRAM_SIZE,
+                        };
+
+                        return Poll::Ready(Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            error,
+                        )));
+                    }
+
+                    self.expected = Some(size);
+                }
+
+                taken
+            }
+            Some(size) => {
+                let taken = (size - self.write.len()).min(buf.len());
+
+                self.write.extend_from_slice(&buf[..taken]);
+
+                taken
+            }
+        };
+
+        // the bytes completing a frame are only accepted once its datagram
+        // is sent so that no complete frame is ever left waiting for a flush
+        if self.poll_send_frame(cx)?.is_pending() {
+            self.write.truncate(buffered);
+            self.expected = expected;
+
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Ok(taken))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        self.poll_send(cx, &[])
+    }
+}
+
+/// Aborts the task reading from a listening socket once neither the listener
+/// nor any of the `UdpSocket`s it accepted are alive anymore
+struct DemuxTask(JoinHandle<()>);
+
+impl Drop for DemuxTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// A remote peer of a `Demultiplexer`
+struct Peer {
+    datagrams: mpsc::Sender<Vec<u8>>,
+    overflowed: Arc<AtomicBool>,
+}
+
+/// Reads datagrams from an unconnected socket and forwards them to one
+/// `UdpSocket` per remote address
+pub(crate) struct Demultiplexer {
+    local: SocketAddr,
+    accepted: mpsc::Receiver<UdpSocket>,
+    task: Arc<DemuxTask>,
+}
+
+impl Demultiplexer {
+    /// Start demultiplexing datagrams received on `socket`
+    pub(crate) fn new(socket: TokioSocket) -> Result<Self> {
+        let local = socket.local_addr()?;
+        let socket = Arc::new(socket);
+        let (sender, accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        let task = task::spawn(Self::run(socket.clone(), sender));
+        let task = Arc::new(DemuxTask(task));
+
+        Ok(Self {
+            local,
+            accepted,
+            task,
+        })
+    }
+
+    /// Local address of the demultiplexed socket
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Wait for a datagram from a new remote address
+    pub(crate) async fn accept(&mut self) -> Result<UdpSocket> {
+        let mut socket = self
+            .accepted
+            .recv()
+            .await
+            .ok_or_else(|| Error::from(ErrorKind::NotConnected))?;
+
+        socket._demux = Some(self.task.clone());
+
+        Ok(socket)
+    }
+
+    async fn run(socket: Arc<TokioSocket>, accepted: mpsc::Sender<UdpSocket>) {
+        let mut peers: HashMap<SocketAddr, Peer> = HashMap::new();
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE + 1];
+
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!(
+                        target: targets::LISTENER,
+                        "failed to receive datagram: {}", e
+                    );
+                    return;
+                }
+            };
+            let datagram = buf[..len].to_vec();
+
+            match peers.entry(from) {
+                Entry::Occupied(entry)
+                    if !entry.get().datagrams.is_closed() =>
+                {
+                    let peer = entry.get();
+
+                    if datagram.is_empty() {
+                        let _ = entry.remove().datagrams.try_send(datagram);
+                    } else if peer.overflowed.load(Ordering::Acquire) {
+                        // the stream is already broken, wait for the peer to
+                        // go away
+                    } else if peer.datagrams.try_send(datagram).is_err() {
+                        warn!(
+                            target: targets::LISTENER,
+                            "closing stream of slow udp peer {}: more than \
+                             {} datagrams waiting",
+                            from,
+                            PEER_BACKLOG
+                        );
+
+                        peer.overflowed.store(true, Ordering::Release);
+                    }
+                }
+                Entry::Occupied(mut entry) => {
+                    if let Some(peer) =
+                        Self::register(&socket, &accepted, from, datagram)
+                    {
+                        entry.insert(peer);
+                    } else {
+                        entry.remove();
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    if let Some(peer) =
+                        Self::register(&socket, &accepted, from, datagram)
+                    {
+                        entry.insert(peer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue a new `UdpSocket` for `from` whose first datagram is `datagram`
+    fn register(
+        socket: &Arc<TokioSocket>,
+        accepted: &mpsc::Sender<UdpSocket>,
+        from: SocketAddr,
+        datagram: Vec<u8>,
+    ) -> Option<Peer> {
+        if datagram.is_empty() {
+            return None;
+        }
+
+        let local = socket.local_addr().ok()?;
+        let (sender, datagrams) = mpsc::channel(PEER_BACKLOG);
+        let overflowed = Arc::new(AtomicBool::new(false));
+        let incoming = Incoming::Demuxed(datagrams, overflowed.clone());
+        let udp = UdpSocket::new(socket.clone(), from, local, incoming);
+
+        sender.try_send(datagram).ok()?;
+
+        match accepted.try_send(udp) {
+            Ok(()) => {
+                debug!(
+                    target: targets::LISTENER,
+                    "new udp peer {}", from
+                );
+                Some(Peer {
+                    datagrams: sender,
+                    overflowed,
+                })
+            }
+            Err(_) => {
+                debug!(
+                    target: targets::LISTENER,
+                    "accept backlog full, ignoring udp peer {}", from
+                );
+                None
+            }
+        }
+    }
+}
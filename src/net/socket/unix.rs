@@ -0,0 +1,82 @@
+use std::io::Result;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::Socket;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixStream;
+
+/// A `Socket` over a Unix domain stream between two processes on the same
+/// host
+pub struct UnixSocket {
+    stream: UnixStream,
+}
+
+impl UnixSocket {
+    /// Wrap a connected `UnixStream`
+    pub fn new(stream: UnixStream) -> Self {
+        Self { stream }
+    }
+}
+
+impl From<UnixStream> for UnixSocket {
+    fn from(stream: UnixStream) -> Self {
+        Self::new(stream)
+    }
+}
+
+/// Both ends of a Unix domain stream are on the local host, which is what the
+/// loopback address without a port stands for
+fn loopback() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 0).into()
+}
+
+impl Socket for UnixSocket {
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(loopback())
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(loopback())
+    }
+
+    fn transport(&self) -> &'static str {
+        "unix"
+    }
+}
+
+impl AsyncRead for UnixSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -15,4 +15,8 @@ impl Socket for BufferedUtpStream {
         Self::local_addr(self)
             .map_or_else(|_| Err(ErrorKind::AddrNotAvailable.into()), Ok)
     }
+
+    fn transport(&self) -> &'static str {
+        "utp"
+    }
 }
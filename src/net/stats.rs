@@ -0,0 +1,65 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Traffic carried by a secured `Connection` since it was created, shared by
+/// both halves it is split into
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Number of encrypted bytes written, including the size prefix of each
+    /// frame
+    pub bytes_sent: u64,
+    /// Number of encrypted bytes read, including the size prefix of each
+    /// frame
+    pub bytes_received: u64,
+    /// Number of messages sent, a fragmented message counting once
+    pub messages_sent: u64,
+    /// Number of messages received, a fragmented message counting once
+    pub messages_received: u64,
+}
+
+/// Counters behind `ConnectionStats`, updated using relaxed atomic operations
+/// since they are only ever read as a whole for monitoring
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+}
+
+/// Bytes taken by the size prefix of each frame
+const PREFIX: u64 = mem::size_of::<u32>() as u64;
+
+impl Counters {
+    /// Count a frame of `len` encrypted bytes as written
+    pub(crate) fn sent_frame(&self, len: usize) {
+        self.bytes_sent
+            .fetch_add(len as u64 + PREFIX, Ordering::Relaxed);
+    }
+
+    /// Count a frame of `len` encrypted bytes as read
+    pub(crate) fn received_frame(&self, len: usize) {
+        self.bytes_received
+            .fetch_add(len as u64 + PREFIX, Ordering::Relaxed);
+    }
+
+    /// Count one whole message as sent
+    pub(crate) fn sent_message(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count one whole message as received
+    pub(crate) fn received_message(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of the counters
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Versioned formats of the artifacts persisted to disk
+//!
+//! Every artifact is stored behind the same header, all integers being
+//! little endian:
+//!
+//! | offset | size | content                                         |
+//! |-------:|-----:|-------------------------------------------------|
+//! |      0 |    8 | magic bytes identifying the type of artifact    |
+//! |      8 |    2 | format version of the payload                   |
+//! |     10 |    - | payload, encoded using [`bincode_options`]      |
+//!
+//! A type is persisted by implementing [`Versioned`], giving it its own
+//! magic bytes and the version of the format it currently encodes to. When
+//! the format changes, `CURRENT_VERSION` is incremented and a [`Migration`]
+//! turning payloads of the previous version into payloads of the new one is
+//! appended to `MIGRATIONS`. Payloads of older versions are then migrated
+//! one version at a time up to the current one before being decoded:
+//!
+//! ```
+//! use drop::persist::{self, Migration, PersistError, Versioned};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize)]
+//! struct SettingsV1 {
+//!     port: u16,
+//! }
+//!
+//! #[derive(Debug, PartialEq, Deserialize, Serialize)]
+//! struct Settings {
+//!     port: u16,
+//!     name: String,
+//! }
+//!
+//! fn name_settings(payload: &[u8]) -> Result<Vec<u8>, PersistError> {
+//!     persist::upgrade(payload, |old: SettingsV1| Settings {
+//!         port: old.port,
+//!         name: String::from("unnamed"),
+//!     })
+//! }
+//!
+//! impl Versioned for Settings {
+//!     const MAGIC: [u8; 8] = *b"settings";
+//!     const CURRENT_VERSION: u16 = 2;
+//!     const MIGRATIONS: &'static [Migration] = &[name_settings];
+//! }
+//!
+//! let settings: Settings =
+//!     Settings::decode_any(1, &4000u16.to_le_bytes()).unwrap();
+//!
+//! assert_eq!(settings.name, "unnamed");
+//! ```
+//!
+//! [`bincode_options`]: crate::codec::bincode_options
+//! [`Versioned`]: self::Versioned
+//! [`Migration`]: self::Migration
+
+use std::{
+    borrow::Cow,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::codec::bincode_options;
+
+/// Number of bytes of the header preceding the payload of an artifact
+pub const HEADER_SIZE: usize = 8 + 2;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+/// Error encountered when persisting or loading an artifact
+pub enum PersistError {
+    #[snafu(display("i/o error on {}: {}", path.display(), source))]
+    /// The artifact could not be read or written
+    PersistIo {
+        /// Path of the artifact
+        path: PathBuf,
+        /// Underlying error cause
+        source: io::Error,
+    },
+
+    #[snafu(display("truncated header: {} bytes", len))]
+    /// The artifact is too short to hold a header
+    TruncatedHeader {
+        /// Size of the artifact
+        len: usize,
+    },
+
+    #[snafu(display(
+        "unknown magic {:?}, expected {:?}",
+        String::from_utf8_lossy(magic),
+        String::from_utf8_lossy(expected)
+    ))]
+    /// The artifact is not of the expected type
+    UnknownMagic {
+        /// Magic bytes of the artifact
+        magic: [u8; 8],
+        /// Magic bytes of the expected type
+        expected: [u8; 8],
+    },
+
+    #[snafu(display(
+        "version {} is newer than the current version {}",
+        version,
+        current
+    ))]
+    /// The artifact was written by a newer version of the format
+    FutureVersion {
+        /// Version of the artifact
+        version: u16,
+        /// Current version of the format
+        current: u16,
+    },
+
+    #[snafu(display(
+        "version {} is older than the oldest supported version {}",
+        version,
+        oldest
+    ))]
+    /// The artifact was written by a version of the format that can not be
+    /// migrated anymore
+    ObsoleteVersion {
+        /// Version of the artifact
+        version: u16,
+        /// Oldest version that can be migrated
+        oldest: u16,
+    },
+
+    #[snafu(display("failed to encode payload: {}", source))]
+    /// The payload could not be encoded
+    EncodePayload {
+        /// Underlying error cause
+        source: bincode::Error,
+    },
+
+    #[snafu(display("failed to migrate payload: {}", source))]
+    /// A payload could not be migrated to the next version
+    MigratePayload {
+        /// Underlying error cause
+        source: bincode::Error,
+    },
+
+    #[snafu(display("corrupted payload of version {}: {}", version, source))]
+    /// The payload could not be decoded
+    DecodePayload {
+        /// Version of the payload
+        version: u16,
+        /// Underlying error cause
+        source: bincode::Error,
+    },
+}
+
+/// A function turning a payload of some version into a payload of the next
+/// version, see [`Versioned::MIGRATIONS`]
+///
+/// [`Versioned::MIGRATIONS`]: self::Versioned::MIGRATIONS
+pub type Migration = fn(&[u8]) -> Result<Vec<u8>, PersistError>;
+
+/// A type that is persisted using a versioned format, see the
+/// [module documentation] for the layout of artifacts and how to migrate
+/// them
+///
+/// [module documentation]: self
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Magic bytes identifying artifacts of this type, which must be unique
+    /// among all `Versioned` types
+    const MAGIC: [u8; 8];
+
+    /// Version of the format this type is encoded to
+    const CURRENT_VERSION: u16;
+
+    /// Migrations from older versions of the format, the last one turning
+    /// payloads of `CURRENT_VERSION - 1` into payloads of `CURRENT_VERSION`,
+    /// the one before it payloads of `CURRENT_VERSION - 2` into payloads of
+    /// `CURRENT_VERSION - 1` and so on
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    /// Oldest version of the format that can still be decoded
+    fn oldest_version() -> u16 {
+        Self::CURRENT_VERSION.saturating_sub(Self::MIGRATIONS.len() as u16)
+    }
+
+    /// Decode a payload of the given version, migrating it to the current
+    /// version first if it is older
+    fn decode_any(version: u16, payload: &[u8]) -> Result<Self, PersistError> {
+        let oldest = Self::oldest_version();
+
+        ensure!(
+            version <= Self::CURRENT_VERSION,
+            FutureVersion {
+                version,
+                current: Self::CURRENT_VERSION,
+            }
+        );
+        ensure!(version >= oldest, ObsoleteVersion { version, oldest });
+
+        let mut payload = Cow::Borrowed(payload);
+
+        for migration in &Self::MIGRATIONS[(version - oldest) as usize..] {
+            payload = Cow::Owned(migration(&payload)?);
+        }
+
+        bincode_options()
+            .deserialize(&payload)
+            .context(DecodePayload {
+                version: Self::CURRENT_VERSION,
+            })
+    }
+}
+
+/// Migrate a payload encoding an `O` into one encoding the `N` obtained by
+/// applying `f`, for use in a [`Migration`]
+///
+/// [`Migration`]: self::Migration
+pub fn upgrade<O, N, F>(payload: &[u8], f: F) -> Result<Vec<u8>, PersistError>
+where
+    O: DeserializeOwned,
+    N: Serialize,
+    F: FnOnce(O) -> N,
+{
+    let old = bincode_options()
+        .deserialize(payload)
+        .context(MigratePayload)?;
+
+    bincode_options().serialize(&f(old)).context(MigratePayload)
+}
+
+/// Encode `value` along with its header
+pub fn encode_versioned<T: Versioned>(
+    value: &T,
+) -> Result<Vec<u8>, PersistError> {
+    let mut output = Vec::with_capacity(HEADER_SIZE);
+
+    output.extend_from_slice(&T::MAGIC);
+    output.extend_from_slice(&T::CURRENT_VERSION.to_le_bytes());
+
+    bincode_options()
+        .serialize_into(&mut output, value)
+        .context(EncodePayload)?;
+
+    Ok(output)
+}
+
+/// Decode an artifact of type `T`, migrating it if it was written using an
+/// older version of the format
+pub fn decode_versioned<T: Versioned>(bytes: &[u8]) -> Result<T, PersistError> {
+    ensure!(
+        bytes.len() >= HEADER_SIZE,
+        TruncatedHeader { len: bytes.len() }
+    );
+
+    let (header, payload) = bytes.split_at(HEADER_SIZE);
+    let magic: [u8; 8] = header[..8].try_into().expect("8 bytes");
+    let version = u16::from_le_bytes([header[8], header[9]]);
+
+    ensure!(
+        magic == T::MAGIC,
+        UnknownMagic {
+            magic,
+            expected: T::MAGIC,
+        }
+    );
+
+    T::decode_any(version, payload)
+}
+
+/// Atomically write `value` along with its header to `path`, see
+/// `write_atomic`
+pub fn save_versioned<T, P>(path: P, value: &T) -> Result<(), PersistError>
+where
+    T: Versioned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = encode_versioned(value)?;
+
+    write_atomic(path, &bytes).context(PersistIo { path })
+}
+
+/// Load the artifact of type `T` stored at `path`, see `decode_versioned`
+pub fn load_versioned<T, P>(path: P) -> Result<T, PersistError>
+where
+    T: Versioned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let bytes = fs::read(path).context(PersistIo { path })?;
+
+    decode_versioned(&bytes)
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it to
+/// `path` once it is synced, so that `path` holds either its previous
+/// content or `contents` even if the process is interrupted. On unix the file
+/// is only readable and writable by its owner since it may hold secret keys.
+pub fn write_atomic<P: AsRef<Path>>(
+    path: P,
+    contents: &[u8],
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")
+    })?;
+    let mut temporary = name.to_owned();
+
+    temporary.push(".tmp");
+
+    let temporary = path.with_file_name(temporary);
+    let written = create_private(&temporary).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+
+    match written.and_then(|_| fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temporary);
+
+            Err(e)
+        }
+    }
+}
+
+/// Create or truncate the file at `path`, making sure only its owner can
+/// access it before anything is written to it
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        let file = options.open(path)?;
+
+        // the mode is only applied when the file is created, a temporary
+        // file left over by an interrupted write may have been more open
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+        Ok(file)
+    }
+
+    #[cfg(not(unix))]
+    options.open(path)
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time;
+use tracing::info;
+
+use crate::telemetry::targets;
+
+/// Time given to each component of a node to report its state when dumping
+/// it, components that take longer are reported as unavailable
+pub const DUMP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// State of one component of a node, if it could be inspected
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentSnapshot<T> {
+    /// The component reported its state in time
+    Available(T),
+    /// The component did not report its state within [`DUMP_TIMEOUT`],
+    /// usually because one of its locks is held
+    ///
+    /// [`DUMP_TIMEOUT`]: self::DUMP_TIMEOUT
+    Unavailable,
+}
+
+impl<T> ComponentSnapshot<T> {
+    /// Get the state of the component if it was available
+    pub fn available(&self) -> Option<&T> {
+        match self {
+            Self::Available(state) => Some(state),
+            Self::Unavailable => None,
+        }
+    }
+
+    /// Check whether the component reported its state
+    pub fn is_available(&self) -> bool {
+        matches!(self, Self::Available(_))
+    }
+}
+
+/// State of a running `SystemManager` obtained using `SystemHandle::dump`.
+/// Peers are keyed by the hexadecimal form of their `PublicKey` and maps are
+/// sorted so that two snapshots can be diffed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    /// Seconds since the unix epoch at which the snapshot was taken
+    pub taken_at: u64,
+    /// `Connection`s known for each peer
+    pub peers: ComponentSnapshot<BTreeMap<String, PeerSnapshot>>,
+    /// Outgoing queue of each peer of the `Sender`
+    pub sender: ComponentSnapshot<BTreeMap<String, QueueSnapshot>>,
+    /// Messages waiting to be processed
+    pub dispatch: ComponentSnapshot<DispatchSnapshot>,
+    /// Peers that committed protocol violations
+    pub scores: ComponentSnapshot<BTreeMap<String, ScoreSnapshot>>,
+    /// Tasks spawned by the `SystemManager`
+    pub tasks: ComponentSnapshot<TasksSnapshot>,
+}
+
+impl NodeSnapshot {
+    /// Serialize this snapshot as indented JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshots always serialize")
+    }
+
+    /// Emit this snapshot as an `INFO` event with target `drop::dump`
+    pub fn log(&self) {
+        info!(target: targets::DUMP, snapshot = %self.to_json(), "node state");
+    }
+}
+
+impl fmt::Display for NodeSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+/// `Connection`s known for a peer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerSnapshot {
+    /// Number of open `ConnectionRead`s from this peer
+    pub reads: usize,
+    /// Whether a `ConnectionWrite` to this peer was added to the `Sender`
+    pub writable: bool,
+}
+
+/// State of the outgoing queue of a peer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    /// Messages enqueued but not sent yet
+    pub queued: usize,
+    /// Size of the messages enqueued but not sent yet
+    pub queued_bytes: u64,
+    /// Messages sent successfully
+    pub sent: u64,
+    /// Messages that could not be sent
+    pub failed: u64,
+    /// Size of sent messages on the wire, without padding
+    pub bytes: u64,
+    /// Whether sending already failed, breaking the `ConnectionWrite`
+    pub broken: bool,
+    /// Milliseconds since the last message was sent, if any was
+    pub idle_ms: Option<u64>,
+    /// Messages held back until the peer catches up, see `Fairness`
+    pub held: usize,
+    /// Whether the peer is lagging behind broadcasts, see `Fairness`
+    pub lagging: bool,
+    /// Number of times the connection was re-established, see
+    /// `NetworkSender::with_reconnect`
+    pub reconnects: u64,
+}
+
+/// State of the queue between receiving agents and processing tasks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DispatchSnapshot {
+    /// Messages received but not processed yet
+    pub pending: usize,
+}
+
+/// Standing of a peer that committed protocol violations
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScoreSnapshot {
+    /// Current score of the peer
+    pub score: f64,
+    /// Whether the peer is banned
+    pub banned: bool,
+}
+
+/// Tasks spawned by a `SystemManager`, all zero once it was stopped
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TasksSnapshot {
+    /// Processing tasks that are still running
+    pub processing: usize,
+    /// `Listener` tasks that are still running
+    pub listeners: usize,
+    /// Whether new `Connection`s are still accepted
+    pub accepting: bool,
+}
+
+/// Get the current time as seconds since the unix epoch
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Wait at most [`DUMP_TIMEOUT`] for the state of a component
+///
+/// [`DUMP_TIMEOUT`]: self::DUMP_TIMEOUT
+pub(crate) async fn gather<T>(
+    state: impl Future<Output = T>,
+) -> ComponentSnapshot<T> {
+    time::timeout(DUMP_TIMEOUT, state)
+        .await
+        .map_or(ComponentSnapshot::Unavailable, ComponentSnapshot::Available)
+}
+
+/// Call `f` until it returns the state of a component, for at most
+/// [`DUMP_TIMEOUT`]. Used for components behind synchronous locks, `f` should
+/// only try to lock them.
+///
+/// [`DUMP_TIMEOUT`]: self::DUMP_TIMEOUT
+pub(crate) async fn poll<T, F>(mut f: F) -> ComponentSnapshot<T>
+where
+    F: FnMut() -> Option<T>,
+{
+    gather(async move {
+        loop {
+            if let Some(state) = f() {
+                return state;
+            }
+
+            time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let snapshot = NodeSnapshot {
+            taken_at: 1,
+            peers: ComponentSnapshot::Available(BTreeMap::from([(
+                "ab".to_string(),
+                PeerSnapshot {
+                    reads: 1,
+                    writable: true,
+                },
+            )])),
+            sender: ComponentSnapshot::Unavailable,
+            dispatch: ComponentSnapshot::Available(DispatchSnapshot {
+                pending: 3,
+            }),
+            scores: ComponentSnapshot::Available(BTreeMap::new()),
+            tasks: ComponentSnapshot::Unavailable,
+        };
+
+        let json = snapshot.to_json();
+
+        assert!(json.contains("\"unavailable\""), "bad format: {}", json);
+        assert_eq!(
+            serde_json::from_str::<NodeSnapshot>(&json).unwrap(),
+            snapshot
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use futures::Stream;
+use tokio::sync::watch;
+
+/// Default number of events retained by a `SystemManager` for consumers that
+/// subscribe late, see `ManagerConfig::error_retention`
+pub const DEFAULT_ERROR_RETENTION: usize = 256;
+
+/// An item of a [`Stream`] of events obtained from an [`EventLog`]
+///
+/// [`Stream`]: futures::Stream
+/// [`EventLog`]: self::EventLog
+#[derive(Debug)]
+pub enum LogEntry<T> {
+    /// An event along with its sequence number. Sequence numbers start at 0
+    /// and increase by one with each event.
+    Event {
+        /// Sequence number of this event, subscribing from `seq + 1` resumes
+        /// right after it
+        seq: u64,
+        /// The event itself
+        event: Arc<T>,
+    },
+    /// Events that were evicted from the log before they could be replayed
+    Gap {
+        /// Number of events that were missed
+        missed: u64,
+    },
+}
+
+impl<T> Clone for LogEntry<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Event { seq, event } => Self::Event {
+                seq: *seq,
+                event: event.clone(),
+            },
+            Self::Gap { missed } => Self::Gap { missed: *missed },
+        }
+    }
+}
+
+struct Retained<T> {
+    events: VecDeque<Arc<T>>,
+    /// Sequence number of the next event
+    next: u64,
+    capacity: usize,
+    closed: bool,
+}
+
+impl<T> Retained<T> {
+    /// Sequence number of the oldest retained event
+    fn first(&self) -> u64 {
+        self.next - self.events.len() as u64
+    }
+}
+
+/// A bounded log of the last events of a `SystemManager`, numbered in the
+/// order in which they happened. Consumers can subscribe from any sequence
+/// number to replay retained events before following new ones.
+pub(crate) struct EventLog<T> {
+    retained: Mutex<Retained<T>>,
+    /// Sequence number of the next event, changed whenever an event is added
+    /// or the log is closed
+    notify: watch::Sender<u64>,
+}
+
+impl<T> EventLog<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Create a log retaining the last `capacity` events
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            retained: Mutex::new(Retained {
+                events: VecDeque::new(),
+                next: 0,
+                capacity,
+                closed: false,
+            }),
+            notify: watch::channel(0).0,
+        }
+    }
+
+    /// Add an event to the log, evicting the oldest one if it is full
+    pub fn push(&self, event: T) {
+        let next = {
+            let mut retained = self.lock();
+
+            if retained.capacity > 0 {
+                if retained.events.len() == retained.capacity {
+                    retained.events.pop_front();
+                }
+
+                retained.events.push_back(Arc::new(event));
+            }
+
+            retained.next += 1;
+            retained.next
+        };
+
+        self.notify.send_replace(next);
+    }
+
+    /// Stop subscriptions once they replayed all the events
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.notify.send_modify(|_| ());
+    }
+
+    /// Sequence number that the next event will have
+    pub fn next_seq(&self) -> u64 {
+        self.lock().next
+    }
+
+    /// Get a `Stream` of the events with a sequence number of at least
+    /// `from`, starting with retained ones. Events missed because they were
+    /// evicted are reported using a single `LogEntry::Gap`.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        from: u64,
+    ) -> impl Stream<Item = LogEntry<T>> {
+        let log = self.clone();
+        let mut changes = self.notify.subscribe();
+
+        async_stream::stream! {
+            let mut cursor = from;
+
+            loop {
+                changes.borrow_and_update();
+
+                let (missed, events, closed) = log.read(cursor);
+
+                if missed > 0 {
+                    yield LogEntry::Gap { missed };
+                    cursor += missed;
+                }
+
+                let caught_up = events.is_empty();
+
+                for event in events {
+                    yield LogEntry::Event { seq: cursor, event };
+                    cursor += 1;
+                }
+
+                if caught_up && (closed || changes.changed().await.is_err()) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Get the retained events from `cursor` onwards along with the number
+    /// of events before them that were evicted
+    fn read(&self, cursor: u64) -> (u64, Vec<Arc<T>>, bool) {
+        let retained = self.lock();
+        let first = retained.first();
+        let missed = first.saturating_sub(cursor);
+        let skip = cursor.saturating_sub(first) as usize;
+        let events = retained.events.iter().skip(skip).cloned().collect();
+
+        (missed, events, retained.closed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Retained<T>> {
+        self.retained.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn events(entries: &[LogEntry<u64>]) -> Vec<(u64, u64)> {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                LogEntry::Event { seq, event } => Some((*seq, **event)),
+                LogEntry::Gap { .. } => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replay_then_live() {
+        let log = Arc::new(EventLog::new(8));
+
+        for i in 0..5 {
+            log.push(i * 10);
+        }
+
+        let mut stream = Box::pin(log.subscribe(2));
+        let mut received = Vec::new();
+
+        for _ in 0..3 {
+            received.push(stream.next().await.expect("no replay"));
+        }
+
+        assert_eq!(events(&received), vec![(2, 20), (3, 30), (4, 40)]);
+
+        let live = tokio::spawn(async move {
+            let mut received = Vec::new();
+
+            while let Some(entry) = stream.next().await {
+                received.push(entry);
+            }
+
+            received
+        });
+
+        log.push(50);
+        log.push(60);
+        log.close();
+
+        let received = live.await.expect("subscriber failed");
+
+        assert_eq!(events(&received), vec![(5, 50), (6, 60)]);
+    }
+
+    #[tokio::test]
+    async fn evicted_events_are_a_gap() {
+        let log = Arc::new(EventLog::new(3));
+
+        for i in 0..10 {
+            log.push(i);
+        }
+
+        log.close();
+
+        let received = log.subscribe(1).collect::<Vec<_>>().await;
+
+        assert!(matches!(received[0], LogEntry::Gap { missed: 6 }));
+        assert_eq!(events(&received), vec![(7, 7), (8, 8), (9, 9)]);
+        assert_eq!(received.len(), 4);
+
+        // a cursor inside the window has no gap
+        let received = log.subscribe(8).collect::<Vec<_>>().await;
+
+        assert_eq!(events(&received), vec![(8, 8), (9, 9)]);
+        assert_eq!(received.len(), 2);
+    }
+}
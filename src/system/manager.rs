@@ -1,21 +1,69 @@
-use std::{iter, marker::PhantomData, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    future::Future,
+    iter,
+    marker::PhantomData,
+    mem,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    task::Poll,
+    time::Duration,
+};
+
+use bincode::Options;
 
 use futures::{
+    future,
     stream::{self, FuturesUnordered, StreamExt},
     FutureExt as _,
 };
-use postage::{dispatch, mpsc, sink::Sink, stream::Stream};
-use snafu::OptionExt;
-use tokio::task::{self, JoinHandle};
-use tracing::{debug, debug_span, error, info, warn};
+use postage::{
+    dispatch, mpsc,
+    sink::{PollSend, Sink},
+    stream::Stream,
+};
+use snafu::{ensure, IntoError, OptionExt, ResultExt};
+use tokio::{
+    sync::{oneshot, watch, Notify},
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{debug, debug_span, error, info, info_span, warn, Level, Span};
 use tracing_futures::Instrument;
 
-use super::{sender::NetworkSender, Sampler, Sender, System};
+use super::{
+    dump::{
+        self, ComponentSnapshot, DispatchSnapshot, NodeSnapshot, PeerSnapshot,
+        TasksSnapshot,
+    },
+    events::{EventLog, LogEntry, DEFAULT_ERROR_RETENTION},
+    node::{Outcome, ShutdownReport, Stage},
+    reconnect::ReconnectPolicy,
+    replay::{Direction, ReplayRecorder},
+    schedule,
+    score::{
+        PeerScoreboard, ScoreConfig, Severity, Violation, ViolationAction,
+    },
+    sender::{
+        AckSender, Acked, BufferLimit, Fairness, NetworkSender, Redialer,
+        SenderError,
+    },
+    Sampler, Sender, System,
+};
 use crate::{
-    Message,
     async_trait,
-    crypto::key::exchange::PublicKey,
-    net::{Connection, ConnectionRead, ConnectionWrite},
+    codec::bincode_options,
+    crypto::{key::exchange::PublicKey, stream::DecryptError, BincodeError},
+    net::{
+        Connection, ConnectionLimits, ConnectionRead, ConnectionWrite,
+        Connector, CryptoPool, ListenerError, Pacer, ReceiveError,
+    },
+    telemetry::targets,
+    Message,
 };
 
 #[async_trait]
@@ -63,6 +111,12 @@ where
 
     /// Called periodically by the manager to start garbage collection by the `Processor`
     async fn garbage_collection(&self);
+
+    /// Called by the manager when shutting down, once messages that were
+    /// already received have been processed and before outgoing `Connection`s
+    /// are closed, allowing the `Processor` to flush its state. This does
+    /// nothing by default.
+    async fn shutdown(&self) {}
 }
 
 /// An asbtract `Handle` type that allows interacting with a `Processor` once it
@@ -80,41 +134,278 @@ pub trait Handle<I, O>: Send + Sync + Clone {
 
     /// Poll this `Handle` for delivery, returning immediately with `Ok(None)`
     /// if no message is available for delivery or `Ok(Some)` if a message is
-
     /// otherwise
     async fn try_deliver(&mut self) -> Result<Option<O>, Self::Error>;
 
+    /// Wait at most `timeout` for a message to be delivered, returning
+    /// `Ok(None)` if none was. <br />
+    /// The default implementation drops the future returned by `deliver` when
+    /// the timeout expires, so a message may be lost unless `deliver` is
+    /// cancel-safe. Implementors whose `deliver` is not should override this.
+    async fn deliver_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<O>, Self::Error>
+    where
+        O: Send,
+    {
+        match time::timeout(timeout, self.deliver()).await {
+            Ok(result) => result.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Deliver up to `max` messages that are available right away, without
+    /// waiting for more
+    async fn drain(&mut self, max: usize) -> Result<Vec<O>, Self::Error>
+    where
+        O: Send,
+    {
+        let mut messages = Vec::new();
+
+        while messages.len() < max {
+            match self.try_deliver().await? {
+                Some(message) => messages.push(message),
+                None => break,
+            }
+        }
+
+        Ok(messages)
+    }
+
     /// Starts broadcasting a message using this `Handle`
     async fn broadcast(&mut self, message: &I) -> Result<(), Self::Error>;
 }
 
+/// Configuration of a `SystemManager`
+#[derive(Clone, Debug, Default)]
+pub struct ManagerConfig {
+    crypto_threads: Option<usize>,
+    scoring: ScoreConfig,
+    limits: Option<Arc<ConnectionLimits>>,
+    buffer: Option<BufferLimit>,
+    fairness: Option<Fairness>,
+    error_retention: Option<usize>,
+    recorder: Option<ReplayRecorder>,
+    node_id: Option<String>,
+    redialer: Option<Redialer>,
+    reconnect_policy: Option<ReconnectPolicy>,
+    reconnect_pacer: Option<Pacer>,
+    health_addr: Option<SocketAddr>,
+}
+
+impl ManagerConfig {
+    /// Encrypt and decrypt messages on a pool of `threads` dedicated threads
+    /// instead of the async runtime that runs the `Processor`. Socket I/O
+    /// still happens on the async runtime and messages on each `Connection`
+    /// keep their order.
+    pub fn crypto_pool(mut self, threads: usize) -> Self {
+        self.crypto_threads = Some(threads);
+        self
+    }
+
+    /// Score protocol violations committed by peers using the given
+    /// `ScoreConfig` instead of the default one
+    pub fn scoring(mut self, config: ScoreConfig) -> Self {
+        self.scoring = config;
+        self
+    }
+
+    /// Apply the given `ConnectionLimits` to every `Connection` registered
+    /// by the `SystemManager` instead of the limits it came with. Overrides
+    /// set using `SystemHandle::set_peer_limits` take precedence.
+    pub fn limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = Some(Arc::new(limits));
+        self
+    }
+
+    /// Bound the total size of the messages queued for all peers using the
+    /// given `BufferLimit`, see `NetworkSender::queued_bytes`
+    pub fn send_buffer(mut self, limit: BufferLimit) -> Self {
+        self.buffer = Some(limit);
+        self
+    }
+
+    /// Pace broadcasts to peers that lag behind the others according to the
+    /// given `Fairness`
+    pub fn fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = Some(fairness);
+        self
+    }
+
+    /// Retain the last `count` errors so that they can be replayed using
+    /// `SystemHandle::errors_from`, instead of `DEFAULT_ERROR_RETENTION`
+    pub fn error_retention(mut self, count: usize) -> Self {
+        self.error_retention = Some(count);
+        self
+    }
+
+    /// Record every message handed to the `Processor` and every message it
+    /// sends using the given `ReplayRecorder`, so that the run can be
+    /// replayed offline
+    pub fn record(mut self, recorder: ReplayRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Re-establish broken connections to peers that have an entry in
+    /// `candidates` using `connector`, see `NetworkSender::with_reconnect`.
+    /// Messages received on re-established connections are processed like
+    /// any others, and the `SystemManager` keeps running while its peers
+    /// are being reconnected.
+    pub fn reconnect<C>(
+        mut self,
+        connector: C,
+        candidates: HashMap<PublicKey, C::Candidate>,
+    ) -> Self
+    where
+        C: Connector + 'static,
+        C::Candidate: Clone,
+    {
+        self.redialer = Some(Redialer::new(connector, candidates));
+        self
+    }
+
+    /// Reconnect to peers according to `policy` instead of the default
+    /// `ReconnectPolicy`, see `reconnect`
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = Some(policy);
+        self
+    }
+
+    /// Start the dials reconnecting to peers as allowed by `pacer`, see
+    /// `reconnect`. Passing the `Pacer` used to add peers, for instance
+    /// with `System::add_peers_paced`, bounds both kinds of dials together.
+    pub fn reconnect_pacer(mut self, pacer: Pacer) -> Self {
+        self.reconnect_pacer = Some(pacer);
+        self
+    }
+
+    /// Serve liveness and readiness probes on `addr` using a `HealthServer`
+    /// once the `SystemHandle` is turned into a `Node`, see `Node::start`
+    pub fn health_addr(mut self, addr: SocketAddr) -> Self {
+        self.health_addr = Some(addr);
+        self
+    }
+
+    /// Identify the node run by the `SystemManager` in every span and event
+    /// emitted by its tasks, to tell apart nodes running in the same process
+    pub fn node_id(mut self, id: impl Into<String>) -> Self {
+        self.node_id = Some(id.into());
+        self
+    }
+
+    /// The span the tasks of the `SystemManager` run in, which records the
+    /// node id if there is one. `Listener`s can be added to the `System`
+    /// inside this span to have their tasks identify the node as well.
+    pub fn span(&self) -> Span {
+        match &self.node_id {
+            Some(id) => {
+                info_span!(target: targets::MANAGER, "node", node = %id)
+            }
+            None => Span::current(),
+        }
+    }
+}
+
+/// `Stream` of `Connection`s accepted by the `Listener`s of a `System`
+type Incoming = Box<dyn futures::Stream<Item = Connection> + Send + Unpin>;
+
+/// A `Connection` waiting to be registered, along with the channel used to
+/// acknowledge registration if it was added using `SystemHandle`
+type Pending = (Connection, Option<oneshot::Sender<()>>);
+
+/// Tasks running the `Listener`s of a `System`
+type Listeners = Vec<JoinHandle<Result<(), ListenerError>>>;
+
 /// Handles sending and receiving messages from all known peers.
 /// Also forwards them to relevant destination for processing
 pub struct SystemManager<M: Message + 'static> {
     _m: PhantomData<M>,
+    config: ManagerConfig,
     reads: Vec<ConnectionRead>,
     writes: Vec<ConnectionWrite>,
+    /// Initiator of the current `Connection` to each peer
+    initiators: HashMap<PublicKey, Option<PublicKey>>,
     /// `Stream` of incoming `Connection`s
-    incoming: Box<dyn futures::Stream<Item = Connection> + Send + Unpin>,
+    incoming: Incoming,
+    /// Tasks running the `Listener`s that feed `incoming`
+    listeners: Listeners,
+    /// Serialized messages left unprocessed by a previous `SystemManager`
+    backlog: Vec<(PublicKey, Vec<u8>)>,
 }
 
 impl<M: Message + 'static> SystemManager<M> {
     /// Create a new `SystemManager` using some previously created `System`
-    pub fn new(mut system: System) -> Self {
-        debug!("creating manager");
+    pub fn new(system: System) -> Self {
+        Self::with_config(system, ManagerConfig::default())
+    }
 
-        let (reads, writes): (Vec<_>, Vec<_>) = system
-            .connections()
-            .into_iter()
-            .filter_map(|connection| connection.split())
-            .unzip();
+    /// Create a new `SystemManager` using some previously created `System`
+    /// and the given `ManagerConfig`
+    pub fn with_config(mut system: System, config: ManagerConfig) -> Self {
+        debug!(target: targets::MANAGER, "creating manager");
 
+        let connections = system.connections();
         let incoming = Box::new(system.peer_source());
+        let listeners = system.listeners();
+
+        Self::from_parts(connections, incoming, listeners, config)
+    }
+
+    /// Create a new `SystemManager` from the `Connection`s and `Listener`s
+    /// taken from a previous `SystemManager` using
+    /// `SystemHandle::into_parts`. Messages in the backlog of `reclaimed` are
+    /// decoded as `M` and processed before any other message from the same
+    /// peer, those that can't be decoded are reported as
+    /// `SystemError::UndecodableBacklog`.
+    pub fn from_reclaimed(reclaimed: ReclaimedSystem) -> Self {
+        let mut manager = Self::from_parts(
+            reclaimed.connections.into_values(),
+            reclaimed.incoming,
+            reclaimed.listeners,
+            reclaimed.config,
+        );
+
+        manager.backlog = reclaimed.backlog;
+
+        manager
+    }
+
+    fn from_parts<C>(
+        connections: C,
+        incoming: Incoming,
+        listeners: Listeners,
+        config: ManagerConfig,
+    ) -> Self
+    where
+        C: IntoIterator<Item = Connection>,
+    {
+        let connections = connections.into_iter().collect::<Vec<_>>();
+        let initiators = connections
+            .iter()
+            .filter_map(|c| Some((c.remote_key()?, c.initiator())))
+            .collect();
+
+        let (reads, writes): (Vec<_>, Vec<_>) = connections
+            .into_iter()
+            .filter_map(|mut connection| {
+                if let Some(limits) = &config.limits {
+                    connection.set_limits(limits.clone());
+                }
+
+                connection.split()
+            })
+            .unzip();
 
         Self {
+            config,
             reads,
             writes,
+            initiators,
             incoming,
+            listeners,
+            backlog: Vec::new(),
             _m: PhantomData,
         }
     }
@@ -132,6 +423,28 @@ impl<M: Message + 'static> SystemManager<M> {
     /// - `sampler`: A [`Sampler`] that probabilistic algorithms will use to sample the set of peers
     /// - `parallelism`: The maximum amount of messages that will be processed in parallel
     pub async fn run<S, P, O, I, H>(
+        self,
+        processor: P,
+        sampler: S,
+        parallelism: usize,
+    ) -> SystemHandle<P, NetworkSender<M>, I, O, M>
+    where
+        S: Sampler,
+        P: Processor<M, I, O, NetworkSender<M>, Handle = H> + 'static,
+        P::Error: 'static,
+        O: Send,
+        I: Send,
+        M: From<I>,
+        H: Handle<I, O>,
+    {
+        let span = self.config.span();
+
+        self.start(processor, sampler, parallelism)
+            .instrument(span)
+            .await
+    }
+
+    async fn start<S, P, O, I, H>(
         self,
         mut processor: P,
         sampler: S,
@@ -146,505 +459,4099 @@ impl<M: Message + 'static> SystemManager<M> {
         M: From<I>,
         H: Handle<I, O>,
     {
-        info!("beginning system setup");
+        info!(target: targets::MANAGER, "beginning system setup");
 
         let sampler = Arc::new(sampler);
-        let sender = Arc::new(NetworkSender::new(self.writes));
+        let peers = Arc::new(PeerCount::new(
+            &self.reads,
+            &self.writes,
+            self.initiators,
+        ));
+        let peers_add = peers.clone();
+        let shared_peers = peers.clone();
+        let pool = self.config.crypto_threads.map(CryptoPool::new);
+        let mut sender = NetworkSender::with_pool(self.writes, pool.clone());
+
+        if let Some(limit) = self.config.buffer {
+            sender = sender.with_buffer_limit(limit);
+        }
+
+        if let Some(fairness) = self.config.fairness {
+            sender = sender.with_fairness(fairness);
+        }
+
+        let recorder = self.config.recorder.clone();
+
+        if let Some(recorder) = recorder.clone() {
+            sender = sender.with_recorder(recorder);
+        }
+
+        let reconnected = match self.config.redialer.clone() {
+            Some(redialer) => {
+                sender = sender.with_redialer(redialer);
+
+                if let Some(policy) = self.config.reconnect_policy {
+                    sender = sender.with_reconnect_policy(policy);
+                }
+
+                if let Some(pacer) = self.config.reconnect_pacer.clone() {
+                    sender = sender.with_reconnect_pacer(pacer);
+                }
+
+                Some(sender.reconnected_reads())
+            }
+            None => None,
+        };
+
+        let sender = Arc::new(sender);
         let sender_add = sender.clone();
+        let shared_sender = sender.clone();
+        let (scoreboard, fired_rx) =
+            PeerScoreboard::new(self.config.scoring.clone());
+        let scoreboard_add = scoreboard.clone();
 
         let (user_connection_tx, user_connection_rx) = mpsc::channel(1);
-        let connection_input =
-            vec![self.incoming, Box::new(user_connection_rx)];
-        let mut incoming = stream::select_all(connection_input);
+        let mut incoming = stream::select(
+            self.incoming.map(|connection| (connection, None)),
+            user_connection_rx,
+        );
         let (msg_tx, msg_rx) = dispatch::channel(128);
-        let (error_tx, error_rx) = dispatch::channel(32);
+        let (mut error_tx, error_rx) = dispatch::channel(32);
+        let errors = Self::spawn_error_log(
+            error_rx,
+            self.config
+                .error_retention
+                .unwrap_or(DEFAULT_ERROR_RETENTION),
+        );
         let (mut connection_tx, connection_rx) = mpsc::channel(16);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (stop_incoming_tx, stop_incoming_rx) = oneshot::channel();
+        let (halt_tx, halt_rx) = watch::channel(false);
+        let (agents_tx, agents_rx) = watch::channel(false);
+        let pending = Arc::new(AtomicUsize::new(0));
+        let shared_pending = pending.clone();
+        let limits = Arc::new(PeerLimits::new(self.config.limits.clone()));
+        let shared_limits = limits.clone();
+        let health_addr = self.config.health_addr;
+        let limits_add = limits.clone();
+        let inflight = Arc::new(InFlight::default());
 
         let perr_tx = error_tx.clone();
 
-        let handles = Self::spawn_network_agents(self.reads, msg_tx.clone())
+        let dispatch = Dispatch {
+            pool,
+            sink: msg_tx,
+            pending: pending.clone(),
+            stop: agents_rx,
+            scoreboard: scoreboard.clone(),
+            limits,
+            inflight: inflight.clone(),
+        };
+
+        let mut backlog = Self::decode_backlog(self.backlog, &mut error_tx);
+
+        let handles = self
+            .reads
+            .into_iter()
+            .map(|read| {
+                let queued = backlog.remove(read.remote_pkey());
+
+                NetworkAgent::new(read, dispatch.clone())
+                    .with_backlog(queued.unwrap_or_default())
+                    .spawn()
+            })
             .collect::<FuturesUnordered<_>>();
 
-        Self::spawn_disconnect_watcher::<P, _, _, _, _>(
+        Self::spawn_backlog(backlog, dispatch.clone());
+
+        let watcher = Self::spawn_disconnect_watcher::<_, _, _, _>(
             handles,
-            msg_tx,
+            peers,
+            dispatch,
+            agents_tx,
             error_tx.clone(),
             connection_rx,
+            reconnected,
+            fired_rx,
+            sender.clone(),
+            stop_rx,
         );
+        let sender_close = sender.clone();
 
         let handle = processor.setup(sampler, sender.clone()).await;
         let processor = Arc::new(processor);
 
-        debug!("setting up processing tasks...");
+        debug!(target: targets::MANAGER, "setting up processing tasks...");
 
-        (0..parallelism)
-            .zip(iter::repeat((processor.clone(), msg_rx, sender, perr_tx)))
-            .map(|(idx, (processor, mut msg_rx, sender, mut err_tx))| {
+        let processing = (0..parallelism)
+            .zip(iter::repeat((processor.clone(), msg_rx.clone(), sender, perr_tx, pending.clone(), inflight, halt_rx, recorder)))
+            .map(|(idx, (processor, mut msg_rx, sender, mut err_tx, pending, inflight, mut halt, recorder))| {
                 task::spawn(async move {
-                    while let Some((pkey, message)) = msg_rx.recv().await {
-                        debug!("starting processing for {:?} from {}", message, pkey);
+                    loop {
+                        let next = futures::select! {
+                            next = msg_rx.recv().fuse() => next,
+                            _ = signaled(&mut halt).fuse() => {
+                                debug!(
+                                    target: targets::MANAGER,
+                                    "message processing halted"
+                                );
+                                return;
+                            }
+                        };
+
+                        let Some((pkey, message)) = next else {
+                            break;
+                        };
+
+                        async {
+                            debug!(
+                                target: targets::MANAGER,
+                                "starting processing for {:?}", message
+                            );
 
-                        if let Err(e) = processor.process(message, pkey, sender.clone()).await {
-                            error!("failed to process message: {}", e);
+                            let rendered = tracing::enabled!(target: targets::MANAGER, Level::DEBUG)
+                                .then(|| render_message(&message));
 
-                            let error = SystemError::ProcessorError { source: e };
+                            if let Some(recorder) = &recorder {
+                                recorder.record(Direction::Inbound, &pkey, &message);
+                            }
 
-                            let _ = err_tx.send(error).await;
+                            if let Err(e) = processor.process(message, pkey, sender.clone()).await {
+                                error!(
+                                    target: targets::MANAGER,
+                                    "failed to process message: {}", e
+                                );
+
+                                let error = SystemError::ProcessorError {
+                                    from: pkey,
+                                    message: rendered,
+                                    source: e,
+                                };
+
+                                let _ = err_tx.send(error).await;
+                            }
                         }
+                        .instrument(debug_span!(
+                            target: targets::MANAGER,
+                            "process_message",
+                            from = %pkey
+                        ))
+                        .await;
+
+                        inflight.release(&pkey);
+                        pending.fetch_sub(1, Ordering::AcqRel);
                     }
 
-                    warn!("message processing ending after all network agents closed");
-                }.instrument(debug_span!("process_task", idx=%idx)))
-            }).for_each(drop); // we want to process the whole iterator but not keep the handles
+                    warn!(
+                        target: targets::MANAGER,
+                        "message processing ending after all network agents closed"
+                    );
+                }.instrument(debug_span!(
+                    target: targets::MANAGER,
+                    "process_task",
+                    idx = %idx
+                )))
+            }).collect::<FuturesUnordered<_>>();
+
+        let mut stop_incoming = stop_incoming_rx.fuse();
 
         // spawn new connection handler
-        task::spawn(async move {
-            while let Some(connection) = incoming.next().await {
-                if let Some((read, write)) = connection.split() {
+        let incoming = spawn_in_span(async move {
+            loop {
+                let (mut connection, ack): Pending = futures::select! {
+                    connection = incoming.next() => match connection {
+                        Some(connection) => connection,
+                        None => break,
+                    },
+                    _ = stop_incoming => break,
+                };
+
+                let initiator = connection.initiator();
+
+                if let Some(limits) = connection
+                    .remote_key()
+                    .and_then(|pkey| limits_add.get(&pkey))
+                {
+                    connection.set_limits(limits);
+                }
+
+                if let Some((read, mut write)) = connection.split() {
+                    let remote = *write.remote_pkey();
+
                     info!(
-                        "new incoming connection from {}",
-                        write.remote_pkey()
+                        target: targets::MANAGER,
+                        "new incoming connection from {}", remote
                     );
-                    sender_add.add_connection(write).await;
+
+                    if !scoreboard_add.admit(&remote) {
+                        info!(
+                            target: targets::MANAGER,
+                            "refusing connection from banned {}", remote
+                        );
+
+                        if let Err(e) = write.close().await {
+                            debug!(
+                                target: targets::MANAGER,
+                                "failed to close connection: {}", e
+                            );
+                        }
+
+                        if let Some(ack) = ack {
+                            let _ = ack.send(());
+                        }
+
+                        continue;
+                    }
+
+                    if peers_add.replace(remote, initiator) {
+                        // peers must only be reported as ready once they
+                        // can be reached through the sender
+                        sender_add.add_connection(write).await;
+                        schedule::interleave().await;
+                        peers_add.write_added(remote);
+                    } else {
+                        debug!(
+                            target: targets::MANAGER,
+                            "closing duplicate connection to {}", remote
+                        );
+
+                        // the remote peer may still be sending on this
+                        // connection so the read end stays open until the
+                        // remote closes it
+                        if let Err(e) = write.close().await {
+                            debug!(
+                                target: targets::MANAGER,
+                                "failed to close connection: {}", e
+                            );
+                        }
+                    }
 
                     let _ = connection_tx.send(read).await;
                 }
+
+                // the peer is now reachable, either through this connection
+                // or through the one that was kept
+                if let Some(ack) = ack {
+                    let _ = ack.send(());
+                }
             }
+
+            incoming.into_inner().0.into_inner()
         });
 
-        info!("done setting up! system now running");
+        info!(target: targets::MANAGER, "done setting up! system now running");
+
+        let tasks = ManagerTasks {
+            config: self.config,
+            listeners: self.listeners,
+            incoming: Some(incoming),
+            stop_incoming: Some(stop_incoming_tx),
+            watcher: Some(watcher),
+            stop: Some(stop_tx),
+            processing,
+            halt: halt_tx,
+            queued: msg_rx,
+            pending,
+            stopped: Leftovers::default(),
+            sender: sender_close,
+        };
 
-        SystemHandle::new(processor, handle, user_connection_tx, error_rx)
+        let shared = Shared {
+            peers: shared_peers,
+            sender: shared_sender,
+            pending: shared_pending,
+            limits: shared_limits,
+            health_addr,
+        };
+
+        SystemHandle::new(
+            processor,
+            handle,
+            user_connection_tx,
+            errors,
+            scoreboard,
+            tasks,
+            shared,
+        )
     }
 
-    fn spawn_network_agents<I, S>(
-        reads: I,
-        sink: S,
-    ) -> impl Iterator<Item = JoinHandle<PublicKey>>
+    /// Record the errors received on `error_rx` in an `EventLog` retaining
+    /// the last `retention` of them
+    fn spawn_error_log<ER>(
+        mut error_rx: dispatch::Receiver<SystemError<ER>>,
+        retention: usize,
+    ) -> Arc<EventLog<SystemError<ER>>>
     where
-        I: IntoIterator<Item = ConnectionRead>,
-        S: Sink<Item = (PublicKey, M)> + Send + Clone + Sync + Unpin + 'static,
+        ER: std::error::Error + Send + Sync + 'static,
     {
-        debug!("spawning networking agents...");
+        let log = Arc::new(EventLog::new(retention));
+        let recorder = log.clone();
 
-        reads
-            .into_iter()
-            .zip(iter::repeat(sink))
-            .map(|(read, tx)| Self::spawn_receive_agent(read, tx))
+        spawn_in_span(async move {
+            while let Some(error) = error_rx.recv().await {
+                recorder.push(error);
+            }
+
+            recorder.close();
+        });
+
+        log
     }
 
-    fn spawn_disconnect_watcher<P, E, D, R, ER>(
-        mut receivers: FuturesUnordered<JoinHandle<PublicKey>>,
-        msg_dispatch: D,
-        mut error_tx: E,
-        mut connection_rx: R,
+    /// Decode the messages left over by a previous `SystemManager`, grouping
+    /// them by sender. Messages that can't be decoded are reported on
+    /// `error_tx`.
+    fn decode_backlog<E, ER>(
+        backlog: Vec<(PublicKey, Vec<u8>)>,
+        error_tx: &mut E,
+    ) -> HashMap<PublicKey, VecDeque<M>>
+    where
+        ER: std::error::Error + Send + Sync + 'static,
+        E: Sink<Item = SystemError<ER>> + Unpin,
+    {
+        let mut decoded = HashMap::<_, VecDeque<_>>::new();
+
+        for (from, payload) in backlog {
+            match bincode_options().deserialize(&payload) {
+                Ok(message) => {
+                    decoded.entry(from).or_default().push_back(message)
+                }
+                Err(source) => {
+                    error!(
+                        target: targets::MANAGER,
+                        "failed to decode message from {}: {}",
+                        from, source
+                    );
+
+                    let error = UndecodableBacklog { from }.into_error(source);
+
+                    if error_tx.try_send(error).is_err() {
+                        error!(
+                            target: targets::MANAGER,
+                            "error channel full, some errors were lost"
+                        );
+                    }
+                }
+            }
+        }
+
+        decoded
+    }
+
+    /// Hand the messages left over by a previous `SystemManager` from peers
+    /// that are no longer connected over to the processing tasks
+    fn spawn_backlog<S>(
+        backlog: HashMap<PublicKey, VecDeque<M>>,
+        mut dispatch: Dispatch<S>,
     ) where
+        S: Sink<Item = (PublicKey, M)> + Send + Sync + Unpin + 'static,
+    {
+        if backlog.is_empty() {
+            return;
+        }
+
+        spawn_in_span(async move {
+            let messages = backlog.into_iter().flat_map(|(from, messages)| {
+                messages.into_iter().map(move |message| (from, message))
+            });
+
+            for message in messages {
+                dispatch.pending.fetch_add(1, Ordering::AcqRel);
+
+                if dispatch.sink.send(message).await.is_err() {
+                    dispatch.pending.fetch_sub(1, Ordering::AcqRel);
+                }
+            }
+        });
+    }
+
+    /// Watch for disconnections. `peers` holds the number of open
+    /// `Connection`s to each peer, duplicate `Connection`s are kept open until
+    /// the remote peer is done sending on them. Actions fired by the
+    /// `PeerScoreboard` are reported and peers that must be disconnected are
+    /// removed from the `NetworkSender`. Connections re-established by the
+    /// `NetworkSender` are received from `reconnected`, and the watcher keeps
+    /// running without any receiving agent as long as there may be some.
+    /// Once `stop` fires all receiving agents are stopped using `agents` and
+    /// the receiving ends they were using are returned.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_disconnect_watcher<E, D, R, ER>(
+        mut receivers: FuturesUnordered<JoinHandle<Exit<M>>>,
+        peers: Arc<PeerCount>,
+        dispatch: Dispatch<D>,
+        agents: watch::Sender<bool>,
+        mut error_tx: E,
+        connection_rx: R,
+        mut reconnected: Option<
+            tokio::sync::mpsc::UnboundedReceiver<ConnectionRead>,
+        >,
+        mut fired: tokio::sync::mpsc::UnboundedReceiver<Violation>,
+        sender: Arc<NetworkSender<M>>,
+        stop: oneshot::Receiver<()>,
+    ) -> JoinHandle<Leftovers<M>>
+    where
         ER: std::error::Error + Send + Sync + 'static,
         E: Sink<Item = SystemError<ER>> + Send + Unpin + 'static,
         D: Sink<Item = (PublicKey, M)> + Clone + Sync + Send + Unpin + 'static,
         R: Stream<Item = ConnectionRead> + Send + Unpin + 'static,
     {
-        debug!("spawning disconnect watcher...");
+        debug!(target: targets::MANAGER, "spawning disconnect watcher...");
 
-        task::spawn(async move {
-            while !receivers.is_empty() {
+        spawn_in_span(async move {
+            let mut stop = stop.fuse();
+            // stop polling for new connections once the handler has exited
+            let mut connection_rx = Some(connection_rx);
+
+            // a system without initial peers waits for its first connection
+            if receivers.is_empty() && reconnected.is_none() {
+                futures::select! {
+                    read = next_connection(&mut connection_rx).fuse() => {
+                        if let Some(read) = read {
+                            peers.read_opened(*read.remote_pkey());
+
+                            receivers.push(
+                                NetworkAgent::new(read, dispatch.clone())
+                                    .spawn(),
+                            );
+                        }
+                    }
+                    _ = stop => {
+                        return Leftovers::collect(receivers, connection_rx).await;
+                    }
+                }
+            }
+
+            while !receivers.is_empty() || reconnected.is_some() {
                 futures::select! {
                     // new connection to be added to list of receivers
-                    read = connection_rx.recv().fuse() => {
+                    read = next_connection(&mut connection_rx).fuse() => {
 
                         if let Some(read) = read {
-                            debug!("new incoming connection");
+                            debug!(
+                                target: targets::MANAGER,
+                                "new incoming connection"
+                            );
+
+                            peers.read_opened(*read.remote_pkey());
+
+                            receivers.push(NetworkAgent::new(read, dispatch.clone()).spawn());
+                        } else {
+                            connection_rx = None;
+                        }
+                    }
+                    // connection re-established by the sender
+                    read = next_reconnected(&mut reconnected).fuse() => {
+                        if let Some(read) = read {
+                            let pkey = *read.remote_pkey();
+
+                            debug!(
+                                target: targets::MANAGER,
+                                "connection to {} re-established", pkey
+                            );
 
-                            receivers.push(NetworkAgent::new(read, msg_dispatch.clone()).spawn());
+                            peers.read_opened(pkey);
+                            peers.write_added(pkey);
+
+                            receivers.push(NetworkAgent::new(read, dispatch.clone()).spawn());
+                        } else {
+                            reconnected = None;
                         }
                     }
                     // disconnection notice
-                    pkey = receivers.next() => {
-                        let pkey = pkey.unwrap().unwrap();
+                    exit = next_exit(&mut receivers).fuse() => {
+                        let Departure { pkey, clean } = match exit.unwrap() {
+                            Exit::Departed(departure) => departure,
+                            Exit::Stopped { .. } => unreachable!("agent stopped early"),
+                        };
+
+                        if !peers.read_closed(pkey) {
+                            debug!(
+                                target: targets::MANAGER,
+                                "duplicate connection to {} closed", pkey
+                            );
+                            continue;
+                        }
+
+                        let notice = if clean {
+                            Closed { pkey }.build()
+                        } else {
+                            Disconnected { pkey }.build()
+                        };
+
+                        if error_tx.send(notice).await.is_err() {
+                            error!(
+                                target: targets::MANAGER,
+                                "error handle dropped too early some errors were lost"
+                            );
+                        }
+                    }
+                    // threshold action fired by the scoreboard
+                    violation = fired.recv().fuse() => {
+                        let Some(Violation { pkey, score, action }) = violation else {
+                            continue;
+                        };
+
+                        if action >= ViolationAction::Disconnect {
+                            sender.remove_connection(&pkey).await;
+                            peers.forget(&pkey);
+                        }
+
+                        let notice = PeerViolation { pkey, score, action }.build();
+
+                        if error_tx.send(notice).await.is_err() {
+                            error!(
+                                target: targets::MANAGER,
+                                "error handle dropped too early some errors were lost"
+                            );
+                        }
+                    }
+                    _ = stop => {
+                        debug!(
+                            target: targets::MANAGER,
+                            "stopping {} network agents", receivers.len()
+                        );
+
+                        let _ = agents.send(true);
 
+                        let mut leftovers =
+                            Leftovers::collect(receivers, connection_rx).await;
 
-                        if error_tx.send(Disconnected { pkey }.build()).await.is_err() {
-                            error!("error handle dropped too early some errors were lost");
+                        if let Some(mut reconnected) = reconnected {
+                            while let Ok(read) = reconnected.try_recv() {
+                                leftovers.reads.push(read);
+                            }
                         }
+
+                        return leftovers;
                     }
                 }
             }
-        });
+
+            Leftovers::default()
+        })
     }
+}
 
-    fn spawn_receive_agent<S>(
-        connection: ConnectionRead,
-        tx: S,
-    ) -> JoinHandle<PublicKey>
-    where
-        S: Sink<Item = (PublicKey, M)> + Send + Sync + Unpin + 'static,
-    {
-        NetworkAgent::new(connection, tx).spawn()
+/// Wait until `signal` is set, never completing if its sender is dropped
+/// without setting it
+async fn signaled(signal: &mut watch::Receiver<bool>) {
+    if signal.wait_for(|set| *set).await.is_err() {
+        future::pending::<()>().await;
     }
 }
 
-#[derive(Debug, snafu::Snafu)]
-/// Errors encountered by [`SystemHandle`]
-///
-/// [`SystemHandle`]: self::SystemHandle
-pub enum SystemError<E: std::error::Error + Send + Sync + 'static> {
-    #[snafu(display("unauthenticated connection"))]
-    /// User tried to add an unauthenticated connection
-    Unauthenticated,
-    #[snafu(display("remote peer {} disconnected", pkey))]
-    /// A connection error caused a remote peer to be disconnected
-    Disconnected {
-        /// Peer's PublicKey
-        pkey: PublicKey,
-    },
-    #[snafu(display("processor error: {}", source))]
-    /// Processor encountered an error
-    ProcessorError {
-        /// Error source
-        source: E,
-    },
-    #[snafu(display("connection channel is closed"))]
-    /// Connection channel was closed and the connection could not be added.
-    /// Adding further connections will not work either
-    Channel,
+/// Spawn `future` inside the current span, so that the tasks of a
+/// `SystemManager` all record the id of its node
+fn spawn_in_span<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    task::spawn(future.in_current_span())
 }
 
-/// This is handle used to interact with a [`SystemManager`] and the [`Processor`]
-/// running on that [`SystemManager`]
-///
-/// [`Processor`]: self::Processor
-/// [`SystemManager`]: self::SystemManager
-#[derive(Clone)]
-pub struct SystemHandle<P, S, I, O, M>
+/// Receive the next `ConnectionRead` from `connection_rx`, never completing
+/// once the channel has been closed
+async fn next_connection<R>(connection_rx: &mut Option<R>) -> Option<R::Item>
 where
-    P: Processor<M, I, O, S>,
-    P::Error: Send + Sync + 'static,
-    O: Send,
-    M: Message + From<I> + 'static,
-    S: Sender<M>,
+    R: Stream + Unpin,
 {
-    inner: P::Handle,
-    processor: Arc<P>,
-    connections: mpsc::Sender<Connection>,
-    error_rx: Option<dispatch::Receiver<SystemError<P::Error>>>,
-    _i: PhantomData<I>,
-    _o: PhantomData<O>,
+    match connection_rx {
+        Some(rx) => rx.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// Wait for the next receiving agent in `receivers` to exit, never
+/// completing while there are none
+async fn next_exit<M: Message + 'static>(
+    receivers: &mut FuturesUnordered<JoinHandle<Exit<M>>>,
+) -> Result<Exit<M>, task::JoinError> {
+    match receivers.next().await {
+        Some(exit) => exit,
+        None => future::pending().await,
+    }
+}
+
+/// Receive the next `ConnectionRead` re-established by the `NetworkSender`,
+/// never completing if it does not reconnect peers
+async fn next_reconnected(
+    reconnected: &mut Option<
+        tokio::sync::mpsc::UnboundedReceiver<ConnectionRead>,
+    >,
+) -> Option<ConnectionRead> {
+    match reconnected {
+        Some(rx) => rx.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// Tasks spawned by `SystemManager::run`, kept so that a `Node` can stop
+/// them in order when shutting down
+pub(crate) struct ManagerTasks<M: Message + 'static> {
+    config: ManagerConfig,
+    listeners: Listeners,
+    incoming: Option<JoinHandle<Incoming>>,
+    /// Stops the incoming connection handler without interrupting it
+    stop_incoming: Option<oneshot::Sender<()>>,
+    watcher: Option<JoinHandle<Leftovers<M>>>,
+    stop: Option<oneshot::Sender<()>>,
+    processing: FuturesUnordered<JoinHandle<()>>,
+    /// Makes processing tasks exit once done with their current message
+    halt: watch::Sender<bool>,
+    /// Messages received but not yet taken by a processing task
+    queued: dispatch::Receiver<(PublicKey, M)>,
+    /// Number of messages received but not yet processed
+    pending: Arc<AtomicUsize>,
+    /// What was left by receiving agents once stopped
+    stopped: Leftovers<M>,
+    sender: Arc<NetworkSender<M>>,
+}
+
+/// Receiving ends and messages recovered from stopped `NetworkAgent`s
+pub(crate) struct Leftovers<M> {
+    reads: Vec<ConnectionRead>,
+    /// Messages that were received but not handed over to processing tasks
+    undelivered: Vec<(PublicKey, M)>,
+}
+
+impl<M> Default for Leftovers<M> {
+    fn default() -> Self {
+        Self {
+            reads: Vec::new(),
+            undelivered: Vec::new(),
+        }
+    }
+}
+
+impl<M: Message + 'static> Leftovers<M> {
+    /// Wait for all `receivers` to stop and take `ConnectionRead`s that were
+    /// not yet given to an agent from `connection_rx`
+    async fn collect<R>(
+        mut receivers: FuturesUnordered<JoinHandle<Exit<M>>>,
+        connection_rx: Option<R>,
+    ) -> Self
+    where
+        R: Stream<Item = ConnectionRead> + Unpin,
+    {
+        let mut stopped = Self::default();
+
+        while let Some(exit) = receivers.next().await {
+            match exit {
+                Ok(Exit::Stopped { read, undelivered }) => {
+                    let from = *read.remote_pkey();
+
+                    stopped.reads.push(*read);
+                    stopped
+                        .undelivered
+                        .extend(undelivered.into_iter().map(|m| (from, m)));
+                }
+                Ok(Exit::Departed(departure)) => {
+                    debug!(
+                        target: targets::MANAGER,
+                        "{} departed while stopping", departure.pkey
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        target: targets::MANAGER,
+                        "network agent failed: {}", e
+                    )
+                }
+            }
+        }
+
+        if let Some(mut connection_rx) = connection_rx {
+            while let Ok(read) = connection_rx.try_recv() {
+                stopped.reads.push(read);
+            }
+        }
+
+        stopped
+    }
+}
+
+/// Result of draining the processing tasks of a `SystemManager`
+pub(crate) struct Drained {
+    /// Number of incoming `Connection`s that were still open
+    pub(crate) connections: usize,
+    /// Whether processing did not finish within the grace period
+    pub(crate) timed_out: bool,
+}
+
+/// Result of joining the tasks of a `SystemManager`
+pub(crate) struct Joined {
+    /// Number of received messages that were never processed
+    pub(crate) dropped: usize,
+    /// Number of processing tasks that panicked
+    pub(crate) panicked: usize,
+}
+
+impl<M: Message + 'static> ManagerTasks<M> {
+    /// Count the tasks that are still running
+    fn snapshot(&self) -> TasksSnapshot {
+        TasksSnapshot {
+            processing: self
+                .processing
+                .iter()
+                .filter(|task| !task.is_finished())
+                .count(),
+            listeners: self
+                .listeners
+                .iter()
+                .filter(|task| !task.is_finished())
+                .count(),
+            accepting: self
+                .incoming
+                .as_ref()
+                .is_some_and(|task| !task.is_finished()),
+        }
+    }
+
+    /// Stop accepting new `Connection`s. The incoming connection handler is
+    /// signaled so that it finishes adding the `Connection` it is handling.
+    /// Returns the number of `Listener`s that were stopped.
+    pub(crate) async fn stop_listeners(&mut self) -> usize {
+        let count = self.listeners.len();
+
+        for listener in self.listeners.drain(..) {
+            listener.abort();
+            let _ = listener.await;
+        }
+
+        if let Some(incoming) = self.incoming.take() {
+            let stopped = self
+                .stop_incoming
+                .take()
+                .is_some_and(|stop| stop.send(()).is_ok());
+
+            if !stopped {
+                incoming.abort();
+            }
+
+            let _ = incoming.await;
+        }
+
+        count
+    }
+
+    /// Stop all receiving agents, keeping what they leave behind
+    async fn stop_receiving(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+
+        if let Some(watcher) = self.watcher.take() {
+            match watcher.await {
+                Ok(stopped) => self.stopped = stopped,
+                Err(e) => {
+                    error!(
+                        target: targets::MANAGER,
+                        "disconnect watcher failed: {}", e
+                    )
+                }
+            }
+        }
+    }
+
+    /// Wait at most `grace` for processing tasks to process messages that
+    /// were already received. Returns `true` if they did not finish in time.
+    async fn finish_processing(&mut self, grace: Duration) -> bool {
+        let processing = &mut self.processing;
+        let timed_out = time::timeout(grace, async move {
+            while processing.next().await.is_some() {}
+        })
+        .await
+        .is_err();
+
+        if timed_out {
+            warn!(
+                target: targets::MANAGER,
+                "processing did not finish within {:?}", grace
+            );
+        }
+
+        timed_out
+    }
+
+    /// Stop receiving messages and give the processing tasks at most `grace`
+    /// to process messages that were already received
+    pub(crate) async fn drain(&mut self, grace: Duration) -> Drained {
+        self.stop_receiving().await;
+
+        let connections = self.stopped.reads.len();
+        let timed_out = self.finish_processing(grace).await;
+
+        Drained {
+            connections,
+            timed_out,
+        }
+    }
+
+    /// Send all queued messages and close outgoing `Connection`s. Returns the
+    /// number of `Connection`s that were closed.
+    pub(crate) async fn close_senders(&self) -> usize {
+        self.sender.close().await
+    }
+
+    /// Abort processing tasks that are still running and wait for all of
+    /// them to exit
+    pub(crate) async fn join(mut self) -> Joined {
+        self.processing.iter().for_each(JoinHandle::abort);
+
+        let mut panicked = 0;
+
+        while let Some(result) = self.processing.next().await {
+            if matches!(result, Err(ref e) if e.is_panic()) {
+                panicked += 1;
+            }
+        }
+
+        Joined {
+            dropped: self.pending.load(Ordering::Acquire)
+                + self.stopped.undelivered.len(),
+            panicked,
+        }
+    }
+
+    /// Stop everything without closing any `Connection`, see
+    /// `SystemHandle::into_parts`
+    async fn reclaim(
+        mut self,
+        drain: Duration,
+    ) -> Result<ReclaimedSystem, ShutdownError> {
+        let incoming = match self.incoming.take() {
+            Some(incoming) => {
+                if let Some(stop) = self.stop_incoming.take() {
+                    let _ = stop.send(());
+                }
+
+                incoming.await.ok()
+            }
+            None => None,
+        };
+
+        self.stop_receiving().await;
+
+        if self.finish_processing(drain).await {
+            // messages being processed are never interrupted
+            let _ = self.halt.send(true);
+
+            while self.processing.next().await.is_some() {}
+        }
+
+        let mut backlog = Vec::new();
+
+        while let Ok(queued) = self.queued.try_recv() {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            backlog.push(queued);
+        }
+
+        backlog.append(&mut self.stopped.undelivered);
+
+        let backlog = backlog
+            .into_iter()
+            .map(|(from, message)| {
+                bincode_options()
+                    .serialize(&message)
+                    .map(|payload| (from, payload))
+                    .context(Encode { from })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut writes = self
+            .sender
+            .reclaim()
+            .await
+            .into_iter()
+            .map(|write| (*write.remote_pkey(), write))
+            .collect::<HashMap<_, _>>();
+
+        let connections = self
+            .stopped
+            .reads
+            .drain(..)
+            .filter_map(|read| {
+                let pkey = *read.remote_pkey();
+                let write = writes.remove(&pkey)?;
+
+                if read.is_pair_of(&write) {
+                    Some((pkey, read.unsplit(write)))
+                } else {
+                    // duplicate connection whose write end was closed
+                    writes.insert(pkey, write);
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        if !writes.is_empty() {
+            debug!(
+                target: targets::MANAGER,
+                "dropping {} connections to departed peers", writes.len()
+            );
+        }
+
+        info!(
+            target: targets::MANAGER,
+            "reclaimed {} connections and {} unprocessed messages",
+            connections.len(),
+            backlog.len()
+        );
+
+        Ok(ReclaimedSystem {
+            connections,
+            backlog,
+            incoming: incoming.unwrap_or_else(|| Box::new(stream::empty())),
+            listeners: mem::take(&mut self.listeners),
+            config: self.config.clone(),
+        })
+    }
+}
+
+/// `Connection`s and `Listener`s taken from a stopped `SystemManager`
+/// using `SystemHandle::into_parts`, ready to be used by a new
+/// `SystemManager` created with `SystemManager::from_reclaimed`
+pub struct ReclaimedSystem {
+    connections: HashMap<PublicKey, Connection>,
+    backlog: Vec<(PublicKey, Vec<u8>)>,
+    incoming: Incoming,
+    listeners: Listeners,
+    config: ManagerConfig,
+}
+
+impl ReclaimedSystem {
+    /// Get the `Connection` to each peer that was connected when the
+    /// `SystemManager` was stopped
+    pub fn connections(&self) -> &HashMap<PublicKey, Connection> {
+        &self.connections
+    }
+
+    /// Get the messages that were received but not processed when the
+    /// `SystemManager` was stopped, along with their sender. Messages are
+    /// serialized using `codec::bincode_options` and ordered as they were
+    /// received from each peer.
+    pub fn backlog(&self) -> &[(PublicKey, Vec<u8>)] {
+        &self.backlog
+    }
+
+    /// Get a mutable reference to the backlog of unprocessed messages, for
+    /// instance to convert them before they are given to a new
+    /// `SystemManager`
+    pub fn backlog_mut(&mut self) -> &mut Vec<(PublicKey, Vec<u8>)> {
+        &mut self.backlog
+    }
+}
+
+#[derive(Debug, snafu::Snafu)]
+/// Errors returned by [`SystemHandle::into_parts`]
+///
+/// [`SystemHandle::into_parts`]: self::SystemHandle::into_parts
+pub enum ShutdownError {
+    #[snafu(display("system manager already stopped"))]
+    /// The `SystemManager` was already stopped using another handle
+    AlreadyStopped,
+    #[snafu(display("failed to encode message from {}: {}", from, source))]
+    /// An unprocessed message could not be serialized
+    Encode {
+        /// Peer that sent the message
+        from: PublicKey,
+        /// Error source
+        source: BincodeError,
+    },
+}
+
+/// Number of peers that are fully connected to a `SystemManager`, meaning
+/// that both a receiving agent and a write half are present for them
+struct PeerCount {
+    state: StdMutex<PeerState>,
+    tx: watch::Sender<usize>,
+}
+
+#[derive(Default)]
+struct PeerState {
+    /// Number of open `ConnectionRead`s for each peer
+    reads: HashMap<PublicKey, usize>,
+    /// Peers for which a `ConnectionWrite` was added to the `Sender`
+    writes: HashSet<PublicKey>,
+    /// Initiator of the current `Connection` to each peer, forgotten once
+    /// all `Connection`s to the peer are closed
+    initiators: HashMap<PublicKey, Option<PublicKey>>,
+}
+
+impl PeerState {
+    fn count(&self) -> usize {
+        self.reads
+            .keys()
+            .filter(|k| self.writes.contains(k))
+            .count()
+    }
+}
+
+impl PeerCount {
+    fn new(
+        reads: &[ConnectionRead],
+        writes: &[ConnectionWrite],
+        initiators: HashMap<PublicKey, Option<PublicKey>>,
+    ) -> Self {
+        let mut state = PeerState {
+            initiators,
+            ..Default::default()
+        };
+
+        for read in reads {
+            *state.reads.entry(*read.remote_pkey()).or_default() += 1;
+        }
+
+        state.writes.extend(writes.iter().map(|w| *w.remote_pkey()));
+
+        let (tx, _) = watch::channel(state.count());
+
+        Self {
+            state: StdMutex::new(state),
+            tx,
+        }
+    }
+
+    fn subscribe(&self) -> watch::Receiver<usize> {
+        self.tx.subscribe()
+    }
+
+    /// Get the `Connection`s known for each peer, or `None` if they are
+    /// currently locked
+    fn try_snapshot(&self) -> Option<BTreeMap<String, PeerSnapshot>> {
+        let state = self.state.try_lock().ok()?;
+        let mut snapshot: BTreeMap<_, _> = state
+            .reads
+            .iter()
+            .map(|(pkey, reads)| {
+                let peer = PeerSnapshot {
+                    reads: *reads,
+                    writable: state.writes.contains(pkey),
+                };
+
+                (pkey.to_string(), peer)
+            })
+            .collect();
+
+        for pkey in &state.writes {
+            snapshot.entry(pkey.to_string()).or_insert(PeerSnapshot {
+                reads: 0,
+                writable: true,
+            });
+        }
+
+        Some(snapshot)
+    }
+
+    fn update<F: FnOnce(&mut PeerState) -> T, T>(&self, f: F) -> T {
+        let mut state = self.state.lock().expect("peer count poisoned");
+        let result = f(&mut state);
+        let count = state.count();
+
+        self.tx.send_if_modified(|current| {
+            let changed = *current != count;
+            *current = count;
+            changed
+        });
+
+        result
+    }
+
+    fn read_opened(&self, pkey: PublicKey) {
+        self.update(|state| *state.reads.entry(pkey).or_default() += 1)
+    }
+
+    fn write_added(&self, pkey: PublicKey) {
+        self.update(|state| state.writes.insert(pkey));
+    }
+
+    /// Check whether a new `Connection` to `pkey` initiated by `initiator`
+    /// replaces the current one, see `keep_new`, and record it as current
+    /// if it does. Peers without an open `Connection` always accept it.
+    fn replace(&self, pkey: PublicKey, initiator: Option<PublicKey>) -> bool {
+        self.update(|state| {
+            let keep = state
+                .initiators
+                .get(&pkey)
+                .is_none_or(|current| keep_new(*current, initiator));
+
+            if keep {
+                state.initiators.insert(pkey, initiator);
+            }
+
+            keep
+        })
+    }
+
+    /// Forget the initiator of the current `Connection` to `pkey` after it
+    /// was disconnected, so that any new `Connection` replaces it
+    fn forget(&self, pkey: &PublicKey) {
+        self.update(|state| state.initiators.remove(pkey));
+    }
+
+    /// Record that a `ConnectionRead` from `pkey` was closed, returns true if
+    /// this was the last open `Connection` to this peer
+    fn read_closed(&self, pkey: PublicKey) -> bool {
+        self.update(|state| match state.reads.get_mut(&pkey) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            _ => {
+                state.reads.remove(&pkey);
+                state.writes.remove(&pkey);
+                state.initiators.remove(&pkey);
+                true
+            }
+        })
+    }
+}
+
+/// Maximum number of characters of a message recorded in a `SystemError`
+pub const MAX_RENDERED_MESSAGE: usize = 256;
+
+fn render_message<M: std::fmt::Debug>(message: &M) -> String {
+    let mut rendered = format!("{:?}", message);
+
+    if let Some((idx, _)) = rendered.char_indices().nth(MAX_RENDERED_MESSAGE) {
+        rendered.truncate(idx);
+        rendered.push_str("...");
+    }
+
+    rendered
+}
+
+/// Decide whether a new `Connection` to a peer should replace the current
+/// one. When both ends dial each other at the same time, both keep the
+/// `Connection` that was initiated by the smallest `PublicKey` so that they
+/// independently agree on the surviving `Connection`.
+fn keep_new(current: Option<PublicKey>, new: Option<PublicKey>) -> bool {
+    match (current, new) {
+        (Some(current), Some(new)) if current != new => new < current,
+        _ => true,
+    }
+}
+
+#[derive(Debug, snafu::Snafu)]
+/// Errors encountered by [`SystemHandle`]
+///
+/// [`SystemHandle`]: self::SystemHandle
+pub enum SystemError<E: std::error::Error + Send + Sync + 'static> {
+    #[snafu(display("unauthenticated connection"))]
+    /// User tried to add an unauthenticated connection
+    Unauthenticated,
+    #[snafu(display("remote peer {} disconnected", pkey))]
+    /// A connection error caused a remote peer to be disconnected
+    Disconnected {
+        /// Peer's PublicKey
+        pkey: PublicKey,
+    },
+    #[snafu(display("remote peer {} closed the connection", pkey))]
+    /// A remote peer cleanly closed its connection, for instance because it
+    /// is shutting down. This is reported so that the departure can be
+    /// observed and does not indicate a failure.
+    Closed {
+        /// Peer's PublicKey
+        pkey: PublicKey,
+    },
+    #[snafu(display("failed to process message from {}: {}", from, source))]
+    /// Processor encountered an error
+    ProcessorError {
+        /// Peer that sent the message that failed to be processed
+        from: PublicKey,
+        /// `Debug` rendering of the message, only recorded when debug logging
+        /// is enabled and truncated to `MAX_RENDERED_MESSAGE` characters
+        message: Option<String>,
+        /// Error source
+        source: E,
+    },
+    #[snafu(display("peer {} {} with score {}", pkey, action, score))]
+    /// A peer reached a threshold of the `PeerScoreboard` by committing
+    /// protocol violations
+    PeerViolation {
+        /// Peer's PublicKey
+        pkey: PublicKey,
+        /// Score of the peer after its last violation
+        score: f64,
+        /// Action taken against the peer
+        action: ViolationAction,
+    },
+    #[snafu(display("peer {} is banned", pkey))]
+    /// Attempted to add a `Connection` to a banned peer
+    Banned {
+        /// Peer's PublicKey
+        pkey: PublicKey,
+    },
+    #[snafu(display("system stopped before adding connection to {}", pkey))]
+    /// The `SystemManager` stopped before the connection could be added.
+    /// Adding further connections will not work either
+    ManagerStopped {
+        /// Remote peer of the connection that was not added
+        pkey: PublicKey,
+    },
+    #[snafu(display(
+        "failed to decode message from {} left by previous manager: {}",
+        from,
+        source
+    ))]
+    /// A message left unprocessed by a previous `SystemManager` could not be
+    /// decoded, see `SystemManager::from_reclaimed`
+    UndecodableBacklog {
+        /// Peer that sent the message
+        from: PublicKey,
+        /// Error source
+        source: BincodeError,
+    },
+}
+
+#[derive(Debug, snafu::Snafu)]
+/// Errors returned by [`SystemHandle::wait_for_peers`]
+///
+/// [`SystemHandle::wait_for_peers`]: self::SystemHandle::wait_for_peers
+pub enum WaitError {
+    #[snafu(display(
+        "only {} out of {} peers connected before timeout",
+        connected,
+        expected
+    ))]
+    /// The expected number of peers was not reached in time
+    Timeout {
+        /// Number of peers connected when the timeout expired
+        connected: usize,
+        /// Number of peers that was expected
+        expected: usize,
+    },
+    #[snafu(display("system manager stopped"))]
+    /// The `SystemManager` stopped tracking connections
+    Stopped,
+}
+
+/// `ConnectionLimits` applied by a `SystemManager` instead of the ones its
+/// `Connection`s came with
+struct PeerLimits {
+    /// Limits from the `ManagerConfig`
+    default: Option<Arc<ConnectionLimits>>,
+    /// Overrides set using `SystemHandle::set_peer_limits`
+    peers: StdMutex<HashMap<PublicKey, Arc<ConnectionLimits>>>,
+}
+
+impl PeerLimits {
+    fn new(default: Option<Arc<ConnectionLimits>>) -> Self {
+        Self {
+            default,
+            peers: StdMutex::default(),
+        }
+    }
+
+    /// Get the limits that apply to `pkey`, if they replace the ones its
+    /// `Connection`s came with
+    fn get(&self, pkey: &PublicKey) -> Option<Arc<ConnectionLimits>> {
+        let peers = self.peers.lock().expect("peer limits poisoned");
+
+        peers.get(pkey).cloned().or_else(|| self.default.clone())
+    }
+
+    fn set(&self, pkey: PublicKey, limits: Arc<ConnectionLimits>) {
+        let mut peers = self.peers.lock().expect("peer limits poisoned");
+
+        peers.insert(pkey, limits);
+    }
 }
 
-impl<P, S, I, O, M> SystemHandle<P, S, I, O, M>
-where
-    P: Processor<M, I, O, S> + Send,
-    P::Error: Send + Sync + 'static,
-    I: Send,
-    O: Send,
-    M: Message + From<I> + 'static,
-    S: Sender<M>,
-{
-    fn new(
-        processor: Arc<P>,
-        inner: P::Handle,
-        connections: mpsc::Sender<Connection>,
-        error_rx: dispatch::Receiver<SystemError<P::Error>>,
-    ) -> Self {
-        Self {
-            inner,
-            processor,
-            connections,
-            error_rx: Some(error_rx),
-            _i: PhantomData,
-            _o: PhantomData,
+/// Number of messages from each peer that were handed over to processing
+/// tasks but not processed yet
+#[derive(Default)]
+struct InFlight {
+    counts: StdMutex<HashMap<PublicKey, usize>>,
+    released: Notify,
+}
+
+impl InFlight {
+    /// Wait until fewer than `max` messages from `pkey` are in flight and
+    /// count one more
+    async fn acquire(&self, pkey: PublicKey, max: usize) {
+        loop {
+            let released = self.released.notified();
+
+            {
+                let mut counts =
+                    self.counts.lock().expect("in flight poisoned");
+                let count = counts.entry(pkey).or_default();
+
+                if *count < max {
+                    *count += 1;
+                    return;
+                }
+            }
+
+            debug!(
+                target: targets::MANAGER,
+                "waiting for {} messages from {} to be processed",
+                max, pkey
+            );
+            released.await;
+        }
+    }
+
+    /// Record that a message from `pkey` is no longer in flight
+    fn release(&self, pkey: &PublicKey) {
+        let mut counts = self.counts.lock().expect("in flight poisoned");
+
+        if let Some(count) = counts.get_mut(pkey) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(pkey);
+            }
+        }
+
+        drop(counts);
+        self.released.notify_waiters();
+    }
+}
+
+/// This is handle used to interact with a [`SystemManager`] and the [`Processor`]
+/// running on that [`SystemManager`]
+///
+/// [`Processor`]: self::Processor
+/// [`SystemManager`]: self::SystemManager
+#[derive(Clone)]
+pub struct SystemHandle<P, S, I, O, M>
+where
+    P: Processor<M, I, O, S>,
+    P::Error: Send + Sync + 'static,
+    O: Send,
+    M: Message + From<I> + 'static,
+    S: Sender<M>,
+{
+    inner: P::Handle,
+    processor: Arc<P>,
+    connections: mpsc::Sender<Pending>,
+    errors: Arc<EventLog<SystemError<P::Error>>>,
+    peers: watch::Receiver<usize>,
+    scoreboard: PeerScoreboard,
+    tasks: Arc<StdMutex<Option<ManagerTasks<M>>>>,
+    shared: Shared<M>,
+    _i: PhantomData<I>,
+    _o: PhantomData<O>,
+}
+
+/// State shared by a running `SystemManager` with its `SystemHandle`
+struct Shared<M: Message + 'static> {
+    peers: Arc<PeerCount>,
+    sender: Arc<NetworkSender<M>>,
+    pending: Arc<AtomicUsize>,
+    limits: Arc<PeerLimits>,
+    /// Address health probes should be served on, from the `ManagerConfig`
+    health_addr: Option<SocketAddr>,
+}
+
+impl<M: Message + 'static> Clone for Shared<M> {
+    fn clone(&self) -> Self {
+        Self {
+            peers: self.peers.clone(),
+            sender: self.sender.clone(),
+            pending: self.pending.clone(),
+            limits: self.limits.clone(),
+            health_addr: self.health_addr,
+        }
+    }
+}
+
+impl<P, S, I, O, M> SystemHandle<P, S, I, O, M>
+where
+    P: Processor<M, I, O, S> + Send,
+    P::Error: Send + Sync + 'static,
+    I: Send,
+    O: Send,
+    M: Message + From<I> + 'static,
+    S: Sender<M>,
+{
+    fn new(
+        processor: Arc<P>,
+        inner: P::Handle,
+        connections: mpsc::Sender<Pending>,
+        errors: Arc<EventLog<SystemError<P::Error>>>,
+        scoreboard: PeerScoreboard,
+        tasks: ManagerTasks<M>,
+        shared: Shared<M>,
+    ) -> Self {
+        Self {
+            inner,
+            processor,
+            connections,
+            errors,
+            peers: shared.peers.subscribe(),
+            scoreboard,
+            tasks: Arc::new(StdMutex::new(Some(tasks))),
+            shared,
+            _i: PhantomData,
+            _o: PhantomData,
+        }
+    }
+
+    /// Get [`Handle`] for the [`Processor`] currently running
+    ///
+    /// [`Handle`]: self::Handle
+    /// [`Processor`]: self::Processor
+    pub fn processor_handle(&self) -> P::Handle {
+        self.inner.clone()
+    }
+
+    /// Force garbage collection of the [`Processor`]
+    ///
+    /// [`Processor`]: self::Processor
+    pub async fn force_gc(&self) {
+        self.processor.garbage_collection().await;
+    }
+
+    /// Get a `Stream` that will yield all errors encountered in the running [`SystemManager`],
+    /// starting with the ones it retained, see `ManagerConfig::error_retention`. Errors that
+    /// were not retained are silently skipped, use `SystemHandle::errors_from` to find out about
+    /// them. The `Stream` ends once the `SystemManager` stops.
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub fn errors(
+        &self,
+    ) -> impl futures::Stream<Item = Arc<SystemError<P::Error>>> {
+        self.errors_from(0).filter_map(|entry| {
+            future::ready(match entry {
+                LogEntry::Event { event, .. } => Some(event),
+                LogEntry::Gap { .. } => None,
+            })
+        })
+    }
+
+    /// Get a `Stream` of the errors encountered in the running [`SystemManager`] starting from
+    /// the one numbered `seq`. Retained errors are replayed before new ones and each of them
+    /// carries its sequence number so that consumers can resume where they left off. Errors
+    /// evicted before they could be replayed are reported using a single `LogEntry::Gap`.
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub fn errors_from(
+        &self,
+        seq: u64,
+    ) -> impl futures::Stream<Item = LogEntry<SystemError<P::Error>>> {
+        self.errors.subscribe(seq)
+    }
+
+    /// Get the sequence number of the next error encountered by the running
+    /// [`SystemManager`]
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub fn next_error_seq(&self) -> u64 {
+        self.errors.next_seq()
+    }
+
+    /// Get a `watch::Receiver` that tracks the number of peers currently
+    /// connected to the [`SystemManager`]. A peer is counted once both its
+    /// incoming and outgoing halves have been registered and stops being
+    /// counted as soon as it disconnects.
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub fn on_peer_count(&self) -> watch::Receiver<usize> {
+        self.peers.clone()
+    }
+
+    /// Wait until at least `count` peers are connected to the
+    /// [`SystemManager`], or until `timeout` expires if one is given.
+    /// Peers that disconnect before the threshold is reached are not counted.
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub async fn wait_for_peers(
+        &self,
+        count: usize,
+        timeout: Option<Duration>,
+    ) -> Result<(), WaitError> {
+        let mut peers = self.peers.clone();
+        let wait = peers.wait_for(|connected| *connected >= count);
+
+        let result = match timeout {
+            Some(timeout) => {
+                time::timeout(timeout, wait).await.map_err(|_| {
+                    Timeout {
+                        connected: *self.peers.borrow(),
+                        expected: count,
+                    }
+                    .build()
+                })?
+            }
+            None => wait.await,
+        };
+
+        result.map(|_| ()).ok().context(Stopped)
+    }
+
+    /// Get the total size of the messages queued for all peers by the
+    /// [`NetworkSender`] of the running [`SystemManager`]
+    ///
+    /// [`NetworkSender`]: super::NetworkSender
+    /// [`SystemManager`]: self::SystemManager
+    pub fn queued_bytes(&self) -> u64 {
+        self.shared.sender.queued_bytes()
+    }
+
+    /// Get the current score of every peer that committed a protocol
+    /// violation, highest score first
+    pub fn peer_scores(&self) -> Vec<(PublicKey, f64)> {
+        self.scoreboard.scores()
+    }
+
+    /// Report a protocol violation committed by `peer`, for instance from
+    /// the [`Processor`]. This returns the action taken against `peer` if
+    /// its score reached a threshold.
+    ///
+    /// [`Processor`]: self::Processor
+    pub fn report_violation(
+        &self,
+        peer: PublicKey,
+        severity: Severity,
+    ) -> Option<ViolationAction> {
+        self.scoreboard.report(peer, severity)
+    }
+
+    /// Get the [`PeerScoreboard`] of the running [`SystemManager`], which can
+    /// be used to inspect or lift bans
+    ///
+    /// [`PeerScoreboard`]: super::PeerScoreboard
+    /// [`SystemManager`]: self::SystemManager
+    pub fn scoreboard(&self) -> &PeerScoreboard {
+        &self.scoreboard
+    }
+
+    /// Take the tasks of the running [`SystemManager`], only the first call
+    /// returns `Some`
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    /// Address health probes should be served on, as set using
+    /// `ManagerConfig::health_addr`
+    pub(super) fn configured_health_addr(&self) -> Option<SocketAddr> {
+        self.shared.health_addr
+    }
+
+    pub(crate) fn tasks(&self) -> Option<ManagerTasks<M>> {
+        self.tasks.lock().expect("manager tasks poisoned").take()
+    }
+
+    /// Apply `limits` to the [`Connection`]s to `pkey`, including future
+    /// ones, instead of the limits from the [`ManagerConfig`] or the ones they
+    /// came with. Limits on receiving apply to live [`Connection`]s from the
+    /// next message on, the send queue bound and the limits on sending only
+    /// apply to [`Connection`]s registered afterwards.
+    ///
+    /// [`Connection`]: crate::net::Connection
+    /// [`ManagerConfig`]: self::ManagerConfig
+    pub fn set_peer_limits(&self, pkey: PublicKey, limits: ConnectionLimits) {
+        self.shared.limits.set(pkey, Arc::new(limits));
+    }
+
+    /// Get the limits applied to `pkey` by the running [`SystemManager`], if
+    /// they replace the ones its [`Connection`]s came with
+    ///
+    /// [`Connection`]: crate::net::Connection
+    /// [`SystemManager`]: self::SystemManager
+    pub fn peer_limits(
+        &self,
+        pkey: &PublicKey,
+    ) -> Option<Arc<ConnectionLimits>> {
+        self.shared.limits.get(pkey)
+    }
+
+    /// Reconnect to `pkey` before peers with a lower `priority` when the
+    /// running [`SystemManager`] reconnects more peers than it can dial at
+    /// once, see `ManagerConfig::reconnect`
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub fn prioritize_reconnect(&self, pkey: PublicKey, priority: u32) {
+        self.shared.sender.prioritize_reconnect(pkey, priority);
+    }
+
+    /// Get the peers the running [`SystemManager`] is reconnecting to
+    ///
+    /// [`SystemManager`]: self::SystemManager
+    pub async fn reconnecting(&self) -> Vec<PublicKey> {
+        self.shared.sender.reconnecting().await
+    }
+
+    /// Take a snapshot of the state of the running [`SystemManager`] without
+    /// stopping it. Components that don't report their state within
+    /// [`DUMP_TIMEOUT`] are marked as unavailable so that a stuck lock can't
+    /// hang the dump.
+    ///
+    /// [`DUMP_TIMEOUT`]: super::DUMP_TIMEOUT
+    /// [`SystemManager`]: self::SystemManager
+    pub async fn dump(&self) -> NodeSnapshot {
+        let shared = &self.shared;
+        let (peers, sender, scores, tasks) = future::join4(
+            dump::poll(|| shared.peers.try_snapshot()),
+            dump::gather(shared.sender.snapshot()),
+            dump::poll(|| self.scoreboard.try_snapshot()),
+            dump::poll(|| {
+                let tasks = self.tasks.try_lock().ok()?;
+
+                Some(
+                    tasks
+                        .as_ref()
+                        .map(ManagerTasks::snapshot)
+                        .unwrap_or_default(),
+                )
+            }),
+        )
+        .await;
+        let dispatch = DispatchSnapshot {
+            pending: shared.pending.load(Ordering::Acquire),
+        };
+
+        NodeSnapshot {
+            taken_at: dump::timestamp(),
+            peers,
+            sender,
+            dispatch: ComponentSnapshot::Available(dispatch),
+            scores,
+            tasks,
+        }
+    }
+
+    /// Stop the running [`SystemManager`] without closing any of its
+    /// [`Connection`]s so that they can be used by a new [`SystemManager`]
+    /// created using [`SystemManager::from_reclaimed`]. <br />
+    /// New `Connection`s stop being accepted and receiving stops, messages
+    /// that were already received are then processed during at most `drain`.
+    /// Messages that were not processed by then are returned in the backlog
+    /// of the [`ReclaimedSystem`], messages that are being processed are
+    /// never interrupted.
+    ///
+    /// [`Connection`]: crate::net::Connection
+    /// [`SystemManager`]: self::SystemManager
+    /// [`SystemManager::from_reclaimed`]: self::SystemManager::from_reclaimed
+    /// [`ReclaimedSystem`]: self::ReclaimedSystem
+    pub async fn into_parts(
+        self,
+        drain: Duration,
+    ) -> Result<ReclaimedSystem, ShutdownError> {
+        let tasks = self.tasks().context(AlreadyStopped)?;
+
+        tasks.reclaim(drain).await
+    }
+
+    /// Shut the running [`SystemManager`] down by executing every [`Stage`]
+    /// after [`Stage::Deregister`] in order: stop accepting new
+    /// [`Connection`]s, stop receiving and give processing at most `grace` to
+    /// drain the messages that were already received, run the garbage
+    /// collection of the [`Processor`] one last time and let it flush its
+    /// state, send queued messages and close outgoing [`Connection`]s, and
+    /// finally wait for all processing tasks to exit. Every [`Stage`] fails
+    /// if the [`SystemManager`] was already stopped using another handle.
+    ///
+    /// [`Connection`]: crate::net::Connection
+    /// [`Processor`]: self::Processor
+    /// [`Stage`]: super::Stage
+    /// [`Stage::Deregister`]: super::Stage::Deregister
+    /// [`SystemManager`]: self::SystemManager
+    pub async fn shutdown(self, grace: Duration) -> ShutdownReport {
+        self.shutdown_with(ShutdownReport::default(), grace).await
+    }
+
+    /// Shut down like `SystemHandle::shutdown`, recording stages after the
+    /// ones already in `report`
+    pub(crate) async fn shutdown_with(
+        self,
+        mut report: ShutdownReport,
+        grace: Duration,
+    ) -> ShutdownReport {
+        let Some(mut tasks) = self.tasks() else {
+            let reason = "system manager already shut down".to_string();
+
+            for stage in [
+                Stage::StopListeners,
+                Stage::Drain,
+                Stage::CloseSenders,
+                Stage::Join,
+            ] {
+                report.record(stage, Outcome::Failed(reason.clone()));
+            }
+
+            return report;
+        };
+
+        let outcome = match tasks.stop_listeners().await {
+            0 => Outcome::Skipped,
+            _ => Outcome::Done,
+        };
+
+        report.record(Stage::StopListeners, outcome);
+
+        let drained = tasks.drain(grace).await;
+
+        // collect once more now that every received message was processed
+        self.processor.garbage_collection().await;
+        self.processor.shutdown().await;
+
+        report.dropped_connections = drained.connections;
+        report.record(
+            Stage::Drain,
+            if drained.timed_out {
+                Outcome::TimedOut
+            } else {
+                Outcome::Done
+            },
+        );
+
+        report.closed_connections = tasks.close_senders().await;
+        report.record(Stage::CloseSenders, Outcome::Done);
+
+        let joined = tasks.join().await;
+
+        report.dropped_messages = joined.dropped;
+        report.record(
+            Stage::Join,
+            match joined.panicked {
+                0 => Outcome::Done,
+                panicked => Outcome::Failed(format!(
+                    "{} processing tasks panicked",
+                    panicked
+                )),
+            },
+        );
+
+        info!(target: targets::MANAGER, "system manager shut down");
+
+        report
+    }
+
+    /// Add a new [`Connection`] to the running [`SystemManager`]. This
+    /// returns the [`PublicKey`] of the remote peer once messages can be sent
+    /// to it, and its read end has been handed over for receiving.
+    ///
+    /// [`Connection`]: crate::net::Connection
+    /// [`PublicKey`]: crate::crypto::key::exchange::PublicKey
+    /// [`SystemManager`]: self::SystemManager
+    pub async fn add_connection(
+        &self,
+        connection: Connection,
+    ) -> Result<PublicKey, SystemError<P::Error>> {
+        let pkey = connection.remote_key().context(Unauthenticated)?;
+
+        ensure!(connection.is_secured(), Unauthenticated);
+        ensure!(!self.scoreboard.is_banned(&pkey), Banned { pkey });
+
+        debug!(
+            target: targets::MANAGER,
+            "adding connection from user to {}", pkey
+        );
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+
+        self.connections
+            .clone()
+            .send((connection, Some(ack_tx)))
+            .await
+            .ok()
+            .context(ManagerStopped { pkey })?;
+
+        ack_rx.await.ok().context(ManagerStopped { pkey })?;
+
+        Ok(pkey)
+    }
+}
+
+/// A `Processor` that wraps another `Processor` to provide at-least-once
+/// delivery together with an `AckSender`. Messages sent using
+/// `AckSender::send_acked` are acknowledged once the inner `Processor` has
+/// successfully processed them, and retransmitted duplicates are suppressed
+/// using a bounded window of recently seen ids for each peer.
+pub struct AckProcessor<M, S, P>
+where
+    M: Message + 'static,
+    S: Sender<Acked<M>>,
+{
+    processor: P,
+    sender: Option<Arc<AckSender<M, S>>>,
+    seen: StdMutex<HashMap<PublicKey, Window>>,
+    window: usize,
+    delay: Duration,
+    retries: usize,
+}
+
+impl<M, S, P> AckProcessor<M, S, P>
+where
+    M: Message + 'static,
+    S: Sender<Acked<M>>,
+{
+    /// Default number of ids remembered for each peer
+    pub const DEFAULT_WINDOW: usize = 1024;
+
+    /// Create a new `AckProcessor` wrapping the given `Processor`
+    pub fn new(processor: P) -> Self {
+        Self::with_window(processor, Self::DEFAULT_WINDOW)
+    }
+
+    /// Create a new `AckProcessor` that remembers the last `window` message ids
+    /// received from each peer to suppress duplicates
+    pub fn with_window(processor: P, window: usize) -> Self {
+        Self {
+            processor,
+            sender: None,
+            seen: StdMutex::new(HashMap::new()),
+            window,
+            delay: AckSender::<M, S>::DEFAULT_DELAY,
+            retries: AckSender::<M, S>::DEFAULT_RETRIES,
+        }
+    }
+
+    /// Set the retry policy used by the `AckSender` created during setup
+    pub fn with_retries(mut self, delay: Duration, retries: usize) -> Self {
+        self.delay = delay;
+        self.retries = retries;
+        self
+    }
+
+    /// Get the `AckSender` used by this `AckProcessor`, if it has been setup
+    pub fn sender(&self) -> Option<Arc<AckSender<M, S>>> {
+        self.sender.clone()
+    }
+
+    fn begin(&self, from: PublicKey, id: u64) -> Seen {
+        let mut seen = self.seen.lock().expect("ack window poisoned");
+
+        seen.entry(from).or_default().begin(id)
+    }
+
+    fn finish(&self, from: PublicKey, id: u64, success: bool) {
+        let mut seen = self.seen.lock().expect("ack window poisoned");
+
+        if let Some(window) = seen.get_mut(&from) {
+            window.finish(id, success, self.window);
+        }
+    }
+}
+
+#[async_trait]
+impl<M, I, O, S, P> Processor<Acked<M>, I, O, S> for AckProcessor<M, S, P>
+where
+    M: Message + 'static,
+    I: Into<M> + Into<Acked<M>>,
+    O: Send,
+    S: Sender<Acked<M>> + 'static,
+    P: Processor<M, I, O, AckSender<M, S>>,
+    P::Error: 'static,
+{
+    type Handle = P::Handle;
+
+    type Error = AckProcessorError<P::Error>;
+
+    async fn process(
+        &self,
+        message: Acked<M>,
+        from: PublicKey,
+        _sender: Arc<S>,
+    ) -> Result<(), Self::Error> {
+        let sender = self.sender.as_ref().context(NotSetup)?;
+
+        match message {
+            Acked::Plain(message) => self
+                .processor
+                .process(message, from, sender.clone())
+                .await
+                .context(Inner),
+            Acked::Ack(id) => {
+                sender.acknowledge(id).await;
+                Ok(())
+            }
+            Acked::Data { id, message } => {
+                match self.begin(from, id) {
+                    Seen::New => {}
+                    Seen::Running => return Ok(()),
+                    Seen::Done => {
+                        debug!(
+                            target: targets::MANAGER,
+                            "duplicate message {} from {}", id, from
+                        );
+
+                        return sender
+                            .inner()
+                            .send(Acked::Ack(id), &from)
+                            .await
+                            .context(Acknowledge { remote: from });
+                    }
+                }
+
+                let result =
+                    self.processor.process(message, from, sender.clone()).await;
+
+                self.finish(from, id, result.is_ok());
+                result.context(Inner)?;
+
+                sender
+                    .inner()
+                    .send(Acked::Ack(id), &from)
+                    .await
+                    .context(Acknowledge { remote: from })
+            }
+        }
+    }
+
+    async fn setup<SA: Sampler>(
+        &mut self,
+        sampler: Arc<SA>,
+        sender: Arc<S>,
+    ) -> Self::Handle {
+        let sender =
+            Arc::new(AckSender::with_retries(sender, self.delay, self.retries));
+
+        self.sender.replace(sender.clone());
+
+        self.processor.setup(sampler, sender).await
+    }
+
+    async fn disconnect<SA: Sampler>(
+        &self,
+        peer: PublicKey,
+        sender: Arc<S>,
+        sampler: Arc<SA>,
+    ) {
+        let sender = match self.sender.as_ref() {
+            Some(sender) => sender.clone(),
+            None => Arc::new(AckSender::new(sender)),
+        };
+
+        self.processor.disconnect(peer, sender, sampler).await
+    }
+
+    async fn garbage_collection(&self) {
+        self.processor.garbage_collection().await
+    }
+}
+
+#[derive(Debug, snafu::Snafu)]
+/// Errors returned by [`AckProcessor`]
+///
+/// [`AckProcessor`]: self::AckProcessor
+pub enum AckProcessorError<E: std::error::Error + Send + Sync + 'static> {
+    #[snafu(display("processor error: {}", source))]
+    /// The wrapped `Processor` failed to process a message
+    Inner {
+        /// Error source
+        source: E,
+    },
+    #[snafu(display(
+        "failed to acknowledge message from {}: {}",
+        remote,
+        source
+    ))]
+    /// The acknowledgement could not be sent back
+    Acknowledge {
+        /// Peer that sent the message
+        remote: PublicKey,
+        /// Error source
+        source: SenderError,
+    },
+    #[snafu(display("processor was not setup"))]
+    /// A message was received before `Processor::setup` was called
+    NotSetup,
+}
+
+enum Seen {
+    New,
+    Running,
+    Done,
+}
+
+/// Ids of messages recently received from a single peer
+#[derive(Default)]
+struct Window {
+    running: HashSet<u64>,
+    done: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl Window {
+    fn begin(&mut self, id: u64) -> Seen {
+        if self.done.contains(&id) {
+            Seen::Done
+        } else if !self.running.insert(id) {
+            Seen::Running
+        } else {
+            Seen::New
+        }
+    }
+
+    fn finish(&mut self, id: u64, success: bool, size: usize) {
+        self.running.remove(&id);
+
+        if !success {
+            return;
+        }
+
+        self.done.insert(id);
+        self.order.push_back(id);
+
+        while self.order.len() > size {
+            if let Some(old) = self.order.pop_front() {
+                self.done.remove(&old);
+            }
+        }
+    }
+}
+
+/// Everything a `NetworkAgent` needs to hand received messages over to the
+/// processing tasks
+#[derive(Clone)]
+struct Dispatch<S> {
+    sink: S,
+    pool: Option<CryptoPool>,
+    /// Number of messages received but not yet processed
+    pending: Arc<AtomicUsize>,
+    /// Set once agents must stop receiving
+    stop: watch::Receiver<bool>,
+    /// Records violations committed by peers
+    scoreboard: PeerScoreboard,
+    limits: Arc<PeerLimits>,
+    inflight: Arc<InFlight>,
+}
+
+/// Notice sent by a `NetworkAgent` once it stops receiving from a peer
+struct Departure {
+    pkey: PublicKey,
+    /// Whether the remote peer closed the `Connection` cleanly
+    clean: bool,
+}
+
+/// Reason for a `NetworkAgent` to exit
+enum Exit<M> {
+    /// The `Connection` was closed or failed
+    Departed(Departure),
+    /// The agent was asked to stop, giving back its `ConnectionRead` along
+    /// with messages it could not hand over
+    Stopped {
+        read: Box<ConnectionRead>,
+        undelivered: Vec<M>,
+    },
+}
+
+/// Outcome of handing a message over to the processing tasks
+enum Delivery<M> {
+    Sent,
+    /// Processing tasks are gone
+    Closed,
+    /// The agent was asked to stop before the message could be handed over
+    Stopped(M),
+}
+
+struct NetworkAgent<M, S>
+where
+    S: Sink<Item = (PublicKey, M)>,
+{
+    dispatch: Dispatch<S>,
+    read: ConnectionRead,
+    pkey: PublicKey,
+    /// Messages to hand over before receiving new ones
+    backlog: VecDeque<M>,
+}
+
+impl<M, S> NetworkAgent<M, S>
+where
+    M: Message + 'static,
+    S: Sink<Item = (PublicKey, M)> + Send + Sync + Unpin + 'static,
+{
+    fn new(read: ConnectionRead, dispatch: Dispatch<S>) -> Self {
+        let pkey = *read.remote_pkey();
+
+        Self {
+            dispatch,
+            read,
+            pkey,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    fn with_backlog(mut self, backlog: VecDeque<M>) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    fn spawn(self) -> JoinHandle<Exit<M>> {
+        let peer = self.pkey.fingerprint();
+
+        task::spawn(self.receive_loop().instrument(
+            debug_span!(target: targets::MANAGER, "network_agent", peer=%peer),
+        ))
+    }
+
+    async fn receive_loop(mut self) -> Exit<M> {
+        let mut stop = self.dispatch.stop.clone();
+        let scoreboard = self.dispatch.scoreboard.clone();
+        let mut evicted = Box::pin(scoreboard.evicted(self.pkey).fuse());
+
+        loop {
+            // limits set while the agent is running apply from the next
+            // message on
+            if let Some(limits) = self.dispatch.limits.get(&self.pkey) {
+                self.read.set_limits(limits);
+            }
+
+            let message = match self.backlog.pop_front() {
+                Some(message) => message,
+                None => {
+                    // reading is cancel safe but decrypting is not
+                    futures::select! {
+                        read = self.read.read_frame().fuse() => match read {
+                            Ok(()) => {}
+                            // only the incomplete message is lost
+                            Err(e @ ReceiveError::IncompleteMessage { .. }) => {
+                                warn!(target: targets::MANAGER, "{}", e);
+                                scoreboard.report_error(self.pkey, &e);
+
+                                continue;
+                            }
+                            Err(e) => {
+                                scoreboard.report_error(self.pkey, &e);
+
+                                return self.departed(e);
+                            }
+                        },
+                        _ = evicted => {
+                            return self.evicted();
+                        }
+                        _ = signaled(&mut stop).fuse() => {
+                            return self.stopped(None);
+                        }
+                    }
+
+                    let received = match &self.dispatch.pool {
+                        Some(pool) => self.read.decrypt_on::<M>(pool).await,
+                        None => self.read.decrypt::<M>(),
+                    };
+
+                    match received {
+                        Ok(Some(message)) => message,
+                        // a fragment of a message that is not complete yet
+                        Ok(None) => continue,
+                        // the stream is still usable after a message that
+                        // decrypted correctly but could not be decoded
+                        Err(ReceiveError::Decrypt {
+                            source: DecryptError::SerializeDecrypt { source },
+                        }) => {
+                            warn!(
+                                target: targets::MANAGER,
+                                "dropping undecodable message: {}", source
+                            );
+                            scoreboard.report(self.pkey, Severity::Major);
+
+                            continue;
+                        }
+                        Err(e) => {
+                            scoreboard.report_error(self.pkey, &e);
+
+                            return self.departed(e);
+                        }
+                    }
+                }
+            };
+
+            let inflight = self.dispatch.inflight.clone();
+            let max = self.read.limits().inflight_receives();
+
+            futures::select! {
+                _ = inflight.acquire(self.pkey, max).fuse() => {}
+                _ = signaled(&mut stop).fuse() => {
+                    return self.stopped(Some(message));
+                }
+            }
+
+            self.dispatch.pending.fetch_add(1, Ordering::AcqRel);
+
+            match self.deliver(message, &mut stop).await {
+                Delivery::Sent => {}
+                Delivery::Closed => {
+                    inflight.release(&self.pkey);
+                    self.dispatch.pending.fetch_sub(1, Ordering::AcqRel);
+                    warn!(
+                        target: targets::MANAGER,
+                        "network agent shutting down"
+                    );
+                }
+                Delivery::Stopped(message) => {
+                    inflight.release(&self.pkey);
+                    self.dispatch.pending.fetch_sub(1, Ordering::AcqRel);
+
+                    return self.stopped(Some(message));
+                }
+            }
+        }
+    }
+
+    /// Hand `message` over to the processing tasks, giving it back if
+    /// `stop` is set while waiting for processing tasks to accept it
+    async fn deliver(
+        &mut self,
+        message: M,
+        stop: &mut watch::Receiver<bool>,
+    ) -> Delivery<M> {
+        let sink = &mut self.dispatch.sink;
+        let pkey = self.pkey;
+        let mut stopped = Box::pin(signaled(stop));
+        let mut message = Some(message);
+
+        future::poll_fn(move |cx| {
+            let item = (pkey, message.take().expect("message delivered"));
+
+            match Pin::new(&mut *sink).poll_send(&mut cx.into(), item) {
+                PollSend::Ready => Poll::Ready(Delivery::Sent),
+                PollSend::Rejected(_) => Poll::Ready(Delivery::Closed),
+                PollSend::Pending((_, item)) => {
+                    if stopped.as_mut().poll(cx).is_ready() {
+                        Poll::Ready(Delivery::Stopped(item))
+                    } else {
+                        message = Some(item);
+                        Poll::Pending
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    fn departed(self, error: ReceiveError) -> Exit<M> {
+        let clean = matches!(error, ReceiveError::Closed);
+
+        if clean {
+            info!(target: targets::MANAGER, "connection closed by remote peer");
+        } else {
+            error!(
+                target: targets::MANAGER,
+                "connection with failed: {}", error
+            );
+        }
+
+        Exit::Departed(Departure {
+            pkey: self.pkey,
+            clean,
+        })
+    }
+
+    fn evicted(self) -> Exit<M> {
+        warn!(target: targets::MANAGER, "disconnecting misbehaving peer");
+
+        Exit::Departed(Departure {
+            pkey: self.pkey,
+            clean: false,
+        })
+    }
+
+    fn stopped(self, undelivered: Option<M>) -> Exit<M> {
+        debug!(target: targets::MANAGER, "network agent stopped");
+
+        Exit::Stopped {
+            read: Box::new(self.read),
+            undelivered: undelivered.into_iter().chain(self.backlog).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        error::Error,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            OnceLock,
+        },
+    };
+
+    use futures::StreamExt as _;
+    use serde::{Deserialize, Serialize};
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::{super::sampler::AllSampler, *};
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        message,
+        net::{
+            Connector, Listener, MemoryConnector, MemoryListener,
+            MemoryRegistry, TcpConnector, TcpListener,
+        },
+        system::{AckError, CollectingSender},
+        test::*,
+    };
+
+    #[derive(Default)]
+    struct Dummy {
+        sender: Option<mpsc::Sender<(PublicKey, usize)>>,
+        flushed: Arc<AtomicBool>,
+        collected: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    #[derive(Debug)]
+    struct UnreachableError;
+    impl std::fmt::Display for UnreachableError {
+        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            Err(std::fmt::Error)
+        }
+    }
+    impl Error for UnreachableError {}
+
+    #[async_trait]
+    impl<S> Processor<usize, usize, (PublicKey, usize), S> for Dummy
+    where
+        S: Sender<usize> + 'static,
+    {
+        type Handle = TestHandle<usize>;
+
+        type Error = UnreachableError;
+
+        async fn process(
+            &self,
+            message: usize,
+            key: PublicKey,
+            _sender: Arc<S>,
+        ) -> Result<(), Self::Error> {
+            time::sleep(self.delay).await;
+
+            self.sender
+                .as_ref()
+                .expect("not setup")
+                .clone()
+                .send((key, message))
+                .await
+                .expect("channel failure");
+
+            Ok(())
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _sampler: Arc<SA>,
+            _sender: Arc<S>,
+        ) -> Self::Handle {
+            let (tx, rx) = mpsc::channel(128);
+
+            self.sender.replace(tx);
+
+            let channel = Arc::new(Mutex::new(rx));
+
+            TestHandle { channel }
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<S>,
+            _: Arc<SA>,
+        ) {
+            unreachable!()
+        }
+
+        async fn garbage_collection(&self) {
+            self.collected.fetch_add(1, Ordering::AcqRel);
+        }
+
+        async fn shutdown(&self) {
+            self.flushed.store(true, Ordering::Release);
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestHandle<M>
+    where
+        M: Message,
+    {
+        channel: Arc<Mutex<mpsc::Receiver<(PublicKey, M)>>>,
+    }
+
+    #[async_trait]
+    impl<M> Handle<M, (PublicKey, M)> for TestHandle<M>
+    where
+        M: Message,
+    {
+        type Error = UnreachableError;
+
+        async fn deliver(&mut self) -> Result<(PublicKey, M), Self::Error> {
+            Ok(self.channel.lock().await.recv().await.expect("no message"))
+        }
+
+        async fn try_deliver(
+            &mut self,
+        ) -> Result<Option<(PublicKey, M)>, Self::Error> {
+            Ok(self.channel.lock().await.try_recv().ok())
+        }
+
+        async fn drain(
+            &mut self,
+            max: usize,
+        ) -> Result<Vec<(PublicKey, M)>, Self::Error> {
+            let mut channel = self.channel.lock().await;
+
+            Ok(iter::from_fn(|| channel.try_recv().ok())
+                .take(max)
+                .collect())
+        }
+
+        async fn broadcast(&mut self, _: &M) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+    }
+
+    /// Number of seeds tried by tests using `stress`
+    const RUNS: u64 = 8;
+
+    #[tokio::test]
+    async fn shutdown_joins_tasks() {
+        const COUNT: usize = 5;
+
+        let (_, handles, system) =
+            create_system(COUNT, |mut connection| async move {
+                for value in 0..3usize {
+                    connection.send(&value).await.expect("send failed");
+                }
+
+                connection
+                    .receive::<usize>()
+                    .await
+                    .expect_err("connection was not closed");
+            })
+            .await;
+        let processor = Dummy::default();
+        let flushed = processor.flushed.clone();
+        let handle = SystemManager::new(system)
+            .run(processor, AllSampler::default(), 2)
+            .await;
+        let mut delivery = handle.processor_handle();
+
+        for _ in 0..COUNT * 3 {
+            delivery.deliver().await.expect("deliver failed");
+        }
+
+        let running = {
+            let tasks = handle.tasks.lock().unwrap();
+            let tasks = tasks.as_ref().expect("manager stopped");
+
+            tasks
+                .processing
+                .iter()
+                .map(JoinHandle::abort_handle)
+                .chain(tasks.incoming.as_ref().map(JoinHandle::abort_handle))
+                .chain(tasks.watcher.as_ref().map(JoinHandle::abort_handle))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(running.len(), 4, "missing tasks");
+
+        let report = handle.shutdown(Duration::from_secs(1)).await;
+
+        assert!(report.is_clean(), "unclean shutdown: {}", report);
+        assert_eq!(report.closed_connections(), COUNT);
+        assert!(flushed.load(Ordering::Acquire), "processor not flushed");
+        assert!(
+            running.iter().all(|task| task.is_finished()),
+            "tasks still running after shutdown"
+        );
+
+        handles.await.expect("connections not closed");
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_messages() {
+        const COUNT: usize = 5;
+        const MESSAGES: usize = 3;
+
+        let (_, handles, system) =
+            create_system(COUNT, |mut connection| async move {
+                for value in 0..MESSAGES {
+                    connection.send(&value).await.expect("send failed");
+                }
+
+                let _ = connection.receive::<usize>().await;
+            })
+            .await;
+        let processor = Dummy {
+            delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let collected = processor.collected.clone();
+        let handle = SystemManager::new(system)
+            .run(processor, AllSampler::default(), 1)
+            .await;
+        let delivery = handle.processor_handle();
+
+        // wait for every message to be received without processing them all
+        while handle.shared.pending.load(Ordering::Acquire)
+            + delivery.channel.lock().await.len()
+            < COUNT * MESSAGES
+        {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        assert!(
+            handle.shared.pending.load(Ordering::Acquire) > 0,
+            "all messages processed before shutdown"
+        );
+
+        let report = handle.shutdown(Duration::from_secs(10)).await;
+
+        assert!(report.is_clean(), "unclean shutdown: {}", report);
+        assert_eq!(report.dropped_messages(), 0, "messages were dropped");
+        assert_eq!(
+            delivery.channel.lock().await.len(),
+            COUNT * MESSAGES,
+            "messages were not processed"
+        );
+        assert_eq!(collected.load(Ordering::Acquire), 1, "no final collection");
+
+        handles.await.expect("connections not closed");
+    }
+
+    #[test]
+    fn receive_from_manager() {
+        const COUNT: usize = 50;
+
+        stress(RUNS, |_| async {
+            let counter = Arc::new(AtomicUsize::new(0));
+
+            let (pkeys, handles, system) =
+                create_system(COUNT, move |mut connection| {
+                    let counter = counter.clone();
+
+                    async move {
+                        let value = counter.fetch_add(1, Ordering::AcqRel);
+
+                        debug!("sending {:?}", value);
+                        connection.send(&value).await.expect("recv failed");
+
+                        debug!("done sending");
+                    }
+                })
+                .await;
+
+            let sampler = AllSampler::default();
+            let processor = Dummy::default();
+            let manager = SystemManager::new(system);
+
+            debug!("manager created");
+
+            debug!("registering processor");
+
+            let system_handle = manager.run(processor, sampler, 1).await;
+            let mut handle = system_handle.processor_handle();
+
+            let mut messages = Vec::with_capacity(COUNT);
+
+            for _ in 0..COUNT {
+                let (pkey, message) =
+                    handle.deliver().await.expect("unexpected error");
+
+                assert!(
+                    pkeys.iter().any(|(key, _)| *key == pkey),
+                    "bad message sender"
+                );
+
+                messages.push(message);
+            }
+
+            messages.sort_unstable();
+
+            assert_eq!(
+                messages,
+                (0..COUNT).collect::<Vec<_>>(),
+                "incorrect message sequence"
+            );
+
+            handles.await.expect("system failure");
+        });
+    }
+
+    #[test]
+    fn disconnect_notice() {
+        static COUNT: usize = 50;
+
+        use std::collections::HashSet;
+
+        crate::test::init_logger();
+
+        stress(RUNS, |_| async {
+            let (pkeys, handles, system) =
+                create_system(COUNT, |_| async {}).await;
+
+            let manager = SystemManager::<usize>::new(system);
+            let processor = Dummy::default();
+
+            let system_handle =
+                manager.run(processor, AllSampler::default(), 1).await;
+
+            let source = system_handle.errors();
+
+            // peers drop their connections cleanly
+            let actual = source
+                .map(|x| match &*x {
+                    SystemError::Closed { pkey } => *pkey,
+                    e => panic!("bad error type: {}", e),
+                })
+                .collect::<HashSet<_>>()
+                .await;
+
+            assert_eq!(
+                actual.len(),
+                COUNT,
+                "wrong number of disconnect notice"
+            );
+
+            let expected =
+                pkeys.into_iter().map(|x| x.0).collect::<HashSet<_>>();
+
+            assert_eq!(actual, expected, "missing some disconnect notices");
+
+            handles.await.expect("system failure");
+        });
+    }
+
+    #[tokio::test]
+    async fn late_error_subscription() {
+        const COUNT: usize = 10;
+        const RETAINED: usize = 4;
+
+        let (_, handles, system) = create_system(COUNT, |_| async {}).await;
+        let config = ManagerConfig::default().error_retention(RETAINED);
+        let handle = SystemManager::<usize>::with_config(system, config)
+            .run(Dummy::default(), AllSampler::default(), 1)
+            .await;
+
+        // wait for every peer to leave and the manager to stop
+        assert_eq!(handle.errors().count().await, RETAINED.min(COUNT));
+        assert_eq!(handle.next_error_seq(), COUNT as u64);
+
+        let replayed = handle.errors_from(0).collect::<Vec<_>>().await;
+
+        assert!(matches!(
+            replayed[0],
+            LogEntry::Gap { missed } if missed == (COUNT - RETAINED) as u64
+        ));
+        assert_eq!(
+            replayed[1..]
+                .iter()
+                .map(|entry| match entry {
+                    LogEntry::Event { seq, event } => {
+                        assert!(matches!(**event, SystemError::Closed { .. }));
+                        *seq
+                    }
+                    LogEntry::Gap { .. } => panic!("second gap"),
+                })
+                .collect::<Vec<_>>(),
+            ((COUNT - RETAINED) as u64..COUNT as u64).collect::<Vec<_>>()
+        );
+
+        let last = handle.errors_from(COUNT as u64 - 1).collect::<Vec<_>>();
+
+        assert_eq!(last.await.len(), 1, "cursor not honored");
+
+        handles.await.expect("system failure");
+    }
+
+    #[tokio::test]
+    async fn tasks_record_node() {
+        use tracing::{span, subscriber, Subscriber};
+        use tracing_subscriber::{
+            layer::{Context, SubscriberExt},
+            registry::LookupSpan,
+            Layer, Registry,
+        };
+
+        const COUNT: usize = 3;
+
+        /// Name of a span along with the name of its parent
+        type Lineage = (&'static str, Option<String>);
+
+        /// Records the lineage of every span
+        #[derive(Clone, Default)]
+        struct Parents(Arc<StdMutex<Vec<Lineage>>>);
+
+        impl<S> Layer<S> for Parents
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            fn on_new_span(
+                &self,
+                attrs: &span::Attributes<'_>,
+                id: &span::Id,
+                ctx: Context<'_, S>,
+            ) {
+                let parent = ctx
+                    .span(id)
+                    .and_then(|span| span.parent())
+                    .map(|parent| parent.name().to_string());
+
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((attrs.metadata().name(), parent));
+            }
+        }
+
+        let parents = Parents::default();
+        let _guard =
+            subscriber::set_default(Registry::default().with(parents.clone()));
+
+        let (_, handles, system) =
+            create_system(COUNT, |mut connection| async move {
+                connection.send(&0usize).await.expect("send failed");
+            })
+            .await;
+        let config = ManagerConfig::default().node_id("alpha");
+        let handle = SystemManager::<usize>::with_config(system, config)
+            .run(Dummy::default(), AllSampler::default(), 1)
+            .await;
+
+        for _ in 0..COUNT {
+            handle
+                .processor_handle()
+                .deliver()
+                .await
+                .expect("no message");
+        }
+
+        handles.await.expect("system failure");
+
+        let spans = parents.0.lock().unwrap().clone();
+
+        for name in ["network_agent", "process_task"] {
+            let parents = spans
+                .iter()
+                .filter(|(span, _)| *span == name)
+                .map(|(_, parent)| parent.as_deref())
+                .collect::<Vec<_>>();
+
+            assert!(!parents.is_empty(), "no {} span", name);
+            assert!(
+                parents.iter().all(|parent| *parent == Some("node")),
+                "{} outside of node span",
+                name
+            );
+        }
+    }
+
+    /// An in-process link that delivers messages straight to the remote
+    /// `AckProcessor` and can be brought down and up again
+    struct Link {
+        local: PublicKey,
+        up: AtomicBool,
+        remote: OnceLock<(PublicKey, Arc<LinkProcessor>, Arc<Link>)>,
+    }
+
+    type LinkProcessor = AckProcessor<usize, Link, Dummy>;
+
+    impl Link {
+        fn new(local: PublicKey) -> Arc<Self> {
+            Arc::new(Self {
+                local,
+                up: AtomicBool::new(true),
+                remote: OnceLock::new(),
+            })
+        }
+
+        fn set_up(&self, up: bool) {
+            self.up.store(up, Ordering::Release);
+        }
+    }
+
+    #[async_trait]
+    impl Sender<Acked<usize>> for Link {
+        async fn send(
+            &self,
+            message: Acked<usize>,
+            to: &PublicKey,
+        ) -> Result<(), SenderError> {
+            let (remote, processor, link) =
+                self.remote.get().expect("link not connected").clone();
+
+            if *to != remote || !self.up.load(Ordering::Acquire) {
+                return Err(SenderError::NoSuchPeer { remote: *to });
+            }
+
+            let local = self.local;
+
+            task::spawn(async move {
+                let _ = processor.process(message, local, link).await;
+            });
+
+            Ok(())
+        }
+
+        async fn keys(&self) -> Vec<PublicKey> {
+            self.remote.get().map(|x| x.0).into_iter().collect()
+        }
+
+        async fn add_connection(&self, _: ConnectionWrite) {
+            unreachable!()
+        }
+
+        async fn remove_connection(&self, _: &PublicKey) {
+            unreachable!()
+        }
+    }
+
+    async fn ack_pair(
+        delay: Duration,
+        retries: usize,
+    ) -> (
+        (PublicKey, Arc<LinkProcessor>, Arc<Link>),
+        (PublicKey, Arc<LinkProcessor>, Arc<Link>, TestHandle<usize>),
+    ) {
+        let mut keys = keyset(2);
+        let (alice, bob) = (keys.next().unwrap(), keys.next().unwrap());
+        let (alice_link, bob_link) = (Link::new(alice), Link::new(bob));
+        let sampler = Arc::new(AllSampler::default());
+
+        let mut alice_processor =
+            AckProcessor::new(Dummy::default()).with_retries(delay, retries);
+        let mut bob_processor =
+            AckProcessor::new(Dummy::default()).with_retries(delay, retries);
+
+        alice_processor
+            .setup(sampler.clone(), alice_link.clone())
+            .await;
+        let handle = bob_processor.setup(sampler, bob_link.clone()).await;
+
+        let (alice_processor, bob_processor) =
+            (Arc::new(alice_processor), Arc::new(bob_processor));
+
+        alice_link
+            .remote
+            .set((bob, bob_processor.clone(), bob_link.clone()))
+            .ok()
+            .unwrap();
+        bob_link
+            .remote
+            .set((alice, alice_processor.clone(), alice_link.clone()))
+            .ok()
+            .unwrap();
+
+        (
+            (alice, alice_processor, alice_link),
+            (bob, bob_processor, bob_link, handle),
+        )
+    }
+
+    #[tokio::test]
+    async fn acked_exactly_once() {
+        const COUNT: usize = 10;
+
+        init_logger();
+
+        let ((_, alice, alice_link), (bob, _, bob_link, mut handle)) =
+            ack_pair(Duration::from_millis(10), 10).await;
+
+        let sender = alice.sender().expect("not setup");
+
+        // the first attempts never reach bob
+        alice_link.set_up(false);
+        // and the acknowledgements of the next ones are lost
+        bob_link.set_up(false);
+
+        let sends = (0..COUNT)
+            .map(|x| {
+                let sender = sender.clone();
+
+                task::spawn(async move { sender.send_acked(x, &bob).await })
+            })
+            .collect::<Vec<_>>();
+
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        alice_link.set_up(true);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        bob_link.set_up(true);
+
+        for send in sends {
+            send.await
+                .expect("send panicked")
+                .expect("message not acknowledged");
+        }
+
+        let mut messages = Vec::with_capacity(COUNT);
+
+        for _ in 0..COUNT {
+            let (from, message) = handle.deliver().await.expect("no message");
+
+            assert_eq!(from, alice_link.local, "wrong sender");
+            messages.push(message);
+        }
+
+        messages.sort_unstable();
+
+        assert_eq!(messages, (0..COUNT).collect::<Vec<_>>());
+        assert_eq!(sender.pending().await, 0, "pending acknowledgements");
+
+        tokio::time::timeout(Duration::from_millis(100), handle.deliver())
+            .await
+            .expect_err("duplicate message processed");
+    }
+
+    #[tokio::test]
+    async fn acked_budget_exhausted() {
+        const RETRIES: usize = 3;
+
+        let ((_, alice, alice_link), (bob, _, _, _)) =
+            ack_pair(Duration::from_millis(1), RETRIES).await;
+
+        alice_link.set_up(false);
+
+        let sender = alice.sender().expect("not setup");
+
+        match sender.send_acked(0, &bob).await {
+            Err(AckError::Unacknowledged { remote, attempts }) => {
+                assert_eq!(remote, bob, "wrong remote");
+                assert_eq!(attempts, RETRIES + 1, "wrong attempt count");
+            }
+            Ok(()) => panic!("acknowledged unreachable message"),
+        }
+
+        assert_eq!(sender.pending().await, 0, "leaked pending message");
+    }
+
+    #[tokio::test]
+    async fn tester_receives_in_order() {
+        const COUNT: usize = 50;
+
+        let keys = keyset(COUNT).collect::<Vec<_>>();
+        let mut tester = ProcessorTester::new(
+            Dummy::default(),
+            CollectingSender::new(keys.clone()),
+        )
+        .await;
+
+        for (idx, key) in keys.iter().enumerate() {
+            tester
+                .deliver_from(*key, idx)
+                .await
+                .expect("process failed");
+        }
+
+        for (idx, key) in keys.iter().enumerate() {
+            let delivered =
+                tester.handle().deliver().await.expect("no message");
+
+            assert_eq!(delivered, (*key, idx), "incorrect message sequence");
+        }
+
+        assert_eq!(tester.handle().try_deliver().await.unwrap(), None);
+        assert!(tester.sent_messages().await.is_empty(), "dummy sent");
+    }
+
+    #[tokio::test]
+    async fn acked_duplicate_scripted() {
+        let keys = keyset(2).collect::<Vec<_>>();
+        let (alice, bob) = (keys[0], keys[1]);
+        let data = |id, message| Acked::Data { id, message };
+        let mut tester = ProcessorTester::new(
+            AckProcessor::new(Dummy::default()),
+            CollectingSender::new(keys),
+        )
+        .await;
+
+        tester
+            .assert_transcript(
+                vec![
+                    Scripted::Receive(alice, data(0, 5)),
+                    // the acknowledgement was lost and alice sends again
+                    Scripted::Receive(alice, data(0, 5)),
+                    Scripted::Receive(bob, Acked::Plain(7)),
+                    Scripted::Receive(bob, data(0, 8)),
+                ],
+                &[
+                    (alice, Acked::Ack(0)),
+                    (alice, Acked::Ack(0)),
+                    (bob, Acked::Ack(0)),
+                ],
+            )
+            .await;
+
+        assert_eq!(
+            tester.handle().drain(10).await.unwrap(),
+            [(alice, 5), (bob, 7), (bob, 8)],
+            "duplicate message processed"
+        );
+    }
+
+    #[test]
+    fn tie_break_is_symmetric() {
+        let mut keys = keyset(2).collect::<Vec<_>>();
+        keys.sort();
+        let (small, large) = (Some(keys[0]), Some(keys[1]));
+
+        // both peers agree to keep the connection dialed by the smallest key
+        assert!(keep_new(large, small));
+        assert!(!keep_new(small, large));
+        // reconnections from the same initiator replace the old connection
+        assert!(keep_new(small, small));
+        assert!(keep_new(None, large));
+    }
+
+    /// A `Processor` that exposes the `Sender` it was setup with
+    #[derive(Default)]
+    struct Relay {
+        dummy: Dummy,
+        sender: Arc<OnceLock<Arc<NetworkSender<usize>>>>,
+    }
+
+    #[async_trait]
+    impl Processor<usize, usize, (PublicKey, usize), NetworkSender<usize>>
+        for Relay
+    {
+        type Handle = TestHandle<usize>;
+
+        type Error = UnreachableError;
+
+        async fn process(
+            &self,
+            message: usize,
+            key: PublicKey,
+            sender: Arc<NetworkSender<usize>>,
+        ) -> Result<(), Self::Error> {
+            Processor::<usize, usize, _, _>::process(
+                &self.dummy,
+                message,
+                key,
+                sender,
+            )
+            .await
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            sampler: Arc<SA>,
+            sender: Arc<NetworkSender<usize>>,
+        ) -> Self::Handle {
+            self.sender.set(sender.clone()).ok().expect("setup twice");
+
+            Processor::<usize, usize, _, _>::setup(
+                &mut self.dummy,
+                sampler,
+                sender,
+            )
+            .await
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<NetworkSender<usize>>,
+            _: Arc<SA>,
+        ) {
+        }
+
+        async fn garbage_collection(&self) {}
+    }
+
+    type RelayHandle = SystemHandle<
+        Relay,
+        NetworkSender<usize>,
+        usize,
+        (PublicKey, usize),
+        usize,
+    >;
+
+    async fn relay_node(
+        exchanger: Exchanger,
+        addr: std::net::SocketAddr,
+    ) -> (RelayHandle, Arc<NetworkSender<usize>>) {
+        relay_node_with(exchanger, addr, ManagerConfig::default()).await
+    }
+
+    async fn relay_node_with(
+        exchanger: Exchanger,
+        addr: std::net::SocketAddr,
+        config: ManagerConfig,
+    ) -> (RelayHandle, Arc<NetworkSender<usize>>) {
+        let mut system = System::default();
+        let listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+
+        let _ = system.add_listener(listener).await;
+
+        let relay = Relay::default();
+        let sender = relay.sender.clone();
+        let handle = SystemManager::with_config(system, config)
+            .run(relay, AllSampler::default(), 1)
+            .await;
+
+        (handle, sender.get().expect("not setup").clone())
+    }
+
+    /// When `ready_peers_reachable` deems peers ready
+    #[derive(Clone, Copy)]
+    enum Readiness {
+        /// Once the `SystemManager` reports them as ready
+        Announced,
+        /// As soon as their `Connection` is established, which races with
+        /// the `SystemManager` adding them to its `Sender`
+        Connected,
+    }
+
+    /// Connect `count` peers to a new `Relay` and check that they can be
+    /// reached as soon as they are deemed ready according to `readiness`
+    async fn ready_peers_reachable(count: usize, readiness: Readiness) {
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let addr = next_test_ip4();
+        let (handle, sender) = relay_node(exchanger, addr).await;
+
+        let peers = (0..count).map(|_| async move {
+            TcpConnector::new(Exchanger::random())
+                .connect(&pkey, &addr)
+                .await
+                .expect("connect failed")
+        });
+        let _connections = futures::future::join_all(peers).await;
+
+        if let Readiness::Announced = readiness {
+            handle
+                .wait_for_peers(count, None)
+                .await
+                .expect("manager stopped");
+        }
+
+        assert_eq!(
+            sender.keys().await.len(),
+            count,
+            "peer ready before it can be reached"
+        );
+    }
+
+    #[test]
+    fn ready_peers_receive_broadcast() {
+        stress(RUNS, |_| ready_peers_reachable(10, Readiness::Announced));
+    }
+
+    #[test]
+    #[should_panic(expected = "peer ready before it can be reached")]
+    fn stress_catches_announce_race() {
+        init_logger();
+
+        stress(RUNS, |_| ready_peers_reachable(10, Readiness::Connected));
+    }
+
+    #[tokio::test]
+    async fn added_connection_reachable() {
+        const COUNT: usize = 100;
+
+        let alice = Exchanger::random();
+        let (handle, sender) = relay_node(alice.clone(), next_test_ip4()).await;
+        let connector = TcpConnector::new(alice);
+        let mut remotes = Vec::with_capacity(COUNT);
+
+        for i in 0..COUNT {
+            let exchanger = Exchanger::random();
+            let addr = next_test_ip4();
+            let mut listener = TcpListener::new(addr, exchanger.clone())
+                .await
+                .expect("listen failed");
+            let remote = task::spawn(async move { listener.accept().await });
+
+            let connection = connector
+                .connect(exchanger.keypair().public(), &addr)
+                .await
+                .expect("connect failed");
+
+            let pkey = handle
+                .add_connection(connection)
+                .await
+                .expect("failed to add connection");
+
+            assert_eq!(&pkey, exchanger.keypair().public(), "wrong peer");
+
+            sender.send(i, &pkey).await.expect("peer not reachable");
+
+            remotes.push(remote);
+        }
+
+        for remote in remotes {
+            let mut connection = remote
+                .await
+                .expect("listener panicked")
+                .expect("accept failed");
+
+            connection.receive::<usize>().await.expect("no message");
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_limits_override() {
+        let (exchanger, addr) = (Exchanger::random(), next_test_ip4());
+        let server = *exchanger.keypair().public();
+        let default = ConnectionLimits::default().max_message_size(1024);
+        let config = ManagerConfig::default().limits(default.clone());
+        let (handle, _) = relay_node_with(exchanger, addr, config).await;
+        let mut count = handle.on_peer_count();
+        let mut delivery = handle.processor_handle();
+        let client = Exchanger::random();
+        let peer = *client.keypair().public();
+        let mut connection = TcpConnector::new(client)
+            .connect(&server, &addr)
+            .await
+            .expect("connect failed");
+
+        count.wait_for(|c| *c == 1).await.expect("manager stopped");
+
+        assert_eq!(handle.peer_limits(&peer).as_deref(), Some(&default));
+
+        // fits within the default limits, but is not a usize
+        connection.send(&vec![0u8; 64]).await.expect("send failed");
+        connection.send(&1usize).await.expect("send failed");
+
+        assert_eq!(delivery.deliver().await.unwrap(), (peer, 1));
+
+        let limits = ConnectionLimits::default().max_message_size(16);
+
+        handle.set_peer_limits(peer, limits.clone());
+
+        assert_eq!(handle.peer_limits(&peer).as_deref(), Some(&limits));
+        assert_eq!(
+            handle.peer_limits(&keyset(1).next().unwrap()).as_deref(),
+            Some(&default),
+            "override applied to other peers"
+        );
+
+        // the override applies once the message being waited for arrives
+        connection.send(&2usize).await.expect("send failed");
+
+        assert_eq!(delivery.deliver().await.unwrap(), (peer, 2));
+
+        connection.send(&vec![0u8; 64]).await.expect("send failed");
+
+        count.wait_for(|c| *c == 0).await.expect("manager stopped");
+    }
+
+    #[tokio::test]
+    async fn misbehaving_peer_banned() {
+        let alice = Exchanger::random();
+        let pkey = *alice.keypair().public();
+        let addr = next_test_ip4();
+        // scores decay in real time, leave some slack below three violations
+        let scoring = ScoreConfig::default()
+            .threshold(ViolationAction::Disconnect, 25.0)
+            .threshold(ViolationAction::Ban, 25.0);
+        let config = ManagerConfig::default().scoring(scoring);
+        let (handle, _) = relay_node_with(alice, addr, config).await;
+        let mut errors = Box::pin(handle.errors());
+        let mut delivery = handle.processor_handle();
+        let (bad, good) = (Exchanger::random(), Exchanger::random());
+        let bad_key = *bad.keypair().public();
+        let good_key = *good.keypair().public();
+        let bad = TcpConnector::new(bad);
+        let good = TcpConnector::new(good);
+        let mut bad_connection = bad.connect(&pkey, &addr).await.unwrap();
+        let mut good_connection = good.connect(&pkey, &addr).await.unwrap();
+
+        handle
+            .wait_for_peers(2, None)
+            .await
+            .expect("peers not added");
+
+        // an empty frame is too short for any usize
+        for _ in 0..3 {
+            bad_connection.send_frame(&[]).await.expect("send failed");
+        }
+
+        loop {
+            match &*errors.next().await.expect("no violation reported") {
+                SystemError::PeerViolation {
+                    pkey,
+                    action: ViolationAction::Ban,
+                    ..
+                } => {
+                    assert_eq!(*pkey, bad_key, "wrong peer banned");
+                    break;
+                }
+                SystemError::PeerViolation { pkey, .. } => {
+                    assert_eq!(*pkey, bad_key, "wrong peer reported")
+                }
+                _ => {}
+            }
+        }
+
+        bad_connection
+            .receive::<usize>()
+            .await
+            .expect_err("banned peer still connected");
+
+        assert!(handle.scoreboard().is_banned(&bad_key), "peer not banned");
+        let scores = handle.peer_scores();
+
+        assert_eq!(scores.len(), 1, "clean peer scored");
+        assert_eq!(scores[0].0, bad_key, "wrong peer scored");
+
+        let mut reconnected = bad.connect(&pkey, &addr).await.unwrap();
+
+        reconnected
+            .receive::<usize>()
+            .await
+            .expect_err("banned peer reconnected");
+
+        good_connection.send(&7usize).await.expect("send failed");
+
+        assert_eq!(
+            delivery.deliver().await.expect("no delivery"),
+            (good_key, 7),
+            "clean peer affected"
+        );
+    }
+
+    #[tokio::test]
+    async fn receive_after_reconnect() {
+        let registry = MemoryRegistry::new();
+        let exchanger = Exchanger::random();
+        let remote = *exchanger.keypair().public();
+        let mut listener = MemoryListener::new(
+            &registry,
+            (std::net::Ipv4Addr::LOCALHOST, 0).into(),
+            exchanger,
+        )
+        .expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+        let local = Exchanger::random();
+        let connector = MemoryConnector::new(registry.clone(), local.clone());
+        let pacer = Pacer::default();
+        let config = ManagerConfig::default()
+            .reconnect(
+                MemoryConnector::new(registry, local.clone()),
+                HashMap::from([(remote, addr)]),
+            )
+            .reconnect_policy(
+                ReconnectPolicy::default()
+                    .delays(Duration::from_millis(1), Duration::from_millis(1))
+                    .max_attempts(Some(2)),
+            )
+            .reconnect_pacer(pacer.clone());
+        let (handle, sender) =
+            relay_node_with(local, next_test_ip4(), config).await;
+        let mut delivery = handle.processor_handle();
+        let (accepted, connection) =
+            tokio::join!(listener.accept(), connector.connect(&remote, &addr));
+
+        handle
+            .add_connection(connection.expect("connect failed"))
+            .await
+            .expect("add failed");
+
+        drop(accepted.expect("accept failed"));
+
+        let (accepted, sent) =
+            tokio::join!(listener.accept(), sender.send(0, &remote));
+        let mut accepted = accepted.expect("accept failed");
+
+        sent.expect("send failed");
+
+        assert_eq!(accepted.receive::<usize>().await.expect("recv"), 0);
+        assert_eq!(pacer.progress().borrow().connected, 1, "redial unpaced");
+
+        accepted.send(&7usize).await.expect("send failed");
+
+        assert_eq!(
+            delivery.deliver().await.expect("no delivery"),
+            (remote, 7),
+            "message lost after reconnect"
+        );
+    }
+
+    #[tokio::test]
+    async fn add_connection_after_stop() {
+        let (handle, _) =
+            relay_node(Exchanger::random(), next_test_ip4()).await;
+        let exchanger = Exchanger::random();
+        let addr = next_test_ip4();
+        let mut listener = TcpListener::new(addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let remote = task::spawn(async move { listener.accept().await });
+
+        let connection = TcpConnector::new(Exchanger::random())
+            .connect(exchanger.keypair().public(), &addr)
+            .await
+            .expect("connect failed");
+
+        handle
+            .tasks()
+            .expect("already stopped")
+            .reclaim(Duration::ZERO)
+            .await
+            .expect("stop failed");
+
+        let err = handle
+            .add_connection(connection)
+            .await
+            .expect_err("added connection to stopped system");
+
+        assert!(matches!(err, SystemError::ManagerStopped { .. }));
+
+        remote.abort();
+    }
+
+    #[tokio::test]
+    async fn simultaneous_open() {
+        const COUNT: usize = 20;
+
+        init_logger();
+
+        let (alice, bob) = (Exchanger::random(), Exchanger::random());
+        let (alice_key, bob_key) =
+            (*alice.keypair().public(), *bob.keypair().public());
+        let (alice_addr, bob_addr) = (next_test_ip4(), next_test_ip4());
+
+        let (mut alice_handle, alice_sender) =
+            relay_node(alice.clone(), alice_addr).await;
+        let (mut bob_handle, bob_sender) =
+            relay_node(bob.clone(), bob_addr).await;
+
+        let alice_connector = TcpConnector::new(alice);
+        let bob_connector = TcpConnector::new(bob);
+
+        let (to_bob, to_alice) = futures::join!(
+            alice_connector.connect(&bob_key, &bob_addr),
+            bob_connector.connect(&alice_key, &alice_addr)
+        );
+
+        let (added_alice, added_bob) = futures::join!(
+            alice_handle.add_connection(to_bob.expect("connect failed")),
+            bob_handle.add_connection(to_alice.expect("connect failed"))
+        );
+
+        added_alice.expect("failed to add connection");
+        added_bob.expect("failed to add connection");
+
+        for sender in [&alice_sender, &bob_sender] {
+            while sender.keys().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        // send while duplicate connections are still being resolved
+        for i in 0..COUNT {
+            alice_sender.send(i, &bob_key).await.expect("send failed");
+            bob_sender.send(i, &alice_key).await.expect("send failed");
+        }
+
+        for (handle, from) in
+            [(&mut alice_handle, bob_key), (&mut bob_handle, alice_key)]
+        {
+            let mut deliver = handle.processor_handle();
+            let mut messages = Vec::with_capacity(COUNT);
+
+            for _ in 0..COUNT {
+                let (pkey, message) =
+                    deliver.deliver().await.expect("no message");
+
+                assert_eq!(pkey, from, "wrong sender");
+                messages.push(message);
+            }
+
+            messages.sort_unstable();
+
+            assert_eq!(messages, (0..COUNT).collect::<Vec<_>>());
+
+            tokio::time::timeout(Duration::from_millis(100), deliver.deliver())
+                .await
+                .expect_err("duplicate message delivered");
+        }
+
+        assert_eq!(alice_sender.keys().await, vec![bob_key]);
+        assert_eq!(bob_sender.keys().await, vec![alice_key]);
+
+        // the losing connection was closed without disconnecting the peers
+        for handle in [&mut alice_handle, &mut bob_handle] {
+            let mut errors = Box::pin(handle.errors());
+
+            tokio::time::timeout(Duration::from_millis(200), errors.next())
+                .await
+                .expect_err("peer was disconnected");
+        }
+
+        // a message sent after resolution uses the surviving connection
+        alice_sender
+            .send(COUNT, &bob_key)
+            .await
+            .expect("send failed");
+
+        let (_, message) = bob_handle
+            .processor_handle()
+            .deliver()
+            .await
+            .expect("no message");
+
+        assert_eq!(message, COUNT, "wrong message");
+    }
+
+    #[tokio::test]
+    async fn redial_after_tie_break_loser_closed() {
+        let (mut alice, mut bob) = (Exchanger::random(), Exchanger::random());
+
+        if alice.keypair().public() > bob.keypair().public() {
+            std::mem::swap(&mut alice, &mut bob);
+        }
+
+        let (alice_key, bob_key) =
+            (*alice.keypair().public(), *bob.keypair().public());
+        let (alice_addr, bob_addr) = (next_test_ip4(), next_test_ip4());
+        let (handle, sender) = relay_node(alice.clone(), alice_addr).await;
+        let mut listener = TcpListener::new(bob_addr, bob.clone())
+            .await
+            .expect("listen failed");
+
+        let alice_connector = TcpConnector::new(alice);
+
+        // another peer keeps the system running while bob is away
+        let _carol = TcpConnector::new(Exchanger::random())
+            .connect(&alice_key, &alice_addr)
+            .await
+            .expect("connect failed");
+
+        handle
+            .wait_for_peers(1, Some(Duration::from_secs(1)))
+            .await
+            .expect("carol not registered");
+
+        // the first connection is dialed by the smaller key
+        let (accepted, connection) = futures::join!(
+            listener.accept(),
+            alice_connector.connect(&bob_key, &bob_addr)
+        );
+
+        handle
+            .add_connection(connection.expect("connect failed"))
+            .await
+            .expect("add failed");
+
+        let mut errors = Box::pin(handle.errors());
+
+        accepted
+            .expect("accept failed")
+            .close()
+            .await
+            .expect("close failed");
+
+        tokio::time::timeout(Duration::from_secs(1), errors.next())
+            .await
+            .expect("closed connection unnoticed")
+            .expect("no disconnect notice");
+
+        // the larger key redials once the first connection is gone
+        let mut redialed = TcpConnector::new(bob)
+            .connect(&alice_key, &alice_addr)
+            .await
+            .expect("redial failed");
+
+        handle
+            .wait_for_peers(2, Some(Duration::from_secs(1)))
+            .await
+            .expect("redial not registered");
+
+        sender.send(7, &bob_key).await.expect("send failed");
+
+        assert_eq!(
+            redialed.receive::<usize>().await.expect("redial closed"),
+            7
+        );
+    }
+
+    #[tokio::test]
+    async fn crypto_pool_ordering() {
+        const COUNT: usize = 100;
+
+        init_logger();
+
+        let (alice, bob) = (Exchanger::random(), Exchanger::random());
+        let bob_key = *bob.keypair().public();
+        let bob_addr = next_test_ip4();
+        let config = ManagerConfig::default().crypto_pool(2);
+
+        let (bob_handle, _) =
+            relay_node_with(bob, bob_addr, config.clone()).await;
+        let (alice_handle, alice_sender) =
+            relay_node_with(alice.clone(), next_test_ip4(), config).await;
+
+        let connection = TcpConnector::new(alice)
+            .connect(&bob_key, &bob_addr)
+            .await
+            .expect("connect failed");
+
+        alice_handle
+            .add_connection(connection)
+            .await
+            .expect("failed to add connection");
+        alice_handle
+            .wait_for_peers(1, None)
+            .await
+            .expect("bob never connected");
+
+        for i in 0..COUNT {
+            alice_sender.send(i, &bob_key).await.expect("send failed");
         }
-    }
 
-    /// Get [`Handle`] for the [`Processor`] currently running
-    ///
-    /// [`Handle`]: self::Handle
-    /// [`Processor`]: self::Processor
-    pub fn processor_handle(&self) -> P::Handle {
-        self.inner.clone()
-    }
+        let mut deliver = bob_handle.processor_handle();
 
-    /// Force garbage collection of the [`Processor`]
-    ///
-    /// [`Processor`]: self::Processor
-    pub async fn force_gc(&self) {
-        self.processor.garbage_collection().await;
-    }
+        for i in 0..COUNT {
+            let (_, message) = deliver.deliver().await.expect("no message");
 
-    /// Get a `Stream` that will yield all errors encountered in the running [`SystemManager`]
-    ///
-    /// # Note
-    /// The `Stream` of errors can only be taken once, further calls will only return `None`
-    ///
-    /// [`SystemManager`]: self::SystemManager
-    pub fn errors(
-        &mut self,
-    ) -> Option<impl futures::Stream<Item = SystemError<P::Error>>> {
-        self.error_rx.take()
+            assert_eq!(message, i, "message out of order");
+        }
     }
 
-    /// Add a new [`Connection`] to the running [`SystemManager`]
-    ///
-    /// [`Connection`]: crate::net::Connection
-    /// [`SystemManager`]: self::SystemManager
-    pub async fn add_connection(
-        &self,
-        connection: Connection,
-    ) -> Result<(), SystemError<P::Error>> {
-        let key = connection.remote_key().context(Unauthenticated)?;
+    #[tokio::test]
+    async fn wait_for_peers() {
+        init_logger();
 
-        debug!("adding connection from user to {}", key);
+        let (exchanger, addr) = (Exchanger::random(), next_test_ip4());
+        let server = *exchanger.keypair().public();
+        let (handle, _) = relay_node(exchanger, addr).await;
+        let handle = Arc::new(handle);
+        let mut count = handle.on_peer_count();
 
-        self.connections
-            .clone()
-            .send(connection)
+        let connect = || async {
+            TcpConnector::new(Exchanger::random())
+                .connect(&server, &addr)
+                .await
+                .expect("connect failed")
+        };
+
+        let waiting = handle.clone();
+        let mut barrier =
+            task::spawn(async move { waiting.wait_for_peers(3, None).await });
+
+        let first = connect().await;
+        count.wait_for(|c| *c == 1).await.expect("manager stopped");
+        let _second = connect().await;
+        count.wait_for(|c| *c == 2).await.expect("manager stopped");
+
+        // the first peer leaving delays the barrier
+        drop(first);
+        count.wait_for(|c| *c == 1).await.expect("manager stopped");
+
+        let _third = connect().await;
+        count.wait_for(|c| *c == 2).await.expect("manager stopped");
+
+        tokio::time::timeout(Duration::from_millis(100), &mut barrier)
             .await
-            .ok()
-            .context(Channel)?;
+            .expect_err("barrier resolved early");
+
+        let _fourth = connect().await;
+
+        barrier
+            .await
+            .expect("barrier panicked")
+            .expect("barrier failed");
 
-        Ok(())
+        assert_eq!(*count.borrow(), 3, "wrong peer count");
     }
-}
 
-struct NetworkAgent<M, S>
-where
-    S: Sink<Item = (PublicKey, M)>,
-{
-    sender: S,
-    read: ConnectionRead,
-    pkey: PublicKey,
-}
+    #[tokio::test]
+    async fn wait_for_peers_timeout() {
+        let (handle, _) =
+            relay_node(Exchanger::random(), next_test_ip4()).await;
 
-impl<M, S> NetworkAgent<M, S>
-where
-    M: Message + 'static,
-    S: Sink<Item = (PublicKey, M)> + Send + Sync + Unpin + 'static,
-{
-    fn new(read: ConnectionRead, sender: S) -> Self {
-        let pkey = *read.remote_pkey();
+        match handle
+            .wait_for_peers(1, Some(Duration::from_millis(50)))
+            .await
+        {
+            Err(WaitError::Timeout {
+                connected,
+                expected,
+            }) => {
+                assert_eq!(connected, 0, "wrong connected count");
+                assert_eq!(expected, 1, "wrong expected count");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
 
-        Self { sender, read, pkey }
+        handle
+            .wait_for_peers(0, Some(Duration::from_millis(50)))
+            .await
+            .expect("empty barrier did not resolve");
     }
 
-    fn spawn(mut self) -> JoinHandle<PublicKey> {
-        let pkey = self.pkey;
+    #[tokio::test]
+    async fn dump_running() {
+        let (exchanger, addr) = (Exchanger::random(), next_test_ip4());
+        let server = *exchanger.keypair().public();
+        let (handle, sender) = relay_node(exchanger, addr).await;
+        let mut count = handle.on_peer_count();
+        let client = Exchanger::random();
+        let peer = *client.keypair().public();
+        let mut connection = TcpConnector::new(client)
+            .connect(&server, &addr)
+            .await
+            .expect("connect failed");
 
-        task::spawn(
-            async move { self.receive_loop().await }
-                .instrument(debug_span!("network_agent", peer=%pkey)),
-        )
-    }
+        count.wait_for(|c| *c == 1).await.expect("manager stopped");
+        sender.send(7, &peer).await.expect("send failed");
+        assert_eq!(connection.receive::<usize>().await.unwrap(), 7);
 
-    async fn receive_loop(&mut self) -> PublicKey {
-        loop {
-            match self.read.receive::<M>().await {
-                Err(e) => {
-                    error!("connection with failed: {}", e);
-                    return self.pkey;
-                }
-                Ok(message) => {
-                    if self.sender.send((self.pkey, message)).await.is_err() {
-                        warn!("network agent shutting down");
-                    }
-                }
+        let snapshot = handle.dump().await;
+        let key = peer.to_string();
+
+        assert_eq!(
+            snapshot.peers.available().expect("peers unavailable")[&key],
+            PeerSnapshot {
+                reads: 1,
+                writable: true
             }
-        }
+        );
+
+        let queue =
+            &snapshot.sender.available().expect("sender unavailable")[&key];
+
+        assert_eq!((queue.queued, queue.sent, queue.failed), (0, 1, 0));
+        assert!(queue.bytes > 0, "sent bytes not counted");
+        assert!(queue.idle_ms.is_some(), "last send not recorded");
+        assert_eq!(
+            snapshot.scores,
+            ComponentSnapshot::Available(Default::default())
+        );
+
+        let tasks = snapshot.tasks.available().expect("tasks unavailable");
+
+        assert!(tasks.accepting, "not accepting connections");
+        assert_eq!(tasks.listeners, 1, "wrong listener count");
+        assert!(tasks.processing > 0, "no processing task");
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::{
-        error::Error,
-        sync::atomic::{AtomicUsize, Ordering},
-    };
+    #[tokio::test]
+    #[allow(clippy::await_holding_lock)]
+    async fn dump_locked() {
+        let (handle, _) =
+            relay_node(Exchanger::random(), next_test_ip4()).await;
+        let tasks = handle.tasks.lock().unwrap();
+        let snapshot = handle.dump().await;
 
-    use tokio::sync::{mpsc, Mutex};
+        drop(tasks);
 
-    use super::{super::sampler::AllSampler, *};
-    use crate::test::*;
+        assert_eq!(snapshot.tasks, ComponentSnapshot::Unavailable);
+        assert!(snapshot.peers.is_available(), "peers unavailable");
+        assert!(snapshot.sender.is_available(), "sender unavailable");
+        assert!(
+            handle.dump().await.tasks.is_available(),
+            "lock not released"
+        );
+    }
 
-    #[derive(Default)]
-    struct Dummy {
-        sender: Option<mpsc::Sender<(PublicKey, usize)>>,
+    #[test]
+    fn processor_error_display() {
+        let from = keyset(1).next().unwrap();
+        let error = SystemError::ProcessorError {
+            from,
+            message: Some(render_message(&"x".repeat(1000))),
+            source: std::fmt::Error,
+        };
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "failed to process message from {}: an error occurred when \
+                 formatting an argument",
+                from
+            )
+        );
+
+        if let SystemError::ProcessorError {
+            message: Some(message),
+            ..
+        } = error
+        {
+            assert_eq!(message.len(), MAX_RENDERED_MESSAGE + 3);
+            assert!(message.ends_with("..."), "message not truncated");
+        }
+
+        assert_eq!(render_message(&12usize), "12");
     }
 
-    #[derive(Debug)]
-    struct UnreachableError;
-    impl std::fmt::Display for UnreachableError {
-        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            Err(std::fmt::Error)
+    #[message]
+    #[derive(Copy)]
+    struct Upgraded(u64);
+
+    impl From<u64> for Upgraded {
+        fn from(value: u64) -> Self {
+            Self(value)
         }
     }
-    impl Error for UnreachableError {}
+
+    impl From<Upgraded> for u64 {
+        fn from(upgraded: Upgraded) -> Self {
+            upgraded.0
+        }
+    }
+
+    /// Value sent back to a peer once its last message was processed
+    const DONE: u64 = u64::MAX;
+
+    /// A `Processor` that records the values it processes, taking `delay`
+    /// to process each of them, and replies `DONE` after processing `last`
+    struct Recorder {
+        processed: Arc<StdMutex<Vec<u64>>>,
+        delay: Duration,
+        last: u64,
+    }
 
     #[async_trait]
-    impl Processor<usize, usize, (PublicKey, usize), NetworkSender<usize>>
-        for Dummy
+    impl<M, S> Processor<M, M, (PublicKey, M), S> for Recorder
+    where
+        M: Message + From<u64> + Into<u64> + 'static,
+        S: Sender<M> + 'static,
     {
-        type Handle = TestHandle<usize>;
+        type Handle = TestHandle<M>;
 
-        type Error = UnreachableError;
+        type Error = SenderError;
 
         async fn process(
             &self,
-            message: usize,
-            key: PublicKey,
-            _sender: Arc<NetworkSender<usize>>,
+            message: M,
+            from: PublicKey,
+            sender: Arc<S>,
         ) -> Result<(), Self::Error> {
-            self.sender
-                .as_ref()
-                .expect("not setup")
-                .clone()
-                .send((key, message))
-                .await
-                .expect("channel failure");
+            let value = message.into();
+
+            time::sleep(self.delay).await;
+            self.processed.lock().unwrap().push(value);
+
+            if value == self.last {
+                sender.send(M::from(DONE), &from).await?;
+            }
 
             Ok(())
         }
 
         async fn setup<SA: Sampler>(
             &mut self,
-            _sampler: Arc<SA>,
-            _sender: Arc<NetworkSender<usize>>,
+            _: Arc<SA>,
+            _: Arc<S>,
         ) -> Self::Handle {
-            let (tx, rx) = mpsc::channel(128);
-
-            self.sender.replace(tx);
-
-            let channel = Arc::new(Mutex::new(rx));
+            let (_, rx) = mpsc::channel(1);
 
-            TestHandle { channel }
+            TestHandle {
+                channel: Arc::new(Mutex::new(rx)),
+            }
         }
 
         async fn disconnect<SA: Sampler>(
             &self,
             _: PublicKey,
-            _: Arc<NetworkSender<usize>>,
+            _: Arc<S>,
             _: Arc<SA>,
         ) {
-            unreachable!()
         }
 
-        async fn garbage_collection(&self) {
-            unreachable!()
-        }
+        async fn garbage_collection(&self) {}
     }
 
-    #[derive(Clone)]
-    struct TestHandle<M>
-    where
-        M: Message,
-    {
-        channel: Arc<Mutex<mpsc::Receiver<(PublicKey, M)>>>,
-    }
+    #[tokio::test]
+    async fn upgrade_processor() {
+        const COUNT: u64 = 50;
 
-    #[async_trait]
-    impl<M> Handle<M, (PublicKey, M)> for TestHandle<M>
-    where
-        M: Message,
-    {
-        type Error = UnreachableError;
+        let (_, peer, system) = create_system(1, |mut connection| async move {
+            for value in 0..COUNT {
+                connection.send(&value).await.expect("send failed");
+            }
 
-        async fn deliver(&mut self) -> Result<(PublicKey, M), Self::Error> {
-            Ok(self.channel.lock().await.recv().await.expect("no message"))
-        }
+            let done = connection
+                .receive::<u64>()
+                .await
+                .expect("peer observed a disconnect");
 
-        async fn try_deliver(
-            &mut self,
-        ) -> Result<Option<(PublicKey, M)>, Self::Error> {
-            unreachable!()
-        }
+            assert_eq!(done, DONE, "wrong reply");
+        })
+        .await;
 
-        async fn broadcast(&mut self, _: &M) -> Result<(), Self::Error> {
-            unreachable!()
+        let before = Arc::new(StdMutex::new(Vec::new()));
+        let after = Arc::new(StdMutex::new(Vec::new()));
+
+        let handle = SystemManager::<u64>::new(system)
+            .run(
+                Recorder {
+                    processed: before.clone(),
+                    delay: Duration::from_millis(20),
+                    last: COUNT - 1,
+                },
+                AllSampler::default(),
+                1,
+            )
+            .await;
+
+        while before.lock().unwrap().is_empty() {
+            time::sleep(Duration::from_millis(5)).await;
         }
+
+        let reclaimed = handle
+            .into_parts(Duration::from_millis(10))
+            .await
+            .expect("failed to reclaim system");
+
+        assert_eq!(reclaimed.connections().len(), 1, "connection lost");
+        assert!(!reclaimed.backlog().is_empty(), "all messages processed");
+
+        let _handle = SystemManager::<Upgraded>::from_reclaimed(reclaimed)
+            .run(
+                Recorder {
+                    processed: after.clone(),
+                    delay: Duration::ZERO,
+                    last: COUNT - 1,
+                },
+                AllSampler::default(),
+                1,
+            )
+            .await;
+
+        time::timeout(Duration::from_secs(10), peer)
+            .await
+            .expect("peer not answered")
+            .expect("peer failed");
+
+        let mut processed = before.lock().unwrap().clone();
+
+        processed.extend(after.lock().unwrap().iter());
+
+        assert_eq!(processed, (0..COUNT).collect::<Vec<_>>());
     }
 
     #[tokio::test]
-    async fn receive_from_manager() {
-        static COUNTER: AtomicUsize = AtomicUsize::new(0);
-        const COUNT: usize = 50;
+    async fn upgrade_keeps_listening() {
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let addr = next_test_ip4();
+        let (handle, _) = relay_node(exchanger, addr).await;
 
-        let (pkeys, handles, system) =
-            create_system(COUNT, |mut connection| async move {
-                let value = COUNTER.fetch_add(1, Ordering::AcqRel);
+        let connect = || async move {
+            TcpConnector::new(Exchanger::random())
+                .connect(&pkey, &addr)
+                .await
+                .expect("connect failed")
+        };
 
-                debug!("sending {:?}", value);
-                connection.send(&value).await.expect("recv failed");
+        let mut early = connect().await;
 
-                debug!("done sending");
-            })
-            .await;
+        handle
+            .wait_for_peers(1, Some(Duration::from_secs(5)))
+            .await
+            .expect("peer not connected");
 
-        let sampler = AllSampler::default();
-        let processor = Dummy::default();
-        let manager = SystemManager::new(system);
+        let reclaimed = handle
+            .into_parts(Duration::from_secs(1))
+            .await
+            .expect("failed to reclaim system");
 
-        debug!("manager created");
+        assert_eq!(reclaimed.connections().len(), 1, "connection lost");
 
-        debug!("registering processor");
+        let upgraded = SystemManager::<usize>::from_reclaimed(reclaimed)
+            .run(Dummy::default(), AllSampler::default(), 1)
+            .await;
+        let mut late = connect().await;
 
-        let system_handle = manager.run(processor, sampler, 1).await;
-        let mut handle = system_handle.processor_handle();
+        upgraded
+            .wait_for_peers(2, Some(Duration::from_secs(5)))
+            .await
+            .expect("listener lost");
 
-        let mut messages = Vec::with_capacity(COUNT);
+        early.send(&1usize).await.expect("send failed");
+        late.send(&2usize).await.expect("send failed");
 
-        for _ in 0..COUNT {
-            let (pkey, message) =
-                handle.deliver().await.expect("unexpected error");
+        let mut received = vec![
+            upgraded.processor_handle().deliver().await.unwrap().1,
+            upgraded.processor_handle().deliver().await.unwrap().1,
+        ];
 
-            assert!(
-                pkeys.iter().any(|(key, _)| *key == pkey),
-                "bad message sender"
-            );
+        received.sort_unstable();
 
-            messages.push(message);
-        }
+        assert_eq!(received, [1, 2]);
+    }
 
-        messages.sort_unstable();
+    #[tokio::test]
+    async fn into_parts_twice() {
+        let exchanger = Exchanger::random();
+        let (handle, _) = relay_node(exchanger, next_test_ip4()).await;
 
-        assert_eq!(
-            messages,
-            (0..COUNT).collect::<Vec<_>>(),
-            "incorrect message sequence"
-        );
+        // the tasks are already taken when shutting down a `Node`
+        let _tasks = handle.tasks().expect("tasks taken");
 
-        handles.await.expect("system failure");
+        assert!(matches!(
+            handle.into_parts(Duration::ZERO).await,
+            Err(ShutdownError::AlreadyStopped)
+        ));
     }
 
-    #[tokio::test]
-    async fn disconnect_notice() {
-        static COUNT: usize = 50;
+    fn test_handle() -> (mpsc::Sender<(PublicKey, usize)>, TestHandle<usize>) {
+        let (tx, rx) = mpsc::channel(16);
+        let channel = Arc::new(Mutex::new(rx));
 
-        use std::collections::HashSet;
+        (tx, TestHandle { channel })
+    }
 
-        crate::test::init_logger();
+    #[tokio::test(start_paused = true)]
+    async fn deliver_timeout_expires() {
+        let (_tx, mut handle) = test_handle();
+
+        assert_eq!(
+            handle
+                .deliver_timeout(Duration::from_secs(1))
+                .await
+                .unwrap(),
+            None,
+            "delivered without messages"
+        );
+    }
 
-        let (pkeys, handles, system) = create_system(COUNT, |_| async {}).await;
+    #[tokio::test(start_paused = true)]
+    async fn deliver_timeout_race() {
+        let (tx, mut handle) = test_handle();
+        let pkey = *Exchanger::random().keypair().public();
+        let late = tx.clone();
 
-        let manager = SystemManager::<usize>::new(system);
-        let processor = Dummy::default();
+        task::spawn(async move {
+            time::sleep(Duration::from_millis(500)).await;
+            tx.send((pkey, 1)).await.unwrap();
+        });
+
+        assert_eq!(
+            handle
+                .deliver_timeout(Duration::from_secs(1))
+                .await
+                .unwrap(),
+            Some((pkey, 1))
+        );
 
-        let mut system_handle =
-            manager.run(processor, AllSampler::default(), 1).await;
+        task::spawn(async move {
+            time::sleep(Duration::from_secs(2)).await;
+            late.send((pkey, 2)).await.unwrap();
+        });
 
-        let source = system_handle.errors().unwrap();
+        assert_eq!(
+            handle
+                .deliver_timeout(Duration::from_secs(1))
+                .await
+                .unwrap(),
+            None
+        );
 
-        let actual = source
-            .map(|x| match x {
-                SystemError::Disconnected { pkey } => pkey,
-                e => panic!("bad error type: {}", e),
-            })
-            .collect::<HashSet<_>>()
-            .await;
+        // a message arriving after the timeout is kept for the next call
+        assert_eq!(
+            handle
+                .deliver_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+            Some((pkey, 2))
+        );
+    }
 
-        assert_eq!(actual.len(), COUNT, "wrong number of disconnect notice");
+    #[tokio::test]
+    async fn drain_buffered() {
+        let (tx, mut handle) = test_handle();
+        let pkey = *Exchanger::random().keypair().public();
 
-        let expected = pkeys.into_iter().map(|x| x.0).collect::<HashSet<_>>();
+        for i in 0..3 {
+            tx.send((pkey, i)).await.unwrap();
+        }
 
-        assert_eq!(actual, expected, "missing some disconnect notices");
+        let drained = handle.drain(2).await.unwrap();
 
-        handles.await.expect("system failure");
+        assert_eq!(drained, [(pkey, 0), (pkey, 1)]);
+        assert_eq!(handle.drain(10).await.unwrap(), [(pkey, 2)]);
+        assert!(handle.drain(10).await.unwrap().is_empty(), "drained twice");
     }
 }
@@ -1,6 +1,15 @@
-use std::{collections::HashMap, fmt, future::Future, net::Ipv4Addr};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io::Error as IoError,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use futures::stream::{select_all, FuturesUnordered, Stream, StreamExt};
+use snafu::Snafu;
 use tokio::{
     sync::mpsc,
     task::{self, JoinHandle},
@@ -10,8 +19,13 @@ use tracing::{debug_span, error, info, warn};
 use tracing_futures::Instrument;
 
 use crate::{
-    crypto::key::exchange::PublicKey,
-    net::{ConnectError, Connection, Connector, Listener, ListenerError},
+    crypto::key::exchange::{KeyPair, PublicKey},
+    net::{
+        AcceptBackoff, CandidateVerifier, ConnectError, Connection,
+        ConnectionLimits, Connector, ContactCard, ContactError, Listener,
+        ListenerError, Pacer, Reachability, Verification,
+    },
+    telemetry::targets,
 };
 
 /// System manager and related traits
@@ -26,59 +40,163 @@ pub use sender::*;
 mod sampler;
 pub use sampler::*;
 
+/// Vote counting utilities for `Processor`s
+mod quorum;
+pub use quorum::*;
+
+/// Ordered shutdown of a running node
+mod node;
+pub use node::*;
+
+/// Config-driven startup of a `System` with diagnostics
+mod startup;
+pub use startup::*;
+
+/// Tracking of protocol violations committed by peers
+mod score;
+pub use score::*;
+
+/// Per-peer state following the peers known by a `Sender`
+mod state;
+pub use state::*;
+
+/// Retention and replay of the errors of a `SystemManager`
+mod events;
+pub use events::{LogEntry, DEFAULT_ERROR_RETENTION};
+
+/// Snapshots of the state of a running node for post-mortem analysis
+mod dump;
+pub use dump::*;
+
+/// Ordering decisions controlled by deterministic test runs
+pub(crate) mod schedule;
+
+/// Static overlays restricting which peers are connected
+mod topology;
+pub use topology::*;
+
+/// Dispatching of messages to different handlers by content
+mod router;
+pub use router::*;
+
+/// Recording of the messages handled by a `SystemManager` for offline replay
+mod replay;
+pub use replay::*;
+
+/// Shared scheduling of reconnections to many peers
+mod reconnect;
+pub use reconnect::*;
+
 /// Easy import path to use the system functionnality from drop
 pub mod prelude {
-    pub use super::{manager::*, sampler::*, sender::*};
+    pub use super::{
+        dump::*, events::*, manager::*, node::*, quorum::*, reconnect::*,
+        replay::*, router::*, sampler::*, score::*, sender::*, startup::*,
+        state::*, topology::*,
+    };
+}
+
+#[derive(Debug, Snafu)]
+/// Errors encountered when creating a [`System`]
+///
+/// [`System`]: self::System
+pub enum SetupError {
+    #[snafu(display(
+        "only {} out of {} required peers connected",
+        connected,
+        required
+    ))]
+    /// Too few initial peers could be connected to
+    QuorumNotReached {
+        /// Number of peers that were connected
+        connected: usize,
+        /// Minimum number of peers that had to be connected
+        required: usize,
+        /// Peers that could not be connected along with the reason
+        failures: Vec<(PublicKey, ConnectError)>,
+    },
 }
 
 /// A representation of a distributed `System` that manages connections to and
 /// from other peers.
+#[derive(Default)]
 pub struct System {
     connections: HashMap<PublicKey, Connection>,
     listeners: Vec<JoinHandle<Result<(), ListenerError>>>,
     _listener_handles: Vec<JoinHandle<Result<(), ListenerError>>>,
     peer_input: Vec<mpsc::Receiver<Connection>>,
+    identity: Option<KeyPair>,
+    listening: Vec<SocketAddr>,
+    external: Vec<SocketAddr>,
+    verification: Option<Verification>,
+    overlay: Option<Overlay>,
 }
 
 impl System {
     /// Create a new `System` using an `Iterator` over pairs of `PublicKey`s and
-    /// `Connection` `Future`s
+    /// `Connection` `Future`s. Peers that could not be connected are left
+    /// out, see `System::try_new` to find out which.
     pub async fn new<
         I: IntoIterator<Item = (PublicKey, F)>,
         F: Future<Output = Result<Connection, ConnectError>>,
     >(
         initial: I,
     ) -> Self {
-        let iter = initial.into_iter();
+        Self::try_new(initial).await.0
+    }
 
-        let connections = iter
+    /// Create a new `System` using an `Iterator` over pairs of `PublicKey`s and
+    /// `Connection` `Future`s. This also returns the peers that could not be
+    /// connected along with the corresponding error, in no particular order.
+    pub async fn try_new<
+        I: IntoIterator<Item = (PublicKey, F)>,
+        F: Future<Output = Result<Connection, ConnectError>>,
+    >(
+        initial: I,
+    ) -> (Self, Vec<(PublicKey, ConnectError)>) {
+        let mut results = initial
+            .into_iter()
             .map(|x| async move {
                 (
-                    x.1.instrument(debug_span!("system_connect", dest = %x.0))
-                        .await,
+                    x.1.instrument(debug_span!(
+                        target: targets::MANAGER,
+                        "system_connect",
+                        dest = %x.0
+                    ))
+                    .await,
                     x.0,
                 )
             })
-            .collect::<FuturesUnordered<_>>()
-            .filter_map(|(result, pkey)| async move {
-                match result {
-                    Ok(connection) => {
-                        info!("connected to {}", pkey);
-                        Some((pkey, connection))
-                    }
-                    Err(e) => {
-                        error!("failed to connect to {}: {}", pkey, e);
-                        None
-                    }
+            .collect::<FuturesUnordered<_>>();
+
+        let mut connections = HashMap::new();
+        let mut failures = Vec::new();
+
+        while let Some((result, pkey)) = results.next().await {
+            match result {
+                Ok(connection) => {
+                    info!(target: targets::MANAGER, "connected to {}", pkey);
+                    connections.insert(pkey, connection);
                 }
-            })
-            .collect::<HashMap<_, _>>()
-            .await;
+                Err(e) => {
+                    error!(
+                        target: targets::MANAGER,
+                        phase = ?e.phase(),
+                        "failed to connect to {}: {}",
+                        pkey,
+                        e
+                    );
+                    failures.push((pkey, e));
+                }
+            }
+        }
 
-        Self {
+        let system = Self {
             connections,
             ..Default::default()
-        }
+        };
+
+        (system, failures)
     }
 
     /// Create a new `System` using a list of peers and some `Connector`
@@ -90,7 +208,23 @@ impl System {
         connector: &C,
         peers: I,
     ) -> Self {
-        Self::new(peers.into_iter().map(|(pkey, candidate)| {
+        Self::try_new_with_connector_zipped(connector, peers)
+            .await
+            .0
+    }
+
+    /// Create a new `System` using a list of peers and some `Connector`,
+    /// returning the peers that could not be connected as well. See
+    /// `System::try_new` for more details.
+    pub async fn try_new_with_connector_zipped<
+        C: Connector<Candidate = CD>,
+        CD: fmt::Display + Send + Sync,
+        I: IntoIterator<Item = (PublicKey, CD)>,
+    >(
+        connector: &C,
+        peers: I,
+    ) -> (Self, Vec<(PublicKey, ConnectError)>) {
+        Self::try_new(peers.into_iter().map(|(pkey, candidate)| {
             (
                 pkey,
                 async move { connector.connect(&pkey, &candidate).await },
@@ -111,13 +245,113 @@ impl System {
         pkeys: I1,
         candidates: I2,
     ) -> Self {
-        Self::new_with_connector_zipped(
+        Self::try_new_with_connector(connector, pkeys, candidates)
+            .await
+            .0
+    }
+
+    /// Create a new `System` from an iterator of `Candidate`s and another of
+    /// `PublicKey`s, returning the peers that could not be connected as well.
+    /// See `System::try_new` for more details.
+    pub async fn try_new_with_connector<
+        C: Connector<Candidate = CD>,
+        CD: fmt::Display + Send + Sync,
+        I1: IntoIterator<Item = PublicKey>,
+        I2: IntoIterator<Item = CD>,
+    >(
+        connector: &C,
+        pkeys: I1,
+        candidates: I2,
+    ) -> (Self, Vec<(PublicKey, ConnectError)>) {
+        Self::try_new_with_connector_zipped(
             connector,
             pkeys.into_iter().zip(candidates),
         )
         .await
     }
 
+    /// Create a new `System` that only connects to the neighbors assigned to
+    /// `local` by `topology` among `peers`. Each pair of neighbors is
+    /// connected once and `Listener`s added afterwards reject `Connection`s
+    /// from peers that are not expected to dial this one.
+    pub async fn new_with_topology<C, CD, T>(
+        connector: &C,
+        topology: T,
+        local: PublicKey,
+        peers: Vec<(PublicKey, CD)>,
+    ) -> Self
+    where
+        C: Connector<Candidate = CD>,
+        CD: fmt::Display + Send + Sync,
+        T: Topology + 'static,
+    {
+        let keys = peers.iter().map(|(pkey, _)| *pkey).collect::<Vec<_>>();
+        let overlay = Overlay::new(Arc::new(topology), local, &keys);
+        let mut system = Self::new_with_connector_zipped(
+            connector,
+            peers.into_iter().filter(|(pkey, _)| overlay.dials(pkey)),
+        )
+        .await;
+
+        system.overlay = Some(overlay);
+
+        system
+    }
+
+    /// Get the `Overlay` this `System` was created with, if any
+    pub fn overlay(&self) -> Option<&Overlay> {
+        self.overlay.as_ref()
+    }
+
+    /// Create a new `System` using a list of peers and some `Connector`,
+    /// failing if fewer than `min_connected` peers could be connected. The
+    /// `Connection`s that were opened are closed in that case.
+    pub async fn new_with_quorum<
+        C: Connector<Candidate = CD>,
+        CD: fmt::Display + Send + Sync,
+        I: IntoIterator<Item = (PublicKey, CD)>,
+    >(
+        connector: &C,
+        peers: I,
+        min_connected: usize,
+    ) -> Result<Self, SetupError> {
+        let (mut system, failures) =
+            Self::try_new_with_connector_zipped(connector, peers).await;
+        let connected = system.connections.len();
+
+        if connected >= min_connected {
+            return Ok(system);
+        }
+
+        warn!(
+            target: targets::MANAGER,
+            "only connected to {} out of {} required peers",
+            connected, min_connected
+        );
+
+        system
+            .connections
+            .iter_mut()
+            .map(|(pkey, connection)| async move {
+                if let Err(e) = connection.close().await {
+                    warn!(
+                        target: targets::MANAGER,
+                        "failed to close connection to {}: {}", pkey, e
+                    );
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+            .await;
+
+        QuorumNotReached {
+            connected,
+            required: min_connected,
+            failures,
+        }
+        .fail()
+    }
+
     /// Add a new peer into the `System` using the provided `Candidate` and
     /// `Connector`
     pub async fn add_peer<CD, C>(
@@ -130,7 +364,8 @@ impl System {
         CD: fmt::Display + Send + Sync,
         C: Connector<Candidate = CD>,
     {
-        let connection = connector.connect_any(public, candidates).await?;
+        let connection =
+            connector.connect_any(public, candidates, None).await?;
 
         self.connections.insert(*public, connection);
 
@@ -143,22 +378,45 @@ impl System {
         connector: &C,
         candidates: &[(CD, PublicKey)],
     ) -> impl Iterator<Item = (PublicKey, ConnectError)>
+    where
+        CD: fmt::Display + Send + Sync,
+        C: Connector<Candidate = CD>,
+    {
+        self.add_peers_paced(connector, candidates, &Pacer::default())
+            .await
+    }
+
+    /// Add many peers to this `System` like `System::add_peers`, starting
+    /// dials when `pacer` allows it. The number of dials started and finished
+    /// so far can be watched using `Pacer::progress`.
+    pub async fn add_peers_paced<CD, C>(
+        &mut self,
+        connector: &C,
+        candidates: &[(CD, PublicKey)],
+        pacer: &Pacer,
+    ) -> impl Iterator<Item = (PublicKey, ConnectError)>
     where
         CD: fmt::Display + Send + Sync,
         C: Connector<Candidate = CD>,
     {
         let (ok, err): (Vec<_>, Vec<_>) = connector
-            .connect_many(candidates)
+            .connect_many_paced(candidates, pacer)
             .await
             .into_iter()
             .zip(candidates.iter().map(|x| x.1))
             .map(|(result, pkey)| match result {
                 Ok(connection) => {
-                    info!("connected to {}", pkey);
+                    info!(target: targets::MANAGER, "connected to {}", pkey);
                     Ok((pkey, connection))
                 }
                 Err(e) => {
-                    error!("failed to connect to {}: {}", pkey, e);
+                    error!(
+                        target: targets::MANAGER,
+                        phase = ?e.phase(),
+                        "failed to connect to {}: {}",
+                        pkey,
+                        e
+                    );
                     Err((pkey, e))
                 }
             })
@@ -169,40 +427,224 @@ impl System {
         err.into_iter().map(Result::unwrap_err)
     }
 
+    /// Add a new peer to this `System` using a `ContactCard` obtained out of
+    /// band, see `ContactCard::connect`
+    pub async fn add_peer_from_card<C>(
+        &mut self,
+        connector: &C,
+        card: &ContactCard,
+    ) -> Result<PublicKey, ContactError>
+    where
+        C: Connector<Candidate = SocketAddr>,
+    {
+        let connection = card.connect(connector).await?;
+        let public = *card.public();
+
+        info!(
+            target: targets::MANAGER,
+            "connected to {} from contact card", public
+        );
+        self.connections.insert(public, connection);
+
+        Ok(public)
+    }
+
+    /// Add external addresses on which this `System` can be reached, that
+    /// will be advertised by its `ContactCard`s after the candidates of its
+    /// `Listener`s
+    pub fn add_external_addrs(
+        &mut self,
+        addrs: impl IntoIterator<Item = SocketAddr>,
+    ) {
+        self.external.extend(addrs);
+    }
+
+    /// Have the directory server at `helper` check which addresses
+    /// advertised by the `ContactCard`s of this `System` are reachable using
+    /// `verifier`. Verified addresses are then advertised first, or
+    /// exclusively if `verifier` is exclusive.
+    pub async fn verify_candidates<C>(
+        &mut self,
+        connector: &C,
+        helper: SocketAddr,
+        verifier: &CandidateVerifier,
+    ) -> Result<Verification, ConnectError>
+    where
+        C: Connector<Candidate = SocketAddr> + ?Sized,
+    {
+        let verification = verifier
+            .verify(connector, helper, &self.candidates())
+            .await?;
+
+        self.verification = Some(verification.clone());
+
+        Ok(verification)
+    }
+
+    /// Get the addresses advertised by the `ContactCard`s of this `System`
+    /// tagged with whether they were verified, see
+    /// `System::verify_candidates`
+    pub fn verification(&self) -> Verification {
+        let candidates = self.candidates();
+
+        match &self.verification {
+            Some(verification) => verification.apply(&candidates),
+            None => Verification::new(
+                candidates
+                    .into_iter()
+                    .map(|addr| (addr, Reachability::Unverified)),
+                false,
+            ),
+        }
+    }
+
+    /// Get a `ContactCard` signed by the key of the first `Listener` added to
+    /// this `System` that lists the candidates of its `Listener`s that are
+    /// socket addresses followed by its external addresses. Once verified
+    /// using `System::verify_candidates`, reachable addresses come first.
+    pub fn contact_card(
+        &self,
+        expiry: Option<SystemTime>,
+    ) -> Result<ContactCard, ContactError> {
+        let keypair = self.identity.as_ref().ok_or(ContactError::NoIdentity)?;
+        let candidates = self.verification().advertised();
+        let card = ContactCard::new(*keypair.public(), candidates);
+        let card = match expiry {
+            Some(expiry) => card.with_expiry(expiry),
+            None => card,
+        };
+
+        card.sign(keypair)
+    }
+
+    fn candidates(&self) -> Vec<SocketAddr> {
+        let mut candidates = Vec::new();
+
+        for addr in self.listening.iter().chain(self.external.iter()) {
+            if addr.port() != 0
+                && !addr.ip().is_unspecified()
+                && !candidates.contains(addr)
+            {
+                candidates.push(*addr);
+            }
+        }
+
+        candidates
+    }
+
     /// Add a `Listener` to this `System` that will accept incoming peer
-    /// `Connection`s
+    /// `Connection`s. The `Listener` runs in the current span, see
+    /// `ManagerConfig::span`.
     pub async fn add_listener<C, L>(
         &mut self,
-        mut listener: L,
+        listener: L,
     ) -> impl Stream<Item = ListenerError>
     where
         C: fmt::Display + Sync + Send,
         L: Listener<Candidate = C> + 'static,
     {
+        self.spawn_listener(listener, None).await
+    }
+
+    /// Add a `Listener` to this `System` like `System::add_listener`, applying
+    /// the given `ConnectionLimits` to every `Connection` it accepts. The
+    /// handshake timeout only applies if it is also set on the `Listener`
+    /// since `Connection`s are secured before being handed over.
+    pub async fn add_listener_with_limits<C, L>(
+        &mut self,
+        listener: L,
+        limits: ConnectionLimits,
+    ) -> impl Stream<Item = ListenerError>
+    where
+        C: fmt::Display + Sync + Send,
+        L: Listener<Candidate = C> + 'static,
+    {
+        self.spawn_listener(listener, Some(Arc::new(limits))).await
+    }
+
+    async fn spawn_listener<C, L>(
+        &mut self,
+        mut listener: L,
+        limits: Option<Arc<ConnectionLimits>>,
+    ) -> ReceiverStream<ListenerError>
+    where
+        C: fmt::Display + Sync + Send,
+        L: Listener<Candidate = C> + 'static,
+    {
+        if self.identity.is_none() {
+            self.identity = Some(listener.exchanger().keypair().clone());
+        }
+
+        match listener.candidates().await {
+            Ok(candidates) => self.listening.extend(
+                candidates
+                    .iter()
+                    .filter_map(|c| c.to_string().parse::<SocketAddr>().ok()),
+            ),
+            Err(e) => {
+                warn!(
+                    target: targets::MANAGER,
+                    "listener has no candidates: {}", e
+                )
+            }
+        }
+
         let (err_tx, err_rx) = mpsc::channel(1);
         let (peer_tx, peer_rx) = mpsc::channel(32);
+        let overlay = self.overlay.clone();
+
+        let accept = async move {
+            let local = listener
+                .local_addr()
+                .unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, 0).into());
+            let mut backoff = AcceptBackoff::default();
+
+            loop {
+                match listener.accept().await {
+                    Err(e) => {
+                        if backoff.recover(&e, local).await {
+                            continue;
+                        }
 
-        let handle =
-            task::spawn(async move {
-                loop {
-                    match listener.accept().await {
-                        Err(e) => {
-                            if let Err(e) = err_tx.send(e).await {
-                                warn!(
-                                    "lost error from listener on {}: {}",
-                                    listener.local_addr().unwrap_or_else(
-                                        || (Ipv4Addr::UNSPECIFIED, 0).into()
-                                    ),
-                                    e,
-                                );
-                            }
+                        if let Err(e) = err_tx.send(e).await {
+                            warn!(
+                                target: targets::MANAGER,
+                                phase = ?e.0.phase(),
+                                "lost error from listener on {}: {}",
+                                local,
+                                e.0,
+                            );
                         }
-                        Ok(connection) => {
-                            let _ = peer_tx.send(connection).await;
+                    }
+                    Ok(mut connection) => {
+                        backoff.reset();
+
+                        if let Some(remote) =
+                            connection.remote_key().filter(|remote| {
+                                overlay
+                                    .as_ref()
+                                    .is_some_and(|o| !o.accepts(remote))
+                            })
+                        {
+                            warn!(
+                                target: targets::MANAGER,
+                                "rejecting {} outside of overlay on {}",
+                                remote, local
+                            );
+                            let _ = connection.close().await;
+                            continue;
+                        }
+
+                        if let Some(limits) = &limits {
+                            connection.set_limits(limits.clone());
                         }
+
+                        let _ = peer_tx.send(connection).await;
                     }
                 }
-            });
+            }
+        };
+        let handle = task::spawn(accept.in_current_span());
 
         self.peer_input.push(peer_rx);
         self.listeners.push(handle);
@@ -210,10 +652,53 @@ impl System {
         ReceiverStream::new(err_rx)
     }
 
+    /// Get the `PublicKey` of every peer this `System` is currently
+    /// connected to, without removing their `Connection`s
+    pub fn peer_keys(&self) -> Vec<PublicKey> {
+        let mut keys = self.connections.keys().copied().collect::<Vec<_>>();
+
+        schedule::arrange(&mut keys, |pkey| *pkey);
+
+        keys
+    }
+
+    /// Remove the peer with the given `PublicKey` from this `System`,
+    /// returning its `Connection` if it was connected. Dropping the returned
+    /// `Connection` closes it abruptly, see `System::disconnect_peer` to close
+    /// it gracefully instead.
+    pub fn remove_peer(&mut self, key: &PublicKey) -> Option<Connection> {
+        self.connections.remove(key)
+    }
+
+    /// Remove the peer with the given `PublicKey` from this `System` and
+    /// gracefully close its `Connection`. Nothing happens if this `System`
+    /// was not connected to it.
+    pub async fn disconnect_peer(
+        &mut self,
+        key: &PublicKey,
+    ) -> Result<(), IoError> {
+        let Some(mut connection) = self.remove_peer(key) else {
+            return Ok(());
+        };
+
+        info!(target: targets::MANAGER, "disconnecting from {}", key);
+
+        connection.close().await
+    }
+
     /// Get all the `Connection`s known to this `System`.
     /// The returned `Connection`s will be removed from the system.
     pub fn connections(&mut self) -> Vec<Connection> {
-        self.connections.drain().map(|x| x.1).collect()
+        let mut connections = self.connections.drain().collect::<Vec<_>>();
+
+        schedule::arrange(&mut connections, |(pkey, _)| *pkey);
+
+        connections.into_iter().map(|x| x.1).collect()
+    }
+
+    /// Take the tasks running the `Listener`s of this `System`
+    fn listeners(&mut self) -> Vec<JoinHandle<Result<(), ListenerError>>> {
+        self.listeners.drain(..).collect()
     }
 
     /// Get a `Stream` that produces incoming `Connection`s from all registered
@@ -224,17 +709,6 @@ impl System {
     }
 }
 
-impl Default for System {
-    fn default() -> Self {
-        Self {
-            connections: Default::default(),
-            listeners: Default::default(),
-            _listener_handles: Vec::new(),
-            peer_input: Vec::new(),
-        }
-    }
-}
-
 impl From<Vec<Connection>> for System {
     fn from(connections: Vec<Connection>) -> Self {
         Self {
@@ -249,15 +723,50 @@ impl From<Vec<Connection>> for System {
 
 #[cfg(test)]
 mod tests {
+    use async_trait::async_trait;
     use futures::StreamExt;
 
+    use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+    use tokio::time;
+
     use super::*;
     use crate::{
         crypto::key::exchange::Exchanger,
-        net::{TcpConnector, TcpListener},
+        net::{
+            server::DirectoryServer, ReceiveError, TcpConnector, TcpListener,
+        },
         test::*,
     };
 
+    /// Start `live` listeners that wait for their connection to be closed and
+    /// pick `dead` addresses without listeners
+    async fn mixed_peers(
+        live: usize,
+        dead: usize,
+    ) -> (
+        Vec<(PublicKey, JoinHandle<()>)>,
+        Vec<(PublicKey, SocketAddr)>,
+    ) {
+        let addrs = test_addrs(live + dead);
+        let peers = addrs
+            .iter()
+            .map(|(exchanger, addr)| (*exchanger.keypair().public(), *addr))
+            .collect();
+        let receivers = create_receivers(
+            addrs.into_iter().take(live),
+            |mut connection| async move {
+                connection
+                    .receive::<usize>()
+                    .await
+                    .expect_err("connection was not closed");
+            },
+        )
+        .await;
+
+        (receivers, peers)
+    }
+
     #[tokio::test]
     async fn add_peers() {
         init_logger();
@@ -302,6 +811,79 @@ mod tests {
         assert_eq!(connections.len(), 11, "not all connections opened");
     }
 
+    #[tokio::test]
+    async fn try_new_reports_failures() {
+        let (receivers, peers) = mixed_peers(3, 2).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let (mut system, failures) =
+            System::try_new_with_connector_zipped(&connector, peers.clone())
+                .await;
+
+        let connected = system
+            .connections()
+            .iter()
+            .map(|c| c.remote_key().expect("unsecured connection"))
+            .collect::<HashSet<_>>();
+        let failed = failures
+            .iter()
+            .map(|(pkey, _)| *pkey)
+            .collect::<HashSet<_>>();
+        let (live, dead) = peers.split_at(receivers.len());
+
+        assert_eq!(connected, live.iter().map(|x| x.0).collect());
+        assert_eq!(failed, dead.iter().map(|x| x.0).collect());
+
+        for (pkey, error) in failures {
+            match error {
+                ConnectError::Io { source } => assert_eq!(
+                    source.kind(),
+                    std::io::ErrorKind::ConnectionRefused,
+                    "wrong error for {}",
+                    pkey
+                ),
+                other => panic!("unexpected error {}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn quorum_not_reached() {
+        let (receivers, peers) = mixed_peers(2, 3).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        match System::new_with_quorum(&connector, peers, 3).await {
+            Err(SetupError::QuorumNotReached {
+                connected,
+                required,
+                failures,
+            }) => {
+                assert_eq!((connected, required), (2, 3), "wrong counts");
+                assert_eq!(failures.len(), 3, "wrong failures");
+            }
+            Ok(_) => panic!("quorum reached with too few peers"),
+        }
+
+        for (_, handle) in receivers {
+            time::timeout(Duration::from_secs(5), handle)
+                .await
+                .expect("connection left open")
+                .expect("receiver failed");
+        }
+    }
+
+    #[tokio::test]
+    async fn quorum_reached() {
+        let (_receivers, peers) = mixed_peers(2, 1).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let mut system = System::new_with_quorum(&connector, peers, 2)
+            .await
+            .expect("quorum not reached");
+
+        assert_eq!(system.connections().len(), 2, "wrong connections");
+    }
+
     #[tokio::test]
     async fn add_listener() {
         let mut system = System::default();
@@ -339,4 +921,238 @@ mod tests {
             "different addresses"
         );
     }
+
+    #[tokio::test]
+    async fn bootstrap_from_card() {
+        let listener = TcpListener::new(next_test_ip4(), Exchanger::random())
+            .await
+            .expect("listen failed");
+        let mut server = System::default();
+        let _errors = server.add_listener(listener).await;
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+
+        // only the string form of the card is shared between both nodes
+        let uri = server
+            .contact_card(Some(expiry))
+            .expect("no contact card")
+            .to_string();
+
+        let card: ContactCard = uri.parse().expect("invalid card");
+        let mut client = System::default();
+        let connector = TcpConnector::new(Exchanger::random());
+
+        let pkey = client
+            .add_peer_from_card(&connector, &card)
+            .await
+            .expect("bootstrap failed");
+
+        assert_eq!(pkey, *card.public(), "wrong peer added");
+
+        let mut outgoing = client.connections().pop().expect("no connection");
+        let mut incoming = server
+            .peer_source()
+            .next()
+            .await
+            .expect("no incoming connection");
+
+        outgoing.send(&7usize).await.expect("send failed");
+
+        assert_eq!(
+            incoming.receive::<usize>().await.expect("receive failed"),
+            7
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_candidates_are_excluded() {
+        let server = next_test_ip4();
+        let (dir_server, exit) = DirectoryServer::new(Box::new(
+            TcpListener::new(server, Exchanger::random())
+                .await
+                .expect("listen failed"),
+        ));
+        let handle = task::spawn(dir_server.serve());
+        let listener = TcpListener::new(next_test_ip4(), Exchanger::random())
+            .await
+            .expect("listen failed");
+        let reachable = listener.local_addr().expect("no address");
+        let bogus = next_test_ip4();
+        let mut system = System::default();
+        let _errors = system.add_listener(listener).await;
+        let connector = TcpConnector::new(Exchanger::random());
+
+        system.add_external_addrs(Some(bogus));
+
+        assert_eq!(
+            system.contact_card(None).unwrap().candidates(),
+            [reachable, bogus]
+        );
+
+        let verifier = CandidateVerifier::new().exclusive(true);
+        let verification = system
+            .verify_candidates(&connector, server, &verifier)
+            .await
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.reachability(&bogus),
+            Some(Reachability::Unverified)
+        );
+        assert_eq!(
+            system.contact_card(None).unwrap().candidates(),
+            [reachable]
+        );
+
+        exit.send(()).expect("exit failed");
+        handle
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+
+    /// A `Processor` whose `Handle` broadcasts to every peer of its
+    /// `NetworkSender`
+    struct Broadcast;
+
+    #[derive(Clone)]
+    struct BroadcastHandle(Arc<NetworkSender<usize>>);
+
+    #[async_trait]
+    impl Handle<usize, ()> for BroadcastHandle {
+        type Error = SenderError;
+
+        async fn deliver(&mut self) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        async fn try_deliver(&mut self) -> Result<Option<()>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn broadcast(
+            &mut self,
+            message: &usize,
+        ) -> Result<(), Self::Error> {
+            let keys = self.0.keys().await;
+
+            self.0.send_many_collecting(*message, keys.iter()).await
+        }
+    }
+
+    #[async_trait]
+    impl Processor<usize, usize, (), NetworkSender<usize>> for Broadcast {
+        type Handle = BroadcastHandle;
+
+        type Error = SenderError;
+
+        async fn process(
+            &self,
+            _: usize,
+            _: PublicKey,
+            _: Arc<NetworkSender<usize>>,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _: Arc<SA>,
+            sender: Arc<NetworkSender<usize>>,
+        ) -> Self::Handle {
+            BroadcastHandle(sender)
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<NetworkSender<usize>>,
+            _: Arc<SA>,
+        ) {
+        }
+
+        async fn garbage_collection(&self) {}
+    }
+
+    #[tokio::test]
+    async fn remove_peer() {
+        let mut addrs = test_addrs(3);
+        let removed = addrs.split_off(1);
+        let candidates = addrs
+            .iter()
+            .chain(removed.iter())
+            .map(|(exchanger, addr)| (*addr, *exchanger.keypair().public()))
+            .collect::<Vec<_>>();
+        let mut kept =
+            create_receivers(addrs.into_iter(), |mut connection| async move {
+                let data = connection
+                    .receive::<usize>()
+                    .await
+                    .expect("receive failed");
+
+                assert_eq!(data, 7, "wrong data received");
+            })
+            .await;
+        let closed = create_receivers(
+            removed.into_iter(),
+            |mut connection| async move {
+                assert!(
+                    matches!(
+                        connection.receive::<usize>().await,
+                        Err(ReceiveError::Closed)
+                    ),
+                    "connection was not closed"
+                );
+            },
+        )
+        .await;
+        let mut system = System::default();
+        let connector = TcpConnector::new(Exchanger::random());
+
+        assert_eq!(system.add_peers(&connector, &candidates).await.count(), 0);
+
+        let (kept_key, kept_handle) = kept.remove(0);
+        let (abrupt, graceful) = (closed[0].0, closed[1].0);
+        let mut keys = system.peer_keys();
+
+        keys.sort();
+
+        let mut expected = vec![kept_key, abrupt, graceful];
+
+        expected.sort();
+
+        assert_eq!(keys, expected, "wrong peers");
+
+        drop(system.remove_peer(&abrupt).expect("peer not connected"));
+        system
+            .disconnect_peer(&graceful)
+            .await
+            .expect("disconnect failed");
+
+        assert!(system.remove_peer(&abrupt).is_none(), "peer not removed");
+        assert_eq!(system.peer_keys(), [kept_key], "peers not removed");
+
+        for (_, handle) in closed {
+            handle.await.expect("removed peer not closed");
+        }
+
+        let handle = SystemManager::new(system)
+            .run(Broadcast, AllSampler::default(), 1)
+            .await;
+
+        handle
+            .processor_handle()
+            .broadcast(&7)
+            .await
+            .expect("broadcast failed");
+
+        kept_handle.await.expect("kept peer failed");
+    }
+
+    #[test]
+    fn contact_card_needs_listener() {
+        assert!(matches!(
+            System::default().contact_card(None),
+            Err(ContactError::NoIdentity)
+        ));
+    }
 }
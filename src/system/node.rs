@@ -0,0 +1,611 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{info, warn};
+
+use super::{NetworkSender, Processor, SystemHandle};
+use crate::{
+    net::{
+        server::{HealthServer, ServerError},
+        DirectoryRegistration,
+    },
+    telemetry::targets,
+    Message,
+};
+
+/// A stage of the shutdown sequence of a [`Node`], in execution order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Removing the [`Node`] from the directory servers it registered with,
+    /// so that peers stop dialing it
+    Deregister,
+    /// Stopping `Listener`s from accepting new `Connection`s
+    StopListeners,
+    /// Processing messages that were already received
+    Drain,
+    /// Sending queued messages and closing outgoing `Connection`s
+    CloseSenders,
+    /// Waiting for all tasks to exit
+    Join,
+}
+
+/// Outcome of a shutdown [`Stage`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The stage completed
+    Done,
+    /// There was nothing to do for this stage
+    Skipped,
+    /// The stage did not complete within the grace period
+    TimedOut,
+    /// The stage failed for the given reason
+    Failed(String),
+}
+
+/// Summary of the shutdown of a [`Node`] or of a [`SystemHandle`]
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownReport {
+    stages: Vec<(Stage, Outcome)>,
+    pub(super) dropped_messages: usize,
+    pub(super) dropped_connections: usize,
+    pub(super) closed_connections: usize,
+}
+
+impl ShutdownReport {
+    pub(super) fn record(&mut self, stage: Stage, outcome: Outcome) {
+        match outcome {
+            Outcome::Done | Outcome::Skipped => {
+                info!(
+                    target: targets::NODE,
+                    "shutdown stage {:?}: {:?}", stage, outcome
+                )
+            }
+            _ => {
+                warn!(
+                    target: targets::NODE,
+                    "shutdown stage {:?}: {:?}", stage, outcome
+                )
+            }
+        }
+
+        self.stages.push((stage, outcome));
+    }
+
+    /// Outcome of all executed stages, in execution order
+    pub fn stages(&self) -> &[(Stage, Outcome)] {
+        &self.stages
+    }
+
+    /// Outcome of a given `Stage`, if it was executed
+    pub fn outcome(&self, stage: Stage) -> Option<&Outcome> {
+        self.stages
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// Number of received messages that were never processed
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped_messages
+    }
+
+    /// Number of incoming `Connection`s that were still open when the
+    /// [`Node`] stopped receiving
+    pub fn dropped_connections(&self) -> usize {
+        self.dropped_connections
+    }
+
+    /// Number of outgoing `Connection`s that were flushed and closed
+    pub fn closed_connections(&self) -> usize {
+        self.closed_connections
+    }
+
+    /// Check if every stage succeeded and no message was dropped
+    pub fn is_clean(&self) -> bool {
+        self.dropped_messages == 0
+            && self.stages.iter().all(|(_, outcome)| {
+                matches!(outcome, Outcome::Done | Outcome::Skipped)
+            })
+    }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} stages, {} messages dropped, {} connections dropped, {} \
+             connections closed",
+            self.stages.len(),
+            self.dropped_messages,
+            self.dropped_connections,
+            self.closed_connections
+        )
+    }
+}
+
+/// A running node made of a [`SystemManager`], the directory servers it
+/// registered with and an optional [`HealthServer`]. This takes care of
+/// shutting everything down in the right order, see [`Node::shutdown`].
+///
+/// [`SystemManager`]: super::SystemManager
+/// [`HealthServer`]: crate::net::server::HealthServer
+pub struct Node<P, I, O, M>
+where
+    P: Processor<M, I, O, NetworkSender<M>>,
+    P::Error: Send + Sync + 'static,
+    O: Send,
+    M: Message + From<I> + 'static,
+{
+    handle: SystemHandle<P, NetworkSender<M>, I, O, M>,
+    registrations: Vec<DirectoryRegistration>,
+    health: Option<Health>,
+}
+
+/// A `HealthServer` spawned by a `Node`
+struct Health {
+    addr: SocketAddr,
+    /// Set once the `Node` starts shutting down so that it stops being ready
+    stopping: Arc<AtomicBool>,
+    exit: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), ServerError>>,
+}
+
+impl Health {
+    async fn stop(self) {
+        let _ = self.exit.send(());
+
+        match self.task.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!(target: targets::NODE, "health server failed: {}", e)
+            }
+            Err(e) => {
+                warn!(target: targets::NODE, "health server panicked: {}", e)
+            }
+        }
+    }
+}
+
+impl<P, I, O, M> Node<P, I, O, M>
+where
+    P: Processor<M, I, O, NetworkSender<M>> + Send,
+    P::Error: Send + Sync + 'static,
+    I: Send,
+    O: Send,
+    M: Message + From<I> + 'static,
+{
+    /// Create a new `Node` from the [`SystemHandle`] of a running
+    /// [`SystemManager`]
+    ///
+    /// [`SystemManager`]: super::SystemManager
+    pub fn new(handle: SystemHandle<P, NetworkSender<M>, I, O, M>) -> Self {
+        Self {
+            handle,
+            registrations: Vec::new(),
+            health: None,
+        }
+    }
+
+    /// Create a new `Node` from the [`SystemHandle`] of a running
+    /// [`SystemManager`], serving health probes on the address set using
+    /// [`ManagerConfig::health_addr`] if any, see [`Node::with_health`]
+    ///
+    /// [`SystemManager`]: super::SystemManager
+    /// [`ManagerConfig::health_addr`]: super::ManagerConfig::health_addr
+    pub async fn start(
+        handle: SystemHandle<P, NetworkSender<M>, I, O, M>,
+    ) -> Result<Self, ServerError> {
+        let addr = handle.configured_health_addr();
+        let node = Self::new(handle);
+
+        match addr {
+            Some(addr) => node.with_health(addr).await,
+            None => Ok(node),
+        }
+    }
+
+    /// Serve liveness and readiness probes for this `Node` on `addr` using a
+    /// [`HealthServer`]. The `Node` is ready once it is connected to at least
+    /// one peer and stops being ready as soon as it starts shutting down.
+    ///
+    /// [`HealthServer`]: crate::net::server::HealthServer
+    pub async fn with_health(
+        mut self,
+        addr: SocketAddr,
+    ) -> Result<Self, ServerError> {
+        let (server, exit) = HealthServer::bind(addr).await?;
+        let addr = server.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let peers = self.handle.on_peer_count();
+        let draining = stopping.clone();
+        let server = server.readiness(move || {
+            !draining.load(Ordering::Acquire) && *peers.borrow() > 0
+        });
+
+        info!(target: targets::NODE, "serving health probes on {}", addr);
+
+        if let Some(previous) = self.health.take() {
+            previous.stop().await;
+        }
+
+        self.health = Some(Health {
+            addr,
+            stopping,
+            exit,
+            task: tokio::spawn(server.serve()),
+        });
+
+        Ok(self)
+    }
+
+    /// Get the address health probes are served on, if any, see
+    /// [`Node::with_health`]
+    pub fn health_addr(&self) -> Option<SocketAddr> {
+        self.health.as_ref().map(|health| health.addr)
+    }
+
+    /// Withdraw the given `DirectoryRegistration` when shutting down
+    pub fn with_registration(
+        mut self,
+        registration: DirectoryRegistration,
+    ) -> Self {
+        self.registrations.push(registration);
+        self
+    }
+
+    /// Get the [`SystemHandle`] of this `Node`
+    pub fn handle(&self) -> &SystemHandle<P, NetworkSender<M>, I, O, M> {
+        &self.handle
+    }
+
+    /// Get a mutable reference to the [`SystemHandle`] of this `Node`
+    pub fn handle_mut(
+        &mut self,
+    ) -> &mut SystemHandle<P, NetworkSender<M>, I, O, M> {
+        &mut self.handle
+    }
+
+    /// Shut this `Node` down by executing every [`Stage`] in order: remove
+    /// this `Node` from directories and then shut its [`SystemHandle`] down,
+    /// see [`SystemHandle::shutdown`]. The health server, if any, reports
+    /// the `Node` as not ready from the start and is stopped last.
+    pub async fn shutdown(self, grace: Duration) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+
+        if let Some(health) = &self.health {
+            health.stopping.store(true, Ordering::Release);
+        }
+
+        let outcome = if self.registrations.is_empty() {
+            Outcome::Skipped
+        } else {
+            let total = self.registrations.len();
+            let failed = future::join_all(
+                self.registrations
+                    .into_iter()
+                    .map(DirectoryRegistration::deregister),
+            )
+            .await
+            .into_iter()
+            .filter(|removed| !removed)
+            .count();
+
+            if failed == 0 {
+                Outcome::Done
+            } else {
+                Outcome::Failed(format!(
+                    "{} out of {} directories did not remove this node",
+                    failed, total
+                ))
+            }
+        };
+
+        report.record(Stage::Deregister, outcome);
+
+        let report = self.handle.shutdown_with(report, grace).await;
+
+        if let Some(health) = self.health {
+            health.stop().await;
+        }
+
+        info!(target: targets::NODE, "node shut down: {}", report);
+
+        report
+    }
+
+    /// Wait for Ctrl-C to be received and then shut this `Node` down, see
+    /// [`Node::shutdown`]
+    #[cfg(feature = "signal")]
+    pub async fn run_until_signal(self, grace: Duration) -> ShutdownReport {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!(
+                target: targets::NODE,
+                "failed to listen for Ctrl-C, shutting down now: {}", e
+            );
+        } else {
+            info!(target: targets::NODE, "received Ctrl-C, shutting down");
+        }
+
+        self.shutdown(grace).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, net::SocketAddr, sync::Arc};
+
+    use futures::StreamExt;
+    use tokio::{task, time};
+
+    use super::*;
+    use crate::{
+        async_trait,
+        crypto::key::exchange::{Exchanger, PublicKey},
+        net::common::directory::{Request, Response},
+        net::{
+            server::DirectoryServer, Connection, Connector, DirectoryListener,
+            TcpConnector, TcpListener,
+        },
+        system::{
+            AllSampler, Handle, ManagerConfig, Sampler, System, SystemError,
+            SystemManager,
+        },
+        test::*,
+    };
+
+    #[derive(Clone)]
+    struct NoHandle;
+
+    #[async_trait]
+    impl Handle<u64, ()> for NoHandle {
+        type Error = io::Error;
+
+        async fn deliver(&mut self) -> Result<(), Self::Error> {
+            Err(io::Error::other("nothing to deliver"))
+        }
+
+        async fn try_deliver(&mut self) -> Result<Option<()>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn broadcast(&mut self, _: &u64) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// A `Processor` that ignores every message
+    struct Ignore;
+
+    #[async_trait]
+    impl Processor<u64, u64, (), NetworkSender<u64>> for Ignore {
+        type Handle = NoHandle;
+
+        type Error = io::Error;
+
+        async fn process(
+            &self,
+            _: u64,
+            _: PublicKey,
+            _: Arc<NetworkSender<u64>>,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _: Arc<SA>,
+            _: Arc<NetworkSender<u64>>,
+        ) -> Self::Handle {
+            NoHandle
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<NetworkSender<u64>>,
+            _: Arc<SA>,
+        ) {
+        }
+
+        async fn garbage_collection(&self) {}
+    }
+
+    async fn fetch(directory: SocketAddr, pkey: PublicKey) -> Response {
+        let connector = TcpConnector::new(Exchanger::random());
+        let mut connection = Connection::new(
+            connector
+                .establish(&pkey, &directory)
+                .await
+                .expect("connect failed"),
+        );
+
+        connection
+            .send_plain(&Request::Fetch(pkey))
+            .await
+            .expect("send failed");
+
+        connection.receive_plain().await.expect("receive failed")
+    }
+
+    async fn probe(addr: SocketAddr, path: &str) -> io::Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut response = String::new();
+
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+            .await?;
+        stream.read_to_string(&mut response).await?;
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn health_probes() {
+        init_logger();
+
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let addr = next_test_ip4();
+        let listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+        let mut system = System::default();
+        let _ = system.add_listener(listener).await;
+        let health = next_test_ip4();
+        let config = ManagerConfig::default().health_addr(health);
+        let handle = SystemManager::with_config(system, config)
+            .run(Ignore, AllSampler::default(), 1)
+            .await;
+        let node = Node::start(handle).await.expect("bind failed");
+
+        assert_eq!(node.health_addr(), Some(health));
+
+        let live = probe(health, "/healthz").await.expect("probe failed");
+        let ready = probe(health, "/readyz").await.expect("probe failed");
+
+        assert!(live.starts_with("HTTP/1.1 200"), "not live: {}", live);
+        assert!(ready.starts_with("HTTP/1.1 503"), "ready without peers");
+
+        let system = System::new_with_connector_zipped(
+            &TcpConnector::new(Exchanger::random()),
+            vec![(pkey, addr)],
+        )
+        .await;
+        let _peer = SystemManager::new(system)
+            .run(Ignore, AllSampler::default(), 1)
+            .await;
+
+        node.handle()
+            .wait_for_peers(1, Some(Duration::from_secs(5)))
+            .await
+            .expect("peer did not connect");
+
+        let ready = probe(health, "/readyz").await.expect("probe failed");
+
+        assert!(ready.starts_with("HTTP/1.1 200"), "not ready: {}", ready);
+
+        node.shutdown(Duration::from_secs(1)).await;
+
+        probe(health, "/healthz")
+            .await
+            .expect_err("health server still running");
+    }
+
+    #[tokio::test]
+    async fn ordered_shutdown() {
+        init_logger();
+
+        let dir_addr = next_test_ip4();
+        let dir_listener = TcpListener::new(dir_addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(dir_listener));
+        let server = task::spawn(server.serve());
+
+        let exchanger = Exchanger::random();
+        let departing = *exchanger.keypair().public();
+        let addr = next_test_ip4();
+        let listener = TcpListener::new(addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let mut listener = DirectoryListener::new(
+            listener,
+            TcpConnector::new(exchanger),
+            dir_addr,
+        )
+        .await
+        .expect("register failed");
+        let registration = listener.registration().expect("no registration");
+
+        time::timeout(Duration::from_secs(5), async {
+            while !matches!(
+                fetch(dir_addr, departing).await,
+                Response::FoundOne(_, found) if found == addr
+            ) {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("node not registered");
+
+        let mut system = System::default();
+        let _ = system.add_listener(listener).await;
+        let handle = SystemManager::new(system)
+            .run(Ignore, AllSampler::default(), 1)
+            .await;
+        let node = Node::new(handle).with_registration(registration);
+
+        let system = System::new_with_connector_zipped(
+            &TcpConnector::new(Exchanger::random()),
+            vec![(departing, addr)],
+        )
+        .await;
+        let surviving = SystemManager::new(system)
+            .run(Ignore, AllSampler::default(), 1)
+            .await;
+        let mut errors = Box::pin(surviving.errors());
+
+        for handle in [node.handle(), &surviving] {
+            handle
+                .wait_for_peers(1, Some(Duration::from_secs(5)))
+                .await
+                .expect("peers did not connect");
+        }
+
+        let report = node.shutdown(Duration::from_secs(1)).await;
+
+        assert!(report.is_clean(), "unclean shutdown: {:?}", report);
+        assert_eq!(
+            report
+                .stages()
+                .iter()
+                .map(|(stage, _)| *stage)
+                .collect::<Vec<_>>(),
+            vec![
+                Stage::Deregister,
+                Stage::StopListeners,
+                Stage::Drain,
+                Stage::CloseSenders,
+                Stage::Join
+            ],
+            "wrong stage order"
+        );
+        assert_eq!(report.outcome(Stage::Deregister), Some(&Outcome::Done));
+        assert_eq!(report.closed_connections(), 1, "wrong closed count");
+
+        let notice = time::timeout(Duration::from_secs(5), errors.next())
+            .await
+            .expect("no disconnect notice")
+            .expect("error stream ended");
+
+        match &*notice {
+            SystemError::Closed { pkey } => {
+                assert_eq!(*pkey, departing, "wrong peer closed")
+            }
+            other => panic!("expected clean close, got {}", other),
+        }
+
+        assert_eq!(
+            fetch(dir_addr, departing).await,
+            Response::NotFound(departing),
+            "directory still lists departed node"
+        );
+
+        exit.send(()).expect("server already stopped");
+        server
+            .await
+            .expect("server panicked")
+            .expect("server failed");
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::Sender;
+use crate::{crypto::key::exchange::PublicKey, Message};
+
+/// Number of independently locked shards used by a `QuorumTracker`
+const SHARDS: usize = 16;
+
+/// Outcome of recording a vote in a `QuorumTracker`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuorumState<V> {
+    /// No value has gathered enough votes yet
+    NotReached,
+    /// This vote made the value reach the quorum. This is returned exactly
+    /// once per context.
+    Reached(V),
+    /// A quorum was already reached for this context by an earlier vote
+    AlreadyReached,
+    /// The voter already voted for a different value in this context, the new
+    /// vote was ignored
+    Equivocation {
+        /// The misbehaving voter
+        voter: PublicKey,
+    },
+}
+
+/// Size of the quorum used by a `QuorumTracker`
+pub enum QuorumSize {
+    /// A fixed number of distinct voters
+    Absolute(usize),
+    /// A size computed from the current number of known peers
+    Relative(Box<dyn Fn(usize) -> usize + Send + Sync>),
+}
+
+impl QuorumSize {
+    /// A quorum computed from the number of known peers
+    pub fn relative<F>(f: F) -> Self
+    where
+        F: Fn(usize) -> usize + Send + Sync + 'static,
+    {
+        Self::Relative(Box::new(f))
+    }
+
+    fn threshold(&self, peers: usize) -> usize {
+        match self {
+            Self::Absolute(size) => *size,
+            Self::Relative(f) => f(peers),
+        }
+        .max(1)
+    }
+}
+
+impl fmt::Debug for QuorumSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Absolute(size) => write!(f, "absolute({})", size),
+            Self::Relative(_) => write!(f, "relative"),
+        }
+    }
+}
+
+struct Votes<V> {
+    voters: HashMap<PublicKey, V>,
+    counts: HashMap<V, usize>,
+    reached: bool,
+    created: Instant,
+    sequence: u64,
+}
+
+impl<V> Votes<V> {
+    fn new(sequence: u64) -> Self {
+        Self {
+            voters: HashMap::new(),
+            counts: HashMap::new(),
+            reached: false,
+            created: Instant::now(),
+            sequence,
+        }
+    }
+}
+
+/// Collects votes from distinct peers for each context (e.g. a round
+/// identifier) until a quorum is reached for some value. Repeated votes from a
+/// voter are ignored and voters sending different values for the same
+/// context are reported. Contexts are spread over independently locked
+/// shards so that the tracker can be shared between processing tasks.
+pub struct QuorumTracker<K, V> {
+    shards: Vec<Mutex<HashMap<K, Votes<V>>>>,
+    size: QuorumSize,
+    peers: AtomicUsize,
+    sequence: AtomicU64,
+    max_age: Option<Duration>,
+    max_contexts: Option<usize>,
+}
+
+impl<K, V> QuorumTracker<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Hash + Eq + Clone,
+{
+    /// Create a new `QuorumTracker` using the given quorum size
+    pub fn new(size: QuorumSize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            size,
+            peers: AtomicUsize::new(0),
+            sequence: AtomicU64::new(0),
+            max_age: None,
+            max_contexts: None,
+        }
+    }
+
+    /// Create a new `QuorumTracker` requiring a fixed number of votes
+    pub fn with_size(size: usize) -> Self {
+        Self::new(QuorumSize::Absolute(size))
+    }
+
+    /// Evict contexts older than `age` when garbage collecting
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Only keep the `count` most recent contexts when garbage collecting
+    pub fn max_contexts(mut self, count: usize) -> Self {
+        self.max_contexts = Some(count);
+        self
+    }
+
+    /// Set the number of known peers used to compute a relative quorum size
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.store(peers, Ordering::Relaxed);
+    }
+
+    /// Update the number of known peers from the given `Sender`
+    pub async fn refresh<M, S>(&self, sender: &S)
+    where
+        M: Message + 'static,
+        S: Sender<M>,
+    {
+        self.set_peers(sender.keys().await.len());
+    }
+
+    /// Current number of votes needed to reach a quorum
+    pub fn threshold(&self) -> usize {
+        self.size.threshold(self.peers.load(Ordering::Relaxed))
+    }
+
+    /// Record a vote from `voter` for `value` in the given `context`
+    pub fn record(
+        &self,
+        context: K,
+        voter: PublicKey,
+        value: V,
+    ) -> QuorumState<V> {
+        let threshold = self.threshold();
+        let mut shard = self.shard(&context).lock().expect("poisoned shard");
+        let votes = shard.entry(context).or_insert_with(|| {
+            Votes::new(self.sequence.fetch_add(1, Ordering::Relaxed))
+        });
+
+        match votes.voters.get(&voter) {
+            Some(previous) if *previous != value => {
+                return QuorumState::Equivocation { voter };
+            }
+            Some(_) => {
+                return if votes.reached {
+                    QuorumState::AlreadyReached
+                } else {
+                    QuorumState::NotReached
+                };
+            }
+            None => {}
+        }
+
+        votes.voters.insert(voter, value.clone());
+
+        let count = votes.counts.entry(value.clone()).or_insert(0);
+        *count += 1;
+
+        if votes.reached {
+            QuorumState::AlreadyReached
+        } else if *count >= threshold {
+            votes.reached = true;
+            QuorumState::Reached(value)
+        } else {
+            QuorumState::NotReached
+        }
+    }
+
+    /// Number of distinct voters for `value` in `context`
+    pub fn votes(&self, context: &K, value: &V) -> usize {
+        self.shard(context)
+            .lock()
+            .expect("poisoned shard")
+            .get(context)
+            .and_then(|votes| votes.counts.get(value).copied())
+            .unwrap_or(0)
+    }
+
+    /// Check if a quorum was reached in `context`
+    pub fn is_reached(&self, context: &K) -> bool {
+        self.shard(context)
+            .lock()
+            .expect("poisoned shard")
+            .get(context)
+            .is_some_and(|votes| votes.reached)
+    }
+
+    /// Forget all votes for `context`
+    pub fn remove(&self, context: &K) {
+        self.shard(context)
+            .lock()
+            .expect("poisoned shard")
+            .remove(context);
+    }
+
+    /// Number of contexts currently tracked
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().expect("poisoned shard").len())
+            .sum()
+    }
+
+    /// Check if no context is currently tracked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Evict old contexts according to the configured age and count limits.
+    /// This is meant to be called from `Processor::garbage_collection`.
+    pub fn garbage_collection(&self) {
+        if let Some(age) = self.max_age {
+            for shard in &self.shards {
+                shard
+                    .lock()
+                    .expect("poisoned shard")
+                    .retain(|_, votes| votes.created.elapsed() < age);
+            }
+        }
+
+        if let Some(count) = self.max_contexts {
+            let mut sequences = self
+                .shards
+                .iter()
+                .flat_map(|shard| {
+                    shard
+                        .lock()
+                        .expect("poisoned shard")
+                        .values()
+                        .map(|votes| votes.sequence)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            if sequences.len() <= count {
+                return;
+            }
+
+            sequences.sort_unstable();
+
+            let oldest_kept = sequences[sequences.len() - count.max(1)];
+
+            for shard in &self.shards {
+                shard
+                    .lock()
+                    .expect("poisoned shard")
+                    .retain(|_, votes| votes.sequence >= oldest_kept);
+            }
+        }
+    }
+
+    fn shard(&self, context: &K) -> &Mutex<HashMap<K, Votes<V>>> {
+        let mut hasher = DefaultHasher::new();
+
+        context.hash(&mut hasher);
+
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl<K, V> fmt::Debug for QuorumTracker<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuorumTracker")
+            .field("size", &self.size)
+            .field("peers", &self.peers.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{system::CollectingSender, test::keyset};
+
+    #[test]
+    fn exact_threshold() {
+        let tracker = QuorumTracker::with_size(3);
+        let mut voters = keyset(4);
+
+        for _ in 0..2 {
+            let state = tracker.record(0, voters.next().unwrap(), "a");
+            assert_eq!(state, QuorumState::NotReached);
+        }
+
+        assert!(!tracker.is_reached(&0));
+
+        let state = tracker.record(0, voters.next().unwrap(), "a");
+        assert_eq!(state, QuorumState::Reached("a"));
+        assert!(tracker.is_reached(&0));
+
+        let state = tracker.record(0, voters.next().unwrap(), "a");
+        assert_eq!(state, QuorumState::AlreadyReached);
+        assert_eq!(tracker.votes(&0, &"a"), 4);
+    }
+
+    #[test]
+    fn split_votes() {
+        let tracker = QuorumTracker::with_size(2);
+        let mut voters = keyset(3);
+
+        assert_eq!(
+            tracker.record(0, voters.next().unwrap(), 1),
+            QuorumState::NotReached
+        );
+        assert_eq!(
+            tracker.record(0, voters.next().unwrap(), 2),
+            QuorumState::NotReached
+        );
+        assert_eq!(
+            tracker.record(0, voters.next().unwrap(), 2),
+            QuorumState::Reached(2)
+        );
+    }
+
+    #[test]
+    fn duplicates_ignored() {
+        let tracker = QuorumTracker::with_size(2);
+        let voter = keyset(1).next().unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(tracker.record(0, voter, 7), QuorumState::NotReached);
+        }
+
+        assert_eq!(tracker.votes(&0, &7), 1, "duplicate votes counted");
+    }
+
+    #[test]
+    fn equivocation_reported() {
+        let tracker = QuorumTracker::with_size(2);
+        let mut voters = keyset(2);
+        let (bad, good) = (voters.next().unwrap(), voters.next().unwrap());
+
+        assert_eq!(tracker.record(0, bad, 1), QuorumState::NotReached);
+        assert_eq!(
+            tracker.record(0, bad, 2),
+            QuorumState::Equivocation { voter: bad }
+        );
+        assert_eq!(tracker.votes(&0, &2), 0, "equivocating vote counted");
+
+        // votes in other contexts are independent
+        assert_eq!(tracker.record(1, bad, 2), QuorumState::NotReached);
+
+        assert_eq!(tracker.record(0, good, 1), QuorumState::Reached(1));
+    }
+
+    #[tokio::test]
+    async fn relative_size() {
+        let keys = keyset(10).collect::<Vec<_>>();
+        let tracker =
+            QuorumTracker::new(QuorumSize::relative(|peers| 2 * peers / 3 + 1));
+        let sender = CollectingSender::<usize>::new(keys.clone());
+
+        tracker.refresh(&sender).await;
+
+        assert_eq!(tracker.threshold(), 7);
+
+        let reached = keys
+            .iter()
+            .map(|key| tracker.record((), *key, 0))
+            .position(|state| state == QuorumState::Reached(0));
+
+        assert_eq!(reached, Some(6), "quorum reached at the wrong vote");
+    }
+
+    #[test]
+    fn garbage_collection_by_count() {
+        let tracker = QuorumTracker::with_size(2).max_contexts(5);
+        let voter = keyset(1).next().unwrap();
+
+        for round in 0..20 {
+            tracker.record(round, voter, ());
+        }
+
+        tracker.garbage_collection();
+
+        assert_eq!(tracker.len(), 5);
+        assert_eq!(tracker.votes(&19, &()), 1, "recent context evicted");
+        assert_eq!(tracker.votes(&14, &()), 0, "old context kept");
+    }
+
+    #[test]
+    fn garbage_collection_by_age() {
+        let tracker =
+            QuorumTracker::with_size(2).max_age(Duration::from_millis(20));
+        let voter = keyset(1).next().unwrap();
+
+        tracker.record(0, voter, ());
+        std::thread::sleep(Duration::from_millis(30));
+        tracker.record(1, voter, ());
+
+        tracker.garbage_collection();
+
+        assert_eq!(tracker.len(), 1);
+        assert_eq!(tracker.votes(&1, &()), 1, "recent context evicted");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_quorum_once() {
+        const VOTERS: usize = 100;
+        const ROUNDS: usize = 20;
+
+        let tracker = Arc::new(QuorumTracker::with_size(VOTERS / 2));
+        let keys = keyset(VOTERS).collect::<Vec<_>>();
+
+        let handles = keys
+            .into_iter()
+            .map(|key| {
+                let tracker = tracker.clone();
+
+                tokio::spawn(async move {
+                    (0..ROUNDS)
+                        .filter(|round| {
+                            matches!(
+                                tracker.record(*round, key, true),
+                                QuorumState::Reached(true)
+                            )
+                        })
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut reached = 0;
+
+        for handle in handles {
+            reached += handle.await.expect("task panicked");
+        }
+
+        assert_eq!(reached, ROUNDS, "quorum not reached exactly once");
+    }
+}
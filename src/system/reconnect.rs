@@ -0,0 +1,754 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures::{
+    future::{self, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+    time::{self, Instant},
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{debug, info, warn};
+
+use crate::{
+    crypto::key::exchange::PublicKey,
+    net::{ConnectError, Connection, Connector, Pacer},
+    telemetry::targets,
+};
+
+/// Default delay before retrying to reconnect to a peer after a first
+/// failure, see `ReconnectPolicy::delays`
+pub const DEFAULT_RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+
+/// Default maximum delay between two attempts to reconnect to the same peer,
+/// see `ReconnectPolicy::delays`
+pub const DEFAULT_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Default number of attempts made to reconnect to a peer before giving up,
+/// see `ReconnectPolicy::max_attempts`
+pub const DEFAULT_RECONNECT_ATTEMPTS: usize = 10;
+
+/// How a [`ReconnectScheduler`] backs off from peers it fails to reconnect to
+///
+/// [`ReconnectScheduler`]: self::ReconnectScheduler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    min_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<usize>,
+}
+
+impl ReconnectPolicy {
+    /// Wait `min` after the first failed attempt to reconnect to a peer,
+    /// doubling the delay after each further failure up to `max`
+    pub fn delays(mut self, min: Duration, max: Duration) -> Self {
+        self.min_delay = min;
+        self.max_delay = max.max(min);
+        self
+    }
+
+    /// Give up on a peer after `attempts` failed attempts, or never if `None`
+    pub fn max_attempts(mut self, attempts: Option<usize>) -> Self {
+        self.max_attempts = attempts.map(|attempts| attempts.max(1));
+        self
+    }
+
+    /// Delay before the next attempt given the `previous` delay, if any
+    pub(crate) fn backoff(&self, previous: Option<Duration>) -> Duration {
+        previous
+            .map_or(self.min_delay, |delay| delay * 2)
+            .min(self.max_delay)
+    }
+
+    /// Whether a peer should be given up on after `attempts` failed attempts
+    pub(crate) fn exhausted(&self, attempts: usize) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            min_delay: DEFAULT_RECONNECT_MIN_DELAY,
+            max_delay: DEFAULT_RECONNECT_MAX_DELAY,
+            max_attempts: Some(DEFAULT_RECONNECT_ATTEMPTS),
+        }
+    }
+}
+
+/// Outcome of reconnecting to a peer, reported by a [`ReconnectScheduler`]
+///
+/// [`ReconnectScheduler`]: self::ReconnectScheduler
+#[derive(Debug)]
+pub enum ReconnectEvent {
+    /// A new `Connection` to the peer was established
+    Restored {
+        /// The reconnected peer
+        pkey: PublicKey,
+        /// `Connection` to the peer
+        connection: Box<Connection>,
+        /// Number of attempts it took
+        attempts: usize,
+    },
+    /// The peer could not be reconnected to within the `ReconnectPolicy`
+    GaveUp {
+        /// The unreachable peer
+        pkey: PublicKey,
+        /// Number of failed attempts
+        attempts: usize,
+        /// Error encountered by the last attempt
+        error: ConnectError,
+    },
+}
+
+impl ReconnectEvent {
+    /// Get the peer this event is about
+    pub fn pkey(&self) -> &PublicKey {
+        match self {
+            Self::Restored { pkey, .. } | Self::GaveUp { pkey, .. } => pkey,
+        }
+    }
+}
+
+enum Command<A> {
+    Want {
+        pkey: PublicKey,
+        candidates: Vec<A>,
+        priority: u32,
+        outcome: Option<oneshot::Sender<ReconnectEvent>>,
+    },
+    Prioritize {
+        pkey: PublicKey,
+        priority: u32,
+    },
+    Unschedule {
+        pkey: PublicKey,
+        reason: &'static str,
+    },
+    Scheduled(oneshot::Sender<Vec<PublicKey>>),
+}
+
+/// Central schedule of the reconnections to many peers. Rather than each
+/// component retrying on its own, they register the peers they want
+/// reconnected using `ReconnectScheduler::want`, and the scheduler dials them
+/// one at a time as allowed by a shared [`Pacer`], highest priority first
+/// and then in the order they are due, backing off from each peer
+/// independently. Cloning a `ReconnectScheduler` yields a handle to the same
+/// schedule, which stops once all handles are dropped.
+///
+/// [`Pacer`]: crate::net::Pacer
+pub struct ReconnectScheduler<A> {
+    commands: mpsc::UnboundedSender<Command<A>>,
+}
+
+impl<A> ReconnectScheduler<A>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    /// Start scheduling reconnections using `connector`, starting dials as
+    /// allowed by `pacer`. Returns the stream of `ReconnectEvent`s reporting
+    /// the outcome for each peer.
+    pub fn new<C>(
+        connector: Arc<C>,
+        pacer: Pacer,
+        policy: ReconnectPolicy,
+    ) -> (Self, UnboundedReceiverStream<ReconnectEvent>)
+    where
+        C: Connector<Candidate = A> + 'static,
+    {
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let schedule = Schedule {
+            connector,
+            pacer,
+            policy,
+            entries: HashMap::new(),
+            events,
+            next_id: 0,
+        };
+
+        task::spawn(schedule.run(commands_rx));
+
+        (Self { commands }, UnboundedReceiverStream::new(events_rx))
+    }
+
+    /// Reconnect to `pkey` using any of `candidates`. Higher `priority` peers
+    /// are dialed first when there are more peers to reconnect to than the
+    /// `Pacer` allows. Registering a peer that is already scheduled updates
+    /// its candidates and priority but keeps its backoff.
+    pub fn want(&self, pkey: PublicKey, candidates: Vec<A>, priority: u32) {
+        self.send(Command::Want {
+            pkey,
+            candidates,
+            priority,
+            outcome: None,
+        });
+    }
+
+    /// Reconnect to `pkey` like `want`, reporting the outcome through the
+    /// returned receiver instead of the stream of `ReconnectEvent`s. The
+    /// receiver fails if the peer is unscheduled before an outcome, or if
+    /// it is registered again by another call to `reconnect`.
+    pub fn reconnect(
+        &self,
+        pkey: PublicKey,
+        candidates: Vec<A>,
+        priority: u32,
+    ) -> oneshot::Receiver<ReconnectEvent> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send(Command::Want {
+            pkey,
+            candidates,
+            priority,
+            outcome: Some(tx),
+        });
+
+        rx
+    }
+
+    /// Change the priority of `pkey` if it is scheduled
+    pub fn prioritize(&self, pkey: PublicKey, priority: u32) {
+        self.send(Command::Prioritize { pkey, priority });
+    }
+
+    /// Stop reconnecting to `pkey` since a `Connection` to it was
+    /// established by other means. The outcome of a dial in flight is
+    /// discarded.
+    pub fn connected(&self, pkey: PublicKey) {
+        self.send(Command::Unschedule {
+            pkey,
+            reason: "connected",
+        });
+    }
+
+    /// Stop reconnecting to `pkey` since it is not wanted anymore. The
+    /// outcome of a dial in flight is discarded.
+    pub fn cancel(&self, pkey: PublicKey) {
+        self.send(Command::Unschedule {
+            pkey,
+            reason: "cancelled",
+        });
+    }
+
+    /// Get the peers that are waiting to be reconnected or being dialed
+    pub async fn scheduled(&self) -> Vec<PublicKey> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send(Command::Scheduled(tx));
+
+        rx.await.unwrap_or_default()
+    }
+
+    fn send(&self, command: Command<A>) {
+        if self.commands.send(command).is_err() {
+            warn!(target: targets::MANAGER, "reconnect schedule stopped");
+        }
+    }
+}
+
+impl<A> Clone for ReconnectScheduler<A> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+/// A peer waiting to be reconnected
+struct Entry<A> {
+    /// Changes whenever the peer is scheduled anew, to recognize stale dials
+    id: u64,
+    candidates: Vec<A>,
+    priority: u32,
+    due: Instant,
+    delay: Option<Duration>,
+    attempts: usize,
+    dialing: bool,
+    /// Where to report the outcome instead of the stream of events
+    outcome: Option<oneshot::Sender<ReconnectEvent>>,
+}
+
+struct Schedule<C: Connector> {
+    connector: Arc<C>,
+    pacer: Pacer,
+    policy: ReconnectPolicy,
+    entries: HashMap<PublicKey, Entry<C::Candidate>>,
+    events: mpsc::UnboundedSender<ReconnectEvent>,
+    next_id: u64,
+}
+
+impl<C> Schedule<C>
+where
+    C: Connector + 'static,
+    C::Candidate: Clone + Send + Sync + 'static,
+{
+    async fn run(
+        mut self,
+        mut commands: mpsc::UnboundedReceiver<Command<C::Candidate>>,
+    ) {
+        let pacer = self.pacer.clone();
+        let mut dials = FuturesUnordered::new();
+
+        loop {
+            let now = Instant::now();
+            let due = self.waiting().any(|entry| entry.due <= now);
+            let wake = self.waiting().map(|entry| entry.due).min();
+
+            // only wait for a dial to be allowed if there is a peer to dial
+            let slot = async {
+                match due {
+                    true => pacer.slot().await,
+                    false => future::pending().await,
+                }
+            };
+            let wake = async {
+                match wake {
+                    Some(wake) if !due => time::sleep_until(wake).await,
+                    _ => future::pending().await,
+                }
+            };
+
+            // registering every pending command before picking the next peer
+            // makes priorities apply right away
+            futures::select_biased! {
+                command = commands.recv().fuse() => match command {
+                    Some(command) => self.apply(command),
+                    None => break,
+                },
+                (pkey, id, result) = dials.select_next_some() => {
+                    self.complete(pkey, id, result);
+                }
+                slot = slot.fuse() => {
+                    if let Some((pkey, id, candidates)) = self.pick() {
+                        let connector = self.connector.clone();
+                        let pacer = pacer.clone();
+
+                        dials.push(async move {
+                            let dial =
+                                connector.connect_any(&pkey, &candidates, None);
+                            let result = pacer.dial_in(slot, dial).await;
+
+                            (pkey, id, result)
+                        });
+                    }
+                }
+                _ = wake.fuse() => {}
+            }
+        }
+
+        debug!(target: targets::MANAGER, "reconnect schedule stopped");
+    }
+
+    /// Entries that are not being dialed
+    fn waiting(&self) -> impl Iterator<Item = &Entry<C::Candidate>> {
+        self.entries.values().filter(|entry| !entry.dialing)
+    }
+
+    fn apply(&mut self, command: Command<C::Candidate>) {
+        match command {
+            Command::Want {
+                pkey,
+                candidates,
+                priority,
+                outcome,
+            } => {
+                if let Some(entry) = self.entries.get_mut(&pkey) {
+                    entry.candidates = candidates;
+                    entry.priority = priority;
+
+                    if outcome.is_some() {
+                        entry.outcome = outcome;
+                    }
+
+                    return;
+                }
+
+                debug!(
+                    target: targets::MANAGER,
+                    "scheduling reconnection to {} with priority {}",
+                    pkey,
+                    priority
+                );
+
+                self.next_id += 1;
+                self.entries.insert(
+                    pkey,
+                    Entry {
+                        id: self.next_id,
+                        candidates,
+                        priority,
+                        due: Instant::now(),
+                        delay: None,
+                        attempts: 0,
+                        dialing: false,
+                        outcome,
+                    },
+                );
+            }
+            Command::Prioritize { pkey, priority } => {
+                if let Some(entry) = self.entries.get_mut(&pkey) {
+                    entry.priority = priority;
+                }
+            }
+            Command::Unschedule { pkey, reason } => {
+                if self.entries.remove(&pkey).is_some() {
+                    debug!(
+                        target: targets::MANAGER,
+                        "{} {}, unscheduling reconnection", pkey, reason
+                    );
+                }
+            }
+            Command::Scheduled(reply) => {
+                let _ = reply.send(self.entries.keys().copied().collect());
+            }
+        }
+    }
+
+    /// Pick the peer to dial next among the ones that are due: the one with
+    /// the highest priority, then the one that has been due the longest
+    fn pick(&mut self) -> Option<(PublicKey, u64, Vec<C::Candidate>)> {
+        let now = Instant::now();
+        let (pkey, entry) = self
+            .entries
+            .iter_mut()
+            .filter(|(_, entry)| !entry.dialing && entry.due <= now)
+            .max_by(|(_, a), (_, b)| {
+                a.priority.cmp(&b.priority).then(b.due.cmp(&a.due))
+            })?;
+
+        entry.dialing = true;
+
+        Some((*pkey, entry.id, entry.candidates.clone()))
+    }
+
+    fn complete(
+        &mut self,
+        pkey: PublicKey,
+        id: u64,
+        result: Result<Connection, ConnectError>,
+    ) {
+        let entry = match self.entries.get_mut(&pkey) {
+            Some(entry) if entry.id == id => entry,
+            _ => {
+                debug!(
+                    target: targets::MANAGER,
+                    "discarding stale reconnection to {}", pkey
+                );
+                return;
+            }
+        };
+
+        entry.attempts += 1;
+
+        let attempts = entry.attempts;
+        let event = match result {
+            Ok(connection) => {
+                info!(
+                    target: targets::MANAGER,
+                    "reconnected to {} after {} attempts", pkey, attempts
+                );
+
+                ReconnectEvent::Restored {
+                    pkey,
+                    connection: Box::new(connection),
+                    attempts,
+                }
+            }
+            Err(error) if self.policy.exhausted(attempts) => {
+                warn!(
+                    target: targets::MANAGER,
+                    "giving up on {} after {} attempts: {}",
+                    pkey,
+                    attempts,
+                    error
+                );
+
+                ReconnectEvent::GaveUp {
+                    pkey,
+                    attempts,
+                    error,
+                }
+            }
+            Err(error) => {
+                let delay = self.policy.backoff(entry.delay);
+
+                debug!(
+                    target: targets::MANAGER,
+                    "failed to reconnect to {}, retrying in {:?}: {}",
+                    pkey,
+                    delay,
+                    error
+                );
+
+                entry.delay = Some(delay);
+                entry.due = Instant::now() + delay;
+                entry.dialing = false;
+
+                return;
+            }
+        };
+
+        let outcome = self.entries.remove(&pkey).and_then(|e| e.outcome);
+
+        match outcome {
+            Some(outcome) => {
+                let _ = outcome.send(event);
+            }
+            None => {
+                let _ = self.events.send(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        net::{Ipv4Addr, SocketAddr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        net::{Pacing, Socket},
+        test::*,
+    };
+
+    /// Time spent by `Mock` establishing each `Socket`
+    const DIAL: Duration = Duration::from_millis(50);
+
+    /// A `Connector` recording the peers it dials and the number of dials in
+    /// flight, failing to reach the peers in `unreachable`
+    struct Mock {
+        exchanger: Exchanger,
+        unreachable: Mutex<HashSet<PublicKey>>,
+        dials: Mutex<Vec<(PublicKey, Instant)>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl Mock {
+        fn new<I: IntoIterator<Item = PublicKey>>(unreachable: I) -> Arc<Self> {
+            Arc::new(Self {
+                exchanger: Exchanger::random(),
+                unreachable: Mutex::new(unreachable.into_iter().collect()),
+                dials: Mutex::new(Vec::new()),
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+            })
+        }
+
+        fn dialed(&self) -> Vec<PublicKey> {
+            self.dials.lock().unwrap().iter().map(|(p, _)| *p).collect()
+        }
+    }
+
+    #[async_trait]
+    impl Connector for Mock {
+        type Candidate = SocketAddr;
+
+        fn exchanger(&self) -> &Exchanger {
+            &self.exchanger
+        }
+
+        async fn establish(
+            &self,
+            pkey: &PublicKey,
+            _: &SocketAddr,
+        ) -> Result<Box<dyn Socket>, ConnectError> {
+            self.dials.lock().unwrap().push((*pkey, Instant::now()));
+
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            time::sleep(DIAL).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            if self.unreachable.lock().unwrap().contains(pkey) {
+                return Err(ConnectError::Io {
+                    source: std::io::ErrorKind::ConnectionRefused.into(),
+                });
+            }
+
+            Ok(Box::new(WireSocket::new(Vec::new()).0))
+        }
+    }
+
+    fn pkey() -> PublicKey {
+        *Exchanger::random().keypair().public()
+    }
+
+    fn candidates() -> Vec<SocketAddr> {
+        vec![(Ipv4Addr::LOCALHOST, 9700).into()]
+    }
+
+    fn capped(max: usize) -> Pacer {
+        Pacer::new(Pacing::new(max, Duration::ZERO))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_cap() {
+        const MAX: usize = 3;
+
+        let peers: Vec<_> = (0..20).map(|_| pkey()).collect();
+        let connector = Mock::new(None);
+        let (scheduler, events) = ReconnectScheduler::new(
+            connector.clone(),
+            capped(MAX),
+            ReconnectPolicy::default(),
+        );
+
+        for peer in &peers {
+            scheduler.want(*peer, candidates(), 0);
+        }
+
+        let restored: HashSet<_> = events
+            .take(peers.len())
+            .map(|event| match event {
+                ReconnectEvent::Restored { pkey, attempts, .. } => {
+                    assert_eq!(attempts, 1, "retried reachable peer");
+                    pkey
+                }
+                ReconnectEvent::GaveUp { pkey, .. } => {
+                    panic!("gave up on {}", pkey)
+                }
+            })
+            .collect()
+            .await;
+
+        assert_eq!(restored, peers.iter().copied().collect());
+        assert_eq!(connector.max_in_flight.load(Ordering::SeqCst), MAX);
+        assert!(scheduler.scheduled().await.is_empty(), "peers left");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn priority_under_contention() {
+        let low: Vec<_> = (0..4).map(|_| pkey()).collect();
+        let (raised, critical) = (pkey(), pkey());
+        let connector = Mock::new(None);
+        let (scheduler, mut events) = ReconnectScheduler::new(
+            connector.clone(),
+            capped(1),
+            ReconnectPolicy::default(),
+        );
+
+        for peer in low.iter().chain(Some(&raised)) {
+            scheduler.want(*peer, candidates(), 0);
+        }
+
+        scheduler.want(critical, candidates(), 10);
+        scheduler.prioritize(raised, 5);
+
+        for _ in 0..low.len() + 2 {
+            events.next().await.expect("schedule stopped");
+        }
+
+        let dialed = connector.dialed();
+
+        assert_eq!(dialed[..2], [critical, raised], "priorities ignored");
+        assert_eq!(dialed.len(), low.len() + 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_grows() {
+        let peer = pkey();
+        let connector = Mock::new(Some(peer));
+        let policy = ReconnectPolicy::default()
+            .delays(Duration::from_millis(100), Duration::from_millis(400))
+            .max_attempts(Some(6));
+        let (scheduler, mut events) =
+            ReconnectScheduler::new(connector.clone(), capped(4), policy);
+
+        scheduler.want(peer, candidates(), 0);
+
+        match events.next().await.expect("schedule stopped") {
+            ReconnectEvent::GaveUp { pkey, attempts, .. } => {
+                assert_eq!(pkey, peer);
+                assert_eq!(attempts, 6);
+            }
+            ReconnectEvent::Restored { .. } => panic!("unreachable restored"),
+        }
+
+        let gaps: Vec<_> = connector
+            .dials
+            .lock()
+            .unwrap()
+            .windows(2)
+            .map(|pair| pair[1].1 - pair[0].1 - DIAL)
+            .collect();
+
+        assert_eq!(
+            gaps,
+            [100, 200, 400, 400, 400].map(Duration::from_millis),
+            "bad backoff"
+        );
+        assert!(scheduler.scheduled().await.is_empty(), "peer kept");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connected_unschedules() {
+        let (peer, other) = (pkey(), pkey());
+        let connector = Mock::new([peer, other]);
+        let policy = ReconnectPolicy::default().max_attempts(None);
+        let (scheduler, _events) =
+            ReconnectScheduler::new(connector.clone(), capped(4), policy);
+
+        scheduler.want(peer, candidates(), 0);
+        scheduler.want(other, candidates(), 0);
+        time::sleep(DIAL * 2).await;
+
+        assert_eq!(connector.dialed().len(), 2, "peers not dialed");
+
+        scheduler.connected(peer);
+
+        assert_eq!(scheduler.scheduled().await, [other]);
+
+        time::sleep(Duration::from_secs(10)).await;
+
+        let dialed = connector.dialed();
+
+        assert_eq!(dialed.iter().filter(|p| **p == peer).count(), 1);
+        assert!(dialed.iter().filter(|p| **p == other).count() > 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_reports_outcome() {
+        let (peer, other, dropped) = (pkey(), pkey(), pkey());
+        let connector = Mock::new([dropped]);
+        let policy = ReconnectPolicy::default().max_attempts(None);
+        let (scheduler, mut events) =
+            ReconnectScheduler::new(connector.clone(), capped(4), policy);
+
+        let restored = scheduler.reconnect(peer, candidates(), 0);
+        let cancelled = scheduler.reconnect(dropped, candidates(), 0);
+
+        scheduler.want(other, candidates(), 0);
+
+        match restored.await.expect("outcome dropped") {
+            ReconnectEvent::Restored { pkey, attempts, .. } => {
+                assert_eq!(pkey, peer);
+                assert_eq!(attempts, 1);
+            }
+            ReconnectEvent::GaveUp { .. } => panic!("reachable peer failed"),
+        }
+
+        let event = events.next().await.expect("schedule stopped");
+
+        assert_eq!(event.pkey(), &other, "outcome reported as event");
+
+        scheduler.cancel(dropped);
+
+        cancelled.await.expect_err("cancelled peer reported");
+        assert!(scheduler.scheduled().await.is_empty(), "peers left");
+    }
+}
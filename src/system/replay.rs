@@ -0,0 +1,410 @@
+use std::{
+    any::Any,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+    codec::{self, bincode_options},
+    crypto::{
+        key::exchange::{Fingerprint, PublicKey},
+        BincodeError,
+    },
+    telemetry::targets,
+    Message,
+};
+
+use bincode::Options;
+
+/// Size of the length prefix of an encoded `Vec<u8>`
+const LENGTH_SIZE: usize = 8;
+
+/// Whether a recorded message was received or sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// The message was handed to the `Processor`
+    Inbound,
+    /// The message was sent by the `Processor`
+    Outbound,
+}
+
+/// A message recorded by a [`ReplayRecorder`]
+///
+/// [`ReplayRecorder`]: self::ReplayRecorder
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    sequence: u64,
+    direction: Direction,
+    peer: PublicKey,
+    elapsed: Duration,
+    payload: Vec<u8>,
+}
+
+impl ReplayEntry {
+    /// Position of this entry in the log, starting at 0
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether the message was received or sent
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The peer the message was received from or sent to
+    pub fn peer(&self) -> &PublicKey {
+        &self.peer
+    }
+
+    /// Short identifier of `peer`
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.peer.fingerprint()
+    }
+
+    /// Time between the creation of the `ReplayRecorder` and the recording of
+    /// this message
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The message as it was serialized
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Decode the recorded message
+    pub fn decode<M: Message>(&self) -> Result<M, BincodeError> {
+        bincode_options().deserialize(&self.payload)
+    }
+}
+
+impl fmt::Display for ReplayEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (way, preposition) = match self.direction {
+            Direction::Inbound => ("received", "from"),
+            Direction::Outbound => ("sent", "to"),
+        };
+
+        write!(
+            f,
+            "#{} {} {} bytes {} {} after {:?}",
+            self.sequence,
+            way,
+            self.payload.len(),
+            preposition,
+            self.fingerprint(),
+            self.elapsed
+        )
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Errors encountered when reading a replay log
+pub enum ReplayLogError {
+    #[snafu(display("failed to read replay log: {}", source))]
+    /// The log could not be read
+    ReadLog {
+        /// Error source
+        source: io::Error,
+    },
+    #[snafu(display(
+        "corrupted replay log after {} entries: {}",
+        read,
+        source
+    ))]
+    /// An entry of the log could not be decoded
+    CorruptedLog {
+        /// Number of entries decoded before the corrupted one
+        read: usize,
+        /// Error source
+        source: BincodeError,
+    },
+}
+
+/// Decode all entries of a replay log
+pub fn read_replay_log(
+    bytes: &[u8],
+) -> Result<Vec<ReplayEntry>, ReplayLogError> {
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+
+    while !rest.is_empty() {
+        let (entry, size) =
+            codec::deserialize_prefix(rest).context(CorruptedLog {
+                read: entries.len(),
+            })?;
+
+        entries.push(entry);
+        rest = &rest[size..];
+    }
+
+    Ok(entries)
+}
+
+/// Read the replay log written by a `ReplayRecorder` at `path`, including the
+/// file it last rotated if any
+pub fn open_replay_log(
+    path: impl AsRef<Path>,
+) -> Result<Vec<ReplayEntry>, ReplayLogError> {
+    let path = path.as_ref();
+    let mut bytes = match fs::read(rotated(path)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).context(ReadLog),
+    };
+
+    bytes.extend(fs::read(path).context(ReadLog)?);
+
+    read_replay_log(&bytes)
+}
+
+/// Path of the file a log at `path` is rotated to
+fn rotated(path: &Path) -> PathBuf {
+    let mut rotated = OsString::from(path);
+
+    rotated.push(".1");
+    rotated.into()
+}
+
+/// Filter deciding which messages are left out of a replay log
+type Skip = Arc<dyn Fn(&dyn Any) -> bool + Send + Sync>;
+
+struct Sink {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    /// File the log is written to, if it can be rotated
+    path: Option<PathBuf>,
+    max_size: Option<u64>,
+    written: u64,
+    sequence: u64,
+    buffer: Vec<u8>,
+    failed: bool,
+}
+
+impl Sink {
+    fn append<M: Serialize>(
+        &mut self,
+        direction: Direction,
+        peer: &PublicKey,
+        elapsed: Duration,
+        message: &M,
+    ) -> io::Result<()> {
+        self.buffer.clear();
+
+        let header = (self.sequence, direction, peer, elapsed);
+
+        codec::serialize_into(&mut self.buffer, &header)
+            .map_err(io::Error::other)?;
+
+        // the message is serialized in place as a `Vec<u8>` would be, so
+        // that entries can be decoded as `ReplayEntry`
+        let start = self.buffer.len() + LENGTH_SIZE;
+
+        self.buffer.resize(start, 0);
+        bincode_options()
+            .serialize_into(&mut self.buffer, message)
+            .map_err(io::Error::other)?;
+
+        let length = (self.buffer.len() - start) as u64;
+
+        self.buffer[start - LENGTH_SIZE..start]
+            .copy_from_slice(&length.to_le_bytes());
+
+        if self
+            .max_size
+            .is_some_and(|max| self.written + self.buffer.len() as u64 > max)
+        {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(&self.buffer)?;
+        self.written += self.buffer.len() as u64;
+        self.sequence += 1;
+
+        Ok(())
+    }
+
+    /// Move the current log aside and start a new one, if it is a file
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        self.writer.flush()?;
+        fs::rename(path, rotated(path))?;
+        self.writer = BufWriter::new(Box::new(File::create(path)?));
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+/// Records the messages processed and sent by a `SystemManager` so that a
+/// `Processor` can later be run again through the same sequence of messages,
+/// see `ManagerConfig::record`. <br />
+/// Each message is serialized once and appended to the log along with its
+/// sequence number, peer and the time elapsed since the recorder was
+/// created. Failing to write the log stops recording without disturbing the
+/// `SystemManager`.
+#[derive(Clone)]
+pub struct ReplayRecorder {
+    sink: Arc<Mutex<Sink>>,
+    skip: Option<Skip>,
+    start: Instant,
+}
+
+impl ReplayRecorder {
+    /// Record to a new file at `path`, replacing any existing one
+    pub fn to_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::create(&path)?;
+        let recorder = Self::to_writer(file);
+
+        recorder.sink().path = Some(path);
+
+        Ok(recorder)
+    }
+
+    /// Record to the given `writer`
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+
+        Self {
+            sink: Arc::new(Mutex::new(Sink {
+                writer: BufWriter::new(writer),
+                path: None,
+                max_size: None,
+                written: 0,
+                sequence: 0,
+                buffer: Vec::new(),
+                failed: false,
+            })),
+            skip: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Once the log reaches `bytes`, move it to the same path with a `.1`
+    /// suffix and start a new one. Only logs written to a path are rotated.
+    pub fn max_size(self, bytes: u64) -> Self {
+        self.sink().max_size = Some(bytes);
+        self
+    }
+
+    /// Leave out the messages of type `M` for which `skip` returns true,
+    /// without serializing them. Skipped inbound messages are not replayed.
+    pub fn skip<M, F>(mut self, skip: F) -> Self
+    where
+        M: Message + 'static,
+        F: Fn(&M) -> bool + Send + Sync + 'static,
+    {
+        self.skip = Some(Arc::new(move |message: &dyn Any| {
+            message.downcast_ref::<M>().is_some_and(&skip)
+        }));
+        self
+    }
+
+    /// Write buffered entries to the underlying log
+    pub fn flush(&self) -> io::Result<()> {
+        self.sink().writer.flush()
+    }
+
+    /// Append `message` to the log unless it is skipped
+    pub(crate) fn record<M: Message + 'static>(
+        &self,
+        direction: Direction,
+        peer: &PublicKey,
+        message: &M,
+    ) {
+        if self.skip.as_ref().is_some_and(|skip| skip(message)) {
+            return;
+        }
+
+        let elapsed = self.start.elapsed();
+        let mut sink = self.sink();
+
+        if sink.failed {
+            return;
+        }
+
+        if let Err(e) = sink.append(direction, peer, elapsed, message) {
+            warn!(
+                target: targets::MANAGER,
+                "failed to record message, recording stopped: {}", e
+            );
+            sink.failed = true;
+        }
+    }
+
+    fn sink(&self) -> std::sync::MutexGuard<'_, Sink> {
+        self.sink.lock().expect("replay log poisoned")
+    }
+}
+
+impl fmt::Debug for ReplayRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sink = self.sink();
+
+        f.debug_struct("ReplayRecorder")
+            .field("path", &sink.path)
+            .field("max_size", &sink.max_size)
+            .field("recorded", &sink.sequence)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::keyset;
+
+    #[test]
+    fn rotated_and_skipped() {
+        const MAX_SIZE: u64 = 200;
+
+        let path = std::env::temp_dir()
+            .join(format!("drop-replay-rotation-{}", std::process::id()));
+        let peer = keyset(1).next().expect("no key");
+        let recorder = ReplayRecorder::to_path(&path)
+            .expect("create failed")
+            .max_size(MAX_SIZE)
+            .skip(|message: &usize| message % 2 == 1);
+
+        for i in 0..20usize {
+            recorder.record(Direction::Inbound, &peer, &i);
+        }
+
+        recorder.flush().expect("flush failed");
+
+        let size = fs::metadata(&path).expect("no log").len();
+        let entries = open_replay_log(&path).expect("bad log");
+        let _ = fs::remove_file(rotated(&path));
+        let _ = fs::remove_file(&path);
+
+        assert!(size <= MAX_SIZE, "log not rotated");
+        assert!(entries.len() < 10, "old entries kept");
+
+        let messages = entries
+            .iter()
+            .map(|entry| entry.decode::<usize>().expect("bad entry"))
+            .collect::<Vec<_>>();
+        let expected = (0..20).step_by(2).collect::<Vec<_>>();
+
+        assert_eq!(messages, expected[10 - entries.len()..], "wrong entries");
+        assert!(
+            entries
+                .windows(2)
+                .all(|w| w[1].sequence() == w[0].sequence() + 1),
+            "sequence numbers not contiguous"
+        );
+    }
+}
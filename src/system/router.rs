@@ -0,0 +1,877 @@
+use std::{
+    collections::HashMap, future::Future, marker::PhantomData, sync::Arc,
+};
+
+use futures::future::BoxFuture;
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::{
+    sync::{mpsc, oneshot, Mutex, Semaphore},
+    task,
+};
+use tracing::{debug, debug_span, trace};
+use tracing_futures::Instrument;
+
+use super::{Handle, Processor, Sampler, Sender, SenderError};
+use crate::{
+    async_trait, crypto::key::exchange::PublicKey, telemetry::targets, Message,
+};
+
+/// Default number of messages waiting in the queue of each route
+pub const DEFAULT_ROUTE_CAPACITY: usize = 128;
+
+/// How the messages handled by a single route are ordered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RouteOrder {
+    /// Messages are started in the order they were routed but may complete
+    /// in any order, as many at once as the route allows
+    #[default]
+    Unordered,
+    /// Messages from the same peer are handled one at a time in the order
+    /// they were routed, messages from different peers concurrently
+    PerPeer,
+    /// Messages are handled one at a time in the order they were routed,
+    /// whatever the concurrency limit of the route
+    Sequential,
+}
+
+/// Configuration of a single route of a [`Router`]
+///
+/// [`Router`]: self::Router
+#[derive(Clone, Copy, Debug)]
+pub struct RouteConfig {
+    limit: usize,
+    order: RouteOrder,
+    capacity: usize,
+}
+
+impl RouteConfig {
+    /// Handle at most `limit` messages of this route at once
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Order the messages of this route according to `order`
+    pub fn order(mut self, order: RouteOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Queue at most `capacity` messages before routing waits for the
+    /// handler to catch up
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    fn permits(&self) -> usize {
+        match self.order {
+            RouteOrder::Sequential => 1,
+            _ => self.limit,
+        }
+    }
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self {
+            limit: 1,
+            order: RouteOrder::default(),
+            capacity: DEFAULT_ROUTE_CAPACITY,
+        }
+    }
+}
+
+/// The typed output of a route, aggregated with the outputs of all other
+/// routes and delivered by the [`RouterHandle`]
+///
+/// [`RouterHandle`]: self::RouterHandle
+pub struct Output<T> {
+    emit: Arc<dyn Fn(T) -> bool + Send + Sync>,
+}
+
+impl<T> Output<T> {
+    /// Deliver `value` through the `RouterHandle`, returning false if it was
+    /// dropped
+    pub fn emit(&self, value: T) -> bool {
+        (self.emit)(value)
+    }
+}
+
+impl<T> Clone for Output<T> {
+    fn clone(&self) -> Self {
+        Self {
+            emit: self.emit.clone(),
+        }
+    }
+}
+
+#[async_trait]
+/// Handler for the messages matching one route of a [`Router`]. This is
+/// implemented for async closures taking the same arguments.
+///
+/// [`Router`]: self::Router
+pub trait MessageHandler<M, T, S>: Send + Sync
+where
+    M: Message + 'static,
+    S: Sender<M>,
+{
+    /// Handle a `message` received from peer `from`
+    async fn handle(
+        &self,
+        message: M,
+        from: PublicKey,
+        sender: Arc<S>,
+        output: Output<T>,
+    );
+}
+
+#[async_trait]
+impl<M, T, S, F, Fut> MessageHandler<M, T, S> for F
+where
+    M: Message + 'static,
+    T: Send + 'static,
+    S: Sender<M> + 'static,
+    F: Fn(M, PublicKey, Arc<S>, Output<T>) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send,
+{
+    async fn handle(
+        &self,
+        message: M,
+        from: PublicKey,
+        sender: Arc<S>,
+        output: Output<T>,
+    ) {
+        self(message, from, sender, output).await
+    }
+}
+
+/// A `MessageHandler` with its output type erased
+type Erased<M, S> =
+    Arc<dyn Fn(M, PublicKey, Arc<S>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Predicate selecting the messages of a route
+type Matches<M> = Box<dyn Fn(&M) -> bool + Send + Sync>;
+
+/// A message waiting in the queue of a route
+type Queued<M> = (PublicKey, M);
+
+struct Route<M, S> {
+    label: String,
+    matches: Option<Matches<M>>,
+    handler: Erased<M, S>,
+    config: RouteConfig,
+    queue: Option<mpsc::Sender<Queued<M>>>,
+}
+
+impl<M, S> Route<M, S>
+where
+    M: Message + 'static,
+    S: Sender<M> + 'static,
+{
+    /// Start handling the messages of this route, replacing the queue used
+    /// by a previous setup, if any
+    fn spawn(&mut self, sender: Arc<S>, budget: Option<Arc<Semaphore>>) {
+        let (tx, rx) = mpsc::channel(self.config.capacity);
+        let dispatcher = Dispatcher {
+            handler: self.handler.clone(),
+            config: self.config,
+            permits: Arc::new(Semaphore::new(self.config.permits())),
+            budget,
+            sender,
+            tails: HashMap::new(),
+        };
+
+        task::spawn(dispatcher.run(rx).instrument(
+            debug_span!(target: targets::ROUTER, "route", label = %self.label),
+        ));
+
+        self.queue = Some(tx);
+    }
+}
+
+/// Task that starts the handler of a single route for each queued message
+struct Dispatcher<M, S> {
+    handler: Erased<M, S>,
+    config: RouteConfig,
+    permits: Arc<Semaphore>,
+    budget: Option<Arc<Semaphore>>,
+    sender: Arc<S>,
+    tails: HashMap<PublicKey, oneshot::Receiver<()>>,
+}
+
+impl<M, S> Dispatcher<M, S>
+where
+    M: Message + 'static,
+    S: Sender<M> + 'static,
+{
+    async fn run(mut self, mut queue: mpsc::Receiver<Queued<M>>) {
+        while let Some((from, message)) = queue.recv().await {
+            let permit = self
+                .permits
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("route permits closed");
+            let (previous, done) = self.chain(from);
+            let budget = self.budget.clone();
+            let handler = self.handler.clone();
+            let sender = self.sender.clone();
+
+            task::spawn(async move {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+
+                let _budget = match budget {
+                    Some(budget) => Some(
+                        budget.acquire_owned().await.expect("budget closed"),
+                    ),
+                    None => None,
+                };
+
+                trace!(
+                    target: targets::ROUTER,
+                    "handling message from {}", from
+                );
+
+                (handler)(message, from, sender).await;
+
+                drop(done);
+                drop(permit);
+            });
+        }
+
+        debug!(target: targets::ROUTER, "route queue closed");
+    }
+
+    /// Make the next message from `from` wait for the previous one when
+    /// ordered by peer, returning the signal to wait for and the one to drop
+    /// once done
+    fn chain(
+        &mut self,
+        from: PublicKey,
+    ) -> (Option<oneshot::Receiver<()>>, Option<oneshot::Sender<()>>) {
+        if self.config.order != RouteOrder::PerPeer {
+            return (None, None);
+        }
+
+        let (done, tail) = oneshot::channel();
+
+        if self.tails.len() > self.config.capacity {
+            self.tails.retain(|_, tail| {
+                !matches!(
+                    tail.try_recv(),
+                    Err(oneshot::error::TryRecvError::Closed)
+                )
+            });
+        }
+
+        (self.tails.insert(from, tail), Some(done))
+    }
+}
+
+/// Builder for a [`RouterProcessor`] that dispatches messages to different
+/// handlers depending on their content, usually the variant of an enum.
+///
+/// [`RouterProcessor`]: self::RouterProcessor
+pub struct Router<M, O, S> {
+    routes: Vec<Route<M, S>>,
+    fallback: Option<Route<M, S>>,
+    budget: Option<usize>,
+    output: mpsc::UnboundedSender<O>,
+    delivered: mpsc::UnboundedReceiver<O>,
+}
+
+impl<M, O, S> Router<M, O, S>
+where
+    M: Message + 'static,
+    O: Send + 'static,
+    S: Sender<M> + 'static,
+{
+    /// Create a `Router` without any route
+    pub fn new() -> Self {
+        let (output, delivered) = mpsc::unbounded_channel();
+
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+            budget: None,
+            output,
+            delivered,
+        }
+    }
+
+    /// Handle messages for which `matches` returns true using `handler`,
+    /// one at a time. Messages are routed to the first matching route.
+    pub fn route<F, H, T>(self, matches: F, handler: H) -> Self
+    where
+        F: Fn(&M) -> bool + Send + Sync + 'static,
+        H: MessageHandler<M, T, S> + 'static,
+        T: Into<O> + Send + 'static,
+    {
+        self.route_with(matches, handler, RouteConfig::default())
+    }
+
+    /// Handle messages for which `matches` returns true using `handler`
+    /// according to `config`
+    pub fn route_with<F, H, T>(
+        mut self,
+        matches: F,
+        handler: H,
+        config: RouteConfig,
+    ) -> Self
+    where
+        F: Fn(&M) -> bool + Send + Sync + 'static,
+        H: MessageHandler<M, T, S> + 'static,
+        T: Into<O> + Send + 'static,
+    {
+        let label = format!("route {}", self.routes.len());
+        let handler = self.erase(handler);
+
+        self.routes.push(Route {
+            label,
+            matches: Some(Box::new(matches)),
+            handler,
+            config,
+            queue: None,
+        });
+        self
+    }
+
+    /// Handle messages that match no route using `handler` according to
+    /// `config`. Without a fallback these messages are refused with
+    /// `RouterError::Unrouted`.
+    pub fn fallback<H, T>(mut self, handler: H, config: RouteConfig) -> Self
+    where
+        H: MessageHandler<M, T, S> + 'static,
+        T: Into<O> + Send + 'static,
+    {
+        let handler = self.erase(handler);
+
+        self.fallback = Some(Route {
+            label: "fallback".into(),
+            matches: None,
+            handler,
+            config,
+            queue: None,
+        });
+        self
+    }
+
+    /// Run at most `budget` handlers at once across all routes. This is
+    /// usually the `parallelism` given to `SystemManager::run`, see
+    /// [`RouterProcessor`] for details.
+    ///
+    /// [`RouterProcessor`]: self::RouterProcessor
+    pub fn budget(mut self, budget: usize) -> Self {
+        self.budget = Some(budget.max(1));
+        self
+    }
+
+    /// Create the `RouterProcessor` using the routes of this `Router`
+    pub fn build(self) -> RouterProcessor<M, O, S> {
+        RouterProcessor {
+            routes: self.routes,
+            fallback: self.fallback,
+            budget: self.budget.map(|budget| Arc::new(Semaphore::new(budget))),
+            delivered: Some(self.delivered),
+        }
+    }
+
+    fn erase<H, T>(&self, handler: H) -> Erased<M, S>
+    where
+        H: MessageHandler<M, T, S> + 'static,
+        T: Into<O> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let tx = self.output.clone();
+        let output = Output {
+            emit: Arc::new(move |value: T| tx.send(value.into()).is_ok()),
+        };
+
+        Arc::new(move |message, from, sender| {
+            let handler = handler.clone();
+            let output = output.clone();
+
+            Box::pin(async move {
+                handler.handle(message, from, sender, output).await
+            })
+        })
+    }
+}
+
+impl<M, O, S> Default for Router<M, O, S>
+where
+    M: Message + 'static,
+    O: Send + 'static,
+    S: Sender<M> + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `Processor` that queues each incoming message to the first route of
+/// its [`Router`] that matches it. <br />
+/// Every route has its own queue and starts handling a message once it has
+/// a free slot according to its `RouteConfig`, independently of other
+/// routes. Processing a message with this `Processor` completes as soon as
+/// the message is queued, so the `parallelism` of the `SystemManager` only
+/// bounds how many messages are being routed at once, and a full queue makes
+/// the manager wait for the route to catch up. Handlers are bounded by the
+/// limit of their route and, across all routes, by the budget of the
+/// `Router` if any: setting it to the `parallelism` of the manager keeps the
+/// same number of messages handled at once as a single `Processor` would.
+///
+/// [`Router`]: self::Router
+pub struct RouterProcessor<M, O, S> {
+    routes: Vec<Route<M, S>>,
+    fallback: Option<Route<M, S>>,
+    budget: Option<Arc<Semaphore>>,
+    delivered: Option<mpsc::UnboundedReceiver<O>>,
+}
+
+impl<M, O, S> RouterProcessor<M, O, S>
+where
+    M: Message + 'static,
+{
+    fn route_for(&self, message: &M) -> Option<&Route<M, S>> {
+        self.routes
+            .iter()
+            .find(|route| route.matches.as_ref().is_some_and(|f| f(message)))
+            .or(self.fallback.as_ref())
+    }
+}
+
+#[async_trait]
+impl<M, I, O, S> Processor<M, I, O, S> for RouterProcessor<M, O, S>
+where
+    M: Message + 'static,
+    I: Into<M> + Clone + Send + Sync + 'static,
+    O: Send + 'static,
+    S: Sender<M> + 'static,
+{
+    type Handle = RouterHandle<M, O, S>;
+
+    type Error = RouterError;
+
+    async fn process(
+        &self,
+        message: M,
+        from: PublicKey,
+        _sender: Arc<S>,
+    ) -> Result<(), Self::Error> {
+        let route = self.route_for(&message).context(Unrouted)?;
+        let queue = route.queue.as_ref().context(NotSetup)?;
+
+        queue.send((from, message)).await.ok().context(Stopped {
+            route: route.label.clone(),
+        })
+    }
+
+    async fn setup<SA: Sampler>(
+        &mut self,
+        _sampler: Arc<SA>,
+        sender: Arc<S>,
+    ) -> Self::Handle {
+        let budget = self.budget.clone();
+
+        self.routes
+            .iter_mut()
+            .chain(self.fallback.as_mut())
+            .for_each(|route| route.spawn(sender.clone(), budget.clone()));
+
+        let delivered = self.delivered.take().unwrap_or_else(|| {
+            debug!(
+                target: targets::ROUTER,
+                "router setup again, previous handle keeps the output"
+            );
+            mpsc::unbounded_channel().1
+        });
+
+        RouterHandle {
+            delivered: Arc::new(Mutex::new(delivered)),
+            sender,
+            _m: PhantomData,
+        }
+    }
+
+    async fn disconnect<SA: Sampler>(
+        &self,
+        _peer: PublicKey,
+        _sender: Arc<S>,
+        _sampler: Arc<SA>,
+    ) {
+    }
+
+    async fn garbage_collection(&self) {}
+}
+
+/// The `Handle` of a [`RouterProcessor`], delivering the outputs of all of
+/// its routes in the order they were emitted
+///
+/// [`RouterProcessor`]: self::RouterProcessor
+pub struct RouterHandle<M, O, S> {
+    delivered: Arc<Mutex<mpsc::UnboundedReceiver<O>>>,
+    sender: Arc<S>,
+    _m: PhantomData<fn() -> M>,
+}
+
+impl<M, O, S> Clone for RouterHandle<M, O, S> {
+    fn clone(&self) -> Self {
+        Self {
+            delivered: self.delivered.clone(),
+            sender: self.sender.clone(),
+            _m: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<M, I, O, S> Handle<I, O> for RouterHandle<M, O, S>
+where
+    M: Message + 'static,
+    I: Into<M> + Clone + Send + Sync + 'static,
+    O: Send + 'static,
+    S: Sender<M> + 'static,
+{
+    type Error = RouterError;
+
+    async fn deliver(&mut self) -> Result<O, Self::Error> {
+        self.delivered.lock().await.recv().await.context(Closed)
+    }
+
+    async fn try_deliver(&mut self) -> Result<Option<O>, Self::Error> {
+        match self.delivered.lock().await.try_recv() {
+            Ok(output) => Ok(Some(output)),
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => Closed.fail(),
+        }
+    }
+
+    async fn broadcast(&mut self, message: &I) -> Result<(), Self::Error> {
+        let keys = self.sender.keys().await;
+
+        self.sender
+            .send_many_collecting(message.clone().into(), keys.iter())
+            .await
+            .context(Broadcast)
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Errors encountered by a [`RouterProcessor`] and its [`RouterHandle`]
+///
+/// [`RouterProcessor`]: self::RouterProcessor
+/// [`RouterHandle`]: self::RouterHandle
+pub enum RouterError {
+    #[snafu(display("no route matches the message and there is no fallback"))]
+    /// A message matched no route and no fallback was given
+    Unrouted,
+    #[snafu(display("router was not setup"))]
+    /// A message was received before `Processor::setup` was called
+    NotSetup,
+    #[snafu(display("{} stopped handling messages", route))]
+    /// The handling task of a route is gone
+    Stopped {
+        /// The route that stopped
+        route: String,
+    },
+    #[snafu(display("all routes stopped"))]
+    /// No route can deliver anything anymore
+    Closed,
+    #[snafu(display("failed to broadcast: {}", source))]
+    /// Broadcasting a message failed
+    Broadcast {
+        /// Error source
+        source: SenderError,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        message,
+        system::{AllSampler, CollectingSender},
+        test::*,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use tokio::time;
+
+    #[message]
+    #[derive(Copy, Eq, PartialEq)]
+    enum Msg {
+        Fast(usize),
+        Slow(usize),
+        Chained(usize),
+        Other(usize),
+    }
+
+    type Out = (&'static str, PublicKey, Msg);
+
+    /// Counts how many messages are handled at once
+    #[derive(Clone, Default)]
+    struct Gauge {
+        running: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+    }
+
+    impl Gauge {
+        fn enter(&self) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+
+            self.max.fetch_max(running, Ordering::SeqCst);
+        }
+
+        fn exit(&self) {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        fn max(&self) -> usize {
+            self.max.load(Ordering::SeqCst)
+        }
+    }
+
+    /// A handler that takes some time and records its concurrency
+    #[derive(Clone)]
+    struct Probe {
+        name: &'static str,
+        delay: Duration,
+        gauge: Gauge,
+        total: Gauge,
+    }
+
+    impl Probe {
+        fn new(name: &'static str, millis: u64, total: &Gauge) -> Self {
+            Self {
+                name,
+                delay: Duration::from_millis(millis),
+                gauge: Gauge::default(),
+                total: total.clone(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl MessageHandler<Msg, Out, CollectingSender<Msg>> for Probe {
+        async fn handle(
+            &self,
+            message: Msg,
+            from: PublicKey,
+            _: Arc<CollectingSender<Msg>>,
+            output: Output<Out>,
+        ) {
+            self.gauge.enter();
+            self.total.enter();
+            time::sleep(self.delay).await;
+            self.total.exit();
+            self.gauge.exit();
+
+            output.emit((self.name, from, message));
+        }
+    }
+
+    fn traffic(peers: &[PublicKey], count: usize) -> Vec<(PublicKey, Msg)> {
+        (0..count)
+            .map(|i| {
+                let message = match i % 4 {
+                    0 => Msg::Fast(i),
+                    1 => Msg::Slow(i),
+                    2 => Msg::Chained(i),
+                    _ => Msg::Other(i),
+                };
+
+                (peers[i % peers.len()], message)
+            })
+            .collect()
+    }
+
+    async fn deliver_all(
+        handle: &mut RouterHandle<Msg, Out, CollectingSender<Msg>>,
+        count: usize,
+    ) -> Vec<Out> {
+        let mut delivered = Vec::with_capacity(count);
+
+        while delivered.len() < count {
+            let output = <_ as Handle<Msg, Out>>::deliver_timeout(
+                handle,
+                Duration::from_secs(5),
+            )
+            .await
+            .expect("delivery failed")
+            .expect("routes stalled");
+
+            delivered.push(output);
+        }
+
+        delivered
+    }
+
+    #[tokio::test]
+    async fn routes_respect_limits() {
+        const COUNT: usize = 160;
+
+        let peers = keyset(5).collect::<Vec<_>>();
+        let total = Gauge::default();
+        let fast = Probe::new("fast", 2, &total);
+        let slow = Probe::new("slow", 5, &total);
+        let chained = Probe::new("chained", 3, &total);
+        let router = Router::new()
+            .route_with(
+                |m: &Msg| matches!(m, Msg::Fast(_)),
+                fast.clone(),
+                RouteConfig::default().limit(4),
+            )
+            .route_with(
+                |m: &Msg| matches!(m, Msg::Slow(_)),
+                slow.clone(),
+                RouteConfig::default()
+                    .limit(8)
+                    .order(RouteOrder::Sequential),
+            )
+            .route_with(
+                |m: &Msg| matches!(m, Msg::Chained(_)),
+                chained.clone(),
+                RouteConfig::default()
+                    .limit(3)
+                    .order(RouteOrder::PerPeer)
+                    .capacity(4),
+            )
+            .fallback(
+                |message: Msg,
+                 from: PublicKey,
+                 _: Arc<CollectingSender<Msg>>,
+                 output: Output<Out>| async move {
+                    output.emit(("fallback", from, message));
+                },
+                RouteConfig::default().limit(2),
+            );
+        let mut manager =
+            DummyManager::with_key(traffic(&peers, COUNT), peers.clone());
+        let mut handle = manager.run::<Msg, _>(router.build()).await;
+        let delivered = deliver_all(&mut handle, COUNT).await;
+
+        assert_eq!(fast.gauge.max(), 4, "fast route not concurrent");
+        assert_eq!(slow.gauge.max(), 1, "sequential route overlapped");
+        assert!(chained.gauge.max() <= 3, "chained route above its limit");
+        assert!(total.max() <= 8, "more handlers than routes allow");
+
+        let order = |name| {
+            delivered
+                .iter()
+                .filter(|(route, _, _)| *route == name)
+                .map(|(_, from, message)| (*from, *message))
+                .collect::<Vec<_>>()
+        };
+        let sent = |f: fn(&Msg) -> bool| {
+            traffic(&peers, COUNT)
+                .into_iter()
+                .filter(|(_, message)| f(message))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(order("slow"), sent(|m| matches!(m, Msg::Slow(_))));
+
+        for peer in &peers {
+            let chained = order("chained")
+                .into_iter()
+                .filter(|(from, _)| from == peer)
+                .collect::<Vec<_>>();
+            let expected = sent(|m| matches!(m, Msg::Chained(_)))
+                .into_iter()
+                .filter(|(from, _)| from == peer)
+                .collect::<Vec<_>>();
+
+            assert_eq!(chained, expected, "chained route reordered a peer");
+        }
+
+        let mut others = order("fallback");
+
+        others.sort_by_key(|(_, message)| match message {
+            Msg::Other(i) => *i,
+            _ => panic!("routed message reached the fallback"),
+        });
+
+        assert_eq!(others, sent(|m| matches!(m, Msg::Other(_))));
+        assert_eq!(order("fast").len(), COUNT / 4);
+    }
+
+    #[tokio::test]
+    async fn budget_shared_by_routes() {
+        const COUNT: usize = 64;
+
+        let peers = keyset(2).collect::<Vec<_>>();
+        let total = Gauge::default();
+        let fast = Probe::new("fast", 2, &total);
+        let slow = Probe::new("slow", 2, &total);
+        let other = Probe::new("other", 2, &total);
+        let config = RouteConfig::default().limit(4);
+        let router = Router::new()
+            .route_with(|m: &Msg| matches!(m, Msg::Fast(_)), fast, config)
+            .route_with(|m: &Msg| matches!(m, Msg::Slow(_)), slow, config)
+            .fallback(other, config)
+            .budget(3);
+        let mut manager =
+            DummyManager::with_key(traffic(&peers, COUNT), peers.clone());
+        let mut handle = manager.run::<Msg, _>(router.build()).await;
+
+        deliver_all(&mut handle, COUNT).await;
+
+        assert_eq!(total.max(), 3, "budget not shared by all routes");
+    }
+
+    async fn route(
+        router: &RouterProcessor<Msg, Out, CollectingSender<Msg>>,
+        message: Msg,
+        from: PublicKey,
+        sender: Arc<CollectingSender<Msg>>,
+    ) -> Result<(), RouterError> {
+        <_ as Processor<Msg, Msg, Out, _>>::process(
+            router, message, from, sender,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn unrouted_without_fallback() {
+        let peers = keyset(1).collect::<Vec<_>>();
+        let sender = Arc::new(CollectingSender::new(peers.clone()));
+        let mut router: RouterProcessor<Msg, Out, _> = Router::new()
+            .route(
+                |m: &Msg| matches!(m, Msg::Fast(_)),
+                Probe::new("fast", 0, &Gauge::default()),
+            )
+            .build();
+        let from = peers[0];
+
+        assert!(matches!(
+            route(&router, Msg::Fast(0), from, sender.clone()).await,
+            Err(RouterError::NotSetup)
+        ));
+
+        let _handle = <_ as Processor<Msg, Msg, Out, _>>::setup(
+            &mut router,
+            Arc::new(AllSampler::default()),
+            sender.clone(),
+        )
+        .await;
+
+        route(&router, Msg::Fast(0), from, sender.clone())
+            .await
+            .expect("routing failed");
+
+        assert!(matches!(
+            route(&router, Msg::Other(0), from, sender).await,
+            Err(RouterError::Unrouted)
+        ));
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt, sync::Mutex};
 
-use peroxide::fuga::*;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
 use snafu::{ensure, OptionExt, Snafu};
 
 use crate::{async_trait, crypto::key::exchange::PublicKey};
@@ -72,12 +72,29 @@ pub trait Sampler: Send + Sync {
 }
 
 /// A naive sampler using Poisson sampling
-#[derive(Clone, Copy)]
-pub struct PoissonSampler {}
+pub struct PoissonSampler {
+    rng: Mutex<Box<dyn RngCore + Send>>,
+}
+
+impl PoissonSampler {
+    /// Create a `PoissonSampler` drawing its randomness from the given `rng`.
+    /// Using a seeded rng makes the sequence of samples reproducible.
+    pub fn from_rng<R: RngCore + Send + 'static>(rng: R) -> Self {
+        Self {
+            rng: Mutex::new(Box::new(rng)),
+        }
+    }
+}
 
 impl Default for PoissonSampler {
     fn default() -> Self {
-        Self {}
+        Self::from_rng(StdRng::from_entropy())
+    }
+}
+
+impl fmt::Debug for PoissonSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoissonSampler").finish_non_exhaustive()
     }
 }
 
@@ -89,32 +106,21 @@ impl Sampler for PoissonSampler {
         expected: usize,
         size: usize,
     ) -> Result<HashSet<PublicKey>, SampleError> {
-        let prob = expected as f64 / size as f64;
-        let sampler = Bernoulli(prob);
-        let mut sample = sampler.sample(size as usize);
-
-        Ok(keys
-            .filter(move |_| {
-                if let Some(x) = sample.pop() {
-                    (x - 1.0).abs() < f64::EPSILON
-                } else {
-                    false
-                }
-            })
-            .collect())
+        if size == 0 {
+            return Ok(HashSet::new());
+        }
+
+        let prob = (expected as f64 / size as f64).min(1.0);
+        let mut rng = self.rng.lock().expect("sampler rng poisoned");
+
+        Ok(keys.take(size).filter(|_| rng.gen_bool(prob)).collect())
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default)]
 /// Sampler that selects all known keys instead of sampling randomly
 pub struct AllSampler {}
 
-impl Default for AllSampler {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
 #[async_trait]
 impl Sampler for AllSampler {
     async fn sample_unchecked<I: Iterator<Item = PublicKey> + Send>(
@@ -127,6 +133,65 @@ impl Sampler for AllSampler {
     }
 }
 
+/// A `Sampler` that only samples among a fixed set of neighbors, such as the
+/// ones assigned by an [`Overlay`], before delegating to another `Sampler`
+///
+/// [`Overlay`]: super::Overlay
+#[derive(Debug)]
+pub struct NeighborSampler<S> {
+    inner: S,
+    neighbors: HashSet<PublicKey>,
+}
+
+impl<S: Sampler> NeighborSampler<S> {
+    /// Create a `NeighborSampler` restricting `inner` to `neighbors`
+    pub fn new(
+        inner: S,
+        neighbors: impl IntoIterator<Item = PublicKey>,
+    ) -> Self {
+        Self {
+            inner,
+            neighbors: neighbors.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Sampler> Sampler for NeighborSampler<S> {
+    async fn sample<I: Iterator<Item = PublicKey> + Send>(
+        &self,
+        keys: I,
+        expected: usize,
+    ) -> Result<HashSet<PublicKey>, SampleError> {
+        let keys = keys
+            .filter(|key| self.neighbors.contains(key))
+            .collect::<Vec<_>>();
+        let actual = keys.len();
+
+        ensure!(expected <= actual, TooSmall { expected, actual });
+
+        self.inner
+            .sample_unchecked(keys.into_iter(), expected, actual)
+            .await
+    }
+
+    async fn sample_unchecked<I: Iterator<Item = PublicKey> + Send>(
+        &self,
+        keys: I,
+        expected: usize,
+        _: usize,
+    ) -> Result<HashSet<PublicKey>, SampleError> {
+        let keys = keys
+            .filter(|key| self.neighbors.contains(key))
+            .collect::<Vec<_>>();
+        let total = keys.len();
+
+        self.inner
+            .sample_unchecked(keys.into_iter(), expected.min(total), total)
+            .await
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::{
@@ -200,6 +265,85 @@ mod test {
         );
     }
 
+    async fn sample_indices(
+        sampler: &PoissonSampler,
+        keys: &[PublicKey],
+        expected: usize,
+    ) -> Vec<usize> {
+        let sample = sampler
+            .sample(keys.iter().copied(), expected)
+            .await
+            .expect("sampling failed");
+
+        keys.iter()
+            .enumerate()
+            .filter(|(_, key)| sample.contains(key))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn poisson_seeded_golden() {
+        const GOLDEN: [&[usize]; 4] =
+            [&[4, 8, 9], &[3, 5, 6, 8], &[3, 7], &[5, 8]];
+
+        let keys = keyset(10).collect::<Vec<_>>();
+        let sampler = PoissonSampler::from_rng(StdRng::seed_from_u64(42));
+
+        for expected in GOLDEN.iter() {
+            let indices = sample_indices(&sampler, &keys, 3).await;
+
+            assert_eq!(&indices, expected, "sample sequence changed");
+        }
+    }
+
+    #[tokio::test]
+    async fn poisson_seeded_reproducible() {
+        let keys = keyset(EXPECTED).collect::<Vec<_>>();
+        let first = PoissonSampler::from_rng(StdRng::seed_from_u64(7));
+        let second = PoissonSampler::from_rng(StdRng::seed_from_u64(7));
+
+        for _ in 0..ROUNDS {
+            assert_eq!(
+                sample_indices(&first, &keys, EXPECTED / 4).await,
+                sample_indices(&second, &keys, EXPECTED / 4).await,
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn poisson_inclusion_probability() {
+        const SIZE: usize = 20;
+        const SAMPLED: usize = 5;
+        const TRIALS: u64 = 2000;
+        const TOLERANCE: f64 = 0.05;
+
+        let keys = keyset(SIZE).collect::<Vec<_>>();
+        let mut counts = [0usize; SIZE];
+
+        for seed in 0..TRIALS {
+            let sampler = PoissonSampler::from_rng(StdRng::seed_from_u64(seed));
+
+            for idx in sample_indices(&sampler, &keys, SAMPLED).await {
+                counts[idx] += 1;
+            }
+        }
+
+        let theoretical = SAMPLED as f64 / SIZE as f64;
+
+        for (idx, count) in counts.iter().enumerate() {
+            let empirical = *count as f64 / TRIALS as f64;
+
+            assert!(
+                (empirical - theoretical).abs() < TOLERANCE,
+                "key {} included with probability {} instead of {}",
+                idx,
+                empirical,
+                theoretical
+            );
+        }
+    }
+
     #[tokio::test]
     async fn all() {
         sampling_test!(AllSampler, EXPECTED, EXPECTED, EXPECTED);
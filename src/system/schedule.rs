@@ -0,0 +1,83 @@
+#[cfg(any(test, feature = "test"))]
+use std::cell::RefCell;
+
+#[cfg(any(test, feature = "test"))]
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+#[cfg(any(test, feature = "test"))]
+use tokio::task;
+
+/// Maximum number of times `interleave` yields to the scheduler
+#[cfg(any(test, feature = "test"))]
+const MAX_YIELDS: usize = 3;
+
+#[cfg(any(test, feature = "test"))]
+thread_local! {
+    /// Generator driving the ordering decisions of the deterministic run
+    /// executing on this thread, if any
+    static ORDER: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Guard returned by `seed`, ordering decisions go back to their default
+/// once it is dropped
+#[cfg(any(test, feature = "test"))]
+pub(crate) struct Seeded(());
+
+#[cfg(any(test, feature = "test"))]
+impl Drop for Seeded {
+    fn drop(&mut self) {
+        ORDER.with(|order| order.borrow_mut().take());
+    }
+}
+
+/// Make ordering decisions taken on the current thread depend only on `seed`
+/// until the returned guard is dropped
+#[cfg(any(test, feature = "test"))]
+pub(crate) fn seed(seed: u64) -> Seeded {
+    ORDER.with(|order| order.replace(Some(StdRng::seed_from_u64(seed))));
+
+    Seeded(())
+}
+
+/// Put `items` in the order chosen by the current deterministic run: items are
+/// sorted by `key` and then shuffled using the seed of the run. Outside of
+/// deterministic runs the order of `items` is left untouched.
+#[cfg(any(test, feature = "test"))]
+pub(crate) fn arrange<T, K, F>(items: &mut [T], key: F)
+where
+    K: AsRef<[u8]>,
+    F: Fn(&T) -> K,
+{
+    ORDER.with(|order| {
+        if let Some(rng) = order.borrow_mut().as_mut() {
+            items.sort_by(|a, b| key(a).as_ref().cmp(key(b).as_ref()));
+            items.shuffle(rng);
+        }
+    });
+}
+
+#[cfg(not(any(test, feature = "test")))]
+pub(crate) fn arrange<T, K, F>(_: &mut [T], _: F)
+where
+    K: AsRef<[u8]>,
+    F: Fn(&T) -> K,
+{
+}
+
+/// Let other tasks run a number of times chosen by the current deterministic
+/// run. This does nothing outside of deterministic runs.
+#[cfg(any(test, feature = "test"))]
+pub(crate) async fn interleave() {
+    let yields = ORDER.with(|order| {
+        order
+            .borrow_mut()
+            .as_mut()
+            .map_or(0, |rng| rng.gen_range(0..=MAX_YIELDS))
+    });
+
+    for _ in 0..yields {
+        task::yield_now().await;
+    }
+}
+
+#[cfg(not(any(test, feature = "test")))]
+pub(crate) async fn interleave() {}
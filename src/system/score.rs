@@ -0,0 +1,428 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use super::dump::ScoreSnapshot;
+use crate::{
+    crypto::key::exchange::PublicKey, net::ReceiveError, telemetry::targets,
+};
+
+/// How bad a protocol violation committed by a peer is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// Suspicious behaviour that may be caused by a benign bug
+    Minor,
+    /// A message that could not be understood
+    Major,
+    /// A violation that breaks the `Connection` it happened on
+    Fatal,
+}
+
+/// Action taken once the score of a peer reaches a threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ViolationAction {
+    /// Only report the peer
+    Log,
+    /// Close all `Connection`s to the peer, it may connect again later
+    Disconnect,
+    /// Close all `Connection`s to the peer and refuse new ones
+    Ban,
+}
+
+impl fmt::Display for ViolationAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self {
+            Self::Log => "logged",
+            Self::Disconnect => "disconnected",
+            Self::Ban => "banned",
+        };
+
+        write!(f, "{}", action)
+    }
+}
+
+/// Points and thresholds used by a [`PeerScoreboard`]. Each violation adds
+/// the weight of its [`Severity`] to the score of the peer, which then decays
+/// exponentially so that old violations are eventually forgiven.
+///
+/// [`PeerScoreboard`]: self::PeerScoreboard
+/// [`Severity`]: self::Severity
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreConfig {
+    weights: [f64; 3],
+    thresholds: [f64; 3],
+    half_life: Duration,
+}
+
+impl ScoreConfig {
+    /// Add `points` to the score of a peer for each violation of the given
+    /// `Severity`
+    pub fn weight(mut self, severity: Severity, points: f64) -> Self {
+        self.weights[severity as usize] = points;
+        self
+    }
+
+    /// Take `action` once the score of a peer reaches `score`. Use
+    /// `f64::INFINITY` to never take it.
+    pub fn threshold(mut self, action: ViolationAction, score: f64) -> Self {
+        self.thresholds[action as usize] = score;
+        self
+    }
+
+    /// Halve scores every `half_life`
+    pub fn half_life(mut self, half_life: Duration) -> Self {
+        self.half_life = half_life;
+        self
+    }
+}
+
+impl Default for ScoreConfig {
+    fn default() -> Self {
+        Self {
+            weights: [1.0, 10.0, 50.0],
+            thresholds: [10.0, 50.0, 100.0],
+            half_life: Duration::from_secs(600),
+        }
+    }
+}
+
+/// A threshold action fired by a [`PeerScoreboard`]
+///
+/// [`PeerScoreboard`]: self::PeerScoreboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Violation {
+    /// The offending peer
+    pub pkey: PublicKey,
+    /// Score of the peer after the violation
+    pub score: f64,
+    /// Action taken against the peer
+    pub action: ViolationAction,
+}
+
+struct Score {
+    value: f64,
+    updated: Instant,
+}
+
+struct Board {
+    scores: HashMap<PublicKey, Score>,
+    banned: HashSet<PublicKey>,
+}
+
+struct Shared {
+    config: ScoreConfig,
+    board: Mutex<Board>,
+    /// Peers whose `Connection`s must be closed
+    evicted: watch::Sender<HashSet<PublicKey>>,
+    fired: mpsc::UnboundedSender<Violation>,
+}
+
+/// Keeps track of the protocol violations committed by each peer of a
+/// `SystemManager` and decides when they should be disconnected or banned.
+/// Cloning a `PeerScoreboard` yields a handle to the same scores.
+#[derive(Clone)]
+pub struct PeerScoreboard {
+    shared: Arc<Shared>,
+}
+
+impl PeerScoreboard {
+    /// Create a new `PeerScoreboard` along with the receiving end of the
+    /// actions it fires
+    pub(crate) fn new(
+        config: ScoreConfig,
+    ) -> (Self, mpsc::UnboundedReceiver<Violation>) {
+        let (fired, fired_rx) = mpsc::unbounded_channel();
+        let board = Board {
+            scores: HashMap::new(),
+            banned: HashSet::new(),
+        };
+
+        let shared = Shared {
+            config,
+            board: Mutex::new(board),
+            evicted: watch::channel(HashSet::new()).0,
+            fired,
+        };
+
+        (
+            Self {
+                shared: Arc::new(shared),
+            },
+            fired_rx,
+        )
+    }
+
+    /// Record a violation committed by `pkey`, returning the strongest action
+    /// whose threshold was crossed by this violation if any
+    pub fn report(
+        &self,
+        pkey: PublicKey,
+        severity: Severity,
+    ) -> Option<ViolationAction> {
+        let config = &self.shared.config;
+        let mut board = self.shared.board.lock().expect("scoreboard poisoned");
+        let now = Instant::now();
+        let score = board.scores.entry(pkey).or_insert(Score {
+            value: 0.0,
+            updated: now,
+        });
+
+        let before = decayed(score, config.half_life, now);
+        let after = before + config.weights[severity as usize];
+
+        score.value = after;
+        score.updated = now;
+
+        warn!(
+            target: targets::MANAGER,
+            "{:?} violation by {}, score is now {}",
+            severity, pkey, after
+        );
+
+        let action = [
+            ViolationAction::Ban,
+            ViolationAction::Disconnect,
+            ViolationAction::Log,
+        ]
+        .into_iter()
+        .find(|action| {
+            let threshold = config.thresholds[*action as usize];
+
+            before < threshold && after >= threshold
+        })?;
+
+        if action == ViolationAction::Ban {
+            board.banned.insert(pkey);
+        }
+
+        if action >= ViolationAction::Disconnect {
+            info!(
+                target: targets::MANAGER,
+                "{} {} after reaching score {}", pkey, action, after
+            );
+
+            self.shared.evicted.send_modify(|evicted| {
+                evicted.insert(pkey);
+            });
+        }
+
+        let _ = self.shared.fired.send(Violation {
+            pkey,
+            score: after,
+            action,
+        });
+
+        Some(action)
+    }
+
+    /// Record a violation that caused receiving from `pkey` to fail, if the
+    /// error is one
+    pub(crate) fn report_error(&self, pkey: PublicKey, error: &ReceiveError) {
+        if let Some(severity) = severity(error) {
+            self.report(pkey, severity);
+        }
+    }
+
+    /// Get the current score of `pkey`
+    pub fn score(&self, pkey: &PublicKey) -> f64 {
+        let board = self.shared.board.lock().expect("scoreboard poisoned");
+        let now = Instant::now();
+
+        board.scores.get(pkey).map_or(0.0, |score| {
+            decayed(score, self.shared.config.half_life, now)
+        })
+    }
+
+    /// Get the current score of every peer that committed a violation,
+    /// highest score first
+    pub fn scores(&self) -> Vec<(PublicKey, f64)> {
+        let board = self.shared.board.lock().expect("scoreboard poisoned");
+        let now = Instant::now();
+        let mut scores: Vec<_> = board
+            .scores
+            .iter()
+            .map(|(pkey, score)| {
+                (*pkey, decayed(score, self.shared.config.half_life, now))
+            })
+            .collect();
+
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+    }
+
+    /// Get the standing of every peer that committed a violation or was
+    /// banned, or `None` if the scores are currently locked
+    pub(crate) fn try_snapshot(
+        &self,
+    ) -> Option<BTreeMap<String, ScoreSnapshot>> {
+        let board = self.shared.board.try_lock().ok()?;
+        let now = Instant::now();
+        let half_life = self.shared.config.half_life;
+        let mut snapshot: BTreeMap<_, _> = board
+            .scores
+            .iter()
+            .map(|(pkey, score)| {
+                let standing = ScoreSnapshot {
+                    score: decayed(score, half_life, now),
+                    banned: board.banned.contains(pkey),
+                };
+
+                (pkey.to_string(), standing)
+            })
+            .collect();
+
+        for pkey in &board.banned {
+            snapshot.entry(pkey.to_string()).or_insert(ScoreSnapshot {
+                score: 0.0,
+                banned: true,
+            });
+        }
+
+        Some(snapshot)
+    }
+
+    /// Check whether `pkey` has been banned
+    pub fn is_banned(&self, pkey: &PublicKey) -> bool {
+        let board = self.shared.board.lock().expect("scoreboard poisoned");
+
+        board.banned.contains(pkey)
+    }
+
+    /// Lift the ban on `pkey`, returning whether it was banned
+    pub fn unban(&self, pkey: &PublicKey) -> bool {
+        let mut board = self.shared.board.lock().expect("scoreboard poisoned");
+
+        board.banned.remove(pkey)
+    }
+
+    /// Check whether a new `Connection` from `pkey` can be accepted, allowing
+    /// peers that were only disconnected to connect again
+    pub(crate) fn admit(&self, pkey: &PublicKey) -> bool {
+        if self.is_banned(pkey) {
+            return false;
+        }
+
+        self.shared
+            .evicted
+            .send_if_modified(|evicted| evicted.remove(pkey));
+
+        true
+    }
+
+    /// Wait until the `Connection`s to `pkey` must be closed
+    pub(crate) async fn evicted(&self, pkey: PublicKey) {
+        let mut evicted = self.shared.evicted.subscribe();
+
+        // the sender is kept alive by self
+        let _ = evicted.wait_for(|evicted| evicted.contains(&pkey)).await;
+    }
+}
+
+/// Apply exponential decay to `score` up to `now`
+fn decayed(score: &Score, half_life: Duration, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(score.updated).as_secs_f64();
+
+    score.value * 0.5f64.powf(elapsed / half_life.as_secs_f64())
+}
+
+/// Get the `Severity` of the violation that caused `error`, if any
+fn severity(error: &ReceiveError) -> Option<Severity> {
+    use crate::crypto::stream::DecryptError;
+
+    match error {
+        ReceiveError::Decrypt {
+            source: DecryptError::SerializeDecrypt { .. },
+        } => Some(Severity::Major),
+        ReceiveError::Decrypt { .. }
+        | ReceiveError::DeserializeReceive { .. }
+        | ReceiveError::OversizedReceive { .. }
+        | ReceiveError::BadFragment { .. } => Some(Severity::Fatal),
+        ReceiveError::IncompleteMessage { .. } => Some(Severity::Minor),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::key::exchange::KeyPair;
+
+    fn pkey() -> PublicKey {
+        *KeyPair::random().public()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn thresholds() {
+        let (board, mut fired) = PeerScoreboard::new(ScoreConfig::default());
+        let (bad, good) = (pkey(), pkey());
+
+        assert_eq!(board.report(bad, Severity::Minor), None);
+        assert_eq!(
+            board.report(bad, Severity::Major),
+            Some(ViolationAction::Log)
+        );
+        assert_eq!(
+            board.report(bad, Severity::Fatal),
+            Some(ViolationAction::Disconnect)
+        );
+        assert!(!board.is_banned(&bad), "banned too early");
+        assert_eq!(
+            board.report(bad, Severity::Fatal),
+            Some(ViolationAction::Ban)
+        );
+        assert!(board.is_banned(&bad), "not banned");
+        assert!(!board.admit(&bad), "banned peer admitted");
+        assert!(board.admit(&good), "clean peer rejected");
+
+        let actions: Vec<_> = (0..3)
+            .map(|_| fired.try_recv().expect("action not fired").action)
+            .collect();
+
+        assert_eq!(
+            actions,
+            [
+                ViolationAction::Log,
+                ViolationAction::Disconnect,
+                ViolationAction::Ban
+            ]
+        );
+        assert_eq!(board.scores(), vec![(bad, 111.0)]);
+
+        board.unban(&bad);
+
+        assert!(board.admit(&bad), "unbanned peer rejected");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decay() {
+        let config = ScoreConfig::default().half_life(Duration::from_secs(60));
+        let (board, _fired) = PeerScoreboard::new(config);
+        let peer = pkey();
+
+        board.report(peer, Severity::Fatal);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+
+        assert!((board.score(&peer) - 25.0).abs() < 1e-6, "no decay");
+
+        // the decayed score stays below the disconnect threshold
+        assert_eq!(board.report(peer, Severity::Major), None);
+
+        tokio::time::advance(Duration::from_secs(3600)).await;
+
+        assert!(board.score(&peer) < 1e-6, "old violations not forgiven");
+        assert_eq!(
+            board.report(peer, Severity::Fatal),
+            Some(ViolationAction::Disconnect)
+        );
+    }
+}
@@ -1,25 +1,43 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     marker::PhantomData,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
 };
 
 use futures::{
-    future,
+    future::{self, BoxFuture, Either, FutureExt},
+    pin_mut,
     stream::{FuturesUnordered, Stream, StreamExt, TryStreamExt},
 };
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use tokio::{
-    sync::{mpsc, oneshot, Mutex, RwLock},
+    sync::{mpsc, oneshot, watch, Mutex, MutexGuard, Notify, RwLock},
     task,
+    time::{self, Instant},
 };
-use tracing::{debug_span, warn};
+use tracing::{debug, debug_span, info, warn};
 use tracing_futures::Instrument;
 
+use super::{
+    dump::QueueSnapshot,
+    reconnect::{ReconnectEvent, ReconnectPolicy, ReconnectScheduler},
+    replay::{Direction, ReplayRecorder},
+    schedule,
+};
 use crate::{
     async_trait,
     crypto::key::exchange::PublicKey,
-    net::{ConnectionWrite, SendError},
+    message,
+    net::{
+        wire_size, ConnectionRead, ConnectionWrite, Connector, CryptoPool,
+        Outgoing, Pacer, SendError,
+    },
+    telemetry::targets,
     Message,
 };
 
@@ -40,7 +58,71 @@ pub enum SenderError {
         /// Actual cause of the error
         source: SendError,
     },
-    #[snafu(display("{} send errors", errors.len()))]
+    #[snafu(display(
+        "could not reconnect to {} after {} attempts: {}",
+        remote,
+        attempts,
+        reason
+    ))]
+    /// The `Connection` with the peer broke and could not be
+    /// re-established, see `NetworkSender::with_reconnect`
+    Reconnect {
+        /// The peer we were trying to send to
+        remote: PublicKey,
+        /// Number of attempts made to reconnect
+        attempts: usize,
+        /// Why the last attempt failed
+        reason: String,
+    },
+    #[snafu(display(
+        "quota exceeded for {}, retry after {:?}",
+        remote,
+        retry_after
+    ))]
+    /// Sending the message would exceed the quota for this peer
+    QuotaExceeded {
+        /// The peer we attempted to send to
+        remote: PublicKey,
+        /// Time after which enough budget will be available
+        retry_after: Duration,
+    },
+    #[snafu(display("{} messages already queued for {}", bound, remote))]
+    /// The outgoing queue of this peer is full, see
+    /// `ConnectionLimits::send_queue_bound`
+    QueueFull {
+        /// The peer we attempted to send to
+        remote: PublicKey,
+        /// Maximum number of queued messages
+        bound: usize,
+    },
+    #[snafu(display(
+        "{} bytes already queued for {}, cap is {}",
+        queued,
+        remote,
+        cap
+    ))]
+    /// Queuing the message would exceed the `BufferLimit` of the
+    /// `NetworkSender`, either globally or for this peer
+    BufferFull {
+        /// The peer we attempted to send to
+        remote: PublicKey,
+        /// Bytes already queued
+        queued: u64,
+        /// Number of bytes that may be queued
+        cap: u64,
+    },
+    #[snafu(display(
+        "connection with {} was re-established while sending",
+        remote
+    ))]
+    /// The message was being sent in fragments when the `Connection` with
+    /// the peer was re-established, so that the fragments sent so far were
+    /// lost, see `NetworkSender::with_reconnect`
+    Interrupted {
+        /// The peer we were trying to send to
+        remote: PublicKey,
+    },
+    #[snafu(display("{}", summarize(errors)))]
     /// Many send errors were encountered
     ManyErrors {
         /// All encountered errors when sending multiple messages
@@ -48,8 +130,36 @@ pub enum SenderError {
     },
 }
 
+/// Maximum number of errors listed when displaying `SenderError::ManyErrors`
+const MAX_DISPLAYED_ERRORS: usize = 3;
+
+fn summarize(errors: &[SenderError]) -> String {
+    let mut summary = format!("{} send errors", errors.len());
+
+    for (idx, error) in errors.iter().take(MAX_DISPLAYED_ERRORS).enumerate() {
+        summary.push_str(if idx == 0 { ": " } else { "; " });
+        summary.push_str(&error.to_string());
+    }
+
+    if errors.len() > MAX_DISPLAYED_ERRORS {
+        summary.push_str(&format!(
+            " and {} more",
+            errors.len() - MAX_DISPLAYED_ERRORS
+        ));
+    }
+
+    summary
+}
+
 #[async_trait]
 /// Trait used when sending messages out from `Processor`s.
+///
+/// # Ordering
+/// Messages sent to the same peer are delivered in the order in which they
+/// were handed to the `Sender`: a call that starts executing before another
+/// one enqueues its messages first, and messages passed to
+/// `send_many_to_one` are delivered in iteration order. Implementations
+/// must uphold this guarantee for all methods.
 pub trait Sender<M: Message + 'static>: Send + Sync {
     /// Add a new `ConnectionWrite` to this `Sender`
     async fn add_connection(&self, write: ConnectionWrite);
@@ -64,6 +174,15 @@ pub trait Sender<M: Message + 'static>: Send + Sync {
     /// at this time.
     async fn keys(&self) -> Vec<PublicKey>;
 
+    /// Subscribe to the set of peers known by this `Sender`. The receiver is
+    /// notified every time a peer is added or removed, intermediate sets may
+    /// be skipped if it lags behind.
+    ///
+    /// The default implementation returns an empty set that never changes.
+    fn watch_keys(&self) -> KeysReceiver {
+        watch::channel(Arc::default()).1
+    }
+
     /// Send a message to a given peer using this `Sender`
     async fn send(
         &self,
@@ -71,7 +190,7 @@ pub trait Sender<M: Message + 'static>: Send + Sync {
         pkey: &PublicKey,
     ) -> Result<(), SenderError>;
 
-    /// Send a set of messages to a remote peer
+    /// Send a set of messages to a remote peer, in iteration order
     ///
     /// # Returns
     /// An `Err` if any message fails to be sent, `Ok` otherwise
@@ -84,12 +203,11 @@ pub trait Sender<M: Message + 'static>: Send + Sync {
         I: IntoIterator<Item = M> + Send,
         I::IntoIter: Send,
     {
-        messages
-            .into_iter()
-            .map(|message| self.send(message, to))
-            .collect::<FuturesUnordered<_>>()
-            .try_fold((), |_, _| future::ready(Ok(())))
-            .await
+        for message in messages {
+            self.send(message, to).await?;
+        }
+
+        Ok(())
     }
 
     /// Send a set of messages contained in an async `Stream` to a remote peer
@@ -117,23 +235,41 @@ pub trait Sender<M: Message + 'static>: Send + Sync {
     ///
     /// # Returns
     ///
-    /// An `Err` if any one message failed to be sent, `Ok` otherwise
+    /// The result of sending to each peer, in the same order as `keys`
     async fn send_many<'a, I: Iterator<Item = &'a PublicKey> + Send>(
         &self,
         message: M,
         keys: I,
-    ) -> Result<(), SenderError> {
-        let errors = keys
-            .map(|key| {
-                let message = message.clone();
-                self.send(message, key)
-            })
-            .collect::<FuturesUnordered<_>>();
+    ) -> Vec<Result<(), SenderError>> {
+        let sends = keys.map(|key| {
+            let message = message.clone();
+            self.send(message, key)
+        });
 
-        let errors = errors
-            .filter_map(|x| async move { x.err() })
-            .collect::<Vec<_>>()
-            .await;
+        future::join_all(sends).await
+    }
+
+    /// Send the same message to many different peers like
+    /// `Sender::send_many`, collecting all failures in a single error
+    ///
+    /// # Returns
+    ///
+    /// An `Err(SenderError::ManyErrors)` if any one message failed to be
+    /// sent, `Ok` otherwise
+    async fn send_many_collecting<'a, I>(
+        &self,
+        message: M,
+        keys: I,
+    ) -> Result<(), SenderError>
+    where
+        I: Iterator<Item = &'a PublicKey> + Send,
+    {
+        let errors = self
+            .send_many(message, keys)
+            .await
+            .into_iter()
+            .filter_map(Result::err)
+            .collect::<Vec<_>>();
 
         if errors.is_empty() {
             Ok(())
@@ -143,9 +279,55 @@ pub trait Sender<M: Message + 'static>: Send + Sync {
     }
 }
 
+/// Receiving end of a subscription to the peers known by a `Sender`
+pub type KeysReceiver = watch::Receiver<Arc<HashSet<PublicKey>>>;
+
+/// Publish the keys of `agents` to `watched` if they changed
+fn publish_keys<V>(
+    watched: &watch::Sender<Arc<HashSet<PublicKey>>>,
+    agents: &HashMap<PublicKey, V>,
+) {
+    watched.send_if_modified(|keys| {
+        let modified = keys.len() != agents.len()
+            || agents.keys().any(|key| !keys.contains(key));
+
+        if modified {
+            *keys = Arc::new(agents.keys().copied().collect());
+        }
+
+        modified
+    });
+}
+
+/// Stop reporting peers that are no longer in `agents` as lagging
+fn retain_lagging<V>(
+    lagging: &watch::Sender<Arc<HashSet<PublicKey>>>,
+    agents: &HashMap<PublicKey, V>,
+) {
+    lagging.send_if_modified(|lagging| {
+        let modified = lagging.iter().any(|key| !agents.contains_key(key));
+
+        if modified {
+            Arc::make_mut(lagging).retain(|key| agents.contains_key(key));
+        }
+
+        modified
+    });
+}
+
 /// A handle to send messages to other known processes
 pub struct NetworkSender<M: Message> {
-    agents: RwLock<HashMap<PublicKey, SenderChannel<M>>>,
+    agents: RwLock<HashMap<PublicKey, AgentHandle<M>>>,
+    watched: watch::Sender<Arc<HashSet<PublicKey>>>,
+    pool: Option<CryptoPool>,
+    buffer: Arc<Buffer>,
+    recorder: Option<ReplayRecorder>,
+    fairness: Option<Fairness>,
+    /// Peers currently lagging behind broadcasts, see `Fairness`
+    lagging: watch::Sender<Arc<HashSet<PublicKey>>>,
+    /// Shared with every agent, which may be spawned before
+    /// `with_reconnect` is called
+    redial: Arc<StdMutex<Redial>>,
 }
 
 impl<M: Message> NetworkSender<M>
@@ -154,281 +336,2053 @@ where
 {
     /// Create a new `Sender` from a `Vec` of `ConnectionWrite`
     pub fn new<I: IntoIterator<Item = ConnectionWrite>>(writes: I) -> Self {
+        Self::with_pool(writes, None)
+    }
+
+    /// Create a new `Sender` that encrypts messages on the given
+    /// `CryptoPool` if any, instead of the async runtime
+    pub fn with_pool<I>(writes: I, pool: Option<CryptoPool>) -> Self
+    where
+        I: IntoIterator<Item = ConnectionWrite>,
+    {
+        let redial = Arc::new(StdMutex::new(Redial::default()));
         let agents = writes
             .into_iter()
-            .map(|x| (*x.remote_pkey(), Self::spawn_agent(x)))
+            .map(|x| {
+                let key = *x.remote_pkey();
+
+                (key, Self::spawn_agent(x, pool.clone(), redial.clone()))
+            })
             .collect::<HashMap<_, _>>();
+        let keys = agents.keys().copied().collect();
 
         Self {
             agents: RwLock::new(agents),
+            watched: watch::Sender::new(Arc::new(keys)),
+            pool,
+            buffer: Arc::new(Buffer::new(None)),
+            recorder: None,
+            fairness: None,
+            lagging: watch::Sender::new(Arc::default()),
+            redial,
         }
     }
 
-    fn spawn_agent(write: ConnectionWrite) -> SenderChannel<M> {
-        let (tx, rx) = mpsc::channel(32);
-        let agent = SenderAgent::new(write, rx);
-
-        agent.spawn();
-
-        tx
+    /// Bound the total size of the messages queued by this `NetworkSender`
+    /// using the given `BufferLimit`
+    pub fn with_buffer_limit(mut self, limit: BufferLimit) -> Self {
+        self.buffer = Arc::new(Buffer::new(Some(limit)));
+        self
     }
-}
 
-#[async_trait]
-impl<M: Message + 'static> Sender<M> for NetworkSender<M> {
-    async fn send(
-        &self,
-        message: M,
-        pkey: &PublicKey,
-    ) -> Result<(), SenderError> {
-        {
-            let guard = self.agents.read().await;
-            let agent =
-                guard.get(pkey).context(NoSuchPeer { remote: *pkey })?;
-            let (tx, rx) = oneshot::channel();
+    /// Pace broadcasts to peers that lag behind the others according to the
+    /// given `Fairness`
+    pub fn with_fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = Some(fairness);
+        self
+    }
 
-            agent
-                .send((message, tx))
-                .await
-                .ok()
-                .context(NoSuchPeer { remote: *pkey })?;
-            rx
-        }
-        .await
-        .ok()
-        .context(NoSuchPeer { remote: *pkey })?
-        .context(ConnectionError { remote: *pkey })
+    /// Re-establish the connection to a peer using `connector` and its
+    /// entry in `candidates` when sending to it fails because the
+    /// connection broke. Sending waits until the peer is reconnected, which
+    /// is attempted according to the `ReconnectPolicy` set using
+    /// `with_reconnect_policy`, and fails with `SenderError::Reconnect` if
+    /// it can't be, as do all later sends to that peer. Messages queued in
+    /// the meantime are sent once reconnected. <br />
+    /// All peers are reconnected by a single `ReconnectScheduler`, so that
+    /// dials are paced by the `Pacer` set using `with_reconnect_pacer`. <br />
+    /// Peers without a candidate and peers removed using
+    /// `Sender::remove_connection` are never reconnected.
+    pub fn with_reconnect<C>(
+        self,
+        connector: C,
+        candidates: HashMap<PublicKey, C::Candidate>,
+    ) -> Self
+    where
+        C: Connector + 'static,
+        C::Candidate: Clone,
+    {
+        self.with_redialer(Redialer::new(connector, candidates))
     }
 
-    /// Add a new `ConnectionWrite` to this `Sender`
-    async fn add_connection(&self, write: ConnectionWrite) {
-        let key = *write.remote_pkey();
-        let agent = Self::spawn_agent(write);
+    /// Re-establish broken connections using the given `Redialer`, see
+    /// `with_reconnect`
+    pub(crate) fn with_redialer(self, redialer: Redialer) -> Self {
+        let mut redial = self.redial.lock().expect("redial poisoned");
 
-        if self.agents.write().await.insert(key, agent).is_some() {
-            warn!("replaced existing outgoing connection to {}, messages may be lost", key);
-        }
-    }
+        redial.start = Some(redialer.0);
+        redial.reset();
+        drop(redial);
 
-    async fn remove_connection(&self, key: &PublicKey) {
-        self.agents.write().await.remove(key);
+        self
     }
 
-    async fn keys(&self) -> Vec<PublicKey> {
-        self.agents.read().await.keys().copied().collect()
-    }
-}
+    /// Reconnect to peers according to `policy` instead of the default
+    /// `ReconnectPolicy`, see `with_reconnect`
+    pub fn with_reconnect_policy(self, policy: ReconnectPolicy) -> Self {
+        let mut redial = self.redial.lock().expect("redial poisoned");
 
-type SenderChannel<M> =
-    mpsc::Sender<(M, oneshot::Sender<Result<(), SendError>>)>;
+        redial.policy = policy;
+        redial.reset();
+        drop(redial);
 
-type AgentChannel<M> =
-    mpsc::Receiver<(M, oneshot::Sender<Result<(), SendError>>)>;
+        self
+    }
 
-struct SenderAgent<M: Message> {
-    connection: ConnectionWrite,
-    commands: AgentChannel<M>,
-}
+    /// Start reconnection dials as allowed by `pacer` instead of right away,
+    /// see `with_reconnect`. Sharing `pacer` with other components bounds
+    /// the dials of all of them together.
+    pub fn with_reconnect_pacer(self, pacer: Pacer) -> Self {
+        let mut redial = self.redial.lock().expect("redial poisoned");
 
-impl<M> SenderAgent<M>
-where
-    M: Message + 'static,
-{
-    fn new(connection: ConnectionWrite, commands: AgentChannel<M>) -> Self {
-        Self {
-            connection,
-            commands,
-        }
+        redial.pacer = pacer;
+        redial.reset();
+        drop(redial);
+
+        self
     }
 
-    fn spawn(self) {
-        let key = *self.connection.remote_pkey();
+    /// Reconnect to `pkey` before peers with a lower `priority` when more
+    /// peers are being reconnected than can be dialed at once, see
+    /// `with_reconnect`. Peers have priority 0 unless set otherwise.
+    pub fn prioritize_reconnect(&self, pkey: PublicKey, priority: u32) {
+        let mut redial = self.redial.lock().expect("redial poisoned");
 
-        task::spawn(
-            async move {
-                self.process_loop().await;
-            }
-            .instrument(debug_span!("sender_agent", remote=%key)),
-        );
-    }
+        redial.priorities.insert(pkey, priority);
 
-    async fn process_loop(mut self) {
-        while let Some((message, resp)) = self.commands.recv().await {
-            let _ = resp.send(self.connection.send(&message).await);
+        if let Some(scheduler) = &redial.scheduler {
+            scheduler.prioritize(pkey, priority);
         }
-
-        warn!("sender agent exiting");
     }
-}
-
-/// A `Sender` that uses an input messages type I and implements an output `Sender`
-/// using the `Into` trait
-pub struct ConvertSender<I, O, S>
-where
-    I: Message + 'static + Into<O>,
-    O: Message + 'static,
-    S: Sender<O>,
-{
-    sender: Arc<S>,
-    _i: PhantomData<I>,
-    _o: PhantomData<O>,
-}
 
-impl<I, O, S> ConvertSender<I, O, S>
-where
-    I: Message + 'static + Into<O>,
-    O: Message + 'static,
-    S: Sender<O>,
-{
-    /// Create a new `ConvertSender` using the provided underlying `Sender`
-    /// to actually send messages
-    pub fn new(sender: Arc<S>) -> Self {
-        Self {
-            sender,
-            _i: PhantomData,
-            _o: PhantomData,
+    /// Get the peers that are being reconnected, see `with_reconnect`
+    pub async fn reconnecting(&self) -> Vec<PublicKey> {
+        let scheduler = self
+            .redial
+            .lock()
+            .expect("redial poisoned")
+            .scheduler
+            .clone();
+
+        match scheduler {
+            Some(scheduler) => scheduler.scheduled().await,
+            None => Vec::new(),
         }
     }
 
-    /// Return the inner sender wrapped by this `ConvertSender`
-    pub fn into_inner(self) -> Arc<S> {
-        self.sender
-    }
-}
+    /// Get the `ConnectionRead` of every connection re-established from now
+    /// on, see `with_reconnect`. They are dropped if this was never called,
+    /// and only the last receiver gets them otherwise.
+    pub fn reconnected_reads(&self) -> mpsc::UnboundedReceiver<ConnectionRead> {
+        let (tx, rx) = mpsc::unbounded_channel();
 
-#[async_trait]
-impl<I, O, S> Sender<I> for ConvertSender<I, O, S>
-where
-    I: Message + 'static + Into<O>,
-    O: Message + 'static,
-    S: Sender<O>,
-{
-    async fn send(
-        &self,
-        message: I,
-        to: &PublicKey,
-    ) -> Result<(), SenderError> {
-        self.sender.send(message.into(), to).await
+        self.redial.lock().expect("redial poisoned").reads = Some(tx);
+
+        rx
     }
 
-    async fn keys(&self) -> Vec<PublicKey> {
-        self.sender.keys().await
+    /// Record every message queued by this `NetworkSender` using the given
+    /// `ReplayRecorder`
+    pub(crate) fn with_recorder(mut self, recorder: ReplayRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
     }
 
-    async fn add_connection(&self, write: ConnectionWrite) {
-        self.sender.add_connection(write).await
+    /// Get the total size of the messages queued for every peer, as
+    /// measured by `wire_size`
+    pub fn queued_bytes(&self) -> u64 {
+        self.buffer.queued.load(Ordering::Acquire)
     }
 
-    async fn remove_connection(&self, key: &PublicKey) {
-        self.sender.remove_connection(key).await;
+    /// Get the total size of the messages queued for `pkey`, if it is known
+    pub async fn peer_queued_bytes(&self, pkey: &PublicKey) -> Option<u64> {
+        self.agents
+            .read()
+            .await
+            .get(pkey)
+            .map(|agent| agent.stats.queued_bytes.load(Ordering::Acquire))
     }
-}
 
-/// A `Sender` that only collects messages instead of sending them
-pub struct CollectingSender<M: Message> {
-    messages: Mutex<Vec<(PublicKey, M)>>,
-    keys: Mutex<HashSet<PublicKey>>,
-}
+    /// Subscribe to the set of peers lagging behind broadcasts, as
+    /// determined by the `Fairness` of this `NetworkSender`. The receiver is
+    /// notified every time a peer starts or stops lagging.
+    pub fn watch_lagging(&self) -> KeysReceiver {
+        self.lagging.subscribe()
+    }
 
-impl<M: Message> CollectingSender<M> {
-    /// Create a new `CollectingSender` using a specified set of `PublicKey`
-    /// destinations
-    pub fn new<I: IntoIterator<Item = PublicKey>>(keys: I) -> Self {
-        Self {
-            messages: Mutex::new(Vec::new()),
-            keys: Mutex::new(keys.into_iter().collect()),
+    fn spawn_agent(
+        write: ConnectionWrite,
+        pool: Option<CryptoPool>,
+        redial: Arc<StdMutex<Redial>>,
+    ) -> AgentHandle<M> {
+        let (channel, rx) = mpsc::unbounded_channel();
+        let stats = Arc::new(AgentStats::default());
+        let bound = write.limits().send_queue();
+        let agent = SenderAgent::new(write, rx, pool, stats.clone(), redial);
+        let task = agent.spawn();
+
+        AgentHandle {
+            channel,
+            task,
+            stats,
+            bound,
         }
     }
 
-    /// Retrieve the set of messages that was sent using this `CollectingSender`
-    pub async fn messages(&self) -> Vec<(PublicKey, M)> {
-        self.messages.lock().await.iter().cloned().collect()
+    /// Get the state of the outgoing queue of every peer, keyed by the
+    /// hexadecimal form of their `PublicKey`
+    pub async fn snapshot(&self) -> BTreeMap<String, QueueSnapshot> {
+        let now = Instant::now();
+
+        self.agents
+            .read()
+            .await
+            .iter()
+            .map(|(pkey, agent)| (pkey.to_string(), agent.stats.snapshot(now)))
+            .collect()
     }
-}
 
-#[async_trait]
-impl<M: Message + 'static> Sender<M> for CollectingSender<M> {
-    async fn send(
-        &self,
-        message: M,
-        key: &PublicKey,
-    ) -> Result<(), SenderError> {
-        ensure!(
-            self.keys.lock().await.contains(key),
-            NoSuchPeer { remote: *key }
-        );
+    /// Stop accepting new messages, send all messages that were already
+    /// queued and close every outgoing `Connection` of this `NetworkSender`.
+    /// Returns the number of `Connection`s that were closed.
+    pub async fn close(&self) -> usize {
+        let agents = self.drain().await;
+        let count = agents.len();
 
-        self.messages.lock().await.push((*key, message));
+        agents
+            .into_iter()
+            .map(|(_, agent)| {
+                // agents exit once their queue is empty and the channel is
+                // closed
+                drop(agent.channel);
+                agent.task
+            })
+            .collect::<FuturesUnordered<_>>()
+            .for_each(|_| future::ready(()))
+            .await;
 
-        Ok(())
+        count
     }
 
-    async fn remove_connection(&self, key: &PublicKey) {
-        self.keys.lock().await.remove(key);
-    }
+    /// Stop accepting new messages, send all messages that were already
+    /// queued and give back the outgoing `ConnectionWrite` of every peer
+    /// instead of closing it, so that it can be used elsewhere.
+    pub async fn reclaim(&self) -> Vec<ConnectionWrite> {
+        let agents = self.drain().await;
 
-    async fn add_connection(&self, write: ConnectionWrite) {
-        self.keys.lock().await.insert(*write.remote_pkey());
+        agents
+            .into_iter()
+            .map(|(_, agent)| {
+                // the agent sends everything queued before stopping
+                let _ = agent.channel.send(Command::Reclaim);
+                agent.task
+            })
+            .collect::<FuturesUnordered<_>>()
+            .filter_map(|write| future::ready(write.ok().flatten()))
+            .collect()
+            .await
     }
 
-    async fn keys(&self) -> Vec<PublicKey> {
-        self.keys.lock().await.clone().iter().copied().collect()
+    /// Remove every agent from this `NetworkSender`
+    async fn drain(&self) -> Vec<(PublicKey, AgentHandle<M>)> {
+        let mut agents = self.agents.write().await;
+        let drained = agents
+            .drain()
+            .inspect(|(_, agent)| agent.stats.remove())
+            .collect();
+
+        publish_keys(&self.watched, &agents);
+        retain_lagging(&self.lagging, &agents);
+
+        drained
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::net::{Ipv4Addr, SocketAddr};
+    /// Hand a message to the agent of a peer without waiting for it to be
+    /// sent. Agent queues never block so that messages are enqueued in the
+    /// order in which they are given to this `NetworkSender`, sending to a
+    /// peer whose queue is full fails instead. Only waiting for room in the
+    /// `Buffer` may block, see `BufferLimit::block`.
+    ///
+    /// The message is held back until the backlog of the agent fits in
+    /// `window` if it does not already, or until the messages held back
+    /// before it were handed to the agent. Returns whether it was held back.
+    async fn enqueue(
+        &self,
+        agent: Option<&AgentTarget<M>>,
+        peers: usize,
+        message: M,
+        pkey: &PublicKey,
+        window: Option<u64>,
+    ) -> Result<(SendResult, bool), SenderError> {
+        let agent = agent.context(NoSuchPeer { remote: *pkey })?;
+        let bytes = wire_size(&message).unwrap_or_default();
+        let reservation = self
+            .buffer
+            .reserve(&agent.stats, peers, bytes, pkey)
+            .await?;
+        let (tx, rx) = oneshot::channel();
+        let reply = Reply {
+            tx,
+            reservation,
+            remote: *pkey,
+        };
+        let queued = agent.stats.queued.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(bound) = agent.bound.filter(|bound| queued >= *bound) {
+            agent.stats.queued.fetch_sub(1, Ordering::Relaxed);
+
+            return QueueFull {
+                remote: *pkey,
+                bound,
+            }
+            .fail();
+        }
 
-    use serde::{Deserialize, Serialize};
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Outbound, pkey, &message);
+        }
 
-    use super::*;
-    use crate::{
-        crypto::key::exchange::Exchanger,
-        message,
-        net::{Connector, Listener, TcpConnector, TcpListener},
-        test::keyset,
-    };
+        let command = Command::Send(message, reply);
+        let stats = &agent.stats;
+        let mut tail = stats.held_tail.lock().expect("agent stats poisoned");
+        let backlog = stats.backlog();
 
-    #[tokio::test]
-    async fn convert_sender() {
-        #[message]
-        #[derive(Copy)]
-        struct M1(u8);
+        // a message alone in the queue is never held back, even if it does
+        // not fit in the window
+        let held = stats.held.load(Ordering::Acquire) > 0
+            || window.is_some_and(|window| backlog > window && backlog > bytes);
 
-        #[message]
-        #[derive(Copy, Eq, PartialEq)]
-        struct M2(u16);
+        if !held {
+            agent
+                .channel
+                .send(command)
+                .ok()
+                .context(NoSuchPeer { remote: *pkey })
+                .inspect_err(|_| {
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
+                })?;
 
-        impl From<M1> for M2 {
-            fn from(v: M1) -> Self {
-                Self(v.0.into())
-            }
+            return Ok((rx, false));
         }
 
-        const COUNT: usize = 10;
+        stats.held.fetch_add(1, Ordering::AcqRel);
+        stats.held_bytes.fetch_add(bytes, Ordering::AcqRel);
 
-        let expected = (0..COUNT as u16).map(M2).collect::<Vec<_>>();
+        let (done, next) = oneshot::channel();
+        let previous = tail.replace(next);
 
-        let peer = keyset(1).next().unwrap();
-        let sender = CollectingSender::<M2>::new(vec![peer]);
+        drop(tail);
 
-        let sender = ConvertSender::new(Arc::new(sender));
+        task::spawn(Self::release(
+            agent.channel.clone(),
+            stats.clone(),
+            command,
+            bytes,
+            window,
+            previous,
+            done,
+        ));
 
-        sender
+        Ok((rx, true))
+    }
+
+    /// Get the agent of each of `keys` along with the number of peers, so
+    /// that the agents are not locked while waiting for room in the `Buffer`
+    async fn targets<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a PublicKey>,
+    ) -> (Vec<Option<AgentTarget<M>>>, usize) {
+        let agents = self.agents.read().await;
+        let targets = keys
+            .into_iter()
+            .map(|key| agents.get(key).map(AgentHandle::target))
+            .collect();
+
+        (targets, agents.len())
+    }
+
+    /// Hand a message that was held back to its agent once `previous` was
+    /// handed over and the backlog of the agent fits in `window`
+    async fn release(
+        channel: SenderChannel<M>,
+        stats: Arc<AgentStats>,
+        command: Command<M>,
+        bytes: u64,
+        window: Option<u64>,
+        previous: Option<oneshot::Receiver<()>>,
+        done: oneshot::Sender<()>,
+    ) {
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+
+        if let Some(window) = window {
+            loop {
+                let drained = stats.drained.notified();
+                let backlog = stats.backlog();
+
+                if backlog == 0 || backlog + bytes <= window {
+                    break;
+                }
+
+                drained.await;
+            }
+        }
+
+        let _tail = stats.held_tail.lock().expect("agent stats poisoned");
+
+        stats.held.fetch_sub(1, Ordering::AcqRel);
+        stats.held_bytes.fetch_sub(bytes, Ordering::AcqRel);
+
+        // the reply is dropped along with the command if the agent stopped,
+        // which fails the send
+        if channel.send(command).is_err() {
+            stats.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        drop(done);
+    }
+
+    /// Update whether `pkey` is lagging after a broadcast, depending on
+    /// whether its message was `held` back
+    fn track_lag(
+        &self,
+        fairness: &Fairness,
+        stats: &AgentStats,
+        pkey: &PublicKey,
+        held: bool,
+    ) {
+        if !held {
+            stats.streak.store(0, Ordering::Relaxed);
+
+            if stats.lagging.swap(false, Ordering::AcqRel) {
+                debug!(target: targets::SENDER, "{} caught up", pkey);
+
+                self.lagging.send_modify(|lagging| {
+                    Arc::make_mut(lagging).remove(pkey);
+                });
+            }
+
+            return;
+        }
+
+        let streak = stats.streak.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if streak >= fairness.lagging_after
+            && !stats.lagging.swap(true, Ordering::AcqRel)
+        {
+            warn!(
+                target: targets::SENDER,
+                "{} lagging behind for {} broadcasts", pkey, streak
+            );
+
+            self.lagging.send_modify(|lagging| {
+                Arc::make_mut(lagging).insert(*pkey);
+            });
+        }
+    }
+
+    /// Wait for an enqueued message to be sent
+    async fn complete(
+        enqueued: Result<SendResult, SenderError>,
+        pkey: PublicKey,
+    ) -> Result<(), SenderError> {
+        enqueued?.await.ok().context(NoSuchPeer { remote: pkey })?
+    }
+}
+
+#[async_trait]
+impl<M: Message + 'static> Sender<M> for NetworkSender<M> {
+    async fn send(
+        &self,
+        message: M,
+        pkey: &PublicKey,
+    ) -> Result<(), SenderError> {
+        let enqueued = {
+            let _admission = self.buffer.admission().await;
+            let (targets, peers) = self.targets([pkey]).await;
+
+            self.enqueue(targets[0].as_ref(), peers, message, pkey, None)
+                .await
+                .map(|(rx, _)| rx)
+        };
+
+        Self::complete(enqueued, *pkey).await
+    }
+
+    async fn send_many_to_one<'a, I>(
+        &self,
+        messages: I,
+        to: &PublicKey,
+    ) -> Result<(), SenderError>
+    where
+        I: IntoIterator<Item = M> + Send,
+        I::IntoIter: Send,
+    {
+        let enqueued = {
+            let _admission = self.buffer.admission().await;
+            let (targets, peers) = self.targets([to]).await;
+            let mut enqueued = Vec::new();
+
+            for message in messages {
+                enqueued.push(
+                    self.enqueue(targets[0].as_ref(), peers, message, to, None)
+                        .await
+                        .map(|(rx, _)| rx),
+                );
+            }
+
+            enqueued
+        };
+
+        enqueued
+            .into_iter()
+            .map(|enqueued| Self::complete(enqueued, *to))
+            .collect::<FuturesUnordered<_>>()
+            .try_fold((), |_, _| future::ready(Ok(())))
+            .await
+    }
+
+    async fn send_many<'a, I: Iterator<Item = &'a PublicKey> + Send>(
+        &self,
+        message: M,
+        keys: I,
+    ) -> Vec<Result<(), SenderError>> {
+        let enqueued = {
+            let _admission = self.buffer.admission().await;
+            let keys = keys.collect::<Vec<_>>();
+            let (targets, peers) = self.targets(keys.iter().copied()).await;
+            let window = self.fairness.map(|fairness| {
+                let mut backlogs = targets
+                    .iter()
+                    .flatten()
+                    .map(|agent| {
+                        agent.stats.queued_bytes.load(Ordering::Acquire)
+                    })
+                    .collect::<Vec<_>>();
+
+                fairness.window(&mut backlogs)
+            });
+            let mut enqueued = Vec::new();
+
+            for (key, agent) in keys.into_iter().zip(&targets) {
+                let message = message.clone();
+                let result = self
+                    .enqueue(agent.as_ref(), peers, message, key, window)
+                    .await;
+
+                if let (Some(fairness), Some(agent)) = (&self.fairness, agent) {
+                    let held = matches!(result, Ok((_, true)));
+
+                    self.track_lag(fairness, &agent.stats, key, held);
+                }
+
+                enqueued.push((*key, result.map(|(rx, _)| rx)));
+            }
+
+            enqueued
+        };
+
+        future::join_all(
+            enqueued
+                .into_iter()
+                .map(|(key, enqueued)| Self::complete(enqueued, key)),
+        )
+        .await
+    }
+
+    /// Add a new `ConnectionWrite` to this `Sender`
+    async fn add_connection(&self, write: ConnectionWrite) {
+        let key = *write.remote_pkey();
+        let agent =
+            Self::spawn_agent(write, self.pool.clone(), self.redial.clone());
+        let mut agents = self.agents.write().await;
+
+        if let Some(previous) = agents.insert(key, agent) {
+            previous.stats.remove();
+
+            warn!(
+                target: targets::SENDER,
+                "replaced existing outgoing connection to {}, messages may be lost",
+                key
+            );
+
+            self.lagging.send_if_modified(|lagging| {
+                lagging.contains(&key) && Arc::make_mut(lagging).remove(&key)
+            });
+        }
+
+        publish_keys(&self.watched, &agents);
+    }
+
+    async fn remove_connection(&self, key: &PublicKey) {
+        let mut agents = self.agents.write().await;
+
+        if let Some(agent) = agents.remove(key) {
+            agent.stats.remove();
+        }
+
+        publish_keys(&self.watched, &agents);
+        retain_lagging(&self.lagging, &agents);
+    }
+
+    async fn keys(&self) -> Vec<PublicKey> {
+        let mut keys =
+            self.agents.read().await.keys().copied().collect::<Vec<_>>();
+
+        schedule::arrange(&mut keys, |pkey| *pkey);
+
+        keys
+    }
+
+    fn watch_keys(&self) -> KeysReceiver {
+        self.watched.subscribe()
+    }
+}
+
+type SendResult = oneshot::Receiver<Result<(), SenderError>>;
+
+/// Commands handled by a `SenderAgent`
+enum Command<M> {
+    /// Send a message and report the outcome
+    Send(M, Reply),
+    /// Stop without closing the `ConnectionWrite` and give it back
+    Reclaim,
+}
+
+type SenderChannel<M> = mpsc::UnboundedSender<Command<M>>;
+
+/// The queue of a running `SenderAgent` along with its task
+struct AgentHandle<M> {
+    channel: SenderChannel<M>,
+    task: task::JoinHandle<Option<ConnectionWrite>>,
+    stats: Arc<AgentStats>,
+    /// Maximum number of queued messages, taken from the `ConnectionLimits`
+    /// of the `ConnectionWrite` when the agent is spawned
+    bound: Option<usize>,
+}
+
+impl<M> AgentHandle<M> {
+    fn target(&self) -> AgentTarget<M> {
+        AgentTarget {
+            channel: self.channel.clone(),
+            stats: self.stats.clone(),
+            bound: self.bound,
+        }
+    }
+}
+
+/// What is needed to hand messages to a running `SenderAgent`, taken from
+/// its `AgentHandle`
+struct AgentTarget<M> {
+    channel: SenderChannel<M>,
+    stats: Arc<AgentStats>,
+    bound: Option<usize>,
+}
+
+/// Channel used to report the outcome of sending a message, holding its
+/// place in the `Buffer` until then
+struct Reply {
+    tx: oneshot::Sender<Result<(), SenderError>>,
+    reservation: Reservation,
+    remote: PublicKey,
+}
+
+impl Reply {
+    /// Size of the message on the wire
+    fn bytes(&self) -> u64 {
+        self.reservation.bytes
+    }
+
+    /// Report the outcome of sending the message, releasing its place in the
+    /// `Buffer`
+    fn send(self, result: Result<(), SendError>) {
+        let remote = self.remote;
+
+        let _ = self.tx.send(result.context(ConnectionError { remote }));
+    }
+
+    /// Report that the message could not be sent because of `error`
+    fn fail(self, error: SenderError) {
+        let _ = self.tx.send(Err(error));
+    }
+}
+
+/// Reconnections to the peers of a `NetworkSender`, scheduled by a
+/// `ReconnectScheduler` shared by all its `SenderAgent`s
+trait Redials: Send + Sync {
+    /// Schedule reconnecting to `pkey`, `None` if it has no candidate
+    fn reconnect(
+        &self,
+        pkey: PublicKey,
+        priority: u32,
+    ) -> Option<oneshot::Receiver<ReconnectEvent>>;
+
+    /// Change the priority of `pkey` if it is being reconnected
+    fn prioritize(&self, pkey: PublicKey, priority: u32);
+
+    /// Stop reconnecting to `pkey`
+    fn cancel(&self, pkey: PublicKey);
+
+    /// Get the peers that are being reconnected
+    fn scheduled(&self) -> BoxFuture<'_, Vec<PublicKey>>;
+}
+
+/// A `ReconnectScheduler` along with the candidates of each peer
+struct Scheduled<A> {
+    scheduler: ReconnectScheduler<A>,
+    candidates: Arc<HashMap<PublicKey, A>>,
+}
+
+impl<A> Redials for Scheduled<A>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    fn reconnect(
+        &self,
+        pkey: PublicKey,
+        priority: u32,
+    ) -> Option<oneshot::Receiver<ReconnectEvent>> {
+        let candidate = self.candidates.get(&pkey)?.clone();
+
+        Some(self.scheduler.reconnect(pkey, vec![candidate], priority))
+    }
+
+    fn prioritize(&self, pkey: PublicKey, priority: u32) {
+        self.scheduler.prioritize(pkey, priority);
+    }
+
+    fn cancel(&self, pkey: PublicKey) {
+        self.scheduler.cancel(pkey);
+    }
+
+    fn scheduled(&self) -> BoxFuture<'_, Vec<PublicKey>> {
+        self.scheduler.scheduled().boxed()
+    }
+}
+
+/// Starts the `ReconnectScheduler` of a `NetworkSender` once it is needed
+type StartFn =
+    Arc<dyn Fn(Pacer, ReconnectPolicy) -> Arc<dyn Redials> + Send + Sync>;
+
+/// Dials peers again using a `Connector` and its candidates, kept aside
+/// until the `NetworkSender` it is meant for is created
+#[derive(Clone)]
+pub(crate) struct Redialer(StartFn);
+
+impl Redialer {
+    /// Dial peers that have an entry in `candidates` using `connector`
+    pub(crate) fn new<C>(
+        connector: C,
+        candidates: HashMap<PublicKey, C::Candidate>,
+    ) -> Self
+    where
+        C: Connector + 'static,
+        C::Candidate: Clone,
+    {
+        let connector = Arc::new(connector);
+        let candidates = Arc::new(candidates);
+
+        Self(Arc::new(move |pacer, policy| {
+            // outcomes are reported to each agent rather than as events
+            let (scheduler, _) =
+                ReconnectScheduler::new(connector.clone(), pacer, policy);
+
+            Arc::new(Scheduled {
+                scheduler,
+                candidates: candidates.clone(),
+            })
+        }))
+    }
+}
+
+impl std::fmt::Debug for Redialer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Redialer")
+    }
+}
+
+/// How `SenderAgent`s re-establish broken connections, see
+/// `NetworkSender::with_reconnect`
+#[derive(Default)]
+struct Redial {
+    start: Option<StartFn>,
+    policy: ReconnectPolicy,
+    pacer: Pacer,
+    /// Scheduler shared by all agents, started on the first reconnection
+    scheduler: Option<Arc<dyn Redials>>,
+    /// Priorities set using `NetworkSender::prioritize_reconnect`
+    priorities: HashMap<PublicKey, u32>,
+    /// Where to hand the `ConnectionRead` of re-established connections
+    reads: Option<mpsc::UnboundedSender<ConnectionRead>>,
+}
+
+impl Redial {
+    /// Schedule reconnecting to `pkey`, `None` if it can't be reconnected
+    fn reconnect(
+        &mut self,
+        pkey: PublicKey,
+    ) -> Option<oneshot::Receiver<ReconnectEvent>> {
+        let start = self.start.as_ref()?;
+        let priority = self.priorities.get(&pkey).copied().unwrap_or_default();
+        let scheduler = self
+            .scheduler
+            .get_or_insert_with(|| start(self.pacer.clone(), self.policy));
+
+        scheduler.reconnect(pkey, priority)
+    }
+
+    /// Restart the scheduler with the current settings when next needed
+    fn reset(&mut self) {
+        self.scheduler = None;
+    }
+}
+
+/// Bound on the total size of the messages queued by a `NetworkSender`.
+/// Each peer may use an equal share of it so that a stalled peer can't
+/// prevent sending to others, messages that don't fit fail with
+/// `SenderError::BufferFull`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferLimit {
+    cap: u64,
+    block: bool,
+}
+
+impl BufferLimit {
+    /// Queue at most `cap` bytes of messages, as measured by `wire_size`
+    pub fn new(cap: u64) -> Self {
+        Self { cap, block: false }
+    }
+
+    /// Wait for room instead of failing when the global cap is reached.
+    /// Sends exceeding the share of their peer still fail, and a waiting
+    /// send delays the ones started after it to keep messages ordered.
+    pub fn block(mut self, block: bool) -> Self {
+        self.block = block;
+        self
+    }
+
+    /// Get the maximum number of queued bytes
+    pub fn cap(&self) -> u64 {
+        self.cap
+    }
+}
+
+/// Default value of `Fairness::min_window`
+pub const DEFAULT_MIN_WINDOW: u64 = 64 * 1024;
+
+/// Default value of `Fairness::lagging_after`
+pub const DEFAULT_LAGGING_AFTER: usize = 8;
+
+/// Pacing of broadcasts made using `Sender::send_many`, so that peers that
+/// can't keep up don't accumulate an ever growing backlog. The backlog of a
+/// peer may not exceed a multiple of the median backlog of the peers of a
+/// broadcast, the message is otherwise held back until the peer catches up
+/// while other peers get it right away. Messages to a peer are still sent
+/// in order. <br />
+/// A peer whose messages are held back for `lagging_after` consecutive
+/// broadcasts is reported as lagging, see `NetworkSender::watch_lagging`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fairness {
+    multiple: u64,
+    min_window: u64,
+    lagging_after: usize,
+}
+
+impl Fairness {
+    /// Allow each peer a backlog of `multiple` times the median backlog
+    pub fn new(multiple: u64) -> Self {
+        Self {
+            multiple,
+            min_window: DEFAULT_MIN_WINDOW,
+            lagging_after: DEFAULT_LAGGING_AFTER,
+        }
+    }
+
+    /// Always allow a backlog of `bytes`, even when the median backlog is
+    /// smaller, instead of `DEFAULT_MIN_WINDOW`
+    pub fn min_window(mut self, bytes: u64) -> Self {
+        self.min_window = bytes;
+        self
+    }
+
+    /// Report a peer as lagging once its messages were held back for
+    /// `broadcasts` consecutive broadcasts, instead of
+    /// `DEFAULT_LAGGING_AFTER`
+    pub fn lagging_after(mut self, broadcasts: usize) -> Self {
+        self.lagging_after = broadcasts.max(1);
+        self
+    }
+
+    /// Get the backlog allowed for each peer given the `backlogs` of all
+    /// peers of a broadcast
+    fn window(&self, backlogs: &mut [u64]) -> u64 {
+        backlogs.sort_unstable();
+
+        let median = backlogs.get(backlogs.len() / 2).copied().unwrap_or(0);
+
+        median.saturating_mul(self.multiple).max(self.min_window)
+    }
+}
+
+/// Accounting of the bytes queued by all agents of a `NetworkSender`
+struct Buffer {
+    queued: AtomicU64,
+    limit: Option<BufferLimit>,
+    /// Notified every time bytes are released
+    released: Notify,
+    /// Held while enqueuing when sends may wait for room, so that they
+    /// enqueue in the order in which they started
+    admission: Mutex<()>,
+}
+
+impl Buffer {
+    fn new(limit: Option<BufferLimit>) -> Self {
+        Self {
+            queued: AtomicU64::new(0),
+            limit,
+            released: Notify::new(),
+            admission: Mutex::new(()),
+        }
+    }
+
+    /// Serialize enqueuing if sends may wait for room
+    async fn admission(&self) -> Option<MutexGuard<'_, ()>> {
+        match self.limit {
+            Some(limit) if limit.block => Some(self.admission.lock().await),
+            _ => None,
+        }
+    }
+
+    /// Reserve `bytes` for a message to the peer with the given `stats` out
+    /// of `peers`
+    async fn reserve(
+        self: &Arc<Self>,
+        stats: &Arc<AgentStats>,
+        peers: usize,
+        bytes: u64,
+        remote: &PublicKey,
+    ) -> Result<Reservation, SenderError> {
+        if let Some(limit) = self.limit {
+            let share = limit.cap / peers.max(1) as u64;
+            let queued = stats.queued_bytes.load(Ordering::Acquire);
+
+            ensure!(
+                queued + bytes <= share,
+                BufferFull {
+                    remote: *remote,
+                    queued,
+                    cap: share,
+                }
+            );
+
+            loop {
+                let released = self.released.notified();
+                let reserved = self.queued.fetch_update(
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                    |queued| {
+                        (queued + bytes <= limit.cap).then_some(queued + bytes)
+                    },
+                );
+
+                match reserved {
+                    Ok(_) => break,
+                    Err(_) if limit.block => released.await,
+                    Err(queued) => {
+                        return BufferFull {
+                            remote: *remote,
+                            queued,
+                            cap: limit.cap,
+                        }
+                        .fail()
+                    }
+                }
+            }
+        } else {
+            self.queued.fetch_add(bytes, Ordering::AcqRel);
+        }
+
+        stats.queued_bytes.fetch_add(bytes, Ordering::AcqRel);
+
+        Ok(Reservation {
+            buffer: self.clone(),
+            stats: stats.clone(),
+            bytes,
+        })
+    }
+}
+
+/// Bytes taken by a queued message in the `Buffer`, released when the
+/// message is sent, fails or is dropped along with the queue of its agent
+struct Reservation {
+    buffer: Arc<Buffer>,
+    stats: Arc<AgentStats>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.stats
+            .queued_bytes
+            .fetch_sub(self.bytes, Ordering::AcqRel);
+        self.buffer.queued.fetch_sub(self.bytes, Ordering::AcqRel);
+        self.buffer.released.notify_waiters();
+        self.stats.drained.notify_waiters();
+    }
+}
+
+/// Counters updated by a `SenderAgent`
+#[derive(Default)]
+struct AgentStats {
+    /// Messages enqueued but not sent yet
+    queued: AtomicUsize,
+    /// Size of the messages enqueued but not sent yet
+    queued_bytes: AtomicU64,
+    sent: AtomicU64,
+    failed: AtomicU64,
+    bytes: AtomicU64,
+    /// Set once sending failed, the `ConnectionWrite` is then unusable
+    broken: AtomicBool,
+    last_send: StdMutex<Option<Instant>>,
+    /// Messages held back by `Fairness`, not handed to the agent yet
+    held: AtomicUsize,
+    /// Size of the messages held back by `Fairness`
+    held_bytes: AtomicU64,
+    /// Resolved once the last held back message was handed to the agent
+    held_tail: StdMutex<Option<oneshot::Receiver<()>>>,
+    /// Consecutive broadcasts for which a message was held back
+    streak: AtomicUsize,
+    lagging: AtomicBool,
+    /// Notified every time queued bytes are released
+    drained: Notify,
+    /// Times the connection was re-established
+    reconnects: AtomicU64,
+    /// Set once the agent was removed from its `NetworkSender`, it then
+    /// stops reconnecting
+    removed: AtomicBool,
+    /// Notified when `removed` is set
+    removal: Notify,
+}
+
+impl AgentStats {
+    /// Size of the messages handed to the agent but not sent yet
+    fn backlog(&self) -> u64 {
+        self.queued_bytes
+            .load(Ordering::Acquire)
+            .saturating_sub(self.held_bytes.load(Ordering::Acquire))
+    }
+
+    /// Mark the agent as removed from its `NetworkSender`
+    fn remove(&self) {
+        self.removed.store(true, Ordering::Release);
+        self.removal.notify_waiters();
+    }
+
+    fn record(&self, bytes: u64, success: bool) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+
+        if success {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            self.broken.store(true, Ordering::Relaxed);
+        }
+
+        *self.last_send.lock().expect("agent stats poisoned") =
+            Some(Instant::now());
+    }
+
+    /// Count a message that could not be sent without the connection
+    /// being broken
+    fn record_interrupted(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, now: Instant) -> QueueSnapshot {
+        let last_send = *self.last_send.lock().expect("agent stats poisoned");
+
+        QueueSnapshot {
+            queued: self.queued.load(Ordering::Relaxed),
+            queued_bytes: self.queued_bytes.load(Ordering::Acquire),
+            sent: self.sent.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            broken: self.broken.load(Ordering::Relaxed),
+            idle_ms: last_send.map(|last| {
+                now.saturating_duration_since(last).as_millis() as u64
+            }),
+            held: self.held.load(Ordering::Acquire),
+            lagging: self.lagging.load(Ordering::Acquire),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+type AgentChannel<M> = mpsc::UnboundedReceiver<Command<M>>;
+
+struct SenderAgent<M: Message> {
+    connection: ConnectionWrite,
+    commands: AgentChannel<M>,
+    pool: Option<CryptoPool>,
+    stats: Arc<AgentStats>,
+    redial: Arc<StdMutex<Redial>>,
+    /// Incremented every time the connection is re-established
+    generation: u64,
+    /// Attempts made and reason given when reconnecting failed for good
+    gave_up: Option<(usize, String)>,
+}
+
+impl<M> SenderAgent<M>
+where
+    M: Message + 'static,
+{
+    fn new(
+        connection: ConnectionWrite,
+        commands: AgentChannel<M>,
+        pool: Option<CryptoPool>,
+        stats: Arc<AgentStats>,
+        redial: Arc<StdMutex<Redial>>,
+    ) -> Self {
+        Self {
+            connection,
+            commands,
+            pool,
+            stats,
+            redial,
+            generation: 0,
+            gave_up: None,
+        }
+    }
+
+    fn spawn(self) -> task::JoinHandle<Option<ConnectionWrite>> {
+        let peer = self.connection.remote_pkey().fingerprint();
+
+        task::spawn(self.process_loop().instrument(
+            debug_span!(target: targets::SENDER, "sender_agent", remote=%peer),
+        ))
+    }
+
+    async fn process_loop(mut self) -> Option<ConnectionWrite> {
+        let mut fragmented = VecDeque::new();
+
+        loop {
+            // wait for commands only when there are no fragments to send,
+            // fragments are otherwise sent in between queued messages
+            let command = if fragmented.is_empty() {
+                self.commands.recv().await
+            } else {
+                self.commands.try_recv().ok()
+            };
+
+            match command {
+                Some(Command::Send(message, resp)) => {
+                    if let Some(pending) = self.send(message, resp).await {
+                        fragmented.push_back(pending);
+                    }
+                }
+                Some(Command::Reclaim) => {
+                    while !fragmented.is_empty() {
+                        self.send_fragment(&mut fragmented).await;
+                    }
+
+                    debug!(
+                        target: targets::SENDER,
+                        "sender agent giving back its connection"
+                    );
+                    return Some(self.connection);
+                }
+                None if fragmented.is_empty() => break,
+                None => {}
+            }
+
+            self.send_fragment(&mut fragmented).await;
+        }
+
+        warn!(target: targets::SENDER, "sender agent exiting");
+
+        if let Err(e) = self.connection.close().await {
+            debug!(
+                target: targets::SENDER,
+                "failed to close connection: {}", e
+            );
+        }
+
+        None
+    }
+
+    /// Send `message`, returning it instead if it needs to be sent in
+    /// fragments
+    async fn send(&mut self, message: M, resp: Reply) -> Option<Fragmented> {
+        let bytes = resp.bytes();
+
+        if let Some((attempts, reason)) = &self.gave_up {
+            self.stats.record(bytes, false);
+
+            let error = Reconnect {
+                remote: resp.remote,
+                attempts: *attempts,
+                reason: reason.clone(),
+            };
+
+            resp.fail(error.build());
+
+            return None;
+        }
+
+        // keep a copy of the message to send it again once reconnected
+        let copy = self.reconnects().then(|| message.clone());
+        let mut result = self.transmit(message).await;
+
+        if let (Err(e), Some(message)) = (&result, copy) {
+            if is_broken(e) {
+                if let Err(error) = self.reconnect(e).await {
+                    self.stats.record(bytes, false);
+
+                    resp.fail(error);
+
+                    return None;
+                }
+
+                result = self.transmit(message).await;
+            }
+        }
+
+        match result {
+            Ok(Some(outgoing)) => Some(Fragmented {
+                outgoing,
+                resp,
+                generation: self.generation,
+            }),
+            Ok(None) => {
+                self.stats.record(bytes, true);
+
+                resp.send(Ok(()));
+
+                None
+            }
+            Err(e) => {
+                self.stats.record(bytes, false);
+
+                resp.send(Err(e));
+
+                None
+            }
+        }
+    }
+
+    /// Send `message` whole, or only prepare it if it needs to be sent in
+    /// fragments
+    async fn transmit(
+        &mut self,
+        message: M,
+    ) -> Result<Option<Outgoing<'static>>, SendError> {
+        if self.connection.limits().fragmentation().is_none() {
+            match &self.pool {
+                Some(pool) => self.connection.send_on(message, pool).await?,
+                None => self.connection.send(&message).await?,
+            }
+
+            return Ok(None);
+        }
+
+        let mut outgoing = self.connection.outgoing(&message)?;
+
+        if outgoing.is_fragmented() {
+            return Ok(Some(outgoing));
+        }
+
+        self.connection
+            .send_next(&mut outgoing, self.pool.as_ref())
+            .await
+            .map(|_| None)
+    }
+
+    /// Send the next fragment of the first message of `fragmented` and move
+    /// it to the back of the queue, unless it was sent entirely
+    async fn send_fragment(&mut self, fragmented: &mut VecDeque<Fragmented>) {
+        let Some(mut pending) = fragmented.pop_front() else {
+            return;
+        };
+
+        // earlier fragments went through a connection that was replaced,
+        // which does not mean that the current one is broken
+        if pending.generation != self.generation {
+            let remote = pending.resp.remote;
+
+            self.stats.record_interrupted();
+            pending.resp.fail(Interrupted { remote }.build());
+
+            return;
+        }
+
+        let result = self
+            .connection
+            .send_next(&mut pending.outgoing, self.pool.as_ref())
+            .await;
+
+        if result.is_ok() && !pending.outgoing.is_done() {
+            fragmented.push_back(pending);
+            return;
+        }
+
+        let bytes = pending.resp.bytes();
+
+        // the message is lost but the messages after it may still be sent
+        if let Err(e) = &result {
+            if is_broken(e) && self.reconnects() {
+                if let Err(error) = self.reconnect(e).await {
+                    self.stats.record(bytes, false);
+
+                    pending.resp.fail(error);
+
+                    return;
+                }
+            }
+        }
+
+        self.stats.record(bytes, result.is_ok());
+
+        pending.resp.send(result);
+    }
+
+    /// Whether the connection is re-established when it breaks, see
+    /// `NetworkSender::with_reconnect`
+    fn reconnects(&self) -> bool {
+        self.redial.lock().expect("redial poisoned").start.is_some()
+    }
+
+    /// Re-establish the connection after `cause` broke it using the
+    /// `ReconnectScheduler` shared with the other agents, which waits
+    /// between attempts according to the `ReconnectPolicy`. Queued messages
+    /// wait for the outcome, and later messages fail right away if it is a
+    /// failure.
+    async fn reconnect(
+        &mut self,
+        cause: &SendError,
+    ) -> Result<(), SenderError> {
+        let remote = *self.connection.remote_pkey();
+        let stats = self.stats.clone();
+        let removal = stats.removal.notified();
+
+        pin_mut!(removal);
+
+        warn!(
+            target: targets::SENDER,
+            "connection to {} broke, reconnecting: {}", remote, cause
+        );
+
+        let (attempts, reason) = if stats.removed.load(Ordering::Acquire) {
+            (0, String::from("peer was removed"))
+        } else {
+            let outcome = self
+                .redial
+                .lock()
+                .expect("redial poisoned")
+                .reconnect(remote);
+
+            match outcome {
+                None => (0, String::from("no candidate for peer")),
+                Some(outcome) => match future::select(removal, outcome).await {
+                    Either::Left((_, outcome)) => {
+                        drop(outcome);
+                        self.cancel_reconnect(remote);
+
+                        (0, String::from("peer was removed"))
+                    }
+                    Either::Right((Ok(event), _)) => match event {
+                        ReconnectEvent::Restored {
+                            connection,
+                            attempts,
+                            ..
+                        } => match connection.split() {
+                            Some((read, write)) => {
+                                self.restore(read, write, attempts);
+
+                                return Ok(());
+                            }
+                            None => (
+                                attempts,
+                                String::from("connection is not secured"),
+                            ),
+                        },
+                        ReconnectEvent::GaveUp {
+                            attempts, error, ..
+                        } => (attempts, error.to_string()),
+                    },
+                    Either::Right((Err(_), _)) => {
+                        (0, String::from("reconnection was cancelled"))
+                    }
+                },
+            }
+        };
+
+        warn!(
+            target: targets::SENDER,
+            "giving up on {} after {} attempts: {}", remote, attempts, reason
+        );
+
+        self.gave_up = Some((attempts, reason.clone()));
+
+        Reconnect {
+            remote,
+            attempts,
+            reason,
+        }
+        .fail()
+    }
+
+    /// Stop reconnecting to `remote` once it was removed
+    fn cancel_reconnect(&self, remote: PublicKey) {
+        let redial = self.redial.lock().expect("redial poisoned");
+
+        if let Some(scheduler) = &redial.scheduler {
+            scheduler.cancel(remote);
+        }
+    }
+
+    /// Replace the broken connection with a re-established one
+    fn restore(
+        &mut self,
+        read: ConnectionRead,
+        mut write: ConnectionWrite,
+        attempts: usize,
+    ) {
+        write.set_limits(self.connection.limits().clone());
+
+        self.connection = write;
+        self.generation += 1;
+        self.stats.broken.store(false, Ordering::Relaxed);
+        self.stats.reconnects.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(reads) = &self.redial.lock().expect("redial poisoned").reads
+        {
+            let _ = reads.send(read);
+        }
+
+        info!(
+            target: targets::SENDER,
+            "reconnected to {} after {} attempts",
+            self.connection.remote_pkey(),
+            attempts
+        );
+    }
+}
+
+/// Whether `error` means that the `ConnectionWrite` is unusable
+fn is_broken(error: &SendError) -> bool {
+    matches!(
+        error,
+        SendError::SendIo { .. } | SendError::CorruptedSend { .. }
+    )
+}
+
+/// A message being sent one fragment at a time by a `SenderAgent`
+struct Fragmented {
+    outgoing: Outgoing<'static>,
+    resp: Reply,
+    /// Generation of the connection the first fragments were sent through
+    generation: u64,
+}
+
+/// A `Sender` that uses an input messages type I and implements an output `Sender`
+/// using the `Into` trait
+pub struct ConvertSender<I, O, S>
+where
+    I: Message + 'static + Into<O>,
+    O: Message + 'static,
+    S: Sender<O>,
+{
+    sender: Arc<S>,
+    _i: PhantomData<I>,
+    _o: PhantomData<O>,
+}
+
+impl<I, O, S> ConvertSender<I, O, S>
+where
+    I: Message + 'static + Into<O>,
+    O: Message + 'static,
+    S: Sender<O>,
+{
+    /// Create a new `ConvertSender` using the provided underlying `Sender`
+    /// to actually send messages
+    pub fn new(sender: Arc<S>) -> Self {
+        Self {
+            sender,
+            _i: PhantomData,
+            _o: PhantomData,
+        }
+    }
+
+    /// Return the inner sender wrapped by this `ConvertSender`
+    pub fn into_inner(self) -> Arc<S> {
+        self.sender
+    }
+}
+
+#[async_trait]
+impl<I, O, S> Sender<I> for ConvertSender<I, O, S>
+where
+    I: Message + 'static + Into<O>,
+    O: Message + 'static,
+    S: Sender<O>,
+{
+    async fn send(
+        &self,
+        message: I,
+        to: &PublicKey,
+    ) -> Result<(), SenderError> {
+        self.sender.send(message.into(), to).await
+    }
+
+    async fn keys(&self) -> Vec<PublicKey> {
+        self.sender.keys().await
+    }
+
+    fn watch_keys(&self) -> KeysReceiver {
+        self.sender.watch_keys()
+    }
+
+    async fn add_connection(&self, write: ConnectionWrite) {
+        self.sender.add_connection(write).await
+    }
+
+    async fn remove_connection(&self, key: &PublicKey) {
+        self.sender.remove_connection(key).await;
+    }
+}
+
+/// A `Sender` that only collects messages instead of sending them
+pub struct CollectingSender<M: Message> {
+    messages: Mutex<Vec<(PublicKey, M)>>,
+    keys: Mutex<HashSet<PublicKey>>,
+    watched: watch::Sender<Arc<HashSet<PublicKey>>>,
+}
+
+impl<M: Message> CollectingSender<M> {
+    /// Create a new `CollectingSender` using a specified set of `PublicKey`
+    /// destinations
+    pub fn new<I: IntoIterator<Item = PublicKey>>(keys: I) -> Self {
+        let keys: HashSet<_> = keys.into_iter().collect();
+
+        Self {
+            messages: Mutex::new(Vec::new()),
+            watched: watch::Sender::new(Arc::new(keys.clone())),
+            keys: Mutex::new(keys),
+        }
+    }
+
+    /// Retrieve the set of messages that was sent using this `CollectingSender`
+    pub async fn messages(&self) -> Vec<(PublicKey, M)> {
+        self.messages.lock().await.iter().cloned().collect()
+    }
+
+    /// Take the messages sent since the last call to `drain`, in the order
+    /// they were sent
+    pub async fn drain(&self) -> Vec<(PublicKey, M)> {
+        std::mem::take(&mut *self.messages.lock().await)
+    }
+}
+
+#[async_trait]
+impl<M: Message + 'static> Sender<M> for CollectingSender<M> {
+    async fn send(
+        &self,
+        message: M,
+        key: &PublicKey,
+    ) -> Result<(), SenderError> {
+        ensure!(
+            self.keys.lock().await.contains(key),
+            NoSuchPeer { remote: *key }
+        );
+
+        self.messages.lock().await.push((*key, message));
+
+        Ok(())
+    }
+
+    async fn remove_connection(&self, key: &PublicKey) {
+        let mut keys = self.keys.lock().await;
+
+        if keys.remove(key) {
+            self.watched.send_replace(Arc::new(keys.clone()));
+        }
+    }
+
+    async fn add_connection(&self, write: ConnectionWrite) {
+        let mut keys = self.keys.lock().await;
+
+        if keys.insert(*write.remote_pkey()) {
+            self.watched.send_replace(Arc::new(keys.clone()));
+        }
+    }
+
+    async fn keys(&self) -> Vec<PublicKey> {
+        self.keys.lock().await.clone().iter().copied().collect()
+    }
+
+    fn watch_keys(&self) -> KeysReceiver {
+        self.watched.subscribe()
+    }
+}
+
+/// Envelope used to carry messages between an `AckSender` and an
+/// `AckProcessor`
+#[message]
+#[derive(PartialEq, Eq)]
+pub enum Acked<M> {
+    /// A message that does not require acknowledgement
+    Plain(M),
+    /// A message that must be acknowledged by the receiver
+    Data {
+        /// Identifier used to acknowledge and deduplicate this message
+        id: u64,
+        /// The actual message
+        message: M,
+    },
+    /// Acknowledgement of a previously received `Data` message
+    Ack(u64),
+}
+
+impl<M> From<M> for Acked<M> {
+    fn from(message: M) -> Self {
+        Self::Plain(message)
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Error returned by `AckSender` when a message could not be delivered
+pub enum AckError {
+    #[snafu(display(
+        "message to {} not acknowledged after {} attempts",
+        remote,
+        attempts
+    ))]
+    /// The remote peer did not acknowledge the message in time
+    Unacknowledged {
+        /// The peer we were sending to
+        remote: PublicKey,
+        /// Number of times the message was sent
+        attempts: usize,
+    },
+}
+
+/// A `Sender` that provides at-least-once delivery for messages sent using
+/// `AckSender::send_acked`. Messages are retransmitted with exponential backoff
+/// until the remote `AckProcessor` acknowledges them or the retry budget is
+/// exhausted. Messages sent through the `Sender` trait are not acknowledged.
+pub struct AckSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<Acked<M>>,
+{
+    sender: Arc<S>,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<()>>>,
+    delay: Duration,
+    retries: usize,
+    _m: PhantomData<M>,
+}
+
+impl<M, S> AckSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<Acked<M>>,
+{
+    /// Default delay before the first retransmission
+    pub const DEFAULT_DELAY: Duration = Duration::from_millis(200);
+
+    /// Default number of retransmissions
+    pub const DEFAULT_RETRIES: usize = 8;
+
+    /// Create a new `AckSender` using the default retry policy
+    pub fn new(sender: Arc<S>) -> Self {
+        Self::with_retries(sender, Self::DEFAULT_DELAY, Self::DEFAULT_RETRIES)
+    }
+
+    /// Create a new `AckSender` with a custom retry policy
+    ///
+    /// # Arguments
+    /// * `sender` - The underlying `Sender` used to send messages
+    /// * `delay` - Delay before the first retransmission, doubled every time
+    /// * `retries` - Number of retransmissions before giving up
+    pub fn with_retries(
+        sender: Arc<S>,
+        delay: Duration,
+        retries: usize,
+    ) -> Self {
+        Self {
+            sender,
+            // start at a random id so that restarting nodes are not mistaken
+            // for duplicates by their peers
+            next_id: AtomicU64::new(rand::random()),
+            pending: Mutex::new(HashMap::new()),
+            delay,
+            retries,
+            _m: PhantomData,
+        }
+    }
+
+    /// Send a message to the given peer, resolving only once it has been
+    /// acknowledged by the remote `AckProcessor`
+    pub async fn send_acked(
+        &self,
+        message: M,
+        to: &PublicKey,
+    ) -> Result<(), AckError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, mut rx) = oneshot::channel();
+
+        self.pending.lock().await.insert(id, tx);
+
+        let mut delay = self.delay;
+
+        for attempt in 0..=self.retries {
+            let data = Acked::Data {
+                id,
+                message: message.clone(),
+            };
+
+            if let Err(e) = self.sender.send(data, to).await {
+                debug!(
+                    target: targets::SENDER,
+                    "attempt {} to send {} failed: {}", attempt, id, e
+                );
+            }
+
+            if let Ok(res) = time::timeout(delay, &mut rx).await {
+                if res.is_ok() {
+                    return Ok(());
+                }
+
+                break;
+            }
+
+            delay *= 2;
+        }
+
+        self.pending.lock().await.remove(&id);
+
+        Unacknowledged {
+            remote: *to,
+            attempts: self.retries + 1,
+        }
+        .fail()
+    }
+
+    /// Mark the message with the given id as acknowledged
+    pub async fn acknowledge(&self, id: u64) {
+        if let Some(tx) = self.pending.lock().await.remove(&id) {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Number of messages that are waiting for an acknowledgement
+    pub async fn pending(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// Get the underlying `Sender` used by this `AckSender`
+    pub fn inner(&self) -> &Arc<S> {
+        &self.sender
+    }
+}
+
+#[async_trait]
+impl<M, S> Sender<M> for AckSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<Acked<M>>,
+{
+    async fn send(
+        &self,
+        message: M,
+        to: &PublicKey,
+    ) -> Result<(), SenderError> {
+        self.sender.send(Acked::Plain(message), to).await
+    }
+
+    async fn keys(&self) -> Vec<PublicKey> {
+        self.sender.keys().await
+    }
+
+    fn watch_keys(&self) -> KeysReceiver {
+        self.sender.watch_keys()
+    }
+
+    async fn add_connection(&self, write: ConnectionWrite) {
+        self.sender.add_connection(write).await
+    }
+
+    async fn remove_connection(&self, key: &PublicKey) {
+        self.sender.remove_connection(key).await;
+    }
+}
+
+/// Behaviour of a `QuotaSender` when a message exceeds the available budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum QuotaMode {
+    /// Wait until enough budget is available to send the message
+    #[default]
+    Wait,
+    /// Fail immediately with `SenderError::QuotaExceeded`
+    FailFast,
+}
+
+/// Current state of the quota for one peer of a `QuotaSender`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuotaState {
+    /// Number of bytes that can be sent right now without waiting
+    pub available: u64,
+    /// Total number of bytes sent to this peer
+    pub sent: u64,
+    /// Number of sends that were delayed or rejected because of the quota
+    pub throttled: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    sent: u64,
+    throttled: u64,
+}
+
+impl Bucket {
+    fn new(burst: u64) -> Self {
+        Self {
+            tokens: burst as f64,
+            last: Instant::now(),
+            sent: 0,
+            throttled: 0,
+        }
+    }
+
+    fn refill(&mut self, rate: u64, burst: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.last = now;
+    }
+}
+
+/// A `Sender` that enforces a per-peer bandwidth quota using token buckets.
+/// The size of each message is computed using `wire_size` so that the quota
+/// matches the number of bytes actually written to the network.
+pub struct QuotaSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<M>,
+{
+    sender: Arc<S>,
+    rate: u64,
+    burst: u64,
+    mode: QuotaMode,
+    buckets: Mutex<HashMap<PublicKey, Bucket>>,
+    _m: PhantomData<M>,
+}
+
+impl<M, S> QuotaSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<M>,
+{
+    /// Create a new `QuotaSender` that waits for budget to be available
+    ///
+    /// # Arguments
+    /// * `sender` - The underlying `Sender` used to send messages
+    /// * `rate` - Number of bytes per second allowed for each peer
+    /// * `burst` - Maximum number of bytes that can be sent at once
+    pub fn new(sender: Arc<S>, rate: u64, burst: u64) -> Self {
+        Self::with_mode(sender, rate, burst, QuotaMode::default())
+    }
+
+    /// Create a new `QuotaSender` using the specified `QuotaMode`
+    pub fn with_mode(
+        sender: Arc<S>,
+        rate: u64,
+        burst: u64,
+        mode: QuotaMode,
+    ) -> Self {
+        Self {
+            sender,
+            rate,
+            burst,
+            mode,
+            buckets: Mutex::new(HashMap::new()),
+            _m: PhantomData,
+        }
+    }
+
+    /// Get the current quota state for the given peer, if anything was ever
+    /// sent to it
+    pub async fn state(&self, peer: &PublicKey) -> Option<QuotaState> {
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets.get_mut(peer)?;
+
+        bucket.refill(self.rate, self.burst);
+
+        Some(QuotaState {
+            available: bucket.tokens.max(0.0) as u64,
+            sent: bucket.sent,
+            throttled: bucket.throttled,
+        })
+    }
+
+    /// Get the current quota state for all known peers
+    pub async fn states(&self) -> HashMap<PublicKey, QuotaState> {
+        let mut buckets = self.buckets.lock().await;
+
+        buckets
+            .iter_mut()
+            .map(|(key, bucket)| {
+                bucket.refill(self.rate, self.burst);
+
+                let state = QuotaState {
+                    available: bucket.tokens.max(0.0) as u64,
+                    sent: bucket.sent,
+                    throttled: bucket.throttled,
+                };
+
+                (*key, state)
+            })
+            .collect()
+    }
+
+    /// Take `size` bytes from the bucket of `to`, returning how long to wait
+    /// if there is not enough budget. Messages larger than the burst size
+    /// only require a full bucket to be sent.
+    async fn acquire(&self, to: &PublicKey, size: u64) -> Option<Duration> {
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets
+            .entry(*to)
+            .or_insert_with(|| Bucket::new(self.burst));
+
+        bucket.refill(self.rate, self.burst);
+
+        let required = size.min(self.burst) as f64;
+
+        if bucket.tokens >= required {
+            bucket.tokens -= size as f64;
+            bucket.sent += size;
+
+            None
+        } else {
+            bucket.throttled += 1;
+
+            let missing = required - bucket.tokens;
+
+            Some(Duration::from_secs_f64(missing / self.rate as f64))
+        }
+    }
+}
+
+#[async_trait]
+impl<M, S> Sender<M> for QuotaSender<M, S>
+where
+    M: Message + 'static,
+    S: Sender<M>,
+{
+    async fn send(
+        &self,
+        message: M,
+        to: &PublicKey,
+    ) -> Result<(), SenderError> {
+        let size =
+            wire_size(&message).context(ConnectionError { remote: *to })?;
+
+        while let Some(retry_after) = self.acquire(to, size).await {
+            ensure!(
+                self.mode == QuotaMode::Wait,
+                QuotaExceeded {
+                    remote: *to,
+                    retry_after
+                }
+            );
+
+            time::sleep(retry_after).await;
+        }
+
+        self.sender.send(message, to).await
+    }
+
+    async fn keys(&self) -> Vec<PublicKey> {
+        self.sender.keys().await
+    }
+
+    fn watch_keys(&self) -> KeysReceiver {
+        self.sender.watch_keys()
+    }
+
+    async fn add_connection(&self, write: ConnectionWrite) {
+        self.sender.add_connection(write).await
+    }
+
+    async fn remove_connection(&self, key: &PublicKey) {
+        self.buckets.lock().await.remove(key);
+        self.sender.remove_connection(key).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        message,
+        net::{
+            ConnectError, ConnectionLimits, Connector, Fragmentation, Listener,
+            MemoryConnector, MemoryListener, MemoryRegistry, Socket,
+            TcpConnector, TcpListener,
+        },
+        test::keyset,
+    };
+
+    #[tokio::test]
+    async fn convert_sender() {
+        #[message]
+        #[derive(Copy)]
+        struct M1(u8);
+
+        #[message]
+        #[derive(Copy, Eq, PartialEq)]
+        struct M2(u16);
+
+        impl From<M1> for M2 {
+            fn from(v: M1) -> Self {
+                Self(v.0.into())
+            }
+        }
+
+        const COUNT: usize = 10;
+
+        let expected = (0..COUNT as u16).map(M2).collect::<Vec<_>>();
+
+        let peer = keyset(1).next().unwrap();
+        let sender = CollectingSender::<M2>::new(vec![peer]);
+
+        let sender = ConvertSender::new(Arc::new(sender));
+
+        sender
             .send_many_to_one(expected.iter().cloned(), &peer)
             .await
             .expect("send failed");
 
         let sender = sender.into_inner();
 
-        let messages = sender.messages().await.into_iter().map(Into::into);
+        let messages = sender.messages().await.into_iter();
 
         assert_eq!(messages.len(), COUNT, "wrong message count");
 
         messages
             .map(|x: (PublicKey, M2)| x.1)
-            .zip(expected.into_iter().map(Into::into))
+            .zip(expected.into_iter())
             .for_each(|(a, b)| assert_eq!(a, b, "bad message"));
     }
 
@@ -469,4 +2423,846 @@ mod test {
 
         handle.await.expect("listener failed");
     }
+
+    #[tokio::test]
+    async fn send_many_results_in_order() {
+        let addr = crate::test::next_test_ip4();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+
+        let handle = task::spawn(async move {
+            listener
+                .accept()
+                .await
+                .expect("accept failed")
+                .receive::<usize>()
+                .await
+                .expect("recv failed")
+        });
+
+        let connection = TcpConnector::new(Exchanger::random())
+            .connect(&public, &addr)
+            .await
+            .expect("connect failed");
+        let write = connection.split().unwrap().1;
+        let sender = NetworkSender::new(std::iter::once(write));
+        let unknown = keyset(2).collect::<Vec<_>>();
+        let keys = [unknown[0], public, unknown[1]];
+
+        let results = sender.send_many(3usize, keys.iter()).await;
+
+        assert_eq!(results.len(), keys.len(), "wrong number of results");
+        assert!(results[1].is_ok(), "send to known peer failed");
+
+        for (result, key) in
+            [(&results[0], unknown[0]), (&results[2], unknown[1])]
+        {
+            match result {
+                Err(SenderError::NoSuchPeer { remote }) => {
+                    assert_eq!(*remote, key, "results out of order")
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        assert_eq!(handle.await.expect("listener failed"), 3);
+
+        match sender.send_many_collecting(4usize, unknown.iter()).await {
+            Err(SenderError::ManyErrors { errors }) => {
+                assert_eq!(errors.len(), unknown.len(), "wrong error count")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn fragments_interleave() {
+        // encryption is too slow in unoptimized builds to send 100MiB
+        const LARGE: usize = if cfg!(debug_assertions) {
+            8 * 1024 * 1024
+        } else {
+            100 * 1024 * 1024
+        };
+        const FRAME: usize = 256 * 1024;
+        const LATENCY: Duration = Duration::from_secs(2);
+
+        let large = (0..LARGE).map(|x| x as u8).collect::<Vec<_>>();
+        let limits = ConnectionLimits::default()
+            .max_message_size(FRAME)
+            .fragment_messages(Fragmentation::default());
+        let addr = crate::test::next_test_ip4();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+        let receiver_limits = Arc::new(limits.clone());
+
+        let receiver = task::spawn(async move {
+            let mut connection =
+                listener.accept().await.expect("accept failed");
+            let mut small = Vec::new();
+
+            connection.set_limits(receiver_limits);
+
+            loop {
+                match connection
+                    .receive::<(u32, Vec<u8>)>()
+                    .await
+                    .expect("recv failed")
+                {
+                    (u32::MAX, large) => return (small, large),
+                    (seq, _) => small.push(seq),
+                }
+            }
+        });
+
+        let connection = TcpConnector::new(Exchanger::random())
+            .with_limits(limits)
+            .connect(&public, &addr)
+            .await
+            .expect("connect failed");
+        let sender = Arc::new(NetworkSender::new(std::iter::once(
+            connection.split().unwrap().1,
+        )));
+
+        let mut large_send = task::spawn({
+            let sender = sender.clone();
+            let large = large.clone();
+
+            async move { sender.send((u32::MAX, large), &public).await }
+        });
+
+        let mut seq = 0;
+
+        let result = loop {
+            let start = Instant::now();
+
+            sender
+                .send((seq, Vec::new()), &public)
+                .await
+                .expect("send failed");
+
+            // the first small message waits for the large one to be
+            // serialized, later ones only for the fragment being sent
+            assert!(
+                seq == 0 || start.elapsed() < LATENCY,
+                "small message blocked behind the large one"
+            );
+
+            seq += 1;
+
+            tokio::select! {
+                result = &mut large_send => break result,
+                _ = time::sleep(Duration::from_millis(5)) => {}
+            }
+        };
+
+        result.expect("sending task panicked").expect("send failed");
+
+        let (small, received) = receiver.await.expect("receiver panicked");
+
+        assert!(received == large, "large message corrupted");
+        assert!(!small.is_empty(), "no small message overtook the large one");
+        assert_eq!(small, (0..small.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn buffer_cap_engages() {
+        const MESSAGE: usize = 16 * 1024;
+        const CAP: u64 = 1024 * 1024;
+        const HEALTHY: usize = 16;
+
+        let connector = TcpConnector::new(Exchanger::random());
+        let (stall_tx, stall_rx) = oneshot::channel::<()>();
+        let mut stall_rx = Some(stall_rx);
+        let mut writes = Vec::new();
+        let mut keys = Vec::new();
+        let mut receivers = Vec::new();
+
+        for stalled in [true, false] {
+            let stall = stall_rx.take().filter(|_| stalled);
+            let addr = crate::test::next_test_ip4();
+            let exchanger = Exchanger::random();
+            let mut listener = TcpListener::new(addr, exchanger.clone())
+                .await
+                .expect("listen failed");
+
+            receivers.push(task::spawn(async move {
+                let mut connection =
+                    listener.accept().await.expect("accept failed");
+
+                if let Some(stall) = stall {
+                    // never read until told to drop the connection
+                    let _ = stall.await;
+                    return;
+                }
+
+                for _ in 0..HEALTHY {
+                    connection.receive::<Vec<u8>>().await.expect("recv failed");
+                }
+            }));
+
+            let public = *exchanger.keypair().public();
+            let connection = connector
+                .connect(&public, &addr)
+                .await
+                .expect("connect failed");
+
+            writes.push(connection.split().unwrap().1);
+            keys.push(public);
+        }
+
+        let (stalled, healthy) = (keys[0], keys[1]);
+        let sender = Arc::new(
+            NetworkSender::new(writes).with_buffer_limit(BufferLimit::new(CAP)),
+        );
+        let message = vec![0u8; MESSAGE];
+
+        let filled = {
+            let sender = sender.clone();
+            let messages = vec![message.clone(); 4 * CAP as usize / MESSAGE];
+
+            task::spawn(async move {
+                sender.send_many_to_one(messages, &stalled).await
+            })
+        };
+
+        let error = filled.await.expect("sending task panicked");
+
+        assert!(
+            matches!(error, Err(SenderError::BufferFull { cap, .. }) if cap == CAP / 2),
+            "stalled peer exceeded its share: {:?}",
+            error
+        );
+        assert!(sender.queued_bytes() <= CAP);
+        assert_eq!(
+            sender.peer_queued_bytes(&stalled).await,
+            Some(sender.queued_bytes())
+        );
+
+        for _ in 0..HEALTHY {
+            sender
+                .send(message.clone(), &healthy)
+                .await
+                .expect("send to healthy peer failed");
+        }
+
+        // messages to the stalled peer are released once its connection fails
+        stall_tx.send(()).expect("stalled peer exited");
+
+        for receiver in receivers {
+            receiver.await.expect("receiver panicked");
+        }
+
+        time::timeout(Duration::from_secs(5), async {
+            while sender.queued_bytes() > 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("queued bytes were not released");
+
+        assert_eq!(sender.peer_queued_bytes(&stalled).await, Some(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quota_paces_burst() {
+        const COUNT: u64 = 6;
+
+        let peer = keyset(1).next().unwrap();
+        let message = vec![0u8; 100];
+        let size = wire_size(&message).unwrap();
+        let collecting = Arc::new(CollectingSender::new(vec![peer]));
+        let sender = QuotaSender::new(collecting.clone(), size, 2 * size);
+
+        let start = Instant::now();
+
+        for _ in 0..COUNT {
+            sender
+                .send(message.clone(), &peer)
+                .await
+                .expect("send failed");
+        }
+
+        let elapsed = start.elapsed();
+
+        // the first two messages fit in the burst, others are paced
+        assert!(elapsed >= Duration::from_secs(COUNT - 2), "too fast");
+        assert!(elapsed < Duration::from_secs(COUNT - 1), "too slow");
+
+        assert_eq!(collecting.messages().await.len(), COUNT as usize);
+
+        let state = sender.state(&peer).await.expect("no quota state");
+
+        assert_eq!(state.sent, COUNT * size, "wrong byte count");
+        assert!(state.throttled >= COUNT - 2, "sends were not throttled");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quota_fail_fast() {
+        let peer = keyset(1).next().unwrap();
+        let size = wire_size(&0usize).unwrap();
+        let collecting = Arc::new(CollectingSender::new(vec![peer]));
+        let sender = QuotaSender::with_mode(
+            collecting.clone(),
+            size,
+            size,
+            QuotaMode::FailFast,
+        );
+
+        sender.send(0usize, &peer).await.expect("send failed");
+
+        match sender.send(1usize, &peer).await {
+            Err(SenderError::QuotaExceeded {
+                remote,
+                retry_after,
+            }) => {
+                assert_eq!(remote, peer, "wrong peer");
+                assert!(retry_after <= Duration::from_secs(1));
+                assert!(retry_after > Duration::from_millis(900));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+
+        sender.send(2usize, &peer).await.expect("send failed");
+
+        let messages = collecting.messages().await;
+
+        assert_eq!(messages.len(), 2, "rejected message was sent");
+    }
+
+    #[test]
+    fn many_errors_display() {
+        let keys = keyset(5).collect::<Vec<_>>();
+        let errors = |count: usize| {
+            ManyErrors {
+                errors: keys[..count]
+                    .iter()
+                    .map(|remote| NoSuchPeer { remote: *remote }.build())
+                    .collect::<Vec<_>>(),
+            }
+            .build()
+        };
+
+        assert_eq!(
+            errors(2).to_string(),
+            format!(
+                "2 send errors: peer {} is unknown; peer {} is unknown",
+                keys[0], keys[1]
+            )
+        );
+
+        assert_eq!(
+            errors(5).to_string(),
+            format!(
+                "5 send errors: peer {} is unknown; peer {} is unknown; \
+                 peer {} is unknown and 2 more",
+                keys[0], keys[1], keys[2]
+            )
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fair_broadcast() {
+        const FAST: usize = 10;
+        const BROADCASTS: usize = 20;
+        const MESSAGE: usize = 16 * 1024;
+        const INTERVAL: Duration = Duration::from_millis(10);
+        const SLOW_READ: Duration = Duration::from_millis(50);
+
+        let registry = MemoryRegistry::new();
+        let connector =
+            MemoryConnector::new(registry.clone(), Exchanger::random());
+        let mut writes = Vec::new();
+        let mut keys = Vec::new();
+        let mut receivers = Vec::new();
+
+        for slow in (0..=FAST).map(|i| i == FAST) {
+            let exchanger = Exchanger::random();
+            let mut listener = MemoryListener::new(
+                &registry,
+                (Ipv4Addr::LOCALHOST, 0).into(),
+                exchanger.clone(),
+            )
+            .expect("bind failed");
+            let addr = listener.local_addr().expect("no address");
+
+            receivers.push(task::spawn(async move {
+                let mut connection =
+                    listener.accept().await.expect("accept failed");
+                let mut received = Vec::with_capacity(BROADCASTS);
+
+                for _ in 0..BROADCASTS {
+                    if slow {
+                        time::sleep(SLOW_READ).await;
+                    }
+
+                    let (seq, _) = connection
+                        .receive::<(usize, Vec<u8>)>()
+                        .await
+                        .expect("recv failed");
+
+                    received.push(seq);
+                }
+
+                (received, Instant::now())
+            }));
+
+            let public = *exchanger.keypair().public();
+            let connection = connector
+                .connect(&public, &addr)
+                .await
+                .expect("connect failed");
+
+            writes.push(connection.split().unwrap().1);
+            keys.push(public);
+        }
+
+        let slow = keys[FAST];
+        let size = wire_size(&(0usize, vec![0u8; MESSAGE])).unwrap() as u64;
+        let window = 2 * size;
+        let sender = Arc::new(NetworkSender::new(writes).with_fairness(
+            Fairness::new(4).min_window(window).lagging_after(3),
+        ));
+        let mut lagging = sender.watch_lagging();
+        let start = Instant::now();
+        let mut broadcasts = Vec::new();
+
+        for seq in 0..BROADCASTS {
+            let sender = sender.clone();
+            let keys = keys.clone();
+
+            broadcasts.push(task::spawn(async move {
+                sender
+                    .send_many_collecting(
+                        (seq, vec![0u8; MESSAGE]),
+                        keys.iter(),
+                    )
+                    .await
+            }));
+
+            time::sleep(INTERVAL).await;
+        }
+
+        let end = Instant::now();
+        let queue = sender.snapshot().await.remove(&slow.to_string()).unwrap();
+
+        assert!(queue.held > 0, "no message held back for slow peer");
+        assert!(queue.lagging, "slow peer not lagging");
+        assert!(
+            queue.queued_bytes - queue.held as u64 * size <= window,
+            "slow peer exceeded its window"
+        );
+        assert!(lagging.has_changed().unwrap(), "lagging not reported");
+        assert_eq!(
+            *lagging.borrow_and_update(),
+            Arc::new(HashSet::from([slow]))
+        );
+
+        for broadcast in broadcasts {
+            broadcast
+                .await
+                .expect("broadcasting task panicked")
+                .expect("broadcast failed");
+        }
+
+        for (i, receiver) in receivers.into_iter().enumerate() {
+            let (received, done) = receiver.await.expect("receiver panicked");
+
+            assert_eq!(received, (0..BROADCASTS).collect::<Vec<_>>());
+
+            if i < FAST {
+                assert!(done < end, "fast peer delayed by slow peer");
+            } else {
+                assert!(done - start >= SLOW_READ * BROADCASTS as u32);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn broadcast_ordering() {
+        const PEERS: usize = 4;
+        const TASKS: usize = 16;
+        const ROUNDS: usize = 20;
+        const TOTAL: usize = TASKS * ROUNDS * 2;
+
+        let mut receivers = Vec::with_capacity(PEERS);
+        let mut writes = Vec::with_capacity(PEERS);
+        let connector = TcpConnector::new(Exchanger::random());
+
+        for _ in 0..PEERS {
+            let addr = crate::test::next_test_ip4();
+            let exchanger = Exchanger::random();
+            let public = *exchanger.keypair().public();
+            let mut listener = TcpListener::new(addr, exchanger)
+                .await
+                .expect("listen failed");
+
+            receivers.push(task::spawn(async move {
+                let mut connection =
+                    listener.accept().await.expect("accept failed");
+                let mut received = Vec::with_capacity(TOTAL);
+
+                for _ in 0..TOTAL {
+                    received.push(
+                        connection
+                            .receive::<(bool, usize, usize)>()
+                            .await
+                            .expect("recv failed"),
+                    );
+                }
+
+                received
+            }));
+
+            let connection = connector
+                .connect(&public, &addr)
+                .await
+                .expect("connect failed");
+
+            writes.push(connection.split().unwrap().1);
+        }
+
+        let sender = Arc::new(NetworkSender::new(writes));
+        let keys = sender.keys().await;
+
+        let tasks = (0..TASKS)
+            .map(|task| {
+                let sender = sender.clone();
+                let keys = keys.clone();
+
+                task::spawn(async move {
+                    for round in 0..ROUNDS {
+                        // issue both broadcasts before awaiting any of them
+                        let first = sender.send_many_collecting(
+                            (false, task, round),
+                            keys.iter(),
+                        );
+                        let second = sender.send_many_collecting(
+                            (true, task, round),
+                            keys.iter(),
+                        );
+
+                        let (first, second) = futures::join!(first, second);
+
+                        first.expect("send failed");
+                        second.expect("send failed");
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            task.await.expect("sending task panicked");
+        }
+
+        for receiver in receivers {
+            let received = receiver.await.expect("receiver panicked");
+            let mut seen = HashSet::new();
+
+            for (second, task, round) in received {
+                if second {
+                    assert!(
+                        seen.contains(&(task, round)),
+                        "second broadcast of {} in round {} received first",
+                        task,
+                        round
+                    );
+                } else {
+                    seen.insert((task, round));
+                }
+            }
+
+            assert_eq!(seen.len(), TASKS * ROUNDS, "missing messages");
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_broken() {
+        let registry = MemoryRegistry::new();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = MemoryListener::new(
+            &registry,
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            exchanger,
+        )
+        .expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+        let connector =
+            MemoryConnector::new(registry.clone(), Exchanger::random());
+        let (accepted, connection) =
+            tokio::join!(listener.accept(), connector.connect(&public, &addr));
+
+        drop(accepted.expect("accept failed"));
+
+        let write = connection.expect("connect failed").split().unwrap().1;
+        let sender = NetworkSender::new(vec![write])
+            .with_reconnect(connector, HashMap::from([(public, addr)]))
+            .with_reconnect_policy(
+                ReconnectPolicy::default()
+                    .delays(Duration::from_millis(1), Duration::from_millis(1))
+                    .max_attempts(Some(2)),
+            );
+        let mut reads = sender.reconnected_reads();
+        let (accepted, sent) = tokio::join!(
+            listener.accept(),
+            sender.send_many_to_one(0..3usize, &public)
+        );
+        let mut accepted = accepted.expect("accept failed");
+
+        sent.expect("send failed");
+
+        for expected in 0..3usize {
+            let received = accepted.receive::<usize>().await.expect("recv");
+
+            assert_eq!(received, expected, "messages reordered");
+        }
+
+        reads.try_recv().expect("no reconnected read half");
+
+        let queue = sender.snapshot().await.remove(&public.to_string());
+
+        assert_eq!(queue.unwrap().reconnects, 1);
+
+        // the peer can't be reached anymore once the listener is gone
+        drop(accepted);
+        drop(listener);
+
+        for _ in 0..2 {
+            assert!(matches!(
+                sender.send(0usize, &public).await,
+                Err(SenderError::Reconnect { attempts: 2, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_removed() {
+        let registry = MemoryRegistry::new();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = MemoryListener::new(
+            &registry,
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            exchanger,
+        )
+        .expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+        let connector =
+            MemoryConnector::new(registry.clone(), Exchanger::random());
+        let (accepted, connection) =
+            tokio::join!(listener.accept(), connector.connect(&public, &addr));
+
+        drop(accepted.expect("accept failed"));
+        drop(listener);
+
+        let write = connection.expect("connect failed").split().unwrap().1;
+        let sender = Arc::new(
+            NetworkSender::new(vec![write])
+                .with_reconnect(connector, HashMap::from([(public, addr)]))
+                .with_reconnect_policy(
+                    ReconnectPolicy::default()
+                        .delays(
+                            Duration::from_millis(10),
+                            Duration::from_millis(10),
+                        )
+                        .max_attempts(None),
+                ),
+        );
+        let sending = task::spawn({
+            let sender = sender.clone();
+
+            async move { sender.send(0usize, &public).await }
+        });
+
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(sender.reconnecting().await, [public]);
+
+        sender.remove_connection(&public).await;
+
+        let result = time::timeout(Duration::from_secs(1), sending)
+            .await
+            .expect("still reconnecting after removal")
+            .expect("sending task panicked");
+
+        assert!(matches!(
+            result,
+            Err(SenderError::Reconnect { ref reason, .. }) if reason == "peer was removed"
+        ));
+        assert!(sender.reconnecting().await.is_empty(), "still scheduled");
+    }
+
+    /// A `MemoryConnector` counting the dials it makes
+    struct CountingConnector {
+        inner: MemoryConnector,
+        dials: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Connector for CountingConnector {
+        type Candidate = SocketAddr;
+
+        fn exchanger(&self) -> &Exchanger {
+            self.inner.exchanger()
+        }
+
+        async fn establish(
+            &self,
+            pkey: &PublicKey,
+            candidate: &SocketAddr,
+        ) -> Result<Box<dyn Socket>, ConnectError> {
+            self.dials.fetch_add(1, Ordering::SeqCst);
+            self.inner.establish(pkey, candidate).await
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_once_for_fragments() {
+        const FRAME: usize = 4 * 1024;
+        const LARGE: usize = 64 * FRAME;
+
+        let registry = MemoryRegistry::new();
+        let exchanger = Exchanger::random();
+        let public = *exchanger.keypair().public();
+        let mut listener = MemoryListener::new(
+            &registry,
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            exchanger,
+        )
+        .expect("bind failed");
+        let addr = listener.local_addr().expect("no address");
+        let limits = ConnectionLimits::default()
+            .max_message_size(FRAME)
+            .fragment_messages(Fragmentation::default());
+        let connector = MemoryConnector::new(registry, Exchanger::random())
+            .with_limits(limits);
+        let (accepted, connection) =
+            tokio::join!(listener.accept(), connector.connect(&public, &addr));
+        let dials = Arc::new(AtomicUsize::new(0));
+        let write = connection.expect("connect failed").split().unwrap().1;
+        let sender = Arc::new(NetworkSender::new(vec![write]).with_reconnect(
+            CountingConnector {
+                inner: connector,
+                dials: dials.clone(),
+            },
+            HashMap::from([(public, addr)]),
+        ));
+        let sends = (0..2u8)
+            .map(|i| {
+                let sender = sender.clone();
+
+                task::spawn(async move {
+                    sender.send(vec![i; LARGE], &public).await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // both messages are being sent when the connection breaks
+        time::sleep(Duration::from_millis(50)).await;
+        drop(accepted.expect("accept failed"));
+
+        let _reconnected = listener.accept().await.expect("accept failed");
+        let mut interrupted = 0;
+
+        for send in sends {
+            match send.await.expect("sending task panicked") {
+                Err(SenderError::Interrupted { remote }) => {
+                    assert_eq!(remote, public);
+                    interrupted += 1;
+                }
+                Err(SenderError::ConnectionError { .. }) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        assert_eq!(interrupted, 1, "no message interrupted");
+        assert_eq!(dials.load(Ordering::SeqCst), 1, "peer redialed");
+    }
+
+    #[tokio::test]
+    async fn blocked_send_leaves_agents_unlocked() {
+        const MESSAGE: usize = 16 * 1024;
+        const CAP: u64 = 256 * 1024;
+
+        let registry = MemoryRegistry::new();
+        let connector =
+            MemoryConnector::new(registry.clone(), Exchanger::random());
+        let mut writes = Vec::new();
+        let mut accepted = Vec::new();
+        let mut keys = Vec::new();
+
+        for _ in 0..2 {
+            let exchanger = Exchanger::random();
+            let public = *exchanger.keypair().public();
+            let mut listener = MemoryListener::new(
+                &registry,
+                (Ipv4Addr::LOCALHOST, 0).into(),
+                exchanger,
+            )
+            .expect("bind failed");
+            let addr = listener.local_addr().expect("no address");
+            let (connection, write) = tokio::join!(
+                listener.accept(),
+                connector.connect(&public, &addr)
+            );
+
+            // peers never read so that messages stay queued
+            accepted.push(connection.expect("accept failed"));
+            writes.push(write.expect("connect failed").split().unwrap().1);
+            keys.push(public);
+        }
+
+        let (stalled, late) = (keys[0], keys[1]);
+        let message = vec![0u8; MESSAGE];
+        let size = wire_size(&message).unwrap();
+        let sender = Arc::new(
+            NetworkSender::new(vec![writes.remove(0)])
+                .with_buffer_limit(BufferLimit::new(CAP).block(true)),
+        );
+
+        time::timeout(Duration::from_secs(5), async {
+            while sender.queued_bytes() + size <= CAP {
+                let sender = sender.clone();
+                let message = message.clone();
+
+                task::spawn(
+                    async move { sender.send(message, &stalled).await },
+                );
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("buffer did not fill up");
+
+        sender.add_connection(writes.remove(0)).await;
+
+        let blocked = {
+            let sender = sender.clone();
+
+            task::spawn(async move { sender.send(message, &late).await })
+        };
+
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert!(!blocked.is_finished(), "send did not wait for room");
+
+        time::timeout(Duration::from_secs(1), sender.remove_connection(&late))
+            .await
+            .expect("agents locked while waiting for room");
+
+        // failing the stalled peer releases its room in the buffer
+        drop(accepted.remove(0));
+
+        let _ = time::timeout(Duration::from_secs(5), blocked)
+            .await
+            .expect("send still blocked");
+    }
 }
@@ -0,0 +1,824 @@
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use futures::future::{self, Future};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::time::{self, Instant};
+use tracing::{info, warn};
+
+use super::{Outcome, System};
+use crate::{
+    crypto::key::exchange::{Exchanger, KeyPair, PublicKey},
+    net::{
+        common::directory::negotiate, Connection, Connector, DirectoryListener,
+        Listener, TcpConnector, TcpListener,
+    },
+    telemetry::targets,
+};
+
+/// Time after which `System::from_config` gives up by default
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where `System::from_config` gets the `KeyPair` of the `System` from
+#[derive(Clone)]
+pub enum KeySource {
+    /// Generate a new random `KeyPair`
+    Random,
+    /// Derive the `KeyPair` from the given seed
+    Seed([u8; 32]),
+    /// Read the 32 bytes of the secret key from a file
+    File(PathBuf),
+    /// Decrypt the secret key from a keyfile written by
+    /// [`encrypt_keypair_to_file`] using the passphrase found in an
+    /// environment variable
+    ///
+    /// [`encrypt_keypair_to_file`]: crate::crypto::keyfile::encrypt_keypair_to_file
+    #[cfg(feature = "keyfile")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "keyfile")))]
+    EncryptedFile {
+        /// Path of the keyfile
+        path: PathBuf,
+        /// Name of the environment variable holding the passphrase
+        passphrase_env: String,
+    },
+    /// Read the secret key as hexadecimal from the given environment variable
+    Env(String),
+}
+
+#[derive(Debug, Snafu)]
+enum KeySourceError {
+    #[snafu(display("could not read {}: {}", path.display(), source))]
+    ReadKey { path: PathBuf, source: io::Error },
+
+    #[snafu(display("{} is {} bytes long instead of 32", origin, len))]
+    KeyLength { origin: String, len: usize },
+
+    #[snafu(display("environment variable {} is not set", var))]
+    MissingVar { var: String },
+
+    #[snafu(display("{} is not hexadecimal: {}", var, source))]
+    KeyHex {
+        var: String,
+        source: hex::FromHexError,
+    },
+
+    #[cfg(feature = "keyfile")]
+    #[snafu(display("could not decrypt {}: {}", path.display(), source))]
+    Keyfile {
+        path: PathBuf,
+        source: crate::crypto::keyfile::KeyfileError,
+    },
+}
+
+impl KeySource {
+    fn load(&self) -> Result<KeyPair, KeySourceError> {
+        let seed = |origin: &dyn fmt::Display, bytes: Vec<u8>| {
+            <[u8; 32]>::try_from(bytes.as_slice())
+                .map(KeyPair::from_seed)
+                .map_err(|_| {
+                    KeyLength {
+                        origin: origin.to_string(),
+                        len: bytes.len(),
+                    }
+                    .build()
+                })
+        };
+        let var = |var: &String| {
+            std::env::var(var)
+                .ok()
+                .context(MissingVar { var: var.clone() })
+        };
+
+        match self {
+            Self::Random => Ok(KeyPair::random()),
+            Self::Seed(seed) => Ok(KeyPair::from_seed(*seed)),
+            Self::File(path) => {
+                let bytes = std::fs::read(path).context(ReadKey { path })?;
+
+                seed(&path.display(), bytes)
+            }
+            #[cfg(feature = "keyfile")]
+            Self::EncryptedFile {
+                path,
+                passphrase_env,
+            } => {
+                let passphrase = var(passphrase_env)?;
+
+                crate::crypto::keyfile::decrypt_keypair_from_file(
+                    path,
+                    passphrase.as_bytes(),
+                )
+                .context(Keyfile { path })
+            }
+            Self::Env(name) => {
+                let bytes = hex::decode(var(name)?.trim())
+                    .context(KeyHex { var: name })?;
+
+                seed(name, bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Random => write!(f, "Random"),
+            Self::Seed(_) => write!(f, "Seed(..)"),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            #[cfg(feature = "keyfile")]
+            Self::EncryptedFile {
+                path,
+                passphrase_env,
+            } => f
+                .debug_struct("EncryptedFile")
+                .field("path", path)
+                .field("passphrase_env", passphrase_env)
+                .finish(),
+            Self::Env(var) => f.debug_tuple("Env").field(var).finish(),
+        }
+    }
+}
+
+/// Everything `System::from_config` needs to start a `System`
+#[derive(Clone, Debug)]
+pub struct StartupConfig {
+    keys: KeySource,
+    listen: Vec<SocketAddr>,
+    directory: Option<SocketAddr>,
+    peers: Vec<(PublicKey, SocketAddr)>,
+    timeout: Duration,
+}
+
+impl StartupConfig {
+    /// Start a `System` with a random `KeyPair`, no `Listener` and no peer
+    pub fn new() -> Self {
+        Self {
+            keys: KeySource::Random,
+            listen: Vec::new(),
+            directory: None,
+            peers: Vec::new(),
+            timeout: DEFAULT_STARTUP_TIMEOUT,
+        }
+    }
+
+    /// Get the `KeyPair` of the `System` from `keys`
+    pub fn keys(mut self, keys: KeySource) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Accept `Connection`s using a `TcpListener` bound to `addr`
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.listen.push(addr);
+        self
+    }
+
+    /// Register every `Listener` with the directory server at `addr`
+    pub fn directory(mut self, addr: SocketAddr) -> Self {
+        self.directory = Some(addr);
+        self
+    }
+
+    /// Connect to the peer with the given `PublicKey` at `addr`
+    pub fn peer(mut self, pkey: PublicKey, addr: SocketAddr) -> Self {
+        self.peers.push((pkey, addr));
+        self
+    }
+
+    /// Give up after `timeout` if the `System` is still not started
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A step of `System::from_config`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Loading the `KeyPair` of the `System`
+    LoadKeys,
+    /// Binding a `Listener` to the given address
+    Listen(SocketAddr),
+    /// Registering a `Listener` with a directory server
+    Register {
+        /// Address the `Listener` is bound to
+        listener: SocketAddr,
+        /// Address of the directory server
+        directory: SocketAddr,
+    },
+    /// Connecting to a peer
+    Dial(PublicKey, SocketAddr),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LoadKeys => write!(f, "load keypair"),
+            Self::Listen(addr) => write!(f, "listen on {}", addr),
+            Self::Register {
+                listener,
+                directory,
+            } => write!(f, "register {} with {}", listener, directory),
+            Self::Dial(pkey, addr) => write!(f, "dial {} at {}", pkey, addr),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Errors encountered when starting a `System` using `System::from_config`,
+/// the accompanying [`StartupReport`] has the details of every step
+///
+/// [`StartupReport`]: self::StartupReport
+pub enum StartupError {
+    #[snafu(display("failed to {}: {}", step, reason))]
+    /// A step failed
+    Failed {
+        /// The step that failed
+        step: Step,
+        /// Why it failed
+        reason: String,
+    },
+
+    #[snafu(display("none of the {} peers could be connected", count))]
+    /// None of the peers could be connected
+    NoPeers {
+        /// Number of peers that were dialed
+        count: usize,
+    },
+
+    #[snafu(display(
+        "startup did not complete within {:?}, stalled at {}",
+        timeout,
+        step.as_ref().map_or("no step".to_string(), Step::to_string)
+    ))]
+    /// The startup did not complete in time
+    TimedOut {
+        /// Time given to the startup
+        timeout: Duration,
+        /// The step that was running when the time ran out
+        step: Option<Step>,
+    },
+}
+
+/// Outcome of a [`Step`] along with the time it took
+///
+/// [`Step`]: self::Step
+#[derive(Clone, Debug)]
+pub struct StepReport {
+    /// The step
+    pub step: Step,
+    /// How the step ended
+    pub outcome: Outcome,
+    /// Time spent on the step
+    pub elapsed: Duration,
+}
+
+/// Summary of the startup of a `System` by `System::from_config`
+#[derive(Clone, Debug, Default)]
+pub struct StartupReport {
+    steps: Vec<StepReport>,
+    elapsed: Duration,
+}
+
+impl StartupReport {
+    fn record(&mut self, step: Step, outcome: Outcome, elapsed: Duration) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+
+        match &outcome {
+            Outcome::Done | Outcome::Skipped => info!(
+                target: targets::MANAGER,
+                step = %step, elapsed_ms, "startup step done"
+            ),
+            Outcome::TimedOut => warn!(
+                target: targets::MANAGER,
+                step = %step, elapsed_ms, "startup step timed out"
+            ),
+            Outcome::Failed(reason) => warn!(
+                target: targets::MANAGER,
+                step = %step, elapsed_ms, error = %reason,
+                "startup step failed"
+            ),
+        }
+
+        self.steps.push(StepReport {
+            step,
+            outcome,
+            elapsed,
+        });
+    }
+
+    /// Report of every step that ended, in the order they ended
+    pub fn steps(&self) -> &[StepReport] {
+        &self.steps
+    }
+
+    /// Outcome of the given `Step`, if it ended
+    pub fn outcome(&self, step: &Step) -> Option<&Outcome> {
+        self.steps
+            .iter()
+            .find(|report| report.step == *step)
+            .map(|report| &report.outcome)
+    }
+
+    /// Report of the steps that did not complete
+    pub fn failures(&self) -> impl Iterator<Item = &StepReport> {
+        self.steps.iter().filter(|report| {
+            !matches!(report.outcome, Outcome::Done | Outcome::Skipped)
+        })
+    }
+
+    /// Total time spent starting the `System`
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self
+            .steps
+            .iter()
+            .map(|report| {
+                let (outcome, reason) = match &report.outcome {
+                    Outcome::Done => ("ok", ""),
+                    Outcome::Skipped => ("skipped", ""),
+                    Outcome::TimedOut => ("timed out", ""),
+                    Outcome::Failed(reason) => ("failed", reason.as_str()),
+                };
+
+                (report.step.to_string(), outcome, report.elapsed, reason)
+            })
+            .collect::<Vec<_>>();
+        let width = rows
+            .iter()
+            .map(|(step, ..)| step.len())
+            .chain(Some("step".len()))
+            .max()
+            .unwrap_or_default();
+
+        writeln!(
+            f,
+            "startup took {} ms, {} of {} steps failed",
+            self.elapsed.as_millis(),
+            self.failures().count(),
+            self.steps.len()
+        )?;
+        writeln!(
+            f,
+            "{:<width$}  {:<9}  {:>8}  error",
+            "step", "outcome", "time"
+        )?;
+
+        for (step, outcome, elapsed, reason) in rows {
+            writeln!(
+                f,
+                "{:<width$}  {:<9}  {:>5} ms  {}",
+                step,
+                outcome,
+                elapsed.as_millis(),
+                reason
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Steps of a startup along with those that are still running
+#[derive(Default)]
+struct Progress {
+    report: StartupReport,
+    running: Vec<(Step, Instant)>,
+}
+
+impl Progress {
+    /// Mark the running steps as timed out, returning the first of them
+    fn stall(&mut self) -> Option<Step> {
+        let running = std::mem::take(&mut self.running);
+        let first = running.first().map(|(step, _)| step.clone());
+
+        for (step, start) in running {
+            self.report.record(step, Outcome::TimedOut, start.elapsed());
+        }
+
+        first
+    }
+}
+
+fn lock(progress: &Mutex<Progress>) -> MutexGuard<'_, Progress> {
+    progress.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Run `step`, recording its outcome in `progress`
+async fn run<T, E, F>(
+    progress: &Mutex<Progress>,
+    step: Step,
+    fut: F,
+) -> Result<T, StartupError>
+where
+    E: fmt::Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+
+    lock(progress).running.push((step.clone(), start));
+
+    let result = fut.await;
+    let mut progress = lock(progress);
+
+    if let Some(i) = progress.running.iter().position(|(s, _)| *s == step) {
+        progress.running.remove(i);
+    }
+
+    match result {
+        Ok(value) => {
+            progress.report.record(step, Outcome::Done, start.elapsed());
+
+            Ok(value)
+        }
+        Err(e) => {
+            let reason = e.to_string();
+
+            progress.report.record(
+                step.clone(),
+                Outcome::Failed(reason.clone()),
+                start.elapsed(),
+            );
+
+            Failed { step, reason }.fail()
+        }
+    }
+}
+
+async fn start(
+    config: &StartupConfig,
+    progress: &Mutex<Progress>,
+) -> Result<System, StartupError> {
+    let keypair =
+        run(progress, Step::LoadKeys, async { config.keys.load() }).await?;
+    let exchanger = Exchanger::new(keypair.clone());
+    let connector = TcpConnector::new(exchanger.clone());
+    let mut system = System {
+        identity: Some(keypair.clone()),
+        ..Default::default()
+    };
+
+    for addr in &config.listen {
+        let listener = run(
+            progress,
+            Step::Listen(*addr),
+            TcpListener::new(*addr, exchanger.clone()),
+        )
+        .await?;
+
+        match config.directory {
+            Some(directory) => {
+                let step = Step::Register {
+                    listener: listener.local_addr().unwrap_or(*addr),
+                    directory,
+                };
+                let listener = run(progress, step, async {
+                    // fails early when the directory is unreachable, the
+                    // registration itself happens in the background
+                    let socket = connector
+                        .establish(keypair.public(), &directory)
+                        .await
+                        .map_err(|e| e.to_string())?;
+
+                    negotiate(&mut Connection::new(socket)).await;
+
+                    DirectoryListener::new(
+                        listener,
+                        TcpConnector::new(exchanger.clone()),
+                        directory,
+                    )
+                    .await
+                    .map_err(|e| e.to_string())
+                })
+                .await?;
+
+                let _ = system.add_listener(listener).await;
+            }
+            None => {
+                let _ = system.add_listener(listener).await;
+            }
+        }
+    }
+
+    let dials = config.peers.iter().map(|(pkey, addr)| async {
+        let step = Step::Dial(*pkey, *addr);
+        let connection = run(progress, step, connector.connect(pkey, addr));
+
+        connection.await.ok().map(|connection| (*pkey, connection))
+    });
+    let connections = future::join_all(dials).await;
+
+    if !config.peers.is_empty() && connections.iter().all(Option::is_none) {
+        return NoPeers {
+            count: config.peers.len(),
+        }
+        .fail();
+    }
+
+    system.connections.extend(connections.into_iter().flatten());
+
+    Ok(system)
+}
+
+impl System {
+    /// Start a `System` as described by `config`, giving up once its timeout
+    /// expires. A `StartupReport` telling how each step went is always
+    /// returned, and each step is also traced as it ends. Failing to dial
+    /// some of the peers is not an error as long as one of them could be
+    /// connected.
+    pub async fn from_config(
+        config: StartupConfig,
+    ) -> (Result<System, StartupError>, StartupReport) {
+        let begin = Instant::now();
+        let progress = Mutex::new(Progress::default());
+        let result =
+            time::timeout(config.timeout, start(&config, &progress)).await;
+        let mut progress =
+            progress.into_inner().unwrap_or_else(|e| e.into_inner());
+        let result = match result {
+            Ok(result) => result,
+            Err(_) => TimedOut {
+                timeout: config.timeout,
+                step: progress.stall(),
+            }
+            .fail(),
+        };
+
+        progress.report.elapsed = begin.elapsed();
+
+        match &result {
+            Ok(_) => info!(
+                target: targets::MANAGER,
+                "system started in {:?}", progress.report.elapsed
+            ),
+            Err(e) => warn!(
+                target: targets::MANAGER,
+                "system failed to start: {}\n{}", e, progress.report
+            ),
+        }
+
+        (result, progress.report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::server::DirectoryServer;
+    use crate::test::*;
+
+    /// Timeout used when the startup is expected to complete
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    async fn directory() -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+        let addr = next_test_ip4();
+        let listener = TcpListener::new(addr, Exchanger::random())
+            .await
+            .expect("listen failed");
+        let (server, exit) = DirectoryServer::new(Box::new(listener));
+
+        tokio::spawn(server.serve());
+
+        (addr, exit)
+    }
+
+    fn failure(report: &StartupReport) -> (&Step, &str) {
+        let failures = report.failures().collect::<Vec<_>>();
+
+        assert_eq!(failures.len(), 1, "wrong failures in\n{}", report);
+
+        match &failures[0].outcome {
+            Outcome::Failed(reason) => (&failures[0].step, reason),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn successful_startup() {
+        let (directory, _exit) = directory().await;
+        let peer = next_test_ip4();
+        let peer_exchanger = Exchanger::random();
+        let peer_listener = TcpListener::new(peer, peer_exchanger.clone())
+            .await
+            .expect("listen failed");
+        let accepted = tokio::spawn(async move {
+            let mut listener = peer_listener;
+
+            listener.accept().await.expect("accept failed")
+        });
+        let listen = next_test_ip4();
+        let config = StartupConfig::new()
+            .listen(listen)
+            .directory(directory)
+            .peer(*peer_exchanger.keypair().public(), peer)
+            .timeout(TIMEOUT);
+
+        let (system, report) = System::from_config(config).await;
+        let mut system = system.expect("startup failed");
+
+        accepted.await.expect("peer failed");
+
+        assert_eq!(report.steps().len(), 4, "{}", report);
+        assert_eq!(report.failures().count(), 0, "{}", report);
+        assert_eq!(system.connections().len(), 1);
+
+        let table = report.to_string();
+
+        assert!(table.contains("0 of 4 steps failed"), "{}", table);
+        assert!(
+            table.contains(&format!("listen on {}", listen)),
+            "{}",
+            table
+        );
+    }
+
+    #[cfg(feature = "keyfile")]
+    #[tokio::test]
+    async fn encrypted_keys() {
+        use crate::crypto::keyfile::{encrypt_keypair_to_file, KdfParams};
+
+        const PASSPHRASE_ENV: &str = "DROP_TEST_STARTUP_PASSPHRASE";
+
+        let keypair = KeyPair::random();
+        let path = std::env::temp_dir()
+            .join(format!("drop-startup-{}", keypair.public()));
+        let keys = |passphrase_env: &str| {
+            StartupConfig::new()
+                .keys(KeySource::EncryptedFile {
+                    path: path.clone(),
+                    passphrase_env: passphrase_env.to_string(),
+                })
+                .timeout(TIMEOUT)
+        };
+
+        encrypt_keypair_to_file(
+            &keypair,
+            &path,
+            b"startup passphrase",
+            KdfParams::minimum(),
+        )
+        .expect("encrypt failed");
+        std::env::set_var(PASSPHRASE_ENV, "startup passphrase");
+
+        let (system, report) = System::from_config(keys(PASSPHRASE_ENV)).await;
+        let system = system.expect("startup failed");
+
+        assert_eq!(report.outcome(&Step::LoadKeys), Some(&Outcome::Done));
+        assert_eq!(
+            system.identity.as_ref().map(KeyPair::public),
+            Some(keypair.public())
+        );
+
+        let (system, report) =
+            System::from_config(keys("DROP_TEST_STARTUP_UNSET")).await;
+
+        std::fs::remove_file(&path).expect("remove failed");
+
+        assert!(system.is_err(), "started without passphrase");
+
+        let (step, reason) = failure(&report);
+
+        assert_eq!(*step, Step::LoadKeys);
+        assert!(reason.contains("DROP_TEST_STARTUP_UNSET"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn dead_directory() {
+        let directory = next_test_ip4();
+        let listen = next_test_ip4();
+        let config = StartupConfig::new()
+            .listen(listen)
+            .directory(directory)
+            .timeout(TIMEOUT);
+
+        let (system, report) = System::from_config(config).await;
+
+        assert!(matches!(
+            system,
+            Err(StartupError::Failed {
+                step: Step::Register { .. },
+                ..
+            })
+        ));
+
+        let (step, reason) = failure(&report);
+
+        assert_eq!(
+            *step,
+            Step::Register {
+                listener: listen,
+                directory
+            }
+        );
+        assert!(reason.contains("refused"), "{}", reason);
+        assert!(report.to_string().contains("failed"), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn dead_peers() {
+        let pkey = *Exchanger::random().keypair().public();
+        let dead = [next_test_ip4(), next_test_ip4()];
+        let config = StartupConfig::new()
+            .peer(pkey, dead[0])
+            .peer(pkey, dead[1])
+            .timeout(TIMEOUT);
+
+        let (system, report) = System::from_config(config).await;
+
+        assert!(matches!(system, Err(StartupError::NoPeers { count: 2 })));
+        assert_eq!(report.failures().count(), 2, "{}", report);
+
+        for addr in dead {
+            assert!(matches!(
+                report.outcome(&Step::Dial(pkey, addr)),
+                Some(Outcome::Failed(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn occupied_port() {
+        let addr = next_test_ip4();
+        let _occupied = tokio::net::TcpListener::bind(addr)
+            .await
+            .expect("bind failed");
+
+        let (system, report) =
+            System::from_config(StartupConfig::new().listen(addr)).await;
+
+        assert!(matches!(
+            system,
+            Err(StartupError::Failed {
+                step: Step::Listen(_),
+                ..
+            })
+        ));
+
+        let (step, reason) = failure(&report);
+
+        assert_eq!(*step, Step::Listen(addr));
+        assert!(reason.contains("in use"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn stalled_directory_times_out() {
+        const STALL: Duration = Duration::from_millis(300);
+
+        let directory = next_test_ip4();
+        let stalled = tokio::net::TcpListener::bind(directory)
+            .await
+            .expect("bind failed");
+        // accept connections but never answer
+        let _server = tokio::spawn(async move {
+            let mut sockets = Vec::new();
+
+            while let Ok((socket, _)) = stalled.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let listen = next_test_ip4();
+        let config = StartupConfig::new()
+            .listen(listen)
+            .directory(directory)
+            .timeout(STALL);
+
+        let (system, report) = System::from_config(config).await;
+        let step = Step::Register {
+            listener: listen,
+            directory,
+        };
+
+        match system {
+            Err(StartupError::TimedOut {
+                step: Some(stalled),
+                timeout,
+            }) => {
+                assert_eq!(stalled, step);
+                assert_eq!(timeout, STALL);
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("startup did not time out"),
+        }
+
+        assert_eq!(report.outcome(&step), Some(&Outcome::TimedOut));
+        assert_eq!(report.outcome(&Step::Listen(listen)), Some(&Outcome::Done));
+        assert!(report.elapsed() >= STALL);
+    }
+}
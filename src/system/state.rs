@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use tokio::task::{self, JoinHandle};
+use tracing::debug;
+
+use super::KeysReceiver;
+use crate::crypto::key::exchange::PublicKey;
+use crate::telemetry::targets;
+
+/// A change in the set of peers followed by a [`PeerStateMap`]
+///
+/// [`PeerStateMap`]: self::PeerStateMap
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeerEvent<S> {
+    /// A peer appeared and its state was initialized with `S::default()`
+    Joined(PublicKey),
+    /// A peer left, along with its final state
+    Left(PublicKey, S),
+}
+
+type States<S> = Arc<Mutex<HashMap<PublicKey, S>>>;
+
+struct Shared<S> {
+    states: States<S>,
+    task: JoinHandle<()>,
+}
+
+impl<S> Drop for Shared<S> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Per-peer state of a `Processor` that follows the set of peers known by a
+/// `Sender`. The state of a peer is created when it is added to the `Sender`
+/// and dropped when it is removed. <br />
+/// A peer that joins and leaves before the `PeerStateMap` notices it is never
+/// reported. Cloning a `PeerStateMap` yields a handle to the same states.
+pub struct PeerStateMap<S> {
+    shared: Arc<Shared<S>>,
+}
+
+impl<S> PeerStateMap<S>
+where
+    S: Default + Send + 'static,
+{
+    /// Follow the peers from the given subscription, obtained using
+    /// [`Sender::watch_keys`]. This must be called from a tokio runtime.
+    ///
+    /// [`Sender::watch_keys`]: super::Sender::watch_keys
+    pub fn new(keys: KeysReceiver) -> Self {
+        Self::with_callback(keys, |_| ())
+    }
+
+    /// Follow the peers from the given subscription, calling `callback` for
+    /// every peer that joins or leaves in the order in which it happens.
+    /// Peers that are already known are reported as joining.
+    pub fn with_callback<F>(mut keys: KeysReceiver, mut callback: F) -> Self
+    where
+        F: FnMut(PeerEvent<S>) + Send + 'static,
+    {
+        let states: States<S> = Arc::default();
+        let initial = update(&states, &keys.borrow_and_update());
+        let task = {
+            let states = states.clone();
+
+            task::spawn(async move {
+                initial.into_iter().for_each(&mut callback);
+
+                while keys.changed().await.is_ok() {
+                    let current = keys.borrow_and_update().clone();
+
+                    update(&states, &current)
+                        .into_iter()
+                        .for_each(&mut callback);
+                }
+
+                debug!(
+                    target: targets::MANAGER,
+                    "sender dropped, peer states are now frozen"
+                );
+            })
+        };
+
+        Self {
+            shared: Arc::new(Shared { states, task }),
+        }
+    }
+}
+
+impl<S> PeerStateMap<S> {
+    /// Apply `f` to the state of `pkey`, returning `None` if the peer is not
+    /// known
+    pub fn with<R, F>(&self, pkey: &PublicKey, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut S) -> R,
+    {
+        self.states().get_mut(pkey).map(f)
+    }
+
+    /// Get a copy of the state of `pkey`
+    pub fn get(&self, pkey: &PublicKey) -> Option<S>
+    where
+        S: Clone,
+    {
+        self.states().get(pkey).cloned()
+    }
+
+    /// Check whether `pkey` currently has a state
+    pub fn contains(&self, pkey: &PublicKey) -> bool {
+        self.states().contains_key(pkey)
+    }
+
+    /// Get the keys of all peers that currently have a state
+    pub fn keys(&self) -> Vec<PublicKey> {
+        self.states().keys().copied().collect()
+    }
+
+    /// Number of peers that currently have a state
+    pub fn len(&self) -> usize {
+        self.states().len()
+    }
+
+    /// Check if no peer currently has a state
+    pub fn is_empty(&self) -> bool {
+        self.states().is_empty()
+    }
+
+    fn states(&self) -> std::sync::MutexGuard<'_, HashMap<PublicKey, S>> {
+        self.shared.states.lock().expect("peer states poisoned")
+    }
+}
+
+impl<S> Clone for PeerStateMap<S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Bring `states` in line with `current`, returning departures first and then
+/// arrivals
+fn update<S: Default>(
+    states: &States<S>,
+    current: &HashSet<PublicKey>,
+) -> Vec<PeerEvent<S>> {
+    let mut states = states.lock().expect("peer states poisoned");
+    let left: Vec<_> = states
+        .keys()
+        .filter(|pkey| !current.contains(pkey))
+        .copied()
+        .collect();
+
+    let mut events: Vec<_> = left
+        .into_iter()
+        .filter_map(|pkey| {
+            states
+                .remove(&pkey)
+                .map(|state| PeerEvent::Left(pkey, state))
+        })
+        .collect();
+
+    for pkey in current {
+        if !states.contains_key(pkey) {
+            states.insert(*pkey, S::default());
+            events.push(PeerEvent::Joined(*pkey));
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        crypto::key::exchange::Exchanger,
+        net::{
+            Connection, ConnectionWrite, Connector, Listener, TcpConnector,
+            TcpListener,
+        },
+        system::{NetworkSender, Sender},
+        test::next_test_ip4,
+    };
+
+    /// Accept `count` connections from distinct peers, returning the writing
+    /// half of each along with the dialing ends
+    async fn writes(count: usize) -> (Vec<ConnectionWrite>, Vec<Connection>) {
+        let addr = next_test_ip4();
+        let exchanger = Exchanger::random();
+        let pkey = *exchanger.keypair().public();
+        let mut listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+        let mut writes = Vec::new();
+        let mut dialers = Vec::new();
+
+        for _ in 0..count {
+            let connector = TcpConnector::new(Exchanger::random());
+
+            dialers.push(connector.connect(&pkey, &addr).await.unwrap());
+
+            let accepted = listener.accept().await.expect("accept failed");
+
+            writes.push(accepted.split().expect("split failed").1);
+        }
+
+        (writes, dialers)
+    }
+
+    #[tokio::test]
+    async fn lifecycle() {
+        let (mut writes, _dialers) = writes(2).await;
+        let (alice, bob) = (*writes[0].remote_pkey(), *writes[1].remote_pkey());
+        let sender = NetworkSender::<usize>::new(Vec::new());
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let states = PeerStateMap::with_callback(sender.watch_keys(), {
+            move |event: PeerEvent<usize>| tx.send(event).unwrap()
+        });
+
+        assert!(states.is_empty(), "state for unknown peer");
+
+        sender.add_connection(writes.remove(0)).await;
+
+        assert_eq!(events.recv().await, Some(PeerEvent::Joined(alice)));
+        assert_eq!(states.with(&alice, |count| *count += 3), Some(()));
+
+        sender.add_connection(writes.remove(0)).await;
+
+        assert_eq!(events.recv().await, Some(PeerEvent::Joined(bob)));
+        assert_eq!(states.len(), 2);
+
+        sender.remove_connection(&alice).await;
+
+        assert_eq!(events.recv().await, Some(PeerEvent::Left(alice, 3)));
+        assert!(!states.contains(&alice), "state kept after leaving");
+
+        // removing an unknown peer is not a transition
+        sender.remove_connection(&alice).await;
+        sender.remove_connection(&bob).await;
+
+        assert_eq!(events.recv().await, Some(PeerEvent::Left(bob, 0)));
+
+        drop(sender);
+
+        assert_eq!(events.recv().await, None, "spurious event");
+        assert!(states.is_empty(), "states left behind");
+    }
+
+    #[tokio::test]
+    async fn initial_peers() {
+        let (writes, _dialers) = writes(2).await;
+        let keys: HashSet<_> =
+            writes.iter().map(|w| *w.remote_pkey()).collect();
+        let sender = NetworkSender::<usize>::new(writes);
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let states = PeerStateMap::<usize>::with_callback(
+            sender.watch_keys(),
+            move |event| tx.send(event).unwrap(),
+        );
+
+        assert_eq!(states.keys().into_iter().collect::<HashSet<_>>(), keys);
+
+        let mut joined = HashSet::new();
+
+        for _ in 0..keys.len() {
+            match events.recv().await {
+                Some(PeerEvent::Joined(pkey)) => assert!(joined.insert(pkey)),
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+
+        assert_eq!(joined, keys);
+        assert_eq!(sender.close().await, 2);
+
+        for _ in 0..keys.len() {
+            match events.recv().await {
+                Some(PeerEvent::Left(pkey, 0)) => assert!(joined.remove(&pkey)),
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+
+        assert!(states.is_empty(), "states left behind");
+    }
+}
@@ -0,0 +1,542 @@
+use std::{collections::BTreeSet, fmt, sync::Arc};
+
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+
+use super::{NeighborSampler, Sampler};
+use crate::crypto::key::exchange::PublicKey;
+
+/// A description of the overlay built by a [`System`] among a known set of
+/// peers. Every peer must compute the same neighbors given the same set of
+/// peers, in whatever order it is given.
+///
+/// [`System`]: super::System
+pub trait Topology: Send + Sync {
+    /// Get the peers that `local` should be connected to among `all`, which
+    /// may or may not contain `local`
+    fn neighbors(&self, local: &PublicKey, all: &[PublicKey])
+        -> Vec<PublicKey>;
+}
+
+/// Sort the keys of `all` along with `local` so that every peer sees the same
+/// set of members
+fn members(local: &PublicKey, all: &[PublicKey]) -> Vec<PublicKey> {
+    all.iter()
+        .chain(Some(local))
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// A [`Topology`] where every peer is connected to every other peer
+///
+/// [`Topology`]: self::Topology
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FullMesh;
+
+impl Topology for FullMesh {
+    fn neighbors(
+        &self,
+        local: &PublicKey,
+        all: &[PublicKey],
+    ) -> Vec<PublicKey> {
+        members(local, all)
+            .into_iter()
+            .filter(|pkey| pkey != local)
+            .collect()
+    }
+}
+
+/// A [`Topology`] where peers are sorted by `PublicKey` on a ring and
+/// connected to the `k` closest peers on each side
+///
+/// [`Topology`]: self::Topology
+#[derive(Clone, Copy, Debug)]
+pub struct Ring {
+    /// Number of neighbors on each side of a peer
+    pub k: usize,
+}
+
+impl Topology for Ring {
+    fn neighbors(
+        &self,
+        local: &PublicKey,
+        all: &[PublicKey],
+    ) -> Vec<PublicKey> {
+        let members = members(local, all);
+        let count = members.len();
+        let position = members
+            .iter()
+            .position(|pkey| pkey == local)
+            .expect("local key is a member");
+
+        (1..=self.k.min(count / 2))
+            .flat_map(|distance| {
+                [
+                    members[(position + distance) % count],
+                    members[(position + count - distance) % count],
+                ]
+            })
+            .filter(|pkey| pkey != local)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// A [`Topology`] where every peer picks `degree` random peers using an rng
+/// seeded with `seed`. Connections are bidirectional, so a peer may end up
+/// with more than `degree` neighbors.
+///
+/// [`Topology`]: self::Topology
+#[derive(Clone, Copy, Debug)]
+pub struct Random {
+    /// Number of neighbors picked by each peer
+    pub degree: usize,
+    /// Seed shared by all peers
+    pub seed: u64,
+}
+
+impl Topology for Random {
+    fn neighbors(
+        &self,
+        local: &PublicKey,
+        all: &[PublicKey],
+    ) -> Vec<PublicKey> {
+        let members = members(local, all);
+        let count = members.len();
+        let position = members
+            .iter()
+            .position(|pkey| pkey == local)
+            .expect("local key is a member");
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut neighbors = BTreeSet::new();
+
+        if count < 2 {
+            return Vec::new();
+        }
+
+        // every peer draws the whole graph to find who picked it
+        for picker in 0..count {
+            let picked =
+                index::sample(&mut rng, count - 1, self.degree.min(count - 1))
+                    .into_iter()
+                    .map(
+                        |other| if other >= picker { other + 1 } else { other },
+                    );
+
+            for other in picked {
+                if picker == position {
+                    neighbors.insert(members[other]);
+                } else if other == position {
+                    neighbors.insert(members[picker]);
+                }
+            }
+        }
+
+        neighbors.into_iter().collect()
+    }
+}
+
+/// The neighbors assigned to the local peer by a [`Topology`], used by a
+/// [`System`] to decide which peers to dial and which `Connection`s to
+/// accept. Each pair of neighbors is connected once: when both are neighbors
+/// of each other, only the peer with the smallest `PublicKey` dials.
+///
+/// [`Topology`]: self::Topology
+/// [`System`]: super::System
+#[derive(Clone)]
+pub struct Overlay {
+    topology: Arc<dyn Topology>,
+    local: PublicKey,
+    peers: Vec<PublicKey>,
+    neighbors: Vec<PublicKey>,
+}
+
+impl Overlay {
+    /// Compute the neighbors of `local` among `peers` using `topology`
+    pub fn new(
+        topology: Arc<dyn Topology>,
+        local: PublicKey,
+        peers: &[PublicKey],
+    ) -> Self {
+        let peers = members(&local, peers);
+        let neighbors = topology.neighbors(&local, &peers);
+
+        Self {
+            topology,
+            local,
+            peers,
+            neighbors,
+        }
+    }
+
+    /// Get the neighbors assigned to the local peer
+    pub fn neighbors(&self) -> &[PublicKey] {
+        &self.neighbors
+    }
+
+    /// Check whether the local peer should dial `remote`
+    pub fn dials(&self, remote: &PublicKey) -> bool {
+        self.neighbors.contains(remote)
+            && (self.local < *remote
+                || !self.remote_neighbors(remote).contains(&self.local))
+    }
+
+    /// Check whether a `Connection` from `remote` should be accepted, which
+    /// is the case when `remote` is a known peer that dials the local peer
+    pub fn accepts(&self, remote: &PublicKey) -> bool {
+        *remote != self.local
+            && self.peers.contains(remote)
+            && self.remote_neighbors(remote).contains(&self.local)
+            && (*remote < self.local || !self.neighbors.contains(remote))
+    }
+
+    /// Restrict `sampler` to the neighbors of the local peer
+    pub fn sampler<S: Sampler>(&self, sampler: S) -> NeighborSampler<S> {
+        NeighborSampler::new(sampler, self.neighbors.iter().copied())
+    }
+
+    fn remote_neighbors(&self, remote: &PublicKey) -> Vec<PublicKey> {
+        self.topology.neighbors(remote, &self.peers)
+    }
+}
+
+impl fmt::Debug for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Overlay")
+            .field("local", &self.local)
+            .field("neighbors", &self.neighbors)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Mutex as StdMutex,
+        time::Duration,
+    };
+
+    use futures::{future, StreamExt};
+    use tokio::{
+        sync::{mpsc, Mutex},
+        time,
+    };
+
+    use super::*;
+    use crate::{
+        async_trait,
+        crypto::key::exchange::Exchanger,
+        net::{Connector, TcpConnector, TcpListener},
+        system::{
+            AllSampler, Handle, NetworkSender, Processor, Sender, SenderError,
+            System, SystemManager,
+        },
+        test::*,
+    };
+
+    fn check_symmetric(topology: &dyn Topology, keys: &[PublicKey]) {
+        for local in keys {
+            for remote in topology.neighbors(local, keys) {
+                assert!(
+                    topology.neighbors(&remote, keys).contains(local),
+                    "asymmetric edge"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ring_neighbors() {
+        let mut keys = keyset(6).collect::<Vec<_>>();
+
+        check_symmetric(&Ring { k: 1 }, &keys);
+        check_symmetric(&Ring { k: 2 }, &keys);
+
+        keys.sort();
+
+        let neighbors = Ring { k: 1 }.neighbors(&keys[0], &keys);
+
+        assert_eq!(neighbors, [keys[1], keys[5]]);
+        assert_eq!(Ring { k: 5 }.neighbors(&keys[0], &keys).len(), 5);
+    }
+
+    #[test]
+    fn random_neighbors() {
+        let keys = keyset(20).collect::<Vec<_>>();
+        let topology = Random { degree: 3, seed: 7 };
+        let mut shuffled = keys.clone();
+
+        shuffled.reverse();
+
+        check_symmetric(&topology, &keys);
+
+        for key in &keys {
+            let neighbors = topology.neighbors(key, &keys);
+
+            assert!(neighbors.len() >= 3, "too few neighbors");
+            assert_eq!(neighbors, topology.neighbors(key, &shuffled));
+        }
+    }
+
+    #[test]
+    fn one_connection_per_edge() {
+        let keys = keyset(8).collect::<Vec<_>>();
+        let topology: Arc<dyn Topology> =
+            Arc::new(Random { degree: 2, seed: 1 });
+        let overlays = keys
+            .iter()
+            .map(|key| (*key, Overlay::new(topology.clone(), *key, &keys)))
+            .collect::<HashMap<_, _>>();
+
+        for (local, overlay) in &overlays {
+            for remote in &keys {
+                let dialed = overlay.dials(remote);
+                let accepted = overlays[remote].accepts(local);
+
+                assert_eq!(dialed, accepted, "dial not accepted");
+                assert!(
+                    !(dialed && overlays[remote].dials(local)),
+                    "edge dialed twice"
+                );
+            }
+
+            let stranger = *Exchanger::random().keypair().public();
+
+            assert!(!overlay.accepts(&stranger), "stranger accepted");
+        }
+    }
+
+    /// A `Processor` that forwards every new message to all of its peers
+    /// except the one it came from
+    #[derive(Default)]
+    struct Flood {
+        seen: Arc<StdMutex<HashSet<usize>>>,
+        delivered: Option<mpsc::UnboundedSender<(PublicKey, usize)>>,
+    }
+
+    #[derive(Clone)]
+    struct FloodHandle {
+        seen: Arc<StdMutex<HashSet<usize>>>,
+        sender: Arc<NetworkSender<usize>>,
+        delivered: Arc<Mutex<mpsc::UnboundedReceiver<(PublicKey, usize)>>>,
+    }
+
+    #[async_trait]
+    impl Handle<usize, (PublicKey, usize)> for FloodHandle {
+        type Error = SenderError;
+
+        async fn deliver(&mut self) -> Result<(PublicKey, usize), Self::Error> {
+            Ok(self.delivered.lock().await.recv().await.expect("no flood"))
+        }
+
+        async fn try_deliver(
+            &mut self,
+        ) -> Result<Option<(PublicKey, usize)>, Self::Error> {
+            Ok(self.delivered.lock().await.try_recv().ok())
+        }
+
+        async fn broadcast(
+            &mut self,
+            message: &usize,
+        ) -> Result<(), Self::Error> {
+            self.seen.lock().unwrap().insert(*message);
+
+            let keys = self.sender.keys().await;
+
+            self.sender
+                .send_many_collecting(*message, keys.iter())
+                .await
+        }
+    }
+
+    #[async_trait]
+    impl Processor<usize, usize, (PublicKey, usize), NetworkSender<usize>>
+        for Flood
+    {
+        type Handle = FloodHandle;
+
+        type Error = SenderError;
+
+        async fn process(
+            &self,
+            message: usize,
+            from: PublicKey,
+            sender: Arc<NetworkSender<usize>>,
+        ) -> Result<(), Self::Error> {
+            if !self.seen.lock().unwrap().insert(message) {
+                return Ok(());
+            }
+
+            let _ = self.delivered.as_ref().unwrap().send((from, message));
+
+            let keys = sender.keys().await;
+
+            sender
+                .send_many_collecting(
+                    message,
+                    keys.iter().filter(|key| **key != from),
+                )
+                .await
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _: Arc<SA>,
+            sender: Arc<NetworkSender<usize>>,
+        ) -> Self::Handle {
+            let (tx, rx) = mpsc::unbounded_channel();
+
+            self.delivered = Some(tx);
+
+            FloodHandle {
+                seen: self.seen.clone(),
+                sender,
+                delivered: Arc::new(Mutex::new(rx)),
+            }
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<NetworkSender<usize>>,
+            _: Arc<SA>,
+        ) {
+        }
+
+        async fn garbage_collection(&self) {}
+    }
+
+    #[tokio::test]
+    async fn ring_broadcast() {
+        const COUNT: usize = 6;
+
+        let addrs = test_addrs(COUNT);
+        let peers = addrs
+            .iter()
+            .map(|(exchanger, addr)| (*exchanger.keypair().public(), *addr))
+            .collect::<Vec<_>>();
+        let keys = peers.iter().map(|(pkey, _)| *pkey).collect::<Vec<_>>();
+        let mut listeners = Vec::new();
+
+        for (exchanger, addr) in &addrs {
+            listeners.push(
+                TcpListener::new(*addr, exchanger.clone())
+                    .await
+                    .expect("listen failed"),
+            );
+        }
+
+        let systems = future::join_all(addrs.iter().map(|(exchanger, _)| {
+            let peers = peers.clone();
+
+            async move {
+                let connector = TcpConnector::new(exchanger.clone());
+
+                System::new_with_topology(
+                    &connector,
+                    Ring { k: 1 },
+                    *exchanger.keypair().public(),
+                    peers,
+                )
+                .await
+            }
+        }))
+        .await;
+
+        let mut handles = Vec::new();
+
+        for (mut system, listener) in systems.into_iter().zip(listeners) {
+            let _ = system.add_listener(listener).await;
+
+            handles.push(
+                SystemManager::new(system)
+                    .run(Flood::default(), AllSampler::default(), 1)
+                    .await,
+            );
+        }
+
+        for handle in &handles {
+            handle
+                .wait_for_peers(2, Some(Duration::from_secs(5)))
+                .await
+                .expect("ring incomplete");
+        }
+
+        handles[0]
+            .processor_handle()
+            .broadcast(&7)
+            .await
+            .expect("broadcast failed");
+
+        for (index, handle) in handles.iter().enumerate().skip(1) {
+            let (from, message) = time::timeout(
+                Duration::from_secs(5),
+                handle.processor_handle().deliver(),
+            )
+            .await
+            .expect("flood did not reach node")
+            .unwrap();
+            let neighbors = Ring { k: 1 }.neighbors(&keys[index], &keys);
+
+            assert_eq!(message, 7);
+            assert!(neighbors.contains(&from), "message skipped a hop");
+        }
+
+        for handle in &handles {
+            assert_eq!(*handle.on_peer_count().borrow(), 2, "extra peers");
+        }
+    }
+
+    #[tokio::test]
+    async fn stranger_rejected() {
+        let addrs = test_addrs(2);
+        let peers = addrs
+            .iter()
+            .map(|(exchanger, addr)| (*exchanger.keypair().public(), *addr))
+            .collect::<Vec<_>>();
+        let (exchanger, addr) = addrs[0].clone();
+        let local = *exchanger.keypair().public();
+        let listener = TcpListener::new(addr, exchanger.clone())
+            .await
+            .expect("listen failed");
+        let connector = TcpConnector::new(exchanger);
+        let mut system =
+            System::new_with_topology(&connector, FullMesh, local, peers).await;
+
+        let _ = system.add_listener(listener).await;
+
+        let mut incoming = system.peer_source();
+        let mut stranger = TcpConnector::new(Exchanger::random())
+            .connect(&local, &addr)
+            .await
+            .expect("connect failed");
+
+        assert!(
+            stranger.receive::<usize>().await.is_err(),
+            "stranger not closed"
+        );
+
+        // the other peer dials since it has the smallest key
+        let known = addrs[1].0.clone();
+
+        if *known.keypair().public() < local {
+            let _connection = TcpConnector::new(known.clone())
+                .connect(&local, &addr)
+                .await
+                .expect("connect failed");
+            let accepted = incoming.next().await.expect("no connection");
+
+            assert_eq!(accepted.remote_key(), Some(*known.keypair().public()));
+        } else {
+            assert!(
+                time::timeout(Duration::from_millis(100), incoming.next())
+                    .await
+                    .is_err(),
+                "stranger accepted"
+            );
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::BTreeMap,
+    sync::{OnceLock, RwLock},
+};
+
+use tracing::{callsite, subscriber::Interest, Metadata, Subscriber};
+pub use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Context, Layer},
+};
+
+/// Level used for targets that no filter matches
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::INFO;
+
+struct Filter {
+    default: LevelFilter,
+    subsystems: BTreeMap<String, LevelFilter>,
+    targets: Targets,
+}
+
+impl Filter {
+    fn new() -> Self {
+        Self {
+            default: DEFAULT_LEVEL,
+            subsystems: BTreeMap::new(),
+            targets: Targets::new().with_default(DEFAULT_LEVEL),
+        }
+    }
+
+    fn update(&mut self) {
+        self.targets = Targets::new()
+            .with_default(self.default)
+            .with_targets(self.subsystems.clone());
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.subsystems
+            .values()
+            .copied()
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+fn filter() -> &'static RwLock<Filter> {
+    static FILTER: OnceLock<RwLock<Filter>> = OnceLock::new();
+
+    FILTER.get_or_init(|| RwLock::new(Filter::new()))
+}
+
+fn modify(f: impl FnOnce(&mut Filter)) {
+    {
+        let mut filter = filter().write().unwrap_or_else(|e| e.into_inner());
+
+        f(&mut filter);
+        filter.update();
+    }
+
+    // callsites cache whether they are enabled
+    callsite::rebuild_interest_cache();
+}
+
+/// A `Layer` filtering events according to the levels set using
+/// [`set_filter`] and [`set_default`]. Targets that no filter matches are
+/// enabled up to `INFO` unless changed using `set_default`.
+///
+/// [`set_filter`]: self::set_filter
+/// [`set_default`]: self::set_default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TelemetryLayer {
+    _private: (),
+}
+
+impl TelemetryLayer {
+    fn enables(&self, metadata: &Metadata<'_>) -> bool {
+        filter()
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .targets
+            .would_enable(metadata.target(), metadata.level())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for TelemetryLayer {
+    fn register_callsite(
+        &self,
+        metadata: &'static Metadata<'static>,
+    ) -> Interest {
+        if self.enables(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _: Context<'_, S>) -> bool {
+        self.enables(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(
+            filter()
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .max_level(),
+        )
+    }
+}
+
+/// Returns a `Layer` that can be added to any subscriber to control the
+/// verbosity of drop at runtime. All layers share the same filters.
+pub fn layer() -> TelemetryLayer {
+    TelemetryLayer::default()
+}
+
+/// Change the maximum level of the events emitted by `subsystem`, either a
+/// full target such as `drop::net::connection` or one relative to drop such
+/// as `net::connection`. Subsystems nest: a filter on `net` applies to every
+/// target in `drop::net` that has no filter of its own.
+pub fn set_filter(subsystem: &str, level: LevelFilter) {
+    let target = if subsystem == "drop" || subsystem.starts_with("drop::") {
+        subsystem.to_string()
+    } else {
+        format!("drop::{}", subsystem)
+    };
+
+    modify(|filter| {
+        filter.subsystems.insert(target, level);
+    });
+}
+
+/// Change the maximum level of targets that no filter set using
+/// [`set_filter`] matches, including targets outside of drop
+///
+/// [`set_filter`]: self::set_filter
+pub fn set_default(level: LevelFilter) {
+    modify(|filter| filter.default = level);
+}
+
+/// Remove all filters and restore the default level to `INFO`
+pub fn reset() {
+    modify(|filter| *filter = Filter::new());
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{debug, info, span, subscriber, trace, warn, Event, Level};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::*;
+    use crate::{telemetry::targets, test::create_system};
+
+    /// Records the target of every event and span along with the module
+    /// emitting it
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(String, String, Level)>>>);
+
+    impl Capture {
+        fn record(&self, metadata: &Metadata<'_>) {
+            self.0.lock().unwrap().push((
+                metadata.target().to_string(),
+                metadata.module_path().unwrap_or_default().to_string(),
+                *metadata.level(),
+            ));
+        }
+
+        fn take(&self) -> Vec<(String, Level)> {
+            self.0
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(target, _, level)| (target, level))
+                .collect()
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            self.record(event.metadata());
+        }
+
+        fn on_new_span(
+            &self,
+            attrs: &span::Attributes<'_>,
+            _: &span::Id,
+            _: Context<'_, S>,
+        ) {
+            self.record(attrs.metadata());
+        }
+    }
+
+    #[tokio::test]
+    async fn targets_are_documented() {
+        let capture = Capture::default();
+        let _guard =
+            subscriber::set_default(Registry::default().with(capture.clone()));
+
+        let (_, handle, _system) =
+            create_system(3, |mut connection| async move {
+                connection.send(&0u64).await.expect("send failed");
+            })
+            .await;
+
+        handle.await.expect("system failure");
+
+        let records = capture.0.lock().unwrap().clone();
+        let used = records
+            .iter()
+            .filter(|(_, module, _)| {
+                module.starts_with("drop::net")
+                    || module.starts_with("drop::system")
+            })
+            .map(|(target, module, _)| {
+                assert!(
+                    targets::ALL.contains(&target.as_str()),
+                    "undocumented target {} in {}",
+                    target,
+                    module
+                );
+
+                target.as_str()
+            })
+            .collect::<HashSet<_>>();
+
+        for target in [targets::CONNECTOR, targets::LISTENER, targets::MANAGER]
+        {
+            assert!(used.contains(target), "nothing emitted to {}", target);
+        }
+    }
+
+    #[test]
+    fn filters_change_at_runtime() {
+        let capture = Capture::default();
+        let subscriber =
+            Registry::default().with(layer()).with(capture.clone());
+
+        subscriber::with_default(subscriber, || {
+            reset();
+
+            debug!(target: targets::CONNECTION, "hidden");
+            info!(target: targets::CONNECTION, "shown");
+
+            assert_eq!(
+                capture.take(),
+                vec![(targets::CONNECTION.to_string(), Level::INFO)]
+            );
+
+            set_filter("net::connection", LevelFilter::DEBUG);
+
+            trace!(target: targets::CONNECTION, "hidden");
+            debug!(target: targets::CONNECTION, "shown");
+            debug!(target: targets::DIRECTORY, "hidden");
+
+            assert_eq!(
+                capture.take(),
+                vec![(targets::CONNECTION.to_string(), Level::DEBUG)]
+            );
+
+            set_filter(targets::CONNECTION, LevelFilter::WARN);
+            set_filter("net", LevelFilter::TRACE);
+
+            info!(target: targets::CONNECTION, "hidden");
+            warn!(target: targets::CONNECTION, "shown");
+            trace!(target: targets::DIRECTORY, "shown");
+            debug!(target: targets::MANAGER, "hidden");
+
+            assert_eq!(
+                capture.take(),
+                vec![
+                    (targets::CONNECTION.to_string(), Level::WARN),
+                    (targets::DIRECTORY.to_string(), Level::TRACE),
+                ]
+            );
+
+            set_default(LevelFilter::OFF);
+            reset();
+
+            info!(target: targets::SENDER, "shown");
+
+            assert_eq!(
+                capture.take(),
+                vec![(targets::SENDER.to_string(), Level::INFO)]
+            );
+        });
+    }
+}
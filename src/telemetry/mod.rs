@@ -0,0 +1,59 @@
+//! Every tracing event and span emitted by drop uses one of the targets
+//! listed in [`targets`], one per subsystem:
+//!
+//! | target                   | subsystem                                     |
+//! |--------------------------|-----------------------------------------------|
+//! | `drop::net::connection`  | encryption and framing of `Connection`s       |
+//! | `drop::net::connector`   | connecting to peers, pooled connections       |
+//! | `drop::net::listener`    | accepting incoming connections                |
+//! | `drop::net::directory`   | directory servers and their clients           |
+//! | `drop::net::health`      | health check server                           |
+//! | `drop::system::manager`  | `SystemManager` and the state of its peers    |
+//! | `drop::system::sender`   | `NetworkSender` and its per peer agents       |
+//! | `drop::system::router`   | `Router` dispatching messages to handlers     |
+//! | `drop::system::node`     | `Node` lifecycle and shutdown                 |
+//! | `drop::dump`             | snapshots of the state of a node              |
+//!
+//! Filters on these targets can be written for any subscriber. The `layer`
+//! returned by [`layer`] additionally allows changing the verbosity of a
+//! subsystem while the application is running using [`set_filter`].
+//!
+//! [`targets`]: self::targets
+//! [`layer`]: self::layer
+//! [`set_filter`]: self::set_filter
+
+/// Tracing targets used by drop
+pub mod targets {
+    /// Encryption and framing of established `Connection`s
+    pub const CONNECTION: &str = "drop::net::connection";
+    /// Connecting to remote peers, including pooled and proxied connections
+    pub const CONNECTOR: &str = "drop::net::connector";
+    /// Accepting incoming connections
+    pub const LISTENER: &str = "drop::net::listener";
+    /// Directory servers and the listeners and connectors using them
+    pub const DIRECTORY: &str = "drop::net::directory";
+    /// Health check server
+    pub const HEALTH: &str = "drop::net::health";
+    /// `SystemManager` and the state of its peers
+    pub const MANAGER: &str = "drop::system::manager";
+    /// `NetworkSender` and its per peer agents
+    pub const SENDER: &str = "drop::system::sender";
+    /// `Router` dispatching messages to their handlers
+    pub const ROUTER: &str = "drop::system::router";
+    /// `Node` lifecycle and shutdown
+    pub const NODE: &str = "drop::system::node";
+    /// Snapshots of the state of a node, see `NodeSnapshot::log`
+    pub const DUMP: &str = "drop::dump";
+
+    /// All the targets used by drop
+    pub const ALL: [&str; 10] = [
+        CONNECTION, CONNECTOR, LISTENER, DIRECTORY, HEALTH, MANAGER, SENDER,
+        ROUTER, NODE, DUMP,
+    ];
+}
+
+#[cfg(feature = "telemetry")]
+mod filter;
+#[cfg(feature = "telemetry")]
+#[cfg_attr(docsrs, doc(cfg(feature = "telemetry")))]
+pub use filter::*;
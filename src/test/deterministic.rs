@@ -0,0 +1,157 @@
+use std::{
+    env,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+};
+
+use tokio::runtime::Builder;
+
+use crate::system::schedule;
+
+/// Environment variable that makes `stress` replay a single seed
+pub const TEST_SEED: &str = "DROP_TEST_SEED";
+
+/// Run the future returned by `body` to completion on a single-threaded
+/// `tokio` runtime with paused time. The ordering decisions taken by drop
+/// itself, such as the order of `Sender::keys` or the interleaving of the
+/// tasks of a `SystemManager`, only depend on `seed` so that two runs using
+/// the same seed schedule their tasks the same way.
+pub fn deterministic<F, Fut>(seed: u64, body: F) -> Fut::Output
+where
+    F: FnOnce(u64) -> Fut,
+    Fut: Future,
+{
+    let runtime = Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .expect("failed to build runtime");
+    let _seeded = schedule::seed(seed);
+
+    runtime.block_on(body(seed))
+}
+
+/// Run `body` using `deterministic` once for every seed in `0..runs`,
+/// stopping at the first failure. The seed of the failed run is reported and
+/// can be replayed by setting the `DROP_TEST_SEED` environment variable.
+pub fn stress<F, Fut>(runs: u64, body: F)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let seeds = match env::var(TEST_SEED) {
+        Ok(seed) => {
+            let seed = seed.parse().expect("invalid test seed");
+
+            seed..seed + 1
+        }
+        Err(_) => 0..runs,
+    };
+
+    for seed in seeds {
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            deterministic(seed, &body)
+        }));
+
+        if let Err(e) = run {
+            eprintln!(
+                "deterministic run failed with seed {}, set {}={} to replay it",
+                seed, TEST_SEED, seed
+            );
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::{task, time};
+
+    use super::*;
+
+    /// Record the order in which a few tasks that yield complete
+    async fn interleaving() -> Vec<usize> {
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let tasks = (0..8)
+            .map(|idx| {
+                let order = order.clone();
+
+                task::spawn(async move {
+                    schedule::interleave().await;
+                    order.lock().unwrap().push(idx);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            task.await.expect("task panicked");
+        }
+
+        let order = order.lock().unwrap().clone();
+
+        order
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        for seed in 0..16 {
+            assert_eq!(
+                deterministic(seed, |_| interleaving()),
+                deterministic(seed, |_| interleaving()),
+                "seed {} is not deterministic",
+                seed
+            );
+        }
+
+        let orders = (0..16)
+            .map(|seed| deterministic(seed, |_| interleaving()))
+            .collect::<std::collections::HashSet<_>>();
+
+        assert!(orders.len() > 1, "seed does not change ordering");
+    }
+
+    #[test]
+    fn arrange_depends_on_seed() {
+        let arranged = |seed| {
+            deterministic(seed, |_| async {
+                let mut items = (0u8..32).rev().collect::<Vec<_>>();
+
+                schedule::arrange(&mut items, |x| [*x]);
+
+                items
+            })
+        };
+
+        assert_eq!(arranged(1), arranged(1), "arrange is not deterministic");
+        assert_ne!(arranged(1), arranged(2), "seed does not change order");
+
+        let mut items = vec![3u8, 1, 2];
+
+        schedule::arrange(&mut items, |x| [*x]);
+
+        assert_eq!(items, [3, 1, 2], "order changed outside of a run");
+    }
+
+    #[test]
+    fn time_is_paused() {
+        deterministic(0, |_| async {
+            let start = time::Instant::now();
+
+            time::sleep(Duration::from_secs(3600)).await;
+
+            assert!(start.elapsed() >= Duration::from_secs(3600));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "failing run")]
+    fn stress_reports_failure() {
+        stress(4, |seed| async move {
+            assert!(seed < 2, "failing run");
+        });
+    }
+}
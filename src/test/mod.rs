@@ -1,12 +1,33 @@
 #[cfg(feature = "net")]
 mod net;
-#[cfg(any(feature = "net", feature = "test"))]
-pub use net::*;
 
 mod log;
 pub use log::*;
 
+mod stability;
+pub use stability::*;
+
 #[cfg(any(feature = "system", feature = "test"))]
 mod system;
 #[cfg(any(feature = "system", feature = "test"))]
 pub use system::*;
+
+#[cfg(any(feature = "system", feature = "test"))]
+mod wire;
+#[cfg(any(feature = "system", feature = "test"))]
+pub use wire::*;
+
+#[cfg(any(feature = "system", feature = "test"))]
+mod deterministic;
+#[cfg(any(feature = "system", feature = "test"))]
+pub use deterministic::*;
+
+#[cfg(any(feature = "system", feature = "test"))]
+mod processor;
+#[cfg(any(feature = "system", feature = "test"))]
+pub use processor::*;
+
+#[cfg(any(feature = "system", feature = "test"))]
+mod replay;
+#[cfg(any(feature = "system", feature = "test"))]
+pub use replay::*;
@@ -3,8 +3,8 @@
 #[macro_export]
 macro_rules! generate_connection {
     ($listener:ty , $connector:ty) => {
-        use crate::crypto::key::exchange::{Exchanger, KeyPair};
-        use crate::net::Connector;
+        use $crate::crypto::key::exchange::{Exchanger, KeyPair};
+        use $crate::net::Connector;
 
         let client = KeyPair::random();
         let server = KeyPair::random();
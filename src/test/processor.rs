@@ -0,0 +1,332 @@
+use std::{
+    collections::BTreeMap, marker::PhantomData, sync::Arc, time::Duration,
+};
+
+use tokio::time;
+
+use crate::{
+    crypto::key::exchange::PublicKey,
+    system::{AllSampler, CollectingSender, Processor, Sampler, Sender},
+    Message,
+};
+
+/// An event of the timeline played by `ProcessorTester::play`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Scripted<M> {
+    /// A message arrives from the given peer
+    Receive(PublicKey, M),
+    /// The connection to the given peer is lost
+    Disconnect(PublicKey),
+    /// The `Processor` is asked to collect garbage
+    Collect,
+    /// The clock moves forward, which requires time to be paused
+    Advance(Duration),
+}
+
+/// Drives a `Processor` without a `SystemManager`, calling it directly from
+/// the current task so that assertions interleave deterministically with
+/// processing. Messages sent by the `Processor` are collected by a
+/// `CollectingSender` instead of reaching the network.
+pub struct ProcessorTester<P, M, I, O, SA = AllSampler>
+where
+    M: Message + 'static,
+    I: Into<M>,
+    O: Send,
+    P: Processor<M, I, O, CollectingSender<M>>,
+    SA: Sampler,
+{
+    processor: Arc<P>,
+    sender: Arc<CollectingSender<M>>,
+    sampler: Arc<SA>,
+    handle: P::Handle,
+    _i: PhantomData<I>,
+    _o: PhantomData<O>,
+}
+
+impl<P, M, I, O> ProcessorTester<P, M, I, O>
+where
+    M: Message + 'static,
+    I: Into<M>,
+    O: Send,
+    P: Processor<M, I, O, CollectingSender<M>>,
+{
+    /// Setup `processor` with `sender` and an `AllSampler`
+    pub async fn new(processor: P, sender: CollectingSender<M>) -> Self {
+        Self::with_sampler(processor, sender, AllSampler::default()).await
+    }
+}
+
+impl<P, M, I, O, SA> ProcessorTester<P, M, I, O, SA>
+where
+    M: Message + 'static,
+    I: Into<M>,
+    O: Send,
+    P: Processor<M, I, O, CollectingSender<M>>,
+    SA: Sampler,
+{
+    /// Setup `processor` with `sender` and the given `Sampler`
+    pub async fn with_sampler(
+        mut processor: P,
+        sender: CollectingSender<M>,
+        sampler: SA,
+    ) -> Self {
+        let sender = Arc::new(sender);
+        let sampler = Arc::new(sampler);
+        let handle = processor.setup(sampler.clone(), sender.clone()).await;
+
+        Self {
+            processor: Arc::new(processor),
+            sender,
+            sampler,
+            handle,
+            _i: PhantomData,
+            _o: PhantomData,
+        }
+    }
+
+    /// Have the `Processor` process `message` from `peer`
+    pub async fn deliver_from(
+        &self,
+        peer: PublicKey,
+        message: M,
+    ) -> Result<(), P::Error> {
+        self.processor
+            .process(message, peer, self.sender.clone())
+            .await
+    }
+
+    /// Drop the connection to `peer` and notify the `Processor`, the way a
+    /// `SystemManager` does when a connection is lost
+    pub async fn disconnect(&self, peer: PublicKey) {
+        self.sender.remove_connection(&peer).await;
+        self.processor
+            .disconnect(peer, self.sender.clone(), self.sampler.clone())
+            .await;
+    }
+
+    /// Have the `Processor` collect garbage
+    pub async fn gc(&self) {
+        self.processor.garbage_collection().await;
+    }
+
+    /// Take the messages sent by the `Processor` since the last call,
+    /// grouped by destination in the order they were sent
+    pub async fn sent_messages(&self) -> BTreeMap<PublicKey, Vec<M>> {
+        let mut sent = BTreeMap::<_, Vec<_>>::new();
+
+        for (to, message) in self.sender.drain().await {
+            sent.entry(to).or_default().push(message);
+        }
+
+        sent
+    }
+
+    /// Get the `Handle` returned by the `Processor` upon setup
+    pub fn handle(&mut self) -> &mut P::Handle {
+        &mut self.handle
+    }
+
+    /// Get the `Processor` being tested
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    /// Get the `CollectingSender` given to the `Processor`
+    pub fn sender(&self) -> &Arc<CollectingSender<M>> {
+        &self.sender
+    }
+
+    /// Play a timeline of `events` in order, returning the messages sent by
+    /// the `Processor` meanwhile as `(destination, message)` pairs. Stops at
+    /// the first message that fails to be processed.
+    pub async fn play(
+        &self,
+        events: impl IntoIterator<Item = Scripted<M>>,
+    ) -> Result<Vec<(PublicKey, M)>, P::Error> {
+        self.sender.drain().await;
+
+        for event in events {
+            match event {
+                Scripted::Receive(from, message) => {
+                    self.deliver_from(from, message).await?
+                }
+                Scripted::Disconnect(peer) => self.disconnect(peer).await,
+                Scripted::Collect => self.gc().await,
+                Scripted::Advance(duration) => time::advance(duration).await,
+            }
+        }
+
+        Ok(self.sender.drain().await)
+    }
+
+    /// Play a timeline of `events` and check that the `Processor` sent
+    /// exactly the `expected` messages, in order
+    pub async fn assert_transcript(
+        &self,
+        events: impl IntoIterator<Item = Scripted<M>>,
+        expected: &[(PublicKey, M)],
+    ) where
+        M: PartialEq,
+        P::Error: std::fmt::Display,
+    {
+        match self.play(events).await {
+            Ok(transcript) => {
+                assert_eq!(transcript, expected, "unexpected transcript")
+            }
+            Err(e) => panic!("processing failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use snafu::{ResultExt, Snafu};
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::{
+        async_trait,
+        system::{Handle, SenderError},
+        test::keyset,
+    };
+
+    #[derive(Debug, Snafu)]
+    enum EchoError {
+        #[snafu(display("failed to echo: {}", source))]
+        Reply { source: SenderError },
+    }
+
+    /// A `Processor` that echoes messages back and remembers when it was
+    /// last contacted by each peer, forgetting peers idle for a second
+    #[derive(Default)]
+    struct Echo {
+        seen: Mutex<Vec<(PublicKey, Instant)>>,
+        disconnected: Mutex<Vec<PublicKey>>,
+    }
+
+    #[derive(Clone)]
+    struct EchoHandle;
+
+    #[async_trait]
+    impl Handle<usize, usize> for EchoHandle {
+        type Error = EchoError;
+
+        async fn deliver(&mut self) -> Result<usize, Self::Error> {
+            unreachable!()
+        }
+
+        async fn try_deliver(&mut self) -> Result<Option<usize>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn broadcast(&mut self, _: &usize) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+    }
+
+    #[async_trait]
+    impl<S: Sender<usize> + 'static> Processor<usize, usize, usize, S> for Echo {
+        type Handle = EchoHandle;
+
+        type Error = EchoError;
+
+        async fn process(
+            &self,
+            message: usize,
+            from: PublicKey,
+            sender: Arc<S>,
+        ) -> Result<(), Self::Error> {
+            self.seen.lock().unwrap().push((from, Instant::now()));
+
+            sender.send(message + 1, &from).await.context(Reply)
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _: Arc<SA>,
+            _: Arc<S>,
+        ) -> Self::Handle {
+            EchoHandle
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            peer: PublicKey,
+            _: Arc<S>,
+            _: Arc<SA>,
+        ) {
+            self.disconnected.lock().unwrap().push(peer);
+        }
+
+        async fn garbage_collection(&self) {
+            self.seen
+                .lock()
+                .unwrap()
+                .retain(|(_, at)| at.elapsed() < Duration::from_secs(1));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scripted_timeline() {
+        let keys = keyset(2).collect::<Vec<_>>();
+        let (alice, bob) = (keys[0], keys[1]);
+        let tester =
+            ProcessorTester::new(Echo::default(), CollectingSender::new(keys))
+                .await;
+
+        tester
+            .assert_transcript(
+                vec![
+                    Scripted::Receive(alice, 1),
+                    Scripted::Advance(Duration::from_secs(2)),
+                    Scripted::Receive(bob, 10),
+                    Scripted::Collect,
+                    Scripted::Receive(alice, 2),
+                    Scripted::Disconnect(bob),
+                ],
+                &[(alice, 2), (bob, 11), (alice, 3)],
+            )
+            .await;
+
+        let seen = tester.processor().seen.lock().unwrap().clone();
+
+        assert_eq!(
+            seen.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            vec![bob, alice],
+            "idle peer not collected"
+        );
+        assert_eq!(*tester.processor().disconnected.lock().unwrap(), [bob]);
+
+        tester
+            .deliver_from(bob, 20)
+            .await
+            .expect_err("echoed to disconnected peer");
+
+        assert!(tester.sent_messages().await.is_empty(), "sent after error");
+    }
+
+    #[tokio::test]
+    async fn grouped_by_destination() {
+        let keys = keyset(2).collect::<Vec<_>>();
+        let (alice, bob) = (keys[0], keys[1]);
+        let mut tester =
+            ProcessorTester::new(Echo::default(), CollectingSender::new(keys))
+                .await;
+
+        for (from, message) in [(alice, 1), (bob, 5), (alice, 3)] {
+            tester
+                .deliver_from(from, message)
+                .await
+                .expect("echo failed");
+        }
+
+        let sent = tester.sent_messages().await;
+
+        assert_eq!(sent.get(&alice), Some(&vec![2, 4]));
+        assert_eq!(sent.get(&bob), Some(&vec![6]));
+        assert!(tester.sent_messages().await.is_empty(), "not drained");
+        assert_eq!(tester.handle().try_deliver().await.unwrap(), None);
+    }
+}
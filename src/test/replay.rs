@@ -0,0 +1,456 @@
+use std::{collections::BTreeSet, fmt, marker::PhantomData, time::Duration};
+
+use bincode::Options;
+use snafu::{ResultExt, Snafu};
+use tokio::time;
+use tracing::warn;
+
+use super::ProcessorTester;
+use crate::{
+    codec::bincode_options,
+    crypto::{key::exchange::PublicKey, BincodeError},
+    system::{CollectingSender, Direction, Processor, ReplayEntry},
+    Message,
+};
+
+/// The first outbound message of a replay that differs from the recording
+#[derive(Debug)]
+pub struct Divergence {
+    index: usize,
+    cause: Option<ReplayEntry>,
+    expected: Option<(ReplayEntry, String)>,
+    actual: Option<(PublicKey, String)>,
+}
+
+impl Divergence {
+    /// Position of the differing message among outbound messages
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The recorded message, if the recording has that many outbound messages
+    pub fn expected(&self) -> Option<&ReplayEntry> {
+        self.expected.as_ref().map(|(entry, _)| entry)
+    }
+
+    /// The destination of the replayed message, if the replay sent that many
+    /// messages
+    pub fn actual(&self) -> Option<&PublicKey> {
+        self.actual.as_ref().map(|(peer, _)| peer)
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "outbound message {} diverged", self.index)?;
+
+        if let Some(cause) = &self.cause {
+            writeln!(f, "  while replaying {}", cause)?;
+        }
+
+        match &self.expected {
+            Some((entry, message)) => {
+                writeln!(f, "  expected {}: {}", entry, message)?
+            }
+            None => writeln!(f, "  expected nothing")?,
+        }
+
+        match &self.actual {
+            Some((peer, message)) => write!(
+                f,
+                "  got message to {}: {}",
+                peer.fingerprint(),
+                message
+            ),
+            None => write!(f, "  got nothing"),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+/// Errors encountered when replaying a recorded run
+pub enum ReplayError {
+    #[snafu(display("undecodable message #{}: {}", sequence, source))]
+    /// A recorded message could not be decoded
+    Undecodable {
+        /// Sequence number of the message
+        sequence: u64,
+        /// Error source
+        source: BincodeError,
+    },
+    #[snafu(display("{}", divergence))]
+    /// The replayed `Processor` did not send the recorded messages
+    Diverged {
+        /// The first differing message
+        divergence: Box<Divergence>,
+    },
+}
+
+/// Runs a fresh `Processor` through the inbound messages of a log recorded
+/// by a `ReplayRecorder`, without any network. The clock is advanced to the
+/// time at which each message was recorded, which requires time to be
+/// paused.
+pub struct ReplayRunner<P, M> {
+    processor: P,
+    entries: Vec<ReplayEntry>,
+    _m: PhantomData<M>,
+}
+
+impl<P, M> ReplayRunner<P, M>
+where
+    M: Message + 'static,
+{
+    /// Prepare replaying the recorded `entries` with `processor`
+    pub fn new(processor: P, entries: Vec<ReplayEntry>) -> Self {
+        Self {
+            processor,
+            entries,
+            _m: PhantomData,
+        }
+    }
+
+    /// Replay the recording, returning the messages sent by the `Processor`
+    /// as `(destination, message)` pairs
+    pub async fn run<I, O>(self) -> Result<Vec<(PublicKey, M)>, ReplayError>
+    where
+        I: Into<M>,
+        O: Send,
+        P: Processor<M, I, O, CollectingSender<M>>,
+    {
+        self.replay(false).await
+    }
+
+    /// Replay the recording and check that the `Processor` sends the same
+    /// messages as recorded, byte for byte and in the same order
+    pub async fn verify<I, O>(self) -> Result<(), ReplayError>
+    where
+        I: Into<M>,
+        O: Send,
+        P: Processor<M, I, O, CollectingSender<M>>,
+    {
+        self.replay(true).await.map(|_| ())
+    }
+
+    async fn replay<I, O>(
+        self,
+        verify: bool,
+    ) -> Result<Vec<(PublicKey, M)>, ReplayError>
+    where
+        I: Into<M>,
+        O: Send,
+        P: Processor<M, I, O, CollectingSender<M>>,
+    {
+        let peers = self
+            .entries
+            .iter()
+            .map(|entry| *entry.peer())
+            .collect::<BTreeSet<_>>();
+        let (inbound, outbound): (Vec<_>, Vec<_>) = self
+            .entries
+            .into_iter()
+            .partition(|entry| entry.direction() == Direction::Inbound);
+        let tester =
+            ProcessorTester::new(self.processor, CollectingSender::new(peers))
+                .await;
+        let mut now = Duration::ZERO;
+        let mut transcript = Vec::new();
+
+        for entry in inbound.iter() {
+            if entry.elapsed() > now {
+                time::advance(entry.elapsed() - now).await;
+                now = entry.elapsed();
+            }
+
+            let message = entry.decode::<M>().context(Undecodable {
+                sequence: entry.sequence(),
+            })?;
+
+            if let Err(e) = tester.deliver_from(*entry.peer(), message).await {
+                warn!("failed to replay message #{}: {}", entry.sequence(), e);
+            }
+
+            for (peer, message) in tester.sender().drain().await {
+                let index = transcript.len();
+
+                if verify {
+                    check(index, entry, outbound.get(index), &peer, &message)?;
+                }
+
+                transcript.push((peer, message));
+            }
+        }
+
+        if verify && transcript.len() < outbound.len() {
+            let index = transcript.len();
+            let expected = &outbound[index];
+
+            return Diverged {
+                divergence: Box::new(Divergence {
+                    index,
+                    cause: inbound.last().cloned(),
+                    expected: Some((expected.clone(), render::<M>(expected))),
+                    actual: None,
+                }),
+            }
+            .fail();
+        }
+
+        Ok(transcript)
+    }
+}
+
+/// Check that `message` sent to `peer` matches the `expected` recording
+fn check<M: Message>(
+    index: usize,
+    cause: &ReplayEntry,
+    expected: Option<&ReplayEntry>,
+    peer: &PublicKey,
+    message: &M,
+) -> Result<(), ReplayError> {
+    let payload = bincode_options().serialize(message).ok();
+    let matches = expected.is_some_and(|expected| {
+        expected.peer() == peer
+            && payload.as_deref() == Some(expected.payload())
+    });
+
+    if matches {
+        return Ok(());
+    }
+
+    Diverged {
+        divergence: Box::new(Divergence {
+            index,
+            cause: Some(cause.clone()),
+            expected: expected.map(|entry| (entry.clone(), render::<M>(entry))),
+            actual: Some((*peer, format!("{:?}", message))),
+        }),
+    }
+    .fail()
+}
+
+/// Render a recorded message for a `Divergence`
+fn render<M: Message>(entry: &ReplayEntry) -> String {
+    match entry.decode::<M>() {
+        Ok(message) => format!("{:?}", message),
+        Err(e) => format!("undecodable ({})", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::*;
+    use crate::{
+        async_trait,
+        crypto::key::exchange::Exchanger,
+        net::{Connector, TcpConnector, TcpListener},
+        system::{
+            open_replay_log, AllSampler, Handle, ManagerConfig, NetworkSender,
+            ReplayRecorder, Sampler, Sender, SenderError, System, SystemHandle,
+            SystemManager,
+        },
+        test::*,
+    };
+
+    /// Value at which the exchange between two `Counter`s stops
+    const LIMIT: usize = 20;
+
+    /// Replies to each number with the next one until reaching `LIMIT`, or
+    /// with a number `step` above if set to diverge from the recording
+    struct Counter {
+        step: usize,
+        done: Option<mpsc::Sender<usize>>,
+    }
+
+    impl Counter {
+        fn new(step: usize) -> Self {
+            Self { step, done: None }
+        }
+    }
+
+    struct CounterHandle<S> {
+        sender: Arc<S>,
+        done: Arc<Mutex<mpsc::Receiver<usize>>>,
+    }
+
+    impl<S> Clone for CounterHandle<S> {
+        fn clone(&self) -> Self {
+            Self {
+                sender: self.sender.clone(),
+                done: self.done.clone(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<S: Sender<usize> + 'static> Handle<usize, usize> for CounterHandle<S> {
+        type Error = SenderError;
+
+        async fn deliver(&mut self) -> Result<usize, Self::Error> {
+            Ok(self.done.lock().await.recv().await.expect("no count"))
+        }
+
+        async fn try_deliver(&mut self) -> Result<Option<usize>, Self::Error> {
+            Ok(self.done.lock().await.try_recv().ok())
+        }
+
+        async fn broadcast(
+            &mut self,
+            start: &usize,
+        ) -> Result<(), Self::Error> {
+            let keys = self.sender.keys().await;
+
+            self.sender.send_many_collecting(*start, keys.iter()).await
+        }
+    }
+
+    #[async_trait]
+    impl<S: Sender<usize> + 'static> Processor<usize, usize, usize, S> for Counter {
+        type Handle = CounterHandle<S>;
+
+        type Error = SenderError;
+
+        async fn process(
+            &self,
+            message: usize,
+            from: PublicKey,
+            sender: Arc<S>,
+        ) -> Result<(), Self::Error> {
+            if message >= LIMIT {
+                let done = self.done.as_ref().expect("not setup");
+                let _ = done.send(message).await;
+
+                return Ok(());
+            }
+
+            sender.send(message + self.step, &from).await
+        }
+
+        async fn setup<SA: Sampler>(
+            &mut self,
+            _: Arc<SA>,
+            sender: Arc<S>,
+        ) -> Self::Handle {
+            let (tx, rx) = mpsc::channel(1);
+
+            self.done = Some(tx);
+
+            CounterHandle {
+                sender,
+                done: Arc::new(Mutex::new(rx)),
+            }
+        }
+
+        async fn disconnect<SA: Sampler>(
+            &self,
+            _: PublicKey,
+            _: Arc<S>,
+            _: Arc<SA>,
+        ) {
+        }
+
+        async fn garbage_collection(&self) {}
+    }
+
+    type CounterNode =
+        SystemHandle<Counter, NetworkSender<usize>, usize, usize, usize>;
+
+    async fn counter_node(
+        exchanger: Exchanger,
+        addr: SocketAddr,
+        config: ManagerConfig,
+    ) -> CounterNode {
+        let mut system = System::default();
+        let listener = TcpListener::new(addr, exchanger)
+            .await
+            .expect("listen failed");
+
+        let _ = system.add_listener(listener).await;
+
+        SystemManager::with_config(system, config)
+            .run(Counter::new(1), AllSampler::default(), 1)
+            .await
+    }
+
+    /// Record the exchange between two `Counter`s on alice's side
+    async fn record_exchange() -> Vec<ReplayEntry> {
+        let path = std::env::temp_dir()
+            .join(format!("drop-replay-{}", std::process::id()));
+        let recorder = ReplayRecorder::to_path(&path).expect("create failed");
+        let config = ManagerConfig::default().record(recorder.clone());
+        let (alice, bob) = (Exchanger::random(), Exchanger::random());
+        let bob_key = *bob.keypair().public();
+        let bob_addr = next_test_ip4();
+        let alice_handle =
+            counter_node(alice.clone(), next_test_ip4(), config).await;
+        let bob_handle =
+            counter_node(bob, bob_addr, ManagerConfig::default()).await;
+
+        let connection = TcpConnector::new(alice)
+            .connect(&bob_key, &bob_addr)
+            .await
+            .expect("connect failed");
+
+        alice_handle
+            .add_connection(connection)
+            .await
+            .expect("failed to add connection");
+        bob_handle
+            .wait_for_peers(1, None)
+            .await
+            .expect("manager stopped");
+
+        bob_handle
+            .processor_handle()
+            .broadcast(&0)
+            .await
+            .expect("broadcast failed");
+
+        let last = alice_handle
+            .processor_handle()
+            .deliver()
+            .await
+            .expect("no count");
+
+        assert_eq!(last, LIMIT, "exchange ended early");
+
+        recorder.flush().expect("flush failed");
+
+        let entries = open_replay_log(&path).expect("bad log");
+        let _ = std::fs::remove_file(path);
+
+        entries
+    }
+
+    #[tokio::test]
+    async fn replay_matches_recording() {
+        init_logger();
+
+        let entries = record_exchange().await;
+
+        assert_eq!(entries.len(), LIMIT + 1, "wrong number of entries");
+
+        time::pause();
+
+        ReplayRunner::new(Counter::new(1), entries.clone())
+            .verify()
+            .await
+            .expect("replay diverged");
+
+        let error = ReplayRunner::new(Counter::new(2), entries)
+            .verify()
+            .await
+            .expect_err("diverging replay verified");
+
+        match error {
+            ReplayError::Diverged { divergence } => {
+                assert_eq!(divergence.index(), 0, "wrong divergence");
+                assert!(divergence.expected().is_some(), "nothing expected");
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+}
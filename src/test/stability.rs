@@ -0,0 +1,477 @@
+//! Every type that drop puts on the wire has its encoding recorded in a
+//! fixture under `fixtures/types`, checked using [`assert_wire_stable`].
+//! Changing the encoding of one of these types breaks compatibility with
+//! peers running older versions, so a failing check must either be fixed or
+//! the fixture regenerated on purpose by running the tests with
+//! `DROP_RECORD_FIXTURES` set. New types sent on the wire should get their
+//! own fixture along with a sample that covers all of their variants.
+//!
+//! [`assert_wire_stable`]: crate::assert_wire_stable
+
+use std::{env, fmt, fs, path::Path};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::RECORD_FIXTURES;
+use crate::codec::bincode_options;
+
+/// Number of bytes displayed on each line of a [`byte_diff`]
+///
+/// [`byte_diff`]: self::byte_diff
+const DIFF_WIDTH: usize = 16;
+
+/// Maximum number of differing lines displayed by a [`byte_diff`]
+///
+/// [`byte_diff`]: self::byte_diff
+const DIFF_LINES: usize = 8;
+
+/// Check that `sample` is encoded exactly as the fixture at `path` using the
+/// canonical bincode options and that decoding the fixture yields `sample`.
+/// See [`check_wire_stable`] for details.
+///
+/// [`check_wire_stable`]: crate::test::check_wire_stable
+#[macro_export]
+macro_rules! assert_wire_stable {
+    ($type:ty, $path:expr, $sample:expr) => {
+        $crate::test::check_wire_stable::<$type, _>(
+            stringify!($type),
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($path),
+            &$sample,
+        )
+    };
+}
+
+/// Check that `sample` of the type called `name` is encoded as the fixture
+/// at `path` and decoded back from it, panicking with a readable diff
+/// otherwise. If the `DROP_RECORD_FIXTURES` environment variable is set the
+/// fixture is written instead.
+pub fn check_wire_stable<T, P>(name: &str, path: P, sample: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let encoded = bincode_options()
+        .serialize(sample)
+        .unwrap_or_else(|e| panic!("failed to encode {}: {}", name, e));
+
+    if env::var_os(RECORD_FIXTURES).is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create fixture dir");
+        }
+
+        fs::write(path, &encoded).expect("failed to write fixture");
+        return;
+    }
+
+    let fixture = fs::read(path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read fixture {} for {}: {}, set {} to record it",
+            path.display(),
+            name,
+            e,
+            RECORD_FIXTURES
+        )
+    });
+
+    assert!(
+        fixture == encoded,
+        "encoding of {} changed, set {} to regenerate {} if this is \
+         intentional\n{}",
+        name,
+        RECORD_FIXTURES,
+        path.display(),
+        byte_diff(&fixture, &encoded)
+    );
+
+    let decoded: T = bincode_options()
+        .deserialize(&fixture)
+        .unwrap_or_else(|e| panic!("failed to decode {}: {}", name, e));
+
+    assert_eq!(&decoded, sample, "{} decoded to a different value", name);
+}
+
+/// Describe the differences between `expected` and `actual` by displaying
+/// the lines of their hex dumps that differ, marking differing bytes with
+/// `^`
+pub fn byte_diff(expected: &[u8], actual: &[u8]) -> String {
+    let mut output = format!(
+        "expected {} bytes, got {} bytes",
+        expected.len(),
+        actual.len()
+    );
+
+    let first = match expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .or_else(|| {
+            (expected.len() != actual.len())
+                .then_some(expected.len().min(actual.len()))
+        }) {
+        Some(first) => first,
+        None => return output,
+    };
+
+    output.push_str(&format!(", first difference at offset {}\n", first));
+
+    let lines = expected.len().max(actual.len()).div_ceil(DIFF_WIDTH);
+    let differing = (0..lines)
+        .map(|line| line * DIFF_WIDTH)
+        .filter(|start| line_of(expected, *start) != line_of(actual, *start))
+        .collect::<Vec<_>>();
+
+    for start in differing.iter().take(DIFF_LINES) {
+        let expected = line_of(expected, *start);
+        let actual = line_of(actual, *start);
+        let markers = (0..DIFF_WIDTH)
+            .map(|i| {
+                if expected.get(i) == actual.get(i) {
+                    "   "
+                } else {
+                    "^^ "
+                }
+            })
+            .collect::<String>();
+
+        output.push_str(&format!("{:08x} - {}\n", start, hex_line(expected)));
+        output.push_str(&format!("{:08x} + {}\n", start, hex_line(actual)));
+        output.push_str(&format!("           {}\n", markers.trim_end()));
+    }
+
+    if differing.len() > DIFF_LINES {
+        output.push_str(&format!(
+            "... {} more differing lines\n",
+            differing.len() - DIFF_LINES
+        ));
+    }
+
+    output
+}
+
+fn line_of(bytes: &[u8], start: usize) -> &[u8] {
+    bytes
+        .get(start..bytes.len().min(start + DIFF_WIDTH))
+        .unwrap_or_default()
+}
+
+fn hex_line(bytes: &[u8]) -> String {
+    (0..DIFF_WIDTH)
+        .map(|i| {
+            bytes
+                .get(i)
+                .map_or_else(|| "..".to_string(), |b| format!("{:02x}", b))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::{
+        crypto::{
+            hash::{hash, Digest},
+            key::exchange::{self, KeyPair},
+            keys::KeyBundle,
+            sign::{self, Signature},
+        },
+        data::syncset::{Entry, Path, Set, Versioned},
+        net::{
+            common::directory::{
+                features, Hello, Info, Request, Response, SignedInfo,
+            },
+            ContactCard, Decision, Envelope,
+        },
+        system::Acked,
+        test::{acceptor, dialer},
+    };
+
+    /// Seed of the signing `KeyPair` used in samples
+    const SIGN_SEED: [u8; 32] = [3; 32];
+
+    /// Expiry used by signed samples
+    const EXPIRY: Duration = Duration::from_secs(1_700_000_000);
+
+    fn fixture(name: &str) -> String {
+        format!("fixtures/types/{}.bin", name)
+    }
+
+    fn keypair() -> KeyPair {
+        dialer().keypair().clone()
+    }
+
+    fn pkey() -> exchange::PublicKey {
+        *acceptor().keypair().public()
+    }
+
+    fn signer() -> sign::KeyPair {
+        sign::PrivateKey::new(SIGN_SEED)
+            .expect("invalid seed")
+            .into()
+    }
+
+    fn v4() -> SocketAddr {
+        (Ipv4Addr::new(192, 0, 2, 1), 9600).into()
+    }
+
+    fn v6() -> SocketAddr {
+        (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 9601).into()
+    }
+
+    fn signed_info() -> SignedInfo {
+        SignedInfo::new(&keypair(), v4(), UNIX_EPOCH + EXPIRY)
+            .expect("signing failed")
+    }
+
+    #[test]
+    fn diff_marks_changes() {
+        let diff = byte_diff(&[0, 1, 2, 3], &[0, 1, 9, 3, 4]);
+
+        assert!(diff.starts_with("expected 4 bytes, got 5 bytes"));
+        assert!(diff.contains("first difference at offset 2"));
+        assert!(diff.contains("00000000 - 00 01 02 03 .."));
+        assert!(diff.contains("00000000 + 00 01 09 03 04"));
+        assert!(diff.contains("      ^^    ^^"));
+        assert_eq!(
+            byte_diff(&[1, 2], &[1, 2]),
+            "expected 2 bytes, got 2 bytes"
+        );
+
+        let long = byte_diff(&[0; 200], &[1; 200]);
+
+        assert!(long.contains("... 5 more differing lines"), "{}", long);
+    }
+
+    #[test]
+    fn primitives_are_stable() {
+        // size prefix of every frame
+        assert_wire_stable!(u32, fixture("frame_size"), 0x0102_0304u32);
+        assert_wire_stable!(
+            Vec<SocketAddr>,
+            fixture("socket_addr"),
+            vec![v4(), v6()]
+        );
+    }
+
+    #[test]
+    fn keys_are_stable() {
+        assert_wire_stable!(
+            exchange::PublicKey,
+            fixture("exchange_key"),
+            pkey()
+        );
+        assert_wire_stable!(
+            sign::PublicKey,
+            fixture("sign_key"),
+            signer().public()
+        );
+        assert_wire_stable!(
+            Signature,
+            fixture("signature"),
+            signer().sign(&0u64).expect("signing failed")
+        );
+        assert_wire_stable!(
+            Digest,
+            fixture("digest"),
+            hash(&0u64).expect("hashing failed")
+        );
+    }
+
+    #[cfg(not(feature = "blst"))]
+    #[test]
+    fn key_bundle_is_stable() {
+        assert_wire_stable!(
+            KeyBundle,
+            fixture("key_bundle"),
+            KeyBundle::new(pkey(), signer().public())
+        );
+    }
+
+    #[cfg(feature = "blst")]
+    #[test]
+    fn key_bundle_is_stable() {
+        assert_wire_stable!(
+            KeyBundle,
+            fixture("key_bundle_bls"),
+            KeyBundle::new(pkey(), signer().public())
+        );
+    }
+
+    #[test]
+    fn directory_is_stable() {
+        assert_wire_stable!(
+            Hello,
+            fixture("hello"),
+            Hello {
+                version: 1,
+                features: features::SIGNED | features::REMOVE,
+            }
+        );
+        assert_wire_stable!(Info, fixture("info"), Info::from((pkey(), v6())));
+        assert_wire_stable!(SignedInfo, fixture("signed_info"), signed_info());
+        assert_wire_stable!(
+            Vec<Request>,
+            fixture("request"),
+            vec![
+                Request::Add(Info::from((pkey(), v4()))),
+                Request::Fetch(pkey()),
+                Request::Wait(3),
+                Request::AddSigned(signed_info()),
+                Request::Remove(pkey()),
+                Request::ProbeMe(v6()),
+                Request::WaitPaged {
+                    count: 3,
+                    page_size: 256,
+                    after: Some(pkey()),
+                },
+            ]
+        );
+        assert_wire_stable!(
+            Vec<Response>,
+            fixture("response"),
+            vec![
+                Response::Ok,
+                Response::FoundOne(pkey(), v4()),
+                Response::NotFound(pkey()),
+                Response::FoundSigned(signed_info()),
+                Response::Error("unknown".into()),
+                Response::Probed {
+                    addr: v4(),
+                    reachable: true,
+                },
+                Response::Conflict(v6()),
+                Response::Page(vec![Info::from((pkey(), v4()))]),
+            ]
+        );
+        assert_wire_stable!(
+            Response,
+            fixture("response_found"),
+            Response::Found(pkey(), vec![v4(), v6()])
+        );
+        assert_wire_stable!(
+            Request,
+            fixture("request_renew"),
+            Request::Renew(pkey())
+        );
+        assert_wire_stable!(
+            Response,
+            fixture("response_expired"),
+            Response::Expired(pkey())
+        );
+
+        let card = ContactCard::new(*keypair().public(), vec![v4(), v6()])
+            .with_expiry(UNIX_EPOCH + EXPIRY)
+            .sign(&keypair())
+            .expect("signing failed");
+
+        assert_wire_stable!(
+            Request,
+            fixture("request_add_card"),
+            Request::AddCard(card.clone())
+        );
+        assert_wire_stable!(
+            Response,
+            fixture("response_found_card"),
+            Response::FoundCard(card)
+        );
+    }
+
+    #[test]
+    fn contact_card_is_stable() {
+        let card = ContactCard::new(*keypair().public(), vec![v4(), v6()])
+            .with_version(1)
+            .with_expiry(UNIX_EPOCH + EXPIRY);
+
+        assert_wire_stable!(ContactCard, fixture("contact_card"), card.clone());
+        assert_wire_stable!(
+            ContactCard,
+            fixture("signed_contact_card"),
+            card.sign(&keypair()).expect("signing failed")
+        );
+    }
+
+    #[test]
+    fn decision_is_stable() {
+        assert_wire_stable!(
+            Vec<Decision>,
+            fixture("decision"),
+            vec![
+                Decision::Accept,
+                Decision::reject("banned"),
+                Decision::AcceptReadOnly,
+            ]
+        );
+    }
+
+    #[test]
+    fn fragment_envelope_is_stable() {
+        assert_wire_stable!(
+            Vec<Envelope>,
+            fixture("fragment_envelope"),
+            vec![
+                Envelope::Whole,
+                Envelope::Fragment {
+                    id: u64::MAX,
+                    index: 1,
+                    total: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_view_is_stable() {
+        let path = Path::new(&0u32).expect("hashing failed");
+
+        assert_wire_stable!(
+            Vec<Set<u32>>,
+            fixture("sync_view"),
+            vec![
+                Set::LabelSet {
+                    prefix: path.prefix(3),
+                    label: hash(&1u32).expect("hashing failed"),
+                },
+                Set::ListSet {
+                    underlying: vec![0, 1],
+                    prefix: path.prefix(9),
+                    dump: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_map_entry_is_stable() {
+        assert_wire_stable!(
+            Entry<u32, Versioned<u64>>,
+            fixture("sync_map_entry"),
+            Entry {
+                key: 7,
+                value: Versioned::new(u64::MAX, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn acked_is_stable() {
+        assert_wire_stable!(
+            Vec<Acked<u64>>,
+            fixture("acked"),
+            vec![
+                Acked::Plain(0),
+                Acked::Data {
+                    id: u64::MAX,
+                    message: 1,
+                },
+                Acked::Ack(42),
+            ]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    env, fmt, fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    crypto::key::exchange::{Exchanger, PublicKey},
+    net::{Connection, Socket},
+};
+
+/// Environment variable that makes `assert_fixture` (re)write fixture files
+/// instead of comparing against them
+pub const RECORD_FIXTURES: &str = "DROP_RECORD_FIXTURES";
+
+/// Seed used to derive the `Exchanger` of the dialing side in fixtures
+pub const DIALER_SEED: [u8; 32] = [1; 32];
+
+/// Seed used to derive the `Exchanger` of the accepting side in fixtures
+pub const ACCEPTOR_SEED: [u8; 32] = [2; 32];
+
+/// Seed used for the stream header of both sides in fixtures
+pub const STREAM_SEED: u64 = 0x64726f70;
+
+/// An in-memory `Socket` that replays a fixed input and records everything
+/// written to it
+pub struct WireSocket {
+    input: Vec<u8>,
+    position: usize,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl WireSocket {
+    /// Create a new `WireSocket` that will read from `input`. The returned
+    /// buffer receives everything written to the socket.
+    pub fn new(input: Vec<u8>) -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        (
+            Self {
+                input,
+                position: 0,
+                output: output.clone(),
+            },
+            output,
+        )
+    }
+}
+
+impl AsyncRead for WireSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let remaining = &self.input[self.position..];
+        let len = remaining.len().min(buf.remaining());
+
+        buf.put_slice(&remaining[..len]);
+        self.position += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WireSocket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.output
+            .lock()
+            .expect("output poisoned")
+            .extend_from_slice(buf);
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Socket for WireSocket {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    fn transport(&self) -> &'static str {
+        "wire"
+    }
+}
+
+/// The deterministic `Exchanger` of the dialing side in fixtures
+pub fn dialer() -> Exchanger {
+    Exchanger::from_seed(DIALER_SEED)
+}
+
+/// The deterministic `Exchanger` of the accepting side in fixtures
+pub fn acceptor() -> Exchanger {
+    Exchanger::from_seed(ACCEPTOR_SEED)
+}
+
+/// Record the bytes sent by the dialing side of a `Connection` that secures
+/// itself and then sends all of `messages` in order
+pub async fn record_dialer<T>(
+    local: &Exchanger,
+    remote: &PublicKey,
+    messages: &[T],
+) -> Vec<u8>
+where
+    T: Serialize + Send + Sync + fmt::Debug,
+{
+    let (socket, output) = WireSocket::new(Vec::new());
+    let mut connection = Connection::new(Box::new(socket));
+
+    connection
+        .secure_as_dialer(local, remote)
+        .await
+        .expect("handshake failed");
+    connection.set_stream_seed(STREAM_SEED);
+
+    for message in messages {
+        connection.send(message).await.expect("send failed");
+    }
+
+    let output = output.lock().expect("output poisoned").clone();
+
+    output
+}
+
+/// Record the bytes sent by the accepting side of a `Connection` that
+/// secures itself using the dialer's handshake in `handshake` and then sends
+/// all of `messages` in order. The handshake itself is not included in the
+/// output.
+pub async fn record_acceptor<T>(
+    local: &Exchanger,
+    handshake: &[u8],
+    messages: &[T],
+) -> Vec<u8>
+where
+    T: Serialize + Send + Sync + fmt::Debug,
+{
+    let (socket, output) = WireSocket::new(handshake.to_vec());
+    let mut connection = Connection::new(Box::new(socket));
+
+    connection
+        .secure_as_acceptor(local)
+        .await
+        .expect("handshake failed");
+    connection.set_stream_seed(STREAM_SEED);
+
+    for message in messages {
+        connection.send(message).await.expect("send failed");
+    }
+
+    let output = output.lock().expect("output poisoned").clone();
+
+    output
+}
+
+/// Feed bytes recorded from a dialer to an accepting `Connection` and decode
+/// `count` messages of type `T` from them. Returns the `PublicKey` announced
+/// by the dialer along with the decoded messages.
+pub async fn replay_as_acceptor<T>(
+    local: &Exchanger,
+    bytes: &[u8],
+    count: usize,
+) -> (PublicKey, Vec<T>)
+where
+    T: for<'de> Deserialize<'de> + Send + fmt::Debug,
+{
+    let (socket, _) = WireSocket::new(bytes.to_vec());
+    let mut connection = Connection::new(Box::new(socket));
+
+    connection
+        .secure_as_acceptor(local)
+        .await
+        .expect("handshake failed");
+
+    let remote = connection.remote_key().expect("no remote key");
+    let mut messages = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        messages.push(connection.receive().await.expect("receive failed"));
+    }
+
+    (remote, messages)
+}
+
+/// Feed bytes recorded from an acceptor to a dialing `Connection` and decode
+/// `count` messages of type `T` from them
+pub async fn replay_as_dialer<T>(
+    local: &Exchanger,
+    remote: &PublicKey,
+    bytes: &[u8],
+    count: usize,
+) -> Vec<T>
+where
+    T: for<'de> Deserialize<'de> + Send + fmt::Debug,
+{
+    let (socket, _) = WireSocket::new(bytes.to_vec());
+    let mut connection = Connection::new(Box::new(socket));
+
+    connection
+        .secure_as_dialer(local, remote)
+        .await
+        .expect("handshake failed");
+
+    let mut messages = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        messages.push(connection.receive().await.expect("receive failed"));
+    }
+
+    messages
+}
+
+/// Compare `bytes` with the content of the fixture at `path`. If the
+/// `DROP_RECORD_FIXTURES` environment variable is set the fixture is written
+/// instead, which should only be done for intentional format changes.
+pub fn assert_fixture<P: AsRef<Path>>(path: P, bytes: &[u8]) {
+    let path = path.as_ref();
+
+    if env::var_os(RECORD_FIXTURES).is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create fixture dir");
+        }
+
+        fs::write(path, bytes).expect("failed to write fixture");
+        return;
+    }
+
+    let expected = fs::read(path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read fixture {}: {}, set {} to record it",
+            path.display(),
+            e,
+            RECORD_FIXTURES
+        )
+    });
+
+    assert!(
+        expected == bytes,
+        "wire format changed for fixture {}, set {} to regenerate it if \
+         this is intentional",
+        path.display(),
+        RECORD_FIXTURES
+    );
+}
+
+/// Read a fixture recorded at `path`
+pub fn read_fixture<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    let path = path.as_ref();
+
+    fs::read(path).unwrap_or_else(|e| {
+        panic!("failed to read fixture {}: {}", path.display(), e)
+    })
+}
+
+/// List all version directories in a fixture directory, such as
+/// `fixtures/wire/0.1.0`, sorted by name
+pub fn fixture_versions<P: AsRef<Path>>(root: P) -> Vec<PathBuf> {
+    let mut versions = fs::read_dir(root.as_ref())
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    versions.sort();
+    versions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::common::directory::{Info, Request, Response};
+    use crate::system::Acked;
+
+    /// Root of the fixtures for this crate
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join("wire")
+    }
+
+    /// Fixtures for the current version of this crate
+    fn current() -> PathBuf {
+        fixtures().join(env!("CARGO_PKG_VERSION"))
+    }
+
+    fn requests() -> Vec<Request> {
+        let addr = (Ipv4Addr::LOCALHOST, 9600).into();
+
+        vec![
+            Request::Add(Info::from((*dialer().keypair().public(), addr))),
+            Request::Fetch(*acceptor().keypair().public()),
+            Request::Wait(3),
+        ]
+    }
+
+    fn responses() -> Vec<Response> {
+        let addr = (Ipv4Addr::LOCALHOST, 9601).into();
+
+        vec![
+            Response::Ok,
+            Response::FoundOne(*acceptor().keypair().public(), addr),
+            Response::NotFound(*dialer().keypair().public()),
+        ]
+    }
+
+    fn acked() -> Vec<Acked<u64>> {
+        vec![
+            Acked::Plain(0),
+            Acked::Data {
+                id: u64::MAX,
+                message: 1,
+            },
+            Acked::Ack(42),
+        ]
+    }
+
+    async fn handshake() -> Vec<u8> {
+        record_dialer::<()>(&dialer(), acceptor().keypair().public(), &[]).await
+    }
+
+    #[tokio::test]
+    async fn handshake_is_stable() {
+        assert_fixture(current().join("handshake.bin"), &handshake().await);
+    }
+
+    #[tokio::test]
+    async fn dialer_is_stable() {
+        let bytes = record_dialer(
+            &dialer(),
+            acceptor().keypair().public(),
+            &requests(),
+        )
+        .await;
+
+        assert_fixture(current().join("requests.bin"), &bytes);
+
+        let bytes =
+            record_dialer(&dialer(), acceptor().keypair().public(), &acked())
+                .await;
+
+        assert_fixture(current().join("acked.bin"), &bytes);
+    }
+
+    #[tokio::test]
+    async fn acceptor_is_stable() {
+        let bytes =
+            record_acceptor(&acceptor(), &handshake().await, &responses())
+                .await;
+
+        assert_fixture(current().join("responses.bin"), &bytes);
+    }
+
+    #[tokio::test]
+    async fn previous_versions_decode() {
+        let versions = fixture_versions(fixtures());
+        let dialer_key = *dialer().keypair().public();
+
+        assert!(!versions.is_empty(), "no recorded fixtures");
+
+        for version in versions {
+            let (remote, decoded) = replay_as_acceptor::<Request>(
+                &acceptor(),
+                &read_fixture(version.join("requests.bin")),
+                requests().len(),
+            )
+            .await;
+
+            assert_eq!(remote, dialer_key, "wrong key in {:?}", version);
+            assert_eq!(decoded, requests(), "bad requests in {:?}", version);
+
+            let (_, decoded) = replay_as_acceptor::<Acked<u64>>(
+                &acceptor(),
+                &read_fixture(version.join("acked.bin")),
+                acked().len(),
+            )
+            .await;
+
+            assert_eq!(
+                format!("{:?}", decoded),
+                format!("{:?}", acked()),
+                "bad acked messages in {:?}",
+                version
+            );
+
+            let decoded = replay_as_dialer::<Response>(
+                &dialer(),
+                acceptor().keypair().public(),
+                &read_fixture(version.join("responses.bin")),
+                responses().len(),
+            )
+            .await;
+
+            assert_eq!(decoded, responses(), "bad responses in {:?}", version);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Checks that sending and receiving small messages on a `Connection` does not
+//! allocate once its buffers are warm. This runs as its own test binary since
+//! it replaces the global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use drop::crypto::key::exchange::Exchanger;
+use drop::net::{Connection, Connector, Listener, TcpConnector, TcpListener};
+
+/// Number of messages exchanged before counting allocations, enough for the
+/// task to exhaust its tokio budget once since the runtime allocates the
+/// first time it defers a task
+const WARMUP: usize = 256;
+
+/// Number of messages exchanged while counting allocations
+const MESSAGES: usize = 1000;
+
+/// A small message without any heap allocated content
+type Small = (u64, [u8; 24]);
+
+/// Counts the allocations made by threads that enabled counting
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if COUNTING.try_with(Cell::get).unwrap_or(false) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Count the allocations made by the current thread while running `f`
+async fn allocations<F: std::future::Future<Output = ()>>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+
+    COUNTING.with(|counting| counting.set(true));
+    f.await;
+    COUNTING.with(|counting| counting.set(false));
+
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+async fn connection_pair() -> (Connection, Connection) {
+    let exchanger = Exchanger::random();
+    let pkey = *exchanger.keypair().public();
+    let any = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let mut listener = TcpListener::new(any, exchanger)
+        .await
+        .expect("listen failed");
+    let addr = listener.local_addr().expect("no address");
+    let connector = TcpConnector::new(Exchanger::random());
+
+    let (accepted, connected) =
+        futures::join!(listener.accept(), connector.connect(&pkey, &addr));
+
+    (
+        accepted.expect("accept failed"),
+        connected.expect("connect failed"),
+    )
+}
+
+fn small(i: usize) -> Small {
+    (i as u64, [i as u8; 24])
+}
+
+#[tokio::test]
+async fn connection_does_not_allocate() {
+    let (mut receiver, mut sender) = connection_pair().await;
+
+    for i in 0..WARMUP {
+        sender.send(&small(i)).await.expect("send failed");
+        receiver.receive::<Small>().await.expect("receive failed");
+    }
+
+    let count = allocations(async {
+        for i in 0..MESSAGES {
+            sender.send(&small(i)).await.expect("send failed");
+
+            let received =
+                receiver.receive::<Small>().await.expect("receive failed");
+
+            assert_eq!(received, small(i), "wrong message");
+        }
+    })
+    .await;
+
+    assert_eq!(count, 0, "allocated while sending small messages");
+}
+
+#[tokio::test]
+async fn halves_do_not_allocate() {
+    let (receiver, sender) = connection_pair().await;
+    let (mut read, _) = receiver.split().expect("not secured");
+    let (_, mut write) = sender.split().expect("not secured");
+
+    for i in 0..WARMUP {
+        write.send(&small(i)).await.expect("send failed");
+        read.receive::<Small>().await.expect("receive failed");
+    }
+
+    let count = allocations(async {
+        for i in 0..MESSAGES {
+            write.send(&small(i)).await.expect("send failed");
+
+            let received =
+                read.receive::<Small>().await.expect("receive failed");
+
+            assert_eq!(received, small(i), "wrong message");
+        }
+    })
+    .await;
+
+    assert_eq!(count, 0, "allocated while sending small messages");
+}
@@ -0,0 +1,229 @@
+//! Saves and loads a toy artifact whose format went through three versions,
+//! checking that older files are migrated and that malformed ones are
+//! refused, along with the artifacts persisted by drop itself.
+
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use bincode::Options;
+
+use drop::codec::bincode_options;
+use drop::crypto::key::exchange::KeyPair;
+use drop::net::ContactCard;
+use drop::persist::{self, Migration, PersistError, Versioned, HEADER_SIZE};
+
+use serde::{Deserialize, Serialize};
+
+/// First version of the toy artifact, only holding a port
+#[derive(Serialize, Deserialize)]
+struct NodeV1 {
+    port: u16,
+}
+
+/// Second version, which added a name
+#[derive(Serialize, Deserialize)]
+struct NodeV2 {
+    port: u16,
+    name: String,
+}
+
+/// Current version, which moved to a list of addresses
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Node {
+    name: String,
+    addrs: Vec<SocketAddr>,
+}
+
+fn v1_to_v2(payload: &[u8]) -> Result<Vec<u8>, PersistError> {
+    persist::upgrade(payload, |old: NodeV1| NodeV2 {
+        port: old.port,
+        name: String::from("unnamed"),
+    })
+}
+
+fn v2_to_v3(payload: &[u8]) -> Result<Vec<u8>, PersistError> {
+    persist::upgrade(payload, |old: NodeV2| Node {
+        name: old.name,
+        addrs: vec![(Ipv4Addr::LOCALHOST, old.port).into()],
+    })
+}
+
+impl Versioned for Node {
+    const MAGIC: [u8; 8] = *b"toy-node";
+
+    const CURRENT_VERSION: u16 = 3;
+
+    const MIGRATIONS: &'static [Migration] = &[v1_to_v2, v2_to_v3];
+}
+
+/// Encode `payload` as an artifact of the toy type with the given version
+fn artifact<T: Serialize>(version: u16, payload: &T) -> Vec<u8> {
+    let mut bytes = Node::MAGIC.to_vec();
+
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend(bincode_options().serialize(payload).expect("encode failed"));
+
+    bytes
+}
+
+fn temporary(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "drop-persist-{}-{}",
+        name,
+        KeyPair::random().public()
+    ))
+}
+
+fn local(port: u16) -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, port).into()
+}
+
+#[test]
+fn round_trip() {
+    let path = temporary("node");
+    let node = Node {
+        name: String::from("node"),
+        addrs: vec![local(4000), local(4001)],
+    };
+
+    persist::save_versioned(&path, &node).expect("save failed");
+
+    let bytes = fs::read(&path).expect("read failed");
+    let loaded: Node = persist::load_versioned(&path).expect("load failed");
+
+    fs::remove_file(&path).expect("remove failed");
+
+    assert_eq!(&bytes[..8], b"toy-node");
+    assert_eq!(&bytes[8..HEADER_SIZE], &3u16.to_le_bytes());
+    assert_eq!(loaded, node);
+}
+
+#[test]
+fn chained_migration() {
+    let v1 = artifact(1, &NodeV1 { port: 4000 });
+    let v2 = artifact(
+        2,
+        &NodeV2 {
+            port: 4001,
+            name: String::from("named"),
+        },
+    );
+
+    let from_v1: Node = persist::decode_versioned(&v1).expect("v1 refused");
+    let from_v2: Node = persist::decode_versioned(&v2).expect("v2 refused");
+
+    assert_eq!(
+        from_v1,
+        Node {
+            name: String::from("unnamed"),
+            addrs: vec![local(4000)],
+        }
+    );
+    assert_eq!(
+        from_v2,
+        Node {
+            name: String::from("named"),
+            addrs: vec![local(4001)],
+        }
+    );
+}
+
+#[test]
+fn corrupted_header() {
+    let bytes = persist::encode_versioned(&Node {
+        name: String::from("node"),
+        addrs: Vec::new(),
+    })
+    .expect("encode failed");
+
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&bytes[..HEADER_SIZE - 1]),
+        Err(PersistError::TruncatedHeader { len }) if len == HEADER_SIZE - 1
+    ));
+
+    let mut corrupted = bytes.clone();
+
+    corrupted[HEADER_SIZE] ^= 0xff;
+
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&corrupted),
+        Err(PersistError::DecodePayload { version: 3, .. })
+    ));
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&bytes[..bytes.len() - 1]),
+        Err(PersistError::DecodePayload { .. })
+    ));
+
+    // an old payload that does not match its version fails its migration
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&artifact(2, &NodeV1 { port: 1 })),
+        Err(PersistError::MigratePayload { .. })
+    ));
+}
+
+#[test]
+fn unknown_magic() {
+    let keypair = KeyPair::random();
+    let bytes = persist::encode_versioned(&keypair).expect("encode failed");
+
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&bytes),
+        Err(PersistError::UnknownMagic { magic, expected })
+            if &magic == b"drop-kpr" && expected == Node::MAGIC
+    ));
+}
+
+#[test]
+fn future_and_obsolete_versions() {
+    let node = Node {
+        name: String::from("node"),
+        addrs: Vec::new(),
+    };
+
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&artifact(4, &node)),
+        Err(PersistError::FutureVersion {
+            version: 4,
+            current: 3
+        })
+    ));
+    assert!(matches!(
+        persist::decode_versioned::<Node>(&artifact(0, &node)),
+        Err(PersistError::ObsoleteVersion {
+            version: 0,
+            oldest: 1
+        })
+    ));
+}
+
+#[test]
+fn keypair_and_contacts() {
+    let keypair = KeyPair::random();
+    let keypair_path = temporary("keypair");
+
+    keypair.save(&keypair_path).expect("save failed");
+
+    let loaded = KeyPair::load(&keypair_path).expect("load failed");
+
+    fs::remove_file(&keypair_path).expect("remove failed");
+
+    assert_eq!(loaded.public(), keypair.public());
+
+    let cards = vec![
+        ContactCard::new(*keypair.public(), vec![local(4000)])
+            .sign(&keypair)
+            .expect("sign failed"),
+        ContactCard::new(*KeyPair::random().public(), vec![local(4001)]),
+    ];
+    let contacts_path = temporary("contacts");
+
+    ContactCard::export(&contacts_path, &cards).expect("export failed");
+
+    let imported = ContactCard::import(&contacts_path).expect("import failed");
+
+    fs::remove_file(&contacts_path).expect("remove failed");
+
+    assert_eq!(imported, cards);
+    imported[0].verify().expect("signature lost");
+}
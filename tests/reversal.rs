@@ -0,0 +1,73 @@
+//! A node that is not reachable through any directory registration asks a
+//! subscribed peer to dial it through the directory server, and gets a
+//! working secured `Connection` out of its `ReversalListener`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use drop::crypto::key::exchange::Exchanger;
+use drop::net::server::DirectoryServer;
+use drop::net::{
+    Listener, MemoryConnector, MemoryListener, MemoryRegistry, ReversalDialer,
+    ReversalListener,
+};
+
+fn any_addr() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 0).into()
+}
+
+#[tokio::test]
+async fn connection_reversed_through_directory() {
+    let registry = MemoryRegistry::new();
+    let directory =
+        MemoryListener::new(&registry, any_addr(), Exchanger::random())
+            .expect("bind failed");
+    let directory_addr = directory.local_addr().expect("no address");
+    let (server, exit) = DirectoryServer::new(Box::new(directory));
+    let server = tokio::spawn(server.serve());
+
+    let dialing = Exchanger::random();
+    let dialing_key = *dialing.keypair().public();
+    let mut dialer = ReversalDialer::new(
+        MemoryConnector::new(registry.clone(), dialing),
+        directory_addr,
+    )
+    .await
+    .expect("subscription failed");
+
+    let requesting = Exchanger::random();
+    let requesting_key = *requesting.keypair().public();
+    let accepting =
+        MemoryListener::new(&registry, any_addr(), requesting.clone())
+            .expect("bind failed");
+    let mut listener = ReversalListener::new(
+        accepting,
+        MemoryConnector::new(registry.clone(), requesting),
+        directory_addr,
+        dialing_key,
+    )
+    .await
+    .expect("connect back request failed");
+
+    let (dialed, accepted) = tokio::join!(dialer.accept(), listener.accept());
+    let mut dialed = dialed.expect("dial failed");
+    let mut accepted = accepted.expect("accept failed");
+
+    // the handshake follows who dialed, the identity who asked
+    assert_eq!(accepted.remote_key(), Some(dialing_key));
+    assert_eq!(dialed.remote_key(), Some(requesting_key));
+    assert_eq!(accepted.initiator(), Some(dialing_key));
+    assert_eq!(dialed.initiator(), Some(dialing_key));
+    assert!(accepted.identity().expect("unsecured").is_reversed());
+    assert!(dialed.identity().expect("unsecured").is_reversed());
+
+    accepted.send(&1u32).await.expect("send failed");
+    assert_eq!(dialed.receive::<u32>().await.expect("receive failed"), 1);
+    dialed.send(&2u32).await.expect("send failed");
+    assert_eq!(accepted.receive::<u32>().await.expect("receive failed"), 2);
+
+    exit.send(()).expect("server already stopped");
+    server
+        .await
+        .expect("server panicked")
+        .expect("server failed");
+}
@@ -0,0 +1,250 @@
+//! Runs a ping protocol between two nodes over a shaped in-memory link with
+//! paused time, and checks that round trip times and retries match what the
+//! `LinkProfile` of the link predicts.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use drop::async_trait;
+use drop::crypto::key::exchange::{Exchanger, PublicKey};
+use drop::net::{
+    Connector, LinkProfile, Listener, MemoryConnector, MemoryListener,
+    MemoryRegistry,
+};
+use drop::system::{
+    AllSampler, Handle, NetworkSender, Processor, Sampler, Sender, SenderError,
+    System, SystemHandle, SystemManager,
+};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{self, Instant};
+
+/// Number of pings sent in the simulation
+const PINGS: u64 = 1000;
+
+/// Time after which a ping is sent again if no pong was received
+const PING_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// One way latency of the simulated link
+const LATENCY: Duration = Duration::from_millis(100);
+
+/// Probability that a frame is lost on the simulated link
+const LOSS: f64 = 0.01;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum Ping {
+    Ping(u64),
+    Pong(u64),
+}
+
+/// Answers pings and hands pongs to its `PingHandle`
+struct Pinger {
+    pongs: mpsc::UnboundedSender<u64>,
+    received: Option<mpsc::UnboundedReceiver<u64>>,
+}
+
+impl Pinger {
+    fn new() -> Self {
+        let (pongs, received) = mpsc::unbounded_channel();
+
+        Self {
+            pongs,
+            received: Some(received),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PingHandle {
+    sender: Arc<NetworkSender<Ping>>,
+    pongs: Arc<Mutex<mpsc::UnboundedReceiver<u64>>>,
+}
+
+#[async_trait]
+impl Handle<Ping, u64> for PingHandle {
+    type Error = SenderError;
+
+    async fn deliver(&mut self) -> Result<u64, Self::Error> {
+        Ok(self
+            .pongs
+            .lock()
+            .await
+            .recv()
+            .await
+            .expect("pinger stopped"))
+    }
+
+    async fn try_deliver(&mut self) -> Result<Option<u64>, Self::Error> {
+        Ok(self.pongs.lock().await.try_recv().ok())
+    }
+
+    async fn broadcast(&mut self, message: &Ping) -> Result<(), Self::Error> {
+        let keys = self.sender.keys().await;
+
+        self.sender
+            .send_many_collecting(message.clone(), keys.iter())
+            .await
+    }
+}
+
+#[async_trait]
+impl Processor<Ping, Ping, u64, NetworkSender<Ping>> for Pinger {
+    type Handle = PingHandle;
+
+    type Error = SenderError;
+
+    async fn process(
+        &self,
+        message: Ping,
+        from: PublicKey,
+        sender: Arc<NetworkSender<Ping>>,
+    ) -> Result<(), Self::Error> {
+        match message {
+            Ping::Ping(seq) => sender.send(Ping::Pong(seq), &from).await,
+            Ping::Pong(seq) => {
+                let _ = self.pongs.send(seq);
+
+                Ok(())
+            }
+        }
+    }
+
+    async fn setup<SA: Sampler>(
+        &mut self,
+        _: Arc<SA>,
+        sender: Arc<NetworkSender<Ping>>,
+    ) -> Self::Handle {
+        let pongs = self.received.take().expect("setup twice");
+
+        PingHandle {
+            sender,
+            pongs: Arc::new(Mutex::new(pongs)),
+        }
+    }
+
+    async fn disconnect<SA: Sampler>(
+        &self,
+        _: PublicKey,
+        _: Arc<NetworkSender<Ping>>,
+        _: Arc<SA>,
+    ) {
+    }
+
+    async fn garbage_collection(&self) {}
+}
+
+type Node = SystemHandle<Pinger, NetworkSender<Ping>, Ping, u64, Ping>;
+
+async fn run(system: System) -> Node {
+    SystemManager::new(system)
+        .run(Pinger::new(), AllSampler::default(), 1)
+        .await
+}
+
+#[tokio::test(start_paused = true)]
+async fn ping_over_lossy_link() {
+    let registry = MemoryRegistry::seeded(7);
+    let server_exchanger = Exchanger::random();
+    let server_key = *server_exchanger.keypair().public();
+    let listener = MemoryListener::new(
+        &registry,
+        (Ipv4Addr::LOCALHOST, 0).into(),
+        server_exchanger,
+    )
+    .expect("bind failed");
+    let server_addr = listener.local_addr().expect("no address");
+    let client_addr = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 9000));
+    let profile = LinkProfile {
+        latency: LATENCY,
+        loss_probability: LOSS,
+        ..Default::default()
+    };
+
+    registry.set_link(server_addr, client_addr, profile);
+
+    let mut server_system = System::default();
+
+    let _ = server_system.add_listener(listener).await;
+
+    let server = run(server_system).await;
+    let client = run(System::default()).await;
+    let connection =
+        MemoryConnector::new(registry.clone(), Exchanger::random())
+            .with_local_addr(client_addr)
+            .connect(&server_key, &server_addr)
+            .await
+            .expect("connect failed");
+
+    client
+        .add_connection(connection)
+        .await
+        .expect("failed to add connection");
+    server
+        .wait_for_peers(1, Some(Duration::from_secs(10)))
+        .await
+        .expect("client did not connect");
+
+    let before = registry.link_stats(server_addr, client_addr);
+    let mut handle = client.processor_handle();
+    let mut rtts = Vec::new();
+    let mut retries = 0u64;
+
+    for seq in 0..PINGS {
+        loop {
+            let start = Instant::now();
+
+            handle
+                .broadcast(&Ping::Ping(seq))
+                .await
+                .expect("ping failed");
+
+            let pong = time::timeout(PING_TIMEOUT, async {
+                while handle.deliver().await.expect("deliver failed") != seq {}
+            });
+
+            match pong.await {
+                Ok(()) => {
+                    rtts.push(start.elapsed());
+                    break;
+                }
+                Err(_) => retries += 1,
+            }
+        }
+    }
+
+    let stats = registry.link_stats(server_addr, client_addr);
+    let retransmissions = stats.retransmissions - before.retransmissions;
+
+    rtts.sort();
+
+    // a ping only needs to be sent again if either it or its pong is lost,
+    // since a retransmission makes the round trip exceed the timeout
+    let p = 1.0 - (1.0 - LOSS) * (1.0 - LOSS);
+    let expected = PINGS as f64 * p;
+    let deviation = (PINGS as f64 * p * (1.0 - p)).sqrt();
+    let mean = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+
+    assert_eq!(rtts[rtts.len() / 2], LATENCY * 2, "wrong median rtt");
+    assert!(
+        (retries as f64 - expected).abs() <= 4.0 * deviation,
+        "{} retries, expected about {}",
+        retries,
+        expected
+    );
+    // a retried ping is answered by its first pong, 100 ms after the retry
+    assert!(
+        mean.abs_diff(LATENCY * 2).as_secs_f64()
+            <= (p + 4.0 * deviation / PINGS as f64) * LATENCY.as_secs_f64(),
+        "mean rtt {:?}",
+        mean
+    );
+    assert!(
+        retransmissions >= retries && retransmissions <= retries + 3,
+        "{} retransmissions for {} retries",
+        retransmissions,
+        retries
+    );
+}
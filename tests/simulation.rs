@@ -0,0 +1,295 @@
+//! Runs many nodes in the same process over the in-memory transport and
+//! checks that they stay isolated from each other. This runs as its own test
+//! binary since it replaces the global allocator to measure memory usage.
+
+use std::alloc::{GlobalAlloc, Layout, System as SystemAlloc};
+use std::collections::{BTreeSet, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use drop::async_trait;
+use drop::crypto::key::exchange::{Exchanger, PublicKey};
+use drop::net::{
+    ConnectionEstablished, Connector, Listener, MemoryConnector,
+    MemoryListener, MemoryRegistry,
+};
+use drop::system::{
+    AllSampler, Handle, ManagerConfig, NetworkSender, Processor, Sampler,
+    Sender, SenderError, System, SystemHandle, SystemManager,
+};
+
+use tokio::time;
+use tracing_futures::Instrument;
+
+/// Number of nodes in the simulation
+const NODES: usize = 50;
+
+/// Number of nodes following each node on the ring that it connects to
+const NEIGHBOURS: usize = 3;
+
+/// Upper bound on the memory used by each node once connected
+const MAX_NODE_BYTES: isize = 512 * 1024;
+
+/// Tracks the number of bytes currently allocated
+struct Tracking;
+
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        SystemAlloc.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        SystemAlloc.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        LIVE.fetch_add(
+            new_size as isize - layout.size() as isize,
+            Ordering::Relaxed,
+        );
+        SystemAlloc.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        SystemAlloc.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Tracking = Tracking;
+
+type Received = Arc<Mutex<Vec<(PublicKey, u64)>>>;
+
+/// Remembers every message it receives
+#[derive(Default)]
+struct Flood {
+    received: Received,
+}
+
+#[derive(Clone)]
+struct FloodHandle {
+    sender: Arc<NetworkSender<u64>>,
+}
+
+#[async_trait]
+impl Handle<u64, u64> for FloodHandle {
+    type Error = SenderError;
+
+    async fn deliver(&mut self) -> Result<u64, Self::Error> {
+        unreachable!()
+    }
+
+    async fn try_deliver(&mut self) -> Result<Option<u64>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn broadcast(&mut self, message: &u64) -> Result<(), Self::Error> {
+        let keys = self.sender.keys().await;
+
+        self.sender
+            .send_many_collecting(*message, keys.iter())
+            .await
+    }
+}
+
+#[async_trait]
+impl Processor<u64, u64, u64, NetworkSender<u64>> for Flood {
+    type Handle = FloodHandle;
+
+    type Error = SenderError;
+
+    async fn process(
+        &self,
+        message: u64,
+        from: PublicKey,
+        _: Arc<NetworkSender<u64>>,
+    ) -> Result<(), Self::Error> {
+        self.received.lock().unwrap().push((from, message));
+
+        Ok(())
+    }
+
+    async fn setup<SA: Sampler>(
+        &mut self,
+        _: Arc<SA>,
+        sender: Arc<NetworkSender<u64>>,
+    ) -> Self::Handle {
+        FloodHandle { sender }
+    }
+
+    async fn disconnect<SA: Sampler>(
+        &self,
+        _: PublicKey,
+        _: Arc<NetworkSender<u64>>,
+        _: Arc<SA>,
+    ) {
+    }
+
+    async fn garbage_collection(&self) {}
+}
+
+struct Node {
+    key: PublicKey,
+    addr: SocketAddr,
+    connector: MemoryConnector,
+    handle: SystemHandle<Flood, NetworkSender<u64>, u64, u64, u64>,
+    received: Received,
+    observed: Arc<Mutex<Vec<PublicKey>>>,
+}
+
+async fn node(registry: &MemoryRegistry, id: usize) -> Node {
+    let exchanger = Exchanger::random();
+    let key = *exchanger.keypair().public();
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let collector = observed.clone();
+    let observer = move |event: &ConnectionEstablished| {
+        collector.lock().unwrap().extend(event.peer);
+    };
+    let any = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let listener = MemoryListener::new(registry, any, exchanger.clone())
+        .expect("bind failed")
+        .with_observer(observer.clone());
+    let addr = listener.local_addr().expect("no address");
+    let connector = MemoryConnector::new(registry.clone(), exchanger)
+        .with_observer(observer);
+    let config = ManagerConfig::default().node_id(format!("node-{}", id));
+    let mut system = System::default();
+
+    let _ = system
+        .add_listener(listener)
+        .instrument(config.span())
+        .await;
+
+    let processor = Flood::default();
+    let received = processor.received.clone();
+    let handle = SystemManager::with_config(system, config)
+        .run(processor, AllSampler::default(), 1)
+        .await;
+
+    Node {
+        key,
+        addr,
+        connector,
+        handle,
+        received,
+        observed,
+    }
+}
+
+/// Nodes connected to node `i` on the ring
+fn neighbours(i: usize) -> impl Iterator<Item = usize> {
+    (1..=NEIGHBOURS)
+        .flat_map(move |k| [(i + k) % NODES, (i + NODES - k) % NODES])
+}
+
+#[tokio::test]
+async fn isolated_nodes() {
+    let registry = MemoryRegistry::new();
+    let before = LIVE.load(Ordering::Relaxed);
+    let mut nodes = Vec::with_capacity(NODES);
+
+    for id in 0..NODES {
+        nodes.push(node(&registry, id).await);
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        for k in 1..=NEIGHBOURS {
+            let remote = &nodes[(i + k) % NODES];
+            let connection = node
+                .connector
+                .connect(&remote.key, &remote.addr)
+                .await
+                .expect("connect failed");
+
+            node.handle
+                .add_connection(connection)
+                .await
+                .expect("failed to add connection");
+        }
+    }
+
+    for node in nodes.iter() {
+        node.handle
+            .wait_for_peers(2 * NEIGHBOURS, Some(Duration::from_secs(10)))
+            .await
+            .expect("peers missing");
+    }
+
+    let used = LIVE.load(Ordering::Relaxed) - before;
+
+    assert_eq!(registry.len(), NODES, "wrong number of listeners");
+    assert!(
+        used / (NODES as isize) < MAX_NODE_BYTES,
+        "using {} bytes per node",
+        used / NODES as isize
+    );
+
+    let index = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.key, i as u64))
+        .collect::<HashMap<_, _>>();
+
+    for (i, node) in nodes.iter().enumerate() {
+        node.handle
+            .processor_handle()
+            .broadcast(&(i as u64))
+            .await
+            .expect("broadcast failed");
+    }
+
+    for node in nodes.iter() {
+        time::timeout(Duration::from_secs(10), async {
+            while node.received.lock().unwrap().len() < 2 * NEIGHBOURS {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("messages missing");
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        let expected =
+            neighbours(i).map(|j| nodes[j].key).collect::<BTreeSet<_>>();
+        let received = node.received.lock().unwrap().clone();
+
+        assert_eq!(received.len(), expected.len(), "wrong number of messages");
+
+        for (from, message) in received {
+            assert!(expected.contains(&from), "message from stranger");
+            assert_eq!(index[&from], message, "message from wrong node");
+        }
+
+        let observed = node
+            .observed
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(observed, expected, "observed another node's connection");
+
+        let scored = node
+            .handle
+            .peer_scores()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<BTreeSet<_>>();
+
+        assert!(scored.is_subset(&expected), "scores of another node");
+        assert_eq!(node.handle.queued_bytes(), 0, "messages left queued");
+    }
+}